@@ -14,7 +14,10 @@ use tycho_simulation::{
     evm::{
         engine_db::tycho_db::PreCachedDB,
         protocol::{
-            filters::{balancer_pool_filter, curve_pool_filter, uniswap_v4_pool_with_hook_filter},
+            filters::{
+                balancer_pool_filter, curve_pool_filter, uniswap_v4_pool_with_hook_filter,
+                ComponentFilterFn,
+            },
             uniswap_v2::state::UniswapV2State,
             uniswap_v3::state::UniswapV3State,
             uniswap_v4::state::UniswapV4State,
@@ -31,6 +34,9 @@ struct Cli {
     /// The tvl threshold to filter the graph by
     #[arg(short, long, default_value_t = 1000.0)]
     tvl_threshold: f64,
+    /// Pools with any token below this quality score are not quoted
+    #[arg(long, default_value_t = 51)]
+    min_token_quality: u8,
 }
 
 #[tokio::main]
@@ -53,8 +59,13 @@ async fn main() {
     let (tick_tx, tick_rx) = mpsc::channel::<BlockUpdate>(12);
 
     let tycho_message_processor: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-        let all_tokens =
-            load_all_tokens(tycho_url.as_str(), false, Some(tycho_api_key.as_str())).await;
+        let all_tokens = load_all_tokens(
+            tycho_url.as_str(),
+            false,
+            Some(tycho_api_key.as_str()),
+            Chain::Ethereum,
+        )
+        .await;
         let tvl_filter = ComponentFilter::with_tvl_range(cli.tvl_threshold, cli.tvl_threshold);
         let mut protocol_stream = ProtocolStreamBuilder::new(&tycho_url, Chain::Ethereum)
             .exchange::<UniswapV2State>("uniswap_v2", tvl_filter.clone(), None)
@@ -62,19 +73,20 @@ async fn main() {
             .exchange::<EVMPoolState<PreCachedDB>>(
                 "vm:balancer_v2",
                 tvl_filter.clone(),
-                Some(balancer_pool_filter),
+                Some(ComponentFilterFn::sync(balancer_pool_filter)),
             )
             .exchange::<EVMPoolState<PreCachedDB>>(
                 "vm:curve",
                 tvl_filter.clone(),
-                Some(curve_pool_filter),
+                Some(ComponentFilterFn::sync(curve_pool_filter)),
             )
             .exchange::<UniswapV4State>(
                 "uniswap_v4",
                 tvl_filter.clone(),
-                Some(uniswap_v4_pool_with_hook_filter),
+                Some(ComponentFilterFn::sync(uniswap_v4_pool_with_hook_filter)),
             )
             .auth_key(Some(tycho_api_key.clone()))
+            .min_token_quality(cli.min_token_quality)
             .set_tokens(all_tokens)
             .await
             .build()
@@ -17,9 +17,14 @@ use ratatui::{
 };
 use tokio::{select, sync::mpsc::Receiver};
 use tycho_core::Bytes;
-use tycho_simulation::protocol::{
-    models::{BlockUpdate, ProtocolComponent},
-    state::ProtocolSim,
+use tycho_simulation::{
+    evm::{engine_db::tycho_db::PreCachedDB, protocol::vm::state::EVMPoolState},
+    protocol::{
+        errors::SimulationError,
+        models::{BlockUpdate, ProtocolComponent},
+        state::ProtocolSim,
+    },
+    utils::{amounts::to_wei, scale_between_decimals, Rounding},
 };
 
 const INFO_TEXT: [&str; 2] = [
@@ -34,6 +39,7 @@ struct TableColors {
     header_bg: Color,
     header_fg: Color,
     row_fg: Color,
+    inactive_row_fg: Color,
     selected_row_style_fg: Color,
     selected_column_style_fg: Color,
     selected_cell_style_fg: Color,
@@ -49,6 +55,7 @@ impl TableColors {
             header_bg: color.c900,
             header_fg: tailwind::SLATE.c200,
             row_fg: tailwind::SLATE.c200,
+            inactive_row_fg: tailwind::SLATE.c600,
             selected_row_style_fg: color.c400,
             selected_column_style_fg: color.c400,
             selected_cell_style_fg: color.c600,
@@ -129,15 +136,15 @@ impl App {
         if let Some(prev_decimals) = current_decimals {
             let comp = &self.items[new_index].component;
             let decimals = comp.tokens[if self.zero2one { 0 } else { 1 }].decimals;
-            if decimals >= prev_decimals {
-                self.quote_amount *= BigUint::from(10u64).pow((decimals - prev_decimals) as u32);
-            } else {
-                self.quote_amount /= BigUint::from(10u64).pow((prev_decimals - decimals) as u32);
-            }
+            self.quote_amount =
+                scale_between_decimals(&self.quote_amount, prev_decimals, decimals, Rounding::Down);
         }
     }
 
     pub fn update_data(&mut self, update: BlockUpdate) {
+        if update.is_resync {
+            self.items.clear();
+        }
         for (id, comp) in update.new_pairs.iter() {
             let name = format!("{:#042x}", comp.address);
             let tokens = comp
@@ -151,16 +158,18 @@ impl App {
                 .map(|el| el.spot_price(&comp.tokens[0], &comp.tokens[1]))
                 .unwrap_or(Ok(0.0));
 
+            let state = update
+                .states
+                .get(id)
+                .unwrap_or_else(|| panic!("Received update for unknown pool {}", comp.address))
+                .clone();
+
             self.items.push(Data {
                 component: comp.clone(),
-                state: update
-                    .states
-                    .get(id)
-                    .unwrap_or_else(|| panic!("Received update for unknown pool {}", comp.address))
-                    .clone(),
+                price: format_price(price, state.is_active()),
+                state,
                 name,
                 tokens,
-                price: format!("{}", price.expect("Expected f64 as spot price")),
             });
         }
 
@@ -173,7 +182,7 @@ impl App {
             if let Some((index, _)) = entry {
                 let row = self.items.get_mut(index).unwrap();
                 let price = state.spot_price(&row.component.tokens[0], &row.component.tokens[1]);
-                row.price = format!("{}", price.expect("Expected f64 as spot price"));
+                row.price = format_price(price, state.is_active());
                 row.state = state.clone();
             }
         }
@@ -242,13 +251,11 @@ impl App {
             let comp = &self.items[idx].component;
             let decimals =
                 if self.zero2one { comp.tokens[0].decimals } else { comp.tokens[1].decimals };
+            let one = to_wei(1.0, decimals).expect("1 is a valid amount");
             if increase {
-                self.quote_amount += BigUint::from(10u64).pow(decimals as u32);
+                self.quote_amount += one;
             } else {
-                self.quote_amount = max(
-                    &self.quote_amount - BigUint::from(10u64).pow(decimals as u32),
-                    BigUint::one(),
-                );
+                self.quote_amount = max(&self.quote_amount - one, BigUint::one());
             }
         }
     }
@@ -295,15 +302,17 @@ impl App {
                     0 => self.colors.normal_row_color,
                     _ => self.colors.alt_row_color,
                 };
+                // Paused pools are greyed out, their prices can't be traded against
+                let fg = if data.state.is_active() {
+                    self.colors.row_fg
+                } else {
+                    self.colors.inactive_row_fg
+                };
                 let item = data.ref_array();
                 item.into_iter()
                     .map(|content| Cell::from(Text::from(format!("\n{content}\n"))))
                     .collect::<Row>()
-                    .style(
-                        Style::new()
-                            .fg(self.colors.row_fg)
-                            .bg(color),
-                    )
+                    .style(Style::new().fg(fg).bg(color))
                     .height(ITEM_HEIGHT as u16)
             });
         let bar = " █ ";
@@ -389,7 +398,16 @@ impl App {
                             self.quote_amount, data.amount, data.gas, duration
                         )
                     })
-                    .unwrap_or_else(|err| format!("{:?}", err));
+                    .unwrap_or_else(|err| {
+                        // VM pools are summarized, their Debug output includes the whole engine
+                        match state
+                            .as_any()
+                            .downcast_ref::<EVMPoolState<PreCachedDB>>()
+                        {
+                            Some(pool) => format!("{err}\nPool: {pool}"),
+                            None => format!("{:?}", err),
+                        }
+                    });
 
                 let block = Block::bordered().title("Quote:");
                 let popup = Paragraph::new(Text::from(text))
@@ -403,6 +421,15 @@ impl App {
     }
 }
 
+/// Formats a spot price, tolerating failures for paused pools which can't be priced.
+fn format_price(price: Result<f64, SimulationError>, active: bool) -> String {
+    match price {
+        Ok(price) => format!("{}", price),
+        Err(_) if !active => "paused".to_string(),
+        Err(e) => panic!("Expected f64 as spot price: {e:?}"),
+    }
+}
+
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 fn popup_area(area: Rect, x: Constraint, y: Constraint) -> Rect {
     let vertical = Layout::vertical([y]).flex(Flex::Center);
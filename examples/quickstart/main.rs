@@ -1,24 +1,29 @@
-use std::{collections::HashMap, env};
+use std::{collections::HashMap, env, error::Error, str::FromStr, sync::Arc};
 
+use alloy::providers::ProviderBuilder;
+use alloy_primitives::{keccak256, Address, U256};
+use alloy_sol_types::SolValue;
 use futures::StreamExt;
-use num_bigint::BigUint;
 use tracing_subscriber::EnvFilter;
 use tycho_simulation::{
     evm::{
-        engine_db::tycho_db::PreCachedDB,
+        engine_db::{simulation_db::SimulationDB, tycho_db::PreCachedDB},
+        erc20::ERC20Slots,
         protocol::{
-            filters::{balancer_pool_filter, uniswap_v4_pool_with_hook_filter},
+            filters::{balancer_pool_filter, uniswap_v4_pool_with_hook_filter, ComponentFilterFn},
             uniswap_v2::state::UniswapV2State,
             uniswap_v4::state::UniswapV4State,
             vm::state::EVMPoolState,
         },
+        simulation::{SimulationEngine, SimulationParameters},
         stream::ProtocolStreamBuilder,
+        ContractCompiler,
     },
     models::Token,
     protocol::models::BlockUpdate,
     tycho_client::feed::component_tracker::ComponentFilter,
-    tycho_core::dto::Chain,
-    utils::load_all_tokens,
+    tycho_core::{dto::Chain, Bytes},
+    utils::{amounts::to_wei, load_all_tokens},
 };
 
 #[tokio::main]
@@ -33,23 +38,30 @@ async fn main() {
     let tycho_api_key: String =
         env::var("TYCHO_API_KEY").unwrap_or_else(|_| "sampletoken".to_string());
 
+    if let Ok(rpc_url) = env::var("RPC_URL") {
+        simulate_router_swap(&rpc_url)
+            .await
+            .expect("Failed to simulate the router swap");
+    }
+
     let tvl_threshold = 10_000.0;
     let tvl_filter = ComponentFilter::with_tvl_range(tvl_threshold, tvl_threshold);
 
-    let all_tokens = load_all_tokens(tycho_url.as_str(), false, Some(tycho_api_key.as_str())).await;
-    let mut pairs: HashMap<String, Vec<Token>> = HashMap::new();
+    let all_tokens =
+        load_all_tokens(tycho_url.as_str(), false, Some(tycho_api_key.as_str()), Chain::Ethereum)
+            .await;
 
     let mut protocol_stream = ProtocolStreamBuilder::new(&tycho_url, Chain::Ethereum)
         .exchange::<UniswapV2State>("uniswap_v2", tvl_filter.clone(), None)
         .exchange::<EVMPoolState<PreCachedDB>>(
             "vm:balancer_v2",
             tvl_filter.clone(),
-            Some(balancer_pool_filter),
+            Some(ComponentFilterFn::sync(balancer_pool_filter)),
         )
         .exchange::<UniswapV4State>(
             "uniswap_v4",
             tvl_filter.clone(),
-            Some(uniswap_v4_pool_with_hook_filter),
+            Some(ComponentFilterFn::sync(uniswap_v4_pool_with_hook_filter)),
         )
         .auth_key(Some(tycho_api_key.clone()))
         .set_tokens(all_tokens.clone())
@@ -60,24 +72,94 @@ async fn main() {
 
     while let Some(message) = protocol_stream.next().await {
         let message = message.expect("Could not receive message");
-        print_calculations(message, &mut pairs);
+        print_calculations(message, &all_tokens);
     }
 }
 
-fn print_calculations(message: BlockUpdate, pairs: &mut HashMap<String, Vec<Token>>) {
+/// Simulates swapping 1 WETH for USDC through the Uniswap V2 router on the EVM, against the state
+/// of the node at `rpc_url`.
+///
+/// The caller holds no WETH: its balance and its allowance for the router are set with storage
+/// overrides instead.
+async fn simulate_router_swap(rpc_url: &str) -> Result<(), Box<dyn Error>> {
+    let client = ProviderBuilder::new()
+        .on_builtin(rpc_url)
+        .await?;
+    let engine = SimulationEngine::new(SimulationDB::new(Arc::new(client), None, None), false);
+
+    let caller = Address::from_str("0x0000000000000000000000000000000000004444")?;
+    let router = Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")?;
+    let weth = Address::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")?;
+    let usdc = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48")?;
+    let amount_in = U256::from(10).pow(U256::from(18));
+    // WETH9 keeps balances in slot 3 and allowances in slot 4
+    let weth_slots = ERC20Slots::new(U256::from(3), U256::from(4));
+
+    let mut data = keccak256("swapExactTokensForTokens(uint256,uint256,address[],address,uint256)")
+        [..4]
+        .to_vec();
+    data.extend((amount_in, U256::ZERO, vec![weth, usdc], caller, U256::MAX).abi_encode_params());
+    let params = SimulationParameters {
+        caller,
+        to: router,
+        data,
+        value: U256::ZERO,
+        overrides: None,
+        balance_overrides: None,
+        gas_limit: None,
+        block_number: 0,
+        timestamp: 0,
+        timeout: None,
+    }
+    .fund_caller(weth, weth_slots.clone(), amount_in, ContractCompiler::Solidity)
+    .approve(weth, weth_slots, router, amount_in, ContractCompiler::Solidity);
+
+    // The database fetches missing state from the node synchronously
+    let result = tokio::task::block_in_place(|| engine.simulate(&params))
+        .map_err(|e| format!("Simulation failed: {e:?}"))?;
+    let amounts = Vec::<U256>::abi_decode(&result.result, true)?;
+    println!(
+        "Swapping 1 WETH for USDC through the Uniswap V2 router: {:?} USDC (takes {} gas)",
+        amounts.last(),
+        result.gas_used
+    );
+    Ok(())
+}
+
+fn print_calculations(message: BlockUpdate, all_tokens: &HashMap<Bytes, Token>) {
     println!("==================== Received block {:?} ====================", message.block_number);
-    for (id, comp) in message.new_pairs.iter() {
-        pairs
-            .entry(id.clone())
-            .or_insert_with(|| comp.tokens.clone());
+    for id in message.removed_pairs.keys() {
+        println!("Pool {:?} was removed: {:?}", id, message.removal_reasons.get(id));
+    }
+    if !message.decode_errors.is_empty() {
+        let recoverable = message
+            .decode_errors
+            .iter()
+            .filter(|e| e.reason.is_recoverable())
+            .count();
+        println!(
+            "{} pools failed to decode, {} of them may decode later",
+            message.decode_errors.len(),
+            recoverable
+        );
     }
     if message.states.is_empty() {
         println!("No pools were updated this block");
         return
     }
-    println!("Using only pools that were updated this block...");
-    for (id, state) in message.states.iter().take(10) {
-        if let Some(tokens) = pairs.get(id) {
+    println!("Using only tradeable pools that were updated this block...");
+    let tradeable = message
+        .states_sorted()
+        .into_iter()
+        .filter(|(_, state)| state.is_tradeable());
+    for (id, state) in tradeable.take(10) {
+        // Each state knows its tokens, so no separate map of the pools' pairs is needed
+        let tokens = state
+            .tokens()
+            .iter()
+            .filter_map(|address| all_tokens.get(address).cloned())
+            .collect::<Vec<_>>();
+        if tokens.len() >= 2 {
             let formatted_token_str = format!("{:}/{:}", &tokens[0].symbol, &tokens[1].symbol);
             println!("Calculations for pool {:?} with tokens {:?}", id, formatted_token_str);
             state
@@ -85,8 +167,7 @@ fn print_calculations(message: BlockUpdate, pairs: &mut HashMap<String, Vec<Toke
                 .map(|price| println!("Spot price {:?}: {:?}", formatted_token_str, price))
                 .map_err(|e| eprintln!("Error calculating spot price for Pool {:?}: {:?}", id, e))
                 .ok();
-            let amount_in =
-                BigUint::from(1u32) * BigUint::from(10u32).pow(tokens[0].decimals as u32);
+            let amount_in = to_wei(1.0, tokens[0].decimals).expect("1 is a valid amount");
             state
                 .get_amount_out(amount_in, &tokens[0], &tokens[1])
                 .map(|result| {
@@ -97,6 +178,8 @@ fn print_calculations(message: BlockUpdate, pairs: &mut HashMap<String, Vec<Toke
                 })
                 .map_err(|e| eprintln!("Error calculating amount out for Pool {:?}: {:?}", id, e))
                 .ok();
+        } else {
+            println!("Skipping pool {:?}, its tokens are unknown", id);
         }
     }
 }
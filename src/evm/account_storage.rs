@@ -1,6 +1,9 @@
-use std::collections::{hash_map::Entry::Vacant, HashMap};
+use std::{
+    collections::{hash_map::Entry::Vacant, BTreeMap, BTreeSet, HashMap},
+    fmt,
+};
 
-use alloy_primitives::{Address, U256};
+use alloy_primitives::{Address, B256, U256};
 use revm::primitives::AccountInfo;
 use tracing::{debug, warn};
 
@@ -25,6 +28,129 @@ pub struct StateUpdate {
     pub storage: Option<HashMap<U256, U256>>,
     pub balance: Option<U256>,
 }
+
+/// A copy of a single account's state at a point in time.
+///
+/// `storage` holds the effective storage, i.e. permanent storage with temp storage applied on top.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountSnapshot {
+    pub address: Address,
+    pub info: AccountInfo,
+    pub storage: HashMap<U256, U256>,
+}
+
+impl AccountSnapshot {
+    fn new(address: Address, account: &Account) -> Self {
+        let mut storage = account.permanent_storage.clone();
+        storage.extend(&account.temp_storage);
+        Self { address, info: account.info.clone(), storage }
+    }
+
+    /// Compares this snapshot against a later snapshot of the same account.
+    pub fn diff(&self, after: &AccountSnapshot) -> AccountDiff {
+        let balance = (self.info.balance != after.info.balance)
+            .then_some(ValueChange { old: self.info.balance, new: after.info.balance });
+        let code_hash = (self.info.code_hash != after.info.code_hash)
+            .then_some(ValueChange { old: self.info.code_hash, new: after.info.code_hash });
+
+        let slots = self
+            .storage
+            .keys()
+            .chain(after.storage.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter_map(|slot| {
+                let old = self.storage.get(slot).copied();
+                let new = after.storage.get(slot).copied();
+                (old != new).then_some((*slot, ValueChange { old, new }))
+            })
+            .collect();
+
+        AccountDiff { balance, code_hash, slots }
+    }
+}
+
+/// A value before and after a change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueChange<T> {
+    pub old: T,
+    pub new: T,
+}
+
+/// Changes to an account present in both compared states.
+///
+/// Slot values are `None` where the slot was not set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub balance: Option<ValueChange<U256>>,
+    pub code_hash: Option<ValueChange<B256>>,
+    pub slots: BTreeMap<U256, ValueChange<Option<U256>>>,
+}
+
+impl AccountDiff {
+    pub fn is_empty(&self) -> bool {
+        self.balance.is_none() && self.code_hash.is_none() && self.slots.is_empty()
+    }
+}
+
+/// Differences between two `AccountStorage` states, see `AccountStorage::diff`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccountStorageDiff {
+    pub added: BTreeSet<Address>,
+    pub removed: BTreeSet<Address>,
+    pub changed: BTreeMap<Address, AccountDiff>,
+}
+
+impl AccountStorageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for AccountStorageDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no changes");
+        }
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )?;
+        for address in &self.added {
+            writeln!(f, "+ {address}")?;
+        }
+        for address in &self.removed {
+            writeln!(f, "- {address}")?;
+        }
+        for (address, diff) in &self.changed {
+            writeln!(f, "~ {address}")?;
+            if let Some(balance) = &diff.balance {
+                writeln!(f, "    balance: {} -> {}", balance.old, balance.new)?;
+            }
+            if let Some(code_hash) = &diff.code_hash {
+                writeln!(f, "    code_hash: {} -> {}", code_hash.old, code_hash.new)?;
+            }
+            for (slot, change) in &diff.slots {
+                writeln!(
+                    f,
+                    "    slot {:#x}: {} -> {}",
+                    slot,
+                    fmt_slot_value(change.old),
+                    fmt_slot_value(change.new)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fmt_slot_value(value: Option<U256>) -> String {
+    value.map_or_else(|| "unset".to_string(), |v| format!("{v:#x}"))
+}
+
 #[derive(Clone, Default, Debug)]
 /// A simpler implementation of CacheDB that can't query a node. It just stores data.
 pub struct AccountStorage {
@@ -125,6 +251,13 @@ impl AccountStorage {
             .map(|acc| &acc.info)
     }
 
+    /// Removes an account together with its storage.
+    ///
+    /// Returns `true` if the account was present.
+    pub fn remove_account(&mut self, address: &Address) -> bool {
+        self.accounts.remove(address).is_some()
+    }
+
     /// Checks if an account with the given address is present in the storage.
     ///
     /// # Arguments
@@ -215,6 +348,56 @@ impl AccountStorage {
             .for_each(|acc| acc.temp_storage.clear());
     }
 
+    /// Captures the current state of a single account.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the account.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the account is not present.
+    pub fn snapshot_account(&self, address: &Address) -> Option<AccountSnapshot> {
+        self.accounts
+            .get(address)
+            .map(|acc| AccountSnapshot::new(*address, acc))
+    }
+
+    /// Compares this storage against a later state.
+    ///
+    /// Reports accounts only present in `other` as added, accounts only present in `self` as
+    /// removed and, for accounts present in both, changed balances, code hashes and storage
+    /// slots. Storage is compared with temp storage applied on top of permanent storage.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The state to compare against, usually captured after applying updates.
+    pub fn diff(&self, other: &AccountStorage) -> AccountStorageDiff {
+        let mut diff = AccountStorageDiff::default();
+        for (address, account) in &self.accounts {
+            match other.accounts.get(address) {
+                Some(other_account) => {
+                    let account_diff = AccountSnapshot::new(*address, account)
+                        .diff(&AccountSnapshot::new(*address, other_account));
+                    if !account_diff.is_empty() {
+                        diff.changed
+                            .insert(*address, account_diff);
+                    }
+                }
+                None => {
+                    diff.removed.insert(*address);
+                }
+            }
+        }
+        diff.added.extend(
+            other
+                .accounts
+                .keys()
+                .filter(|address| !self.accounts.contains_key(*address)),
+        );
+        diff
+    }
+
     /// Checks if an account is mocked based on its address.
     ///
     /// # Arguments
@@ -341,6 +524,20 @@ mod tests {
         assert_eq!(non_existing_account, None, "Non-existing account should return None");
     }
 
+    #[test]
+    fn test_remove_account() {
+        let mut account_storage = AccountStorage::default();
+        let address = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap();
+        let storage = HashMap::from([(U256::from(1), U256::from(5))]);
+        account_storage.init_account(address, AccountInfo::default(), Some(storage), false);
+
+        assert!(account_storage.remove_account(&address));
+
+        assert_eq!(account_storage.get_account_info(&address), None);
+        assert_eq!(account_storage.get_storage(&address, &U256::from(1)), None);
+        assert!(!account_storage.remove_account(&address));
+    }
+
     #[test]
     fn test_account_present() {
         let mut account_storage = AccountStorage::default();
@@ -547,4 +744,91 @@ mod tests {
             "Expected None for existing account without permanent storage"
         );
     }
+
+    #[test]
+    fn test_diff_slot_changes() {
+        let address = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap();
+        let mut before = AccountStorage::new();
+        before.init_account(
+            address,
+            AccountInfo::default(),
+            Some(HashMap::from([(U256::from(1), U256::from(10)), (U256::from(2), U256::from(20))])),
+            false,
+        );
+        let mut after = before.clone();
+        after.update_account(
+            &address,
+            &StateUpdate {
+                storage: Some(HashMap::from([
+                    (U256::from(1), U256::from(11)),
+                    (U256::from(3), U256::from(30)),
+                ])),
+                balance: Some(U256::from(5)),
+            },
+        );
+
+        let diff = before.diff(&after);
+
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        let account_diff = &diff.changed[&address];
+        assert_eq!(account_diff.balance, Some(ValueChange { old: U256::ZERO, new: U256::from(5) }));
+        assert_eq!(account_diff.code_hash, None);
+        assert_eq!(
+            account_diff.slots,
+            BTreeMap::from([
+                (
+                    U256::from(1),
+                    ValueChange { old: Some(U256::from(10)), new: Some(U256::from(11)) }
+                ),
+                (U256::from(3), ValueChange { old: None, new: Some(U256::from(30)) }),
+            ])
+        );
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_diff_account_creation() {
+        let existing = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap();
+        let created = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dd").unwrap();
+        let mut before = AccountStorage::new();
+        before.init_account(existing, AccountInfo::default(), None, false);
+        let mut after = before.clone();
+        after.init_account(
+            created,
+            AccountInfo { balance: U256::from(100), ..Default::default() },
+            None,
+            false,
+        );
+
+        let diff = before.diff(&after);
+
+        assert_eq!(diff.added, BTreeSet::from([created]));
+        assert!(diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+        assert_eq!(after.diff(&before).removed, BTreeSet::from([created]));
+        assert!(diff
+            .to_string()
+            .contains(&format!("+ {created}")));
+    }
+
+    #[test]
+    fn test_snapshot_account_includes_temp_storage() {
+        let address = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap();
+        let mut account_storage = AccountStorage::new();
+        account_storage.init_account(
+            address,
+            AccountInfo::default(),
+            Some(HashMap::from([(U256::from(1), U256::from(10))])),
+            false,
+        );
+        account_storage.set_temp_storage(address, U256::from(1), U256::from(12));
+
+        let snapshot = account_storage
+            .snapshot_account(&address)
+            .unwrap();
+
+        assert_eq!(snapshot.storage, HashMap::from([(U256::from(1), U256::from(12))]));
+        assert_eq!(account_storage.snapshot_account(&Address::ZERO), None);
+    }
 }
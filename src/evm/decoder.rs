@@ -1,27 +1,51 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    any::TypeId,
+    collections::{hash_map::Entry, HashMap, HashSet},
     future::Future,
     pin::Pin,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Instant,
 };
 
 use alloy_primitives::Address;
+use futures::{future, stream, StreamExt};
+use itertools::Itertools;
+use revm::primitives::{AccountInfo, Bytecode};
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::{sync::RwLock, task::JoinHandle};
 use tracing::{debug, info, warn};
 use tycho_client::feed::{synchronizer::ComponentWithState, FeedMessage, Header};
-use tycho_core::Bytes;
+use tycho_core::{dto::ProtocolStateDelta, Bytes};
 
 use crate::{
     evm::{
-        engine_db::{update_engine, SHARED_TYCHO_DB},
+        engine_db::{
+            engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
+            tycho_db::PreCachedDB, update_engine,
+        },
+        metrics::{record_stream_event, MetricsRecorder},
+        protocol::{
+            filters::ComponentFilterFn,
+            vm::{
+                constants::{default_adapter_address, get_adapter_file},
+                state::EVMPoolState,
+                token_quality::{TokenClassification, TokenQualityAnalyzer},
+                tycho_decoder::{loaded_code, stateless_contracts},
+                utils::get_code_for_contracts,
+            },
+        },
+        rpc::{EnvRpc, EthRpc},
         tycho_models::{AccountUpdate, ResponseAccount},
     },
-    models::Token,
+    models::{token_quality, Token},
     protocol::{
-        errors::InvalidSnapshotError,
-        models::{BlockUpdate, ProtocolComponent, TryFromWithBlock},
+        errors::{InvalidSnapshotError, SimulationError},
+        gas::{GasModel, GasModelState},
+        models::{
+            BlockUpdate, DecodeError, DecodeErrorReason, ProtocolComponent, RemovalReason,
+            TryFromWithBlock,
+        },
         state::ProtocolSim,
     },
 };
@@ -36,13 +60,35 @@ pub enum StreamDecodeError {
 struct DecoderState {
     tokens: HashMap<Bytes, Token>,
     states: HashMap<String, Box<dyn ProtocolSim>>,
+    /// Components of all tracked pools, used to find pools affected by token quality changes
+    components: HashMap<String, ProtocolComponent>,
+    /// Behavior of the tokens analyzed so far, see `TychoStreamDecoder::quarantine_bad_tokens`
+    token_classifications: HashMap<Bytes, TokenClassification>,
+    /// Set once a block was decoded, new pools of later blocks may load their contracts in the
+    /// background
+    synced: bool,
+}
+
+/// A new VM pool held back while the stateless contracts it's missing are loaded
+struct PendingPool {
+    protocol: String,
+    component: ProtocolComponent,
+    /// Latest snapshot of the pool, with the deltas received while pending applied
+    snapshot: ComponentWithState,
+    loading: JoinHandle<()>,
 }
 
 type DecodeFut =
     Pin<Box<dyn Future<Output = Result<Box<dyn ProtocolSim>, InvalidSnapshotError>> + Send + Sync>>;
-type RegistryFn =
-    dyn Fn(ComponentWithState, Header, Arc<RwLock<DecoderState>>) -> DecodeFut + Send + Sync;
-type FilterFn = fn(&ComponentWithState) -> bool;
+type RegistryFn = dyn Fn(
+        ComponentWithState,
+        Header,
+        Arc<RwLock<DecoderState>>,
+        PreCachedDB,
+        Option<Arc<dyn EthRpc>>,
+    ) -> DecodeFut
+    + Send
+    + Sync;
 
 /// A decoder to process raw messages.
 ///
@@ -59,9 +105,30 @@ type FilterFn = fn(&ComponentWithState) -> bool;
 pub(super) struct TychoStreamDecoder {
     state: Arc<RwLock<DecoderState>>,
     skip_state_decode_failures: bool,
-    min_token_quality: u32,
+    min_token_quality: u8,
+    quarantine_bad_tokens: bool,
     registry: HashMap<String, Box<RegistryFn>>,
-    inclusion_filters: HashMap<String, FilterFn>,
+    inclusion_filters: HashMap<String, ComponentFilterFn>,
+    /// Pool ids per exchange that bypass client-side filters
+    included_pools: HashMap<String, HashSet<String>>,
+    /// Pool ids per exchange that are never decoded
+    excluded_pools: HashMap<String, HashSet<String>>,
+    /// Maximum number of snapshots decoded concurrently
+    decode_concurrency: usize,
+    /// Database VM storage is loaded into and `EVMPoolState`s simulate on
+    engine_db: PreCachedDB,
+    /// Client VM decoders fetch missing contract code with, see `EVMPoolStateBuilder::rpc_client`
+    rpc_client: Option<Arc<dyn EthRpc>>,
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    /// Model the emitted states are wrapped with, see `GasModelState`
+    gas_model: Option<Arc<dyn GasModel>>,
+    /// Adapter address of each registered VM exchange, `None` for the protocol's default
+    vm_exchanges: HashMap<String, Option<Address>>,
+    /// Contracts loaded into `engine_db` by `warmup`
+    preloaded_contracts: Vec<Address>,
+    background_contract_loading: bool,
+    /// Pools held back by `background_contract_loading`, by id
+    pending_pools: Mutex<HashMap<String, PendingPool>>,
 }
 
 impl TychoStreamDecoder {
@@ -70,8 +137,20 @@ impl TychoStreamDecoder {
             state: Arc::new(RwLock::new(DecoderState::default())),
             skip_state_decode_failures: false,
             min_token_quality: 51,
+            quarantine_bad_tokens: false,
             registry: HashMap::new(),
             inclusion_filters: HashMap::new(),
+            included_pools: HashMap::new(),
+            excluded_pools: HashMap::new(),
+            decode_concurrency: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            engine_db: PreCachedDB::new().expect("Failed to create PreCachedDB"),
+            rpc_client: None,
+            metrics: None,
+            gas_model: None,
+            vm_exchanges: HashMap::new(),
+            preloaded_contracts: Vec::new(),
+            background_contract_loading: false,
+            pending_pools: Mutex::new(HashMap::new()),
         }
     }
 
@@ -84,10 +163,115 @@ impl TychoStreamDecoder {
         guard.tokens = tokens;
     }
 
+    /// Forgets all tracked pools, keeping the known tokens.
+    ///
+    /// Used before decoding the snapshot of a new subscription, so it's treated as the full state.
+    pub async fn reset_states(&self) {
+        let mut guard = self.state.write().await;
+        guard.states.clear();
+        guard.components.clear();
+        guard.synced = false;
+        self.pending_pools
+            .lock()
+            .unwrap()
+            .clear();
+    }
+
     pub fn skip_state_decode_failures(&mut self, skip: bool) {
         self.skip_state_decode_failures = skip;
     }
 
+    /// Sets the database VM storage is loaded into. Defaults to a new, empty database.
+    ///
+    /// Decoders with separate databases don't share any contract state, so they can track
+    /// different chains in the same process.
+    pub fn engine_db(&mut self, db: PreCachedDB) {
+        self.engine_db = db;
+    }
+
+    /// Sets the client VM decoders request contract code from the node with. Defaults to the node
+    /// at the `RPC_URL` environment variable.
+    pub fn rpc_client(&mut self, client: Arc<dyn EthRpc>) {
+        self.rpc_client = Some(client);
+    }
+
+    /// Sets a recorder notified of decoded blocks and failed component decodings, in addition to
+    /// the global `MetricsRecorder`.
+    pub fn metrics(&mut self, metrics: Arc<dyn MetricsRecorder>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Sets the gas model adjusting the gas estimates of the emitted states.
+    ///
+    /// States are wrapped in a `GasModelState` when emitted, the tracked states stay unwrapped.
+    pub fn gas_model(&mut self, model: Arc<dyn GasModel>) {
+        self.gas_model = Some(model);
+    }
+
+    /// Registers contracts `warmup` loads into `engine_db`, e.g. the stateless contracts pools of
+    /// the registered VM exchanges are known to use.
+    pub fn preload_contracts(&mut self, addresses: Vec<Address>) {
+        self.preloaded_contracts
+            .extend(addresses);
+    }
+
+    /// Enables holding back new VM pools whose stateless contracts aren't loaded yet, instead of
+    /// fetching the contracts while decoding the block.
+    ///
+    /// The contracts are loaded in the background and the pool is emitted, as a new pair, with the
+    /// first block decoded afterwards. Pools of the first block after a (re)subscription are always
+    /// decoded right away.
+    pub fn background_contract_loading(&mut self, enabled: bool) {
+        self.background_contract_loading = enabled;
+    }
+
+    /// Loads the adapter contracts of the registered VM exchanges, and the contracts registered
+    /// with `preload_contracts`, into `engine_db`.
+    ///
+    /// Contracts already present in the database aren't fetched again, neither here nor when
+    /// decoding the pools using them.
+    pub async fn warmup(&self) -> Result<(), SimulationError> {
+        for (exchange, adapter_address) in &self.vm_exchanges {
+            let protocol = exchange
+                .strip_prefix("vm:")
+                .unwrap_or(exchange);
+            let code = Bytecode::new_raw(get_adapter_file(protocol)?.into());
+            let address = adapter_address.unwrap_or_else(|| default_adapter_address(protocol));
+            init_contract(&self.engine_db, address, code);
+        }
+        let missing = self
+            .preloaded_contracts
+            .iter()
+            .filter(|address| loaded_code(&self.engine_db, address).is_none())
+            .copied()
+            .unique()
+            .collect::<Vec<_>>();
+        info!(
+            adapters = self.vm_exchanges.len(),
+            contracts = missing.len(),
+            "Warming up engine database"
+        );
+        load_contracts(&self.engine_db, &missing, self.rpc_client.clone()).await
+    }
+
+    /// Sets the minimum quality a token needs for its pools to be decoded.
+    ///
+    /// Pools with any token below this threshold are skipped. Already tracked pools are removed
+    /// once one of their tokens drops below it.
+    pub fn min_token_quality(&mut self, quality: u8) {
+        self.min_token_quality = quality;
+    }
+
+    /// Enables classifying the tokens of snapshots with a `TokenQualityAnalyzer`.
+    ///
+    /// Pools with a `Broken` token are skipped, and the tax of `FeeOnTransfer` tokens is set on
+    /// their `Token`. Tokens are analyzed once, on the decoder's `engine_db`, so only tokens whose
+    /// contract is part of the VM storage of a snapshot can be classified. Others are decoded as
+    /// usual.
+    pub fn quarantine_bad_tokens(&mut self, quarantine: bool) {
+        self.quarantine_bad_tokens = quarantine;
+    }
+
     /// Registers a decoder for a given exchange.
     ///
     /// This method maps an exchange identifier to a specific protocol simulation type.
@@ -99,6 +283,9 @@ impl TychoStreamDecoder {
     /// this function with `register_decoder::<UniswapV2State>("uniswap_v2")`.
     /// This ensures that the exchange ID `uniswap_v2` is properly associated with the
     /// `UniswapV2State` decoder for use in the protocol stream.
+    ///
+    /// `EVMPoolState<PreCachedDB>` is decoded on this decoder's `engine_db` rather than the
+    /// global `SHARED_TYCHO_DB`.
     pub fn register_decoder<T>(&mut self, exchange: &str)
     where
         T: ProtocolSim
@@ -106,10 +293,16 @@ impl TychoStreamDecoder {
             + Send
             + 'static,
     {
+        if TypeId::of::<T>() == TypeId::of::<EVMPoolState<PreCachedDB>>() {
+            self.insert_vm_decoder(exchange, None);
+            return;
+        }
         let decoder = Box::new(
             move |component: ComponentWithState,
                   header: Header,
-                  state: Arc<RwLock<DecoderState>>| {
+                  state: Arc<RwLock<DecoderState>>,
+                  _db: PreCachedDB,
+                  _rpc_client: Option<Arc<dyn EthRpc>>| {
                 Box::pin(async move {
                     let guard = state.read().await;
                     T::try_from_with_block(component, header, &guard.tokens)
@@ -122,6 +315,40 @@ impl TychoStreamDecoder {
             .insert(exchange.to_string(), decoder);
     }
 
+    /// Registers the `EVMPoolState` decoder for a VM exchange, deploying the exchange's adapter
+    /// contract at `adapter_address` instead of the protocol's default address.
+    pub fn register_vm_decoder(&mut self, exchange: &str, adapter_address: Address) {
+        self.insert_vm_decoder(exchange, Some(adapter_address));
+    }
+
+    fn insert_vm_decoder(&mut self, exchange: &str, adapter_address: Option<Address>) {
+        self.vm_exchanges
+            .insert(exchange.to_string(), adapter_address);
+        let decoder = Box::new(
+            move |component: ComponentWithState,
+                  header: Header,
+                  state: Arc<RwLock<DecoderState>>,
+                  db: PreCachedDB,
+                  rpc_client: Option<Arc<dyn EthRpc>>| {
+                Box::pin(async move {
+                    let guard = state.read().await;
+                    EVMPoolState::<PreCachedDB>::try_from_with_db(
+                        component,
+                        header,
+                        &guard.tokens,
+                        adapter_address,
+                        db,
+                        rpc_client,
+                    )
+                    .await
+                    .map(|c| Box::new(c) as Box<dyn ProtocolSim>)
+                }) as DecodeFut
+            },
+        );
+        self.registry
+            .insert(exchange.to_string(), decoder);
+    }
+
     /// Registers a client-side filter function for a given exchange.
     ///
     /// Associates a filter function with an exchange ID, enabling custom filtering of protocol
@@ -137,18 +364,335 @@ impl TychoStreamDecoder {
     /// For example, you might use a filter to exclude pools that are not fully supported in the
     /// protocol, or to ignore pools with certain attributes that are irrelevant to your
     /// application.
-    pub fn register_filter(&mut self, exchange: &str, predicate: FilterFn) {
+    ///
+    /// Asynchronous filters are evaluated concurrently while processing a snapshot, with the same
+    /// bound as `decode_concurrency`.
+    pub fn register_filter(&mut self, exchange: &str, predicate: ComponentFilterFn) {
         self.inclusion_filters
             .insert(exchange.to_string(), predicate);
     }
 
+    /// Registers pools of an exchange that are always decoded, even if a client-side filter
+    /// registered with `register_filter` would reject them.
+    ///
+    /// Pool ids are compared case-insensitively.
+    pub fn register_included_pools(&mut self, exchange: &str, ids: Vec<String>) {
+        self.included_pools
+            .entry(exchange.to_string())
+            .or_default()
+            .extend(ids.iter().map(|id| id.to_lowercase()));
+    }
+
+    /// Registers pools of an exchange that are dropped from snapshots, deltas and removals before
+    /// decoding. Exclusion takes precedence over inclusion.
+    ///
+    /// Pool ids are compared case-insensitively.
+    pub fn register_excluded_pools(&mut self, exchange: &str, ids: Vec<String>) {
+        self.excluded_pools
+            .entry(exchange.to_string())
+            .or_default()
+            .extend(ids.iter().map(|id| id.to_lowercase()));
+    }
+
+    /// Sets how many snapshots are decoded concurrently. Deltas are always applied sequentially.
+    pub fn decode_concurrency(&mut self, n: usize) {
+        self.decode_concurrency = n.max(1);
+    }
+
+    /// Decodes snapshots of a single exchange, running up to `decode_concurrency` decodings at a
+    /// time on the tokio runtime.
+    ///
+    /// Returns the result of each snapshot, in no particular order. A failing snapshot does not
+    /// affect the others.
+    async fn decode_snapshots(
+        &self,
+        protocol: &str,
+        snapshots: Vec<(String, ComponentWithState)>,
+        block: &Header,
+    ) -> Vec<(String, Result<Box<dyn ProtocolSim>, InvalidSnapshotError>)> {
+        let Some(state_decode_f) = self.registry.get(protocol) else {
+            return Vec::new();
+        };
+        stream::iter(snapshots)
+            .map(|(id, snapshot)| {
+                let decode = state_decode_f(
+                    snapshot,
+                    block.clone(),
+                    self.state.clone(),
+                    self.engine_db.clone(),
+                    self.rpc_client.clone(),
+                );
+                async move {
+                    let result = tokio::spawn(decode)
+                        .await
+                        .unwrap_or_else(|e| {
+                            Err(InvalidSnapshotError::ValueError(format!(
+                                "Decoding task failed: {e}"
+                            )))
+                        });
+                    (id, result)
+                }
+            })
+            .buffer_unordered(self.decode_concurrency)
+            .collect()
+            .await
+    }
+
+    /// Keeps the snapshots passing the exchange's filter, evaluating up to `decode_concurrency`
+    /// filters at a time. Explicitly included pools are always kept.
+    ///
+    /// The order of the snapshots is preserved.
+    async fn filter_snapshots(
+        &self,
+        protocol: &str,
+        filter: &ComponentFilterFn,
+        snapshots: Vec<(String, ComponentWithState)>,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> Vec<(String, ComponentWithState)> {
+        stream::iter(snapshots)
+            .map(|(id, snapshot)| async move {
+                let keep = Self::is_pool_listed(&self.included_pools, protocol, &id) ||
+                    filter.matches(&snapshot, tokens).await;
+                keep.then_some((id, snapshot))
+            })
+            .buffered(self.decode_concurrency)
+            .filter_map(future::ready)
+            .collect()
+            .await
+    }
+
+    /// Classifies the known tokens among `tokens` that weren't classified yet, attaching the tax of
+    /// fee-on-transfer tokens to them.
+    ///
+    /// Tokens that can't be analyzed, e.g. because their contract isn't loaded, stay unclassified
+    /// and are retried with the next snapshot containing them. The tokens are analyzed without
+    /// holding the state lock, which is only taken to select them and to store the results.
+    async fn classify_tokens(&self, tokens: Vec<Bytes>, block: &BlockHeader) {
+        let tokens = {
+            let state_guard = self.state.read().await;
+            tokens
+                .into_iter()
+                .filter(|addr| {
+                    state_guard.tokens.contains_key(addr) &&
+                        !state_guard
+                            .token_classifications
+                            .contains_key(addr)
+                })
+                .collect::<Vec<_>>()
+        };
+        if tokens.is_empty() {
+            return;
+        }
+        let analyzer = match TokenQualityAnalyzer::new(self.engine_db.clone()) {
+            Ok(analyzer) => analyzer,
+            Err(e) => {
+                warn!(error = %e, "TokenAnalyzerUnavailable");
+                return;
+            }
+        };
+        let classifications = tokens
+            .into_iter()
+            .filter_map(|addr| match analyzer.analyze(&Address::from_slice(&addr[..20]), block) {
+                Ok(classification) => Some((addr, classification)),
+                Err(e) => {
+                    debug!(token = %addr, error = %e, "TokenNotClassified");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut state_guard = self.state.write().await;
+        for (addr, classification) in classifications {
+            match classification {
+                TokenClassification::Broken => warn!(token = %addr, "BrokenToken"),
+                TokenClassification::FeeOnTransfer { bps } => {
+                    debug!(token = %addr, bps, "FeeOnTransferToken");
+                    if let Some(token) = state_guard.tokens.get_mut(&addr) {
+                        token.tax = classification.transfer_tax();
+                    }
+                }
+                _ => {}
+            }
+            state_guard
+                .token_classifications
+                .insert(addr, classification);
+        }
+    }
+
+    /// Starts loading the stateless contracts `snapshots` are missing in the background, holding
+    /// back their pools until the contracts are loaded. Returns the snapshots ready to decode.
+    fn defer_pools_missing_contracts(
+        &self,
+        protocol: &str,
+        snapshots: Vec<(String, ComponentWithState)>,
+        new_pairs: &mut HashMap<String, ProtocolComponent>,
+    ) -> Vec<(String, ComponentWithState)> {
+        let mut pending = self.pending_pools.lock().unwrap();
+        let mut ready = Vec::new();
+        for (id, snapshot) in snapshots {
+            let missing = stateless_contracts(&snapshot)
+                .into_iter()
+                .filter(|(_, code)| code.is_none())
+                // Addresses resolved with a call are only known while decoding
+                .filter_map(|(address, _)| address.parse::<Address>().ok())
+                .filter(|address| loaded_code(&self.engine_db, address).is_none())
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                pending.remove(&id);
+                ready.push((id, snapshot));
+                continue;
+            }
+            let component = new_pairs
+                .remove(&id)
+                .expect("Snapshots to decode have a pair");
+            debug!(pool = id, contracts = missing.len(), "LoadingContractsInBackground");
+            match pending.entry(id) {
+                Entry::Occupied(mut entry) => {
+                    let pool = entry.get_mut();
+                    pool.component = component;
+                    pool.snapshot = snapshot;
+                }
+                Entry::Vacant(entry) => {
+                    let db = self.engine_db.clone();
+                    let rpc_client = self.rpc_client.clone();
+                    let loading = tokio::spawn(async move {
+                        if let Err(e) = load_contracts(&db, &missing, rpc_client).await {
+                            warn!(error = %e, "BackgroundContractLoadingFailure");
+                        }
+                    });
+                    entry.insert(PendingPool {
+                        protocol: protocol.to_string(),
+                        component,
+                        snapshot,
+                        loading,
+                    });
+                }
+            }
+        }
+        ready
+    }
+
+    /// Applies `delta` to the snapshot of a pending pool. Returns whether the pool is pending.
+    fn update_pending_pool(&self, id: &str, delta: &ProtocolStateDelta) -> bool {
+        let mut pending = self.pending_pools.lock().unwrap();
+        let Some(pool) = pending.get_mut(id) else {
+            return false;
+        };
+        let attributes = &mut pool.snapshot.state.attributes;
+        attributes.extend(delta.updated_attributes.clone());
+        for key in &delta.deleted_attributes {
+            attributes.remove(key);
+        }
+        true
+    }
+
+    /// Decodes the pending pools whose contracts finished loading, adding them as new pairs.
+    ///
+    /// Contracts that failed to load are fetched again while decoding, and decoding failures are
+    /// handled like those of snapshots.
+    async fn decode_loaded_pools(
+        &self,
+        block: &Header,
+        updated_states: &mut HashMap<String, Box<dyn ProtocolSim>>,
+        new_pairs: &mut HashMap<String, ProtocolComponent>,
+        decode_errors: &mut Vec<DecodeError>,
+    ) -> Result<(), StreamDecodeError> {
+        let mut loaded: HashMap<String, Vec<(String, ComponentWithState)>> = HashMap::new();
+        let mut components = HashMap::new();
+        {
+            let mut pending = self.pending_pools.lock().unwrap();
+            let ids = pending
+                .iter()
+                .filter(|(_, pool)| pool.loading.is_finished())
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<_>>();
+            for id in ids {
+                let pool = pending
+                    .remove(&id)
+                    .expect("Finished pool is pending");
+                components.insert(id.clone(), pool.component);
+                loaded
+                    .entry(pool.protocol)
+                    .or_default()
+                    .push((id, pool.snapshot));
+            }
+        }
+
+        for (protocol, snapshots) in loaded {
+            for (id, result) in self
+                .decode_snapshots(&protocol, snapshots, block)
+                .await
+            {
+                if let Err(e) = &result {
+                    record_stream_event(self.metrics.as_ref(), |r| r.on_decode_error(&protocol, e));
+                }
+                match result {
+                    Ok(state) => {
+                        new_pairs.extend(
+                            components
+                                .remove(&id)
+                                .map(|c| (id.clone(), c)),
+                        );
+                        updated_states.insert(id, state);
+                    }
+                    Err(e) if self.skip_state_decode_failures => {
+                        warn!(pool = id, error = %e, "StateDecodingFailure");
+                        decode_errors.push(DecodeError {
+                            component_id: id,
+                            exchange: protocol.clone(),
+                            reason: DecodeErrorReason::from(&e),
+                        });
+                    }
+                    Err(e) => return Err(StreamDecodeError::Fatal(e.to_string())),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `delta` to `state`. VM pools also apply their component `balances`, move to
+    /// `block` and recompute their prices if `touched_contracts` changed their storage, see
+    /// `EVMPoolState::transition_with_contracts`.
+    fn transition(
+        state: &mut Box<dyn ProtocolSim>,
+        delta: ProtocolStateDelta,
+        balances: &HashMap<Bytes, Bytes>,
+        touched_contracts: &HashSet<Address>,
+        block: BlockHeader,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> Result<(), StreamDecodeError> {
+        let result = match state
+            .as_any_mut()
+            .downcast_mut::<EVMPoolState<PreCachedDB>>()
+        {
+            Some(vm_state) => vm_state.transition_with_contracts(
+                delta,
+                balances,
+                touched_contracts,
+                block,
+                tokens,
+            ),
+            None => state.delta_transition(delta, tokens),
+        };
+        result.map_err(|e| StreamDecodeError::Fatal(format!("TransitionFailure: {e:?}")))
+    }
+
+    fn is_pool_listed(pools: &HashMap<String, HashSet<String>>, exchange: &str, id: &str) -> bool {
+        pools
+            .get(exchange)
+            .is_some_and(|ids| ids.contains(&id.to_lowercase()))
+    }
+
     /// Decodes a `FeedMessage` into a `BlockUpdate` containing the updated states of protocol
     /// components
     pub async fn decode(&self, msg: FeedMessage) -> Result<BlockUpdate, StreamDecodeError> {
+        let start = Instant::now();
         // stores all states updated in this tick/msg
         let mut updated_states = HashMap::new();
         let mut new_pairs = HashMap::new();
         let mut removed_pairs = HashMap::new();
+        let mut removal_reasons = HashMap::new();
+        let mut decode_errors = Vec::new();
 
         let block = msg
             .state_msgs
@@ -157,16 +701,55 @@ impl TychoStreamDecoder {
             .ok_or_else(|| StreamDecodeError::Fatal("Missing block!".into()))?
             .header
             .clone();
+        let header = BlockHeader::from(block.clone()).with_chain(
+            self.engine_db
+                .chain()
+                .unwrap_or_default(),
+        );
 
         for (protocol, protocol_msg) in msg.state_msgs.iter() {
             // Add any new tokens
             if let Some(deltas) = protocol_msg.deltas.as_ref() {
                 let mut state_guard = self.state.write().await;
+
+                // Update the quality of known tokens and drop pools whose tokens fell below the
+                // threshold
+                #[allow(clippy::mutable_key_type)]
+                let mut degraded_tokens = HashSet::new();
+                for (addr, t) in deltas.new_tokens.iter() {
+                    if let Some(token) = state_guard.tokens.get_mut(addr) {
+                        token.quality = token_quality(t.quality);
+                        if token.quality < self.min_token_quality {
+                            degraded_tokens.insert(addr.clone());
+                        }
+                    }
+                }
+                if !degraded_tokens.is_empty() {
+                    let degraded_pools = state_guard
+                        .components
+                        .iter()
+                        .filter(|(_, comp)| {
+                            comp.tokens
+                                .iter()
+                                .any(|t| degraded_tokens.contains(&t.address))
+                        })
+                        .map(|(id, _)| id.clone())
+                        .collect::<Vec<_>>();
+                    for id in degraded_pools {
+                        debug!(pool = id, "RemovingPoolWithLowQualityToken");
+                        state_guard.states.remove(&id);
+                        if let Some(comp) = state_guard.components.remove(&id) {
+                            removed_pairs.insert(id.clone(), comp);
+                            removal_reasons.insert(id, RemovalReason::LowTokenQuality);
+                        }
+                    }
+                }
+
                 let res = deltas
                     .new_tokens
                     .iter()
                     .filter_map(|(addr, t)| {
-                        if t.quality < self.min_token_quality ||
+                        if t.quality < self.min_token_quality as u32 ||
                             // Do not add the token if it's already included in the state_guard
                             state_guard.tokens.contains_key(addr)
                         {
@@ -190,41 +773,6 @@ impl TychoStreamDecoder {
                 }
             }
 
-            let state_guard = self.state.read().await;
-            removed_pairs.extend(
-                protocol_msg
-                    .removed_components
-                    .iter()
-                    .flat_map(|(id, comp)| match Bytes::from_str(id) {
-                        Ok(addr) => Some(Ok((id, addr, comp))),
-                        Err(e) => {
-                            if self.skip_state_decode_failures {
-                                None
-                            } else {
-                                Some(Err(StreamDecodeError::Fatal(e.to_string())))
-                            }
-                        }
-                    })
-                    .collect::<Result<Vec<_>, StreamDecodeError>>()?
-                    .into_iter()
-                    .flat_map(|(id, address, comp)| {
-                        let tokens = comp
-                            .tokens
-                            .iter()
-                            .flat_map(|addr| state_guard.tokens.get(addr).cloned())
-                            .collect::<Vec<_>>();
-
-                        if tokens.len() == comp.tokens.len() {
-                            Some((id.clone(), ProtocolComponent::new(address, tokens)))
-                        } else {
-                            // We may reach this point if the removed component
-                            //  contained low quality tokens, in this case the component
-                            //  was never added, so we can skip emitting it.
-                            None
-                        }
-                    }),
-            );
-
             // UPDATE VM STORAGE
             let storage_by_address: HashMap<Address, ResponseAccount> = protocol_msg
                 .clone()
@@ -234,40 +782,130 @@ impl TychoStreamDecoder {
                 .map(|(key, value)| (Address::from_slice(&key[..20]), value.clone().into()))
                 .collect();
             info!("Updating engine with snapshot");
-            update_engine(
-                SHARED_TYCHO_DB.clone(),
-                block.clone().into(),
-                Some(storage_by_address),
-                HashMap::new(),
-            )
-            .await;
+            update_engine(self.engine_db.clone(), header, Some(storage_by_address), HashMap::new())
+                .await;
             info!("Engine updated with snapshot");
 
+            // Token contracts are part of the VM storage, so tokens are analyzed once it's loaded
+            if self.quarantine_bad_tokens {
+                let tokens = protocol_msg
+                    .snapshots
+                    .get_states()
+                    .values()
+                    .flat_map(|snapshot| snapshot.component.tokens.clone())
+                    .unique()
+                    .collect();
+                self.classify_tokens(tokens, &header)
+                    .await;
+            }
+
+            let state_guard = self.state.read().await;
+            let deleted_components = protocol_msg
+                .deltas
+                .iter()
+                .flat_map(|deltas| {
+                    deltas
+                        .deleted_protocol_components
+                        .iter()
+                })
+                .map(|(id, comp)| (id, comp, RemovalReason::Deleted));
+            let removed = protocol_msg
+                .removed_components
+                .iter()
+                .map(|(id, comp)| (id, comp, RemovalReason::BelowTvlThreshold))
+                .chain(deleted_components)
+                .filter(|(id, _, _)| !Self::is_pool_listed(&self.excluded_pools, protocol, id))
+                // Included pools are tracked by id regardless of their TVL
+                .filter(|(id, _, reason)| {
+                    *reason != RemovalReason::BelowTvlThreshold ||
+                        !Self::is_pool_listed(&self.included_pools, protocol, id)
+                })
+                .flat_map(|(id, comp, reason)| match Bytes::from_str(id) {
+                    Ok(addr) => Some(Ok((id, addr, comp, reason))),
+                    Err(e) => {
+                        if self.skip_state_decode_failures {
+                            None
+                        } else {
+                            Some(Err(StreamDecodeError::Fatal(e.to_string())))
+                        }
+                    }
+                })
+                .collect::<Result<Vec<_>, StreamDecodeError>>()?;
+            for (id, address, comp, reason) in removed {
+                if self
+                    .pending_pools
+                    .lock()
+                    .unwrap()
+                    .remove(id)
+                    .is_some()
+                {
+                    // The pool was never emitted, so there's nothing to report
+                    continue;
+                }
+                let tokens = comp
+                    .tokens
+                    .iter()
+                    .flat_map(|addr| state_guard.tokens.get(addr).cloned())
+                    .collect::<Vec<_>>();
+
+                if tokens.len() == comp.tokens.len() {
+                    removed_pairs.insert(id.clone(), ProtocolComponent::new(address, tokens));
+                    removal_reasons.insert(id.clone(), reason);
+                }
+                // Otherwise the removed component contained low quality tokens, in this case
+                //  the component was never added, so we can skip emitting it.
+            }
+
             let mut new_components = HashMap::new();
 
             // PROCESS SNAPSHOTS
-            'outer: for (id, snapshot) in protocol_msg
+            let snapshots = protocol_msg
                 .snapshots
                 .get_states()
                 .clone()
+                .into_iter()
+                .filter(|(id, _)| !Self::is_pool_listed(&self.excluded_pools, protocol, id))
+                .collect::<Vec<_>>();
+            // Skip any unsupported pools, unless explicitly included
+            let snapshots = match self
+                .inclusion_filters
+                .get(protocol.as_str())
             {
-                // Skip any unsupported pools
-                if let Some(predicate) = self
-                    .inclusion_filters
-                    .get(protocol.as_str())
-                {
-                    if !predicate(&snapshot) {
-                        continue
-                    }
+                Some(filter) => {
+                    self.filter_snapshots(protocol, filter, snapshots, &state_guard.tokens)
+                        .await
                 }
+                None => snapshots,
+            };
 
+            let mut to_decode = Vec::new();
+            'outer: for (id, snapshot) in snapshots {
                 // Construct component from snapshot
                 let mut component_tokens = Vec::new();
                 for token in snapshot.component.tokens.clone() {
                     match state_guard.tokens.get(&token) {
+                        Some(token) if token.quality < self.min_token_quality => {
+                            debug!("Low quality token {}, ignoring pool {:x?}", token.address, id);
+                            continue 'outer;
+                        }
+                        Some(token)
+                            if self.quarantine_bad_tokens &&
+                                state_guard
+                                    .token_classifications
+                                    .get(&token.address) ==
+                                    Some(&TokenClassification::Broken) =>
+                        {
+                            debug!("Broken token {}, ignoring pool {:x?}", token.address, id);
+                            continue 'outer;
+                        }
                         Some(token) => component_tokens.push(token.clone()),
                         None => {
                             debug!("Token not found {}, ignoring pool {:x?}", token, id);
+                            decode_errors.push(DecodeError {
+                                component_id: id,
+                                exchange: protocol.clone(),
+                                reason: DecodeErrorReason::MissingToken(token),
+                            });
                             continue 'outer;
                         }
                     }
@@ -277,23 +915,18 @@ impl TychoStreamDecoder {
                     ProtocolComponent::new(Bytes::from(id.as_str()), component_tokens),
                 );
 
-                // Construct state from snapshot
-                if let Some(state_decode_f) = self.registry.get(protocol.as_str()) {
-                    match state_decode_f(snapshot, block.clone(), self.state.clone()).await {
-                        Ok(state) => {
-                            new_components.insert(id.clone(), state);
-                        }
-                        Err(e) => {
-                            if self.skip_state_decode_failures {
-                                warn!(pool = id, error = %e, "StateDecodingFailure");
-                                continue 'outer;
-                            } else {
-                                return Err(StreamDecodeError::Fatal(format!("{e}")));
-                            }
-                        }
-                    }
+                if self
+                    .registry
+                    .contains_key(protocol.as_str())
+                {
+                    to_decode.push((id, snapshot));
                 } else if self.skip_state_decode_failures {
                     warn!(pool = id, "MissingDecoderRegistration");
+                    decode_errors.push(DecodeError {
+                        component_id: id,
+                        exchange: protocol.clone(),
+                        reason: DecodeErrorReason::MissingDecoder,
+                    });
                     continue 'outer;
                 } else {
                     return Err(StreamDecodeError::Fatal(format!(
@@ -302,6 +935,58 @@ impl TychoStreamDecoder {
                 }
             }
 
+            // Once synced, new pools missing contracts don't hold back the block
+            let to_decode = if self.background_contract_loading &&
+                state_guard.synced &&
+                self.vm_exchanges
+                    .contains_key(protocol.as_str())
+            {
+                self.defer_pools_missing_contracts(protocol, to_decode, &mut new_pairs)
+            } else {
+                to_decode
+            };
+
+            // Construct states from snapshots
+            let mut failures = Vec::new();
+            for (id, result) in self
+                .decode_snapshots(protocol, to_decode, &block)
+                .await
+            {
+                if let Err(e) = &result {
+                    record_stream_event(self.metrics.as_ref(), |r| r.on_decode_error(protocol, e));
+                }
+                match result {
+                    Ok(state) => {
+                        new_components.insert(id, state);
+                    }
+                    Err(e) if self.skip_state_decode_failures => {
+                        warn!(pool = id, error = %e, "StateDecodingFailure");
+                        new_pairs.remove(&id);
+                        decode_errors.push(DecodeError {
+                            component_id: id.clone(),
+                            exchange: protocol.clone(),
+                            reason: DecodeErrorReason::from(&e),
+                        });
+                        // Consumers already know about this pool, so it has to be reported as
+                        // removed.
+                        if let Some(comp) = state_guard.components.get(&id) {
+                            removed_pairs.insert(id.clone(), comp.clone());
+                            removal_reasons.insert(id, RemovalReason::DecodeFailure);
+                        }
+                    }
+                    Err(e) => failures.push((id, e.to_string())),
+                }
+            }
+            if !failures.is_empty() {
+                failures.sort();
+                return Err(StreamDecodeError::Fatal(
+                    failures
+                        .into_iter()
+                        .map(|(_, e)| e)
+                        .join("; "),
+                ));
+            }
+
             if !new_components.is_empty() {
                 debug!("Decoded {} snapshots for protocol {}", new_components.len(), protocol);
             }
@@ -315,26 +1000,50 @@ impl TychoStreamDecoder {
                     .iter()
                     .map(|(key, value)| (Address::from_slice(&key[..20]), value.clone().into()))
                     .collect();
+                let touched_contracts = account_update_by_address
+                    .keys()
+                    .copied()
+                    .collect::<HashSet<_>>();
+                // Balances of the components changed in the block, by token
+                let component_balances: HashMap<String, HashMap<Bytes, Bytes>> = deltas
+                    .component_balances
+                    .iter()
+                    .map(|(id, balances)| {
+                        let balances = balances
+                            .0
+                            .iter()
+                            .map(|(token, balance)| (token.clone(), balance.balance.clone()))
+                            .collect();
+                        (id.clone(), balances)
+                    })
+                    .collect();
+                let no_balances = HashMap::new();
                 info!("Updating engine with deltas");
-                update_engine(
-                    SHARED_TYCHO_DB.clone(),
-                    block.clone().into(),
-                    None,
-                    account_update_by_address,
-                )
-                .await;
+                update_engine(self.engine_db.clone(), header, None, account_update_by_address)
+                    .await;
                 info!("Engine updated with deltas");
 
                 for (id, update) in deltas.state_updates {
+                    if removed_pairs.contains_key(&id) ||
+                        Self::is_pool_listed(&self.excluded_pools, protocol, &id)
+                    {
+                        continue;
+                    }
+                    let balances = component_balances
+                        .get(&id)
+                        .unwrap_or(&no_balances);
                     match updated_states.entry(id.clone()) {
                         Entry::Occupied(mut entry) => {
                             // if state exists in updated_states, apply the delta to it
                             let state: &mut Box<dyn ProtocolSim> = entry.get_mut();
-                            state
-                                .delta_transition(update, &state_guard.tokens)
-                                .map_err(|e| {
-                                    StreamDecodeError::Fatal(format!("TransitionFailure: {e:?}"))
-                                })?;
+                            Self::transition(
+                                state,
+                                update,
+                                balances,
+                                &touched_contracts,
+                                header,
+                                &state_guard.tokens,
+                            )?;
                         }
                         Entry::Vacant(_) => {
                             match state_guard.states.get(&id) {
@@ -342,15 +1051,17 @@ impl TychoStreamDecoder {
                                 // stored state
                                 Some(stored_state) => {
                                     let mut state = stored_state.clone();
-                                    state
-                                        .delta_transition(update, &state_guard.tokens)
-                                        .map_err(|e| {
-                                            StreamDecodeError::Fatal(format!(
-                                                "TransitionFailure: {e:?}"
-                                            ))
-                                        })?;
+                                    Self::transition(
+                                        &mut state,
+                                        update,
+                                        balances,
+                                        &touched_contracts,
+                                        header,
+                                        &state_guard.tokens,
+                                    )?;
                                     updated_states.insert(id, state);
                                 }
+                                None if self.update_pending_pool(&id, &update) => {}
                                 None => warn!(
                                     pool = id,
                                     reason = "MissingState",
@@ -360,57 +1071,178 @@ impl TychoStreamDecoder {
                         }
                     }
                 }
+
+                // VM pools price from their balances and the storage of their contracts, which may
+                // have changed without a state update. Only pools with new balances or touched
+                // contracts other than their balance owner are emitted, see
+                // `EVMPoolState::is_touched_by`.
+                for (id, stored_state) in state_guard.states.iter() {
+                    if updated_states.contains_key(id) || removed_pairs.contains_key(id) {
+                        continue;
+                    }
+                    let Some(vm_state) = stored_state
+                        .as_any()
+                        .downcast_ref::<EVMPoolState<PreCachedDB>>()
+                    else {
+                        continue;
+                    };
+                    let balances = component_balances.get(id);
+                    if balances.is_none() && !vm_state.is_touched_by(&touched_contracts) {
+                        continue;
+                    }
+                    let mut state = stored_state.clone();
+                    let delta = ProtocolStateDelta {
+                        component_id: id.clone(),
+                        updated_attributes: HashMap::new(),
+                        deleted_attributes: HashSet::new(),
+                    };
+                    Self::transition(
+                        &mut state,
+                        delta,
+                        balances.unwrap_or(&no_balances),
+                        &touched_contracts,
+                        header,
+                        &state_guard.tokens,
+                    )?;
+                    updated_states.insert(id.clone(), state);
+                }
             };
         }
 
-        // Persist the newly added/updated states
+        self.decode_loaded_pools(&block, &mut updated_states, &mut new_pairs, &mut decode_errors)
+            .await?;
+
+        // Persist the newly added/updated states, dropping removed ones unless they were re-added
         let mut state_guard = self.state.write().await;
+        state_guard.synced = true;
+        for id in removed_pairs.keys() {
+            state_guard.states.remove(id);
+            state_guard.components.remove(id);
+        }
         state_guard
             .states
             .extend(updated_states.clone().into_iter());
+        state_guard
+            .components
+            .extend(new_pairs.clone());
+
+        let duration = start.elapsed();
+        record_stream_event(self.metrics.as_ref(), |r| {
+            r.on_block_decoded(duration, updated_states.len(), new_pairs.len(), removed_pairs.len())
+        });
+
+        let updated_states = match &self.gas_model {
+            Some(model) => updated_states
+                .into_iter()
+                .map(|(id, state)| {
+                    let state: Box<dyn ProtocolSim> =
+                        Box::new(GasModelState::new(state, model.clone()));
+                    (id, state)
+                })
+                .collect(),
+            None => updated_states,
+        };
 
         // Send the tick with all updated states
         Ok(BlockUpdate::new(block.number, updated_states, new_pairs)
-            .set_removed_pairs(removed_pairs))
+            .set_removed_pairs(removed_pairs)
+            .set_removal_reasons(removal_reasons)
+            .set_decode_errors(decode_errors))
+    }
+}
+
+/// Initializes a contract without storage on `db`.
+fn init_contract(db: &PreCachedDB, address: Address, code: Bytecode) {
+    db.init_account(
+        address,
+        AccountInfo {
+            balance: Default::default(),
+            nonce: 0,
+            code_hash: code.hash_slow(),
+            code: Some(code),
+        },
+        None,
+        false,
+    );
+}
+
+/// Fetches the code of `addresses` in a single request and loads it into `db`.
+async fn load_contracts(
+    db: &PreCachedDB,
+    addresses: &[Address],
+    rpc_client: Option<Arc<dyn EthRpc>>,
+) -> Result<(), SimulationError> {
+    if addresses.is_empty() {
+        return Ok(());
+    }
+    let rpc = rpc_client.unwrap_or_else(|| Arc::new(EnvRpc::default()));
+    let code = get_code_for_contracts(addresses, rpc.as_ref()).await?;
+    for (address, code) in addresses.iter().zip(code) {
+        init_contract(db, *address, code);
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{fs, path::Path};
+    use std::{
+        collections::HashMap,
+        fs,
+        path::Path,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
 
-    use num_bigint::ToBigUint;
+    use alloy_primitives::{Address, U256};
+    use num_bigint::BigUint;
     use rstest::*;
-    use tycho_client::feed::FeedMessage;
+    use serde_json::{json, Value};
+    use tycho_client::feed::{synchronizer::ComponentWithState, FeedMessage};
     use tycho_core::Bytes;
 
     use crate::{
         evm::{
             decoder::{StreamDecodeError, TychoStreamDecoder},
-            protocol::uniswap_v2::state::UniswapV2State,
+            engine_db::tycho_db::PreCachedDB,
+            metrics::MetricsRecorder,
+            protocol::{
+                filters::{token_filter, token_quality_filter, ComponentFilterFn, FilterFuture},
+                uniswap_v2::state::UniswapV2State,
+                vm::{constants::default_adapter_address, state::EVMPoolState},
+            },
+            tycho_models::Chain,
         },
         models::Token,
+        protocol::{
+            errors::InvalidSnapshotError,
+            gas::{GasModel, L1DataGasModel, SWAP_CALLDATA_BYTES},
+            models::RemovalReason,
+            state::ProtocolSim,
+        },
+        testing::{token_at, token_map, BlockUpdateBuilder, MockEthRpc},
     };
 
     async fn setup_decoder(set_tokens: bool) -> TychoStreamDecoder {
         let mut decoder = TychoStreamDecoder::new();
         decoder.register_decoder::<UniswapV2State>("uniswap_v2");
         if set_tokens {
-            let tokens = [
-                Bytes::from("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").lpad(20, 0),
-                Bytes::from("0xdac17f958d2ee523a2206206994597c13d831ec7").lpad(20, 0),
-            ]
-            .iter()
-            .map(|addr| {
-                let addr_str = format!("{:x}", addr);
-                (addr.clone(), Token::new(&addr_str, 18, &addr_str, 100_000.to_biguint().unwrap()))
-            })
-            .collect();
-            decoder.set_tokens(tokens).await;
+            decoder
+                .set_tokens(token_map([weth(), usdt()]))
+                .await;
         }
         decoder
     }
 
+    fn weth() -> Token {
+        let address = Bytes::from("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").lpad(20, 0);
+        token_at(address, "WETH", 18)
+    }
+
+    fn usdt() -> Token {
+        let address = Bytes::from("0xdac17f958d2ee523a2206206994597c13d831ec7").lpad(20, 0);
+        token_at(address, "USDT", 6)
+    }
+
     fn load_test_msg(name: &str) -> FeedMessage {
         let project_root = env!("CARGO_MANIFEST_DIR");
         let asset_path =
@@ -434,21 +1266,142 @@ mod tests {
             .await
             .expect("decode failure");
 
+        let expected = BlockUpdateBuilder::new(21284145)
+            .token(weth())
+            .token(usdt())
+            .new_pair("0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852", "WETH/USDT")
+            .build();
+        assert_eq!(res1.block_number, expected.block_number);
+        assert_eq!(res1.new_pairs, expected.new_pairs);
         assert_eq!(res1.states.len(), 1);
         assert_eq!(res2.states.len(), 1);
+        // The snapshot asset also reports the pool as dropped by the component tracker
+        assert_eq!(
+            res1.removal_reasons["0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"],
+            RemovalReason::BelowTvlThreshold
+        );
+    }
+
+    /// Builds a snapshot of `n` v2 pools, the first `broken` of which lack a reserve.
+    fn synthetic_snapshot(n: usize, broken: usize) -> FeedMessage {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let asset_path =
+            Path::new(project_root).join("tests/assets/decoder/uniswap_v2_snapshot.json");
+        let json_data = fs::read_to_string(asset_path).expect("Failed to read test asset");
+        let mut msg: Value = serde_json::from_str(&json_data).unwrap();
+
+        let protocol_msg = &mut msg["state_msgs"]["uniswap_v2"];
+        protocol_msg["removed_components"] = json!({});
+        let states = protocol_msg["snapshots"]["states"]
+            .as_object_mut()
+            .unwrap();
+        let template = states.values().next().unwrap().clone();
+        states.clear();
+        for i in 0..n {
+            let id = format!("{:#042x}", i + 1);
+            let mut state = template.clone();
+            state["component"]["id"] = json!(id);
+            state["state"]["component_id"] = json!(id);
+            state["state"]["attributes"]["reserve0"] = json!(format!("{:#x}", 1_000_000 + i));
+            if i < broken {
+                state["state"]["attributes"]
+                    .as_object_mut()
+                    .unwrap()
+                    .remove("reserve0");
+            }
+            states.insert(id, state);
+        }
+        serde_json::from_value(msg).expect("Failed to deserialize FeedMsg json!")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_concurrent_matches_sequential() {
+        let mut sequential = setup_decoder(true).await;
+        sequential.decode_concurrency(1);
+        let mut concurrent = setup_decoder(true).await;
+        concurrent.decode_concurrency(8);
+
+        let expected = sequential
+            .decode(synthetic_snapshot(300, 0))
+            .await
+            .expect("decode failure");
+        let res = concurrent
+            .decode(synthetic_snapshot(300, 0))
+            .await
+            .expect("decode failure");
+
+        assert_eq!(expected.states.len(), 300);
+        assert_eq!(res.states.len(), expected.states.len());
+        assert_eq!(res.new_pairs, expected.new_pairs);
+        for (id, state) in expected.states.iter() {
+            assert!(
+                ProtocolSim::eq(state.as_ref(), res.states[id].as_ref()),
+                "State mismatch for {id}"
+            );
+        }
+    }
+
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_concurrent_collects_failures(#[case] skip_failures: bool) {
+        let mut decoder = setup_decoder(true).await;
+        decoder.decode_concurrency(4);
+        decoder.skip_state_decode_failures = skip_failures;
+
+        match decoder
+            .decode(synthetic_snapshot(10, 3))
+            .await
+        {
+            Ok(res) => {
+                assert!(skip_failures, "Expected failures to be raised");
+                assert_eq!(res.states.len(), 7);
+                assert_eq!(res.new_pairs.len(), 7);
+            }
+            Err(StreamDecodeError::Fatal(msg)) => {
+                assert!(!skip_failures, "Expected failures to be ignored. Err: {}", msg);
+                assert_eq!(
+                    msg.matches("Missing attributes reserve0")
+                        .count(),
+                    3
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_reports_decode_errors() {
+        let mut decoder = setup_decoder(true).await;
+        decoder.skip_state_decode_failures = true;
+
+        let res = decoder
+            .decode(synthetic_snapshot(4, 1))
+            .await
+            .expect("decode failure");
+
+        let broken = format!("{:#042x}", 1);
+        assert_eq!(res.states.len(), 3);
+        assert!(!res.states.contains_key(&broken));
+        assert_eq!(
+            res.decode_errors,
+            vec![DecodeError {
+                component_id: broken,
+                exchange: "uniswap_v2".to_string(),
+                reason: DecodeErrorReason::Unsupported("Missing attributes reserve0".to_string()),
+            }]
+        );
+        assert!(!res.decode_errors[0]
+            .reason
+            .is_recoverable());
     }
 
     #[tokio::test]
     async fn test_decode_component_missing_token() {
         let decoder = setup_decoder(false).await;
-        let tokens = [Bytes::from("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").lpad(20, 0)]
-            .iter()
-            .map(|addr| {
-                let addr_str = format!("{:x}", addr);
-                (addr.clone(), Token::new(&addr_str, 18, &addr_str, 100_000.to_biguint().unwrap()))
-            })
-            .collect();
-        decoder.set_tokens(tokens).await;
+        decoder
+            .set_tokens(token_map([weth()]))
+            .await;
 
         let msg = load_test_msg("uniswap_v2_snapshot");
         let res1 = decoder
@@ -457,6 +1410,209 @@ mod tests {
             .expect("decode failure");
 
         assert_eq!(res1.states.len(), 0);
+        assert_eq!(res1.decode_errors.len(), 1);
+        assert_eq!(res1.decode_errors[0].reason, DecodeErrorReason::MissingToken(usdt().address));
+        assert!(res1.decode_errors[0]
+            .reason
+            .is_recoverable());
+    }
+
+    #[tokio::test]
+    async fn test_decode_skips_low_quality_tokens() {
+        let mut decoder = setup_decoder(false).await;
+        decoder.min_token_quality(51);
+        decoder
+            .set_tokens(token_map([weth(), usdt().with_quality(10)]))
+            .await;
+
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), 0);
+        assert!(res.new_pairs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_decode_removes_pool_on_token_quality_drop() {
+        let decoder = setup_decoder(true).await;
+        let pool = "0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852";
+
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        let res1 = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+        let msg = load_test_msg("uniswap_v2_delta_low_quality_token");
+        let res2 = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(res1.new_pairs.contains_key(pool));
+        assert!(res2.removed_pairs.contains_key(pool));
+        assert_eq!(res2.removal_reasons[pool], RemovalReason::LowTokenQuality);
+        assert!(res2.states.is_empty());
+        assert!(!decoder
+            .state
+            .read()
+            .await
+            .states
+            .contains_key(pool));
+    }
+
+    #[tokio::test]
+    async fn test_decode_excluded_pools() {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_excluded_pools(
+            "uniswap_v2",
+            vec!["0x0D4A11D5EEAAC28EC3F61D100DAF4D40471F1852".to_string()],
+        );
+
+        let msg = load_test_msg("uniswap_v2_snapshot_multiple_pools");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), 1);
+        assert!(res
+            .states
+            .contains_key("0xa478c2975ab1ea89e8196811f51a7b7ade33eb11"));
+        assert!(!res
+            .new_pairs
+            .contains_key("0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"));
+
+        // The removal of an excluded pool is not reported either
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(res.states.is_empty());
+        assert!(res.removed_pairs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_decode_included_pools_bypass_filter() {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_filter("uniswap_v2", ComponentFilterFn::sync(|_, _| false));
+        decoder.register_included_pools(
+            "uniswap_v2",
+            vec!["0xa478c2975ab1ea89e8196811f51a7b7ade33eb11".to_string()],
+        );
+
+        let msg = load_test_msg("uniswap_v2_snapshot_multiple_pools");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), 1);
+        assert!(res
+            .states
+            .contains_key("0xa478c2975ab1ea89e8196811f51a7b7ade33eb11"));
+    }
+
+    #[tokio::test]
+    async fn test_decode_included_pools_not_removed_below_tvl() {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_included_pools(
+            "uniswap_v2",
+            vec!["0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852".to_string()],
+        );
+
+        let msg = load_test_msg("uniswap_v2_snapshot");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(res
+            .states
+            .contains_key("0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"));
+        assert!(res.removal_reasons.is_empty());
+        assert!(res.removed_pairs.is_empty());
+    }
+
+    fn only_usdt_weth_pool<'a>(
+        component: &'a ComponentWithState,
+        _tokens: &'a HashMap<Bytes, Token>,
+    ) -> FilterFuture<'a> {
+        Box::pin(async move {
+            tokio::task::yield_now().await;
+            component.component.id == "0xa478c2975ab1ea89e8196811f51a7b7ade33eb11"
+        })
+    }
+
+    #[tokio::test]
+    async fn test_decode_async_filter() {
+        let mut decoder = setup_decoder(true).await;
+        decoder.register_filter("uniswap_v2", ComponentFilterFn::new_async(only_usdt_weth_pool));
+
+        let msg = load_test_msg("uniswap_v2_snapshot_multiple_pools");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), 1);
+        assert!(res
+            .states
+            .contains_key("0xa478c2975ab1ea89e8196811f51a7b7ade33eb11"));
+    }
+
+    #[rstest]
+    #[case::below_threshold(80, 0)]
+    #[case::above_threshold(50, 2)]
+    #[tokio::test]
+    async fn test_decode_token_quality_filter(#[case] min_quality: u8, #[case] exp_states: usize) {
+        let mut decoder = setup_decoder(false).await;
+        decoder
+            .set_tokens(token_map([weth(), usdt().with_quality(60)]))
+            .await;
+        decoder.register_filter("uniswap_v2", token_quality_filter(min_quality));
+
+        let msg = load_test_msg("uniswap_v2_snapshot_multiple_pools");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), exp_states);
+    }
+
+    #[rstest]
+    #[case::subset(vec![weth(), usdt()], true, 2)]
+    #[case::not_subset(vec![weth()], true, 0)]
+    #[case::intersects(vec![weth()], false, 2)]
+    #[case::disjoint(vec![dai()], false, 0)]
+    #[tokio::test]
+    async fn test_decode_token_filter(
+        #[case] allowed: Vec<Token>,
+        #[case] require_all: bool,
+        #[case] exp_states: usize,
+    ) {
+        let mut decoder = setup_decoder(true).await;
+        let allowed = allowed
+            .into_iter()
+            .map(|token| token.address)
+            .collect();
+        decoder.register_filter(
+            "uniswap_v2",
+            token_filter(allowed, require_all).and(token_quality_filter(0)),
+        );
+
+        let msg = load_test_msg("uniswap_v2_snapshot_multiple_pools");
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.len(), exp_states);
     }
 
     #[rstest]
@@ -489,6 +1645,81 @@ mod tests {
         }
     }
 
+    /// Records the calls of the stream metrics hooks
+    #[derive(Debug, Default)]
+    struct RecordingMetrics {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl MetricsRecorder for RecordingMetrics {
+        fn on_block_decoded(
+            &self,
+            _duration: Duration,
+            n_states: usize,
+            n_new: usize,
+            n_removed: usize,
+        ) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("block {n_states} {n_new} {n_removed}"));
+        }
+
+        fn on_decode_error(&self, protocol: &str, _err: &InvalidSnapshotError) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("decode_error {protocol}"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_applies_gas_model() {
+        let model = L1DataGasModel::new(10, 1);
+        let l1_gas = model.l1_data_gas(SWAP_CALLDATA_BYTES);
+        let mut decoder = setup_decoder(true).await;
+        decoder.gas_model(Arc::new(model));
+
+        let res = decoder
+            .decode(load_test_msg("uniswap_v2_snapshot"))
+            .await
+            .expect("decode failure");
+
+        let state = &res.states["0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"];
+        let amount_in = BigUint::from(10u64).pow(18);
+        let wrapped = state
+            .get_amount_out(amount_in.clone(), &weth(), &usdt())
+            .unwrap();
+        let unwrapped = state
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap()
+            .get_amount_out(amount_in, &weth(), &usdt())
+            .unwrap();
+        assert_eq!(wrapped.amount, unwrapped.amount);
+        assert_eq!(wrapped.gas, unwrapped.gas + l1_gas);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_reports_metrics() {
+        let metrics = Arc::new(RecordingMetrics::default());
+        let mut decoder = setup_decoder(true).await;
+        decoder.skip_state_decode_failures(true);
+        decoder.metrics(metrics.clone());
+
+        decoder
+            .decode(synthetic_snapshot(5, 2))
+            .await
+            .expect("decode failure");
+
+        let mut calls = metrics.calls.lock().unwrap().clone();
+        calls.sort();
+        assert_eq!(
+            calls,
+            vec!["block 3 3 0", "decode_error uniswap_v2", "decode_error uniswap_v2"]
+        );
+    }
+
     #[rstest]
     #[case(true)]
     #[case(false)]
@@ -515,4 +1746,406 @@ mod tests {
             }
         }
     }
+
+    /// Builds a snapshot of the Balancer V2 DAI/BAL pool on `chain`, including the storage of its
+    /// contracts.
+    fn balancer_snapshot(chain: &str, block: u64) -> FeedMessage {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let asset_path =
+            Path::new(project_root).join("tests/assets/decoder/balancer_snapshot.json");
+        let json_data = fs::read_to_string(asset_path).expect("Failed to read test asset");
+        let data: Value = serde_json::from_str(&json_data).unwrap();
+
+        let vm_storage = data["accounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|account| {
+                let mut account = account.clone();
+                account["chain"] = json!(chain);
+                account
+                    .as_object_mut()
+                    .unwrap()
+                    .remove("change");
+                (
+                    account["address"]
+                        .as_str()
+                        .unwrap()
+                        .to_string(),
+                    account,
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        let vault = "0xba12222222228d8ba445958a75a0704d566bf2c8";
+        let header = json!({
+            "hash": format!("{block:#066x}"),
+            "number": block,
+            "parent_hash": format!("{:#066x}", block - 1),
+            "revert": false
+        });
+        let mut sync_state = header.clone();
+        sync_state["status"] = json!("ready");
+
+        let msg = json!({
+            "state_msgs": {
+                "vm:balancer_v2": {
+                    "header": header,
+                    "snapshots": {
+                        "states": {
+                            pool_id: {
+                                "state": {
+                                    "component_id": pool_id,
+                                    "attributes": { "balance_owner": vault },
+                                    "balances": { DAI: "0x01", BAL: "0x01" }
+                                },
+                                "component": {
+                                    "id": pool_id,
+                                    "protocol_system": "vm:balancer_v2",
+                                    "protocol_type_name": "balancer_v2_pool",
+                                    "chain": chain,
+                                    "tokens": [DAI, BAL],
+                                    "contract_ids": [vault],
+                                    "static_attributes": { "manual_updates": "0x01" },
+                                    "change": "Creation",
+                                    "creation_tx": "0x0000",
+                                    "created_at": "2021-06-01T05:40:00"
+                                }
+                            }
+                        },
+                        "vm_storage": vm_storage
+                    },
+                    "deltas": null,
+                    "removed_components": {}
+                }
+            },
+            "sync_states": { "vm:balancer_v2": sync_state }
+        });
+        serde_json::from_value(msg).expect("Failed to deserialize FeedMsg json!")
+    }
+
+    /// Declares `library` as a stateless contract of the balancer pool, without its code.
+    fn add_stateless_contract(msg: &mut FeedMessage, library: Address) {
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        // Stateless contract addresses are UTF-8 encoded, and fetched if no code is given
+        msg.state_msgs
+            .get_mut("vm:balancer_v2")
+            .unwrap()
+            .snapshots
+            .states
+            .get_mut(pool_id)
+            .unwrap()
+            .state
+            .attributes
+            .insert(
+                "stateless_contract_addr_0".to_string(),
+                Bytes::from(library.to_string().into_bytes()),
+            );
+    }
+
+    /// A balancer block loading the vault's storage, without any pool snapshot.
+    fn balancer_block(block: u64) -> FeedMessage {
+        let mut msg = balancer_snapshot("ethereum", block);
+        msg.state_msgs
+            .get_mut("vm:balancer_v2")
+            .unwrap()
+            .snapshots
+            .states
+            .clear();
+        msg
+    }
+
+    /// A balancer block updating the DAI balance of the pool, without any state update.
+    fn balancer_balance_update(block: u64, dai_balance: &str) -> FeedMessage {
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        let mut msg = serde_json::to_value(balancer_block(block)).unwrap();
+        msg["state_msgs"]["vm:balancer_v2"]["deltas"] = json!({
+            "extractor": "vm:balancer_v2",
+            "chain": "ethereum",
+            "block": {
+                "number": block,
+                "hash": format!("{block:#066x}"),
+                "parent_hash": format!("{:#066x}", block - 1),
+                "chain": "ethereum",
+                "ts": "2024-11-28T05:30:35"
+            },
+            "finalized_block_height": block,
+            "revert": false,
+            "new_tokens": {},
+            "account_updates": {},
+            "state_updates": {},
+            "new_protocol_components": {},
+            "deleted_protocol_components": {},
+            "component_balances": {
+                pool_id: {
+                    DAI: {
+                        "token": DAI,
+                        "balance": dai_balance,
+                        "balance_float": 0.0,
+                        "modify_tx": "0x0000",
+                        "component_id": pool_id
+                    }
+                }
+            },
+            "component_tvl": {}
+        });
+        serde_json::from_value(msg).unwrap()
+    }
+
+    /// The balancer snapshot of `block`, with a pool recomputing its prices whenever the storage
+    /// of the vault or the pool contract changes.
+    fn automatic_balancer_snapshot(block: u64) -> FeedMessage {
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        let mut msg = serde_json::to_value(balancer_snapshot("ethereum", block)).unwrap();
+        let component =
+            &mut msg["state_msgs"]["vm:balancer_v2"]["snapshots"]["states"][pool_id]["component"];
+        component["static_attributes"] = json!({});
+        component["contract_ids"] = json!([BALANCER_VAULT, BALANCER_POOL]);
+        serde_json::from_value(msg).unwrap()
+    }
+
+    /// A balancer block changing the storage of the contract at `address`, without any state or
+    /// balance update.
+    fn balancer_account_update(block: u64, address: &str) -> FeedMessage {
+        let mut msg = serde_json::to_value(balancer_balance_update(block, "0x01")).unwrap();
+        let deltas = &mut msg["state_msgs"]["vm:balancer_v2"]["deltas"];
+        deltas["component_balances"] = json!({});
+        deltas["account_updates"] = json!({
+            address: {
+                "address": address,
+                "chain": "ethereum",
+                "slots": { "0x01": "0x02" },
+                "balance": null,
+                "code": null,
+                "change": "Update"
+            }
+        });
+        serde_json::from_value(msg).unwrap()
+    }
+
+    const BALANCER_VAULT: &str = "0xba12222222228d8ba445958a75a0704d566bf2c8";
+    const BALANCER_POOL: &str = "0x4626d81b3a1711beb79f4cecff2413886d461677";
+    const DAI: &str = "0x6b175474e89094c44da98b954eedeac495271d0f";
+    const BAL: &str = "0xba100000625a3754423978a60c9317c58a424e3d";
+
+    fn dai() -> Token {
+        token_at(Bytes::from(DAI), "DAI", 18)
+    }
+
+    fn bal() -> Token {
+        token_at(Bytes::from(BAL), "BAL", 18)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_vm_concurrently_on_different_chains() {
+        let eth_db = PreCachedDB::for_chain(Chain::Ethereum).unwrap();
+        let arb_db = PreCachedDB::for_chain(Chain::Arbitrum).unwrap();
+        let mut eth_decoder = TychoStreamDecoder::new();
+        let mut arb_decoder = TychoStreamDecoder::new();
+        for (decoder, db) in [(&mut eth_decoder, &eth_db), (&mut arb_decoder, &arb_db)] {
+            decoder.engine_db(db.clone());
+            decoder.register_decoder::<EVMPoolState<PreCachedDB>>("vm:balancer_v2");
+            decoder
+                .set_tokens(token_map([dai(), bal()]))
+                .await;
+        }
+
+        let (eth_res, arb_res) = tokio::join!(
+            eth_decoder.decode(balancer_snapshot("ethereum", 1)),
+            arb_decoder.decode(balancer_snapshot("arbitrum", 2))
+        );
+        let eth_res = eth_res.expect("decode failure");
+        let arb_res = arb_res.expect("decode failure");
+
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        assert_eq!(eth_res.block_number, 1);
+        assert_eq!(arb_res.block_number, 2);
+        assert_eq!(
+            eth_res.states[pool_id]
+                .spot_price(&dai(), &bal())
+                .unwrap(),
+            arb_res.states[pool_id]
+                .spot_price(&dai(), &bal())
+                .unwrap()
+        );
+        // Each stream only moved its own database forward
+        assert_eq!(eth_db.block_number(), Some(1));
+        assert_eq!(arb_db.block_number(), Some(2));
+    }
+
+    #[rstest]
+    #[case::pool_contract(BALANCER_POOL, true)]
+    #[case::balance_owner(BALANCER_VAULT, false)]
+    #[case::unrelated("0x1111111111111111111111111111111111111111", false)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_vm_touched_contracts(#[case] touched: &str, #[case] emitted: bool) {
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.engine_db(PreCachedDB::for_chain(Chain::Ethereum).unwrap());
+        decoder.register_decoder::<EVMPoolState<PreCachedDB>>("vm:balancer_v2");
+        decoder
+            .set_tokens(token_map([dai(), bal()]))
+            .await;
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        decoder
+            .decode(automatic_balancer_snapshot(1))
+            .await
+            .expect("decode failure");
+
+        let res = decoder
+            .decode(balancer_account_update(2, touched))
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.states.contains_key(pool_id), emitted);
+        if emitted {
+            let state = res.states[pool_id]
+                .as_any()
+                .downcast_ref::<EVMPoolState<PreCachedDB>>()
+                .unwrap();
+            assert!(state.spot_prices_dirty());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_vm_component_balances() {
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.engine_db(PreCachedDB::for_chain(Chain::Ethereum).unwrap());
+        decoder.register_decoder::<EVMPoolState<PreCachedDB>>("vm:balancer_v2");
+        decoder
+            .set_tokens(token_map([dai(), bal()]))
+            .await;
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        decoder
+            .decode(balancer_snapshot("ethereum", 1))
+            .await
+            .expect("decode failure");
+
+        let res = decoder
+            .decode(balancer_balance_update(2, "0x2a"))
+            .await
+            .expect("decode failure");
+
+        let state = res.states[pool_id]
+            .as_any()
+            .downcast_ref::<EVMPoolState<PreCachedDB>>()
+            .unwrap();
+        assert_eq!(
+            state.get_balances()[&Address::from_slice(&Bytes::from(DAI))],
+            U256::from(42u64)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_vm_fetches_code_with_rpc_client() {
+        let library = Address::repeat_byte(0x12);
+        let rpc = Arc::new(MockEthRpc::new().with_code(library, vec![0x00]));
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.engine_db(PreCachedDB::for_chain(Chain::Ethereum).unwrap());
+        decoder.rpc_client(rpc.clone());
+        decoder.register_decoder::<EVMPoolState<PreCachedDB>>("vm:balancer_v2");
+        decoder
+            .set_tokens(token_map([dai(), bal()]))
+            .await;
+        let mut msg = balancer_snapshot("ethereum", 1);
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        add_stateless_contract(&mut msg, library);
+
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(res.states.contains_key(pool_id));
+        assert_eq!(rpc.calls("get_code_batch"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_warmup_preloads_contracts() {
+        let library = Address::repeat_byte(0x12);
+        let rpc = Arc::new(MockEthRpc::new().with_code(library, vec![0x00]));
+        let db = PreCachedDB::for_chain(Chain::Ethereum).unwrap();
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.engine_db(db.clone());
+        decoder.rpc_client(rpc.clone());
+        decoder.register_decoder::<EVMPoolState<PreCachedDB>>("vm:balancer_v2");
+        decoder.preload_contracts(vec![library]);
+        decoder
+            .set_tokens(token_map([dai(), bal()]))
+            .await;
+
+        decoder
+            .warmup()
+            .await
+            .expect("warmup failure");
+
+        assert!(db
+            .snapshot_account(&default_adapter_address("balancer_v2"))
+            .is_some());
+        assert!(db.snapshot_account(&library).is_some());
+        assert_eq!(rpc.calls("get_code_batch"), 1);
+
+        // Pools using a preloaded contract don't request it again
+        let mut msg = balancer_snapshot("ethereum", 1);
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        add_stateless_contract(&mut msg, library);
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(res.states.contains_key(pool_id));
+        assert_eq!(rpc.calls("get_code_batch"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_decode_vm_loads_contracts_in_background() {
+        let library = Address::repeat_byte(0x12);
+        let delay = Duration::from_millis(500);
+        let rpc = Arc::new(
+            MockEthRpc::new()
+                .with_code(library, vec![0x00])
+                .with_code_delay(delay),
+        );
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.engine_db(PreCachedDB::for_chain(Chain::Ethereum).unwrap());
+        decoder.rpc_client(rpc.clone());
+        decoder.background_contract_loading(true);
+        decoder.register_decoder::<EVMPoolState<PreCachedDB>>("vm:balancer_v2");
+        decoder
+            .set_tokens(token_map([dai(), bal()]))
+            .await;
+        decoder
+            .decode(balancer_block(1))
+            .await
+            .expect("decode failure");
+
+        // The block of a new pool missing a contract is delivered without waiting for it
+        let mut msg = balancer_snapshot("ethereum", 2);
+        let pool_id = "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011";
+        add_stateless_contract(&mut msg, library);
+        let start = Instant::now();
+        let res = decoder
+            .decode(msg)
+            .await
+            .expect("decode failure");
+
+        assert!(start.elapsed() < delay);
+        assert_eq!(res.block_number, 2);
+        assert!(!res.states.contains_key(pool_id));
+        assert!(!res.new_pairs.contains_key(pool_id));
+
+        // The pool is emitted with the first block after its contract loaded
+        tokio::time::sleep(delay * 2).await;
+        let res = decoder
+            .decode(balancer_block(3))
+            .await
+            .expect("decode failure");
+
+        assert_eq!(res.block_number, 3);
+        assert!(res.new_pairs.contains_key(pool_id));
+        assert!(res.states[pool_id]
+            .spot_price(&dai(), &bal())
+            .is_ok());
+        assert_eq!(rpc.calls("get_code_batch"), 1);
+    }
 }
@@ -3,6 +3,9 @@ use std::collections::HashMap;
 use alloy_primitives::U256;
 use revm::{precompile::Address, primitives::AccountInfo, DatabaseRef};
 
+use super::simulation_db::BlockHeader;
+use crate::evm::tycho_models::Chain;
+
 pub trait EngineDatabaseInterface: DatabaseRef + Send + Sync {
     type Error;
 
@@ -28,4 +31,20 @@ pub trait EngineDatabaseInterface: DatabaseRef + Send + Sync {
     );
 
     fn clear_temp_storage(&mut self);
+
+    /// Number of the block the database state currently reflects, if known.
+    fn block_number(&self) -> Option<u64> {
+        self.block().map(|header| header.number)
+    }
+
+    /// Header of the block the database state currently reflects, if known.
+    fn block(&self) -> Option<BlockHeader> {
+        None
+    }
+
+    /// Chain the database state belongs to, if known. Engines created on the database simulate
+    /// this chain, see `create_engine`.
+    fn chain(&self) -> Option<Chain> {
+        None
+    }
 }
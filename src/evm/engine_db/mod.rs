@@ -34,6 +34,8 @@ lazy_static! {
 ///
 /// - `trace`: Whether to trace calls. Only meant for debugging purposes, might print a lot of data
 ///   to stdout.
+///
+/// The engine simulates the chain of `db` if it's bound to one, Ethereum otherwise.
 pub fn create_engine<D: EngineDatabaseInterface + Clone + Debug>(
     db: D,
     trace: bool,
@@ -42,7 +44,10 @@ where
     <D as EngineDatabaseInterface>::Error: Debug,
     <D as DatabaseRef>::Error: Debug,
 {
-    let engine = SimulationEngine::new(db.clone(), trace);
+    let mut engine = SimulationEngine::new(db.clone(), trace);
+    if let Some(chain) = EngineDatabaseInterface::chain(&db) {
+        engine = engine.with_chain(chain);
+    }
 
     let zero_account_info =
         AccountInfo { balance: Default::default(), nonce: 0, code_hash: KECCAK_EMPTY, code: None };
@@ -1,20 +1,27 @@
 use std::{
-    collections::HashMap,
-    fmt::Debug,
-    sync::{Arc, RwLock},
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
 };
 
-use alloy::providers::Provider;
 use alloy_primitives::StorageValue;
+use itertools::Itertools;
 use revm::{
     db::DatabaseRef,
     interpreter::analysis::to_analysed,
     primitives::{AccountInfo, Address, Bytecode, B256, U256},
 };
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
 use super::{
-    super::account_storage::{AccountStorage, StateUpdate},
+    super::{
+        account_storage::{AccountStorage, StateUpdate},
+        rpc::EthRpc,
+        tycho_models::Chain,
+    },
     engine_db_interface::EngineDatabaseInterface,
 };
 
@@ -25,6 +32,9 @@ pub struct OverriddenSimulationDB<'a, DB: DatabaseRef> {
     /// A mapping from account address to storage.
     /// Storage is a mapping from slot index to slot value.
     pub overrides: &'a HashMap<Address, HashMap<U256, U256>>,
+    /// A mapping from account address to its native balance, e.g. to fund pools holding ETH.
+    /// Accounts not found in the wrapped database are created with the given balance.
+    pub balance_overrides: Option<&'a HashMap<Address, U256>>,
 }
 
 impl<'a, DB: DatabaseRef> OverriddenSimulationDB<'a, DB> {
@@ -39,7 +49,13 @@ impl<'a, DB: DatabaseRef> OverriddenSimulationDB<'a, DB> {
     ///
     /// A new instance of OverriddenSimulationDB.
     pub fn new(inner_db: &'a DB, overrides: &'a HashMap<Address, HashMap<U256, U256>>) -> Self {
-        OverriddenSimulationDB { inner_db, overrides }
+        OverriddenSimulationDB { inner_db, overrides, balance_overrides: None }
+    }
+
+    /// Overrides the native balances of the given accounts.
+    pub fn with_balance_overrides(mut self, balance_overrides: &'a HashMap<Address, U256>) -> Self {
+        self.balance_overrides = Some(balance_overrides);
+        self
     }
 }
 
@@ -47,7 +63,17 @@ impl<DB: DatabaseRef> DatabaseRef for OverriddenSimulationDB<'_, DB> {
     type Error = DB::Error;
 
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        self.inner_db.basic_ref(address)
+        let info = self.inner_db.basic_ref(address)?;
+        match self
+            .balance_overrides
+            .and_then(|balances| balances.get(&address))
+        {
+            Some(balance) => {
+                debug!(%address, %balance, "Overriding balance of account {:x?}", address);
+                Ok(Some(AccountInfo { balance: *balance, ..info.unwrap_or_default() }))
+            }
+            None => Ok(info),
+        }
     }
 
     fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
@@ -77,27 +103,107 @@ impl<DB: DatabaseRef> DatabaseRef for OverriddenSimulationDB<'_, DB> {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default)]
+/// The block simulations run at
+///
+/// Feeds differ between chains: the hash may be unavailable, and rollups can report the L1 block
+/// their block derives from.
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default, Serialize, Deserialize)]
 pub struct BlockHeader {
     pub number: u64,
-    pub hash: B256,
+    /// `None` if the feed doesn't provide it, `BLOCKHASH` then returns zero for the block
+    pub hash: Option<B256>,
     pub timestamp: u64,
+    pub chain: Chain,
+    /// The L1 block the block derives from, on rollups like Arbitrum
+    pub l1_block_number: Option<u64>,
 }
 
-/// A wrapper over an Alloy Provider with local storage cache and overrides.
-#[derive(Clone, Debug)]
-pub struct SimulationDB<P: Provider + Debug> {
+impl BlockHeader {
+    /// Creates the header of an Ethereum block with a known hash
+    pub fn new(number: u64, hash: B256, timestamp: u64) -> Self {
+        BlockHeader { number, hash: Some(hash), timestamp, ..Default::default() }
+    }
+
+    pub fn with_chain(mut self, chain: Chain) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    pub fn with_l1_block_number(mut self, l1_block_number: u64) -> Self {
+        self.l1_block_number = Some(l1_block_number);
+        self
+    }
+
+    /// Whether this block comes after `other`, i.e. state at this block supersedes state at
+    /// `other`. Only meaningful for blocks of the same chain.
+    pub fn is_after(&self, other: &BlockHeader) -> bool {
+        self.number > other.number
+    }
+}
+
+/// A wrapper over a node client with local storage cache and overrides.
+///
+/// The client is any `EthRpc`, e.g. an Alloy `Provider`, or `dyn EthRpc` for an injected client.
+/// Accounts and storage missing from the cache are requested from the node at the current block,
+/// or at the latest block if none is set.
+///
+/// **Archival mode:** to simulate as of a historical block, e.g. to reproduce an `eth_call`,
+/// enable `archival` and pin the block with `set_block`. Requests then fail instead of falling
+/// back to the latest block if no block is set. The node needs to serve historical state.
+/// `SimulationEngine::with_block_mismatch` catches simulations whose `block_number` doesn't
+/// match the database's block.
+#[derive(Debug)]
+pub struct SimulationDB<P: EthRpc + ?Sized> {
     /// Client to connect to the RPC
     client: Arc<P>,
     /// Cached data
     account_storage: Arc<RwLock<AccountStorage>>,
+    /// Accounts requested from the node, dropped from the cache when the block changes
+    queried_accounts: Arc<RwLock<HashSet<Address>>>,
     /// Current block
     block: Option<BlockHeader>,
+    /// If set, requests to the node require a block
+    archival: bool,
     /// Tokio runtime to execute async code
     pub runtime: Option<Arc<tokio::runtime::Runtime>>,
+    /// Storage requests sent to the node, shared between clones
+    fetch_counters: Arc<FetchCounters>,
+}
+
+#[derive(Debug, Default)]
+struct FetchCounters {
+    individual: AtomicU64,
+    batches: AtomicU64,
+    batched_slots: AtomicU64,
+}
+
+/// Storage slots a `SimulationDB` requested from the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StorageFetchStats {
+    /// Slots requested one at a time, on cache misses during simulations
+    pub individual: u64,
+    /// Batched requests sent by `SimulationDB::prefetch_storage`
+    pub batches: u64,
+    /// Slots requested in batches
+    pub batched_slots: u64,
+}
+
+// Derived `Clone` would require the client to be `Clone`, which `dyn EthRpc` is not
+impl<P: EthRpc + ?Sized> Clone for SimulationDB<P> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            account_storage: self.account_storage.clone(),
+            queried_accounts: self.queried_accounts.clone(),
+            block: self.block,
+            archival: self.archival,
+            runtime: self.runtime.clone(),
+            fetch_counters: self.fetch_counters.clone(),
+        }
+    }
 }
 
-impl<P: Provider + Debug + 'static> SimulationDB<P> {
+impl<P: EthRpc + ?Sized + 'static> SimulationDB<P> {
     pub fn new(
         client: Arc<P>,
         runtime: Option<Arc<tokio::runtime::Runtime>>,
@@ -106,14 +212,53 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
         Self {
             client,
             account_storage: Arc::new(RwLock::new(AccountStorage::new())),
+            queried_accounts: Arc::new(RwLock::new(HashSet::new())),
             block,
+            archival: false,
             runtime,
+            fetch_counters: Arc::default(),
         }
     }
 
+    /// Enables archival mode: requests to the node fail if no block is set instead of falling
+    /// back to the latest block.
+    pub fn archival(mut self, archival: bool) -> Self {
+        self.archival = archival;
+        self
+    }
+
     /// Set the block that will be used when querying a node
-    pub fn set_block(&mut self, block: Option<BlockHeader>) {
-        self.block = block;
+    ///
+    /// If `clear_cache` is set, accounts and storage requested at the previous block are dropped
+    /// and requested again at the new block. Accounts set up with `init_account` and their
+    /// permanent storage are kept.
+    pub fn set_block(&mut self, header: BlockHeader, clear_cache: bool) {
+        self.block = Some(header);
+        if clear_cache {
+            let mut account_storage = self.account_storage.write().unwrap();
+            account_storage.clear_temp_storage();
+            for address in self
+                .queried_accounts
+                .write()
+                .unwrap()
+                .drain()
+            {
+                account_storage.remove_account(&address);
+            }
+        }
+    }
+
+    /// Returns the number of the block to request from the node, `None` for the latest block.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if archival mode is enabled and no block is set.
+    fn request_block(&self) -> Result<Option<u64>, <SimulationDB<P> as DatabaseRef>::Error> {
+        match (&self.block, self.archival) {
+            (Some(block), _) => Ok(Some(block.number)),
+            (None, true) => Err("Archival SimulationDB has no block set".into()),
+            (None, false) => Ok(None),
+        }
     }
 
     /// Update the simulation state.
@@ -190,21 +335,15 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
         address: Address,
     ) -> Result<AccountInfo, <SimulationDB<P> as DatabaseRef>::Error> {
         debug!("Querying account info of {:x?} at block {:?}", address, self.block);
+        let block = self.request_block()?;
 
         let (balance, nonce, code) = self.block_on(async {
-            let mut balance_request = self.client.get_balance(address);
-            let mut nonce_request = self
-                .client
-                .get_transaction_count(address);
-            let mut code_request = self.client.get_code_at(address);
-
-            if let Some(block) = &self.block {
-                balance_request = balance_request.number(block.number);
-                nonce_request = nonce_request.number(block.number);
-                code_request = code_request.number(block.number);
-            }
-
-            tokio::join!(balance_request, nonce_request, code_request,)
+            tokio::join!(
+                self.client.get_balance(address, block),
+                self.client
+                    .get_transaction_count(address, block),
+                self.client.get_code(address, block),
+            )
         });
         let code = to_analysed(Bytecode::new_raw(revm::primitives::Bytes::copy_from_slice(&code?)));
 
@@ -227,17 +366,83 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
         address: Address,
         index: U256,
     ) -> Result<StorageValue, <SimulationDB<P> as DatabaseRef>::Error> {
-        let storage = self.block_on(async {
-            let mut request = self
-                .client
-                .get_storage_at(address, index);
-            if let Some(block) = &self.block {
-                request = request.number(block.number);
+        let block = self.request_block()?;
+        let value = self.block_on(
+            self.client
+                .get_storage_at(address, index, block),
+        )?;
+        self.fetch_counters
+            .individual
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(value)
+    }
+
+    /// Fetches the storage slots of `address` missing from the cache with a single batched
+    /// request.
+    ///
+    /// Use it before simulating calls known to read many slots, e.g. of Curve pools, which would
+    /// otherwise be requested one at a time. The account is requested first if it isn't cached,
+    /// and nothing is fetched for mocked accounts.
+    pub fn prefetch_storage(
+        &self,
+        address: Address,
+        slots: &[U256],
+    ) -> Result<(), <SimulationDB<P> as DatabaseRef>::Error> {
+        self.basic_ref(address)?;
+        let missing: Vec<_> = {
+            let account_storage = self.account_storage.read().unwrap();
+            if account_storage.is_mocked_account(&address) != Some(false) {
+                return Ok(());
             }
-            request.await.unwrap()
-        });
+            slots
+                .iter()
+                .filter(|index| {
+                    account_storage
+                        .get_storage(&address, index)
+                        .is_none()
+                })
+                .unique()
+                .map(|index| (address, *index))
+                .collect()
+        };
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let block = self.request_block()?;
+        let values = self.block_on(
+            self.client
+                .get_storage_batch(&missing, block),
+        )?;
+        self.fetch_counters
+            .batches
+            .fetch_add(1, Ordering::Relaxed);
+        self.fetch_counters
+            .batched_slots
+            .fetch_add(missing.len() as u64, Ordering::Relaxed);
+        let mut account_storage = self.account_storage.write().unwrap();
+        for ((address, index), value) in missing.into_iter().zip(values) {
+            account_storage.set_temp_storage(address, index, value);
+        }
+        Ok(())
+    }
 
-        Ok(storage)
+    /// Returns how many storage slots were requested from the node, individually and in
+    /// batches.
+    pub fn storage_fetch_stats(&self) -> StorageFetchStats {
+        StorageFetchStats {
+            individual: self
+                .fetch_counters
+                .individual
+                .load(Ordering::Relaxed),
+            batches: self
+                .fetch_counters
+                .batches
+                .load(Ordering::Relaxed),
+            batched_slots: self
+                .fetch_counters
+                .batched_slots
+                .load(Ordering::Relaxed),
+        }
     }
 
     fn block_on<F: core::future::Future>(&self, f: F) -> F::Output {
@@ -251,10 +456,7 @@ impl<P: Provider + Debug + 'static> SimulationDB<P> {
     }
 }
 
-impl<P: Provider + Debug> EngineDatabaseInterface for SimulationDB<P>
-where
-    P: Provider + Send + Sync + 'static,
-{
+impl<P: EthRpc + ?Sized + 'static> EngineDatabaseInterface for SimulationDB<P> {
     type Error = String;
 
     /// Sets up a single account
@@ -296,12 +498,13 @@ where
             .unwrap()
             .clear_temp_storage();
     }
+
+    fn block(&self) -> Option<BlockHeader> {
+        self.block
+    }
 }
 
-impl<P: Provider> DatabaseRef for SimulationDB<P>
-where
-    P: Provider + Debug + Send + Sync + 'static,
-{
+impl<P: EthRpc + ?Sized + 'static> DatabaseRef for SimulationDB<P> {
     type Error = Box<dyn std::error::Error + Send + Sync>;
 
     /// Retrieves basic information about an account.
@@ -345,6 +548,10 @@ where
         }
         let account_info = self.query_account_info(address)?;
         self.init_account(address, account_info.clone(), None, false);
+        self.queried_accounts
+            .write()
+            .unwrap()
+            .insert(address);
         Ok(Some(account_info))
     }
 
@@ -423,6 +630,10 @@ where
                 let account_info = self.query_account_info(address)?;
                 let storage_value = self.query_storage(address, index)?;
                 self.init_account(address, account_info, None, false);
+                self.queried_accounts
+                    .write()
+                    .unwrap()
+                    .insert(address);
                 let mut account_storage = self.account_storage.write().unwrap();
                 account_storage.set_temp_storage(address, index, storage_value);
                 debug!("This is non-initialised account. Fetched value: {}", storage_value);
@@ -431,19 +642,19 @@ where
         }
     }
 
-    /// If block header is set, returns the hash. Otherwise returns a zero hash
+    /// If block header with a hash is set, returns the hash. Otherwise returns a zero hash
     /// instead of querying a node.
     fn block_hash_ref(&self, _number: u64) -> Result<B256, Self::Error> {
-        match &self.block {
-            Some(header) => Ok(header.hash),
-            None => Ok(B256::ZERO),
-        }
+        Ok(self
+            .block
+            .and_then(|header| header.hash)
+            .unwrap_or(B256::ZERO))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{env, error::Error, str::FromStr};
+    use std::{env, error::Error, str::FromStr, sync::Mutex};
 
     use alloy::{
         providers::{ProviderBuilder, RootProvider},
@@ -451,9 +662,15 @@ mod tests {
     };
     use dotenv::dotenv;
     use rstest::rstest;
-    use tokio::runtime::Runtime;
+    use serde_json::{json, Value};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+        runtime::Runtime,
+    };
 
     use super::*;
+    use crate::testing::MockEthRpc;
 
     fn get_runtime() -> Option<Arc<Runtime>> {
         let runtime = tokio::runtime::Handle::try_current()
@@ -499,13 +716,16 @@ mod tests {
         let mut db = SimulationDB::new(get_client(), get_runtime(), None);
         let block = BlockHeader {
             number: 20308186,
-            hash: B256::from_str(
-                "0x61c51e3640b02ae58a03201be0271e84e02dac8a4826501995cbe4da24174b52",
-            )
-            .unwrap(),
+            hash: Some(
+                B256::from_str(
+                    "0x61c51e3640b02ae58a03201be0271e84e02dac8a4826501995cbe4da24174b52",
+                )
+                .unwrap(),
+            ),
             timestamp: 234,
+            ..Default::default()
         };
-        db.set_block(Some(block));
+        db.set_block(block, false);
         let address = Address::from_str("0x168b93113fe5902c87afaecE348581A1481d0f93").unwrap();
         db.init_account(address, AccountInfo::default(), None, false);
 
@@ -565,7 +785,7 @@ mod tests {
         let update = StateUpdate { storage: Some(new_storage), balance: Some(new_balance) };
         let mut updates = HashMap::default();
         updates.insert(address, update);
-        let new_block = BlockHeader { number: 1, hash: B256::default(), timestamp: 234 };
+        let new_block = BlockHeader::new(1, B256::default(), 234);
 
         let reverse_update = db.update_state(&updates, new_block);
 
@@ -689,4 +909,382 @@ mod tests {
             "Overridden slot of an overridden non-existent account should hold an overriden value."
         );
     }
+
+    #[rstest]
+    fn test_overridden_db_balances() {
+        let db = SimulationDB::new(get_client(), get_runtime(), None);
+        let code = Bytecode::new_raw(vec![0x60, 0x00].into());
+        let contract = Address::from_str("0000000000000000000000000000000000000001").unwrap();
+        let eoa = Address::from_str("0000000000000000000000000000000000000002").unwrap();
+        db.init_account(
+            contract,
+            AccountInfo { balance: U256::from(1), nonce: 3, ..AccountInfo::from_bytecode(code) },
+            None,
+            true,
+        );
+        db.init_account(eoa, AccountInfo::default(), None, true);
+        let storage_overrides = HashMap::new();
+        let balance_overrides =
+            HashMap::from([(contract, U256::from(1_000)), (eoa, U256::from(2_000))]);
+
+        let overridden_db = OverriddenSimulationDB::new(&db, &storage_overrides)
+            .with_balance_overrides(&balance_overrides);
+
+        let info = overridden_db
+            .basic_ref(contract)
+            .unwrap()
+            .unwrap();
+        assert_eq!(info.balance, U256::from(1_000));
+        // Everything but the balance is kept
+        assert_eq!(info.nonce, 3);
+        assert_eq!(
+            info.code_hash,
+            db.basic_ref(contract)
+                .unwrap()
+                .unwrap()
+                .code_hash
+        );
+        assert_eq!(
+            overridden_db
+                .basic_ref(eoa)
+                .unwrap()
+                .unwrap()
+                .balance,
+            U256::from(2_000)
+        );
+        // The wrapped database is unchanged
+        assert_eq!(
+            db.basic_ref(contract)
+                .unwrap()
+                .unwrap()
+                .balance,
+            U256::from(1)
+        );
+    }
+
+    /// Methods and block tags of the requests received by a mock node
+    type NodeRequests = Arc<Mutex<Vec<(String, Value)>>>;
+
+    /// Starts a JSON-RPC node on `runtime` answering account and storage requests. Storage slots
+    /// hold the number of the requested block, so values fetched at different blocks differ.
+    fn mock_node(runtime: &Runtime) -> (Arc<RootProvider<BoxTransport>>, NodeRequests) {
+        let requests = NodeRequests::default();
+        let listener = runtime
+            .block_on(TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let node_requests = requests.clone();
+        runtime.spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve_rpc(stream, node_requests.clone()));
+            }
+        });
+        let client = runtime.block_on(async {
+            ProviderBuilder::new()
+                .on_builtin(&url)
+                .await
+                .unwrap()
+        });
+        (Arc::new(client), requests)
+    }
+
+    async fn serve_rpc(stream: TcpStream, requests: NodeRequests) {
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                if reader
+                    .read_line(&mut line)
+                    .await
+                    .unwrap_or(0) ==
+                    0
+                {
+                    return;
+                }
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader
+                .read_exact(&mut body)
+                .await
+                .unwrap();
+
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            // Batches are recorded as a `batch` entry with their size, followed by their requests
+            let response = match request.as_array() {
+                Some(batch) => {
+                    requests
+                        .lock()
+                        .unwrap()
+                        .push(("batch".to_string(), json!(batch.len())));
+                    Value::Array(
+                        batch
+                            .iter()
+                            .map(|request| rpc_response(request, &requests))
+                            .collect(),
+                    )
+                }
+                None => rpc_response(&request, &requests),
+            }
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            reader
+                .get_mut()
+                .write_all(response.as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    fn rpc_response(request: &Value, requests: &NodeRequests) -> Value {
+        let method = request["method"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let block = request["params"]
+            .as_array()
+            .and_then(|params| params.last().cloned())
+            .unwrap_or_default();
+        let result = match method.as_str() {
+            "eth_getStorageAt" if block.as_str() == Some("latest") => json!("0x0"),
+            "eth_getStorageAt" => block.clone(),
+            "eth_getCode" => json!("0x"),
+            _ => json!("0x1"),
+        };
+        requests
+            .lock()
+            .unwrap()
+            .push((method, block));
+        json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+    }
+
+    fn block_at(number: u64) -> BlockHeader {
+        BlockHeader::new(number, B256::default(), 0)
+    }
+
+    #[test]
+    fn test_block_header_is_after() {
+        assert!(block_at(2).is_after(&block_at(1)));
+        assert!(!block_at(1).is_after(&block_at(1)));
+        assert!(!block_at(1).is_after(&block_at(2)));
+        // Rollup blocks deriving from the same L1 block are ordered by their own number
+        let l2_block = |number| {
+            block_at(number)
+                .with_chain(Chain::Arbitrum)
+                .with_l1_block_number(20)
+        };
+        assert!(l2_block(3).is_after(&l2_block(2)));
+    }
+
+    #[test]
+    fn test_archival_requests_are_block_tagged() {
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let (client, requests) = mock_node(&runtime);
+        let mut db = SimulationDB::new(client, Some(runtime), Some(block_at(16))).archival(true);
+        let address = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        let slot = U256::from(1);
+
+        assert_eq!(
+            db.basic_ref(address)
+                .unwrap()
+                .unwrap()
+                .balance,
+            U256::from(1)
+        );
+        assert_eq!(db.storage_ref(address, slot).unwrap(), U256::from(16));
+        let mut methods: Vec<_> = requests
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|(method, block)| {
+                assert_eq!(block, json!("0x10"), "{method} isn't pinned to the block");
+                method
+            })
+            .collect();
+        methods.sort();
+        assert_eq!(
+            methods,
+            ["eth_getBalance", "eth_getCode", "eth_getStorageAt", "eth_getTransactionCount"]
+        );
+
+        // Without clearing the cache, values fetched at the previous block are kept
+        db.set_block(block_at(24), false);
+        assert_eq!(db.storage_ref(address, slot).unwrap(), U256::from(16));
+        assert!(requests.lock().unwrap().is_empty());
+
+        db.set_block(block_at(32), true);
+        assert_eq!(db.storage_ref(address, slot).unwrap(), U256::from(32));
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests
+            .iter()
+            .all(|(_, block)| block == &json!("0x20")));
+    }
+
+    #[test]
+    fn test_set_block_keeps_initialised_accounts() {
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let (client, requests) = mock_node(&runtime);
+        let mut db = SimulationDB::new(client, Some(runtime), Some(block_at(16)));
+        let address = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        let storage = HashMap::from([(U256::from(1), U256::from(42))]);
+        db.init_account(address, AccountInfo::default(), Some(storage), false);
+
+        db.set_block(block_at(32), true);
+
+        assert_eq!(
+            db.storage_ref(address, U256::from(1))
+                .unwrap(),
+            U256::from(42)
+        );
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_archival_requires_block() {
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let (client, requests) = mock_node(&runtime);
+        let db = SimulationDB::new(client, Some(runtime), None).archival(true);
+        let address = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+
+        assert!(db.basic_ref(address).is_err());
+        assert!(db
+            .storage_ref(address, U256::from(1))
+            .is_err());
+        assert!(requests.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_injected_client() {
+        let address = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        let rpc = Arc::new(
+            MockEthRpc::new()
+                .with_code(address, vec![0x60, 0x00])
+                .with_balance(address, U256::from(7))
+                .with_storage(address, U256::from(1), U256::from(42)),
+        );
+        let client: Arc<dyn EthRpc> = rpc.clone();
+        let db = SimulationDB::new(client, None, Some(block_at(16)));
+
+        let info = db.basic_ref(address).unwrap().unwrap();
+
+        assert_eq!(info.balance, U256::from(7));
+        assert_eq!(
+            info.code
+                .unwrap()
+                .original_bytes()
+                .to_vec(),
+            vec![0x60, 0x00]
+        );
+        assert_eq!(
+            db.storage_ref(address, U256::from(1))
+                .unwrap(),
+            U256::from(42)
+        );
+        assert_eq!(rpc.calls("get_code"), 1);
+        assert_eq!(rpc.calls("get_storage_at"), 1);
+    }
+
+    #[test]
+    fn test_prefetch_storage_batches_requests() {
+        let address = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        let rpc = Arc::new(
+            MockEthRpc::new()
+                .with_storage(address, U256::from(1), U256::from(42))
+                .with_storage(address, U256::from(2), U256::from(43)),
+        );
+        let db = SimulationDB::new(rpc.clone(), None, Some(block_at(16)));
+        let slots = [1, 2, 3, 1].map(U256::from);
+
+        db.prefetch_storage(address, &slots)
+            .unwrap();
+
+        assert_eq!(rpc.calls("get_storage_batch"), 1);
+        assert_eq!(
+            db.storage_ref(address, U256::from(1))
+                .unwrap(),
+            U256::from(42)
+        );
+        assert_eq!(
+            db.storage_ref(address, U256::from(2))
+                .unwrap(),
+            U256::from(43)
+        );
+        assert_eq!(
+            db.storage_ref(address, U256::from(3))
+                .unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(
+            db.storage_ref(address, U256::from(4))
+                .unwrap(),
+            U256::ZERO
+        );
+        assert_eq!(rpc.calls("get_storage_at"), 1);
+        assert_eq!(
+            db.storage_fetch_stats(),
+            StorageFetchStats { individual: 1, batches: 1, batched_slots: 3 }
+        );
+        // Cached slots aren't requested again
+        db.prefetch_storage(address, &slots)
+            .unwrap();
+        assert_eq!(rpc.calls("get_storage_batch"), 1);
+        assert_eq!(rpc.calls("get_balance"), 1);
+    }
+
+    #[test]
+    fn test_prefetch_storage_skips_mocked_accounts() {
+        let address = Address::from_str("0x0000000000000000000000000000000000005678").unwrap();
+        let rpc = Arc::new(MockEthRpc::new());
+        let db = SimulationDB::new(rpc.clone(), None, None);
+        db.init_account(address, AccountInfo::default(), None, true);
+
+        db.prefetch_storage(address, &[U256::from(1)])
+            .unwrap();
+
+        assert_eq!(rpc.calls("get_storage_batch"), 0);
+        assert_eq!(db.storage_fetch_stats(), StorageFetchStats::default());
+    }
+
+    #[test]
+    fn test_prefetch_storage_sends_json_rpc_batch() {
+        let runtime = Arc::new(Runtime::new().unwrap());
+        let (client, requests) = mock_node(&runtime);
+        let db = SimulationDB::new(client, Some(runtime), Some(block_at(16)));
+        let address = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        db.init_account(address, AccountInfo::default(), None, false);
+
+        db.prefetch_storage(address, &[1, 2, 3].map(U256::from))
+            .unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                ("batch".to_string(), json!(3)),
+                ("eth_getStorageAt".to_string(), json!("0x10")),
+                ("eth_getStorageAt".to_string(), json!("0x10")),
+                ("eth_getStorageAt".to_string(), json!("0x10")),
+            ]
+        );
+        assert_eq!(
+            db.storage_ref(address, U256::from(2))
+                .unwrap(),
+            U256::from(16)
+        );
+        assert_eq!(requests.lock().unwrap().len(), 4);
+    }
 }
@@ -12,9 +12,10 @@ use thiserror::Error;
 use tracing::{debug, error, info, instrument, warn};
 
 use crate::evm::{
-    account_storage::{AccountStorage, StateUpdate},
+    account_storage::{AccountSnapshot, AccountStorage, StateUpdate},
     engine_db::{engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader},
-    tycho_models::{AccountUpdate, ChangeType},
+    metrics::recorder,
+    tycho_models::{AccountUpdate, Chain, ChangeType},
 };
 
 /// Perform bytecode analysis on the code of an account.
@@ -55,6 +56,9 @@ pub struct PreCachedDBInner {
     accounts: AccountStorage,
     /// Current block
     block: Option<BlockHeader>,
+    /// Chain this database tracks. If set, updates of accounts on other chains are ignored, so
+    /// the current block always belongs to this chain.
+    chain: Option<Chain>,
 }
 
 #[derive(Clone, Debug)]
@@ -74,19 +78,62 @@ impl PreCachedDB {
             inner: Arc::new(RwLock::new(PreCachedDBInner {
                 accounts: AccountStorage::new(),
                 block: None,
+                chain: None,
             })),
         })
     }
 
+    /// Create a new PreCachedDB instance tracking a single chain.
+    ///
+    /// Account updates for any other chain are skipped, which allows running several instances
+    /// for different chains side by side in one process.
+    pub fn for_chain(chain: Chain) -> Result<Self, PreCachedDBError> {
+        Ok(PreCachedDB {
+            inner: Arc::new(RwLock::new(PreCachedDBInner {
+                accounts: AccountStorage::new(),
+                block: None,
+                chain: Some(chain),
+            })),
+        })
+    }
+
+    /// Returns the chain this database is bound to, if any.
+    pub fn chain(&self) -> Option<Chain> {
+        self.inner.read().unwrap().chain
+    }
+
     #[instrument(skip_all)]
     pub fn update(&self, account_updates: Vec<AccountUpdate>, block: Option<BlockHeader>) {
         // Hold the write lock for the duration of the function so that no other thread can
         // write to the storage.
         let mut write_guard = self.inner.write().unwrap();
 
+        let block = block.map(|block| match write_guard.block {
+            // Keep the hash of the current block if the update doesn't provide one
+            Some(current) if block.hash.is_none() && current.number == block.number => {
+                BlockHeader { hash: current.hash, ..block }
+            }
+            _ => block,
+        });
+        if let (Some(current), Some(block)) = (write_guard.block, block) {
+            if current.is_after(&block) {
+                debug!(
+                    current = current.number,
+                    block = block.number,
+                    "Moving back to an older block"
+                );
+            }
+        }
         write_guard.block = block;
 
         for update in account_updates {
+            if write_guard
+                .chain
+                .is_some_and(|chain| chain != update.chain)
+            {
+                warn!(%update.address, chain = %update.chain, "Skipping update for another chain");
+                continue;
+            }
             match update.change {
                 ChangeType::Update => {
                     info!(%update.address, "Updating account");
@@ -223,20 +270,44 @@ impl PreCachedDB {
             .clone()
     }
 
-    /// If block is set, returns the number. Otherwise returns None.
-    pub fn block_number(&self) -> Option<u64> {
+    /// Captures the current state of a single account.
+    ///
+    /// Take a snapshot before and after applying updates and compare them with
+    /// `AccountSnapshot::diff` to see exactly what an update changed.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the account.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the account is not present.
+    pub fn snapshot_account(&self, address: &Address) -> Option<AccountSnapshot> {
         self.inner
             .read()
             .unwrap()
-            .block
-            .as_ref()
-            .map(|header| header.number)
+            .accounts
+            .snapshot_account(address)
+    }
+
+    /// If block is set, returns the number. Otherwise returns None.
+    pub fn block_number(&self) -> Option<u64> {
+        self.block().map(|header| header.number)
+    }
+
+    /// If block is set, returns its header. Otherwise returns None.
+    pub fn block(&self) -> Option<BlockHeader> {
+        self.inner.read().unwrap().block
     }
 }
 
 impl EngineDatabaseInterface for PreCachedDB {
     type Error = String;
 
+    fn chain(&self) -> Option<Chain> {
+        PreCachedDB::chain(self)
+    }
+
     /// Sets up a single account
     ///
     /// Full control over setting up an accounts. Allows to set up EOAs as well as smart contracts.
@@ -265,6 +336,10 @@ impl EngineDatabaseInterface for PreCachedDB {
     fn clear_temp_storage(&mut self) {
         debug!("Temp storage in TychoDB is never set, nothing to clear");
     }
+
+    fn block(&self) -> Option<BlockHeader> {
+        PreCachedDB::block(self)
+    }
 }
 
 impl DatabaseRef for PreCachedDB {
@@ -282,12 +357,15 @@ impl DatabaseRef for PreCachedDB {
     /// Returns a `Result` containing the account information or an error if the account is not
     /// found.
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        self.inner
+        let info = self
+            .inner
             .read()
             .unwrap()
             .accounts
             .get_account_info(&address)
-            .map(|acc| Some(acc.clone()))
+            .cloned();
+        recorder().on_db_read(info.is_some());
+        info.map(Some)
             .ok_or(PreCachedDBError::MissingAccount(address))
     }
 
@@ -317,6 +395,7 @@ impl DatabaseRef for PreCachedDB {
             .get_storage(&address, &index)
         {
             debug!(%address, %index, %storage_value, "Got value locally");
+            recorder().on_db_read(true);
             Ok(storage_value)
         } else {
             // At this point we either don't know this address or we don't have anything at this
@@ -327,21 +406,26 @@ impl DatabaseRef for PreCachedDB {
                 // As we only store non-zero values, if the account is present it means this
                 // slot is zero.
                 debug!(%address, %index, "Account found, but slot is zero");
+                recorder().on_db_read(true);
                 Ok(U256::ZERO)
             } else {
                 // At this point we know we don't have data for this address.
                 debug!(%address, %index, "Account not found");
+                recorder().on_db_read(false);
                 Err(PreCachedDBError::MissingAccount(address))
             }
         }
     }
 
-    /// If block header is set, returns the hash. Otherwise returns a zero hash.
+    /// If block header with a hash is set, returns the hash. Otherwise returns a zero hash.
     fn block_hash_ref(&self, _number: u64) -> Result<B256, Self::Error> {
-        match self.inner.read().unwrap().block {
-            Some(header) => Ok(header.hash),
-            None => Ok(B256::default()),
-        }
+        Ok(self
+            .inner
+            .read()
+            .unwrap()
+            .block
+            .and_then(|header| header.hash)
+            .unwrap_or_default())
     }
 }
 
@@ -362,6 +446,7 @@ mod tests {
             inner: Arc::new(RwLock::new(PreCachedDBInner {
                 accounts: AccountStorage::new(),
                 block: None,
+                chain: None,
             })),
         }
     }
@@ -486,6 +571,34 @@ mod tests {
         Ok(())
     }
 
+    #[rstest]
+    fn test_snapshot_account_diff(mut mock_db: PreCachedDB) -> Result<(), Box<dyn Error>> {
+        let address = Address::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc")?;
+        mock_db.init_account(address, AccountInfo::default(), None, false);
+        let before = mock_db
+            .snapshot_account(&address)
+            .expect("account is present");
+
+        let slot = U256::from(7);
+        let update =
+            StateUpdate { storage: Some(HashMap::from([(slot, U256::from(1))])), balance: None };
+        mock_db.update_state(&HashMap::from([(address, update)]), BlockHeader::default());
+        let after = mock_db
+            .snapshot_account(&address)
+            .expect("account is present");
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.balance, None);
+        assert_eq!(diff.slots.len(), 1);
+        assert_eq!(diff.slots[&slot].old, None);
+        assert_eq!(diff.slots[&slot].new, Some(U256::from(1)));
+        assert!(mock_db
+            .snapshot_account(&Address::ZERO)
+            .is_none());
+
+        Ok(())
+    }
+
     #[rstest]
     #[tokio::test]
     async fn test_block_number_getter(mut mock_db: PreCachedDB) -> Result<(), Box<dyn Error>> {
@@ -518,6 +631,7 @@ mod tests {
             inner: Arc::new(RwLock::new(PreCachedDBInner {
                 accounts: AccountStorage::new(),
                 block: None,
+                chain: None,
             })),
         };
 
@@ -572,6 +686,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_update_skips_other_chains() {
+        let db = PreCachedDB::for_chain(Chain::Arbitrum).unwrap();
+        let eth_address = Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap();
+        let arb_address = Address::from_str("0x4752ba5DBc23f44D87826276BF6Fd6b1C372aD24").unwrap();
+        let creation = |address, chain| {
+            AccountUpdate::new(
+                address,
+                chain,
+                HashMap::new(),
+                Some(U256::from(500)),
+                Some(Vec::<u8>::new()),
+                ChangeType::Creation,
+            )
+        };
+
+        db.update(
+            vec![creation(eth_address, Chain::Ethereum), creation(arb_address, Chain::Arbitrum)],
+            None,
+        );
+
+        assert_eq!(db.chain(), Some(Chain::Arbitrum));
+        assert!(db
+            .get_account_storage()
+            .get_account_info(&eth_address)
+            .is_none());
+        assert!(db
+            .get_account_storage()
+            .get_account_info(&arb_address)
+            .is_some());
+    }
+
+    #[test]
+    fn test_update_keeps_hash_of_same_block() {
+        let db = PreCachedDB::for_chain(Chain::Ethereum).unwrap();
+        let hash = B256::repeat_byte(1);
+
+        db.update(vec![], Some(BlockHeader::new(1, hash, 0)));
+        db.update(vec![], Some(BlockHeader { number: 1, ..Default::default() }));
+        assert_eq!(db.block_hash_ref(1).unwrap(), hash);
+
+        db.update(vec![], Some(BlockHeader { number: 2, ..Default::default() }));
+        assert_eq!(db.block_number(), Some(2));
+        assert_eq!(db.block_hash_ref(2).unwrap(), B256::ZERO);
+    }
+
     /// This test requires a running TychoDB instance.
     ///
     /// To run this test, start TychoDB with the following command:
@@ -0,0 +1,249 @@
+//! ERC20 storage overrides
+//!
+//! Computes the storage slots of ERC20 balances and allowances, to fund accounts in simulations
+//! without real transfers, see `SimulationParameters::fund_caller`. The slots of a token are
+//! found with `protocol::vm::erc20_token::discover_slots`.
+use std::collections::HashMap;
+
+use alloy_primitives::{Address, U256};
+use lazy_static::lazy_static;
+
+use super::{ContractCompiler, SlotId};
+
+lazy_static! {
+    /// Address protocols commonly use for the chain's native token, e.g. ETH on Ethereum
+    pub static ref NATIVE_TOKEN_SENTINEL: Address = Address::from_slice(
+        &hex::decode("EeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE")
+            .expect("Invalid string for native token sentinel"),
+    );
+}
+
+/// Whether `address` stands for the chain's native token rather than an ERC20 contract.
+///
+/// Protocols use either the zero address or `NATIVE_TOKEN_SENTINEL`. Native balances are account
+/// balances, they can't be overwritten in a token's storage.
+pub fn is_native_token(address: &Address) -> bool {
+    *address == Address::ZERO || *address == *NATIVE_TOKEN_SENTINEL
+}
+
+/// Get storage slot index of a value stored at a certain key in a mapping
+///
+/// # Arguments
+///
+/// * `key`: Key in a mapping. Can be any H160 value (such as an address).
+/// * `mapping_slot`: An `U256` representing the storage slot at which the mapping itself is stored.
+///   See the examples for more explanation.
+/// * `compiler`: The compiler with which the target contract was compiled. Solidity and Vyper
+///   handle maps differently.
+///
+/// # Returns
+///
+/// An `U256` representing the  index of a storage slot where the value at the given
+/// key is stored.
+///
+/// # Examples
+///
+/// If a mapping is declared as a first variable in Solidity code, its storage slot
+/// is 0 (e.g. `balances` in our mocked ERC20 contract). Here's how to compute
+/// a storage slot where balance of a given account is stored:
+///
+/// ```
+/// use alloy_primitives::{U256, Address};
+/// use tycho_simulation::evm::ContractCompiler;
+/// use tycho_simulation::evm::erc20::get_storage_slot_index_at_key;
+/// let address: Address = "0xC63135E4bF73F637AF616DFd64cf701866BB2628".parse().expect("Invalid address");
+/// get_storage_slot_index_at_key(address, U256::from(0), ContractCompiler::Solidity);
+/// ```
+///
+/// For nested mappings, we need to apply the function twice. An example of this is
+/// `allowances` in ERC20. It is a mapping of form:
+/// `HashMap<Owner, HashMap<Spender, U256>>`. In our mocked ERC20 contract, `allowances`
+/// is a second variable, so it is stored at slot 1. Here's how to get a storage slot
+/// where an allowance of `address_spender` to spend `address_owner`'s money is stored:
+///
+/// ```
+/// use alloy_primitives::{U256, Address};
+/// use tycho_simulation::evm::ContractCompiler;
+/// use tycho_simulation::evm::erc20::get_storage_slot_index_at_key;
+/// let address_spender: Address = "0xC63135E4bF73F637AF616DFd64cf701866BB2628".parse().expect("Invalid address");
+/// let address_owner: Address = "0x6F4Feb566b0f29e2edC231aDF88Fe7e1169D7c05".parse().expect("Invalid address");
+/// get_storage_slot_index_at_key(address_spender, get_storage_slot_index_at_key(address_owner, U256::from(1), ContractCompiler::Solidity), ContractCompiler::Solidity);
+/// ```
+///
+/// # See Also
+///
+/// [Solidity Storage Layout documentation](https://docs.soliditylang.org/en/v0.8.13/internals/layout_in_storage.html#mappings-and-dynamic-arrays)
+pub fn get_storage_slot_index_at_key(
+    key: Address,
+    mapping_slot: SlotId,
+    compiler: ContractCompiler,
+) -> SlotId {
+    let mut key_bytes = key.as_slice().to_vec();
+    if key_bytes.len() < 32 {
+        let padding = vec![0u8; 32 - key_bytes.len()];
+        key_bytes.splice(0..0, padding); // Prepend zeros to the start
+    }
+
+    let mapping_slot_bytes: [u8; 32] = mapping_slot.to_be_bytes();
+    compiler.compute_map_slot(&mapping_slot_bytes, &key_bytes)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// A struct representing ERC20 tokens storage slots.
+pub struct ERC20Slots {
+    // Base slot for the balance map
+    pub balance_map: SlotId,
+    // Base slot for the allowance map
+    pub allowance_map: SlotId,
+}
+
+impl ERC20Slots {
+    pub fn new(balance: SlotId, allowance: SlotId) -> Self {
+        Self { balance_map: balance, allowance_map: allowance }
+    }
+}
+
+pub type Overwrites = HashMap<SlotId, U256>;
+
+pub struct ERC20OverwriteFactory {
+    token_address: Address,
+    overwrites: Overwrites,
+    balance_slot: SlotId,
+    allowance_slot: SlotId,
+    compiler: ContractCompiler,
+}
+
+impl ERC20OverwriteFactory {
+    pub fn new(
+        token_address: Address,
+        token_slots: ERC20Slots,
+        compiler: ContractCompiler,
+    ) -> Self {
+        ERC20OverwriteFactory {
+            token_address,
+            overwrites: HashMap::new(),
+            balance_slot: token_slots.balance_map,
+            allowance_slot: token_slots.allowance_map,
+            compiler,
+        }
+    }
+
+    pub fn set_balance(&mut self, balance: U256, owner: Address) {
+        let storage_index = get_storage_slot_index_at_key(owner, self.balance_slot, self.compiler);
+        self.overwrites
+            .insert(storage_index, balance);
+    }
+
+    pub fn set_allowance(&mut self, allowance: U256, spender: Address, owner: Address) {
+        let owner_slot = get_storage_slot_index_at_key(owner, self.allowance_slot, self.compiler);
+        let storage_index = get_storage_slot_index_at_key(spender, owner_slot, self.compiler);
+        self.overwrites
+            .insert(storage_index, allowance);
+    }
+
+    #[cfg(test)]
+    pub fn set_total_supply(&mut self, supply: U256) {
+        let total_supply_slot = SlotId::from(2);
+        self.overwrites
+            .insert(total_supply_slot, supply);
+    }
+
+    /// Returns the storage overwrites of the token.
+    ///
+    /// Native tokens (see `is_native_token`) have no storage to overwrite, so there are no
+    /// overwrites for them. Their balances need to be set as account balances instead.
+    pub fn get_overwrites(&self) -> HashMap<Address, Overwrites> {
+        let mut result = HashMap::new();
+        if !is_native_token(&self.token_address) {
+            result.insert(self.token_address, self.overwrites.clone());
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_factory() -> ERC20OverwriteFactory {
+        let token_address: Address = Address::from_slice(
+            &hex::decode("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")
+                .expect("Invalid token address"),
+        );
+
+        let slots = ERC20Slots::new(SlotId::from(5), SlotId::from(6));
+        ERC20OverwriteFactory::new(token_address, slots, ContractCompiler::Solidity)
+    }
+
+    #[test]
+    fn test_set_balance() {
+        let mut factory = setup_factory();
+        let owner = Address::random();
+        let balance = U256::from(1000);
+
+        factory.set_balance(balance, owner);
+
+        assert_eq!(factory.overwrites.len(), 1);
+        assert!(factory
+            .overwrites
+            .values()
+            .any(|&v| v == balance));
+    }
+
+    #[test]
+    fn test_set_allowance() {
+        let mut factory = setup_factory();
+        let owner = Address::random();
+        let spender = Address::random();
+        let allowance = U256::from(500);
+
+        factory.set_allowance(allowance, spender, owner);
+
+        assert_eq!(factory.overwrites.len(), 1);
+        assert!(factory
+            .overwrites
+            .values()
+            .any(|&v| v == allowance));
+    }
+
+    #[test]
+    fn test_set_total_supply() {
+        let mut factory = setup_factory();
+        let supply = U256::from(1_000_000);
+
+        factory.set_total_supply(supply);
+
+        assert_eq!(factory.overwrites.len(), 1);
+        let total_supply_slot = SlotId::from(2);
+        assert_eq!(factory.overwrites[&total_supply_slot], supply);
+    }
+
+    #[test]
+    fn test_get_overwrites() {
+        let mut factory = setup_factory();
+        let supply = U256::from(1_000_000);
+        factory.set_total_supply(supply);
+
+        let overwrites = factory.get_overwrites();
+
+        assert_eq!(overwrites.len(), 1);
+        assert!(overwrites.contains_key(&factory.token_address));
+        assert_eq!(overwrites[&factory.token_address].len(), 1);
+        let total_supply_slot = SlotId::from(2);
+        assert_eq!(overwrites[&factory.token_address][&total_supply_slot], supply);
+    }
+
+    #[test]
+    fn test_get_overwrites_native_token() {
+        for token in [Address::ZERO, *NATIVE_TOKEN_SENTINEL] {
+            let mut factory = ERC20OverwriteFactory::new(
+                token,
+                ERC20Slots::new(SlotId::from(0), SlotId::from(1)),
+                ContractCompiler::Solidity,
+            );
+            factory.set_balance(U256::from(1000), Address::random());
+
+            assert!(factory.get_overwrites().is_empty());
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Calldata encoding of swap sequences
+//!
+//! A `SwapEncoder` turns a `SwapSequence`, e.g. an opportunity found by
+//! `ProtoGraph::search_opportunities`, into a transaction to a router contract executing it:
+//!  - `UniswapV2Encoder` swaps through Uniswap V2 pairs with the V2 router.
+//!  - `UniswapV3Encoder` swaps through Uniswap V3 pools with the Universal Router.
+//!
+//! The sender needs to hold and have approved the first token of the sequence, the encoders don't
+//! handle native ETH.
+use alloy_primitives::{keccak256, Address, U256};
+use num_bigint::BigUint;
+use thiserror::Error;
+
+use crate::{
+    evm::protocol::{u256_num::biguint_to_u256, utils::bytes_to_address},
+    graph::protograph::SwapSequence,
+    models::Token,
+};
+
+pub mod uniswap_v2;
+pub mod uniswap_v3;
+
+pub use uniswap_v2::UniswapV2Encoder;
+pub use uniswap_v3::UniswapV3Encoder;
+
+/// Precision slippage tolerances are applied with
+const SLIPPAGE_PRECISION: u64 = 1_000_000;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum EncodingError {
+    #[error("Swap sequence is empty")]
+    EmptySequence,
+    #[error("Swap {index} spends {token_in} but the previous swap returns {token_out}")]
+    DisconnectedSwaps { index: usize, token_in: String, token_out: String },
+    #[error("Invalid swap: {0}")]
+    InvalidSwap(String),
+    #[error("Invalid parameters: {0}")]
+    InvalidParameters(String),
+}
+
+/// Parameters of the encoded transaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodingParams {
+    /// Receiver of the last token of the sequence
+    pub recipient: Address,
+    /// Minimum amount of the last token to receive, the transaction reverts below it
+    pub min_amount_out: BigUint,
+    /// Tolerated slippage as a ratio of the sequence's expected amount out, e.g. 0.005 for 0.5%.
+    /// If set, the minimum amount out is raised to `amount_out * (1 - slippage)`.
+    pub slippage: Option<f64>,
+    /// Unix timestamp after which the transaction reverts
+    pub deadline: u64,
+}
+
+impl EncodingParams {
+    pub fn new(recipient: Address, min_amount_out: BigUint, deadline: u64) -> Self {
+        Self { recipient, min_amount_out, slippage: None, deadline }
+    }
+
+    pub fn with_slippage(mut self, slippage: f64) -> Self {
+        self.slippage = Some(slippage);
+        self
+    }
+
+    /// Returns the minimum amount out of `swaps`: the larger of `min_amount_out` and the expected
+    /// amount out reduced by `slippage`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EncodingError::InvalidParameters` if the slippage isn't between 0 and 1.
+    pub fn min_amount_out(&self, swaps: &SwapSequence) -> Result<BigUint, EncodingError> {
+        let Some(slippage) = self.slippage else {
+            return Ok(self.min_amount_out.clone());
+        };
+        if !(0.0..=1.0).contains(&slippage) {
+            return Err(EncodingError::InvalidParameters(format!(
+                "Slippage must be between 0 and 1, got {slippage}"
+            )));
+        }
+        let expected = swaps
+            .amount_out()
+            .ok_or(EncodingError::EmptySequence)?;
+        let kept = SLIPPAGE_PRECISION - (slippage * SLIPPAGE_PRECISION as f64).round() as u64;
+        let from_slippage = expected * kept / SLIPPAGE_PRECISION;
+        Ok(from_slippage.max(self.min_amount_out.clone()))
+    }
+}
+
+/// A transaction executing a swap sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedSwap {
+    /// The contract to call
+    pub to: Address,
+    pub calldata: Vec<u8>,
+    /// Native ETH to send along
+    pub value: U256,
+}
+
+/// Encodes swap sequences into calls to a router contract
+pub trait SwapEncoder {
+    /// Encodes a transaction swapping the `amount_in` of the first swap through all swaps of
+    /// `swaps`, sending the output to `params.recipient`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EncodingError` if the sequence is empty, consecutive swaps don't share a token
+    /// or a swap can't be executed by the router.
+    fn encode(
+        &self,
+        swaps: &SwapSequence,
+        params: &EncodingParams,
+    ) -> Result<EncodedSwap, EncodingError>;
+}
+
+/// Checks that `swaps` isn't empty and each swap spends the token the previous one returns.
+pub fn validate_sequence(swaps: &SwapSequence) -> Result<(), EncodingError> {
+    if swaps.swaps.is_empty() {
+        return Err(EncodingError::EmptySequence);
+    }
+    for (index, pair) in swaps.swaps.windows(2).enumerate() {
+        if pair[0].token_out != pair[1].token_in {
+            return Err(EncodingError::DisconnectedSwaps {
+                index: index + 1,
+                token_in: pair[1].token_in.symbol.clone(),
+                token_out: pair[0].token_out.symbol.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the first 4 bytes of the hash of a function signature.
+fn selector(signature: &str) -> [u8; 4] {
+    keccak256(signature.as_bytes())[..4]
+        .try_into()
+        .expect("selector is 4 bytes")
+}
+
+fn token_address(token: &Token) -> Result<Address, EncodingError> {
+    bytes_to_address(&token.address).map_err(|e| EncodingError::InvalidSwap(e.to_string()))
+}
+
+fn to_u256(amount: &BigUint) -> Result<U256, EncodingError> {
+    if amount.bits() > 256 {
+        return Err(EncodingError::InvalidSwap(format!("Amount {amount} exceeds 256 bits")));
+    }
+    Ok(biguint_to_u256(amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        graph::protograph::Swap,
+        testing::{symbol_address, token},
+    };
+
+    pub(super) fn swap(token_in: &str, token_out: &str, amount_in: u64, amount_out: u64) -> Swap {
+        Swap {
+            pool: symbol_address(&format!("{token_in}/{token_out}")),
+            token_in: token(token_in),
+            token_out: token(token_out),
+            amount_in: BigUint::from(amount_in),
+            amount_out: BigUint::from(amount_out),
+            fee: 0.003,
+        }
+    }
+
+    #[test]
+    fn test_validate_sequence() {
+        let connected = SwapSequence {
+            swaps: vec![swap("A", "B", 10, 20), swap("B", "C", 20, 30)],
+            gas: BigUint::default(),
+        };
+        assert_eq!(validate_sequence(&connected), Ok(()));
+
+        let disconnected = SwapSequence {
+            swaps: vec![swap("A", "B", 10, 20), swap("C", "D", 20, 30)],
+            gas: BigUint::default(),
+        };
+        assert_eq!(
+            validate_sequence(&disconnected),
+            Err(EncodingError::DisconnectedSwaps {
+                index: 1,
+                token_in: "C".to_string(),
+                token_out: "B".to_string()
+            })
+        );
+
+        let empty = SwapSequence { swaps: Vec::new(), gas: BigUint::default() };
+        assert_eq!(validate_sequence(&empty), Err(EncodingError::EmptySequence));
+    }
+
+    #[test]
+    fn test_min_amount_out() {
+        let swaps =
+            SwapSequence { swaps: vec![swap("A", "B", 10, 10_000)], gas: BigUint::default() };
+        let params = EncodingParams::new(Address::ZERO, BigUint::from(9_000u64), 0);
+        assert_eq!(params.min_amount_out(&swaps), Ok(BigUint::from(9_000u64)));
+
+        // The larger of both minimums applies
+        let params = params.with_slippage(0.005);
+        assert_eq!(params.min_amount_out(&swaps), Ok(BigUint::from(9_950u64)));
+        let params = EncodingParams { min_amount_out: BigUint::from(9_990u64), ..params };
+        assert_eq!(params.min_amount_out(&swaps), Ok(BigUint::from(9_990u64)));
+
+        let params = params.with_slippage(1.5);
+        assert!(matches!(params.min_amount_out(&swaps), Err(EncodingError::InvalidParameters(_))));
+    }
+}
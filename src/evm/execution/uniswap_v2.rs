@@ -0,0 +1,114 @@
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolValue;
+
+use super::{
+    selector, to_u256, token_address, validate_sequence, EncodedSwap, EncodingError,
+    EncodingParams, SwapEncoder,
+};
+use crate::graph::protograph::SwapSequence;
+
+/// Signature of the V2 router function swapping an exact amount in along a path
+const SWAP_EXACT_TOKENS_FOR_TOKENS: &str =
+    "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)";
+
+/// Encodes swaps through Uniswap V2 pairs as a call to the Uniswap V2 router
+///
+/// The router transfers the input directly to the first pair, each pair then sends its output
+/// directly to the next one. Pairs are looked up by the router from their tokens, so the pool of
+/// each swap must be the factory's pair of its tokens, e.g. Uniswap V2 pools for the Uniswap V2
+/// router or SushiSwap pools for the SushiSwap router.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniswapV2Encoder {
+    router: Address,
+}
+
+impl UniswapV2Encoder {
+    pub fn new(router: Address) -> Self {
+        Self { router }
+    }
+}
+
+impl SwapEncoder for UniswapV2Encoder {
+    fn encode(
+        &self,
+        swaps: &SwapSequence,
+        params: &EncodingParams,
+    ) -> Result<EncodedSwap, EncodingError> {
+        validate_sequence(swaps)?;
+        let amount_in = to_u256(&swaps.swaps[0].amount_in)?;
+        let min_amount_out = to_u256(&params.min_amount_out(swaps)?)?;
+        let path = std::iter::once(&swaps.swaps[0].token_in)
+            .chain(
+                swaps
+                    .swaps
+                    .iter()
+                    .map(|swap| &swap.token_out),
+            )
+            .map(token_address)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut calldata = selector(SWAP_EXACT_TOKENS_FOR_TOKENS).to_vec();
+        calldata.extend(
+            (amount_in, min_amount_out, path, params.recipient, U256::from(params.deadline))
+                .abi_encode_params(),
+        );
+        Ok(EncodedSwap { to: self.router, calldata, value: U256::ZERO })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use alloy_primitives::hex;
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{evm::execution::tests::swap, testing::token};
+
+    #[test]
+    fn test_encode() {
+        let router = Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap();
+        let recipient = Address::from_str("0x0000000000000000000000000000000000000bee").unwrap();
+        let swaps = SwapSequence {
+            swaps: vec![swap("A", "B", 1_000, 2_000), swap("B", "C", 2_000, 3_000)],
+            gas: BigUint::default(),
+        };
+        let params = EncodingParams::new(recipient, BigUint::from(2_900u64), 1_700_000_000);
+
+        let encoded = UniswapV2Encoder::new(router)
+            .encode(&swaps, &params)
+            .unwrap();
+
+        assert_eq!(encoded.to, router);
+        assert_eq!(encoded.value, U256::ZERO);
+        assert_eq!(encoded.calldata[..4], hex!("38ed1739"));
+        let (amount_in, min_out, path, to, deadline) =
+            <(U256, U256, Vec<Address>, Address, U256)>::abi_decode_params(
+                &encoded.calldata[4..],
+                true,
+            )
+            .unwrap();
+        assert_eq!(amount_in, U256::from(1_000));
+        assert_eq!(min_out, U256::from(2_900));
+        let expected_path = ["A", "B", "C"]
+            .map(|symbol| Address::from_slice(&token(symbol).address))
+            .to_vec();
+        assert_eq!(path, expected_path);
+        assert_eq!(to, recipient);
+        assert_eq!(deadline, U256::from(1_700_000_000));
+    }
+
+    #[test]
+    fn test_encode_rejects_disconnected_swaps() {
+        let swaps = SwapSequence {
+            swaps: vec![swap("A", "B", 1_000, 2_000), swap("C", "D", 2_000, 3_000)],
+            gas: BigUint::default(),
+        };
+        let params = EncodingParams::new(Address::ZERO, BigUint::default(), 0);
+
+        let res = UniswapV2Encoder::new(Address::ZERO).encode(&swaps, &params);
+
+        assert!(matches!(res, Err(EncodingError::DisconnectedSwaps { index: 1, .. })));
+    }
+}
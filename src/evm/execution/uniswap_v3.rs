@@ -0,0 +1,184 @@
+use alloy_primitives::{Address, Bytes, U256};
+use alloy_sol_types::SolValue;
+
+use super::{
+    selector, to_u256, token_address, validate_sequence, EncodedSwap, EncodingError,
+    EncodingParams, SwapEncoder,
+};
+use crate::graph::protograph::{Swap, SwapSequence};
+
+/// Signature of the Universal Router function executing commands before a deadline
+const EXECUTE: &str = "execute(bytes,bytes[],uint256)";
+
+/// Universal Router command swapping an exact amount in through V3 pools
+const V3_SWAP_EXACT_IN: u8 = 0x00;
+
+/// Largest fee a V3 path can encode, in hundredths of a basis point
+const MAX_FEE: u32 = (1 << 24) - 1;
+
+/// Encodes swaps through Uniswap V3 pools as a `V3_SWAP_EXACT_IN` command of the Universal
+/// Router
+///
+/// The swaps are encoded as a single path of packed tokens and fees:
+/// `token_0 (20 bytes) | fee_0 (3 bytes) | token_1 | ... | token_n`. Pools are looked up by the
+/// router from their tokens and fee, the fee of each swap is taken from `Swap::fee`.
+///
+/// The input is pulled from the sender with Permit2, which the router needs to be approved on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UniswapV3Encoder {
+    router: Address,
+}
+
+impl UniswapV3Encoder {
+    pub fn new(router: Address) -> Self {
+        Self { router }
+    }
+
+    /// Returns the packed V3 path of `swaps`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EncodingError` if the sequence is invalid or a fee can't be encoded.
+    pub fn encode_path(swaps: &SwapSequence) -> Result<Vec<u8>, EncodingError> {
+        validate_sequence(swaps)?;
+        let mut path = Vec::with_capacity(20 + swaps.swaps.len() * 23);
+        path.extend_from_slice(token_address(&swaps.swaps[0].token_in)?.as_slice());
+        for swap in &swaps.swaps {
+            path.extend_from_slice(&fee_pips(swap)?.to_be_bytes()[1..]);
+            path.extend_from_slice(token_address(&swap.token_out)?.as_slice());
+        }
+        Ok(path)
+    }
+}
+
+impl SwapEncoder for UniswapV3Encoder {
+    fn encode(
+        &self,
+        swaps: &SwapSequence,
+        params: &EncodingParams,
+    ) -> Result<EncodedSwap, EncodingError> {
+        let path = Self::encode_path(swaps)?;
+        let amount_in = to_u256(&swaps.swaps[0].amount_in)?;
+        let min_amount_out = to_u256(&params.min_amount_out(swaps)?)?;
+        let payer_is_user = true;
+        let input = (params.recipient, amount_in, min_amount_out, Bytes::from(path), payer_is_user)
+            .abi_encode_params();
+
+        let mut calldata = selector(EXECUTE).to_vec();
+        calldata.extend(
+            (
+                Bytes::from(vec![V3_SWAP_EXACT_IN]),
+                vec![Bytes::from(input)],
+                U256::from(params.deadline),
+            )
+                .abi_encode_params(),
+        );
+        Ok(EncodedSwap { to: self.router, calldata, value: U256::ZERO })
+    }
+}
+
+/// Converts the fee of a swap to hundredths of a basis point, e.g. 0.003 to 3000.
+fn fee_pips(swap: &Swap) -> Result<u32, EncodingError> {
+    let pips = (swap.fee * 1_000_000.0).round();
+    if !(0.0..=MAX_FEE as f64).contains(&pips) {
+        return Err(EncodingError::InvalidSwap(format!(
+            "Fee {} of pool {} can't be encoded in a V3 path",
+            swap.fee, swap.pool
+        )));
+    }
+    Ok(pips as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use alloy_primitives::hex;
+    use num_bigint::BigUint;
+    use tycho_core::Bytes as TychoBytes;
+
+    use super::*;
+    use crate::{evm::execution::tests::swap, testing::token_at};
+
+    fn swap_at(token_in: (&str, &str), token_out: (&str, &str), fee: f64) -> Swap {
+        Swap {
+            token_in: token_at(TychoBytes::from_str(token_in.1).unwrap(), token_in.0, 18),
+            token_out: token_at(TychoBytes::from_str(token_out.1).unwrap(), token_out.0, 18),
+            fee,
+            ..swap(token_in.0, token_out.0, 1_000_000_000, 400_000_000_000_000)
+        }
+    }
+
+    const USDC: (&str, &str) = ("USDC", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
+    const WETH: (&str, &str) = ("WETH", "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+    const DAI: (&str, &str) = ("DAI", "0x6b175474e89094c44da98b954eedeac495271d0f");
+
+    #[test]
+    fn test_encode_path() {
+        let swaps = SwapSequence {
+            swaps: vec![swap_at(USDC, WETH, 0.0005), swap_at(WETH, DAI, 0.003)],
+            gas: BigUint::default(),
+        };
+
+        let path = UniswapV3Encoder::encode_path(&swaps).unwrap();
+
+        // Path of Universal Router swaps from USDC through the WETH 0.05% and DAI 0.3% pools
+        assert_eq!(
+            path,
+            hex!(
+                "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"
+                "0001f4"
+                "c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"
+                "000bb8"
+                "6b175474e89094c44da98b954eedeac495271d0f"
+            )
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let router = Address::from_str("0x3fC91A3afd70395Cd496C647d5a6CC9D4B2b7FAD").unwrap();
+        let recipient = Address::from_str("0x0000000000000000000000000000000000000bee").unwrap();
+        let swaps =
+            SwapSequence { swaps: vec![swap_at(USDC, WETH, 0.0005)], gas: BigUint::default() };
+        let params =
+            EncodingParams::new(recipient, BigUint::from(1u64), 1_700_000_000).with_slippage(0.01);
+
+        let encoded = UniswapV3Encoder::new(router)
+            .encode(&swaps, &params)
+            .unwrap();
+
+        assert_eq!(encoded.to, router);
+        assert_eq!(encoded.value, U256::ZERO);
+        // `execute(bytes,bytes[],uint256)` of the Universal Router
+        assert_eq!(encoded.calldata[..4], hex!("3593564c"));
+        let (commands, inputs, deadline) =
+            <(Bytes, Vec<Bytes>, U256)>::abi_decode_params(&encoded.calldata[4..], true).unwrap();
+        assert_eq!(commands, Bytes::from(vec![V3_SWAP_EXACT_IN]));
+        assert_eq!(deadline, U256::from(1_700_000_000));
+        assert_eq!(inputs.len(), 1);
+        // abi.encode(recipient, amountIn, amountOutMin, path, payerIsUser)
+        let expected_input = hex!(
+            "0000000000000000000000000000000000000000000000000000000000000bee"
+            "000000000000000000000000000000000000000000000000000000003b9aca00"
+            "00000000000000000000000000000000000000000000000000016828ef54c000"
+            "00000000000000000000000000000000000000000000000000000000000000a0"
+            "0000000000000000000000000000000000000000000000000000000000000001"
+            "000000000000000000000000000000000000000000000000000000000000002b"
+            "a0b86991c6218b36c1d19d4a2e9eb0ce3606eb480001f4c02aaa39b223fe8d0a"
+            "0e5c4f27ead9083c756cc2000000000000000000000000000000000000000000"
+        );
+        assert_eq!(inputs[0].as_ref(), expected_input.as_slice());
+    }
+
+    #[test]
+    fn test_encode_rejects_invalid_fee() {
+        let swaps =
+            SwapSequence { swaps: vec![swap_at(USDC, WETH, 20.0)], gas: BigUint::default() };
+        let params = EncodingParams::new(Address::ZERO, BigUint::default(), 0);
+
+        let res = UniswapV3Encoder::new(Address::ZERO).encode(&swaps, &params);
+
+        assert!(matches!(res, Err(EncodingError::InvalidSwap(_))));
+    }
+}
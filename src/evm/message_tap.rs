@@ -0,0 +1,199 @@
+//! Persistence of raw Tycho feed messages
+//!
+//! A `MessageTap` attached to a `ProtocolStreamBuilder` writes every `FeedMessage` received from
+//! Tycho to a file, before it is decoded. `ReplayStreamBuilder` reads such a file back and decodes
+//! it exactly like the live stream would, e.g. to backtest a solver without access to Tycho.
+//!
+//! Each message is stored as a frame: its length as a 4 byte big-endian integer, followed by the
+//! JSON encoded `TappedMessage`.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tycho_client::feed::FeedMessage;
+
+/// A feed message along with the time it was received
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TappedMessage {
+    /// Milliseconds since the unix epoch. Used to replay messages at their original cadence.
+    pub received_at_ms: u64,
+    /// Whether the message is the first one received after the stream reconnected
+    #[serde(default)]
+    pub reconnected: bool,
+    pub message: FeedMessage,
+}
+
+impl TappedMessage {
+    /// Stamps `message` with the current time.
+    pub fn new(message: FeedMessage, reconnected: bool) -> Self {
+        let received_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        Self { received_at_ms, reconnected, message }
+    }
+}
+
+/// Appends the feed messages of a stream to a file, see `ProtocolStreamBuilder::message_tap`.
+///
+/// Clones share the same file.
+#[derive(Clone, Debug)]
+pub struct MessageTap {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl MessageTap {
+    /// Creates a tap appending to the file at `path`, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self { writer: Arc::new(Mutex::new(BufWriter::new(file))) })
+    }
+
+    /// Writes `tapped` as one frame and flushes it, so the file is complete up to the last
+    /// message even if the process is killed.
+    pub fn write(&self, tapped: &TappedMessage) -> io::Result<()> {
+        let body = serde_json::to_vec(tapped)?;
+        let len = u32::try_from(body.len())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Feed message too large"))?;
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&len.to_be_bytes())?;
+        writer.write_all(&body)?;
+        writer.flush()
+    }
+
+    /// Like `write`, but writes on tokio's blocking thread pool so the file IO and the wait for
+    /// the lock don't stall the async runtime.
+    pub async fn write_async(&self, tapped: TappedMessage) -> io::Result<()> {
+        let tap = self.clone();
+        tokio::task::spawn_blocking(move || tap.write(&tapped))
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Reads the frames of a file written by a `MessageTap`, in order.
+///
+/// Frames are read lazily. Iteration ends at the end of the file, or after the first error, e.g.
+/// a frame truncated because the process writing it was killed.
+pub struct TappedMessageReader<R: Read> {
+    reader: R,
+    failed: bool,
+}
+
+impl TappedMessageReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> TappedMessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader, failed: false }
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<TappedMessage>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.reader.read_exact(&mut body)?;
+        Ok(Some(serde_json::from_slice(&body)?))
+    }
+}
+
+impl<R: Read> Iterator for TappedMessageReader<R> {
+    type Item = io::Result<TappedMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let frame = self.read_frame();
+        self.failed = frame.is_err();
+        frame.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use super::*;
+
+    fn message(n: u64) -> TappedMessage {
+        TappedMessage {
+            received_at_ms: n,
+            reconnected: false,
+            message: FeedMessage { state_msgs: HashMap::new(), sync_states: HashMap::new() },
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_frames() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let tap = MessageTap::create(file.path()).unwrap();
+
+        tap.write(&message(1)).unwrap();
+        tap.clone().write(&message(2)).unwrap();
+
+        let read = TappedMessageReader::open(file.path())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let received_at: Vec<_> = read
+            .iter()
+            .map(|tapped| tapped.received_at_ms)
+            .collect();
+        assert_eq!(received_at, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_write_async() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let tap = MessageTap::create(file.path()).unwrap();
+
+        tap.write_async(message(1))
+            .await
+            .unwrap();
+        tap.write_async(message(2))
+            .await
+            .unwrap();
+
+        let received_at: Vec<_> = TappedMessageReader::open(file.path())
+            .unwrap()
+            .map(|tapped| tapped.unwrap().received_at_ms)
+            .collect();
+        assert_eq!(received_at, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_read_truncated_frame() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        MessageTap::create(file.path())
+            .unwrap()
+            .write(&message(1))
+            .unwrap();
+        let mut bytes = fs::read(file.path()).unwrap();
+        bytes.extend_from_slice(&100u32.to_be_bytes());
+        bytes.extend_from_slice(b"{\"received");
+        fs::write(file.path(), bytes).unwrap();
+
+        let read: Vec<_> = TappedMessageReader::open(file.path())
+            .unwrap()
+            .collect();
+
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].as_ref().unwrap().received_at_ms, 1);
+        assert!(read[1].is_err());
+    }
+}
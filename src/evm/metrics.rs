@@ -0,0 +1,477 @@
+//! Operational metrics
+//!
+//! Decoding, component decode failures, simulations, `PreCachedDB` reads and stream
+//! reconnections are reported to a global `MetricsRecorder`, installed once with
+//! `set_metrics_recorder`. Nothing is recorded by default. A protocol stream additionally reports
+//! its own events to the recorder passed to `ProtocolStreamBuilder::metrics`, to monitor streams
+//! separately.
+//!
+//! With the `metrics` feature, `MetricsCrateRecorder` forwards everything to the `metrics` crate,
+//! so any of its exporters (e.g. Prometheus) can be plugged in. The metric names are listed in
+//! `names`. With the `prometheus` feature, `PrometheusMetrics` counts the stream events and
+//! renders them in the Prometheus text format, without further dependencies.
+use std::{
+    fmt::Debug,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use crate::protocol::errors::InvalidSnapshotError;
+
+/// Names of the metrics reported by `MetricsCrateRecorder`
+pub mod names {
+    /// Histogram of the time to decode a block, in seconds
+    pub const BLOCK_DECODE_SECONDS: &str = "tycho_simulation_block_decode_seconds";
+    /// Counter of pool states updated by decoded blocks
+    pub const POOLS_UPDATED: &str = "tycho_simulation_pools_updated_total";
+    /// Counter of pools added by decoded blocks
+    pub const POOLS_ADDED: &str = "tycho_simulation_pools_added_total";
+    /// Counter of pools removed by decoded blocks
+    pub const POOLS_REMOVED: &str = "tycho_simulation_pools_removed_total";
+    /// Counter of component snapshots that failed to decode, labeled with `protocol`
+    pub const DECODE_ERRORS: &str = "tycho_simulation_decode_errors_total";
+    /// Counter of simulations, labeled with `outcome` (`success` or `failure`)
+    pub const SIMULATIONS: &str = "tycho_simulation_simulations_total";
+    /// Histogram of the simulation durations, in seconds
+    pub const SIMULATION_SECONDS: &str = "tycho_simulation_simulation_seconds";
+    /// Counter of `PreCachedDB` reads, labeled with `result` (`hit` or `miss`)
+    pub const DB_READS: &str = "tycho_simulation_db_reads_total";
+    /// Counter of reconnections of the protocol stream
+    pub const STREAM_RECONNECTS: &str = "tycho_simulation_stream_reconnects_total";
+}
+
+/// Receives the metrics of this crate. All methods default to doing nothing.
+///
+/// Methods are called on hot paths, e.g. for every storage read of a simulation, so they should
+/// return quickly.
+pub trait MetricsRecorder: Send + Sync + Debug {
+    /// Called after a block was decoded in `duration`, with the number of updated states, new
+    /// pairs and removed pairs of its `BlockUpdate`.
+    fn on_block_decoded(
+        &self,
+        _duration: Duration,
+        _n_states: usize,
+        _n_new: usize,
+        _n_removed: usize,
+    ) {
+    }
+
+    /// Called for every component snapshot of `protocol` that failed to decode, whether the
+    /// failure is skipped or fails the block.
+    fn on_decode_error(&self, _protocol: &str, _err: &InvalidSnapshotError) {}
+
+    /// Called after a simulation ran for `duration`.
+    fn on_simulation(&self, _duration: Duration, _success: bool) {}
+
+    /// Called for every account or storage read of a `PreCachedDB`. A miss means the account
+    /// isn't known to the database.
+    fn on_db_read(&self, _hit: bool) {}
+
+    /// Called after the protocol stream reconnected to Tycho.
+    fn on_stream_reconnect(&self) {}
+}
+
+/// Allows keeping a handle on the installed recorder.
+impl<T: MetricsRecorder + ?Sized> MetricsRecorder for Arc<T> {
+    fn on_block_decoded(
+        &self,
+        duration: Duration,
+        n_states: usize,
+        n_new: usize,
+        n_removed: usize,
+    ) {
+        (**self).on_block_decoded(duration, n_states, n_new, n_removed)
+    }
+
+    fn on_decode_error(&self, protocol: &str, err: &InvalidSnapshotError) {
+        (**self).on_decode_error(protocol, err)
+    }
+
+    fn on_simulation(&self, duration: Duration, success: bool) {
+        (**self).on_simulation(duration, success)
+    }
+
+    fn on_db_read(&self, hit: bool) {
+        (**self).on_db_read(hit)
+    }
+
+    fn on_stream_reconnect(&self) {
+        (**self).on_stream_reconnect()
+    }
+}
+
+/// The default recorder, discarding all metrics
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopRecorder;
+
+impl MetricsRecorder for NoopRecorder {}
+
+static RECORDER: OnceLock<Box<dyn MetricsRecorder>> = OnceLock::new();
+
+/// Installs the global recorder.
+///
+/// The recorder is read without locking on hot paths, so it can only be installed once. Returns
+/// the given recorder back if one is already installed.
+pub fn set_metrics_recorder<R: MetricsRecorder + 'static>(recorder: R) -> Result<(), R> {
+    let mut recorder = Some(recorder);
+    RECORDER.get_or_init(|| Box::new(recorder.take().unwrap()));
+    match recorder {
+        None => Ok(()),
+        Some(recorder) => Err(recorder),
+    }
+}
+
+/// Returns the global recorder.
+pub(crate) fn recorder() -> &'static dyn MetricsRecorder {
+    RECORDER
+        .get()
+        .map_or(&NoopRecorder, |recorder| recorder.as_ref())
+}
+
+/// Reports an event of a stream to the global recorder and to the stream's own, if any.
+pub(crate) fn record_stream_event(
+    own: Option<&Arc<dyn MetricsRecorder>>,
+    event: impl Fn(&dyn MetricsRecorder),
+) {
+    event(recorder());
+    if let Some(own) = own {
+        event(own.as_ref());
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub use metrics_crate::MetricsCrateRecorder;
+
+#[cfg(feature = "metrics")]
+mod metrics_crate {
+    use std::time::Duration;
+
+    use super::{names, MetricsRecorder};
+    use crate::protocol::errors::InvalidSnapshotError;
+
+    /// Forwards all metrics to the `metrics` crate, using the names in `names`
+    ///
+    /// Install an exporter of the `metrics` ecosystem, then this recorder with
+    /// `set_metrics_recorder(MetricsCrateRecorder)`.
+    #[derive(Debug, Default, Clone, Copy)]
+    pub struct MetricsCrateRecorder;
+
+    impl MetricsRecorder for MetricsCrateRecorder {
+        fn on_block_decoded(
+            &self,
+            duration: Duration,
+            n_states: usize,
+            n_new: usize,
+            n_removed: usize,
+        ) {
+            ::metrics::histogram!(names::BLOCK_DECODE_SECONDS).record(duration.as_secs_f64());
+            ::metrics::counter!(names::POOLS_UPDATED).increment(n_states as u64);
+            ::metrics::counter!(names::POOLS_ADDED).increment(n_new as u64);
+            ::metrics::counter!(names::POOLS_REMOVED).increment(n_removed as u64);
+        }
+
+        fn on_decode_error(&self, protocol: &str, _err: &InvalidSnapshotError) {
+            ::metrics::counter!(names::DECODE_ERRORS, "protocol" => protocol.to_string())
+                .increment(1);
+        }
+
+        fn on_simulation(&self, duration: Duration, success: bool) {
+            let outcome = if success { "success" } else { "failure" };
+            ::metrics::counter!(names::SIMULATIONS, "outcome" => outcome).increment(1);
+            ::metrics::histogram!(names::SIMULATION_SECONDS).record(duration.as_secs_f64());
+        }
+
+        fn on_db_read(&self, hit: bool) {
+            let result = if hit { "hit" } else { "miss" };
+            ::metrics::counter!(names::DB_READS, "result" => result).increment(1);
+        }
+
+        fn on_stream_reconnect(&self) {
+            ::metrics::counter!(names::STREAM_RECONNECTS).increment(1);
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+pub use prometheus::PrometheusMetrics;
+
+#[cfg(feature = "prometheus")]
+mod prometheus {
+    use std::{
+        collections::BTreeMap,
+        fmt::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
+
+    use super::MetricsRecorder;
+    use crate::protocol::errors::InvalidSnapshotError;
+
+    /// Counts the stream's events and renders them in the Prometheus text exposition format
+    ///
+    /// Pass it to the stream wrapped in an `Arc` and serve `render` from a `/metrics` endpoint.
+    #[derive(Debug, Default)]
+    pub struct PrometheusMetrics {
+        blocks: AtomicU64,
+        states: AtomicU64,
+        new_pairs: AtomicU64,
+        removed_pairs: AtomicU64,
+        reconnects: AtomicU64,
+        decode_errors: Mutex<BTreeMap<String, u64>>,
+    }
+
+    impl PrometheusMetrics {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Renders all counters in the Prometheus text exposition format.
+        pub fn render(&self) -> String {
+            let mut out = String::new();
+            let counters = [
+                ("tycho_stream_blocks_total", "Decoded blocks", &self.blocks),
+                ("tycho_stream_states_total", "Updated component states", &self.states),
+                ("tycho_stream_new_pairs_total", "Added components", &self.new_pairs),
+                ("tycho_stream_removed_pairs_total", "Removed components", &self.removed_pairs),
+                ("tycho_stream_reconnects_total", "Reconnections to Tycho", &self.reconnects),
+            ];
+            for (name, help, counter) in counters {
+                let _ = writeln!(out, "# HELP {name} {help}");
+                let _ = writeln!(out, "# TYPE {name} counter");
+                let _ = writeln!(out, "{name} {}", counter.load(Ordering::Relaxed));
+            }
+            let name = "tycho_stream_decode_errors_total";
+            let _ = writeln!(out, "# HELP {name} Component snapshots that failed to decode");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (protocol, count) in self
+                .decode_errors
+                .lock()
+                .unwrap()
+                .iter()
+            {
+                let _ = writeln!(out, "{name}{{protocol=\"{protocol}\"}} {count}");
+            }
+            out
+        }
+    }
+
+    impl MetricsRecorder for PrometheusMetrics {
+        fn on_block_decoded(
+            &self,
+            _duration: Duration,
+            n_states: usize,
+            n_new: usize,
+            n_removed: usize,
+        ) {
+            self.blocks
+                .fetch_add(1, Ordering::Relaxed);
+            self.states
+                .fetch_add(n_states as u64, Ordering::Relaxed);
+            self.new_pairs
+                .fetch_add(n_new as u64, Ordering::Relaxed);
+            self.removed_pairs
+                .fetch_add(n_removed as u64, Ordering::Relaxed);
+        }
+
+        fn on_decode_error(&self, protocol: &str, _err: &InvalidSnapshotError) {
+            *self
+                .decode_errors
+                .lock()
+                .unwrap()
+                .entry(protocol.to_string())
+                .or_default() += 1;
+        }
+
+        fn on_stream_reconnect(&self) {
+            self.reconnects
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_render() {
+            let metrics = PrometheusMetrics::new();
+            metrics.on_block_decoded(Duration::ZERO, 3, 2, 1);
+            metrics.on_block_decoded(Duration::ZERO, 1, 0, 0);
+            metrics
+                .on_decode_error("uniswap_v2", &InvalidSnapshotError::MissingAttribute("a".into()));
+            metrics.on_stream_reconnect();
+
+            let rendered = metrics.render();
+
+            assert!(rendered.contains("tycho_stream_blocks_total 2\n"));
+            assert!(rendered.contains("tycho_stream_states_total 4\n"));
+            assert!(rendered.contains("tycho_stream_new_pairs_total 2\n"));
+            assert!(rendered.contains("tycho_stream_removed_pairs_total 1\n"));
+            assert!(rendered.contains("tycho_stream_reconnects_total 1\n"));
+            assert!(
+                rendered.contains("tycho_stream_decode_errors_total{protocol=\"uniswap_v2\"} 1\n")
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        path::Path,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            OnceLock,
+        },
+    };
+
+    use revm::{
+        primitives::{hex, AccountInfo, Address, Bytecode, Bytes, U256},
+        DatabaseRef,
+    };
+    use tycho_core::Bytes as TychoBytes;
+
+    use super::*;
+    use crate::{
+        evm::{
+            decoder::TychoStreamDecoder,
+            engine_db::{
+                create_engine, engine_db_interface::EngineDatabaseInterface, tycho_db::PreCachedDB,
+            },
+            protocol::uniswap_v2::state::UniswapV2State,
+            simulation::SimulationParameters,
+        },
+        testing::{token_at, token_map},
+    };
+
+    #[derive(Debug, Default)]
+    struct CountingRecorder {
+        blocks: AtomicU64,
+        pools: AtomicU64,
+        simulations: AtomicU64,
+        db_hits: AtomicU64,
+        db_misses: AtomicU64,
+    }
+
+    impl MetricsRecorder for CountingRecorder {
+        fn on_block_decoded(
+            &self,
+            _duration: Duration,
+            n_states: usize,
+            _n_new: usize,
+            _n_removed: usize,
+        ) {
+            self.blocks
+                .fetch_add(1, Ordering::SeqCst);
+            self.pools
+                .fetch_add(n_states as u64, Ordering::SeqCst);
+        }
+
+        fn on_simulation(&self, _duration: Duration, _success: bool) {
+            self.simulations
+                .fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_db_read(&self, hit: bool) {
+            let counter = if hit { &self.db_hits } else { &self.db_misses };
+            counter.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Installs a shared counting recorder. Tests run concurrently, so they only check that
+    /// counters grew by at least the expected amount.
+    fn counting_recorder() -> Arc<CountingRecorder> {
+        static INSTALLED: OnceLock<Arc<CountingRecorder>> = OnceLock::new();
+        INSTALLED
+            .get_or_init(|| {
+                let recorder = Arc::new(CountingRecorder::default());
+                set_metrics_recorder(recorder.clone()).unwrap();
+                recorder
+            })
+            .clone()
+    }
+
+    #[test]
+    fn test_simulation_and_db_reads_are_recorded() {
+        let recorder = counting_recorder();
+        let simulations = recorder
+            .simulations
+            .load(Ordering::SeqCst);
+        let hits = recorder.db_hits.load(Ordering::SeqCst);
+        let misses = recorder
+            .db_misses
+            .load(Ordering::SeqCst);
+
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        // Returns the value of storage slot 0
+        let code = Bytecode::new_raw(Bytes::from(hex::decode("60005460005260206000f3").unwrap()));
+        let contract = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        engine.state.init_account(
+            contract,
+            AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+            Some(HashMap::from([(U256::ZERO, U256::from(42))])),
+            true,
+        );
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to: contract,
+            data: Vec::new(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        };
+        engine.simulate(&params).unwrap();
+        let unknown = Address::from_str("0x0000000000000000000000000000000000005678").unwrap();
+        assert!(engine.state.basic_ref(unknown).is_err());
+
+        assert!(
+            recorder
+                .simulations
+                .load(Ordering::SeqCst) >
+                simulations
+        );
+        assert!(recorder.db_hits.load(Ordering::SeqCst) > hits);
+        assert!(
+            recorder
+                .db_misses
+                .load(Ordering::SeqCst) >
+                misses
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_decoding_is_recorded() {
+        let recorder = counting_recorder();
+        let blocks = recorder.blocks.load(Ordering::SeqCst);
+        let pools = recorder.pools.load(Ordering::SeqCst);
+
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.register_decoder::<UniswapV2State>("uniswap_v2");
+        let tokens = [
+            ("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", "WETH", 18),
+            ("0xdac17f958d2ee523a2206206994597c13d831ec7", "USDT", 6),
+        ]
+        .map(|(address, symbol, decimals)| token_at(TychoBytes::from(address), symbol, decimals));
+        decoder
+            .set_tokens(token_map(tokens))
+            .await;
+        let asset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/assets/decoder/uniswap_v2_snapshot.json");
+        let msg = serde_json::from_str(&fs::read_to_string(asset_path).unwrap()).unwrap();
+
+        decoder.decode(msg).await.unwrap();
+
+        assert!(recorder.blocks.load(Ordering::SeqCst) > blocks);
+        assert!(recorder.pools.load(Ordering::SeqCst) > pools);
+    }
+}
@@ -4,14 +4,37 @@ use tycho_core::keccak256;
 pub mod account_storage;
 pub mod decoder;
 pub mod engine_db;
+pub mod erc20;
+pub mod execution;
+pub mod message_tap;
+pub mod metrics;
 pub mod protocol;
+pub mod recorder;
+pub mod rpc;
 pub mod simulation;
 pub mod stream;
+pub mod stream_health;
 pub mod traces;
 pub mod tycho_models;
 
 pub type SlotId = U256;
 
+/// Runtime code prefixes of Vyper versions that don't append metadata to the bytecode.
+const VYPER_PROLOGUES: [&[u8]; 2] = [
+    // 0.2.x: `PUSH1 4 CALLDATASIZE LT ISZERO PUSH2`, guarding the selector dispatch
+    &[0x60, 0x04, 0x36, 0x10, 0x15, 0x61],
+    // 0.1.x: `PUSH1 0 CALLDATALOAD PUSH1 0x1c MSTORE`, extracting the selector
+    &[0x60, 0x00, 0x35, 0x60, 0x1c, 0x52],
+];
+
+/// Runtime code prefixes initializing Solidity's free memory pointer.
+const SOLIDITY_PROLOGUES: [&[u8]; 2] = [
+    // `PUSH1 0x80 PUSH1 0x40 MSTORE`
+    &[0x60, 0x80, 0x60, 0x40, 0x52],
+    // `PUSH1 0x60 PUSH1 0x40 MSTORE`, before 0.4.22
+    &[0x60, 0x60, 0x60, 0x40, 0x52],
+];
+
 /// Enum representing the type of contract compiler.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum ContractCompiler {
@@ -46,4 +69,74 @@ impl ContractCompiler {
 
         SlotId::from_be_slice(&slot_bytes)
     }
+
+    /// Guesses the compiler of a contract from its runtime bytecode.
+    ///
+    /// The CBOR metadata both compilers append to the code is checked first: it names the
+    /// compiler (`vyper`, or `solc`/`bzzr` for Solidity). Code without metadata is matched
+    /// against the characteristic prologues of each compiler. Defaults to `Solidity` if neither
+    /// is conclusive.
+    pub fn detect(bytecode: &[u8]) -> ContractCompiler {
+        if let Some(metadata) = cbor_metadata(bytecode) {
+            if contains(metadata, b"vyper") {
+                return ContractCompiler::Vyper;
+            }
+            if contains(metadata, b"solc") || contains(metadata, b"bzzr") {
+                return ContractCompiler::Solidity;
+            }
+        }
+        if SOLIDITY_PROLOGUES
+            .iter()
+            .any(|prologue| bytecode.starts_with(prologue))
+        {
+            return ContractCompiler::Solidity;
+        }
+        if VYPER_PROLOGUES
+            .iter()
+            .any(|prologue| bytecode.starts_with(prologue))
+        {
+            return ContractCompiler::Vyper;
+        }
+        ContractCompiler::Solidity
+    }
+}
+
+/// Returns the CBOR metadata section at the end of `bytecode`, whose length is encoded in the last
+/// two bytes.
+fn cbor_metadata(bytecode: &[u8]) -> Option<&[u8]> {
+    let (rest, len) = bytecode.split_at(bytecode.len().checked_sub(2)?);
+    let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+    rest.len()
+        .checked_sub(len)
+        .filter(|_| len > 0)
+        .map(|start| &rest[start..])
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy_primitives::hex;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::protocol::vm::constants::{BALANCER_V2, CURVE, ERC20_BYTECODE};
+
+    #[rstest]
+    #[case::erc20(ERC20_BYTECODE, ContractCompiler::Solidity)]
+    #[case::balancer_adapter(BALANCER_V2, ContractCompiler::Solidity)]
+    #[case::curve_adapter(CURVE, ContractCompiler::Solidity)]
+    // {"vyper": [0, 3, 7]} followed by its length
+    #[case::vyper_metadata(&hex!("61000c5600a165767970657283000307000b"), ContractCompiler::Vyper)]
+    #[case::vyper_prologue(&hex!("600436101561000d57"), ContractCompiler::Vyper)]
+    #[case::legacy_vyper_prologue(&hex!("600035601c527401"), ContractCompiler::Vyper)]
+    #[case::empty(&[], ContractCompiler::Solidity)]
+    #[case::ambiguous(&[0x00, 0x01, 0x02, 0x03], ContractCompiler::Solidity)]
+    fn test_detect_compiler(#[case] bytecode: &[u8], #[case] expected: ContractCompiler) {
+        assert_eq!(ContractCompiler::detect(bytecode), expected);
+    }
 }
@@ -1,15 +1,151 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+};
 
 use num_bigint::BigInt;
 use tracing::{debug, info};
 use tycho_client::feed::synchronizer::ComponentWithState;
+use tycho_core::Bytes;
 
-use crate::evm::protocol::vm::utils::json_deserialize_be_bigint_list;
+use crate::{evm::protocol::vm::utils::json_deserialize_be_bigint_list, models::Token};
 
 const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
 const ZERO_ADDRESS_ARR: [u8; 20] = [0u8; 20];
 
-pub fn balancer_pool_filter(component: &ComponentWithState) -> bool {
+/// The future returned by an asynchronous component filter
+pub type FilterFuture<'a> = Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+
+type SyncFilter = dyn Fn(&ComponentWithState, &HashMap<Bytes, Token>) -> bool + Send + Sync;
+type AsyncFilter = dyn for<'a> Fn(&'a ComponentWithState, &'a HashMap<Bytes, Token>) -> FilterFuture<'a>
+    + Send
+    + Sync;
+
+/// A client-side filter deciding whether a component is decoded.
+///
+/// Filters receive the component with its state and all tokens known to the decoder, and return
+/// `true` to keep the component. Asynchronous filters can e.g. probe on-chain state, the decoder
+/// evaluates them concurrently while processing a snapshot.
+#[derive(Clone)]
+pub enum ComponentFilterFn {
+    Sync(Arc<SyncFilter>),
+    Async(Arc<AsyncFilter>),
+}
+
+impl ComponentFilterFn {
+    /// Wraps a synchronous filter, e.g. `ComponentFilterFn::sync(balancer_pool_filter)`.
+    pub fn sync(
+        f: impl Fn(&ComponentWithState, &HashMap<Bytes, Token>) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        ComponentFilterFn::Sync(Arc::new(f))
+    }
+
+    /// Wraps an asynchronous filter. The returned future may borrow the filter's arguments.
+    pub fn new_async(
+        f: impl for<'a> Fn(&'a ComponentWithState, &'a HashMap<Bytes, Token>) -> FilterFuture<'a>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        ComponentFilterFn::Async(Arc::new(f))
+    }
+
+    /// Combines two filters, keeping components that pass both. `other` is only evaluated for
+    /// components `self` keeps.
+    pub fn and(self, other: ComponentFilterFn) -> Self {
+        match (self, other) {
+            (ComponentFilterFn::Sync(first), ComponentFilterFn::Sync(second)) => {
+                ComponentFilterFn::sync(move |component, tokens| {
+                    first(component, tokens) && second(component, tokens)
+                })
+            }
+            (first, second) => ComponentFilterFn::new_async(move |component, tokens| {
+                let (first, second) = (first.clone(), second.clone());
+                Box::pin(async move {
+                    first.matches(component, tokens).await &&
+                        second.matches(component, tokens).await
+                })
+            }),
+        }
+    }
+
+    /// Whether the component passes the filter
+    pub async fn matches(
+        &self,
+        component: &ComponentWithState,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> bool {
+        match self {
+            ComponentFilterFn::Sync(f) => f(component, tokens),
+            ComponentFilterFn::Async(f) => f(component, tokens).await,
+        }
+    }
+}
+
+impl fmt::Debug for ComponentFilterFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ComponentFilterFn::Sync(_) => f.write_str("ComponentFilterFn::Sync"),
+            ComponentFilterFn::Async(_) => f.write_str("ComponentFilterFn::Async"),
+        }
+    }
+}
+
+/// Filters out pools with a token that is unknown or whose quality is below `min_quality`.
+///
+/// Tycho rates tokens from 0 to 100, lower scores flag tokens that failed some of its checks.
+pub fn token_quality_filter(min_quality: u8) -> ComponentFilterFn {
+    ComponentFilterFn::sync(move |component, tokens| {
+        let keep = component
+            .component
+            .tokens
+            .iter()
+            .all(|address| {
+                tokens
+                    .get(address)
+                    .is_some_and(|token| token.quality >= min_quality)
+            });
+        if !keep {
+            debug!(
+                "Filtering out pool {} because it has a token below quality {}",
+                component.component.id, min_quality
+            );
+        }
+        keep
+    })
+}
+
+/// Keeps pools whose tokens are all in `allowed` if `require_all` is set, otherwise pools with
+/// at least one token in `allowed`.
+///
+/// E.g. `token_filter([usdc, weth, usdt].into(), true)` keeps only pools between these three
+/// tokens. Like all client-side filters it applies on top of the exchange's `ComponentFilter`:
+/// a pool is decoded only if it passes both, e.g. the TVL range and the token allowlist.
+pub fn token_filter(allowed: HashSet<Bytes>, require_all: bool) -> ComponentFilterFn {
+    ComponentFilterFn::sync(move |component, _tokens| {
+        let mut pool_tokens = component.component.tokens.iter();
+        let keep = if require_all {
+            pool_tokens.all(|address| allowed.contains(address))
+        } else {
+            pool_tokens.any(|address| allowed.contains(address))
+        };
+        if !keep {
+            debug!(
+                "Filtering out pool {} because its tokens aren't allowed",
+                component.component.id
+            );
+        }
+        keep
+    })
+}
+
+pub fn balancer_pool_filter(
+    component: &ComponentWithState,
+    _tokens: &HashMap<Bytes, Token>,
+) -> bool {
     // Check for rate_providers in static_attributes
     info!("Checking Balancer pool {}", component.component.id);
     if let Some(rate_providers_data) = component
@@ -74,7 +210,7 @@ pub fn balancer_pool_filter(component: &ComponentWithState) -> bool {
     info!("Balancer pool will not be filtered out.");
     true
 }
-pub fn curve_pool_filter(component: &ComponentWithState) -> bool {
+pub fn curve_pool_filter(component: &ComponentWithState, _tokens: &HashMap<Bytes, Token>) -> bool {
     if let Some(asset_types) = component
         .component
         .static_attributes
@@ -127,7 +263,10 @@ pub fn curve_pool_filter(component: &ComponentWithState) -> bool {
 }
 
 /// Filters out pool that have hooks in Uniswap V4
-pub fn uniswap_v4_pool_with_hook_filter(component: &ComponentWithState) -> bool {
+pub fn uniswap_v4_pool_with_hook_filter(
+    component: &ComponentWithState,
+    _tokens: &HashMap<Bytes, Token>,
+) -> bool {
     if let Some(hooks) = component
         .component
         .static_attributes
@@ -0,0 +1,574 @@
+//! Fixed Point Math
+//!
+//! Arithmetic on wads, fixed point numbers with 18 decimals, and rays, with 27 decimals, rounding
+//! in an explicit direction. Pools round against the trader, e.g. down for amounts out and up for
+//! amounts in, so native implementations have to round exactly like the contract to match it.
+//!
+//! The wad functions match Balancer's `FixedPoint` library: products and quotients fail where the
+//! contract reverts, i.e. when an intermediate value overflows 256 bits, and `pow_wad` is a port
+//! of its `LogExpMath.pow`. All functions return a `SimulationError::FatalError` on overflow,
+//! division by zero or inputs out of the supported range.
+use alloy_primitives::U256;
+
+use super::safe_math::{safe_add_u256, safe_mul_u256, safe_sub_u256};
+use crate::protocol::errors::SimulationError;
+
+/// 1 as a wad, 10^18
+pub const WAD: U256 = U256::from_limbs([1000000000000000000, 0, 0, 0]);
+/// 1 as a ray, 10^27
+pub const RAY: U256 = U256::from_limbs([11515845246265065472, 54210108, 0, 0]);
+
+/// Bound of the relative error of `pow_wad`, as a wad: 10^-14
+const MAX_POW_RELATIVE_ERROR: U256 = U256::from_limbs([10000, 0, 0, 0]);
+
+// Constants of `LogExpMath`. Exponents and logarithms have 18 decimals, except inside `exp` and
+// `ln` which use 20 and `ln_36` which uses 36.
+const ONE_20: U256 = U256::from_limbs([7766279631452241920, 5, 0, 0]);
+const ONE_36: U256 = U256::from_limbs([12919594847110692864, 54210108624275221, 0, 0]);
+/// 130, the largest exponent `exp` accepts
+const MAX_NATURAL_EXPONENT: U256 = U256::from_limbs([872791484033138688, 7, 0, 0]);
+/// 41, the magnitude of the smallest negative exponent `exp` accepts
+const MIN_NATURAL_EXPONENT: U256 = U256::from_limbs([4106511852580896768, 2, 0, 0]);
+/// Bases within (0.9, 1.1) take their logarithm with 36 decimals, for precision
+const LN_36_LOWER_BOUND: U256 = U256::from_limbs([900000000000000000, 0, 0, 0]);
+const LN_36_UPPER_BOUND: U256 = U256::from_limbs([1100000000000000000, 0, 0, 0]);
+/// 2^254 / 10^20, exponents must be below it so their product with a logarithm doesn't overflow
+const MILD_EXPONENT_BOUND: U256 =
+    U256::from_limbs([4720311721447089458, 12146009947018874712, 850705917302346158, 0]);
+
+/// 128 and e^128, without decimals
+const X0: U256 = U256::from_limbs([17319535557742690304, 6, 0, 0]);
+const A0: U256 =
+    U256::from_limbs([171843153341448192, 17670479068478958691, 114249481722274167, 0]);
+/// 64 and e^64, without decimals
+const X1: U256 = U256::from_limbs([8659767778871345152, 3, 0, 0]);
+const A1: U256 = U256::from_limbs([17696838799657497472, 338008108, 0, 0]);
+/// `x_n` and `e^x_n` with 20 decimals, for x_n = 32, 16, 8, 4, 2, 1, 1/2, 1/4, 1/8 and 1/16
+const EXP_TERMS: [(U256, U256); 10] = [
+    (
+        U256::from_limbs([8713275248247570432, 173, 0, 0]),
+        U256::from_limbs([17871857890508685312, 428059064879743, 0, 0]),
+    ),
+    (
+        U256::from_limbs([13580009660978561024, 86, 0, 0]),
+        U256::from_limbs([12108528782385981184, 48171701, 0, 0]),
+    ),
+    (
+        U256::from_limbs([6790004830489280512, 43, 0, 0]),
+        U256::from_limbs([14861217100182911056, 16159, 0, 0]),
+    ),
+    (
+        U256::from_limbs([12618374452099416064, 21, 0, 0]),
+        U256::from_limbs([18025501570106181090, 295, 0, 0]),
+    ),
+    (
+        U256::from_limbs([15532559262904483840, 10, 0, 0]),
+        U256::from_limbs([1035846944682958083, 40, 0, 0]),
+    ),
+    (
+        U256::from_limbs([7766279631452241920, 5, 0, 0]),
+        U256::from_limbs([13573765813970800912, 14, 0, 0]),
+    ),
+    (
+        U256::from_limbs([13106511852580896768, 2, 0, 0]),
+        U256::from_limbs([17298174480336401757, 8, 0, 0]),
+    ),
+    (
+        U256::from_limbs([6553255926290448384, 1, 0, 0]),
+        U256::from_limbs([17722077226516838711, 6, 0, 0]),
+    ),
+    (
+        U256::from_limbs([12500000000000000000, 0, 0, 0]),
+        U256::from_limbs([2634380864425321987, 6, 0, 0]),
+    ),
+    (
+        U256::from_limbs([6250000000000000000, 0, 0, 0]),
+        U256::from_limbs([14215725523238184876, 5, 0, 0]),
+    ),
+];
+
+/// Computes `a * b` of two wads, rounding down.
+pub fn mul_wad_down(a: U256, b: U256) -> Result<U256, SimulationError> {
+    mul_down(a, b, WAD)
+}
+
+/// Computes `a * b` of two wads, rounding up.
+pub fn mul_wad_up(a: U256, b: U256) -> Result<U256, SimulationError> {
+    mul_up(a, b, WAD)
+}
+
+/// Computes `a / b` of two wads, rounding down.
+pub fn div_wad_down(a: U256, b: U256) -> Result<U256, SimulationError> {
+    div_down(a, b, WAD)
+}
+
+/// Computes `a / b` of two wads, rounding up.
+pub fn div_wad_up(a: U256, b: U256) -> Result<U256, SimulationError> {
+    div_up(a, b, WAD)
+}
+
+/// Computes `a * b` of two rays, rounding down.
+pub fn mul_ray_down(a: U256, b: U256) -> Result<U256, SimulationError> {
+    mul_down(a, b, RAY)
+}
+
+/// Computes `a * b` of two rays, rounding up.
+pub fn mul_ray_up(a: U256, b: U256) -> Result<U256, SimulationError> {
+    mul_up(a, b, RAY)
+}
+
+/// Computes `a / b` of two rays, rounding down.
+pub fn div_ray_down(a: U256, b: U256) -> Result<U256, SimulationError> {
+    div_down(a, b, RAY)
+}
+
+/// Computes `a / b` of two rays, rounding up.
+pub fn div_ray_up(a: U256, b: U256) -> Result<U256, SimulationError> {
+    div_up(a, b, RAY)
+}
+
+/// Computes `x^y` of two wads like Balancer's `LogExpMath.pow`, as `e^(ln(x) * y)`.
+///
+/// The result is an approximation whose relative error is below `MAX_POW_RELATIVE_ERROR`, it may
+/// be above or below the exact power. Use `pow_wad_down` or `pow_wad_up` to bound it.
+///
+/// # Errors
+///
+/// Fails if `x` is 2^255 or above, if `y` is 2^254 / 10^20 or above, or if `ln(x) * y` is
+/// outside of [-41, 130].
+pub fn pow_wad(x: U256, y: U256) -> Result<U256, SimulationError> {
+    if y.is_zero() {
+        return Ok(WAD);
+    }
+    if x.is_zero() {
+        return Ok(U256::ZERO);
+    }
+    if x.bit(255) {
+        return Err(SimulationError::FatalError(format!("Base {x} of pow is out of bounds")));
+    }
+    if y >= MILD_EXPONENT_BOUND {
+        return Err(SimulationError::FatalError(format!("Exponent {y} of pow is out of bounds")));
+    }
+
+    // Logarithms are negative for bases below 1, they're computed as magnitude and sign
+    let (negative, ln_x_times_y) = if LN_36_LOWER_BOUND < x && x < LN_36_UPPER_BOUND {
+        let (negative, ln_36_x) = ln_36(x);
+        (negative, (ln_36_x / WAD) * y + ((ln_36_x % WAD) * y) / WAD)
+    } else {
+        let (negative, ln_x) = ln(x);
+        (negative, ln_x * y)
+    };
+    let exponent = ln_x_times_y / WAD;
+
+    let bound = if negative { MIN_NATURAL_EXPONENT } else { MAX_NATURAL_EXPONENT };
+    if exponent > bound {
+        return Err(SimulationError::FatalError(format!(
+            "Product of the logarithm of {x} and {y} is out of bounds"
+        )));
+    }
+    if negative {
+        Ok((WAD * WAD) / exp(exponent))
+    } else {
+        Ok(exp(exponent))
+    }
+}
+
+/// Computes `x^y` of two wads like Balancer's `FixedPoint.powDown`, i.e. a lower bound of the
+/// exact power.
+///
+/// Exponents of 1, 2 and 4 are computed exactly by multiplication, other exponents subtract the
+/// maximum error from `pow_wad`.
+pub fn pow_wad_down(x: U256, y: U256) -> Result<U256, SimulationError> {
+    if y == WAD {
+        return Ok(x);
+    }
+    if y == WAD * U256::from(2) {
+        return mul_wad_down(x, x);
+    }
+    if y == WAD * U256::from(4) {
+        let square = mul_wad_down(x, x)?;
+        return mul_wad_down(square, square);
+    }
+    let raw = pow_wad(x, y)?;
+    let max_error = safe_add_u256(mul_wad_up(raw, MAX_POW_RELATIVE_ERROR)?, U256::from(1))?;
+    Ok(raw.saturating_sub(max_error))
+}
+
+/// Computes `x^y` of two wads like Balancer's `FixedPoint.powUp`, i.e. an upper bound of the
+/// exact power.
+///
+/// Exponents of 1, 2 and 4 are computed exactly by multiplication, other exponents add the
+/// maximum error to `pow_wad`.
+pub fn pow_wad_up(x: U256, y: U256) -> Result<U256, SimulationError> {
+    if y == WAD {
+        return Ok(x);
+    }
+    if y == WAD * U256::from(2) {
+        return mul_wad_up(x, x);
+    }
+    if y == WAD * U256::from(4) {
+        let square = mul_wad_up(x, x)?;
+        return mul_wad_up(square, square);
+    }
+    let raw = pow_wad(x, y)?;
+    let max_error = safe_add_u256(mul_wad_up(raw, MAX_POW_RELATIVE_ERROR)?, U256::from(1))?;
+    safe_add_u256(raw, max_error)
+}
+
+fn mul_down(a: U256, b: U256, one: U256) -> Result<U256, SimulationError> {
+    Ok(safe_mul_u256(a, b)? / one)
+}
+
+fn mul_up(a: U256, b: U256, one: U256) -> Result<U256, SimulationError> {
+    let product = safe_mul_u256(a, b)?;
+    if product.is_zero() {
+        return Ok(U256::ZERO);
+    }
+    Ok((product - U256::from(1)) / one + U256::from(1))
+}
+
+fn div_down(a: U256, b: U256, one: U256) -> Result<U256, SimulationError> {
+    if b.is_zero() {
+        return Err(SimulationError::FatalError("Division by zero".to_string()));
+    }
+    Ok(safe_mul_u256(a, one)? / b)
+}
+
+fn div_up(a: U256, b: U256, one: U256) -> Result<U256, SimulationError> {
+    if b.is_zero() {
+        return Err(SimulationError::FatalError("Division by zero".to_string()));
+    }
+    if a.is_zero() {
+        return Ok(U256::ZERO);
+    }
+    let inflated = safe_mul_u256(a, one)?;
+    Ok(safe_sub_u256(inflated, U256::from(1))? / b + U256::from(1))
+}
+
+/// Computes `e^x` for `x` in [0, 130] with 18 decimals.
+///
+/// `x` is decomposed into a sum of the `x_n`, whose powers are precomputed, and a remainder
+/// below 1/4 whose power is computed with its Taylor series.
+fn exp(x: U256) -> U256 {
+    let (x, first_an) = if x >= X0 {
+        (x - X0, A0)
+    } else if x >= X1 {
+        (x - X1, A1)
+    } else {
+        (x, U256::from(1))
+    };
+
+    // Switch to 20 decimals for precision
+    let mut x = x * U256::from(100);
+    let mut product = ONE_20;
+    for (x_n, a_n) in &EXP_TERMS[..8] {
+        if x >= *x_n {
+            x -= *x_n;
+            product = (product * *a_n) / ONE_20;
+        }
+    }
+
+    let mut series_sum = ONE_20;
+    let mut term = x;
+    series_sum += term;
+    for n in 2..=12u64 {
+        term = ((term * x) / ONE_20) / U256::from(n);
+        series_sum += term;
+    }
+
+    (((product * series_sum) / ONE_20) * first_an) / U256::from(100)
+}
+
+/// Computes `ln(a)` of a positive `a` with 18 decimals, returned as whether it's negative and its
+/// magnitude.
+///
+/// `a` is divided by the `e^x_n` it contains, and the logarithm of the remainder is computed
+/// with the series of `ln(a) = 2 * atanh((a - 1) / (a + 1))`.
+fn ln(a: U256) -> (bool, U256) {
+    if a < WAD {
+        // ln(a) = -ln(1 / a)
+        return (true, ln((WAD * WAD) / a).1);
+    }
+
+    let mut a = a;
+    let mut sum = U256::ZERO;
+    if a >= A0 * WAD {
+        a /= A0;
+        sum += X0;
+    }
+    if a >= A1 * WAD {
+        a /= A1;
+        sum += X1;
+    }
+
+    // Switch to 20 decimals for precision
+    sum *= U256::from(100);
+    a *= U256::from(100);
+    for (x_n, a_n) in &EXP_TERMS {
+        if a >= *a_n {
+            a = (a * ONE_20) / *a_n;
+            sum += *x_n;
+        }
+    }
+
+    let z = ((a - ONE_20) * ONE_20) / (a + ONE_20);
+    let z_squared = (z * z) / ONE_20;
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3u64, 5, 7, 9, 11] {
+        num = (num * z_squared) / ONE_20;
+        series_sum += num / U256::from(n);
+    }
+    series_sum *= U256::from(2);
+
+    (false, (sum + series_sum) / U256::from(100))
+}
+
+/// Computes `ln(x)` of an `x` close to 1 with 36 decimals, returned as whether it's negative and
+/// its magnitude.
+///
+/// Uses the same series as `ln`, which converges quickly close to 1.
+fn ln_36(x: U256) -> (bool, U256) {
+    let x = x * WAD;
+    let negative = x < ONE_36;
+    let distance = if negative { ONE_36 - x } else { x - ONE_36 };
+
+    let z = (distance * ONE_36) / (x + ONE_36);
+    let z_squared = (z * z) / ONE_36;
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3u64, 5, 7, 9, 11, 13, 15] {
+        num = (num * z_squared) / ONE_36;
+        series_sum += num / U256::from(n);
+    }
+
+    (negative, series_sum * U256::from(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use num_bigint::BigUint;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::protocol::u256_num::{biguint_to_u256, u256_to_biguint, u256_to_f64};
+
+    fn u256(s: &str) -> U256 {
+        U256::from_str(s).unwrap()
+    }
+
+    fn wad(value: f64) -> U256 {
+        U256::from((value * 1e18) as u128)
+    }
+
+    /// Values of all magnitudes, sorted, to check the functions against references
+    fn samples() -> Vec<U256> {
+        let mut values = vec![
+            U256::ZERO,
+            U256::from(1),
+            U256::from(2),
+            U256::from(999),
+            WAD - U256::from(1),
+            WAD,
+            WAD + U256::from(1),
+            u256("1234567890123456789"),
+            u256("314159265358979323846"),
+            RAY - U256::from(1),
+            RAY,
+            RAY + U256::from(7),
+            U256::from(1) << 128,
+            u256("98765432109876543210987654321098765432109876543210"),
+            U256::MAX / WAD,
+            U256::MAX / RAY,
+            U256::MAX,
+        ];
+        values.sort();
+        values
+    }
+
+    /// Reference of `a * b / den` with arbitrary precision, `None` where the product overflows
+    fn mul_div_reference(a: U256, b: U256, den: U256, round_up: bool) -> Option<U256> {
+        let product = u256_to_biguint(a) * u256_to_biguint(b);
+        if product > u256_to_biguint(U256::MAX) {
+            return None;
+        }
+        let den = u256_to_biguint(den);
+        let mut res = &product / &den;
+        if round_up && &res * &den != product {
+            res += 1u8;
+        }
+        Some(biguint_to_u256(&res))
+    }
+
+    #[rstest]
+    #[case::exact(wad(1.5), wad(2.0), wad(3.0), wad(3.0))]
+    #[case::rounded(U256::from(1), wad(0.5), U256::ZERO, U256::from(1))]
+    #[case::zero(U256::ZERO, wad(2.0), U256::ZERO, U256::ZERO)]
+    #[case::below_one(u256("333333333333333333"), U256::from(3), U256::ZERO, U256::from(1))]
+    fn test_mul_wad(#[case] a: U256, #[case] b: U256, #[case] down: U256, #[case] up: U256) {
+        assert_eq!(mul_wad_down(a, b).unwrap(), down);
+        assert_eq!(mul_wad_up(a, b).unwrap(), up);
+    }
+
+    #[rstest]
+    #[case::exact(wad(3.0), wad(2.0), wad(1.5), wad(1.5))]
+    #[case::third(wad(1.0), wad(3.0), u256("333333333333333333"), u256("333333333333333334"))]
+    #[case::zero(U256::ZERO, wad(3.0), U256::ZERO, U256::ZERO)]
+    fn test_div_wad(#[case] a: U256, #[case] b: U256, #[case] down: U256, #[case] up: U256) {
+        assert_eq!(div_wad_down(a, b).unwrap(), down);
+        assert_eq!(div_wad_up(a, b).unwrap(), up);
+    }
+
+    #[test]
+    fn test_ray() {
+        let third = div_ray_down(RAY, RAY * U256::from(3)).unwrap();
+
+        assert_eq!(third, u256("333333333333333333333333333"));
+        assert_eq!(div_ray_up(RAY, RAY * U256::from(3)).unwrap(), third + U256::from(1));
+        assert_eq!(mul_ray_down(third, RAY * U256::from(3)).unwrap(), RAY - U256::from(1));
+        assert_eq!(mul_ray_up(third, RAY * U256::from(3)).unwrap(), RAY - U256::from(1));
+    }
+
+    #[rstest]
+    #[case::mul_down(mul_wad_down(U256::MAX, U256::from(2)))]
+    #[case::mul_up(mul_ray_up(U256::MAX, U256::from(2)))]
+    #[case::div_down_zero(div_wad_down(WAD, U256::ZERO))]
+    #[case::div_up_zero(div_ray_up(U256::ZERO, U256::ZERO))]
+    #[case::div_inflated(div_wad_down(U256::MAX, WAD))]
+    fn test_overflow(#[case] result: Result<U256, SimulationError>) {
+        assert!(matches!(result, Err(SimulationError::FatalError(_))));
+    }
+
+    #[rstest]
+    #[case::wad(WAD)]
+    #[case::ray(RAY)]
+    fn test_against_reference(#[case] one: U256) {
+        for &a in &samples() {
+            for &b in &samples() {
+                assert_eq!(
+                    mul_down(a, b, one).ok(),
+                    mul_div_reference(a, b, one, false),
+                    "{a} * {b}"
+                );
+                assert_eq!(mul_up(a, b, one).ok(), mul_div_reference(a, b, one, true), "{a} * {b}");
+                if b.is_zero() {
+                    continue;
+                }
+                assert_eq!(
+                    div_down(a, b, one).ok(),
+                    mul_div_reference(a, one, b, false),
+                    "{a} / {b}"
+                );
+                assert_eq!(div_up(a, b, one).ok(), mul_div_reference(a, one, b, true), "{a} / {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &a in &samples() {
+            for &b in samples()
+                .iter()
+                .filter(|b| !b.is_zero())
+            {
+                let (Ok(down), Ok(up)) = (mul_wad_down(a, b), mul_wad_up(a, b)) else {
+                    continue;
+                };
+                // Rounding in the same direction twice never crosses the original value
+                if let Ok(res) = div_wad_down(down, b) {
+                    assert!(res <= a, "{a} * {b} / {b}");
+                }
+                if let Ok(res) = div_wad_up(up, b) {
+                    assert!(res >= a, "{a} * {b} / {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let samples = samples();
+        for &b in &samples {
+            let results = samples
+                .iter()
+                .map_while(|&a| Some((mul_wad_down(a, b).ok()?, mul_wad_up(a, b).ok()?)))
+                .collect::<Vec<_>>();
+            for pair in results.windows(2) {
+                assert!(pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1, "* {b}");
+            }
+            for (down, up) in results {
+                assert!(up - down <= U256::from(1), "* {b}");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case::sqrt(wad(2.0), wad(0.5), "1414213562373095047")]
+    #[case::square(wad(3.0), wad(2.0), "8999999999999999991")]
+    #[case::below_one(wad(0.5), wad(3.0), "125000000000000000")]
+    #[case::fractional(u256("1234567890000000000"), wad(1.5), "1371742093758573428")]
+    #[case::close_to_one(u256("1050000000000000000"), wad(2.5), "1129726321947045720")]
+    #[case::close_to_one_below(u256("950000000000000000"), wad(7.0), "698337296093750000")]
+    #[case::large(wad(10000.0), wad(3.0), "999999999999999999746590469972")]
+    #[case::zero_exponent(wad(5.0), U256::ZERO, "1000000000000000000")]
+    #[case::zero_base(U256::ZERO, wad(5.0), "0")]
+    fn test_pow_wad(#[case] x: U256, #[case] y: U256, #[case] exp: &str) {
+        assert_eq!(pow_wad(x, y).unwrap(), u256(exp));
+    }
+
+    #[rstest]
+    #[case::base(U256::from(1) << 255, WAD)]
+    #[case::exponent(WAD * U256::from(2), MILD_EXPONENT_BOUND)]
+    #[case::product_too_large(wad(1e18), wad(10.0))]
+    #[case::product_too_small(U256::from(1), wad(2.0))]
+    fn test_pow_wad_out_of_bounds(#[case] x: U256, #[case] y: U256) {
+        assert!(matches!(pow_wad(x, y), Err(SimulationError::FatalError(_))));
+    }
+
+    #[test]
+    fn test_pow_wad_against_f64() {
+        let exponents = [0.1, 0.5, 0.8, 1.5, 2.2, 3.0, 7.5];
+        let bases = samples()
+            .into_iter()
+            .filter(|x| *x > U256::from(1000) && *x < (U256::from(1) << 128));
+        for x in bases {
+            let mut previous = None;
+            for y in exponents.map(wad) {
+                let Ok(raw) = pow_wad(x, y) else {
+                    continue;
+                };
+                let down = pow_wad_down(x, y).unwrap();
+                let up = pow_wad_up(x, y).unwrap();
+                let exact = (u256_to_f64(x) / 1e18).powf(u256_to_f64(y) / 1e18) * 1e18;
+
+                assert!(down <= raw && raw <= up, "{x} ^ {y}");
+                assert!(u256_to_f64(down) <= exact * (1.0 + 1e-15), "{x} ^ {y}");
+                assert!(u256_to_f64(up) >= exact * (1.0 - 1e-15), "{x} ^ {y}");
+                // Powers of bases above 1 grow with the exponent, those below 1 shrink
+                if let Some(previous) = previous {
+                    let monotonic = if x > WAD { raw >= previous } else { raw <= previous };
+                    assert!(monotonic, "{x} ^ {y}");
+                }
+                previous = Some(raw);
+            }
+        }
+    }
+
+    #[rstest]
+    #[case::one(WAD)]
+    #[case::two(wad(2.0))]
+    #[case::four(wad(4.0))]
+    fn test_pow_wad_integer_exponents(#[case] y: U256) {
+        let x = u256("1234567890123456789");
+        let exact = u256_to_biguint(x).pow((y / WAD).to::<u32>()) /
+            BigUint::from(10u8).pow(18 * ((y / WAD).to::<u32>() - 1));
+
+        // Integer exponents are exact up to the rounding of each multiplication
+        let down = u256_to_biguint(pow_wad_down(x, y).unwrap());
+        let up = u256_to_biguint(pow_wad_up(x, y).unwrap());
+        assert!(down <= exact && exact <= up);
+        assert!(&up - &down <= BigUint::from(4u8));
+    }
+}
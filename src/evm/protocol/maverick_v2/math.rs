@@ -0,0 +1,221 @@
+//! Maverick V2 tick math
+//!
+//! Like in the pool contract, amounts, sqrt prices and liquidities are fixed point numbers with
+//! 18 decimals. Token amounts are scaled to 18 decimals, so prices are in whole units of token B
+//! per whole unit of token A.
+//!
+//! The liquidity of a tick is spread over its price range like a Uniswap V3 position: with `L`
+//! the tick's liquidity, `p` the current sqrt price and `[pl, pu]` the tick's sqrt price range,
+//! its reserves are `a = L * (1/p - 1/pu)` and `b = L * (p - pl)`.
+use alloy_primitives::{U256, U512};
+
+use crate::{
+    evm::protocol::{
+        safe_math::{safe_div_u512, safe_mul_u512, safe_sub_u256},
+        utils::uniswap::tick_math::{get_sqrt_ratio_at_tick, MAX_TICK, MIN_TICK},
+    },
+    protocol::errors::SimulationError,
+};
+
+/// 1 as an 18 decimals fixed point number
+pub(super) const ONE: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+
+/// Returns the sqrt prices at the lower and upper end of `tick`.
+///
+/// The tick covers the prices from `1.0001^(tick * tick_spacing)` to
+/// `1.0001^((tick + 1) * tick_spacing)`.
+pub(super) fn tick_sqrt_prices(
+    tick_spacing: u32,
+    tick: i32,
+) -> Result<(U256, U256), SimulationError> {
+    let lower = i64::from(tick) * i64::from(tick_spacing);
+    Ok((sqrt_price_at(lower)?, sqrt_price_at(lower + i64::from(tick_spacing))?))
+}
+
+fn sqrt_price_at(tick: i64) -> Result<U256, SimulationError> {
+    if tick < i64::from(MIN_TICK) || tick > i64::from(MAX_TICK) {
+        return Err(SimulationError::FatalError(format!("Tick price {tick} out of range")));
+    }
+    let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick as i32)?;
+    // Below 2^161, so the product fits into 256 bits
+    Ok((sqrt_price_x96 * ONE) >> 96)
+}
+
+/// Returns the liquidity of a tick holding the given reserves.
+///
+/// Solves the reserve equations of the module documentation for `L`, eliminating the unknown
+/// current price, which gives the quadratic equation
+/// `(1 - pl/pu) * L^2 - (a * pl + b / pu) * L - a * b = 0`.
+pub(super) fn tick_liquidity(
+    reserve_a: U256,
+    reserve_b: U256,
+    sqrt_lower: U256,
+    sqrt_upper: U256,
+) -> Result<U256, SimulationError> {
+    if reserve_a.is_zero() && reserve_b.is_zero() {
+        return Ok(U256::ZERO);
+    }
+    let one = U512::from(ONE);
+    let (a, b) = (U512::from(reserve_a), U512::from(reserve_b));
+    let (lower, upper) = (U512::from(sqrt_lower), U512::from(sqrt_upper));
+    let diff = U512::from(safe_sub_u256(sqrt_upper, sqrt_lower)?);
+
+    let linear = a * lower / one + safe_div_u512(b * one, upper)?;
+    let constant = safe_div_u512(U512::from(4u8) * diff * a * b, upper)?;
+    let root = sqrt_u512(safe_mul_u512(linear, linear)? + constant);
+    let liquidity = safe_div_u512((linear + root) * upper, U512::from(2u8) * diff)?;
+    to_u256(liquidity)
+}
+
+/// Returns the current sqrt price of a tick with the given liquidity and reserve of token B.
+///
+/// Ticks without liquidity are at their lower price.
+pub(super) fn tick_sqrt_price(
+    reserve_b: U256,
+    liquidity: U256,
+    sqrt_lower: U256,
+    sqrt_upper: U256,
+) -> U256 {
+    if liquidity.is_zero() {
+        return sqrt_lower;
+    }
+    let above_lower = U512::from(reserve_b) * U512::from(ONE) / U512::from(liquidity);
+    to_u256(above_lower)
+        .map(|above_lower| sqrt_lower.saturating_add(above_lower))
+        .unwrap_or(sqrt_upper)
+        .min(sqrt_upper)
+}
+
+/// Returns the reserve of the input token a tick holds once its output reserve is drained.
+pub(super) fn drained_reserve(
+    a_in: bool,
+    liquidity: U256,
+    sqrt_lower: U256,
+    sqrt_upper: U256,
+) -> Result<U256, SimulationError> {
+    let (l, lower, upper) = (U512::from(liquidity), U512::from(sqrt_lower), U512::from(sqrt_upper));
+    let diff = upper - lower;
+    let reserve = if a_in {
+        // L * (1/pl - 1/pu), rounded up
+        div_up(l * diff * U512::from(ONE), lower * upper)?
+    } else {
+        // L * (pu - pl), rounded up
+        div_up(l * diff, U512::from(ONE))?
+    };
+    to_u256(reserve)
+}
+
+/// Returns the amount out of a swap of `amount_in`, net of fees, that doesn't drain the tick.
+///
+/// Rounds in favor of the pool.
+pub(super) fn amount_out_in_tick(
+    a_in: bool,
+    amount_in: U256,
+    liquidity: U256,
+    sqrt_price: U256,
+) -> Result<U256, SimulationError> {
+    let one = U512::from(ONE);
+    let (amount, l, price) = (U512::from(amount_in), U512::from(liquidity), U512::from(sqrt_price));
+    let out = if a_in {
+        // 1/p' = 1/p + amount / L
+        let new_price = div_up(l * price, l + amount * price / one)?;
+        l * (price - new_price.min(price)) / one
+    } else {
+        // p' = p + amount / L
+        let new_price = price + safe_div_u512(amount * one, l)?;
+        safe_div_u512(l * (new_price - price) * one, price * new_price)?
+    };
+    to_u256(out)
+}
+
+/// Integer square root, rounded down
+fn sqrt_u512(value: U512) -> U512 {
+    if value.is_zero() {
+        return value;
+    }
+    // A power of two not below the root
+    let mut root = U512::from(1u8) << value.bit_len().div_ceil(2);
+    loop {
+        let next = (root + value / root) >> 1;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
+fn div_up(num: U512, den: U512) -> Result<U512, SimulationError> {
+    let quotient = safe_div_u512(num, den)?;
+    Ok(if quotient * den == num { quotient } else { quotient + U512::from(1u8) })
+}
+
+fn to_u256(value: U512) -> Result<U256, SimulationError> {
+    U256::checked_from(value)
+        .ok_or_else(|| SimulationError::FatalError("Maverick V2 math overflow".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+    use crate::evm::protocol::u256_num::u256_to_f64;
+
+    fn d18(value: f64) -> U256 {
+        U256::from((value * 1e18) as u128)
+    }
+
+    fn f64_d18(value: U256) -> f64 {
+        u256_to_f64(value) / 1e18
+    }
+
+    #[test]
+    fn test_tick_sqrt_prices() {
+        let (lower, upper) = tick_sqrt_prices(10, 1).unwrap();
+
+        assert_relative_eq!(f64_d18(lower), 1.0001f64.powi(10).sqrt(), max_relative = 1e-12);
+        assert_relative_eq!(f64_d18(upper), 1.0001f64.powi(20).sqrt(), max_relative = 1e-12);
+        assert!(tick_sqrt_prices(1, MAX_TICK).is_err());
+    }
+
+    #[test]
+    fn test_tick_liquidity_roundtrip() {
+        let (lower, upper) = tick_sqrt_prices(100, 0).unwrap();
+        let (l, p) = (1_000.0, 1.002);
+        // Reserves of liquidity `l` at sqrt price `p`
+        let a = l * (1.0 / p - 1.0 / f64_d18(upper));
+        let b = l * (p - f64_d18(lower));
+
+        let liquidity = tick_liquidity(d18(a), d18(b), lower, upper).unwrap();
+        let price = tick_sqrt_price(d18(b), liquidity, lower, upper);
+
+        assert_relative_eq!(f64_d18(liquidity), l, max_relative = 1e-9);
+        assert_relative_eq!(f64_d18(price), p, max_relative = 1e-9);
+    }
+
+    #[test]
+    fn test_single_sided_tick_liquidity() {
+        let (lower, upper) = tick_sqrt_prices(100, 0).unwrap();
+
+        let only_b = tick_liquidity(U256::ZERO, ONE, lower, upper).unwrap();
+        let only_a = tick_liquidity(ONE, U256::ZERO, lower, upper).unwrap();
+
+        // All B: the price is at the top of the tick, all A: at the bottom
+        assert_eq!(tick_sqrt_price(ONE, only_b, lower, upper), upper);
+        assert_eq!(tick_sqrt_price(U256::ZERO, only_a, lower, upper), lower);
+        assert_relative_eq!(
+            f64_d18(only_b),
+            1.0 / (f64_d18(upper) - f64_d18(lower)),
+            max_relative = 1e-9
+        );
+    }
+
+    #[test]
+    fn test_sqrt_u512() {
+        assert_eq!(sqrt_u512(U512::ZERO), U512::ZERO);
+        assert_eq!(sqrt_u512(U512::from(15u8)), U512::from(3u8));
+        assert_eq!(sqrt_u512(U512::from(16u8)), U512::from(4u8));
+        let big = U512::from(u128::MAX);
+        assert_eq!(sqrt_u512(big * big), big);
+    }
+}
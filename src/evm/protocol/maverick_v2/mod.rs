@@ -0,0 +1,4 @@
+//! Maverick V2 Decentralized Exchange
+mod math;
+pub mod state;
+pub mod tycho_decoder;
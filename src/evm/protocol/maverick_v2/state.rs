@@ -0,0 +1,1019 @@
+use std::{any::Any, cmp::Ordering, collections::BTreeMap};
+
+use alloy_primitives::U256;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use tycho_core::{dto::ProtocolStateDelta, Bytes};
+
+use super::math::{
+    amount_out_in_tick, drained_reserve, tick_liquidity, tick_sqrt_price, tick_sqrt_prices, ONE,
+};
+use crate::{
+    evm::protocol::{
+        safe_math::{safe_add_u256, safe_div_u256, safe_mul_u256, safe_sub_u256},
+        u256_num::{
+            biguint_to_u256_checked, scale_between_decimals, u256_to_biguint, u256_to_f64, Rounding,
+        },
+        utils::uniswap::{i24_be_bytes_to_i32, TickGasModel},
+    },
+    models::Token,
+    protocol::{
+        errors::{SimulationError, TransitionError},
+        models::{swap_gas_breakdown, GetAmountOutResult},
+        state::{fingerprint, ProtocolSim},
+    },
+};
+
+/// Gas of the pool's swap logic, see `TickGasModel`
+const GAS_MODEL: TickGasModel = TickGasModel {
+    base: 60_000,
+    // Writes of the tick's reserves, and the bitmap search for the next tick with liquidity
+    per_crossed_tick: 18_000,
+    per_word: 0,
+};
+
+/// Internal amounts of Maverick V2 pools have 18 decimals, whatever the token's decimals
+const INTERNAL_DECIMALS: usize = 18;
+
+/// Log prices are in ticks with 8 decimals
+const LOG_PRICE_ONE: i64 = 100_000_000;
+
+/// How a bin's liquidity moves when the pool's price moves, see `Bin`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BinKind {
+    /// Stays at its tick
+    Static,
+    /// Follows the price when it moves up
+    Right,
+    /// Follows the price when it moves down
+    Left,
+    /// Follows the price in both directions
+    Both,
+}
+
+impl TryFrom<u8> for BinKind {
+    type Error = String;
+
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0 => Ok(BinKind::Static),
+            1 => Ok(BinKind::Right),
+            2 => Ok(BinKind::Left),
+            3 => Ok(BinKind::Both),
+            _ => Err(format!("Unknown bin kind {kind}")),
+        }
+    }
+}
+
+/// Reserves of a tick, as 18 decimals internal amounts
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TickState {
+    pub reserve_a: U256,
+    pub reserve_b: U256,
+    /// Total tick balance of the bins in this tick
+    pub total_supply: U256,
+}
+
+impl TickState {
+    fn is_empty(&self) -> bool {
+        self.reserve_a.is_zero() && self.reserve_b.is_zero()
+    }
+}
+
+/// Time weighted average price of the pool, which movement bins follow
+///
+/// Log prices are in ticks with 8 decimals: the integer part is the tick, the fraction the
+/// position within it. The average moves towards the last price over `lookback` seconds:
+/// `twa = last_twa + (last_log_price - last_twa) * min(elapsed, lookback) / lookback`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Twa {
+    /// Seconds over which the average reaches a new price
+    pub lookback: u64,
+    pub last_twa_d8: i64,
+    /// Log price at the end of the last swap
+    pub last_log_price_d8: i64,
+    /// Time of the last update of the average, in seconds since the unix epoch
+    pub last_timestamp: u64,
+}
+
+impl Twa {
+    /// Returns the average at `timestamp`.
+    fn at(&self, timestamp: u64) -> i64 {
+        let elapsed = timestamp.saturating_sub(self.last_timestamp);
+        if elapsed >= self.lookback {
+            return self.last_log_price_d8;
+        }
+        let moved = i128::from(self.last_log_price_d8 - self.last_twa_d8) * i128::from(elapsed) /
+            i128::from(self.lookback);
+        self.last_twa_d8 + moved as i64
+    }
+}
+
+/// Returns the tick of a log price.
+fn log_price_tick(log_price_d8: i64) -> i32 {
+    log_price_d8.div_euclid(LOG_PRICE_ONE) as i32
+}
+
+/// A liquidity position of the pool
+///
+/// Each bin owns `tick_balance` out of the `total_supply` of its tick, and the same share of the
+/// tick's reserves.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Bin {
+    pub kind: BinKind,
+    pub tick: i32,
+    pub tick_balance: U256,
+}
+
+/// Native simulation of a Maverick V2 pool
+///
+/// The pool's liquidity is held by bins, grouped into ticks covering a price range of
+/// `tick_spacing` basis points each. Swaps consume the liquidity of the active tick, then of the
+/// next ticks in the direction of the swap, like in Uniswap V3. Fees depend on the swap's
+/// direction and are added to the reserves of the ticks swapped through.
+///
+/// Movement bins (`Right`, `Left` and `Both`) follow the pool's time weighted average price, see
+/// `Twa`. A swap first brings the average up to date and moves the bins to its tick, then swaps.
+/// Swaps are simulated at the time of the last update by default, where the average doesn't
+/// move; set a later time, e.g. of the next block, with `with_timestamp`. The protocol's share of
+/// the fees is left in the tick reserves.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MaverickV2State {
+    tick_spacing: u32,
+    /// Fee of swaps of token A for token B, as an 18 decimals fraction of the amount in
+    fee_a_in: U256,
+    /// Fee of swaps of token B for token A, as an 18 decimals fraction of the amount in
+    fee_b_in: U256,
+    active_tick: i32,
+    ticks: BTreeMap<i32, TickState>,
+    bins: BTreeMap<u32, Bin>,
+    #[serde(default)]
+    twa: Twa,
+    /// Time swaps are simulated at, the time of the last update of `twa` if `None`
+    #[serde(default)]
+    timestamp: Option<u64>,
+    /// Addresses of token A and B. Empty unless set with `with_tokens`.
+    #[serde(default)]
+    tokens: Vec<Bytes>,
+}
+
+/// Outcome of a swap, in internal amounts
+#[derive(Debug)]
+struct SwapOutcome {
+    amount_out: U256,
+    /// Amount in left once all ticks are drained
+    amount_remaining: U256,
+    new_state: MaverickV2State,
+    crossed_ticks: u32,
+}
+
+impl MaverickV2State {
+    /// Creates a new `MaverickV2State`.
+    ///
+    /// # Arguments
+    /// - `tick_spacing`: Width of a tick, in basis points of price.
+    /// - `fee_a_in`, `fee_b_in`: Fees of each swap direction, as 18 decimals fractions.
+    /// - `active_tick`: The tick holding the current price.
+    /// - `ticks`: Reserves of the ticks with liquidity, by tick.
+    /// - `bins`: The pool's bins, by id.
+    pub fn new(
+        tick_spacing: u32,
+        fee_a_in: U256,
+        fee_b_in: U256,
+        active_tick: i32,
+        ticks: BTreeMap<i32, TickState>,
+        bins: BTreeMap<u32, Bin>,
+    ) -> Result<Self, String> {
+        if tick_spacing == 0 {
+            return Err("Tick spacing must not be zero".to_string());
+        }
+        if fee_a_in >= ONE || fee_b_in >= ONE {
+            return Err("Fees must be below 100%".to_string());
+        }
+        Ok(Self {
+            tick_spacing,
+            fee_a_in,
+            fee_b_in,
+            active_tick,
+            ticks,
+            bins,
+            twa: Twa::default(),
+            timestamp: None,
+            tokens: Vec::new(),
+        })
+    }
+
+    /// Sets the time weighted average price the movement bins follow.
+    pub fn with_twa(mut self, twa: Twa) -> Self {
+        self.twa = twa;
+        self
+    }
+
+    /// Simulates swaps at `timestamp`, in seconds since the unix epoch, instead of at the time of
+    /// the last update of the average price.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the addresses of token A and B reported by `tokens`.
+    pub fn with_tokens(mut self, tokens: Vec<Bytes>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    pub fn active_tick(&self) -> i32 {
+        self.active_tick
+    }
+
+    pub fn tick(&self, tick: i32) -> Option<&TickState> {
+        self.ticks.get(&tick)
+    }
+
+    pub fn bin(&self, id: u32) -> Option<&Bin> {
+        self.bins.get(&id)
+    }
+
+    pub fn twa(&self) -> &Twa {
+        &self.twa
+    }
+
+    /// Returns the reserves of token A and B owned by a bin, as 18 decimals internal amounts.
+    pub fn bin_reserves(&self, id: u32) -> Option<(U256, U256)> {
+        let bin = self.bins.get(&id)?;
+        let tick = self.ticks.get(&bin.tick)?;
+        if tick.total_supply.is_zero() {
+            return Some((U256::ZERO, U256::ZERO));
+        }
+        let share = |reserve: U256| {
+            safe_mul_u256(reserve, bin.tick_balance)
+                .and_then(|product| safe_div_u256(product, tick.total_supply))
+                .ok()
+        };
+        Some((share(tick.reserve_a)?, share(tick.reserve_b)?))
+    }
+
+    /// Applies a Tycho attribute of the pool's state:
+    ///  - `active_tick`: the tick holding the current price.
+    ///  - `last_twa_d8`, `last_log_price_d8`, `last_timestamp`: the time weighted average price,
+    ///    see `Twa`.
+    ///  - `ticks/{tick}/reserve_a`, `ticks/{tick}/reserve_b`, `ticks/{tick}/total_supply`: the
+    ///    reserves and total bin balance of a tick.
+    ///  - `bins/{id}/kind`, `bins/{id}/tick`, `bins/{id}/tick_balance`: the properties of a bin.
+    ///    New bins are created as `Static` bins of tick 0 until all their attributes are set.
+    ///
+    /// Returns whether the attribute is known.
+    pub(super) fn set_attribute(&mut self, key: &str, value: &Bytes) -> Result<bool, String> {
+        match key {
+            "active_tick" => {
+                self.active_tick = bytes_to_i32(value);
+                return Ok(true);
+            }
+            "last_twa_d8" => {
+                self.twa.last_twa_d8 = bytes_to_i64(value);
+                return Ok(true);
+            }
+            "last_log_price_d8" => {
+                self.twa.last_log_price_d8 = bytes_to_i64(value);
+                return Ok(true);
+            }
+            "last_timestamp" => {
+                self.twa.last_timestamp = u64::try_from(U256::from_be_slice(value))
+                    .map_err(|_| format!("Invalid timestamp {value}"))?;
+                return Ok(true);
+            }
+            _ => {}
+        }
+        let parts: Vec<&str> = key.split('/').collect();
+        match parts.as_slice() {
+            ["ticks", tick, field] => {
+                let tick = tick
+                    .parse::<i32>()
+                    .map_err(|e| format!("Invalid tick in {key}: {e}"))?;
+                let state = self.ticks.entry(tick).or_default();
+                let value = U256::from_be_slice(value);
+                match *field {
+                    "reserve_a" => state.reserve_a = value,
+                    "reserve_b" => state.reserve_b = value,
+                    "total_supply" => state.total_supply = value,
+                    _ => return Ok(false),
+                }
+                if state.is_empty() && state.total_supply.is_zero() {
+                    self.ticks.remove(&tick);
+                }
+                Ok(true)
+            }
+            ["bins", id, field] => {
+                let id = id
+                    .parse::<u32>()
+                    .map_err(|e| format!("Invalid bin id in {key}: {e}"))?;
+                let bin = self.bins.entry(id).or_insert(Bin {
+                    kind: BinKind::Static,
+                    tick: 0,
+                    tick_balance: U256::ZERO,
+                });
+                match *field {
+                    "kind" => bin.kind = BinKind::try_from(value.last().copied().unwrap_or(0))?,
+                    "tick" => bin.tick = bytes_to_i32(value),
+                    "tick_balance" => bin.tick_balance = U256::from_be_slice(value),
+                    _ => return Ok(false),
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Removes a tick or bin attribute, as for attributes deleted by a Tycho delta.
+    fn delete_attribute(&mut self, key: &str) -> Result<(), String> {
+        match key
+            .split('/')
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            ["ticks", ..] => {
+                self.set_attribute(key, &Bytes::from(vec![0u8]))?;
+            }
+            ["bins", id, _] => {
+                if let Ok(id) = id.parse::<u32>() {
+                    self.bins.remove(&id);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn fee_for(&self, a_in: bool) -> U256 {
+        if a_in {
+            self.fee_a_in
+        } else {
+            self.fee_b_in
+        }
+    }
+
+    /// Returns the current sqrt price, that of the active tick.
+    fn sqrt_price(&self) -> Result<U256, SimulationError> {
+        let (lower, upper) = tick_sqrt_prices(self.tick_spacing, self.active_tick)?;
+        let Some(tick) = self.ticks.get(&self.active_tick) else {
+            return Ok(lower);
+        };
+        let liquidity = tick_liquidity(tick.reserve_a, tick.reserve_b, lower, upper)?;
+        Ok(tick_sqrt_price(tick.reserve_b, liquidity, lower, upper))
+    }
+
+    /// Swaps `amount_in` of token A if `a_in`, of token B otherwise, as internal amounts.
+    ///
+    /// Swapping token A moves the price down: the active tick is swapped through first, then the
+    /// ticks below it, until the amount in is spent. Each tick is drained of the output token
+    /// before moving to the next one.
+    fn swap(&self, a_in: bool, amount_in: U256) -> Result<SwapOutcome, SimulationError> {
+        let fee = self.fee_for(a_in);
+        let fee_complement = ONE - fee;
+        let mut new_state = self.clone();
+        new_state.update_twa()?;
+        let ticks: Vec<i32> = if a_in {
+            new_state
+                .ticks
+                .range(..=new_state.active_tick)
+                .rev()
+                .map(|(tick, _)| *tick)
+                .collect()
+        } else {
+            new_state
+                .ticks
+                .range(new_state.active_tick..)
+                .map(|(tick, _)| *tick)
+                .collect()
+        };
+
+        let mut remaining = amount_in;
+        let mut amount_out = U256::ZERO;
+        let mut crossed_ticks = 0u32;
+        for tick in ticks {
+            if remaining.is_zero() {
+                break;
+            }
+            let state = new_state
+                .ticks
+                .get_mut(&tick)
+                .expect("ticks are taken from the state");
+            let (reserve_in, reserve_out) = if a_in {
+                (state.reserve_a, state.reserve_b)
+            } else {
+                (state.reserve_b, state.reserve_a)
+            };
+            if reserve_out.is_zero() {
+                continue;
+            }
+            let (lower, upper) = tick_sqrt_prices(self.tick_spacing, tick)?;
+            let liquidity = tick_liquidity(state.reserve_a, state.reserve_b, lower, upper)?;
+            let drained = drained_reserve(a_in, liquidity, lower, upper)?;
+            // Net amount draining the tick, and the gross amount including fees
+            let net_to_drain = drained.saturating_sub(reserve_in);
+            let gross_to_drain = div_up(safe_mul_u256(net_to_drain, ONE)?, fee_complement)?;
+
+            let (spent, received) = if remaining >= gross_to_drain {
+                // The price leaves the drained tick
+                crossed_ticks += 1;
+                new_state.active_tick = if a_in { tick - 1 } else { tick + 1 };
+                (gross_to_drain, reserve_out)
+            } else {
+                new_state.active_tick = tick;
+                let net = safe_mul_u256(remaining, fee_complement)? / ONE;
+                let sqrt_price = tick_sqrt_price(state.reserve_b, liquidity, lower, upper);
+                let out = amount_out_in_tick(a_in, net, liquidity, sqrt_price)?;
+                (remaining, out.min(reserve_out))
+            };
+            let (new_in, new_out) =
+                (safe_add_u256(reserve_in, spent)?, safe_sub_u256(reserve_out, received)?);
+            if a_in {
+                (state.reserve_a, state.reserve_b) = (new_in, new_out);
+            } else {
+                (state.reserve_b, state.reserve_a) = (new_in, new_out);
+            }
+            remaining -= spent;
+            amount_out = safe_add_u256(amount_out, received)?;
+        }
+        new_state.twa.last_log_price_d8 = new_state.log_price_d8()?;
+        Ok(SwapOutcome { amount_out, amount_remaining: remaining, new_state, crossed_ticks })
+    }
+
+    /// Brings the average price up to the simulation time and moves the movement bins to its
+    /// tick.
+    fn update_twa(&mut self) -> Result<(), SimulationError> {
+        let Some(timestamp) = self.timestamp else {
+            return Ok(());
+        };
+        if timestamp <= self.twa.last_timestamp {
+            return Ok(());
+        }
+        let from = log_price_tick(self.twa.last_twa_d8);
+        self.twa.last_twa_d8 = self.twa.at(timestamp);
+        self.twa.last_timestamp = timestamp;
+        self.move_bins(from, log_price_tick(self.twa.last_twa_d8))
+    }
+
+    /// Moves the movement bins left behind by the average price moving from tick `from` to `to`.
+    ///
+    /// Moving up, the `Right` and `Both` bins of the ticks from `from` up to below `to` move to
+    /// `to`. Moving down, the `Left` and `Both` bins of the ticks above `to` up to `from` do.
+    fn move_bins(&mut self, from: i32, to: i32) -> Result<(), SimulationError> {
+        let moved: Vec<u32> = self
+            .bins
+            .iter()
+            .filter(|(_, bin)| match (bin.kind, to.cmp(&from)) {
+                (BinKind::Right | BinKind::Both, Ordering::Greater) => {
+                    bin.tick >= from && bin.tick < to
+                }
+                (BinKind::Left | BinKind::Both, Ordering::Less) => {
+                    bin.tick > to && bin.tick <= from
+                }
+                _ => false,
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in moved {
+            self.move_bin(id, to)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the reserves of a bin from its tick to `to`.
+    ///
+    /// The bin's balance in its new tick is its share of the tick's total reserves, counting
+    /// token A and B alike.
+    fn move_bin(&mut self, id: u32, to: i32) -> Result<(), SimulationError> {
+        let (reserve_a, reserve_b) = self
+            .bin_reserves(id)
+            .ok_or_else(|| SimulationError::FatalError(format!("Bin {id} has no tick")))?;
+        let bin = self
+            .bins
+            .get_mut(&id)
+            .expect("bin reserves are found");
+        let source = self
+            .ticks
+            .get_mut(&bin.tick)
+            .expect("bin reserves are found");
+        source.reserve_a = safe_sub_u256(source.reserve_a, reserve_a)?;
+        source.reserve_b = safe_sub_u256(source.reserve_b, reserve_b)?;
+        source.total_supply = safe_sub_u256(source.total_supply, bin.tick_balance)?;
+        if source.is_empty() && source.total_supply.is_zero() {
+            self.ticks.remove(&bin.tick);
+        }
+
+        let target = self.ticks.entry(to).or_default();
+        let moved = safe_add_u256(reserve_a, reserve_b)?;
+        let held = safe_add_u256(target.reserve_a, target.reserve_b)?;
+        let tick_balance = if target.total_supply.is_zero() || held.is_zero() {
+            moved
+        } else {
+            safe_div_u256(safe_mul_u256(target.total_supply, moved)?, held)?
+        };
+        target.reserve_a = safe_add_u256(target.reserve_a, reserve_a)?;
+        target.reserve_b = safe_add_u256(target.reserve_b, reserve_b)?;
+        target.total_supply = safe_add_u256(target.total_supply, tick_balance)?;
+        bin.tick = to;
+        bin.tick_balance = tick_balance;
+        Ok(())
+    }
+
+    /// Returns the current log price. The position within the active tick is interpolated
+    /// linearly between the tick's sqrt prices.
+    fn log_price_d8(&self) -> Result<i64, SimulationError> {
+        let (lower, upper) = tick_sqrt_prices(self.tick_spacing, self.active_tick)?;
+        let position = safe_div_u256(
+            safe_mul_u256(safe_sub_u256(self.sqrt_price()?, lower)?, U256::from(LOG_PRICE_ONE))?,
+            safe_sub_u256(upper, lower)?,
+        )?;
+        Ok(i64::from(self.active_tick) * LOG_PRICE_ONE + position.to::<u64>() as i64)
+    }
+}
+
+fn div_up(num: U256, den: U256) -> Result<U256, SimulationError> {
+    let quotient = safe_div_u256(num, den)?;
+    Ok(if quotient * den == num { quotient } else { quotient + U256::from(1u8) })
+}
+
+/// Decodes a big-endian, two's complement integer of up to 8 bytes.
+fn bytes_to_i64(value: &Bytes) -> i64 {
+    let sign_extension = if value
+        .first()
+        .is_some_and(|byte| byte & 0x80 != 0)
+    {
+        0xff
+    } else {
+        0
+    };
+    let mut bytes = [sign_extension; 8];
+    let len = value.len().min(8);
+    bytes[8 - len..].copy_from_slice(&value[value.len() - len..]);
+    i64::from_be_bytes(bytes)
+}
+
+/// Decodes a big-endian, two's complement integer of up to 4 bytes.
+fn bytes_to_i32(value: &Bytes) -> i32 {
+    if value.len() >= 4 {
+        let bytes: [u8; 4] = value[value.len() - 4..]
+            .try_into()
+            .expect("slice has 4 bytes");
+        i32::from_be_bytes(bytes)
+    } else {
+        i24_be_bytes_to_i32(value)
+    }
+}
+
+impl ProtocolSim for MaverickV2State {
+    /// The higher of the two direction dependent fees
+    fn fee(&self) -> f64 {
+        u256_to_f64(self.fee_a_in.max(self.fee_b_in)) / 1e18
+    }
+
+    fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        let sqrt_price = u256_to_f64(self.sqrt_price()?) / 1e18;
+        let price = sqrt_price * sqrt_price;
+        // Internal amounts all have 18 decimals, so the price is in whole units already
+        if base < quote {
+            Ok(price)
+        } else {
+            Ok(1.0 / price)
+        }
+    }
+
+    fn get_amount_out(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let a_in = token_in < token_out;
+        let internal_in = biguint_to_u256_checked(&scale_between_decimals(
+            &amount_in,
+            token_in.decimals,
+            INTERNAL_DECIMALS,
+            Rounding::Down,
+        ))?;
+        if internal_in.is_zero() {
+            return Err(SimulationError::InvalidInput("Amount in cannot be zero".to_string(), None));
+        }
+        let outcome = self.swap(a_in, internal_in)?;
+        let amount_out = scale_between_decimals(
+            &u256_to_biguint(outcome.amount_out),
+            INTERNAL_DECIMALS,
+            token_out.decimals,
+            Rounding::Down,
+        );
+        let result = GetAmountOutResult::with_gas_breakdown(
+            amount_out,
+            swap_gas_breakdown(u256_to_biguint(GAS_MODEL.gas(outcome.crossed_ticks, 0))),
+            Box::new(outcome.new_state),
+        )
+        .with_crossed_ticks(outcome.crossed_ticks);
+        if !outcome.amount_remaining.is_zero() {
+            return Err(SimulationError::InvalidInput(
+                "Not enough liquidity to swap the full amount".to_string(),
+                Some(result),
+            ));
+        }
+        Ok(result)
+    }
+
+    fn delta_transition(
+        &mut self,
+        delta: ProtocolStateDelta,
+        _tokens: &std::collections::HashMap<Bytes, Token>,
+    ) -> Result<(), TransitionError<String>> {
+        for (key, value) in &delta.updated_attributes {
+            self.set_attribute(key, value)
+                .map_err(TransitionError::DecodeError)?;
+        }
+        for key in &delta.deleted_attributes {
+            self.delete_attribute(key)
+                .map_err(TransitionError::DecodeError)?;
+        }
+        Ok(())
+    }
+
+    /// Some tick holds reserves. Fees are checked on construction already.
+    fn is_tradeable(&self) -> bool {
+        self.ticks
+            .values()
+            .any(|tick| !tick.is_empty())
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.tokens.clone()
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        fingerprint(self)
+    }
+
+    fn clone_box(&self) -> Box<dyn ProtocolSim> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn eq(&self, other: &dyn ProtocolSim) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|other| self == other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use approx::assert_relative_eq;
+    use num_traits::ToPrimitive;
+    use rstest::rstest;
+
+    use super::*;
+    use crate::testing::token_at;
+
+    const TICK_SPACING: u32 = 10;
+
+    fn token_a() -> Token {
+        token_at(Bytes::from(vec![1u8; 20]), "A", 18)
+    }
+
+    fn token_b() -> Token {
+        token_at(Bytes::from(vec![2u8; 20]), "B", 18)
+    }
+
+    fn d18(value: f64) -> U256 {
+        U256::from((value * 1e18) as u128)
+    }
+
+    fn sqrt_prices(tick: i32) -> (f64, f64) {
+        let lower = 1.0001f64
+            .powi(tick * TICK_SPACING as i32)
+            .sqrt();
+        (
+            lower,
+            1.0001f64
+                .powi((tick + 1) * TICK_SPACING as i32)
+                .sqrt(),
+        )
+    }
+
+    /// Reserves of a tick holding `liquidity` at `sqrt_price`
+    fn tick_at(tick: i32, liquidity: f64, sqrt_price: f64) -> TickState {
+        let (lower, upper) = sqrt_prices(tick);
+        let sqrt_price = sqrt_price.clamp(lower, upper);
+        TickState {
+            reserve_a: d18(liquidity * (1.0 / sqrt_price - 1.0 / upper)),
+            reserve_b: d18(liquidity * (sqrt_price - lower)),
+            total_supply: d18(1.0),
+        }
+    }
+
+    /// A pool at sqrt price `sqrt_price` in tick 0, with liquidity `liquidity` in ticks -2 to 2
+    fn pool(liquidity: f64, sqrt_price: f64, fee_a_in: f64, fee_b_in: f64) -> MaverickV2State {
+        let ticks = (-2..=2)
+            .map(|tick| (tick, tick_at(tick, liquidity, sqrt_price)))
+            .collect();
+        MaverickV2State::new(TICK_SPACING, d18(fee_a_in), d18(fee_b_in), 0, ticks, BTreeMap::new())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_spot_price() {
+        let state = pool(1_000.0, 1.0002, 0.0, 0.0);
+
+        assert_relative_eq!(
+            state
+                .spot_price(&token_a(), &token_b())
+                .unwrap(),
+            1.0002f64.powi(2),
+            max_relative = 1e-9
+        );
+        assert_relative_eq!(
+            state
+                .spot_price(&token_b(), &token_a())
+                .unwrap(),
+            1.0 / 1.0002f64.powi(2),
+            max_relative = 1e-9
+        );
+    }
+
+    #[rstest]
+    #[case::a_in(true)]
+    #[case::b_in(false)]
+    fn test_swap_within_tick(#[case] a_in: bool) {
+        let (liquidity, sqrt_price, fee) = (1_000.0, 1.0002, 0.001);
+        let state = pool(liquidity, sqrt_price, fee, fee);
+        let (token_in, token_out) =
+            if a_in { (token_a(), token_b()) } else { (token_b(), token_a()) };
+        let amount_in = 0.01;
+
+        let res = state
+            .get_amount_out(u256_to_biguint(d18(amount_in)), &token_in, &token_out)
+            .unwrap();
+
+        // Constant liquidity reference: the sqrt price moves by the net amount over the liquidity
+        let net = amount_in * (1.0 - fee);
+        let expected = if a_in {
+            let new_price = 1.0 / (1.0 / sqrt_price + net / liquidity);
+            liquidity * (sqrt_price - new_price)
+        } else {
+            let new_price = sqrt_price + net / liquidity;
+            liquidity * (1.0 / sqrt_price - 1.0 / new_price)
+        };
+        assert_relative_eq!(res.amount.to_f64().unwrap() / 1e18, expected, max_relative = 1e-9);
+        assert_eq!(res.crossed_ticks, Some(0));
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<MaverickV2State>()
+            .unwrap();
+        assert_eq!(new_state.active_tick(), 0);
+        let price_moved = new_state
+            .spot_price(&token_a(), &token_b())
+            .unwrap() -
+            state
+                .spot_price(&token_a(), &token_b())
+                .unwrap();
+        assert_eq!(price_moved < 0.0, a_in);
+    }
+
+    #[test]
+    fn test_swap_crosses_ticks() {
+        let (liquidity, sqrt_price) = (1_000.0, 1.0002);
+        let state = pool(liquidity, sqrt_price, 0.0, 0.0);
+
+        let res = state
+            .get_amount_out(u256_to_biguint(d18(1.0)), &token_a(), &token_b())
+            .unwrap();
+
+        // All ticks have the same liquidity, so it's constant along the swap
+        let new_price = 1.0 / (1.0 / sqrt_price + 1.0 / liquidity);
+        assert_relative_eq!(
+            res.amount.to_f64().unwrap() / 1e18,
+            liquidity * (sqrt_price - new_price),
+            max_relative = 1e-9
+        );
+        assert_eq!(res.crossed_ticks, Some(2));
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<MaverickV2State>()
+            .unwrap();
+        assert_eq!(new_state.active_tick(), -2);
+        assert!(new_state
+            .tick(0)
+            .unwrap()
+            .reserve_b
+            .is_zero());
+        assert!(new_state
+            .tick(-1)
+            .unwrap()
+            .reserve_b
+            .is_zero());
+        assert!(res.gas > u256_to_biguint(GAS_MODEL.gas(0, 0)));
+    }
+
+    #[test]
+    fn test_swap_insufficient_liquidity() {
+        let state = pool(1_000.0, 1.0002, 0.0, 0.0);
+        let reserve_b =
+            (-2..=0).fold(U256::ZERO, |total, tick| total + state.tick(tick).unwrap().reserve_b);
+
+        let res = state.get_amount_out(u256_to_biguint(d18(10.0)), &token_a(), &token_b());
+
+        let Err(SimulationError::InvalidInput(_, Some(partial))) = res else {
+            panic!("Expected an insufficient liquidity error, got {res:?}");
+        };
+        assert_eq!(partial.amount, u256_to_biguint(reserve_b));
+        assert_eq!(partial.crossed_ticks, Some(3));
+    }
+
+    #[test]
+    fn test_direction_fees() {
+        let state = pool(1_000.0, 1.0002, 0.001, 0.003);
+        let amount_in = u256_to_biguint(d18(0.01));
+        let amount_out = |state: &MaverickV2State, token_in: &Token, token_out: &Token| {
+            state
+                .get_amount_out(amount_in.clone(), token_in, token_out)
+                .unwrap()
+                .amount
+        };
+
+        assert_relative_eq!(state.fee(), 0.003);
+        assert_eq!(
+            amount_out(&state, &token_a(), &token_b()),
+            amount_out(&pool(1_000.0, 1.0002, 0.001, 0.001), &token_a(), &token_b())
+        );
+        assert_eq!(
+            amount_out(&state, &token_b(), &token_a()),
+            amount_out(&pool(1_000.0, 1.0002, 0.003, 0.003), &token_b(), &token_a())
+        );
+    }
+
+    /// The pool of `pool` with a `Right`, a `Left` and a `Static` bin in tick 0, and a `Both` bin
+    /// in tick 1
+    fn pool_with_bins(twa: Twa) -> MaverickV2State {
+        let mut state = pool(1_000.0, 1.0002, 0.0, 0.0);
+        state.bins = [
+            (1, BinKind::Right, 0, 0.25),
+            (2, BinKind::Left, 0, 0.25),
+            (3, BinKind::Both, 1, 0.5),
+            (4, BinKind::Static, 0, 0.5),
+        ]
+        .into_iter()
+        .map(|(id, kind, tick, balance)| (id, Bin { kind, tick, tick_balance: d18(balance) }))
+        .collect();
+        state.with_twa(twa)
+    }
+
+    #[rstest]
+    #[case::no_time_passed(0, 250_000_000, [0, 0, 1, 0])]
+    #[case::half_lookback(30, 250_000_000, [1, 0, 1, 0])]
+    #[case::full_lookback(60, 250_000_000, [2, 0, 2, 0])]
+    #[case::beyond_lookback(600, 250_000_000, [2, 0, 2, 0])]
+    #[case::down(60, -150_000_000, [0, -2, 1, 0])]
+    fn test_swap_moves_bins(
+        #[case] elapsed: u64,
+        #[case] last_log_price_d8: i64,
+        #[case] exp_ticks: [i32; 4],
+    ) {
+        let twa = Twa { lookback: 60, last_twa_d8: 0, last_log_price_d8, last_timestamp: 1_000 };
+        let state = pool_with_bins(twa).with_timestamp(1_000 + elapsed);
+
+        let res = state
+            .get_amount_out(u256_to_biguint(d18(0.0001)), &token_b(), &token_a())
+            .unwrap();
+
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<MaverickV2State>()
+            .unwrap();
+        assert_eq!([1, 2, 3, 4].map(|id| new_state.bin(id).unwrap().tick), exp_ticks);
+        assert_eq!(new_state.twa().last_timestamp, 1_000 + elapsed);
+        // The swap leaves the price in the active tick
+        assert_eq!(log_price_tick(new_state.twa().last_log_price_d8), new_state.active_tick());
+    }
+
+    #[test]
+    fn test_move_bins_keeps_reserves() {
+        let mut state = pool_with_bins(Twa::default());
+        let totals = |state: &MaverickV2State| {
+            state
+                .ticks
+                .values()
+                .fold((U256::ZERO, U256::ZERO), |(a, b), tick| {
+                    (a + tick.reserve_a, b + tick.reserve_b)
+                })
+        };
+        let before = totals(&state);
+        let moved = state.bin_reserves(3).unwrap();
+
+        state.move_bins(1, 3).unwrap();
+
+        assert_eq!(totals(&state), before);
+        assert_eq!(state.bin(1).unwrap().tick, 0);
+        assert_eq!(state.bin(3).unwrap().tick, 3);
+        // Tick 3 was empty, so the bin owns all of it
+        assert_eq!(state.bin_reserves(3), Some(moved));
+    }
+
+    #[test]
+    fn test_is_tradeable() {
+        let without_ticks = MaverickV2State::new(
+            TICK_SPACING,
+            U256::ZERO,
+            U256::ZERO,
+            0,
+            BTreeMap::new(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+
+        assert!(pool(1_000.0, 1.0002, 0.001, 0.003).is_tradeable());
+        assert!(!pool(0.0, 1.0002, 0.001, 0.003).is_tradeable());
+        assert!(!without_ticks.is_tradeable());
+    }
+
+    #[test]
+    fn test_swap_scales_decimals() {
+        let state = pool(1_000.0, 1.0002, 0.0, 0.0);
+        let token_b_6 = token_at(token_b().address, "B", 6);
+
+        let res_18 = state
+            .get_amount_out(u256_to_biguint(d18(0.01)), &token_a(), &token_b())
+            .unwrap();
+        let res_6 = state
+            .get_amount_out(u256_to_biguint(d18(0.01)), &token_a(), &token_b_6)
+            .unwrap();
+
+        assert_eq!(res_6.amount, res_18.amount / BigUint::from(10u64.pow(12)));
+    }
+
+    #[test]
+    fn test_delta_transition() {
+        let mut state = pool(1_000.0, 1.0002, 0.0, 0.0);
+        let updated_attributes: HashMap<String, Bytes> = [
+            ("active_tick", Bytes::from((-1i32).to_be_bytes().to_vec())),
+            ("ticks/-1/reserve_a", Bytes::from(300u64.to_be_bytes().to_vec())),
+            ("ticks/-1/total_supply", Bytes::from(4u64.to_be_bytes().to_vec())),
+            ("bins/7/kind", Bytes::from(vec![2u8])),
+            ("bins/7/tick", Bytes::from(vec![0xffu8])),
+            ("bins/7/tick_balance", Bytes::from(vec![1u8])),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+
+        state
+            .delta_transition(
+                ProtocolStateDelta {
+                    component_id: "pool".to_string(),
+                    updated_attributes,
+                    deleted_attributes: HashSet::from(["ticks/2/reserve_a".to_string()]),
+                },
+                &HashMap::new(),
+            )
+            .unwrap();
+
+        assert_eq!(state.active_tick(), -1);
+        let tick = state.tick(-1).unwrap();
+        assert_eq!(tick.reserve_a, U256::from(300u64));
+        assert_eq!(
+            state.bin(7),
+            Some(&Bin { kind: BinKind::Left, tick: -1, tick_balance: U256::from(1u64) })
+        );
+        assert_eq!(
+            state.bin_reserves(7),
+            Some((U256::from(75u64), tick.reserve_b / U256::from(4u64)))
+        );
+        // Tick 2 only held token A
+        assert!(state.tick(2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delta_transition_invalid_bin_kind() {
+        let mut state = pool(1_000.0, 1.0002, 0.0, 0.0);
+        let delta = ProtocolStateDelta {
+            component_id: "pool".to_string(),
+            updated_attributes: HashMap::from([(
+                "bins/1/kind".to_string(),
+                Bytes::from(vec![4u8]),
+            )]),
+            deleted_attributes: HashSet::new(),
+        };
+
+        let res = state.delta_transition(delta, &HashMap::new());
+
+        assert!(matches!(res, Err(TransitionError::DecodeError(_))));
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap};
+
+use alloy_primitives::U256;
+use tycho_client::feed::{synchronizer::ComponentWithState, Header};
+use tycho_core::Bytes;
+
+use super::state::{MaverickV2State, Twa};
+use crate::{
+    models::Token,
+    protocol::{errors::InvalidSnapshotError, models::TryFromWithBlock},
+};
+
+impl TryFromWithBlock<ComponentWithState> for MaverickV2State {
+    type Error = InvalidSnapshotError;
+
+    /// Decodes a `ComponentWithState` into a `MaverickV2State`. Errors with a
+    /// `InvalidSnapshotError` if the tick spacing, a fee or the active tick is missing.
+    ///
+    /// The static attributes are `tick_spacing`, the 18 decimals fees `fee_a_in` and `fee_b_in`,
+    /// and optionally the `lookback` of the average price in seconds. The state attributes are
+    /// `active_tick` and the average price, tick and bin attributes listed in
+    /// `MaverickV2State::set_attribute`.
+    async fn try_from_with_block(
+        snapshot: ComponentWithState,
+        _block: Header,
+        _all_tokens: &HashMap<Bytes, Token>,
+    ) -> Result<Self, Self::Error> {
+        let static_attribute = |name: &str| {
+            snapshot
+                .component
+                .static_attributes
+                .get(name)
+                .ok_or_else(|| InvalidSnapshotError::MissingAttribute(name.to_string()))
+        };
+        let tick_spacing = u32::from(static_attribute("tick_spacing")?.clone());
+        let fee_a_in = U256::from_be_slice(static_attribute("fee_a_in")?);
+        let fee_b_in = U256::from_be_slice(static_attribute("fee_b_in")?);
+        let lookback = match snapshot
+            .component
+            .static_attributes
+            .get("lookback")
+        {
+            Some(lookback) => u64::try_from(U256::from_be_slice(lookback)).map_err(|_| {
+                InvalidSnapshotError::ValueError(format!("Invalid lookback {lookback}"))
+            })?,
+            None => 0,
+        };
+        if !snapshot
+            .state
+            .attributes
+            .contains_key("active_tick")
+        {
+            return Err(InvalidSnapshotError::MissingAttribute("active_tick".to_string()));
+        }
+
+        let mut state = MaverickV2State::new(
+            tick_spacing,
+            fee_a_in,
+            fee_b_in,
+            0,
+            BTreeMap::new(),
+            BTreeMap::new(),
+        )
+        .map_err(InvalidSnapshotError::ValueError)?
+        .with_twa(Twa { lookback, ..Twa::default() });
+        for (key, value) in &snapshot.state.attributes {
+            state
+                .set_attribute(key, value)
+                .map_err(InvalidSnapshotError::ValueError)?;
+        }
+        Ok(state.with_tokens(snapshot.component.tokens.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::DateTime;
+    use tycho_core::dto::{Chain, ChangeType, ProtocolComponent, ResponseProtocolState};
+
+    use super::*;
+    use crate::evm::protocol::maverick_v2::state::{Bin, BinKind};
+
+    fn maverick_component() -> ProtocolComponent {
+        let creation_time = DateTime::from_timestamp(1622526000, 0)
+            .unwrap()
+            .naive_utc();
+        let static_attributes = [
+            ("tick_spacing", 10u32.to_be_bytes().to_vec()),
+            (
+                "fee_a_in",
+                1_000_000_000_000_000u64
+                    .to_be_bytes()
+                    .to_vec(),
+            ),
+            (
+                "fee_b_in",
+                2_000_000_000_000_000u64
+                    .to_be_bytes()
+                    .to_vec(),
+            ),
+            ("lookback", 600u64.to_be_bytes().to_vec()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), Bytes::from(value)))
+        .collect();
+
+        ProtocolComponent {
+            id: "State1".to_string(),
+            protocol_system: "maverick_v2".to_string(),
+            protocol_type_name: "maverick_v2_pool".to_string(),
+            chain: Chain::Ethereum,
+            tokens: Vec::new(),
+            contract_ids: Vec::new(),
+            static_attributes,
+            change: ChangeType::Creation,
+            creation_tx: Bytes::from_str("0x0000").unwrap(),
+            created_at: creation_time,
+        }
+    }
+
+    fn header() -> Header {
+        Header {
+            number: 1,
+            hash: Bytes::from(vec![0; 32]),
+            parent_hash: Bytes::from(vec![0; 32]),
+            revert: false,
+        }
+    }
+
+    fn snapshot(attributes: &[(&str, Vec<u8>)]) -> ComponentWithState {
+        ComponentWithState {
+            state: ResponseProtocolState {
+                component_id: "State1".to_owned(),
+                attributes: attributes
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), Bytes::from(value.clone())))
+                    .collect(),
+                balances: HashMap::new(),
+            },
+            component: maverick_component(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_maverick_v2_try_from() {
+        let snapshot = snapshot(&[
+            ("active_tick", (-3i32).to_be_bytes().to_vec()),
+            ("ticks/-3/reserve_a", 100u64.to_be_bytes().to_vec()),
+            ("ticks/-3/reserve_b", 200u64.to_be_bytes().to_vec()),
+            ("ticks/-3/total_supply", 10u64.to_be_bytes().to_vec()),
+            ("bins/1/kind", vec![1]),
+            ("bins/1/tick", vec![0xfd]),
+            ("bins/1/tick_balance", 10u64.to_be_bytes().to_vec()),
+            ("last_twa_d8", (-250_000_000i64).to_be_bytes().to_vec()),
+            ("last_log_price_d8", (-275_000_000i64).to_be_bytes().to_vec()),
+            ("last_timestamp", 1_700_000_000u64.to_be_bytes().to_vec()),
+        ]);
+
+        let state = MaverickV2State::try_from_with_block(snapshot, header(), &HashMap::new())
+            .await
+            .unwrap();
+
+        assert_eq!(state.active_tick(), -3);
+        let tick = state.tick(-3).unwrap();
+        assert_eq!((tick.reserve_a, tick.reserve_b), (U256::from(100u64), U256::from(200u64)));
+        assert_eq!(
+            state.bin(1),
+            Some(&Bin { kind: BinKind::Right, tick: -3, tick_balance: U256::from(10u64) })
+        );
+        assert_eq!(state.bin_reserves(1), Some((U256::from(100u64), U256::from(200u64))));
+        assert_eq!(
+            state.twa(),
+            &Twa {
+                lookback: 600,
+                last_twa_d8: -250_000_000,
+                last_log_price_d8: -275_000_000,
+                last_timestamp: 1_700_000_000,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_maverick_v2_try_from_missing_active_tick() {
+        let snapshot = snapshot(&[("ticks/0/reserve_a", 100u64.to_be_bytes().to_vec())]);
+
+        let result =
+            MaverickV2State::try_from_with_block(snapshot, header(), &HashMap::new()).await;
+
+        assert!(matches!(
+            result.err().unwrap(),
+            InvalidSnapshotError::MissingAttribute(attr) if attr == *"active_tick"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_maverick_v2_try_from_invalid_tick() {
+        let snapshot = snapshot(&[
+            ("active_tick", vec![0]),
+            ("ticks/x/reserve_a", 100u64.to_be_bytes().to_vec()),
+        ]);
+
+        let result =
+            MaverickV2State::try_from_with_block(snapshot, header(), &HashMap::new()).await;
+
+        assert!(matches!(result.err().unwrap(), InvalidSnapshotError::ValueError(_)));
+    }
+}
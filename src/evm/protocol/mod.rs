@@ -1,6 +1,9 @@
 pub mod filters;
+pub mod fixed_point;
+pub mod maverick_v2;
 pub mod safe_math;
 pub mod u256_num;
+pub mod uniswap_math;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 pub mod uniswap_v4;
@@ -5,10 +5,39 @@
 //! Should an operation cause an overflow a result containing TradeSimulationError
 //! will be returned.
 //! Functions for the types I256, U256, U512 are available.
+//!
+//! The `checked_*` variants report failures according to a `MathMode`, so pool implementations
+//! can let a single pathological pool fail without aborting the caller. The `saturating_*`
+//! variants clamp at the bounds of the type instead of failing.
 use alloy_primitives::{I256, U256, U512};
+use serde::{Deserialize, Serialize};
 
 use crate::protocol::errors::SimulationError;
 
+/// How a pool reports arithmetic failures, i.e. overflows and divisions by zero
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MathMode {
+    /// Failures are `SimulationError::FatalError`s
+    #[default]
+    Strict,
+    /// Failures are `SimulationError::RecoverableError`s, so only the affected pool fails
+    Lenient,
+}
+
+impl MathMode {
+    /// Reports an error of a strict computation according to this mode.
+    ///
+    /// In lenient mode fatal errors become recoverable, other errors are returned unchanged.
+    pub fn map_err(self, err: SimulationError) -> SimulationError {
+        match (self, err) {
+            (MathMode::Lenient, SimulationError::FatalError(msg)) => {
+                SimulationError::RecoverableError(msg)
+            }
+            (_, err) => err,
+        }
+    }
+}
+
 pub fn safe_mul_u256(a: U256, b: U256) -> Result<U256, SimulationError> {
     let res = a.checked_mul(b);
     _construc_result_u256(res)
@@ -29,6 +58,52 @@ pub fn safe_sub_u256(a: U256, b: U256) -> Result<U256, SimulationError> {
     _construc_result_u256(res)
 }
 
+/// Computes `base^exp`, e.g. `10^decimals` when scaling token amounts.
+///
+/// # Errors
+/// Returns a `SimulationError::FatalError` if the result doesn't fit into a U256, e.g. for
+/// `10^78` and above.
+pub fn checked_pow(base: U256, exp: u32) -> Result<U256, SimulationError> {
+    let res = base.checked_pow(U256::from(exp));
+    _construc_result_u256(res)
+}
+
+pub fn checked_mul_u256(a: U256, b: U256, mode: MathMode) -> Result<U256, SimulationError> {
+    safe_mul_u256(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn checked_div_u256(a: U256, b: U256, mode: MathMode) -> Result<U256, SimulationError> {
+    safe_div_u256(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn checked_add_u256(a: U256, b: U256, mode: MathMode) -> Result<U256, SimulationError> {
+    safe_add_u256(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn checked_sub_u256(a: U256, b: U256, mode: MathMode) -> Result<U256, SimulationError> {
+    safe_sub_u256(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn saturating_mul_u256(a: U256, b: U256) -> U256 {
+    a.saturating_mul(b)
+}
+
+pub fn saturating_add_u256(a: U256, b: U256) -> U256 {
+    a.saturating_add(b)
+}
+
+pub fn saturating_sub_u256(a: U256, b: U256) -> U256 {
+    a.saturating_sub(b)
+}
+
+/// Narrows a U512 to a U256.
+///
+/// # Errors
+/// Returns the overflow error of `mode` if the value exceeds 256 bits.
+pub fn checked_u512_to_u256(value: U512, mode: MathMode) -> Result<U256, SimulationError> {
+    _construc_result_u256(U256::checked_from(value)).map_err(|e| mode.map_err(e))
+}
+
 pub fn div_mod_u256(a: U256, b: U256) -> Result<(U256, U256), SimulationError> {
     if b.is_zero() {
         return Err(SimulationError::FatalError("Division by zero".to_string()));
@@ -65,6 +140,22 @@ pub fn safe_sub_u512(a: U512, b: U512) -> Result<U512, SimulationError> {
     _construc_result_u512(res)
 }
 
+pub fn checked_mul_u512(a: U512, b: U512, mode: MathMode) -> Result<U512, SimulationError> {
+    safe_mul_u512(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn checked_div_u512(a: U512, b: U512, mode: MathMode) -> Result<U512, SimulationError> {
+    safe_div_u512(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn checked_add_u512(a: U512, b: U512, mode: MathMode) -> Result<U512, SimulationError> {
+    safe_add_u512(a, b).map_err(|e| mode.map_err(e))
+}
+
+pub fn checked_sub_u512(a: U512, b: U512, mode: MathMode) -> Result<U512, SimulationError> {
+    safe_sub_u512(a, b).map_err(|e| mode.map_err(e))
+}
+
 pub fn div_mod_u512(a: U512, b: U512) -> Result<(U512, U512), SimulationError> {
     if b.is_zero() {
         return Err(SimulationError::FatalError("Division by zero".to_string()));
@@ -223,6 +314,81 @@ mod safe_math_tests {
         }
     }
 
+    #[rstest]
+    #[case::zero_exp(u256("10"), 0, Some(u256("1")))]
+    #[case::ten_pow_18(u256("10"), 18, Some(u256("1000000000000000000")))]
+    #[case::largest_power_of_ten(
+        u256("10"),
+        77,
+        Some(u256(
+            "100000000000000000000000000000000000000000000000000000000000000000000000000000"
+        ))
+    )]
+    #[case::overflow_at_ten_pow_78(u256("10"), 78, None)]
+    #[case::largest_power_of_two(u256("2"), 255, Some(U256::from(1u64) << 255))]
+    #[case::overflow_at_two_pow_256(u256("2"), 256, None)]
+    fn test_checked_pow(#[case] base: U256, #[case] exp: u32, #[case] expected: Option<U256>) {
+        let res = checked_pow(base, exp);
+
+        match expected {
+            Some(expected) => assert_eq!(res.unwrap(), expected),
+            None => assert!(matches!(res, Err(SimulationError::FatalError(_)))),
+        }
+    }
+
+    #[rstest]
+    #[case::strict_overflow(MathMode::Strict, U256_MAX, u256("2"), Err("fatal"))]
+    #[case::lenient_overflow(MathMode::Lenient, U256_MAX, u256("2"), Err("recoverable"))]
+    #[case::lenient_ok(MathMode::Lenient, u256("3"), u256("2"), Ok(u256("6")))]
+    fn test_checked_mul_u256(
+        #[case] mode: MathMode,
+        #[case] a: U256,
+        #[case] b: U256,
+        #[case] expected: Result<U256, &str>,
+    ) {
+        let res = checked_mul_u256(a, b, mode);
+
+        match expected {
+            Ok(expected) => assert_eq!(res.unwrap(), expected),
+            Err("fatal") => assert!(matches!(res, Err(SimulationError::FatalError(_)))),
+            Err(_) => assert!(matches!(res, Err(SimulationError::RecoverableError(_)))),
+        }
+    }
+
+    #[rstest]
+    #[case::strict(MathMode::Strict)]
+    #[case::lenient(MathMode::Lenient)]
+    fn test_checked_div_by_zero(#[case] mode: MathMode) {
+        let res = checked_div_u512(u512("1"), u512("0"), mode);
+
+        match mode {
+            MathMode::Strict => assert!(matches!(res, Err(SimulationError::FatalError(_)))),
+            MathMode::Lenient => {
+                assert!(matches!(res, Err(SimulationError::RecoverableError(_))))
+            }
+        }
+    }
+
+    #[test]
+    fn test_saturating_u256() {
+        assert_eq!(saturating_add_u256(U256_MAX, u256("1")), U256_MAX);
+        assert_eq!(saturating_mul_u256(U256_MAX, u256("2")), U256_MAX);
+        assert_eq!(saturating_sub_u256(u256("1"), u256("2")), u256("0"));
+        assert_eq!(saturating_add_u256(u256("1"), u256("2")), u256("3"));
+    }
+
+    #[rstest]
+    #[case::fits(U512::from(U256_MAX), Some(U256_MAX))]
+    #[case::too_large(U512::from(U256_MAX) + U512::from(1u64), None)]
+    fn test_checked_u512_to_u256(#[case] value: U512, #[case] expected: Option<U256>) {
+        let res = checked_u512_to_u256(value, MathMode::Lenient);
+
+        match expected {
+            Some(expected) => assert_eq!(res.unwrap(), expected),
+            None => assert!(matches!(res, Err(SimulationError::RecoverableError(_)))),
+        }
+    }
+
     fn u512(s: &str) -> U512 {
         U512::from_str(s).unwrap()
     }
@@ -1,8 +1,28 @@
 //! Numeric methods for the U256 type
+//!
+//! Conversions between `U256`, `BigUint` and `f64`:
+//!  - `u256_to_biguint` is lossless, `biguint_to_u256_checked` fails beyond 256 bits.
+//!  - `u256_to_f64`, `ratio_to_f64` and `to_f64_with_decimals` round once, to the nearest `f64`.
+//!  - `from_f64_with_decimals` and `scale_between_decimals` round as the caller asks with a
+//!    `Rounding`, e.g. down for amounts received and up for amounts paid.
 use std::{cmp::max, collections::HashMap, panic};
 
 use alloy_primitives::{bytes::Bytes, U256};
 use num_bigint::BigUint;
+use num_traits::{Float, ToPrimitive, Zero};
+
+use crate::protocol::errors::SimulationError;
+
+/// How a conversion rounds a result that isn't exactly representable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards zero, e.g. for amounts a user receives
+    Down,
+    /// Away from zero, e.g. for amounts a user pays
+    Up,
+    /// To the closest value, ties away from zero
+    Nearest,
+}
 
 /// Converts a U256 integer into it's closest floating point representation
 ///
@@ -94,16 +114,146 @@ pub fn u256_to_f64(x: U256) -> f64 {
     res.unwrap_or_else(|_| panic!("Conversion f64 -> U256 panicked for {x}"))
 }
 
+/// Converts the ratio `num / den` into its closest floating point representation
+///
+/// The quotient is computed on integers and rounded once, unlike dividing the converted
+/// numerator by the converted denominator, which rounds three times. This keeps the result exact
+/// to the last bit for ratios of large numbers, e.g. reserves scaled by powers of ten.
+///
+/// Returns infinity if `den` is zero and `num` isn't.
+pub fn ratio_to_f64(num: &BigUint, den: &BigUint) -> f64 {
+    if num.is_zero() {
+        return 0.0;
+    }
+    if den.is_zero() {
+        return f64::INFINITY;
+    }
+    // Scale the numerator so the quotient has at least 64 significant bits, more than the 53 a
+    // f64 holds. A non-zero remainder is kept as a sticky bit so the final rounding is correct.
+    let shift = (den.bits() as i64 - num.bits() as i64 + 64).max(0);
+    let scaled = num << shift;
+    let mut quotient = &scaled / den;
+    if !(scaled % den).is_zero() {
+        quotient |= BigUint::from(1u8);
+    }
+    let mut value = quotient
+        .to_f64()
+        .expect("BigUint converts to f64");
+    // 2^shift overflows a f64 beyond 2^1023, e.g. for tokens with very different decimals, so
+    // large shifts are applied in steps. Each step is exact while the result stays normal.
+    let mut shift = shift;
+    while shift > 0 {
+        let step = shift.min(f64::MAX_EXP as i64 - 1);
+        value /= 2f64.powi(step as i32);
+        shift -= step;
+    }
+    value
+}
+
+/// Converts a U256 integer into a BigUint. Never loses precision.
 pub fn u256_to_biguint(value: U256) -> BigUint {
     let bytes: [u8; 32] = value.to_be_bytes();
     BigUint::from_bytes_be(&bytes)
 }
 
+/// Converts a BigUint into a U256 integer.
+///
+/// # Panics
+///
+/// Panics if `value` exceeds 256 bits, use `biguint_to_u256_checked` for unvalidated input.
 pub fn biguint_to_u256(value: &BigUint) -> U256 {
     let bytes = value.to_bytes_be();
     U256::from_be_slice(&bytes)
 }
 
+/// Converts a BigUint into a U256 integer, failing instead of panicking if it doesn't fit.
+///
+/// # Errors
+///
+/// Returns `SimulationError::InvalidInput` if `value` exceeds 256 bits.
+pub fn biguint_to_u256_checked(value: &BigUint) -> Result<U256, SimulationError> {
+    if value.bits() > 256 {
+        return Err(SimulationError::InvalidInput(format!("{value} exceeds 256 bits"), None));
+    }
+    Ok(biguint_to_u256(value))
+}
+
+/// Converts an amount in the token's smallest unit into token units, e.g. 1_500_000 with 6
+/// decimals into 1.5.
+///
+/// Rounds once to the closest `f64`, so amounts beyond 2^53 units lose their last digits.
+pub fn to_f64_with_decimals(value: &BigUint, decimals: usize) -> f64 {
+    ratio_to_f64(value, &pow10(decimals))
+}
+
+/// Converts an amount in token units into the token's smallest unit, e.g. 1.5 with 6 decimals
+/// into 1_500_000.
+///
+/// The conversion is exact up to the final `rounding`: `value` is taken as the binary number it
+/// holds, so 0.1 with 18 decimals is 100000000000000005.55…, not 10^17.
+///
+/// # Errors
+///
+/// Returns `SimulationError::InvalidInput` if `value` is negative, NaN or infinite.
+pub fn from_f64_with_decimals(
+    value: f64,
+    decimals: usize,
+    rounding: Rounding,
+) -> Result<BigUint, SimulationError> {
+    if !value.is_finite() || value < 0.0 {
+        return Err(SimulationError::InvalidInput(
+            format!("Can't convert {value} into an unsigned amount"),
+            None,
+        ));
+    }
+    // value = mantissa * 2^exponent, exactly
+    let (mantissa, exponent, _) = value.integer_decode();
+    let scaled = BigUint::from(mantissa) * pow10(decimals);
+    if exponent >= 0 {
+        Ok(scaled << exponent as usize)
+    } else {
+        Ok(div_rounding(&scaled, &(BigUint::from(1u8) << (-(exponent as i32)) as usize), rounding))
+    }
+}
+
+/// Converts an amount of a token with `from` decimals into the same amount with `to` decimals,
+/// e.g. 1 USDC (10^6) into 10^18 for an 18 decimals representation.
+///
+/// Adding decimals is exact, removing them rounds as given by `rounding`.
+pub fn scale_between_decimals(
+    value: &BigUint,
+    from: usize,
+    to: usize,
+    rounding: Rounding,
+) -> BigUint {
+    if to >= from {
+        value * pow10(to - from)
+    } else {
+        div_rounding(value, &pow10(from - to), rounding)
+    }
+}
+
+fn pow10(exponent: usize) -> BigUint {
+    BigUint::from(10u8).pow(exponent as u32)
+}
+
+/// Divides `num` by a non-zero `den`, rounding the quotient as given by `rounding`.
+fn div_rounding(num: &BigUint, den: &BigUint, rounding: Rounding) -> BigUint {
+    let quotient = num / den;
+    let remainder = num % den;
+    let round_up = !remainder.is_zero() &&
+        match rounding {
+            Rounding::Down => false,
+            Rounding::Up => true,
+            Rounding::Nearest => remainder * 2u8 >= *den,
+        };
+    if round_up {
+        quotient + 1u8
+    } else {
+        quotient
+    }
+}
+
 pub fn bytes_to_u256(bytes: Bytes) -> U256 {
     // Ensure the input is exactly 32 bytes
     let mut padded_bytes = [0u8; 32];
@@ -147,4 +297,157 @@ mod test {
 
         assert_eq!(res, out);
     }
+
+    #[rstest]
+    #[case::exact(3u64, 4u64, 0.75)]
+    #[case::third(1u64, 3u64, 1.0 / 3.0)]
+    #[case::zero(0u64, 3u64, 0.0)]
+    #[case::division_by_zero(1u64, 0u64, f64::INFINITY)]
+    // 2^53 + 1 can't be represented, the ratio rounds to the nearest even
+    #[case::beyond_53_bits(2u64.pow(53) + 1, 1u64, 2f64.powi(53))]
+    fn test_ratio_to_f64(#[case] num: u64, #[case] den: u64, #[case] exp: f64) {
+        assert_eq!(ratio_to_f64(&BigUint::from(num), &BigUint::from(den)), exp);
+    }
+
+    #[test]
+    fn test_ratio_to_f64_large_operands() {
+        // (10^40 + 1) / 3 * 10^-38, the converted operands alone are rounded
+        let num = BigUint::from(10u8).pow(40) + 1u8;
+        let den = BigUint::from(3u8) * BigUint::from(10u8).pow(38);
+
+        assert_eq!(ratio_to_f64(&num, &den), 100.0 / 3.0);
+    }
+
+    #[test]
+    fn test_ratio_to_f64_large_shift() {
+        // The quotient is scaled by more than 2^1023
+        let den = BigUint::from(10u8).pow(300);
+
+        assert_eq!(ratio_to_f64(&BigUint::from(1u8), &den), 1e-300);
+    }
+
+    fn u256_max() -> BigUint {
+        (BigUint::from(1u8) << 256) - 1u8
+    }
+
+    #[rstest]
+    #[case::zero(BigUint::zero(), Some(U256::ZERO))]
+    #[case::one(BigUint::from(1u8), Some(U256::from(1u8)))]
+    #[case::max(u256_max(), Some(U256::MAX))]
+    #[case::overflow(u256_max() + 1u8, None)]
+    fn test_biguint_to_u256_checked(#[case] value: BigUint, #[case] exp: Option<U256>) {
+        let res = biguint_to_u256_checked(&value);
+
+        match exp {
+            Some(exp) => assert_eq!(res.unwrap(), exp),
+            None => assert!(matches!(res, Err(SimulationError::InvalidInput(..)))),
+        }
+    }
+
+    #[rstest]
+    #[case::zero(U256::ZERO)]
+    #[case::one(U256::from(1u8))]
+    #[case::max(U256::MAX)]
+    fn test_u256_biguint_roundtrip(#[case] value: U256) {
+        assert_eq!(biguint_to_u256(&u256_to_biguint(value)), value);
+    }
+
+    #[rstest]
+    #[case::zero(BigUint::zero(), 18, 0.0)]
+    #[case::one_ether(BigUint::from(10u8).pow(18), 18, 1.0)]
+    #[case::usdc(BigUint::from(1_500_000u32), 6, 1.5)]
+    #[case::no_decimals(BigUint::from(42u8), 0, 42.0)]
+    #[case::fraction(BigUint::from(123_456_789u32), 6, 123.456789)]
+    // 2^53 + 1 units can't be represented, the amount rounds to the nearest even
+    #[case::beyond_53_bits(BigUint::from(2u64.pow(53) + 1), 0, 2f64.powi(53))]
+    #[case::max(u256_max(), 18, 1.157920892373162e59)]
+    fn test_to_f64_with_decimals(
+        #[case] value: BigUint,
+        #[case] decimals: usize,
+        #[case] exp: f64,
+    ) {
+        assert_eq!(to_f64_with_decimals(&value, decimals), exp);
+    }
+
+    #[rstest]
+    #[case::zero(0.0, 18, Rounding::Up, 0u64)]
+    #[case::negative_zero(-0.0, 18, Rounding::Up, 0u64)]
+    #[case::usdc(1.5, 6, Rounding::Down, 1_500_000u64)]
+    #[case::exact_power_of_two(2f64.powi(60), 0, Rounding::Down, 2u64.pow(60))]
+    // 0.1 is 0.1000000000000000055511151231257827 in binary
+    #[case::tenth_down(0.1, 18, Rounding::Down, 100_000_000_000_000_005u64)]
+    #[case::tenth_up(0.1, 18, Rounding::Up, 100_000_000_000_000_006u64)]
+    #[case::tenth_nearest(0.1, 18, Rounding::Nearest, 100_000_000_000_000_006u64)]
+    #[case::dust_down(1e-19, 18, Rounding::Down, 0u64)]
+    #[case::dust_up(1e-19, 18, Rounding::Up, 1u64)]
+    #[case::dust_nearest(1e-19, 18, Rounding::Nearest, 0u64)]
+    #[case::half_nearest(0.5, 0, Rounding::Nearest, 1u64)]
+    fn test_from_f64_with_decimals(
+        #[case] value: f64,
+        #[case] decimals: usize,
+        #[case] rounding: Rounding,
+        #[case] exp: u64,
+    ) {
+        assert_eq!(from_f64_with_decimals(value, decimals, rounding).unwrap(), BigUint::from(exp));
+    }
+
+    #[test]
+    fn test_from_f64_with_decimals_large() {
+        // f64::MAX is an integer of 1024 bits, beyond U256 but not beyond BigUint
+        let res = from_f64_with_decimals(f64::MAX, 0, Rounding::Down).unwrap();
+
+        assert_eq!(res.bits(), 1024);
+        assert_eq!(res.to_f64(), Some(f64::MAX));
+    }
+
+    #[rstest]
+    #[case::negative(-1.0)]
+    #[case::negative_dust(-1e-30)]
+    #[case::nan(f64::NAN)]
+    #[case::infinity(f64::INFINITY)]
+    #[case::negative_infinity(f64::NEG_INFINITY)]
+    fn test_from_f64_with_decimals_rejects(#[case] value: f64) {
+        assert!(matches!(
+            from_f64_with_decimals(value, 18, Rounding::Down),
+            Err(SimulationError::InvalidInput(..))
+        ));
+    }
+
+    #[rstest]
+    #[case::same(1_234u64, 6, 6, Rounding::Up, 1_234u64)]
+    #[case::zero(0u64, 18, 6, Rounding::Up, 0u64)]
+    #[case::add_decimals(1_234_567u64, 6, 18, Rounding::Down, 1_234_567_000_000_000_000u64)]
+    #[case::remove_down(1_234_567_890_123_456_789u64, 18, 6, Rounding::Down, 1_234_567_890_123u64)]
+    #[case::remove_up(1_234_567_890_123_456_789u64, 18, 6, Rounding::Up, 1_234_567_890_124u64)]
+    #[case::remove_nearest(
+        1_234_567_890_123_456_789u64,
+        18,
+        6,
+        Rounding::Nearest,
+        1_234_567_890_123u64
+    )]
+    #[case::remove_exact(5_000u64, 3, 0, Rounding::Up, 5u64)]
+    #[case::tie_nearest(1_500u64, 3, 0, Rounding::Nearest, 2u64)]
+    #[case::below_unit_up(1u64, 18, 6, Rounding::Up, 1u64)]
+    #[case::below_unit_down(1u64, 18, 6, Rounding::Down, 0u64)]
+    fn test_scale_between_decimals(
+        #[case] value: u64,
+        #[case] from: usize,
+        #[case] to: usize,
+        #[case] rounding: Rounding,
+        #[case] exp: u64,
+    ) {
+        assert_eq!(
+            scale_between_decimals(&BigUint::from(value), from, to, rounding),
+            BigUint::from(exp)
+        );
+    }
+
+    #[test]
+    fn test_scale_between_decimals_max() {
+        // No intermediate result is bounded to 256 bits
+        let scaled = scale_between_decimals(&u256_max(), 0, 18, Rounding::Down);
+
+        assert_eq!(scale_between_decimals(&scaled, 18, 0, Rounding::Up), u256_max());
+    }
 }
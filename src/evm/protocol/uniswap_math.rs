@@ -0,0 +1,14 @@
+//! Tick and sqrt price conversions of Uniswap V3 style concentrated liquidity pools
+//!
+//! This is the stable public surface of the math used internally by the Uniswap V3 and V4
+//! states. Prices are sqrt prices in Q64.96 fixed point representation, as stored on-chain.
+//! `TickInfo` and `PriceDepth` are the types the states take and return.
+pub use super::utils::uniswap::{
+    sqrt_price_math::{sqrt_price_q96_to_f64, sqrt_price_q96_to_price},
+    tick_list::TickInfo,
+    tick_math::{
+        get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO,
+        MIN_TICK,
+    },
+    PriceDepth,
+};
@@ -1,6 +1,7 @@
 use alloy_primitives::U256;
+use num_bigint::BigUint;
 
-use crate::evm::protocol::u256_num::u256_to_f64;
+use crate::evm::protocol::u256_num::{ratio_to_f64, u256_to_biguint, u256_to_f64};
 
 /// Computes a spot price given two token reserves
 ///
@@ -18,6 +19,24 @@ pub(super) fn spot_price_from_reserves(
     (u256_to_f64(r1) / u256_to_f64(r0)) * token_correction
 }
 
+/// Computes the price of the base token in the quote token given their reserves
+///
+/// Unlike `spot_price_from_reserves`, the reserves are adjusted for decimals and divided on
+/// integers and the result is rounded once, so the price is exact to the last bit in either
+/// direction, even for large decimal differences.
+pub(super) fn exact_spot_price_from_reserves(
+    reserve_base: U256,
+    reserve_quote: U256,
+    base_decimals: u32,
+    quote_decimals: u32,
+) -> f64 {
+    let ten = BigUint::from(10u8);
+    ratio_to_f64(
+        &(u256_to_biguint(reserve_quote) * ten.pow(base_decimals)),
+        &(u256_to_biguint(reserve_base) * ten.pow(quote_decimals)),
+    )
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -1,46 +1,96 @@
 use std::{any::Any, collections::HashMap};
 
-use alloy_primitives::U256;
-use num_bigint::{BigUint, ToBigUint};
+use alloy_primitives::{U256, U512};
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
 use tycho_core::{dto::ProtocolStateDelta, Bytes};
 
-use super::reserve_price::spot_price_from_reserves;
+use super::reserve_price::{exact_spot_price_from_reserves, spot_price_from_reserves};
 use crate::{
     evm::protocol::{
-        safe_math::{safe_add_u256, safe_div_u256, safe_mul_u256, safe_sub_u256},
-        u256_num::{biguint_to_u256, u256_to_biguint},
+        safe_math::{
+            checked_add_u256, checked_add_u512, checked_div_u512, checked_mul_u512,
+            checked_sub_u256, checked_sub_u512, checked_u512_to_u256, MathMode,
+        },
+        u256_num::{biguint_to_u256, biguint_to_u256_checked, u256_to_biguint},
     },
     models::Token,
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
-        state::ProtocolSim,
+        models::{swap_gas_breakdown, GetAmountOutResult},
+        state::{fingerprint, ProtocolSim},
     },
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Gas used by the pair's own swap logic, on top of the transaction and transfer costs
+const SWAP_GAS: u64 = 39_000;
+
+/// The fee of Uniswap V2 itself, in basis points
+pub const DEFAULT_FEE_BPS: u32 = 30;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UniswapV2State {
     pub reserve0: U256,
     pub reserve1: U256,
+    /// Swap fee in basis points, e.g. 30 for Uniswap V2 or 25 for PancakeSwap V2
+    pub fee_bps: u32,
+    /// How arithmetic failures are reported, see `with_math_mode`
+    #[serde(default)]
+    pub math_mode: MathMode,
+    /// Addresses of the pool's tokens, in the pool's order. Empty unless set with `with_tokens`.
+    #[serde(default)]
+    pub tokens: Vec<Bytes>,
 }
 
 impl UniswapV2State {
-    /// Creates a new instance of `UniswapV2State` with the given reserves.
+    /// Creates a new instance of `UniswapV2State` with the given reserves and Uniswap V2's 0.3%
+    /// fee.
     ///
     /// # Arguments
     ///
     /// * `reserve0` - Reserve of token 0.
     /// * `reserve1` - Reserve of token 1.
     pub fn new(reserve0: U256, reserve1: U256) -> Self {
-        UniswapV2State { reserve0, reserve1 }
+        Self::new_with_fee(reserve0, reserve1, DEFAULT_FEE_BPS)
+    }
+
+    /// Creates a new instance of `UniswapV2State` for a fork charging a different fee.
+    ///
+    /// # Arguments
+    ///
+    /// * `reserve0` - Reserve of token 0.
+    /// * `reserve1` - Reserve of token 1.
+    /// * `fee_bps` - Swap fee in basis points.
+    pub fn new_with_fee(reserve0: U256, reserve1: U256, fee_bps: u32) -> Self {
+        UniswapV2State {
+            reserve0,
+            reserve1,
+            fee_bps,
+            math_mode: MathMode::Strict,
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Sets how arithmetic failures are reported. In `MathMode::Lenient` a swap that would
+    /// overflow fails with a `SimulationError::RecoverableError` instead of a fatal error.
+    pub fn with_math_mode(mut self, mode: MathMode) -> Self {
+        self.math_mode = mode;
+        self
+    }
+
+    /// Sets the addresses of the pool's tokens reported by `tokens`, in the pool's order.
+    pub fn with_tokens(mut self, tokens: Vec<Bytes>) -> Self {
+        self.tokens = tokens;
+        self
     }
 }
 
 impl ProtocolSim for UniswapV2State {
     fn fee(&self) -> f64 {
-        0.003
+        self.fee_bps as f64 / 10_000.0
     }
 
+    /// The marginal price implied by the reserves, before fees.
     fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
         if base < quote {
             Ok(spot_price_from_reserves(
@@ -59,13 +109,28 @@ impl ProtocolSim for UniswapV2State {
         }
     }
 
+    /// Computed from the reserves directly rather than as `1 / spot_price`.
+    fn spot_price_inverse(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        let (reserve_base, reserve_quote) = if base < quote {
+            (self.reserve0, self.reserve1)
+        } else {
+            (self.reserve1, self.reserve0)
+        };
+        Ok(exact_spot_price_from_reserves(
+            reserve_quote,
+            reserve_base,
+            quote.decimals as u32,
+            base.decimals as u32,
+        ))
+    }
+
     fn get_amount_out(
         &self,
         amount_in: BigUint,
         token_in: &Token,
         token_out: &Token,
     ) -> Result<GetAmountOutResult, SimulationError> {
-        let amount_in = biguint_to_u256(&amount_in);
+        let amount_in = biguint_to_u256_checked(&amount_in)?;
         if amount_in == U256::from(0u64) {
             return Err(SimulationError::InvalidInput("Amount in cannot be zero".to_string(), None));
         }
@@ -77,25 +142,45 @@ impl ProtocolSim for UniswapV2State {
             return Err(SimulationError::RecoverableError("No liquidity".to_string()));
         }
 
-        let amount_in_with_fee = safe_mul_u256(amount_in, U256::from(997))?;
-        let numerator = safe_mul_u256(amount_in_with_fee, reserve_buy)?;
-        let denominator =
-            safe_add_u256(safe_mul_u256(reserve_sell, U256::from(1000))?, amount_in_with_fee)?;
-
-        let amount_out = safe_div_u256(numerator, denominator)?;
+        // Fee-on-transfer tokens: only the taxed amount reaches the pool
+        let amount_in = match &token_in.tax {
+            Some(tax) => tax.apply_sell(amount_in)?,
+            None => amount_in,
+        };
+        // Computed on 512 bits, so the products can't overflow for any reserves
+        let mode = self.math_mode;
+        let fee_precision = U512::from(10_000u64);
+        let amount_in_with_fee = checked_mul_u512(
+            U512::from(amount_in),
+            checked_sub_u512(fee_precision, U512::from(self.fee_bps), mode)?,
+            mode,
+        )?;
+        let numerator = checked_mul_u512(amount_in_with_fee, U512::from(reserve_buy), mode)?;
+        let denominator = checked_add_u512(
+            checked_mul_u512(U512::from(reserve_sell), fee_precision, mode)?,
+            amount_in_with_fee,
+            mode,
+        )?;
+
+        // Always below reserve_buy, so it fits into 256 bits
+        let amount_out =
+            checked_u512_to_u256(checked_div_u512(numerator, denominator, mode)?, mode)?;
         let mut new_state = self.clone();
         if zero2one {
-            new_state.reserve0 = safe_add_u256(self.reserve0, amount_in)?;
-            new_state.reserve1 = safe_sub_u256(self.reserve1, amount_out)?;
+            new_state.reserve0 = checked_add_u256(self.reserve0, amount_in, mode)?;
+            new_state.reserve1 = checked_sub_u256(self.reserve1, amount_out, mode)?;
         } else {
-            new_state.reserve0 = safe_sub_u256(self.reserve0, amount_out)?;
-            new_state.reserve1 = safe_add_u256(self.reserve1, amount_in)?;
+            new_state.reserve0 = checked_sub_u256(self.reserve0, amount_out, mode)?;
+            new_state.reserve1 = checked_add_u256(self.reserve1, amount_in, mode)?;
         };
-        Ok(GetAmountOutResult::new(
-            u256_to_biguint(amount_out),
-            120_000
-                .to_biguint()
-                .expect("Expected an unsigned integer as gas value"),
+        // The pool sends out the full amount, the recipient receives it net of tax
+        let amount_received = match &token_out.tax {
+            Some(tax) => tax.apply_buy(amount_out)?,
+            None => amount_out,
+        };
+        Ok(GetAmountOutResult::with_gas_breakdown(
+            u256_to_biguint(amount_received),
+            swap_gas_breakdown(SWAP_GAS),
             Box::new(new_state),
         ))
     }
@@ -122,6 +207,19 @@ impl ProtocolSim for UniswapV2State {
         Ok(())
     }
 
+    /// Both reserves are nonzero and the fee is below 100%.
+    fn is_tradeable(&self) -> bool {
+        !self.reserve0.is_zero() && !self.reserve1.is_zero() && self.fee_bps < 10_000
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.tokens.clone()
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        fingerprint(self)
+    }
+
     fn clone_box(&self) -> Box<dyn ProtocolSim> {
         Box::new(self.clone())
     }
@@ -138,7 +236,10 @@ impl ProtocolSim for UniswapV2State {
             .as_any()
             .downcast_ref::<UniswapV2State>()
         {
-            self.reserve0 == other_state.reserve0 && self.reserve1 == other_state.reserve1
+            self.reserve0 == other_state.reserve0 &&
+                self.reserve1 == other_state.reserve1 &&
+                self.fee_bps == other_state.fee_bps &&
+                self.math_mode == other_state.math_mode
         } else {
             false
         }
@@ -152,12 +253,19 @@ mod tests {
         str::FromStr,
     };
 
-    use approx::assert_ulps_eq;
+    use approx::{assert_relative_eq, assert_ulps_eq};
+    use num_bigint::ToBigUint;
     use num_traits::One;
     use rstest::rstest;
     use tycho_core::hex_bytes::Bytes;
 
     use super::*;
+    use crate::{
+        evm::protocol::u256_num::u256_to_f64,
+        models::TransferTax,
+        protocol::models::{GasItem, GasSource, BASE_TX_GAS},
+        testing::token,
+    };
 
     #[rstest]
     #[case::same_dec(
@@ -215,6 +323,136 @@ mod tests {
         assert_eq!(state.reserve1, r1);
     }
 
+    #[rstest]
+    #[case::within_limit(120_000, true)]
+    #[case::exceeds_limit(119_999, false)]
+    fn test_get_amount_out_with_gas_limit(#[case] gas_limit: u64, #[case] ok: bool) {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(1_000_000u64));
+
+        let res = state.get_amount_out_with_gas_limit(BigUint::from(1_000u64), &t0, &t1, gas_limit);
+
+        match res {
+            Ok(res) => {
+                assert!(ok);
+                assert_eq!(res.gas, BigUint::from(120_000u64));
+            }
+            Err(SimulationError::InvalidInput(_, Some(res))) => {
+                assert!(!ok);
+                assert_eq!(res.gas, BigUint::from(120_000u64));
+            }
+            Err(e) => panic!("Unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_aggregate_gas_breakdown() {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let state = UniswapV2State::new(U256::from(1_000_000u64), U256::from(1_000_000u64));
+
+        let mut res = state
+            .get_amount_out(BigUint::from(1_000u64), &t0, &t1)
+            .unwrap();
+        assert_eq!(res.gas, BigUint::from(120_000u64));
+        let second = res
+            .new_state
+            .get_amount_out(res.amount.clone(), &t1, &t0)
+            .unwrap();
+        res.aggregate(&second);
+
+        assert_eq!(res.amount, second.amount);
+        // The base transaction cost is only paid once
+        assert_eq!(
+            res.gas_breakdown,
+            vec![
+                GasItem::new(GasSource::BaseTx, BASE_TX_GAS),
+                GasItem::new(GasSource::TokenTransfer, 120_000u64),
+                GasItem::new(GasSource::PoolComputation, 78_000u64),
+            ]
+        );
+        assert_eq!(res.gas, BigUint::from(219_000u64));
+    }
+
+    #[test]
+    fn test_get_amount_out_transfer_tax() {
+        let reserve = U256::from_str("1000000000000000000000000").unwrap();
+        let amount_in = BigUint::from_str("1000000000000000000000").unwrap();
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let taxed_t0 = t0
+            .clone()
+            .with_transfer_tax(TransferTax::new(0, 1_000));
+        let taxed_t1 = t1
+            .clone()
+            .with_transfer_tax(TransferTax::new(500, 0));
+        let state = UniswapV2State::new(reserve, reserve);
+
+        let untaxed = state
+            .get_amount_out(amount_in.clone(), &t0, &t1)
+            .unwrap();
+        let taxed = state
+            .get_amount_out(amount_in, &taxed_t0, &taxed_t1)
+            .unwrap();
+
+        assert_eq!(untaxed.amount, BigUint::from_str("996006981039903216493").unwrap());
+        // 10% of the input is lost on the way in, 5% of the output on the way out
+        assert_eq!(taxed.amount, BigUint::from_str("851670795794933206432").unwrap());
+        let new_state = taxed
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+        assert_eq!(new_state.reserve0, reserve + U256::from_str("900000000000000000000").unwrap());
+        assert_eq!(new_state.reserve1, reserve - U256::from_str("896495574520982322561").unwrap());
+    }
+
+    #[test]
+    fn test_tokens() {
+        let state = UniswapV2State::new(U256::from(1_000u64), U256::from(2_000u64));
+        // A pool created by hand doesn't know its tokens until they're given
+        assert!(state.tokens().is_empty());
+
+        let tokens = vec![token("A").address, token("B").address];
+        assert_eq!(
+            state
+                .with_tokens(tokens.clone())
+                .tokens(),
+            tokens
+        );
+    }
+
     #[test]
     fn test_get_amount_out_overflow() {
         let r0 = U256::from_str("33372357002392258830279").unwrap();
@@ -242,6 +480,74 @@ mod tests {
         assert!(matches!(err, SimulationError::FatalError(_)));
     }
 
+    #[test]
+    fn test_get_amount_out_beyond_u256() {
+        let state = UniswapV2State::new(U256::from(1_000u64), U256::from(1_000u64));
+        let amount_in = BigUint::one() << 256;
+
+        let res = state.get_amount_out(amount_in, &token("A"), &token("B"));
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(..))));
+    }
+
+    #[test]
+    fn test_get_amount_out_near_max_reserves() {
+        let reserve = U256::MAX / U256::from(2u64);
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let state = UniswapV2State::new(reserve, reserve);
+
+        let res = state
+            .get_amount_out(BigUint::from(10u64).pow(18), &t0, &t1)
+            .unwrap();
+
+        // 0.3% fee, the price impact is negligible against these reserves
+        assert_eq!(res.amount, BigUint::from_str("996999999999999999").unwrap());
+    }
+
+    #[rstest]
+    #[case::strict(MathMode::Strict)]
+    #[case::lenient(MathMode::Lenient)]
+    fn test_get_amount_out_reserve_overflow(#[case] mode: MathMode) {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let state = UniswapV2State::new(
+            U256::MAX - U256::from(10u64),
+            U256::from(10u64).pow(U256::from(30u64)),
+        )
+        .with_math_mode(mode);
+
+        let res = state.get_amount_out(BigUint::from(10u64).pow(18), &t0, &t1);
+
+        // The amount out is computable, but the new reserve doesn't fit into 256 bits
+        match mode {
+            MathMode::Strict => assert!(matches!(res, Err(SimulationError::FatalError(_)))),
+            MathMode::Lenient => {
+                assert!(matches!(res, Err(SimulationError::RecoverableError(_))))
+            }
+        }
+    }
+
     #[rstest]
     #[case(true, 0.0008209719947624441f64)]
     #[case(false, 1218.0683462769755f64)]
@@ -272,6 +578,238 @@ mod tests {
         assert_ulps_eq!(res, exp);
     }
 
+    #[rstest]
+    #[case::usdc_weth(true)]
+    #[case::weth_usdc(false)]
+    fn test_raw_spot_price(#[case] zero_to_one: bool) {
+        let r0 = U256::from_str("36925554990922").unwrap();
+        let r1 = U256::from_str("30314846538607556521556").unwrap();
+        let state = UniswapV2State::new(r0, r1);
+        let usdc = Token::new(
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            6,
+            "USDC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+
+        let (res, exp) = if zero_to_one {
+            (
+                state
+                    .raw_spot_price(&usdc, &weth)
+                    .unwrap(),
+                u256_to_f64(r1) / u256_to_f64(r0),
+            )
+        } else {
+            (
+                state
+                    .raw_spot_price(&weth, &usdc)
+                    .unwrap(),
+                u256_to_f64(r0) / u256_to_f64(r1),
+            )
+        };
+
+        // The raw price is the plain reserve ratio, 10^12 away from the decimal-adjusted one
+        assert_ulps_eq!(res, exp);
+    }
+
+    #[rstest]
+    // Reference prices are the exact reserve ratios, rounded once
+    #[case::usdc_weth(
+        U256::from_str("36925554990922").unwrap(),
+        U256::from_str("30314846538607556521556").unwrap(),
+        ("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48", 6),
+        ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", 18),
+        1218.0683462769755f64,
+        0.0008209719947624441f64
+    )]
+    #[case::wbtc_weth(
+        U256::from_str("12345678901").unwrap(),
+        U256::from_str("2345678901234567890123").unwrap(),
+        ("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599", 8),
+        ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", 18),
+        // 1 / spot_price rounds to 0.052631580965759095
+        0.0526315809657591f64,
+        18.999999271360995f64
+    )]
+    fn test_spot_price_inverse(
+        #[case] r0: U256,
+        #[case] r1: U256,
+        #[case] token_0: (&str, usize),
+        #[case] token_1: (&str, usize),
+        #[case] exp_1_in_0: f64,
+        #[case] exp_0_in_1: f64,
+    ) {
+        let state = UniswapV2State::new(r0, r1);
+        let t0 = Token::new(token_0.0, token_0.1, "T0", 10_000.to_biguint().unwrap());
+        let t1 = Token::new(token_1.0, token_1.1, "T1", 10_000.to_biguint().unwrap());
+
+        assert_eq!(
+            state
+                .spot_price_inverse(&t0, &t1)
+                .unwrap(),
+            exp_1_in_0
+        );
+        assert_eq!(
+            state
+                .spot_price_inverse(&t1, &t0)
+                .unwrap(),
+            exp_0_in_1
+        );
+        assert_ulps_eq!(
+            state
+                .spot_price_inverse(&t0, &t1)
+                .unwrap(),
+            1.0 / state.spot_price(&t0, &t1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fee_adjusted_price() {
+        let state = UniswapV2State::new(
+            U256::from_str("36925554990922").unwrap(),
+            U256::from_str("30314846538607556521556").unwrap(),
+        );
+        let usdc = Token::new(
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            6,
+            "USDC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+
+        let res = state
+            .fee_adjusted_price(&weth, &usdc)
+            .unwrap();
+
+        assert_ulps_eq!(res, 1218.0683462769755 * 0.997);
+        // Selling a small amount realizes the fee adjusted price
+        let effective = state
+            .effective_price(BigUint::from(10u64).pow(16), &weth, &usdc)
+            .unwrap();
+        assert_relative_eq!(effective, res, max_relative = 1e-6);
+    }
+
+    #[rstest]
+    #[case::no_slippage(0, 1214374202u64)]
+    // 1214374202 * 0.995 = 1208302330.99, rounded down
+    #[case::half_percent(50, 1208302330u64)]
+    #[case::full(10_000, 0u64)]
+    fn test_min_amount_out(#[case] slippage_bps: u16, #[case] exp: u64) {
+        let state = UniswapV2State::new(
+            U256::from_str("36925554990922").unwrap(),
+            U256::from_str("30314846538607556521556").unwrap(),
+        );
+        let usdc = Token::new(
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            6,
+            "USDC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+        let amount_in = BigUint::from(10u64).pow(18);
+
+        let res = state
+            .min_amount_out(amount_in.clone(), &weth, &usdc, slippage_bps)
+            .unwrap();
+
+        assert_eq!(res, BigUint::from(exp));
+        assert!(matches!(
+            state.min_amount_out(amount_in, &weth, &usdc, 10_001),
+            Err(SimulationError::InvalidInput(..))
+        ));
+    }
+
+    #[test]
+    fn test_spot_price_in() {
+        let state = UniswapV2State::new(
+            U256::from_str("36925554990922").unwrap(),
+            U256::from_str("30314846538607556521556").unwrap(),
+        );
+        let usdc = Token::new(
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            6,
+            "USDC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+
+        // USDC at 2 USD
+        let weth_usd = state
+            .spot_price_in(&weth, &usdc, 2.0)
+            .unwrap();
+        assert_ulps_eq!(weth_usd, 2.0 * state.spot_price(&weth, &usdc).unwrap());
+        // Going back through the pool with WETH as numeraire recovers the USDC price
+        let usdc_usd = state
+            .spot_price_in(&usdc, &weth, weth_usd)
+            .unwrap();
+        assert_relative_eq!(usdc_usd, 2.0, max_relative = 1e-9);
+    }
+
+    #[rstest]
+    #[case::cake_to_wbnb(
+        true,
+        BigUint::from_str("1000000000000000000000").unwrap(),
+        BigUint::from_str("3583644243207332273").unwrap()
+    )]
+    #[case::wbnb_to_cake(
+        false,
+        BigUint::from_str("5000000000000000000").unwrap(),
+        BigUint::from_str("1386855316922121608694").unwrap()
+    )]
+    fn test_get_amount_out_pancake_fee(
+        #[case] zero_to_one: bool,
+        #[case] amount_in: BigUint,
+        #[case] exp: BigUint,
+    ) {
+        // Expected amounts follow PancakeRouter's getAmountOut, which charges 25bps
+        let cake = Token::new(
+            "0x0E09FaBB73Bd3Ade0a17ECC321fD13a19e81cE82",
+            18,
+            "CAKE",
+            10_000.to_biguint().unwrap(),
+        );
+        let wbnb = Token::new(
+            "0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c",
+            18,
+            "WBNB",
+            10_000.to_biguint().unwrap(),
+        );
+        let state = UniswapV2State::new_with_fee(
+            U256::from_str("2354876263110912573443512").unwrap(),
+            U256::from_str("8463772881098362184004").unwrap(),
+            25,
+        );
+        let (token_in, token_out) = if zero_to_one { (&cake, &wbnb) } else { (&wbnb, &cake) };
+
+        let res = state
+            .get_amount_out(amount_in, token_in, token_out)
+            .unwrap();
+
+        assert_eq!(res.amount, exp);
+        assert_ulps_eq!(state.fee(), 0.0025);
+    }
+
     #[test]
     fn test_fee() {
         let state = UniswapV2State::new(
@@ -284,6 +822,23 @@ mod tests {
         assert_ulps_eq!(res, 0.003);
     }
 
+    #[rstest]
+    #[case::tradeable(1_000, 2_000, 30, true)]
+    #[case::zero_reserve0(0, 2_000, 30, false)]
+    #[case::zero_reserve1(1_000, 0, 30, false)]
+    #[case::full_fee(1_000, 2_000, 10_000, false)]
+    fn test_is_tradeable(
+        #[case] reserve0: u64,
+        #[case] reserve1: u64,
+        #[case] fee_bps: u32,
+        #[case] exp: bool,
+    ) {
+        let state =
+            UniswapV2State::new_with_fee(U256::from(reserve0), U256::from(reserve1), fee_bps);
+
+        assert_eq!(state.is_tradeable(), exp);
+    }
+
     #[test]
     fn test_delta_transition() {
         let mut state =
@@ -332,4 +887,36 @@ mod tests {
             _ => panic!("Test failed: was expecting an Err value"),
         };
     }
+
+    #[rstest]
+    #[case::same_reserves(1000, 1000, true)]
+    #[case::new_reserves(1500, 2000, false)]
+    #[case::new_reserve1(1000, 1001, false)]
+    fn test_fingerprint_after_delta(
+        #[case] reserve0: u64,
+        #[case] reserve1: u64,
+        #[case] unchanged: bool,
+    ) {
+        let state = UniswapV2State::new(U256::from(1000), U256::from(1000));
+        let mut transitioned = state.clone();
+        let delta = ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: HashMap::from([
+                ("reserve0".to_string(), Bytes::from(reserve0.to_be_bytes().to_vec())),
+                ("reserve1".to_string(), Bytes::from(reserve1.to_be_bytes().to_vec())),
+            ]),
+            deleted_attributes: HashSet::new(),
+        };
+
+        transitioned
+            .delta_transition(delta, &HashMap::new())
+            .unwrap();
+
+        assert_eq!(transitioned.state_fingerprint() == state.state_fingerprint(), unchanged);
+        assert_ne!(
+            UniswapV2State::new_with_fee(U256::from(1000), U256::from(1000), 25)
+                .state_fingerprint(),
+            state.state_fingerprint()
+        );
+    }
 }
@@ -4,7 +4,7 @@ use alloy_primitives::U256;
 use tycho_client::feed::{synchronizer::ComponentWithState, Header};
 use tycho_core::Bytes;
 
-use super::state::UniswapV2State;
+use super::state::{UniswapV2State, DEFAULT_FEE_BPS};
 use crate::{
     models::Token,
     protocol::{errors::InvalidSnapshotError, models::TryFromWithBlock},
@@ -15,6 +15,9 @@ impl TryFromWithBlock<ComponentWithState> for UniswapV2State {
 
     /// Decodes a `ComponentWithState` into a `UniswapV2State`. Errors with a `InvalidSnapshotError`
     /// if either reserve0 or reserve1 attributes are missing.
+    ///
+    /// The fee is read from the `fee` static attribute (in basis points) if present, otherwise it
+    /// is the known fee of the component's protocol system.
     async fn try_from_with_block(
         snapshot: ComponentWithState,
         _block: Header,
@@ -36,7 +39,30 @@ impl TryFromWithBlock<ComponentWithState> for UniswapV2State {
                 .ok_or(InvalidSnapshotError::MissingAttribute("reserve1".to_string()))?,
         );
 
-        Ok(UniswapV2State::new(reserve0, reserve1))
+        let fee_bps = match snapshot
+            .component
+            .static_attributes
+            .get("fee")
+        {
+            Some(fee) => u32::from(fee.clone()),
+            None => default_fee_bps(&snapshot.component.protocol_system),
+        };
+        if fee_bps >= 10_000 {
+            return Err(InvalidSnapshotError::ValueError(format!(
+                "Fee of {fee_bps} bps is not below 100%"
+            )));
+        }
+
+        Ok(UniswapV2State::new_with_fee(reserve0, reserve1, fee_bps)
+            .with_tokens(snapshot.component.tokens.clone()))
+    }
+}
+
+/// Fee of Uniswap V2 forks that don't report it as a static attribute
+fn default_fee_bps(protocol_system: &str) -> u32 {
+    match protocol_system {
+        "pancakeswap_v2" => 25,
+        _ => DEFAULT_FEE_BPS,
     }
 }
 
@@ -45,6 +71,7 @@ mod tests {
     use std::{collections::HashMap, str::FromStr};
 
     use chrono::DateTime;
+    use rstest::rstest;
     use tycho_core::{
         dto::{Chain, ChangeType, ProtocolComponent, ResponseProtocolState},
         hex_bytes::Bytes,
@@ -88,13 +115,19 @@ mod tests {
         ]
         .into_iter()
         .collect();
+        let tokens = vec![
+            Bytes::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap(),
+            Bytes::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2").unwrap(),
+        ];
+        let mut component = usv2_component();
+        component.tokens = tokens.clone();
         let snapshot = ComponentWithState {
             state: ResponseProtocolState {
                 component_id: "State1".to_owned(),
                 attributes,
                 balances: HashMap::new(),
             },
-            component: usv2_component(),
+            component,
         };
 
         let result = UniswapV2State::try_from_with_block(snapshot, header(), &HashMap::new()).await;
@@ -103,6 +136,47 @@ mod tests {
         let res = result.unwrap();
         assert_eq!(res.reserve0, U256::from_str("100").unwrap());
         assert_eq!(res.reserve1, U256::from_str("200").unwrap());
+        assert_eq!(res.fee_bps, 30);
+        assert_eq!(res.tokens, tokens);
+    }
+
+    #[tokio::test]
+    #[rstest]
+    #[case::static_attribute("uniswap_v2", Some(20), 20)]
+    #[case::pancakeswap_default("pancakeswap_v2", None, 25)]
+    #[case::sushiswap_default("sushiswap_v2", None, 30)]
+    async fn test_usv2_try_from_fee(
+        #[case] protocol_system: &str,
+        #[case] fee: Option<u32>,
+        #[case] exp: u32,
+    ) {
+        let attributes: HashMap<String, Bytes> = vec![
+            ("reserve0".to_string(), Bytes::from(100_u64.to_be_bytes().to_vec())),
+            ("reserve1".to_string(), Bytes::from(200_u64.to_be_bytes().to_vec())),
+        ]
+        .into_iter()
+        .collect();
+        let mut component = usv2_component();
+        component.protocol_system = protocol_system.to_string();
+        if let Some(fee) = fee {
+            component
+                .static_attributes
+                .insert("fee".to_string(), Bytes::from(fee.to_be_bytes().to_vec()));
+        }
+        let snapshot = ComponentWithState {
+            state: ResponseProtocolState {
+                component_id: "State1".to_owned(),
+                attributes,
+                balances: HashMap::new(),
+            },
+            component,
+        };
+
+        let result = UniswapV2State::try_from_with_block(snapshot, header(), &HashMap::new())
+            .await
+            .unwrap();
+
+        assert_eq!(result.fee_bps, exp);
     }
 
     #[tokio::test]
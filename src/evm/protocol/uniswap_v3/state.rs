@@ -1,42 +1,88 @@
 use std::{any::Any, collections::HashMap};
 
-use alloy_primitives::{Sign, I256, U256};
+use alloy_primitives::{I256, U256};
 use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
 use tracing::trace;
 use tycho_core::{dto::ProtocolStateDelta, Bytes};
 
 use super::enums::FeeAmount;
 use crate::{
     evm::protocol::{
-        safe_math::{safe_add_u256, safe_sub_u256},
-        u256_num::u256_to_biguint,
+        safe_math::{safe_add_u256, MathMode},
+        u256_num::{biguint_to_u256_checked, u256_to_biguint},
         utils::uniswap::{
-            i24_be_bytes_to_i32, liquidity_math,
-            sqrt_price_math::sqrt_price_q96_to_f64,
+            depth_to_sqrt_price, i24_be_bytes_to_i32, liquidity_math, resolve_sqrt_price_limit,
+            sqrt_price_math::{
+                price_to_sqrt_price_q96, sqrt_price_q96_to_f64, sqrt_price_q96_to_price,
+            },
             swap_math,
             tick_list::{TickInfo, TickList, TickListErrorKind},
-            tick_math::{
-                get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_SQRT_RATIO, MAX_TICK,
-                MIN_SQRT_RATIO, MIN_TICK,
-            },
-            StepComputation, SwapResults, SwapState,
+            tick_math::{get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_TICK, MIN_TICK},
+            to_i256, PriceDepth, StepComputation, SwapResults, SwapState, TickGasModel,
+            COLD_SLOAD_GAS, COLD_SSTORE_GAS, SWAP_STEP_GAS,
         },
     },
     models::Token,
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
-        state::ProtocolSim,
+        models::{swap_gas_breakdown, CurvePoint, GetAmountOutResult},
+        state::{fingerprint, ProtocolSim},
     },
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Gas of the pool's swap logic, see `TickGasModel`
+const GAS_MODEL: TickGasModel = TickGasModel {
+    base: 49_000,
+    // The step to the tick, a cold read of the tick's liquidity and writes of its two fee growth
+    // outside slots and of the slot packing its oracle accumulators
+    per_crossed_tick: SWAP_STEP_GAS + COLD_SLOAD_GAS + 3 * COLD_SSTORE_GAS,
+    // A cold read of the bitmap word
+    per_word: COLD_SLOAD_GAS,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UniswapV3State {
     liquidity: u128,
     sqrt_price: U256,
     fee: FeeAmount,
     tick: i32,
     ticks: TickList,
+    #[serde(default)]
+    math_mode: MathMode,
+    /// Addresses of the pool's tokens, in the pool's order. Empty unless set with `with_tokens`.
+    #[serde(default)]
+    tokens: Vec<Bytes>,
+}
+
+/// The progress of a swap after a step, see `UniswapV3State::swap_from`
+#[derive(Clone, Debug)]
+struct SwapCheckpoint {
+    /// Part of the specified amount used up to this point
+    amount_used: I256,
+    amount_calculated: I256,
+    sqrt_price: U256,
+    tick: i32,
+    liquidity: u128,
+    crossed_ticks: u32,
+    loaded_words: u32,
+    new_word: bool,
+}
+
+impl SwapCheckpoint {
+    /// The start of a swap on `pool`
+    fn start(pool: &UniswapV3State) -> Self {
+        SwapCheckpoint {
+            amount_used: I256::from_raw(U256::from(0u64)),
+            amount_calculated: I256::from_raw(U256::from(0u64)),
+            sqrt_price: pool.sqrt_price,
+            tick: pool.tick,
+            liquidity: pool.liquidity,
+            crossed_ticks: 0,
+            loaded_words: 0,
+            new_word: true,
+        }
+    }
 }
 
 impl UniswapV3State {
@@ -57,7 +103,192 @@ impl UniswapV3State {
     ) -> Self {
         let spacing = UniswapV3State::get_spacing(fee);
         let tick_list = TickList::from(spacing, ticks);
-        UniswapV3State { liquidity, sqrt_price, fee, tick, ticks: tick_list }
+        UniswapV3State {
+            liquidity,
+            sqrt_price,
+            fee,
+            tick,
+            ticks: tick_list,
+            math_mode: MathMode::Strict,
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Sets how arithmetic failures are reported. In `MathMode::Lenient` a swap that would
+    /// overflow fails with a `SimulationError::RecoverableError` instead of a fatal error.
+    pub fn with_math_mode(mut self, mode: MathMode) -> Self {
+        self.math_mode = mode;
+        self
+    }
+
+    /// Sets the addresses of the pool's tokens reported by `tokens`, in the pool's order.
+    pub fn with_tokens(mut self, tokens: Vec<Bytes>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Returns the initialized ticks of the pool together with their net liquidity, ordered by
+    /// tick index.
+    ///
+    /// Crossing a tick from left to right adds its net liquidity to the active liquidity, so the
+    /// sum of all net liquidities at or below `active_tick` equals `liquidity`.
+    pub fn liquidity_depth(&self) -> Vec<(i32, i128)> {
+        self.ticks
+            .ticks()
+            .iter()
+            .map(|tick| (tick.index, tick.net_liquidity))
+            .collect()
+    }
+
+    /// Returns the current tick of the pool.
+    pub fn active_tick(&self) -> i32 {
+        self.tick
+    }
+
+    /// Returns the liquidity currently in range.
+    pub fn liquidity(&self) -> u128 {
+        self.liquidity
+    }
+
+    /// Swaps `amount_in` like `get_amount_out`, but stops once the pool's sqrt price reaches
+    /// `sqrt_price_limit`, like the `sqrtPriceLimitX96` argument of the pool's `swap`.
+    ///
+    /// Returns the result together with the part of `amount_in` the swap consumed, which is less
+    /// than `amount_in` if the limit was reached first. Errors with
+    /// `SimulationError::InvalidInput` if the limit isn't beyond the current price in the swap
+    /// direction.
+    pub fn get_amount_out_with_price_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_price_limit: U256,
+    ) -> Result<(GetAmountOutResult, BigUint), SimulationError> {
+        let (result, amount_remaining) =
+            self.swap_exact_in(&amount_in, token_in, token_out, Some(sqrt_price_limit))?;
+        Ok((result, amount_in - u256_to_biguint(amount_remaining)))
+    }
+
+    /// Returns the amounts a swap of `token_in` for `token_out` takes until the price of
+    /// `token_in` in `token_out`, as returned by `spot_price`, falls to `price_limit`.
+    ///
+    /// E.g. `price_limit = 0.98 * spot_price` gives the liquidity within a 2% price move. The
+    /// amounts are computed from the initialized ticks directly, like a swap up to the limit
+    /// would. If the limit lies beyond the initialized ticks, the depth up to the last tick is
+    /// returned and flagged as partial. Errors with `SimulationError::InvalidInput` if the limit
+    /// is above the spot price.
+    pub fn depth_within_price_bounds(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+        price_limit: f64,
+    ) -> Result<PriceDepth, SimulationError> {
+        let zero_for_one = token_in < token_out;
+        let target = price_to_sqrt_price_q96(
+            price_limit,
+            token_in.decimals as u32,
+            token_out.decimals as u32,
+            zero_for_one,
+        )?;
+        // Selling token 0 lowers the sqrt price, selling token 1 raises it
+        let above_spot =
+            if zero_for_one { target > self.sqrt_price } else { target < self.sqrt_price };
+        if above_spot {
+            return Err(SimulationError::InvalidInput(
+                format!("Price limit {price_limit} is above the spot price"),
+                None,
+            ));
+        }
+        depth_to_sqrt_price(
+            self.sqrt_price,
+            self.tick,
+            self.liquidity,
+            &self.ticks,
+            self.fee as u32,
+            target,
+        )
+    }
+
+    /// Returns the amount of `token_in`, including fees, a swap for `token_out` takes to move the
+    /// pool's sqrt price to `target_sqrt_price`.
+    ///
+    /// Computed from the initialized ticks like `depth_within_price_bounds`. Errors with
+    /// `SimulationError::InvalidInput` if selling `token_in` moves the price away from the target
+    /// or if the target lies beyond the initialized ticks, out of reach of the pool's liquidity.
+    pub fn amount_to_reach_price(
+        &self,
+        target_sqrt_price: U256,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<BigUint, SimulationError> {
+        let zero_for_one = token_in < token_out;
+        let wrong_direction = if zero_for_one {
+            target_sqrt_price > self.sqrt_price
+        } else {
+            target_sqrt_price < self.sqrt_price
+        };
+        if wrong_direction {
+            return Err(SimulationError::InvalidInput(
+                format!(
+                    "Selling {} can't move the sqrt price to {target_sqrt_price}",
+                    token_in.symbol
+                ),
+                None,
+            ));
+        }
+        let depth = depth_to_sqrt_price(
+            self.sqrt_price,
+            self.tick,
+            self.liquidity,
+            &self.ticks,
+            self.fee as u32,
+            target_sqrt_price,
+        )?;
+        if depth.partial {
+            return Err(SimulationError::InvalidInput(
+                format!("Sqrt price {target_sqrt_price} is beyond the pool's liquidity"),
+                None,
+            ));
+        }
+        Ok(depth.amount_in)
+    }
+
+    /// Swaps `amount_in` up to the price limit, returns the result and the unswapped amount.
+    fn swap_exact_in(
+        &self,
+        amount_in: &BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_price_limit: Option<U256>,
+    ) -> Result<(GetAmountOutResult, U256), SimulationError> {
+        let zero_for_one = token_in < token_out;
+        // Arithmetic failures, including amounts beyond the I256 range, are reported according
+        // to the math mode
+        let result = biguint_to_u256_checked(amount_in)
+            .and_then(to_i256)
+            .and_then(|amount_specified| {
+                self.swap(zero_for_one, amount_specified, sqrt_price_limit)
+            })
+            .map_err(|e| self.math_mode.map_err(e))?;
+
+        trace!(?amount_in, ?token_in, ?token_out, ?zero_for_one, ?result, "V3 SWAP");
+        let mut new_state = self.clone();
+        new_state.liquidity = result.liquidity;
+        new_state.tick = result.tick;
+        new_state.sqrt_price = result.sqrt_price;
+
+        let amount_out = GetAmountOutResult::with_gas_breakdown(
+            u256_to_biguint(
+                result
+                    .amount_calculated
+                    .abs()
+                    .into_raw(),
+            ),
+            swap_gas_breakdown(u256_to_biguint(result.gas_used)),
+            Box::new(new_state),
+        )
+        .with_crossed_ticks(result.crossed_ticks);
+        Ok((amount_out, result.amount_remaining.into_raw()))
     }
 
     fn get_spacing(fee: FeeAmount) -> u16 {
@@ -78,36 +309,45 @@ impl UniswapV3State {
         if self.liquidity == 0 {
             return Err(SimulationError::RecoverableError("No liquidity".to_string()));
         }
-        let price_limit = if let Some(limit) = sqrt_price_limit {
-            limit
-        } else if zero_for_one {
-            safe_add_u256(MIN_SQRT_RATIO, U256::from(1u64))?
-        } else {
-            safe_sub_u256(MAX_SQRT_RATIO, U256::from(1u64))?
-        };
-
-        if zero_for_one {
-            assert!(price_limit > MIN_SQRT_RATIO);
-            assert!(price_limit < self.sqrt_price);
-        } else {
-            assert!(price_limit < MAX_SQRT_RATIO);
-            assert!(price_limit > self.sqrt_price);
-        }
+        let price_limit =
+            resolve_sqrt_price_limit(self.sqrt_price, zero_for_one, sqrt_price_limit)?;
+        self.swap_from(SwapCheckpoint::start(self), zero_for_one, amount_specified, price_limit)
+            .map(|(result, _)| result)
+    }
 
+    /// Continues a swap of `amount_specified` from `start`, a checkpoint of a swap of a smaller
+    /// or equal amount in the same direction and with the same price limit.
+    ///
+    /// Returns the result and the checkpoint after the last step that reached its target tick.
+    fn swap_from(
+        &self,
+        start: SwapCheckpoint,
+        zero_for_one: bool,
+        amount_specified: I256,
+        price_limit: U256,
+    ) -> Result<(SwapResults, SwapCheckpoint), SimulationError> {
         let exact_input = amount_specified > I256::from_raw(U256::from(0u64));
 
         let mut state = SwapState {
-            amount_remaining: amount_specified,
-            amount_calculated: I256::from_raw(U256::from(0u64)),
-            sqrt_price: self.sqrt_price,
-            tick: self.tick,
-            liquidity: self.liquidity,
+            amount_remaining: amount_specified - start.amount_used,
+            amount_calculated: start.amount_calculated,
+            sqrt_price: start.sqrt_price,
+            tick: start.tick,
+            liquidity: start.liquidity,
         };
-        let mut gas_used = U256::from(130_000);
+        let mut crossed_ticks = start.crossed_ticks;
+        let mut loaded_words = start.loaded_words;
+        // The first step searches the word of the current tick, later steps only search a new
+        // word after reaching the end of the previous one
+        let mut new_word = start.new_word;
+        let mut checkpoint = start;
 
         while state.amount_remaining != I256::from_raw(U256::from(0u64)) &&
             state.sqrt_price != price_limit
         {
+            if new_word {
+                loaded_words += 1;
+            }
             let (mut next_tick, initialized) = match self
                 .ticks
                 .next_initialized_tick_within_one_word(state.tick, zero_for_one)
@@ -121,11 +361,16 @@ impl UniswapV3State {
                         new_state.sqrt_price = state.sqrt_price;
                         return Err(SimulationError::InvalidInput(
                             "Ticks exceeded".into(),
-                            Some(GetAmountOutResult::new(
-                                u256_to_biguint(state.amount_calculated.abs().into_raw()),
-                                u256_to_biguint(gas_used),
-                                Box::new(new_state),
-                            )),
+                            Some(
+                                GetAmountOutResult::with_gas_breakdown(
+                                    u256_to_biguint(state.amount_calculated.abs().into_raw()),
+                                    swap_gas_breakdown(u256_to_biguint(
+                                        GAS_MODEL.gas(crossed_ticks, loaded_words),
+                                    )),
+                                    Box::new(new_state),
+                                )
+                                .with_crossed_ticks(crossed_ticks),
+                            ),
                         ));
                     }
                     _ => return Err(SimulationError::FatalError("Unknown error".to_string())),
@@ -154,21 +399,12 @@ impl UniswapV3State {
                 fee_amount,
             };
             if exact_input {
-                state.amount_remaining -= I256::checked_from_sign_and_abs(
-                    Sign::Positive,
-                    safe_add_u256(step.amount_in, step.fee_amount)?,
-                )
-                .unwrap();
-                state.amount_calculated -=
-                    I256::checked_from_sign_and_abs(Sign::Positive, step.amount_out).unwrap();
+                state.amount_remaining -= to_i256(safe_add_u256(step.amount_in, step.fee_amount)?)?;
+                state.amount_calculated -= to_i256(step.amount_out)?;
             } else {
-                state.amount_remaining +=
-                    I256::checked_from_sign_and_abs(Sign::Positive, step.amount_out).unwrap();
-                state.amount_calculated += I256::checked_from_sign_and_abs(
-                    Sign::Positive,
-                    safe_add_u256(step.amount_in, step.fee_amount)?,
-                )
-                .unwrap();
+                state.amount_remaining += to_i256(step.amount_out)?;
+                state.amount_calculated +=
+                    to_i256(safe_add_u256(step.amount_in, step.fee_amount)?)?;
             }
             if state.sqrt_price == step.sqrt_price_next {
                 if step.initialized {
@@ -180,20 +416,89 @@ impl UniswapV3State {
                     let liquidity_net = if zero_for_one { -liquidity_raw } else { liquidity_raw };
                     state.liquidity =
                         liquidity_math::add_liquidity_delta(state.liquidity, liquidity_net);
+                    crossed_ticks += 1;
                 }
                 state.tick = if zero_for_one { step.tick_next - 1 } else { step.tick_next };
             } else if state.sqrt_price != step.sqrt_price_start {
                 state.tick = get_tick_at_sqrt_ratio(state.sqrt_price)?;
             }
-            gas_used = safe_add_u256(gas_used, U256::from(2000))?;
+            new_word = state.sqrt_price == step.sqrt_price_next && !step.initialized;
+            // A step that reaches its target takes the same amounts whatever the amount
+            // remaining, so larger swaps pass through the same state
+            if state.sqrt_price == step.sqrt_price_next {
+                checkpoint = SwapCheckpoint {
+                    amount_used: amount_specified - state.amount_remaining,
+                    amount_calculated: state.amount_calculated,
+                    sqrt_price: state.sqrt_price,
+                    tick: state.tick,
+                    liquidity: state.liquidity,
+                    crossed_ticks,
+                    loaded_words,
+                    new_word,
+                };
+            }
         }
-        Ok(SwapResults {
+        let result = SwapResults {
             amount_calculated: state.amount_calculated,
+            amount_remaining: state.amount_remaining,
             sqrt_price: state.sqrt_price,
             liquidity: state.liquidity,
             tick: state.tick,
-            gas_used,
-        })
+            gas_used: GAS_MODEL.gas(crossed_ticks, loaded_words),
+            crossed_ticks,
+            loaded_words,
+        };
+        Ok((result, checkpoint))
+    }
+
+    /// Quotes the ascending `amounts` in a single pass over the ticks: each amount resumes the
+    /// swap of the previous one from its last completed step.
+    ///
+    /// Amounts the single pass can't quote, e.g. because they exhaust the initialized ticks, and
+    /// all amounts after them are quoted with `get_amount_out`, so results and errors match it.
+    fn quote_ascending(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+        amounts: &[BigUint],
+    ) -> Result<Vec<(BigUint, BigUint, BigUint)>, SimulationError> {
+        let zero_for_one = token_in < token_out;
+        let price_limit = resolve_sqrt_price_limit(self.sqrt_price, zero_for_one, None)?;
+        let mut checkpoint = (self.liquidity != 0).then(|| SwapCheckpoint::start(self));
+        let mut quotes = Vec::with_capacity(amounts.len());
+        for amount_in in amounts {
+            let swapped = checkpoint.clone().and_then(|start| {
+                biguint_to_u256_checked(amount_in)
+                    .and_then(to_i256)
+                    .and_then(|amount_specified| {
+                        self.swap_from(start, zero_for_one, amount_specified, price_limit)
+                    })
+                    .ok()
+            });
+            let quote = match swapped {
+                Some((result, next)) => {
+                    checkpoint = Some(next);
+                    let gas = swap_gas_breakdown(u256_to_biguint(result.gas_used))
+                        .iter()
+                        .map(|item| &item.gas)
+                        .sum();
+                    let amount_out = u256_to_biguint(
+                        result
+                            .amount_calculated
+                            .abs()
+                            .into_raw(),
+                    );
+                    (amount_in.clone(), amount_out, gas)
+                }
+                None => {
+                    checkpoint = None;
+                    let result = self.get_amount_out(amount_in.clone(), token_in, token_out)?;
+                    (amount_in.clone(), result.amount, result.gas)
+                }
+            };
+            quotes.push(quote);
+        }
+        Ok(quotes)
     }
 
     fn get_sqrt_ratio_target(
@@ -229,37 +534,34 @@ impl ProtocolSim for UniswapV3State {
         }
     }
 
+    /// Computed from the sqrt price directly rather than as `1 / spot_price`.
+    fn spot_price_inverse(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        Ok(sqrt_price_q96_to_price(
+            self.sqrt_price,
+            quote.decimals as u32,
+            base.decimals as u32,
+            quote < base,
+        ))
+    }
+
     fn get_amount_out(
         &self,
         amount_in: BigUint,
         token_a: &Token,
         token_b: &Token,
     ) -> Result<GetAmountOutResult, SimulationError> {
-        let zero_for_one = token_a < token_b;
-        let amount_specified = I256::checked_from_sign_and_abs(
-            Sign::Positive,
-            U256::from_be_slice(&amount_in.to_bytes_be()),
-        )
-        .unwrap();
-
-        let result = self.swap(zero_for_one, amount_specified, None)?;
-
-        trace!(?amount_in, ?token_a, ?token_b, ?zero_for_one, ?result, "V3 SWAP");
-        let mut new_state = self.clone();
-        new_state.liquidity = result.liquidity;
-        new_state.tick = result.tick;
-        new_state.sqrt_price = result.sqrt_price;
+        self.swap_exact_in(&amount_in, token_a, token_b, None)
+            .map(|(result, _)| result)
+    }
 
-        Ok(GetAmountOutResult::new(
-            u256_to_biguint(
-                result
-                    .amount_calculated
-                    .abs()
-                    .into_raw(),
-            ),
-            u256_to_biguint(result.gas_used),
-            Box::new(new_state),
-        ))
+    fn quote_curve(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+        amounts: &[BigUint],
+    ) -> Result<Vec<CurvePoint>, SimulationError> {
+        CurvePoint::check_grid(amounts)?;
+        Ok(CurvePoint::curve(self.quote_ascending(token_in, token_out, amounts)?))
     }
 
     fn delta_transition(
@@ -346,6 +648,19 @@ impl ProtocolSim for UniswapV3State {
         Ok(())
     }
 
+    /// The pool is initialized and has liquidity at the current price.
+    fn is_tradeable(&self) -> bool {
+        self.liquidity > 0 && !self.sqrt_price.is_zero()
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.tokens.clone()
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        fingerprint(self)
+    }
+
     fn clone_box(&self) -> Box<dyn ProtocolSim> {
         Box::new(self.clone())
     }
@@ -367,7 +682,8 @@ impl ProtocolSim for UniswapV3State {
                 self.sqrt_price == other_state.sqrt_price &&
                 self.fee == other_state.fee &&
                 self.tick == other_state.tick &&
-                self.ticks == other_state.ticks
+                self.ticks == other_state.ticks &&
+                self.math_mode == other_state.math_mode
         } else {
             false
         }
@@ -382,9 +698,12 @@ mod tests {
     };
 
     use num_bigint::ToBigUint;
+    use num_traits::ToPrimitive;
+    use rstest::rstest;
     use tycho_core::hex_bytes::Bytes;
 
     use super::*;
+    use crate::protocol::models::{from_snapshot, to_snapshot, GasSource};
 
     #[test]
     fn test_get_amount_out_full_range_liquidity() {
@@ -418,12 +737,619 @@ mod tests {
         assert_eq!(res.amount, expected);
     }
 
+    #[test]
+    fn test_get_amount_out_with_price_limit() {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV3State::new(
+            liquidity as u128,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![
+                TickInfo::new(-600, liquidity),
+                TickInfo::new(600, liquidity),
+                TickInfo::new(1200, -2 * liquidity),
+            ],
+        );
+        let amount_in = BigUint::from(20u64) * BigUint::from(10u64).pow(18);
+        let limit = get_sqrt_ratio_at_tick(900).unwrap();
+
+        let (res, consumed) = pool
+            .get_amount_out_with_price_limit(amount_in.clone(), &token_y, &token_x, limit)
+            .unwrap();
+
+        // Halts halfway through the second range, before the input is consumed
+        assert!(consumed > BigUint::ZERO && consumed < amount_in);
+        assert_eq!(res.crossed_ticks, Some(1));
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert_eq!(new_state.sqrt_price, limit);
+        assert_eq!(new_state.active_tick(), 900);
+        assert_eq!(new_state.liquidity(), 2 * liquidity as u128);
+    }
+
+    /// Returns the largest amount of `token_in` whose swap keeps the spot price of `token_in` at
+    /// or above `price_limit`, by bisection, and its amount out.
+    fn bisect_depth(
+        pool: &UniswapV3State,
+        token_in: &Token,
+        token_out: &Token,
+        price_limit: f64,
+    ) -> (BigUint, BigUint) {
+        let within_limit = |amount: &BigUint| {
+            pool.get_amount_out(amount.clone(), token_in, token_out)
+                .ok()
+                .filter(|res| {
+                    res.new_state
+                        .spot_price(token_in, token_out)
+                        .unwrap() >=
+                        price_limit
+                })
+        };
+        let (mut lo, mut hi) = (BigUint::ZERO, BigUint::from(10u64).pow(30));
+        while &hi - &lo > BigUint::from(1u8) {
+            let mid = (&lo + &hi) / 2u8;
+            if within_limit(&mid).is_some() {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let amount_out = within_limit(&lo).unwrap().amount;
+        (lo, amount_out)
+    }
+
+    #[rstest]
+    #[case::within_range(0.98)]
+    #[case::crossing_a_tick(0.9)]
+    #[case::crossing_ticks(0.8)]
+    fn test_depth_within_price_bounds(#[case] price_move: f64) {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            6,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        // Three nested positions around tick 0
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV3State::new(
+            3 * liquidity as u128,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![
+                TickInfo::new(-6000, liquidity),
+                TickInfo::new(-1200, liquidity),
+                TickInfo::new(-600, liquidity),
+                TickInfo::new(600, -liquidity),
+                TickInfo::new(1200, -liquidity),
+                TickInfo::new(6000, -liquidity),
+            ],
+        );
+
+        for (token_in, token_out) in [(&token_x, &token_y), (&token_y, &token_x)] {
+            let price_limit = pool
+                .spot_price(token_in, token_out)
+                .unwrap() *
+                price_move;
+
+            let depth = pool
+                .depth_within_price_bounds(token_in, token_out, price_limit)
+                .unwrap();
+
+            let (amount_in, amount_out) = bisect_depth(&pool, token_in, token_out, price_limit);
+            assert!(!depth.partial);
+            let relative_diff = |a: &BigUint, b: &BigUint| {
+                (a.to_f64().unwrap() - b.to_f64().unwrap()).abs() / b.to_f64().unwrap()
+            };
+            assert!(relative_diff(&depth.amount_in, &amount_in) < 1e-9);
+            assert!(relative_diff(&depth.amount_out, &amount_out) < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_depth_beyond_initialized_ticks() {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV3State::new(
+            liquidity as u128,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![TickInfo::new(-600, liquidity), TickInfo::new(600, -liquidity)],
+        );
+        let spot_price = pool
+            .spot_price(&token_x, &token_y)
+            .unwrap();
+
+        // A 50% move goes beyond tick -600, the last one
+        let depth = pool
+            .depth_within_price_bounds(&token_x, &token_y, spot_price * 0.5)
+            .unwrap();
+        let res = pool
+            .get_amount_out(depth.amount_in.clone(), &token_x, &token_y)
+            .unwrap();
+
+        assert!(depth.partial);
+        assert_eq!(res.amount, depth.amount_out);
+        assert_eq!(
+            res.new_state
+                .as_any()
+                .downcast_ref::<UniswapV3State>()
+                .unwrap()
+                .sqrt_price,
+            get_sqrt_ratio_at_tick(-600).unwrap()
+        );
+        assert!(matches!(
+            pool.depth_within_price_bounds(&token_x, &token_y, spot_price * 1.1),
+            Err(SimulationError::InvalidInput(_, None))
+        ));
+    }
+
+    #[test]
+    fn test_amount_to_reach_price() {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV3State::new(
+            liquidity as u128,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![
+                TickInfo::new(-600, liquidity),
+                TickInfo::new(600, liquidity),
+                TickInfo::new(1800, -2 * liquidity),
+            ],
+        );
+        let target = get_sqrt_ratio_at_tick(1200).unwrap();
+
+        // Selling Y moves the pool up from tick 0 to tick 1200
+        let amount_in = pool
+            .amount_to_reach_price(target, &token_y, &token_x)
+            .unwrap();
+
+        // L * (sqrt price delta) in each range, grossed up by the 0.3% fee
+        let l = liquidity as f64;
+        let net_in =
+            l * (1.0001f64.powi(300) - 1.0) + 2.0 * l * (1.0001f64.powi(600) - 1.0001f64.powi(300));
+        let exp = net_in / (1.0 - 0.003);
+        assert!((amount_in.to_f64().unwrap() - exp).abs() / exp < 1e-9);
+        let res = pool
+            .get_amount_out(amount_in, &token_y, &token_x)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        // The fee rounds in favor of the pool, which can overshoot by a few wei of input
+        assert!(new_state.sqrt_price >= target && new_state.sqrt_price - target < target >> 60);
+        assert_eq!(new_state.active_tick(), 1200);
+        // Beyond the last tick, and against the swap direction
+        assert!(pool
+            .amount_to_reach_price(get_sqrt_ratio_at_tick(2400).unwrap(), &token_y, &token_x)
+            .is_err());
+        assert!(pool
+            .amount_to_reach_price(target, &token_x, &token_y)
+            .is_err());
+    }
+
+    #[test]
+    fn test_get_amount_out_with_invalid_price_limit() {
+        let pool = UniswapV3State::new(
+            10u128.pow(20),
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![TickInfo::new(-600, 10i128.pow(20)), TickInfo::new(600, -10i128.pow(20))],
+        );
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+
+        // Selling X lowers the price, so the limit must be below it
+        let res = pool.get_amount_out_with_price_limit(
+            BigUint::from(1000u64),
+            &token_x,
+            &token_y,
+            get_sqrt_ratio_at_tick(60).unwrap(),
+        );
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_, None))));
+    }
+
+    #[rstest]
+    #[case::within_range(1, 0)]
+    #[case::one_tick(5, 1)]
+    #[case::multiple_ticks(20, 3)]
+    fn test_crossed_ticks(#[case] amount_in: u64, #[case] exp_crossed: u32) {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        // Liquidity grows with every tick crossed upwards from tick 0, all ticks are in one word
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV3State::new(
+            liquidity as u128,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![
+                TickInfo::new(-600, liquidity),
+                TickInfo::new(600, liquidity),
+                TickInfo::new(1200, liquidity),
+                TickInfo::new(1800, liquidity),
+                TickInfo::new(2400, -4 * liquidity),
+            ],
+        );
+        let amount_in = BigUint::from(amount_in) * BigUint::from(10u64).pow(18);
+
+        let res = pool
+            .get_amount_out(amount_in, &token_y, &token_x)
+            .unwrap();
+
+        assert_eq!(res.crossed_ticks, Some(exp_crossed));
+        let pool_gas = res
+            .gas_breakdown
+            .iter()
+            .find(|item| item.source == GasSource::PoolComputation)
+            .unwrap();
+        assert_eq!(pool_gas.gas, u256_to_biguint(GAS_MODEL.gas(exp_crossed, 1)));
+    }
+
+    #[test]
+    fn test_quote_curve() {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV3State::new(
+            liquidity as u128,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![
+                TickInfo::new(-600, liquidity),
+                TickInfo::new(600, liquidity),
+                TickInfo::new(1200, liquidity),
+                TickInfo::new(1800, liquidity),
+                TickInfo::new(2400, -4 * liquidity),
+            ],
+        );
+        // Crosses the ticks at 600, 1200 and 1800, with amounts ending on and between them
+        let mut amounts = (0..=40u64)
+            .map(|i| BigUint::from(i) * BigUint::from(10u64).pow(17) * 5u64)
+            .collect::<Vec<_>>();
+        amounts.insert(7, amounts[6].clone());
+        amounts.insert(12, amounts[11].clone() + 1u64);
+
+        let curve = pool
+            .quote_curve(&token_y, &token_x, &amounts)
+            .unwrap();
+
+        assert_eq!(curve.len(), amounts.len());
+        for (point, amount_in) in curve.iter().zip(&amounts) {
+            let naive = pool
+                .get_amount_out(amount_in.clone(), &token_y, &token_x)
+                .unwrap();
+            assert_eq!(&point.amount_in, amount_in);
+            assert_eq!(point.amount_out, naive.amount);
+            assert_eq!(point.gas, naive.gas);
+            assert!(point.monotonic);
+        }
+        assert_eq!(
+            pool.get_amount_out(amounts[40].clone(), &token_y, &token_x)
+                .unwrap()
+                .crossed_ticks,
+            Some(3)
+        );
+
+        // Selling X exhausts the ticks below, the curve fails like the naive loop
+        let amounts =
+            [1u64, 1_000].map(|units| BigUint::from(units) * BigUint::from(10u64).pow(18));
+        let naive = pool.get_amount_out(amounts[1].clone(), &token_x, &token_y);
+        let res = pool.quote_curve(&token_x, &token_y, &amounts);
+        match (res, naive) {
+            (
+                Err(SimulationError::InvalidInput(msg, Some(partial))),
+                Err(SimulationError::InvalidInput(naive_msg, Some(naive_partial))),
+            ) => {
+                assert_eq!(msg, naive_msg);
+                assert_eq!(partial.amount, naive_partial.amount);
+            }
+            other => panic!("Unexpected results {other:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case::strict(MathMode::Strict)]
+    #[case::lenient(MathMode::Lenient)]
+    fn test_get_amount_out_overflow(#[case] mode: MathMode) {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let pool = UniswapV3State::new(
+            u128::MAX,
+            U256::from_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![TickInfo::new(0, 0), TickInfo::new(46080, 0)],
+        )
+        .with_math_mode(mode);
+        // Doesn't fit into an I256
+        let sell_amount = BigUint::from(1u64) << 255;
+
+        let res = pool.get_amount_out(sell_amount, &token_x, &token_y);
+
+        match mode {
+            MathMode::Strict => assert!(matches!(res, Err(SimulationError::FatalError(_)))),
+            MathMode::Lenient => {
+                assert!(matches!(res, Err(SimulationError::RecoverableError(_))))
+            }
+        }
+    }
+
+    #[test]
+    fn test_effective_price() {
+        let token_x = Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            10_000.to_biguint().unwrap(),
+        );
+        let token_y = Token::new(
+            "0xf1ca9cb74685755965c7458528a36934df52a3ef",
+            18,
+            "Y",
+            10_000.to_biguint().unwrap(),
+        );
+        let pool = UniswapV3State::new(
+            8330443394424070888454257,
+            U256::from_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![TickInfo::new(0, 0), TickInfo::new(46080, 0)],
+        );
+        let sell_amount = BigUint::from_str("11_000_000000000000000000").unwrap();
+
+        let res = pool
+            .effective_price(sell_amount, &token_x, &token_y)
+            .unwrap();
+
+        // Amounts of the same trade in test_get_amount_out_full_range_liquidity
+        let expected = 61927.070842678722935941 / 11_000.0;
+        assert!((res - expected).abs() / expected < 1e-12);
+        // A large trade realizes a worse price than the marginal one
+        assert!(
+            res < pool
+                .spot_price(&token_x, &token_y)
+                .unwrap()
+        );
+        assert!(pool
+            .effective_price(BigUint::ZERO, &token_x, &token_y)
+            .is_err());
+    }
+
+    #[test]
+    fn test_spot_price_decimals() {
+        let usdc = Token::new(
+            "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            6,
+            "USDC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+        // USDC is token 0, a raw price of ~5e8 WETH wei per USDC unit is ~2000 USDC per WETH
+        let tick = 200311;
+        let pool = UniswapV3State::new(
+            1_000_000_000_000_000_000,
+            get_sqrt_ratio_at_tick(tick).unwrap(),
+            FeeAmount::Medium,
+            tick,
+            vec![],
+        );
+
+        let weth_price = pool.spot_price(&weth, &usdc).unwrap();
+        let usdc_price = pool.spot_price(&usdc, &weth).unwrap();
+        let raw_usdc_price = pool
+            .raw_spot_price(&usdc, &weth)
+            .unwrap();
+
+        assert!((weth_price - 2000.0).abs() / 2000.0 < 1e-4);
+        assert!((usdc_price * weth_price - 1.0).abs() < 1e-12);
+        assert!((raw_usdc_price - 1.0001f64.powi(tick)).abs() / raw_usdc_price < 1e-9);
+        assert!((raw_usdc_price - usdc_price * 1e12).abs() / raw_usdc_price < 1e-12);
+    }
+
+    #[rstest]
+    // Reference prices are the exact squared sqrt prices, rounded once
+    #[case::wbtc_weth(
+        ("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599", 8),
+        ("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", 18),
+        "33728738793710834664467217410667110",
+        259243,
+        0.05517711172004163f64,
+        18.123456789f64
+    )]
+    #[case::usdc_weth(
+        ("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48", 6),
+        ("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", 18),
+        "1777088903921806274759829776256902",
+        200373,
+        1987.654321f64,
+        0.0005031055900589869f64
+    )]
+    fn test_spot_price_inverse(
+        #[case] token_0: (&str, usize),
+        #[case] token_1: (&str, usize),
+        #[case] sqrt_price: &str,
+        #[case] tick: i32,
+        #[case] exp_1_in_0: f64,
+        #[case] exp_0_in_1: f64,
+    ) {
+        let t0 = Token::new(token_0.0, token_0.1, "T0", 10_000.to_biguint().unwrap());
+        let t1 = Token::new(token_1.0, token_1.1, "T1", 10_000.to_biguint().unwrap());
+        let pool = UniswapV3State::new(
+            1_000_000_000_000_000_000,
+            U256::from_str(sqrt_price).unwrap(),
+            FeeAmount::Medium,
+            tick,
+            vec![],
+        );
+
+        assert_eq!(
+            pool.spot_price_inverse(&t0, &t1)
+                .unwrap(),
+            exp_1_in_0
+        );
+        assert_eq!(
+            pool.spot_price_inverse(&t1, &t0)
+                .unwrap(),
+            exp_0_in_1
+        );
+        let spot_price = pool.spot_price(&t0, &t1).unwrap();
+        assert!((spot_price - exp_0_in_1).abs() / exp_0_in_1 < 1e-12);
+        let fee_adjusted = pool
+            .fee_adjusted_price(&t0, &t1)
+            .unwrap();
+        assert_eq!(fee_adjusted, spot_price * 0.997);
+    }
+
     struct SwapTestCase {
         symbol: &'static str,
         sell: BigUint,
         exp: BigUint,
     }
 
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let wbtc = Token::new(
+            "0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599",
+            8,
+            "WBTC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+        let pool = UniswapV3State::new(
+            377952820878029838,
+            U256::from_str("28437325270877025820973479874632004").unwrap(),
+            FeeAmount::Low,
+            255830,
+            vec![
+                TickInfo::new(255760, 1759015528199933i128),
+                TickInfo::new(255770, 6393138051835308i128),
+                TickInfo::new(255830, 678916926147901i128),
+                TickInfo::new(255840, 12208947683433103i128),
+                TickInfo::new(255900, 77340284046725227i128),
+            ],
+        );
+
+        let snapshot = to_snapshot(&pool).expect("UniswapV3State is snapshotable");
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let restored = from_snapshot(serde_json::from_str(&json).unwrap()).unwrap();
+
+        assert!(json.contains(r#""protocol":"uniswap_v3""#));
+        assert!(restored.eq(&pool));
+        let sell = 500000000.to_biguint().unwrap();
+        assert_eq!(
+            restored
+                .get_amount_out(sell.clone(), &wbtc, &weth)
+                .unwrap()
+                .amount,
+            pool.get_amount_out(sell, &wbtc, &weth)
+                .unwrap()
+                .amount
+        );
+    }
+
     #[test]
     fn test_get_amount_out() {
         let wbtc = Token::new(
@@ -589,6 +1515,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_liquidity_depth() {
+        // Three positions: [-600, 600), [-120, 1200) and [600, 1800). Only the first two are in
+        // range at tick 300.
+        let (l1, l2, l3) = (2_000_000_i128, 500_000_i128, 750_000_i128);
+        let pool = UniswapV3State::new(
+            (l1 + l2) as u128,
+            get_sqrt_ratio_at_tick(300).unwrap(),
+            FeeAmount::Medium,
+            300,
+            vec![
+                TickInfo::new(-600, l1),
+                TickInfo::new(-120, l2),
+                TickInfo::new(600, l3 - l1),
+                TickInfo::new(1200, -l2),
+                TickInfo::new(1800, -l3),
+            ],
+        );
+
+        let depth = pool.liquidity_depth();
+
+        assert_eq!(depth, vec![(-600, l1), (-120, l2), (600, l3 - l1), (1200, -l2), (1800, -l3)]);
+        let active: i128 = depth
+            .iter()
+            .filter(|(tick, _)| *tick <= pool.active_tick())
+            .map(|(_, net_liquidity)| net_liquidity)
+            .sum();
+        assert_eq!(active, pool.liquidity() as i128);
+        assert_eq!(
+            depth
+                .iter()
+                .map(|(_, n)| n)
+                .sum::<i128>(),
+            0
+        );
+    }
+
+    #[rstest]
+    #[case::in_range(1_000_000, true)]
+    #[case::no_active_liquidity(0, false)]
+    fn test_is_tradeable(#[case] liquidity: u128, #[case] exp: bool) {
+        let pool = UniswapV3State::new(
+            liquidity,
+            U256::from(1u64) << 96,
+            FeeAmount::Medium,
+            0,
+            vec![TickInfo::new(-60, 1_000_000), TickInfo::new(60, -1_000_000)],
+        );
+
+        assert_eq!(pool.is_tradeable(), exp);
+    }
+
     #[test]
     fn test_delta_transition() {
         let mut pool = UniswapV3State::new(
@@ -640,4 +1618,41 @@ mod tests {
             9800
         );
     }
+
+    #[rstest]
+    #[case::empty(vec![], true)]
+    #[case::same_liquidity(vec![("liquidity", 1000_u64.to_be_bytes().to_vec())], true)]
+    #[case::new_liquidity(vec![("liquidity", 2000_u64.to_be_bytes().to_vec())], false)]
+    #[case::new_tick(vec![("tick", 120_i32.to_be_bytes().to_vec())], false)]
+    #[case::new_tick_liquidity(
+        vec![("ticks/255900/net_liquidity", (-9000_i128).to_be_bytes().to_vec())],
+        false
+    )]
+    fn test_fingerprint_after_delta(
+        #[case] attributes: Vec<(&str, Vec<u8>)>,
+        #[case] unchanged: bool,
+    ) {
+        let pool = UniswapV3State::new(
+            1000,
+            U256::from(1000),
+            FeeAmount::Low,
+            100,
+            vec![TickInfo::new(255760, 10000), TickInfo::new(255900, -10000)],
+        );
+        let mut transitioned = pool.clone();
+        let delta = ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: attributes
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), Bytes::from(value)))
+                .collect(),
+            deleted_attributes: HashSet::new(),
+        };
+
+        transitioned
+            .delta_transition(delta, &HashMap::new())
+            .unwrap();
+
+        assert_eq!(transitioned.state_fingerprint() == pool.state_fingerprint(), unchanged);
+    }
 }
@@ -120,7 +120,8 @@ impl TryFromWithBlock<ComponentWithState> for UniswapV3State {
 
         ticks.sort_by_key(|tick| tick.index);
 
-        Ok(UniswapV3State::new(liquidity, sqrt_price, fee, tick, ticks))
+        Ok(UniswapV3State::new(liquidity, sqrt_price, fee, tick, ticks)
+            .with_tokens(snapshot.component.tokens.clone()))
     }
 }
 
@@ -1,44 +1,60 @@
 use std::{any::Any, collections::HashMap};
 
-use alloy_primitives::{Sign, I256, U256};
+use alloy_primitives::{I256, U256};
 use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
 use tracing::trace;
 use tycho_core::{dto::ProtocolStateDelta, Bytes};
 
 use crate::{
     evm::protocol::{
-        safe_math::{safe_add_u256, safe_sub_u256},
-        u256_num::u256_to_biguint,
+        safe_math::{safe_add_u256, MathMode},
+        u256_num::{biguint_to_u256_checked, u256_to_biguint},
         utils::uniswap::{
-            i24_be_bytes_to_i32, liquidity_math,
-            sqrt_price_math::sqrt_price_q96_to_f64,
+            depth_to_sqrt_price, i24_be_bytes_to_i32, liquidity_math, resolve_sqrt_price_limit,
+            sqrt_price_math::{
+                price_to_sqrt_price_q96, sqrt_price_q96_to_f64, sqrt_price_q96_to_price,
+            },
             swap_math,
             tick_list::{TickInfo, TickList, TickListErrorKind},
-            tick_math::{
-                get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_SQRT_RATIO, MAX_TICK,
-                MIN_SQRT_RATIO, MIN_TICK,
-            },
-            StepComputation, SwapResults, SwapState,
+            tick_math::{get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio, MAX_TICK, MIN_TICK},
+            to_i256, PriceDepth, StepComputation, SwapResults, SwapState, TickGasModel,
+            COLD_SLOAD_GAS, COLD_SSTORE_GAS, SWAP_STEP_GAS,
         },
     },
     models::Token,
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
-        state::ProtocolSim,
+        models::{swap_gas_breakdown, GetAmountOutResult},
+        state::{fingerprint, ProtocolSim},
     },
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Gas of the pool's swap logic, see `TickGasModel`
+const GAS_MODEL: TickGasModel = TickGasModel {
+    base: 49_000,
+    // The step to the tick, a cold read of the tick's liquidity and writes of its two fee growth
+    // outside slots. V4 ticks have no oracle accumulators.
+    per_crossed_tick: SWAP_STEP_GAS + COLD_SLOAD_GAS + 2 * COLD_SSTORE_GAS,
+    // A cold read of the bitmap word
+    per_word: COLD_SLOAD_GAS,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UniswapV4State {
     liquidity: u128,
     sqrt_price: U256,
     fees: UniswapV4Fees,
     tick: i32,
     ticks: TickList,
+    #[serde(default)]
+    math_mode: MathMode,
+    /// Addresses of the pool's tokens, in the pool's order. Empty unless set with `with_tokens`.
+    #[serde(default)]
+    tokens: Vec<Bytes>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct UniswapV4Fees {
     // Protocol fees in the zero for one direction
     zero_for_one: u32,
@@ -77,7 +93,142 @@ impl UniswapV4State {
                 .expect("tick_spacing should always be positive"),
             ticks,
         );
-        UniswapV4State { liquidity, sqrt_price, fees, tick, ticks: tick_list }
+        UniswapV4State {
+            liquidity,
+            sqrt_price,
+            fees,
+            tick,
+            ticks: tick_list,
+            math_mode: MathMode::Strict,
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Sets how arithmetic failures are reported. In `MathMode::Lenient` a swap that would
+    /// overflow fails with a `SimulationError::RecoverableError` instead of a fatal error.
+    pub fn with_math_mode(mut self, mode: MathMode) -> Self {
+        self.math_mode = mode;
+        self
+    }
+
+    /// Sets the addresses of the pool's tokens reported by `tokens`, in the pool's order.
+    pub fn with_tokens(mut self, tokens: Vec<Bytes>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Returns the initialized ticks of the pool together with their net liquidity, ordered by
+    /// tick index.
+    ///
+    /// Crossing a tick from left to right adds its net liquidity to the active liquidity, so the
+    /// sum of all net liquidities at or below `active_tick` equals `liquidity`.
+    pub fn liquidity_depth(&self) -> Vec<(i32, i128)> {
+        self.ticks
+            .ticks()
+            .iter()
+            .map(|tick| (tick.index, tick.net_liquidity))
+            .collect()
+    }
+
+    /// Returns the current tick of the pool.
+    pub fn active_tick(&self) -> i32 {
+        self.tick
+    }
+
+    /// Returns the liquidity currently in range.
+    pub fn liquidity(&self) -> u128 {
+        self.liquidity
+    }
+
+    /// Swaps `amount_in` like `get_amount_out`, but stops once the pool's sqrt price reaches
+    /// `sqrt_price_limit`, like the `sqrtPriceLimitX96` swap parameter.
+    ///
+    /// Returns the result together with the part of `amount_in` the swap consumed, see
+    /// `UniswapV3State::get_amount_out_with_price_limit`.
+    pub fn get_amount_out_with_price_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_price_limit: U256,
+    ) -> Result<(GetAmountOutResult, BigUint), SimulationError> {
+        let (result, amount_remaining) =
+            self.swap_exact_in(&amount_in, token_in, token_out, Some(sqrt_price_limit))?;
+        Ok((result, amount_in - u256_to_biguint(amount_remaining)))
+    }
+
+    /// Returns the amounts a swap of `token_in` for `token_out` takes until the price of
+    /// `token_in` in `token_out` falls to `price_limit`, see
+    /// `UniswapV3State::depth_within_price_bounds`.
+    pub fn depth_within_price_bounds(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+        price_limit: f64,
+    ) -> Result<PriceDepth, SimulationError> {
+        let zero_for_one = token_in < token_out;
+        let target = price_to_sqrt_price_q96(
+            price_limit,
+            token_in.decimals as u32,
+            token_out.decimals as u32,
+            zero_for_one,
+        )?;
+        // Selling token 0 lowers the sqrt price, selling token 1 raises it
+        let above_spot =
+            if zero_for_one { target > self.sqrt_price } else { target < self.sqrt_price };
+        if above_spot {
+            return Err(SimulationError::InvalidInput(
+                format!("Price limit {price_limit} is above the spot price"),
+                None,
+            ));
+        }
+        depth_to_sqrt_price(
+            self.sqrt_price,
+            self.tick,
+            self.liquidity,
+            &self.ticks,
+            self.fees
+                .calculate_swap_fees_pips(zero_for_one),
+            target,
+        )
+    }
+
+    /// Swaps `amount_in` up to the price limit, returns the result and the unswapped amount.
+    fn swap_exact_in(
+        &self,
+        amount_in: &BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        sqrt_price_limit: Option<U256>,
+    ) -> Result<(GetAmountOutResult, U256), SimulationError> {
+        let zero_for_one = token_in < token_out;
+        // Arithmetic failures, including amounts beyond the I256 range, are reported according
+        // to the math mode
+        let result = biguint_to_u256_checked(amount_in)
+            .and_then(to_i256)
+            .and_then(|amount_specified| {
+                self.swap(zero_for_one, amount_specified, sqrt_price_limit)
+            })
+            .map_err(|e| self.math_mode.map_err(e))?;
+
+        trace!(?amount_in, ?token_in, ?token_out, ?zero_for_one, ?result, "V4 SWAP");
+        let mut new_state = self.clone();
+        new_state.liquidity = result.liquidity;
+        new_state.tick = result.tick;
+        new_state.sqrt_price = result.sqrt_price;
+
+        let amount_out = GetAmountOutResult::with_gas_breakdown(
+            u256_to_biguint(
+                result
+                    .amount_calculated
+                    .abs()
+                    .into_raw(),
+            ),
+            swap_gas_breakdown(u256_to_biguint(result.gas_used)),
+            Box::new(new_state),
+        )
+        .with_crossed_ticks(result.crossed_ticks);
+        Ok((amount_out, result.amount_remaining.into_raw()))
     }
 
     fn swap(
@@ -89,21 +240,8 @@ impl UniswapV4State {
         if self.liquidity == 0 {
             return Err(SimulationError::RecoverableError("No liquidity".to_string()));
         }
-        let price_limit = if let Some(limit) = sqrt_price_limit {
-            limit
-        } else if zero_for_one {
-            safe_add_u256(MIN_SQRT_RATIO, U256::from(1u64))?
-        } else {
-            safe_sub_u256(MAX_SQRT_RATIO, U256::from(1u64))?
-        };
-
-        if zero_for_one {
-            assert!(price_limit > MIN_SQRT_RATIO);
-            assert!(price_limit < self.sqrt_price);
-        } else {
-            assert!(price_limit < MAX_SQRT_RATIO);
-            assert!(price_limit > self.sqrt_price);
-        }
+        let price_limit =
+            resolve_sqrt_price_limit(self.sqrt_price, zero_for_one, sqrt_price_limit)?;
 
         let exact_input = amount_specified > I256::from_raw(U256::from(0u64));
 
@@ -114,11 +252,18 @@ impl UniswapV4State {
             tick: self.tick,
             liquidity: self.liquidity,
         };
-        let mut gas_used = U256::from(130_000);
+        let mut crossed_ticks = 0u32;
+        let mut loaded_words = 0u32;
+        // The first step searches the word of the current tick, later steps only search a new
+        // word after reaching the end of the previous one
+        let mut new_word = true;
 
         while state.amount_remaining != I256::from_raw(U256::from(0u64)) &&
             state.sqrt_price != price_limit
         {
+            if new_word {
+                loaded_words += 1;
+            }
             let (mut next_tick, initialized) = match self
                 .ticks
                 .next_initialized_tick_within_one_word(state.tick, zero_for_one)
@@ -132,11 +277,16 @@ impl UniswapV4State {
                         new_state.sqrt_price = state.sqrt_price;
                         return Err(SimulationError::InvalidInput(
                             "Ticks exceeded".into(),
-                            Some(GetAmountOutResult::new(
-                                u256_to_biguint(state.amount_calculated.abs().into_raw()),
-                                u256_to_biguint(gas_used),
-                                Box::new(new_state),
-                            )),
+                            Some(
+                                GetAmountOutResult::with_gas_breakdown(
+                                    u256_to_biguint(state.amount_calculated.abs().into_raw()),
+                                    swap_gas_breakdown(u256_to_biguint(
+                                        GAS_MODEL.gas(crossed_ticks, loaded_words),
+                                    )),
+                                    Box::new(new_state),
+                                )
+                                .with_crossed_ticks(crossed_ticks),
+                            ),
                         ));
                     }
                     _ => return Err(SimulationError::FatalError("Unknown error".to_string())),
@@ -166,21 +316,12 @@ impl UniswapV4State {
                 fee_amount,
             };
             if exact_input {
-                state.amount_remaining -= I256::checked_from_sign_and_abs(
-                    Sign::Positive,
-                    safe_add_u256(step.amount_in, step.fee_amount)?,
-                )
-                .unwrap();
-                state.amount_calculated -=
-                    I256::checked_from_sign_and_abs(Sign::Positive, step.amount_out).unwrap();
+                state.amount_remaining -= to_i256(safe_add_u256(step.amount_in, step.fee_amount)?)?;
+                state.amount_calculated -= to_i256(step.amount_out)?;
             } else {
-                state.amount_remaining +=
-                    I256::checked_from_sign_and_abs(Sign::Positive, step.amount_out).unwrap();
-                state.amount_calculated += I256::checked_from_sign_and_abs(
-                    Sign::Positive,
-                    safe_add_u256(step.amount_in, step.fee_amount)?,
-                )
-                .unwrap();
+                state.amount_remaining += to_i256(step.amount_out)?;
+                state.amount_calculated +=
+                    to_i256(safe_add_u256(step.amount_in, step.fee_amount)?)?;
             }
             if state.sqrt_price == step.sqrt_price_next {
                 if step.initialized {
@@ -192,19 +333,23 @@ impl UniswapV4State {
                     let liquidity_net = if zero_for_one { -liquidity_raw } else { liquidity_raw };
                     state.liquidity =
                         liquidity_math::add_liquidity_delta(state.liquidity, liquidity_net);
+                    crossed_ticks += 1;
                 }
                 state.tick = if zero_for_one { step.tick_next - 1 } else { step.tick_next };
             } else if state.sqrt_price != step.sqrt_price_start {
                 state.tick = get_tick_at_sqrt_ratio(state.sqrt_price)?;
             }
-            gas_used = safe_add_u256(gas_used, U256::from(2000))?;
+            new_word = state.sqrt_price == step.sqrt_price_next && !step.initialized;
         }
         Ok(SwapResults {
             amount_calculated: state.amount_calculated,
+            amount_remaining: state.amount_remaining,
             sqrt_price: state.sqrt_price,
             liquidity: state.liquidity,
             tick: state.tick,
-            gas_used,
+            gas_used: GAS_MODEL.gas(crossed_ticks, loaded_words),
+            crossed_ticks,
+            loaded_words,
         })
     }
 
@@ -228,10 +373,23 @@ impl UniswapV4State {
 }
 
 impl ProtocolSim for UniswapV4State {
-    // Not possible to implement correctly with the current interface because we need to know the
-    // swap direction.
+    /// The protocol fee depends on the swap direction, the larger of the two directions' fees is
+    /// returned.
     fn fee(&self) -> f64 {
-        todo!()
+        let zero_for_one = self.fees.calculate_swap_fees_pips(true);
+        let one_for_zero = self
+            .fees
+            .calculate_swap_fees_pips(false);
+        zero_for_one.max(one_for_zero) as f64 / 1_000_000.0
+    }
+
+    /// Applies the fee of selling `base`, unlike the default which applies `fee`.
+    fn fee_adjusted_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        let fee_pips = self
+            .fees
+            .calculate_swap_fees_pips(base < quote);
+        self.spot_price(base, quote)
+            .map(|price| price * (1.0 - fee_pips as f64 / 1_000_000.0))
     }
 
     fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
@@ -247,37 +405,24 @@ impl ProtocolSim for UniswapV4State {
         }
     }
 
+    /// Computed from the sqrt price directly rather than as `1 / spot_price`.
+    fn spot_price_inverse(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        Ok(sqrt_price_q96_to_price(
+            self.sqrt_price,
+            quote.decimals as u32,
+            base.decimals as u32,
+            quote < base,
+        ))
+    }
+
     fn get_amount_out(
         &self,
         amount_in: BigUint,
         token_in: &Token,
         token_out: &Token,
     ) -> Result<GetAmountOutResult, SimulationError> {
-        let zero_for_one = token_in < token_out;
-        let amount_specified = I256::checked_from_sign_and_abs(
-            Sign::Positive,
-            U256::from_be_slice(&amount_in.to_bytes_be()),
-        )
-        .expect("UniswapV4 I256 overflow");
-
-        let result = self.swap(zero_for_one, amount_specified, None)?;
-
-        trace!(?amount_in, ?token_in, ?token_out, ?zero_for_one, ?result, "V4 SWAP");
-        let mut new_state = self.clone();
-        new_state.liquidity = result.liquidity;
-        new_state.tick = result.tick;
-        new_state.sqrt_price = result.sqrt_price;
-
-        Ok(GetAmountOutResult::new(
-            u256_to_biguint(
-                result
-                    .amount_calculated
-                    .abs()
-                    .into_raw(),
-            ),
-            u256_to_biguint(result.gas_used),
-            Box::new(new_state),
-        ))
+        self.swap_exact_in(&amount_in, token_in, token_out, None)
+            .map(|(result, _)| result)
     }
 
     fn delta_transition(
@@ -347,6 +492,25 @@ impl ProtocolSim for UniswapV4State {
         Ok(())
     }
 
+    /// The pool is initialized, has liquidity at the current price and charges less than 100% in
+    /// both directions.
+    fn is_tradeable(&self) -> bool {
+        self.liquidity > 0 &&
+            !self.sqrt_price.is_zero() &&
+            self.fees.calculate_swap_fees_pips(true) < 1_000_000 &&
+            self.fees
+                .calculate_swap_fees_pips(false) <
+                1_000_000
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.tokens.clone()
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        fingerprint(self)
+    }
+
     fn clone_box(&self) -> Box<dyn ProtocolSim> {
         Box::new(self.clone())
     }
@@ -368,7 +532,8 @@ impl ProtocolSim for UniswapV4State {
                 self.sqrt_price == other_state.sqrt_price &&
                 self.fees == other_state.fees &&
                 self.tick == other_state.tick &&
-                self.ticks == other_state.ticks
+                self.ticks == other_state.ticks &&
+                self.math_mode == other_state.math_mode
         } else {
             false
         }
@@ -384,12 +549,13 @@ mod tests {
 
     use num_bigint::ToBigUint;
     use num_traits::FromPrimitive;
+    use rstest::rstest;
     use serde_json::Value;
     use tycho_client::feed::synchronizer::ComponentWithState;
     use tycho_core::hex_bytes::Bytes;
 
     use super::*;
-    use crate::protocol::models::TryFromWithBlock;
+    use crate::protocol::models::{GasSource, TryFromWithBlock};
 
     #[test]
     fn test_delta_transition() {
@@ -446,6 +612,199 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fee() {
+        let pool = UniswapV4State::new(
+            1_000_000,
+            get_sqrt_ratio_at_tick(0).unwrap(),
+            UniswapV4Fees::new(100, 200, 3000),
+            0,
+            60,
+            vec![],
+        );
+
+        assert_eq!(pool.fee(), 0.0032);
+    }
+
+    #[rstest]
+    #[case::strict(MathMode::Strict)]
+    #[case::lenient(MathMode::Lenient)]
+    fn test_get_amount_out_overflow(#[case] mode: MathMode) {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000002",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let pool = UniswapV4State::new(
+            u128::MAX,
+            get_sqrt_ratio_at_tick(0).unwrap(),
+            UniswapV4Fees::new(0, 0, 3000),
+            0,
+            60,
+            vec![TickInfo::new(-600, 0), TickInfo::new(600, 0)],
+        )
+        .with_math_mode(mode);
+        // Doesn't fit into an I256
+        let sell_amount = BigUint::from(1u64) << 255;
+
+        let res = pool.get_amount_out(sell_amount, &t0, &t1);
+
+        match mode {
+            MathMode::Strict => assert!(matches!(res, Err(SimulationError::FatalError(_)))),
+            MathMode::Lenient => {
+                assert!(matches!(res, Err(SimulationError::RecoverableError(_))))
+            }
+        }
+    }
+
+    #[test]
+    fn test_fee_adjusted_price() {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000002",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        let pool = UniswapV4State::new(
+            1_000_000,
+            get_sqrt_ratio_at_tick(0).unwrap(),
+            UniswapV4Fees::new(100, 200, 3000),
+            0,
+            60,
+            vec![],
+        );
+
+        let zero_for_one = pool
+            .fee_adjusted_price(&t0, &t1)
+            .unwrap();
+        let one_for_zero = pool
+            .fee_adjusted_price(&t1, &t0)
+            .unwrap();
+
+        approx::assert_relative_eq!(zero_for_one, 1.0 - 0.0031, max_relative = 1e-12);
+        approx::assert_relative_eq!(one_for_zero, 1.0 - 0.0032, max_relative = 1e-12);
+    }
+
+    #[rstest]
+    #[case::within_range(1, 0)]
+    #[case::one_tick(5, 1)]
+    #[case::multiple_ticks(20, 3)]
+    fn test_crossed_ticks(#[case] amount_in: u64, #[case] exp_crossed: u32) {
+        let t0 = Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T0",
+            10_000.to_biguint().unwrap(),
+        );
+        let t1 = Token::new(
+            "0x0000000000000000000000000000000000000002",
+            18,
+            "T1",
+            10_000.to_biguint().unwrap(),
+        );
+        // Liquidity grows with every tick crossed upwards from tick 0, all ticks are in one word
+        let liquidity = 10i128.pow(20);
+        let pool = UniswapV4State::new(
+            liquidity as u128,
+            U256::from(1u64) << 96,
+            UniswapV4Fees::new(0, 0, 3000),
+            0,
+            60,
+            vec![
+                TickInfo::new(-600, liquidity),
+                TickInfo::new(600, liquidity),
+                TickInfo::new(1200, liquidity),
+                TickInfo::new(1800, liquidity),
+                TickInfo::new(2400, -4 * liquidity),
+            ],
+        );
+        let amount_in = BigUint::from(amount_in) * BigUint::from(10u64).pow(18);
+
+        let res = pool
+            .get_amount_out(amount_in, &t1, &t0)
+            .unwrap();
+
+        assert_eq!(res.crossed_ticks, Some(exp_crossed));
+        let pool_gas = res
+            .gas_breakdown
+            .iter()
+            .find(|item| item.source == GasSource::PoolComputation)
+            .unwrap();
+        assert_eq!(pool_gas.gas, u256_to_biguint(GAS_MODEL.gas(exp_crossed, 1)));
+    }
+
+    #[test]
+    fn test_spot_price_decimals() {
+        let usdc = Token::new(
+            "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            6,
+            "USDC",
+            10_000.to_biguint().unwrap(),
+        );
+        let weth = Token::new(
+            "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            18,
+            "WETH",
+            10_000.to_biguint().unwrap(),
+        );
+        // USDC is token 0, a raw price of ~5e8 WETH wei per USDC unit is ~2000 USDC per WETH
+        let tick = 200311;
+        let pool = UniswapV4State::new(
+            1_000_000_000_000_000_000,
+            get_sqrt_ratio_at_tick(tick).unwrap(),
+            UniswapV4Fees::new(0, 0, 3000),
+            tick,
+            60,
+            vec![],
+        );
+
+        let weth_price = pool.spot_price(&weth, &usdc).unwrap();
+        let usdc_price = pool.spot_price(&usdc, &weth).unwrap();
+        let raw_usdc_price = pool
+            .raw_spot_price(&usdc, &weth)
+            .unwrap();
+
+        assert!((weth_price - 2000.0).abs() / 2000.0 < 1e-4);
+        assert!((usdc_price * weth_price - 1.0).abs() < 1e-12);
+        assert!((raw_usdc_price - 1.0001f64.powi(tick)).abs() / raw_usdc_price < 1e-9);
+        assert!((raw_usdc_price - usdc_price * 1e12).abs() / raw_usdc_price < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn test_liquidity_depth() {
+        let data_str = include_str!("assets/sepolia_state_block_7239119.json");
+        let data: Value = serde_json::from_str(data_str).expect("Failed to parse JSON");
+        let state: ComponentWithState = serde_json::from_value(data)
+            .expect("Expected json to match ComponentWithState structure");
+        let usv4_state =
+            UniswapV4State::try_from_with_block(state, Default::default(), &Default::default())
+                .await
+                .unwrap();
+
+        let depth = usv4_state.liquidity_depth();
+
+        assert_eq!(depth, vec![(-887220, 10000010000000), (887220, -10000010000000)]);
+        let active: i128 = depth
+            .iter()
+            .filter(|(tick, _)| *tick <= usv4_state.active_tick())
+            .map(|(_, net_liquidity)| net_liquidity)
+            .sum();
+        assert_eq!(active, usv4_state.liquidity() as i128);
+    }
+
     #[tokio::test]
     /// Compares a quote that we got from the UniswapV4 Quoter contract on Sepolia with a simulation
     /// using Tycho-simulation and a state extracted with Tycho-indexer
@@ -504,4 +863,42 @@ mod tests {
         let expected_amount = BigUint::from(9999909699895_u64);
         assert_eq!(res.amount, expected_amount);
     }
+
+    #[rstest]
+    #[case::empty(vec![], true)]
+    #[case::same_liquidity(vec![("liquidity", 1000_u64.to_be_bytes().to_vec())], true)]
+    #[case::new_liquidity(vec![("liquidity", 2000_u64.to_be_bytes().to_vec())], false)]
+    #[case::new_tick(vec![("tick", 120_i32.to_be_bytes().to_vec())], false)]
+    #[case::new_tick_liquidity(
+        vec![("ticks/180/net_liquidity", (-9000_i128).to_be_bytes().to_vec())],
+        false
+    )]
+    fn test_fingerprint_after_delta(
+        #[case] attributes: Vec<(&str, Vec<u8>)>,
+        #[case] unchanged: bool,
+    ) {
+        let pool = UniswapV4State::new(
+            1000,
+            U256::from(1000),
+            UniswapV4Fees::new(100, 90, 700),
+            100,
+            60,
+            vec![TickInfo::new(120, 10000), TickInfo::new(180, -10000)],
+        );
+        let mut transitioned = pool.clone();
+        let delta = ProtocolStateDelta {
+            component_id: "State1".to_owned(),
+            updated_attributes: attributes
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), Bytes::from(value)))
+                .collect(),
+            deleted_attributes: HashSet::new(),
+        };
+
+        transitioned
+            .delta_transition(delta, &HashMap::new())
+            .unwrap();
+
+        assert_eq!(transitioned.state_fingerprint() == pool.state_fingerprint(), unchanged);
+    }
 }
@@ -120,7 +120,8 @@ impl TryFromWithBlock<ComponentWithState> for UniswapV4State {
 
         ticks.sort_by_key(|tick| tick.index);
 
-        Ok(UniswapV4State::new(liquidity, sqrt_price, fees, tick, tick_spacing, ticks))
+        Ok(UniswapV4State::new(liquidity, sqrt_price, fees, tick, tick_spacing, ticks)
+            .with_tokens(snapshot.component.tokens.clone()))
     }
 }
 
@@ -1,4 +1,4 @@
-pub mod uniswap;
+pub(crate) mod uniswap;
 
 use alloy_primitives::Address;
 use tycho_core::Bytes;
@@ -1,12 +1,19 @@
-use alloy_primitives::{I256, U256};
+use alloy_primitives::{Sign, I256, U256};
+use num_bigint::BigUint;
+use tick_list::TickList;
 use tycho_core::Bytes;
 
-pub mod liquidity_math;
+use crate::{
+    evm::protocol::{safe_math::safe_add_u256, u256_num::u256_to_biguint},
+    protocol::errors::SimulationError,
+};
+
+pub(crate) mod liquidity_math;
 mod solidity_math;
-pub mod sqrt_price_math;
-pub mod swap_math;
-pub mod tick_list;
-pub mod tick_math;
+pub(crate) mod sqrt_price_math;
+pub(crate) mod swap_math;
+pub(crate) mod tick_list;
+pub(crate) mod tick_math;
 
 #[derive(Debug)]
 pub struct SwapState {
@@ -31,10 +38,157 @@ pub struct StepComputation {
 #[derive(Debug)]
 pub struct SwapResults {
     pub amount_calculated: I256,
+    /// Part of the specified amount the swap didn't fill because it reached the price limit
+    pub amount_remaining: I256,
     pub sqrt_price: U256,
     pub liquidity: u128,
     pub tick: i32,
+    /// Gas of the pool's swap logic, see `TickGasModel`
     pub gas_used: U256,
+    /// Number of initialized ticks the swap crossed
+    pub crossed_ticks: u32,
+    /// Number of tick bitmap words searched for initialized ticks
+    pub loaded_words: u32,
+}
+
+/// Gas of a cold storage read (EIP-2929)
+pub const COLD_SLOAD_GAS: u64 = 2_100;
+/// Gas of a write of a cold non-zero slot to another non-zero value (EIP-2929 and EIP-2200)
+pub const COLD_SSTORE_GAS: u64 = COLD_SLOAD_GAS + 2_900;
+/// Gas of the computation of one swap step: the price at the next tick, the step's amounts and
+/// fee, and the update of the swap state
+pub const SWAP_STEP_GAS: u64 = 4_000;
+
+/// Gas model of concentrated liquidity swaps.
+///
+/// The cost of a swap scales with its path through the ticks: every crossed initialized tick
+/// updates the tick's storage, and every tick bitmap word searched for the next initialized tick
+/// is a storage read. The gas is `base + per_crossed_tick * crossings + per_word * loads`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickGasModel {
+    /// Gas of the swap logic independent of the ticks
+    pub base: u64,
+    /// Gas per crossed initialized tick
+    pub per_crossed_tick: u64,
+    /// Gas per searched tick bitmap word
+    pub per_word: u64,
+}
+
+impl TickGasModel {
+    pub fn gas(&self, crossed_ticks: u32, loaded_words: u32) -> U256 {
+        U256::from(self.base) +
+            U256::from(self.per_crossed_tick) * U256::from(crossed_ticks) +
+            U256::from(self.per_word) * U256::from(loaded_words)
+    }
+}
+
+/// Converts a positive amount to an I256, failing for amounts of 2^255 and above.
+pub fn to_i256(amount: U256) -> Result<I256, SimulationError> {
+    I256::checked_from_sign_and_abs(Sign::Positive, amount)
+        .ok_or_else(|| SimulationError::FatalError("I256 arithmetic overflow".to_string()))
+}
+
+/// Returns the sqrt price a swap from `sqrt_price` stops at, the extreme price if `limit` is
+/// `None`.
+///
+/// Errors with `SimulationError::InvalidInput` where the pool contract reverts: if the limit is
+/// out of range or not in the swap direction.
+pub fn resolve_sqrt_price_limit(
+    sqrt_price: U256,
+    zero_for_one: bool,
+    limit: Option<U256>,
+) -> Result<U256, SimulationError> {
+    let limit = limit.unwrap_or(if zero_for_one {
+        tick_math::MIN_SQRT_RATIO + U256::from(1u64)
+    } else {
+        tick_math::MAX_SQRT_RATIO - U256::from(1u64)
+    });
+    let valid = if zero_for_one {
+        limit > tick_math::MIN_SQRT_RATIO && limit < sqrt_price
+    } else {
+        limit < tick_math::MAX_SQRT_RATIO && limit > sqrt_price
+    };
+    if !valid {
+        return Err(SimulationError::InvalidInput(
+            format!("Invalid sqrt price limit {limit} for a swap from {sqrt_price}"),
+            None,
+        ));
+    }
+    Ok(limit)
+}
+
+/// Amounts a swap takes until the price reaches a limit, see
+/// `UniswapV3State::depth_within_price_bounds`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceDepth {
+    /// Amount of the sold token, including fees
+    pub amount_in: BigUint,
+    /// Amount of the bought token
+    pub amount_out: BigUint,
+    /// Whether the limit lies beyond the initialized ticks. The amounts then only cover the
+    /// liquidity up to the last initialized tick.
+    pub partial: bool,
+}
+
+/// Returns the amounts a swap takes to move the sqrt price of a pool to `target`.
+///
+/// Walks the initialized ticks from the current price towards the target and sums the amounts of
+/// each range, without searching for the swap amount.
+pub fn depth_to_sqrt_price(
+    sqrt_price: U256,
+    tick: i32,
+    liquidity: u128,
+    ticks: &TickList,
+    fee_pips: u32,
+    target: U256,
+) -> Result<PriceDepth, SimulationError> {
+    let zero_for_one = target < sqrt_price;
+    // The initialized ticks in swap direction, in the order the swap crosses them
+    let crossings: Vec<_> = if zero_for_one {
+        ticks
+            .ticks()
+            .iter()
+            .rev()
+            .filter(|next| next.index <= tick)
+            .collect()
+    } else {
+        ticks
+            .ticks()
+            .iter()
+            .filter(|next| next.index > tick)
+            .collect()
+    };
+
+    let (mut sqrt_price, mut liquidity) = (sqrt_price, liquidity);
+    let (mut amount_in, mut amount_out) = (U256::ZERO, U256::ZERO);
+    let depth = |amount_in, amount_out, partial| PriceDepth {
+        amount_in: u256_to_biguint(amount_in),
+        amount_out: u256_to_biguint(amount_out),
+        partial,
+    };
+    for next in crossings {
+        let step_target =
+            if zero_for_one { next.sqrt_price.max(target) } else { next.sqrt_price.min(target) };
+        if liquidity > 0 && step_target != sqrt_price {
+            // An unbounded input always reaches the step's target
+            let (_, step_in, step_out, fee) = swap_math::compute_swap_step(
+                sqrt_price,
+                step_target,
+                liquidity,
+                I256::MAX,
+                fee_pips,
+            )?;
+            amount_in = safe_add_u256(amount_in, safe_add_u256(step_in, fee)?)?;
+            amount_out = safe_add_u256(amount_out, step_out)?;
+        }
+        sqrt_price = step_target;
+        if sqrt_price == target {
+            return Ok(depth(amount_in, amount_out, false));
+        }
+        let net_liquidity = if zero_for_one { -next.net_liquidity } else { next.net_liquidity };
+        liquidity = liquidity_math::add_liquidity_delta(liquidity, net_liquidity);
+    }
+    Ok(depth(amount_in, amount_out, sqrt_price != target))
 }
 
 /// Converts a slice of bytes representing a big-endian 24-bit signed integer
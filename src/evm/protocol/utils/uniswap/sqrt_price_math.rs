@@ -1,10 +1,14 @@
 use alloy_primitives::U256;
+use num_bigint::BigUint;
 
 use super::solidity_math::{mul_div, mul_div_rounding_up};
 use crate::{
     evm::protocol::{
         safe_math::{div_mod_u256, safe_add_u256, safe_div_u256, safe_mul_u256, safe_sub_u256},
-        u256_num::u256_to_f64,
+        u256_num::{
+            biguint_to_u256_checked, from_f64_with_decimals, ratio_to_f64, u256_to_biguint,
+            u256_to_f64, Rounding,
+        },
     },
     protocol::errors::SimulationError,
 };
@@ -158,8 +162,22 @@ fn get_next_sqrt_price_from_amount1_rounding_down(
 
 /// Converts a sqrt price in Q96 representation to its approximate f64 representation
 ///
+/// The result is the price of token 0 denominated in token 1, adjusted for the decimals of both
+/// tokens.
+///
 /// # Panics
 /// Will panic if the `x` is bigger than U160.
+///
+/// # Example
+/// ```
+/// use alloy_primitives::U256;
+/// use tycho_simulation::evm::protocol::uniswap_math::sqrt_price_q96_to_f64;
+///
+/// let one = U256::from(1u64) << 96;
+/// assert_eq!(sqrt_price_q96_to_f64(one, 18, 18), 1.0);
+/// // A raw price of 1 between an 18 and a 6 decimals token
+/// assert_eq!(sqrt_price_q96_to_f64(one, 18, 6), 1e12);
+/// ```
 pub fn sqrt_price_q96_to_f64(x: U256, token_0_decimals: u32, token_1_decimals: u32) -> f64 {
     assert!(x < U160_MAX);
     let token_correction = 10f64.powi(token_0_decimals as i32 - token_1_decimals as i32);
@@ -168,11 +186,71 @@ pub fn sqrt_price_q96_to_f64(x: U256, token_0_decimals: u32, token_1_decimals: u
     price.powi(2) * token_correction
 }
 
+/// Converts a sqrt price in Q96 representation to the price of `base` in `quote`
+///
+/// Unlike `sqrt_price_q96_to_f64`, the price is squared and adjusted for decimals on integers and
+/// rounded once, so it's exact to the last bit in both directions: the price of token 1 in token
+/// 0 isn't derived from a rounded price of token 0.
+///
+/// # Example
+/// ```
+/// use alloy_primitives::U256;
+/// use tycho_simulation::evm::protocol::uniswap_math::sqrt_price_q96_to_price;
+///
+/// // A raw price of 4 token 1 per token 0
+/// let two = U256::from(2u64) << 96;
+/// assert_eq!(sqrt_price_q96_to_price(two, 18, 18, true), 4.0);
+/// assert_eq!(sqrt_price_q96_to_price(two, 18, 18, false), 0.25);
+/// // Token 0 has 6 decimals, token 1 has 18
+/// assert_eq!(sqrt_price_q96_to_price(two, 18, 6, false), 0.25e12);
+/// ```
+pub fn sqrt_price_q96_to_price(
+    x: U256,
+    base_decimals: u32,
+    quote_decimals: u32,
+    base_is_token_0: bool,
+) -> f64 {
+    let price_x192 = u256_to_biguint(x).pow(2);
+    let q192 = BigUint::from(1u8) << 192;
+    let (num, den) = if base_is_token_0 { (price_x192, q192) } else { (q192, price_x192) };
+    let ten = BigUint::from(10u8);
+    ratio_to_f64(&(num * ten.pow(base_decimals)), &(den * ten.pow(quote_decimals)))
+}
+
+/// Converts the price of `base` in `quote` into a sqrt price in Q96 representation, the inverse
+/// of `sqrt_price_q96_to_price`.
+///
+/// The result is only as precise as an `f64`. Errors with `SimulationError::InvalidInput` if the
+/// price isn't positive or its sqrt price doesn't fit into 160 bits.
+pub fn price_to_sqrt_price_q96(
+    price: f64,
+    base_decimals: u32,
+    quote_decimals: u32,
+    base_is_token_0: bool,
+) -> Result<U256, SimulationError> {
+    if price.is_nan() || price <= 0.0 {
+        return Err(SimulationError::InvalidInput(format!("Invalid price {price}"), None));
+    }
+    // Raw amount of token 1 per raw amount of token 0
+    let decimals_diff = quote_decimals as i32 - base_decimals as i32;
+    let raw_price = if base_is_token_0 {
+        price * 10f64.powi(decimals_diff)
+    } else {
+        10f64.powi(-decimals_diff) / price
+    };
+    let sqrt_price = from_f64_with_decimals(raw_price.sqrt() * 2f64.powi(96), 0, Rounding::Down)
+        .and_then(|sqrt_price| biguint_to_u256_checked(&sqrt_price))?;
+    if sqrt_price >= U160_MAX {
+        return Err(SimulationError::InvalidInput(format!("Price {price} is out of range"), None));
+    }
+    Ok(sqrt_price)
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use approx::assert_ulps_eq;
+    use approx::{assert_relative_eq, assert_ulps_eq};
     use rstest::rstest;
 
     use super::*;
@@ -347,4 +425,25 @@ mod tests {
 
         assert_ulps_eq!(res, exp, epsilon = f64::EPSILON);
     }
+
+    #[rstest]
+    #[case::usdc_eth(u256("2209221051636112667296733914466103"), 6, 18)]
+    #[case::wbtc_eth(u256("29654479368916176338227069900580738"), 8, 18)]
+    #[case::shib_usdc(u256("231479673319799999440"), 18, 6)]
+    fn test_price_to_sqrt_price_q96(#[case] sqrt_price: U256, #[case] t0d: u32, #[case] t1d: u32) {
+        for base_is_token_0 in [true, false] {
+            let (base_decimals, quote_decimals) =
+                if base_is_token_0 { (t0d, t1d) } else { (t1d, t0d) };
+            let price =
+                sqrt_price_q96_to_price(sqrt_price, base_decimals, quote_decimals, base_is_token_0);
+
+            let res =
+                price_to_sqrt_price_q96(price, base_decimals, quote_decimals, base_is_token_0)
+                    .unwrap();
+
+            assert_relative_eq!(u256_to_f64(res), u256_to_f64(sqrt_price), max_relative = 1e-12);
+        }
+        assert!(price_to_sqrt_price_q96(0.0, t0d, t1d, true).is_err());
+        assert!(price_to_sqrt_price_q96(f64::NAN, t0d, t1d, true).is_err());
+    }
 }
@@ -1,10 +1,11 @@
 use std::cmp;
 
 use alloy_primitives::U256;
+use serde::{Deserialize, Serialize};
 
 use super::tick_math;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TickInfo {
     pub index: i32,
     pub net_liquidity: i128,
@@ -39,7 +40,7 @@ pub enum TickListErrorKind {
     TicksExeeded,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TickList {
     tick_spacing: u16,
     ticks: Vec<TickInfo>,
@@ -56,6 +57,11 @@ impl TickList {
         }
     }
 
+    /// Returns the initialized ticks, ordered by index.
+    pub fn ticks(&self) -> &[TickInfo] {
+        &self.ticks
+    }
+
     // Asserts that all attributes are valid. Checks for:
     // 1. Tick spacing > 0
     // 2. Tick indexes have no rest when divided by tick spacing
@@ -17,6 +17,24 @@ pub const MIN_SQRT_RATIO: U256 = U256::from_limbs([4295128739u64, 0, 0, 0]);
 pub const MAX_SQRT_RATIO: U256 =
     U256::from_limbs([6743328256752651558u64, 17280870778742802505u64, 4294805859u64, 0]);
 
+/// Returns the sqrt price, as a Q64.96 fixed point number, at the given tick.
+///
+/// Matches `TickMath.getSqrtRatioAtTick` of Uniswap V3.
+///
+/// # Panics
+/// Will panic if the absolute value of `tick` is bigger than `MAX_TICK`.
+///
+/// # Example
+/// ```
+/// use alloy_primitives::U256;
+/// use tycho_simulation::evm::protocol::uniswap_math::{
+///     get_sqrt_ratio_at_tick, MIN_SQRT_RATIO, MIN_TICK,
+/// };
+///
+/// // A price of 1 is 2^96 in Q64.96
+/// assert_eq!(get_sqrt_ratio_at_tick(0).unwrap(), U256::from(1u64) << 96);
+/// assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK).unwrap(), MIN_SQRT_RATIO);
+/// ```
 pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256, SimulationError> {
     assert!(tick.abs() <= MAX_TICK);
     let abs_tick = U256::from(tick.unsigned_abs());
@@ -154,6 +172,26 @@ fn most_significant_bit(x: U256) -> usize {
     x.bit_len() - 1
 }
 
+/// Returns the greatest tick whose sqrt price is less than or equal to `sqrt_price`.
+///
+/// Matches `TickMath.getTickAtSqrtRatio` of Uniswap V3.
+///
+/// # Panics
+/// Will panic if `sqrt_price` is not within `MIN_SQRT_RATIO..MAX_SQRT_RATIO`.
+///
+/// # Example
+/// ```
+/// use alloy_primitives::U256;
+/// use tycho_simulation::evm::protocol::uniswap_math::{
+///     get_sqrt_ratio_at_tick, get_tick_at_sqrt_ratio,
+/// };
+///
+/// let sqrt_price = get_sqrt_ratio_at_tick(-1000).unwrap();
+/// assert_eq!(get_tick_at_sqrt_ratio(sqrt_price).unwrap(), -1000);
+/// // Any price between two ticks resolves to the lower one
+/// let in_between = sqrt_price + U256::from(1u64);
+/// assert_eq!(get_tick_at_sqrt_ratio(in_between).unwrap(), -1000);
+/// ```
 pub fn get_tick_at_sqrt_ratio(sqrt_price: U256) -> Result<i32, SimulationError> {
     assert!(sqrt_price >= MIN_SQRT_RATIO && sqrt_price < MAX_SQRT_RATIO);
     let ratio_x128 = sqrt_price << 32;
@@ -88,11 +88,21 @@ where
         amount: U256,
         block: u64,
         overwrites: Option<HashMap<Address, HashMap<U256, U256>>>,
+        gas_limit: Option<u64>,
     ) -> Result<(Trade, HashMap<Address, StateUpdate>), SimulationError> {
         let args = (string_to_bytes32(pair_id)?, sell_token, buy_token, is_buy, amount);
         let selector = "swap(bytes32,address,address,uint8,uint256)";
 
-        let res = self.call(selector, args, block, None, overwrites, None, U256::from(0u64))?;
+        let res = self.call_with_gas_limit(
+            selector,
+            args,
+            block,
+            None,
+            overwrites,
+            None,
+            U256::from(0u64),
+            gas_limit,
+        )?;
 
         let decoded: SwapReturn = SwapReturn::abi_decode(&res.return_value, true).map_err(|_| {
             SimulationError::FatalError(format!(
@@ -0,0 +1,119 @@
+//! Simulation budgets and circuit breaking for VM pools
+//!
+//! A misbehaving adapter, e.g. one stuck in a huge loop, can spend seconds of CPU in every
+//! `get_amount_out`. A `SimulationBudget` bounds the gas and wall-clock time of each simulation,
+//! and a `CircuitBreaker` stops simulating a pool after repeated failures until its state
+//! changes.
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Limits applied to the simulations of a VM pool. All limits are disabled by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulationBudget {
+    /// Gas limit of every simulation, replacing the engine's default and lowering the limit a
+    /// caller passes if needed
+    pub max_gas: Option<u64>,
+    /// Wall-clock limit of every simulation. The EVM is halted once it is exceeded.
+    pub max_duration: Option<Duration>,
+    /// Number of consecutive failed swaps after which the pool stops simulating until the next
+    /// state delta
+    pub max_consecutive_failures: Option<u32>,
+}
+
+/// Counters of a pool's swap simulations, see `EVMPoolState::simulation_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulationStats {
+    /// Failed swaps since the last successful swap or state delta
+    pub consecutive_failures: u32,
+    /// Failed swaps in total, timeouts included
+    pub failures: u64,
+    /// Swaps that failed after exceeding `SimulationBudget::max_duration`
+    pub timeouts: u64,
+    /// Swaps rejected without simulating because the circuit breaker was open
+    pub rejected: u64,
+}
+
+/// Tracks failed swaps of a pool.
+///
+/// The counters are atomics so they can be updated from `&self` simulations, and are shared by
+/// all clones of a pool state.
+#[derive(Debug, Default)]
+pub(super) struct CircuitBreaker {
+    consecutive_failures: AtomicU32,
+    failures: AtomicU64,
+    timeouts: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl CircuitBreaker {
+    /// Whether `max_consecutive_failures` is reached, counting the call as rejected if so.
+    pub(super) fn reject(&self, max_consecutive_failures: Option<u32>) -> bool {
+        let open = max_consecutive_failures.is_some_and(|max| {
+            self.consecutive_failures
+                .load(Ordering::Relaxed) >=
+                max
+        });
+        if open {
+            self.rejected
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        open
+    }
+
+    pub(super) fn record_success(&self) {
+        self.consecutive_failures
+            .store(0, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_failure(&self, timed_out: bool) {
+        self.consecutive_failures
+            .fetch_add(1, Ordering::Relaxed);
+        self.failures
+            .fetch_add(1, Ordering::Relaxed);
+        if timed_out {
+            self.timeouts
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Closes the breaker, e.g. after the pool's state changed. Totals are kept.
+    pub(super) fn reset(&self) {
+        self.record_success();
+    }
+
+    pub(super) fn stats(&self) -> SimulationStats {
+        SimulationStats {
+            consecutive_failures: self
+                .consecutive_failures
+                .load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::default();
+
+        breaker.record_failure(true);
+        breaker.record_failure(false);
+        assert!(!breaker.reject(None));
+        assert!(!breaker.reject(Some(3)));
+        assert!(breaker.reject(Some(2)));
+
+        breaker.reset();
+        assert!(!breaker.reject(Some(2)));
+        assert_eq!(
+            breaker.stats(),
+            SimulationStats { consecutive_failures: 0, failures: 2, timeouts: 1, rejected: 1 }
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use std::str::FromStr;
+
 use alloy_primitives::{Address, U256};
 use lazy_static::lazy_static;
 
+pub use crate::evm::erc20::{is_native_token, NATIVE_TOKEN_SENTINEL};
 use crate::protocol::errors::SimulationError;
 
 lazy_static! {
@@ -11,9 +14,20 @@ lazy_static! {
     pub static ref MAX_BALANCE: U256 = U256::MAX / U256::from(2);
 }
 
+/// Gas added on top of a caller's gas limit when simulating a swap, as the simulated transaction
+/// also pays for the calldata and the adapter's own logic, which are not part of the estimate.
+pub const ADAPTER_GAS_HEADROOM: u64 = 50_000;
 pub const ERC20_BYTECODE: &[u8] = include_bytes!("assets/ERC20.bin");
 pub const BALANCER_V2: &[u8] = include_bytes!("assets/BalancerV2SwapAdapter.evm.runtime");
 pub const CURVE: &[u8] = include_bytes!("assets/CurveSwapAdapter.evm.runtime");
+
+/// The address the adapter of a protocol is deployed at unless overridden: the hex encoded
+/// protocol name, left padded with zeros.
+pub fn default_adapter_address(protocol: &str) -> Address {
+    Address::from_str(&format!("{:0>40}", hex::encode(protocol)))
+        .expect("Can't convert protocol name to address")
+}
+
 pub fn get_adapter_file(protocol: &str) -> Result<&'static [u8], SimulationError> {
     match protocol {
         "balancer_v2" => Ok(BALANCER_V2),
@@ -1,99 +1,38 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::fmt::Debug;
 
 use alloy_primitives::{Address, U256};
 use alloy_sol_types::SolValue;
 use lazy_static::lazy_static;
 use revm::DatabaseRef;
 
-use super::{
-    constants::EXTERNAL_ACCOUNT, tycho_simulation_contract::TychoSimulationContract,
-    utils::get_storage_slot_index_at_key,
-};
+use super::{constants::EXTERNAL_ACCOUNT, tycho_simulation_contract::TychoSimulationContract};
+pub use crate::evm::erc20::{ERC20OverwriteFactory, ERC20Slots, Overwrites};
 use crate::{
     evm::{
         engine_db::{engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader},
+        protocol::u256_num::u256_to_f64,
         simulation::SimulationEngine,
         ContractCompiler, SlotId,
     },
     protocol::errors::SimulationError,
 };
 
-#[derive(Clone, Debug, PartialEq)]
-/// A struct representing ERC20 tokens storage slots.
-pub struct ERC20Slots {
-    // Base slot for the balance map
-    pub balance_map: SlotId,
-    // Base slot for the allowance map
-    pub allowance_map: SlotId,
-}
-
-impl ERC20Slots {
-    pub fn new(balance: SlotId, allowance: SlotId) -> Self {
-        Self { balance_map: balance, allowance_map: allowance }
-    }
-}
-
-pub type Overwrites = HashMap<SlotId, U256>;
-
-pub struct ERC20OverwriteFactory {
-    token_address: Address,
-    overwrites: Overwrites,
-    balance_slot: SlotId,
-    allowance_slot: SlotId,
-    compiler: ContractCompiler,
-}
-
-impl ERC20OverwriteFactory {
-    pub fn new(
-        token_address: Address,
-        token_slots: ERC20Slots,
-        compiler: ContractCompiler,
-    ) -> Self {
-        ERC20OverwriteFactory {
-            token_address,
-            overwrites: HashMap::new(),
-            balance_slot: token_slots.balance_map,
-            allowance_slot: token_slots.allowance_map,
-            compiler,
-        }
-    }
-
-    pub fn set_balance(&mut self, balance: U256, owner: Address) {
-        let storage_index = get_storage_slot_index_at_key(owner, self.balance_slot, self.compiler);
-        self.overwrites
-            .insert(storage_index, balance);
-    }
-
-    pub fn set_allowance(&mut self, allowance: U256, spender: Address, owner: Address) {
-        let owner_slot = get_storage_slot_index_at_key(owner, self.allowance_slot, self.compiler);
-        let storage_index = get_storage_slot_index_at_key(spender, owner_slot, self.compiler);
-        self.overwrites
-            .insert(storage_index, allowance);
-    }
-
-    #[cfg(test)]
-    pub fn set_total_supply(&mut self, supply: U256) {
-        let total_supply_slot = SlotId::from(2);
-        self.overwrites
-            .insert(total_supply_slot, supply);
-    }
-
-    pub fn get_overwrites(&self) -> HashMap<Address, Overwrites> {
-        let mut result = HashMap::new();
-        result.insert(self.token_address, self.overwrites.clone());
-        result
-    }
-}
-
 lazy_static! {
     static ref MARKER_VALUE: U256 = U256::from(3141592653589793238462643383u128);
     static ref SPENDER: Address = Address::from_slice(
         &hex::decode("08d967bb0134F2d07f7cfb6E246680c53927DD30")
             .expect("Invalid string for spender"),
     );
+    static ref TRANSFER_RECIPIENT: Address = Address::from_slice(
+        &hex::decode("3f2a8ec1a46c6d0d1c7e4d5a7c6a1d50b6e7a1f9")
+            .expect("Invalid string for transfer recipient"),
+    );
 }
 type U256Return = U256;
 
+/// Highest base slot probed by `discover_slots`.
+pub const MAX_DISCOVERY_SLOT: u64 = 30;
+
 /// Brute-force detection of storage slots for token balances and allowances.
 ///
 /// This function attempts to determine the storage slots used by a token contract
@@ -226,6 +165,210 @@ where
     ))
 }
 
+/// Deterministic detection of the storage slots of a token's balance and allowance maps.
+///
+/// Writes a sentinel value at the entry of `holder` in each candidate map and checks whether
+/// `balanceOf(holder)` (respectively `allowance(holder, spender)`) returns it. Base slots
+/// `0..=MAX_DISCOVERY_SLOT` are tried in order, each with the layout of the compiler guessed by
+/// `ContractCompiler::detect` first and the other layout second, and the first match is returned.
+/// The allowance map is then searched with the layout of the balance map.
+///
+/// Unlike `brute_force_slots`, the result only depends on the token's code, so it's safe to cache.
+///
+/// # Parameters
+///
+/// * `engine` - The simulation engine, must already have the token contract set up.
+/// * `token_addr` - Address of the token.
+/// * `holder` - Account whose map entries are overwritten. Any account works, but it should not
+///   hold an allowance that could be mistaken for the sentinel.
+/// * `block` - The block header at which the simulation is executed.
+///
+/// # Errors
+///
+/// Returns a `SimulationError::FatalError` if no candidate slot matches for either map, or if any
+/// of the calls fail.
+pub fn discover_slots<D: EngineDatabaseInterface + Clone + Debug>(
+    engine: &SimulationEngine<D>,
+    token_addr: &Address,
+    holder: &Address,
+    block: &BlockHeader,
+) -> Result<(ERC20Slots, ContractCompiler), SimulationError>
+where
+    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
+{
+    let token_contract = TychoSimulationContract::new(*token_addr, engine.clone())?;
+    let compilers = match engine
+        .state
+        .basic_ref(*token_addr)
+        .ok()
+        .flatten()
+        .and_then(|info| info.code)
+        .map(|code| ContractCompiler::detect(&code.original_bytes()))
+    {
+        Some(ContractCompiler::Vyper) => [ContractCompiler::Vyper, ContractCompiler::Solidity],
+        _ => [ContractCompiler::Solidity, ContractCompiler::Vyper],
+    };
+
+    let mut balance = None;
+    'outer: for slot in 0..=MAX_DISCOVERY_SLOT {
+        for compiler in compilers {
+            let mut factory = ERC20OverwriteFactory::new(
+                *token_addr,
+                ERC20Slots::new(SlotId::from(slot), SlotId::from(0)),
+                compiler,
+            );
+            factory.set_balance(*MARKER_VALUE, *holder);
+            let value = call_with_overwrites(
+                &token_contract,
+                "balanceOf(address)",
+                *holder,
+                block,
+                factory,
+            )?;
+            if value == *MARKER_VALUE {
+                balance = Some((SlotId::from(slot), compiler));
+                break 'outer;
+            }
+        }
+    }
+    let (balance_slot, compiler) = balance.ok_or_else(|| {
+        SimulationError::FatalError(format!("Couldn't discover balance slot of token {token_addr}"))
+    })?;
+
+    for slot in 0..=MAX_DISCOVERY_SLOT {
+        let mut factory = ERC20OverwriteFactory::new(
+            *token_addr,
+            ERC20Slots::new(balance_slot, SlotId::from(slot)),
+            compiler,
+        );
+        factory.set_allowance(*MARKER_VALUE, *SPENDER, *holder);
+        let value = call_with_overwrites(
+            &token_contract,
+            "allowance(address,address)",
+            (*holder, *SPENDER),
+            block,
+            factory,
+        )?;
+        if value == *MARKER_VALUE {
+            return Ok((ERC20Slots::new(balance_slot, SlotId::from(slot)), compiler));
+        }
+    }
+    Err(SimulationError::FatalError(format!(
+        "Couldn't discover allowance slot of token {token_addr}"
+    )))
+}
+
+/// Calls a token view function returning a `uint256` with the overwrites of `factory` applied.
+fn call_with_overwrites<D: EngineDatabaseInterface + Clone + Debug>(
+    token_contract: &TychoSimulationContract<D>,
+    signature: &str,
+    args: impl SolValue,
+    block: &BlockHeader,
+    factory: ERC20OverwriteFactory,
+) -> Result<U256, SimulationError>
+where
+    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
+{
+    let res = token_contract
+        .call(
+            signature,
+            args,
+            block.number,
+            Some(block.timestamp),
+            Some(factory.get_overwrites()),
+            Some(*EXTERNAL_ACCOUNT),
+            U256::from(0u64),
+        )?
+        .return_value;
+    U256Return::abi_decode(&res, true).map_err(|e| {
+        SimulationError::FatalError(format!("Failed to decode {signature} return value: {e:?}"))
+    })
+}
+
+/// Measures the tax a token charges on transfers at `block`, see
+/// `utils::token::measure_transfer_tax`.
+///
+/// Funds an override account with `amount` tokens, simulates a `transfer` of the full amount to a
+/// fresh recipient and reads the recipient's balance afterwards. Storage slots of the token are
+/// detected with `brute_force_slots`.
+///
+/// # Parameters
+///
+/// * `engine` - The simulation engine, must already have the token contract set up.
+/// * `token_addr` - Address of the token to measure.
+/// * `amount` - Amount to transfer. Should be large enough for the tax not to round down to zero.
+/// * `block` - The block header at which the simulation is executed.
+///
+/// # Returns
+///
+/// The fraction of `amount` that did not arrive at the recipient, e.g. `0.05` for a token taxing
+/// transfers by 5%. Regular tokens return `0.0`.
+///
+/// # Errors
+///
+/// Returns a `SimulationError` if slot detection or any of the simulations fail, or if `amount` is
+/// zero.
+pub fn measure_transfer_tax_at<D: EngineDatabaseInterface + Clone + Debug>(
+    engine: &SimulationEngine<D>,
+    token_addr: &Address,
+    amount: U256,
+    block: &BlockHeader,
+) -> Result<f64, SimulationError>
+where
+    <D as DatabaseRef>::Error: std::fmt::Debug,
+    <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
+{
+    if amount.is_zero() {
+        return Err(SimulationError::InvalidInput("Amount cannot be zero".to_string(), None));
+    }
+    let (slots, compiler) = brute_force_slots(token_addr, block, engine)?;
+    let token_contract = TychoSimulationContract::new(*token_addr, engine.clone())?;
+
+    let mut overwrite_factory = ERC20OverwriteFactory::new(*token_addr, slots, compiler);
+    overwrite_factory.set_balance(amount, *EXTERNAL_ACCOUNT);
+    let mut overwrites = overwrite_factory.get_overwrites();
+
+    let transfer = token_contract.call(
+        "transfer(address,uint256)",
+        (*TRANSFER_RECIPIENT, amount),
+        block.number,
+        Some(block.timestamp),
+        Some(overwrites.clone()),
+        Some(*EXTERNAL_ACCOUNT),
+        U256::from(0u64),
+    )?;
+
+    // Apply the storage changes of the transfer before reading the recipient's balance
+    for (address, update) in transfer.simulation_result.state_updates {
+        if let Some(storage) = update.storage {
+            overwrites
+                .entry(address)
+                .or_default()
+                .extend(storage);
+        }
+    }
+
+    let res = token_contract
+        .call(
+            "balanceOf(address)",
+            *TRANSFER_RECIPIENT,
+            block.number,
+            Some(block.timestamp),
+            Some(overwrites),
+            Some(*EXTERNAL_ACCOUNT),
+            U256::from(0u64),
+        )?
+        .return_value;
+    let received = U256Return::abi_decode(&res, true).map_err(|e| {
+        SimulationError::FatalError(format!("Failed to decode balanceOf return value: {:?}", e))
+    })?;
+
+    let lost = amount.saturating_sub(received);
+    Ok(u256_to_f64(lost) / u256_to_f64(amount))
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env, str::FromStr, sync::Arc};
@@ -240,74 +383,6 @@ mod tests {
     use super::*;
     use crate::evm::engine_db::simulation_db::SimulationDB;
 
-    fn setup_factory() -> ERC20OverwriteFactory {
-        let token_address: Address = Address::from_slice(
-            &hex::decode("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2")
-                .expect("Invalid token address"),
-        );
-
-        let slots = ERC20Slots::new(SlotId::from(5), SlotId::from(6));
-        ERC20OverwriteFactory::new(token_address, slots, ContractCompiler::Solidity)
-    }
-
-    #[test]
-    fn test_set_balance() {
-        let mut factory = setup_factory();
-        let owner = Address::random();
-        let balance = U256::from(1000);
-
-        factory.set_balance(balance, owner);
-
-        assert_eq!(factory.overwrites.len(), 1);
-        assert!(factory
-            .overwrites
-            .values()
-            .any(|&v| v == balance));
-    }
-
-    #[test]
-    fn test_set_allowance() {
-        let mut factory = setup_factory();
-        let owner = Address::random();
-        let spender = Address::random();
-        let allowance = U256::from(500);
-
-        factory.set_allowance(allowance, spender, owner);
-
-        assert_eq!(factory.overwrites.len(), 1);
-        assert!(factory
-            .overwrites
-            .values()
-            .any(|&v| v == allowance));
-    }
-
-    #[test]
-    fn test_set_total_supply() {
-        let mut factory = setup_factory();
-        let supply = U256::from(1_000_000);
-
-        factory.set_total_supply(supply);
-
-        assert_eq!(factory.overwrites.len(), 1);
-        let total_supply_slot = SlotId::from(2);
-        assert_eq!(factory.overwrites[&total_supply_slot], supply);
-    }
-
-    #[test]
-    fn test_get_overwrites() {
-        let mut factory = setup_factory();
-        let supply = U256::from(1_000_000);
-        factory.set_total_supply(supply);
-
-        let overwrites = factory.get_overwrites();
-
-        assert_eq!(overwrites.len(), 1);
-        assert!(overwrites.contains_key(&factory.token_address));
-        assert_eq!(overwrites[&factory.token_address].len(), 1);
-        let total_supply_slot = SlotId::from(2);
-        assert_eq!(overwrites[&factory.token_address][&total_supply_slot], supply);
-    }
-
     fn new_state() -> SimulationDB<RootProvider<BoxTransport>> {
         dotenv().ok();
         let eth_rpc_url = env::var("ETH_RPC_URL").expect("Missing ETH_RPC_URL in environment");
@@ -373,4 +448,30 @@ mod tests {
         assert_eq!(ERC20Slots::new(U256::from(38), U256::from(39)), slots);
         assert_eq!(ContractCompiler::Vyper, compiler);
     }
+
+    #[test]
+    #[cfg_attr(not(feature = "network_tests"), ignore)]
+    fn test_measure_transfer_tax_regular_token() {
+        let state = new_state();
+
+        let eng = SimulationEngine::new(state, false);
+        let block = BlockHeader {
+            number: 20_000_000,
+            timestamp: NaiveDateTime::parse_from_str("2024-06-01T22:36:47", "%Y-%m-%dT%H:%M:%S")
+                .unwrap()
+                .and_utc()
+                .timestamp() as u64,
+            ..Default::default()
+        };
+
+        let tax = measure_transfer_tax_at(
+            &eng,
+            &Address::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap(),
+            U256::from(1_000_000_000u64),
+            &block,
+        )
+        .unwrap();
+
+        assert_eq!(tax, 0.0);
+    }
 }
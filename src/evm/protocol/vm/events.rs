@@ -0,0 +1,110 @@
+//! Pool events decoded by `EVMPoolState::transition_from_logs`
+//!
+//! Recognized events, by protocol:
+//!  - Balancer V2: the vault's `Swap(bytes32 indexed poolId, address indexed tokenIn, address
+//!    indexed tokenOut, uint256 amountIn, uint256 amountOut)` and `PoolBalanceChanged(bytes32
+//!    indexed poolId, address indexed liquidityProvider, address[] tokens, int256[] deltas,
+//!    uint256[] protocolFeeAmounts)`, for the pool whose id is `poolId`.
+//!  - Uniswap V2 forks: `Sync(uint112 reserve0, uint112 reserve1)`, emitted by the pool itself. The
+//!    reserves are taken in the order of the pool's tokens.
+use alloy_primitives::{keccak256, Address, Log, B256, I256, U256};
+use alloy_sol_types::SolValue;
+use lazy_static::lazy_static;
+
+use crate::protocol::errors::SimulationError;
+
+lazy_static! {
+    static ref BALANCER_V2_SWAP: B256 = keccak256("Swap(bytes32,address,address,uint256,uint256)");
+    static ref BALANCER_V2_POOL_BALANCE_CHANGED: B256 =
+        keccak256("PoolBalanceChanged(bytes32,address,address[],int256[],uint256[])");
+    static ref UNISWAP_V2_SYNC: B256 = keccak256("Sync(uint112,uint112)");
+}
+
+/// How an event changes a pool's balance of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BalanceChange {
+    Set(U256),
+    Increase(U256),
+    Decrease(U256),
+}
+
+/// Decodes the balance changes a log makes to the pool with the given id and tokens.
+///
+/// Returns no changes for logs of other pools or unrecognized events, and an error for
+/// recognized events that can't be decoded.
+pub(super) fn decode_balance_changes(
+    log: &Log,
+    pool_id: &[u8],
+    tokens: &[Address],
+) -> Result<Vec<(Address, BalanceChange)>, SimulationError> {
+    let topics = log.topics();
+    let Some(signature) = topics.first() else {
+        return Ok(Vec::new());
+    };
+    let data = &log.data.data;
+
+    if signature == &*BALANCER_V2_SWAP {
+        if !matches_pool_id(topics, pool_id) || topics.len() < 4 {
+            return Ok(Vec::new());
+        }
+        let (amount_in, amount_out) = <(U256, U256)>::abi_decode_params(data, true)
+            .map_err(|e| decode_error("Balancer Swap", e))?;
+        Ok(vec![
+            (topic_address(&topics[2]), BalanceChange::Increase(amount_in)),
+            (topic_address(&topics[3]), BalanceChange::Decrease(amount_out)),
+        ])
+    } else if signature == &*BALANCER_V2_POOL_BALANCE_CHANGED {
+        if !matches_pool_id(topics, pool_id) {
+            return Ok(Vec::new());
+        }
+        let (event_tokens, deltas, protocol_fees) =
+            <(Vec<Address>, Vec<I256>, Vec<U256>)>::abi_decode_params(data, true)
+                .map_err(|e| decode_error("Balancer PoolBalanceChanged", e))?;
+        if deltas.len() != event_tokens.len() || protocol_fees.len() != event_tokens.len() {
+            return Err(decode_error("Balancer PoolBalanceChanged", "array lengths differ"));
+        }
+        // Protocol fees are paid out of the pool's balance on joins and exits alike
+        let mut changes = Vec::with_capacity(event_tokens.len() * 2);
+        for ((token, delta), fee) in event_tokens
+            .into_iter()
+            .zip(deltas)
+            .zip(protocol_fees)
+        {
+            let change = if delta.is_negative() {
+                BalanceChange::Decrease(delta.unsigned_abs())
+            } else {
+                BalanceChange::Increase(delta.unsigned_abs())
+            };
+            changes.push((token, change));
+            changes.push((token, BalanceChange::Decrease(fee)));
+        }
+        Ok(changes)
+    } else if signature == &*UNISWAP_V2_SYNC {
+        if log.address.as_slice() != pool_id || tokens.len() != 2 {
+            return Ok(Vec::new());
+        }
+        let (reserve0, reserve1) =
+            <(U256, U256)>::abi_decode_params(data, true).map_err(|e| decode_error("Sync", e))?;
+        Ok(vec![
+            (tokens[0], BalanceChange::Set(reserve0)),
+            (tokens[1], BalanceChange::Set(reserve1)),
+        ])
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+fn matches_pool_id(topics: &[B256], pool_id: &[u8]) -> bool {
+    topics
+        .get(1)
+        .is_some_and(|topic| topic.as_slice() == pool_id)
+}
+
+/// An address indexed in a topic, i.e. left-padded to 32 bytes
+fn topic_address(topic: &B256) -> Address {
+    Address::from_slice(&topic[12..])
+}
+
+fn decode_error(event: &str, error: impl std::fmt::Display) -> SimulationError {
+    SimulationError::FatalError(format!("Failed to decode {event} event: {error}"))
+}
@@ -1,9 +1,12 @@
 mod adapter_contract;
+pub mod circuit_breaker;
 pub mod constants;
-mod erc20_token;
+pub mod erc20_token;
+mod events;
 mod models;
 pub mod state;
 pub mod state_builder;
+pub mod token_quality;
 pub mod tycho_decoder;
 mod tycho_simulation_contract;
 pub mod utils;
@@ -1,19 +1,27 @@
 use std::{
     any::Any,
-    collections::{HashMap, HashSet},
-    fmt::Debug,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Debug},
     str::FromStr,
+    sync::{Arc, RwLock},
+    time::Instant,
 };
 
-use alloy_primitives::{Address, U256};
+use alloy_dyn_abi::{DynSolValue, FunctionExt, JsonAbiExt};
+use alloy_json_abi::Function;
+use alloy_primitives::{Address, Log, U256};
+use alloy_sol_types::SolValue;
 use itertools::Itertools;
 use num_bigint::BigUint;
 use revm::DatabaseRef;
 use tycho_core::{dto::ProtocolStateDelta, Bytes};
 
 use super::{
-    constants::{EXTERNAL_ACCOUNT, MAX_BALANCE},
-    erc20_token::{ERC20OverwriteFactory, ERC20Slots, Overwrites},
+    circuit_breaker::{CircuitBreaker, SimulationBudget, SimulationStats},
+    constants::{is_native_token, ADAPTER_GAS_HEADROOM, EXTERNAL_ACCOUNT, MAX_BALANCE},
+    erc20_token::{discover_slots, ERC20OverwriteFactory, ERC20Slots, Overwrites},
+    events::{decode_balance_changes, BalanceChange},
     models::Capability,
     tycho_simulation_contract::TychoSimulationContract,
 };
@@ -23,17 +31,88 @@ use crate::{
             engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
             tycho_db::PreCachedDB,
         },
-        protocol::{u256_num::u256_to_biguint, utils::bytes_to_address},
+        protocol::{
+            u256_num::{biguint_to_u256_checked, u256_to_biguint},
+            utils::bytes_to_address,
+        },
         ContractCompiler, SlotId,
     },
     models::Token,
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
-        state::ProtocolSim,
+        models::{GasItem, GasSource, GetAmountOutResult, ProtocolSimSnapshot, BASE_TX_GAS},
+        state::{fingerprint, ProtocolSim},
     },
 };
 
+/// Spot prices of a pool by token pair.
+///
+/// Pairs affected by a change of the pool are marked dirty and recomputed on the next
+/// `spot_price` call for them, so pairs that did not change are not simulated again.
+#[derive(Debug, Default)]
+struct SpotPriceCache {
+    prices: HashMap<(Address, Address), f64>,
+    /// Pairs whose price is outdated
+    dirty_pairs: HashSet<(Address, Address)>,
+}
+
+#[derive(Debug, Default)]
+struct SpotPrices(RwLock<SpotPriceCache>);
+
+impl SpotPrices {
+    fn new(prices: HashMap<(Address, Address), f64>) -> Self {
+        Self(RwLock::new(SpotPriceCache { prices, dirty_pairs: HashSet::new() }))
+    }
+}
+
+impl Clone for SpotPrices {
+    fn clone(&self) -> Self {
+        let cache = self.0.read().unwrap();
+        Self(RwLock::new(SpotPriceCache {
+            prices: cache.prices.clone(),
+            dirty_pairs: cache.dirty_pairs.clone(),
+        }))
+    }
+}
+
+impl PartialEq for SpotPrices {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.read().unwrap().prices == other.0.read().unwrap().prices
+    }
+}
+
+/// Compact description of an `EVMPoolState` for logs and error messages, see
+/// `EVMPoolState::summary`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolSummary {
+    pub id: String,
+    pub tokens: Vec<Bytes>,
+    /// Balances by token, ordered by address
+    pub balances: BTreeMap<Address, U256>,
+    /// Capabilities, ordered by name
+    pub capabilities: Vec<Capability>,
+    pub block: BlockHeader,
+}
+
+impl fmt::Display for PoolSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at block {}: tokens [{}], balances [{}], capabilities [{}]",
+            self.id,
+            self.block.number,
+            self.tokens.iter().join(", "),
+            self.balances
+                .iter()
+                .map(|(token, balance)| format!("{token}: {balance}"))
+                .join(", "),
+            self.capabilities.iter().join(", ")
+        )
+    }
+}
+
+/// The `Debug` output includes the adapter's engine and bytecode, `Display` only the pool's
+/// `summary`.
 #[derive(Clone, Debug)]
 pub struct EVMPoolState<D: EngineDatabaseInterface + Clone + Debug>
 where
@@ -47,15 +126,30 @@ where
     /// The current block, will be used to set vm context
     block: BlockHeader,
     /// The pool's token balances
+    ///
+    /// These are overwritten into storage for every simulation. Rebasing tokens (e.g. stETH or
+    /// aTokens) change balances without emitting updates, so unless `balance_stale_after_blocks`
+    /// is set or `refresh_balances` is called, spot prices of such pools slowly diverge from
+    /// the on-chain state.
     balances: HashMap<Address, U256>,
+    /// Block number at which `balances` were last read from the chain state
+    balances_block: u64,
+    /// If set, balances are re-read during `delta_transition` once they are at least this many
+    /// blocks old.
+    balance_stale_after_blocks: Option<u64>,
     /// The contract address for where protocol balances are stored (i.e. a vault contract).
     /// If given, balances will be overwritten here instead of on the pool contract during
-    /// simulations
+    /// simulations. Balances of the native token (see `is_native_token`) are set as the account
+    /// balance of this contract.
     balance_owner: Option<Address>,
-    /// Spot prices of the pool by token pair
-    spot_prices: HashMap<(Address, Address), f64>,
+    /// Spot prices of the pool by token pair, recomputed lazily after the pool changed
+    spot_prices: SpotPrices,
+    /// Decimals of the pool's tokens, needed to scale recomputed spot prices
+    token_decimals: HashMap<Address, usize>,
     /// The supported capabilities of this pool
     capabilities: HashSet<Capability>,
+    /// Whether swaps on the pool are currently disabled, tracked through the `paused` attribute
+    paused: bool,
     /// Storage overwrites that will be applied to all simulations. They will be cleared
     /// when ``clear_all_cache`` is called, i.e. usually at each block. Hence, the name.
     block_lasting_overwrites: HashMap<Address, Overwrites>,
@@ -73,6 +167,10 @@ where
     manual_updates: bool,
     /// The adapter contract. This is used to interact with the protocol when running simulations
     adapter_contract: TychoSimulationContract<D>,
+    /// Gas, time and failure limits of the pool's simulations
+    simulation_budget: SimulationBudget,
+    /// Failed swaps of the pool, shared by its clones
+    circuit_breaker: Arc<CircuitBreaker>,
 }
 
 impl<D> EVMPoolState<D>
@@ -99,20 +197,130 @@ where
         token_storage_slots: HashMap<Address, (ERC20Slots, ContractCompiler)>,
         manual_updates: bool,
         adapter_contract: TychoSimulationContract<D>,
+        balance_stale_after_blocks: Option<u64>,
+        paused: bool,
     ) -> Self {
         Self {
             id,
             tokens,
+            balances_block: block.number,
             block,
             balances,
+            balance_stale_after_blocks,
             balance_owner,
-            spot_prices,
+            spot_prices: SpotPrices::new(spot_prices),
+            token_decimals: HashMap::new(),
             capabilities,
+            paused,
             block_lasting_overwrites,
             involved_contracts,
             token_storage_slots,
             manual_updates,
             adapter_contract,
+            simulation_budget: SimulationBudget::default(),
+            circuit_breaker: Arc::default(),
+        }
+    }
+
+    /// Returns the pool with its simulations limited by `budget`.
+    ///
+    /// `max_gas` and `max_duration` apply to all simulations of the pool,
+    /// `max_consecutive_failures` to swaps.
+    pub fn with_simulation_budget(mut self, budget: SimulationBudget) -> Self {
+        self.adapter_contract = self
+            .adapter_contract
+            .with_timeout(budget.max_duration)
+            .with_max_gas(budget.max_gas);
+        self.simulation_budget = budget;
+        self
+    }
+
+    /// The limits the pool's simulations run with
+    pub fn simulation_budget(&self) -> &SimulationBudget {
+        &self.simulation_budget
+    }
+
+    /// Counters of the pool's swaps, failed and rejected ones
+    pub fn simulation_stats(&self) -> SimulationStats {
+        self.circuit_breaker.stats()
+    }
+
+    /// Whether swaps are rejected without simulating, after `max_consecutive_failures` failed
+    /// swaps since the last state delta
+    pub fn is_circuit_open(&self) -> bool {
+        self.simulation_budget
+            .max_consecutive_failures
+            .is_some_and(|max| {
+                self.simulation_stats()
+                    .consecutive_failures >=
+                    max
+            })
+    }
+
+    /// A compact description of the pool, without the engine and bytecode `Debug` prints.
+    pub fn summary(&self) -> PoolSummary {
+        PoolSummary {
+            id: self.id.clone(),
+            tokens: self.tokens.clone(),
+            balances: self
+                .balances
+                .iter()
+                .map(|(token, balance)| (*token, *balance))
+                .collect(),
+            capabilities: self
+                .capabilities
+                .iter()
+                .cloned()
+                .sorted_by_key(|capability| capability.to_string())
+                .collect(),
+            block: self.block,
+        }
+    }
+
+    /// The capabilities the pool's adapter reported
+    pub fn capabilities(&self) -> &HashSet<Capability> {
+        &self.capabilities
+    }
+
+    /// Whether the pool supports the given capability
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// The address of the adapter contract the pool is simulated through
+    pub fn adapter_address(&self) -> Address {
+        self.adapter_contract.address
+    }
+
+    /// Creates a serializable snapshot of the pool's attributes.
+    ///
+    /// `balance_owner`, `paused` and `manual_updates` use the names and encoding of Tycho's
+    /// protocol state. `adapter_address` records the adapter the pool simulated through.
+    pub fn to_snapshot(&self) -> ProtocolSimSnapshot {
+        let mut attributes = HashMap::from([
+            ("paused".to_string(), Bytes::from(vec![self.paused as u8])),
+            ("adapter_address".to_string(), Bytes::from(self.adapter_address().to_vec())),
+        ]);
+        if let Some(balance_owner) = self.balance_owner {
+            attributes.insert("balance_owner".to_string(), Bytes::from(balance_owner.to_vec()));
+        }
+        if self.manual_updates {
+            attributes.insert("manual_updates".to_string(), Bytes::from(vec![1u8]));
+        }
+        let balances = self
+            .balances
+            .iter()
+            .map(|(token, balance)| {
+                (Bytes::from(token.to_vec()), Bytes::from(balance.to_be_bytes_vec()))
+            })
+            .collect();
+
+        ProtocolSimSnapshot::Vm {
+            id: self.id.clone(),
+            block_number: self.block.number,
+            tokens: self.tokens.clone(),
+            balances,
+            attributes,
         }
     }
 
@@ -174,15 +382,104 @@ where
         &mut self,
         tokens: &HashMap<Bytes, Token>,
     ) -> Result<(), SimulationError> {
-        self.ensure_capability(Capability::PriceFunction)?;
-        for [sell_token_address, buy_token_address] in self
+        self.update_token_decimals(tokens);
+        let prices = self.compute_spot_prices(&self.token_decimals, &self.token_pairs()?)?;
+        *self.spot_prices.0.get_mut().unwrap() =
+            SpotPriceCache { prices, dirty_pairs: HashSet::new() };
+        Ok(())
+    }
+
+    /// Recomputes the spot prices of the pairs that changed since they were last computed.
+    ///
+    /// `spot_price` does this lazily, calling this right after applying a block's deltas moves
+    /// the simulations out of the pricing path. Requires the decimals of all the pool's tokens to
+    /// be known from a previous `set_spot_prices` or `delta_transition` call.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError` if the spot prices can't be computed. The pairs stay dirty.
+    pub fn ensure_spot_prices(&mut self) -> Result<(), SimulationError> {
+        let dirty_pairs = self
+            .spot_prices
+            .0
+            .get_mut()
+            .unwrap()
+            .dirty_pairs
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        if dirty_pairs.is_empty() {
+            return Ok(());
+        }
+        let prices = self.compute_spot_prices(&self.token_decimals, &dirty_pairs)?;
+        let cache = self.spot_prices.0.get_mut().unwrap();
+        cache.prices.extend(prices);
+        cache.dirty_pairs.clear();
+        Ok(())
+    }
+
+    /// Whether the spot price of any pair needs to be recomputed before it can be used.
+    pub fn spot_prices_dirty(&self) -> bool {
+        !self
+            .spot_prices
+            .0
+            .read()
+            .unwrap()
+            .dirty_pairs
+            .is_empty()
+    }
+
+    /// Marks the spot prices of the pairs containing any of `touched_tokens` for recomputation,
+    /// or of all pairs if `touched_tokens` is `None`.
+    fn mark_spot_prices_dirty(&mut self, touched_tokens: Option<&HashSet<Address>>) {
+        let Ok(pairs) = self.token_pairs() else {
+            return;
+        };
+        let touched = pairs
+            .into_iter()
+            .filter(|(sell, buy)| match touched_tokens {
+                Some(touched) => touched.contains(sell) || touched.contains(buy),
+                None => true,
+            });
+        self.spot_prices
+            .0
+            .get_mut()
+            .unwrap()
+            .dirty_pairs
+            .extend(touched);
+    }
+
+    /// All ordered pairs of the pool's tokens, as (sell token, buy token)
+    fn token_pairs(&self) -> Result<Vec<(Address, Address)>, SimulationError> {
+        let tokens = self
             .tokens
             .iter()
+            .map(bytes_to_address)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(tokens
+            .into_iter()
             .permutations(2)
-            .map(|p| [p[0], p[1]])
-        {
-            let sell_token_address = bytes_to_address(sell_token_address)?;
-            let buy_token_address = bytes_to_address(buy_token_address)?;
+            .map(|p| (p[0], p[1]))
+            .collect())
+    }
+
+    fn update_token_decimals(&mut self, tokens: &HashMap<Bytes, Token>) {
+        for token in &self.tokens {
+            if let (Some(info), Ok(address)) = (tokens.get(token), bytes_to_address(token)) {
+                self.token_decimals
+                    .insert(address, info.decimals);
+            }
+        }
+    }
+
+    fn compute_spot_prices(
+        &self,
+        decimals: &HashMap<Address, usize>,
+        pairs: &[(Address, Address)],
+    ) -> Result<HashMap<(Address, Address), f64>, SimulationError> {
+        self.ensure_capability(Capability::PriceFunction)?;
+        let mut prices = HashMap::new();
+        for &(sell_token_address, buy_token_address) in pairs {
             let overwrites = Some(self.get_overwrites(
                 vec![sell_token_address, buy_token_address],
                 *MAX_BALANCE / U256::from(100),
@@ -191,7 +488,7 @@ where
                 vec![sell_token_address, buy_token_address],
                 overwrites.clone(),
             )?;
-            let price_result = self.adapter_contract.price(
+            let price_result = self.adapter()?.price(
                 &self.id,
                 sell_token_address,
                 buy_token_address,
@@ -211,26 +508,25 @@ where
                 let unscaled_price = price_result.first().ok_or_else(|| {
                     SimulationError::FatalError("Calculated price array is empty".to_string())
                 })?;
-                let sell_token_decimals = self.get_decimals(tokens, &sell_token_address)?;
-                let buy_token_decimals = self.get_decimals(tokens, &buy_token_address)?;
+                let sell_token_decimals = self.get_decimals(decimals, &sell_token_address)?;
+                let buy_token_decimals = self.get_decimals(decimals, &buy_token_address)?;
                 *unscaled_price * 10f64.powi(sell_token_decimals as i32) /
                     10f64.powi(buy_token_decimals as i32)
             };
 
-            self.spot_prices
-                .insert((sell_token_address, buy_token_address), price);
+            prices.insert((sell_token_address, buy_token_address), price);
         }
-        Ok(())
+        Ok(prices)
     }
 
     fn get_decimals(
         &self,
-        tokens: &HashMap<Bytes, Token>,
+        decimals: &HashMap<Address, usize>,
         sell_token_address: &Address,
     ) -> Result<usize, SimulationError> {
-        tokens
-            .get(&Bytes::from(sell_token_address.as_slice()))
-            .map(|t| t.decimals)
+        decimals
+            .get(sell_token_address)
+            .copied()
             .ok_or_else(|| {
                 SimulationError::FatalError(format!(
                     "Failed to scale spot prices! Pool: {} Token 0x{:x} is not available!",
@@ -260,7 +556,7 @@ where
         tokens: Vec<Address>,
         overwrites: Option<HashMap<Address, HashMap<U256, U256>>>,
     ) -> Result<U256, SimulationError> {
-        let limits = self.adapter_contract.get_limits(
+        let limits = self.adapter()?.get_limits(
             &self.id,
             tokens[0],
             tokens[1],
@@ -271,13 +567,291 @@ where
         Ok(limits?.0)
     }
 
-    fn clear_all_cache(&mut self, tokens: &HashMap<Bytes, Token>) -> Result<(), SimulationError> {
+    /// Applies the component balances of a block, by token address and encoded like the
+    /// snapshot's `balances`. Balances of tokens that aren't the pool's are ignored.
+    ///
+    /// Returns the tokens whose balance was updated.
+    fn update_balances(
+        &mut self,
+        balances: &HashMap<Bytes, Bytes>,
+    ) -> Result<HashSet<Address>, TransitionError<String>> {
+        let mut updated = HashSet::new();
+        for (token, value) in balances {
+            if !self.tokens.contains(token) {
+                continue;
+            }
+            let token = bytes_to_address(token)?;
+            let balance = U256::try_from_be_slice(value).ok_or_else(|| {
+                TransitionError::DecodeError(format!("Invalid balance of token {token}"))
+            })?;
+            self.balances.insert(token, balance);
+            updated.insert(token);
+        }
+        Ok(updated)
+    }
+
+    /// Re-reads the pool's token balances from the engine's current state.
+    ///
+    /// Calls `balanceOf` on each token for the balance owner (or the pool itself) without any
+    /// overwrites and replaces the cached balances. This is needed for rebasing tokens, whose
+    /// balances change without any state update for the pool. Spot prices are not recomputed.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError` if the owner address can't be determined or a `balanceOf` call
+    /// fails.
+    pub fn refresh_balances(&mut self) -> Result<(), SimulationError> {
+        let owner = self.get_balance_owner_address()?;
+        let block_number = self
+            .adapter_contract
+            .engine
+            .state
+            .block_number()
+            .unwrap_or(self.block.number);
+
+        for token in &self.tokens {
+            let token_address = bytes_to_address(token)?;
+            if is_native_token(&token_address) {
+                let balance = self
+                    .adapter_contract
+                    .engine
+                    .state
+                    .basic_ref(owner)
+                    .map_err(|e| {
+                        SimulationError::FatalError(format!(
+                            "Failed to read native balance of 0x{owner:x}: {e:?}"
+                        ))
+                    })?
+                    .map(|info| info.balance)
+                    .unwrap_or_default();
+                self.balances
+                    .insert(token_address, balance);
+                continue;
+            }
+            let token_contract =
+                TychoSimulationContract::new(token_address, self.adapter_contract.engine.clone())?;
+            let res = token_contract
+                .call(
+                    "balanceOf(address)",
+                    owner,
+                    block_number,
+                    Some(self.block.timestamp),
+                    None,
+                    Some(*EXTERNAL_ACCOUNT),
+                    U256::from(0u64),
+                )?
+                .return_value;
+            let balance = U256::abi_decode(&res, true).map_err(|e| {
+                SimulationError::FatalError(format!(
+                    "Failed to decode balanceOf return value: {:?}",
+                    e
+                ))
+            })?;
+            self.balances
+                .insert(token_address, balance);
+        }
+        self.balances_block = block_number;
+        Ok(())
+    }
+
+    /// Applies a block's `delta` like `delta_transition`, given the pool's component `balances`
+    /// the block changed, by token, and the contracts whose storage it changed, without
+    /// rebuilding the pool.
+    ///
+    /// The pool moves to `block`, so later simulations run with its number and timestamp, and the
+    /// overwrites of the previous block are dropped. If the pool is touched by
+    /// `touched_contracts`, see `is_touched_by`, its spot prices are marked for recomputation,
+    /// even without a state delta. Pools with `manual_updates` ignore touched contracts and only
+    /// recompute on an `update_marker`. Capabilities and the adapter contract are kept as they
+    /// are.
+    pub fn transition_with_contracts(
+        &mut self,
+        delta: ProtocolStateDelta,
+        balances: &HashMap<Bytes, Bytes>,
+        touched_contracts: &HashSet<Address>,
+        block: BlockHeader,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> Result<(), TransitionError<String>> {
+        if block.number != self.block.number {
+            self.block_lasting_overwrites.clear();
+        }
+        self.block = block;
+        let contracts_touched = self.is_touched_by(touched_contracts);
+
+        self.apply_delta(delta, balances, tokens)?;
+        if contracts_touched && !self.paused {
+            self.clear_all_cache(tokens);
+        }
+        Ok(())
+    }
+
+    /// Applies the delta and the component `balances` and marks the affected spot prices for
+    /// recomputation.
+    ///
+    /// A block only updating balances marks the pairs with those tokens: the adapter doesn't
+    /// expose the pool's pricing, so a pair's price is taken to only depend on the balances of its
+    /// own tokens, as in weighted pools. Pools whose invariant ties all balances together, e.g.
+    /// stable pools, keep serving the other pairs from the cache until their contracts are
+    /// touched. Any other delta marks all pairs, unless the pool has `manual_updates`, where only
+    /// a truthy `update_marker` does.
+    fn apply_delta(
+        &mut self,
+        delta: ProtocolStateDelta,
+        balances: &HashMap<Bytes, Bytes>,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> Result<(), TransitionError<String>> {
+        // The pool changed, simulations that failed before may succeed now
+        self.circuit_breaker.reset();
+
+        let updated_balances = self.update_balances(balances)?;
+
+        if let Some(paused) = delta.updated_attributes.get("paused") {
+            self.paused = is_truthy(paused);
+        } else if delta
+            .deleted_attributes
+            .contains("paused")
+        {
+            self.paused = false;
+        }
+        if self.paused {
+            // The adapter can't quote a paused pool, so there is nothing to recompute
+            return Ok(());
+        }
+
+        if self.balances_stale() {
+            self.refresh_balances()?;
+            // Spot prices depend on the balances, so they need to be recomputed regardless of
+            // the update rules below.
+            self.clear_all_cache(tokens);
+            return Ok(());
+        }
+
+        if self.manual_updates {
+            // Directly check for "update_marker" in `updated_attributes`
+            if let Some(marker) = delta
+                .updated_attributes
+                .get("update_marker")
+            {
+                if is_truthy(marker) {
+                    self.clear_all_cache(tokens);
+                }
+            }
+        } else if !updated_balances.is_empty() &&
+            delta.updated_attributes.is_empty() &&
+            delta.deleted_attributes.is_empty()
+        {
+            // Only balances changed, which only affects the prices of pairs with those tokens
+            self.clear_cache(tokens, Some(&updated_balances));
+        } else {
+            self.clear_all_cache(tokens);
+        }
+
+        Ok(())
+    }
+
+    /// Whether the storage of any of the pool's involved contracts is in `contracts`, and the pool
+    /// recomputes its prices on such changes, i.e. it has no `manual_updates`.
+    ///
+    /// The `balance_owner` doesn't count: it's shared by many pools, e.g. the Balancer vault is
+    /// touched by every swap through any of its pools, and Tycho reports the changes relevant to
+    /// this pool as component balances.
+    pub fn is_touched_by(&self, contracts: &HashSet<Address>) -> bool {
+        !self.manual_updates &&
+            self.involved_contracts
+                .iter()
+                .any(|contract| {
+                    Some(*contract) != self.balance_owner && contracts.contains(contract)
+                })
+    }
+
+    /// Updates the pool's balances from raw chain logs, for consumers that don't receive Tycho's
+    /// deltas.
+    ///
+    /// Logs of other pools and unrecognized events are skipped, see the `events` module for the
+    /// events recognized per protocol. Like `delta_transition`, a change clears the block's
+    /// overwrites and marks the spot prices of the pairs with a changed balance for recomputation.
+    /// Unlike it, the storage of the involved
+    /// contracts isn't updated: logs only carry the balances, so pools that price from storage
+    /// the events don't cover also need their accounts updated in the engine's database.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TransitionError::DecodeError` if a recognized event can't be decoded or
+    /// decreases a balance below zero. The pool is left unchanged in that case.
+    pub fn transition_from_logs(&mut self, logs: &[Log]) -> Result<(), TransitionError<String>> {
+        let pool_id = Bytes::from_str(&self.id)
+            .map_err(|_| TransitionError::DecodeError(format!("Invalid pool id {}", self.id)))?;
+        let tokens = self
+            .tokens
+            .iter()
+            .map(bytes_to_address)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut balances = self.balances.clone();
+        let mut changed_tokens = HashSet::new();
+        for log in logs {
+            let changes = decode_balance_changes(log, &pool_id, &tokens)
+                .map_err(|e| TransitionError::DecodeError(e.to_string()))?;
+            for (token, change) in changes {
+                let balance = balances.entry(token).or_default();
+                *balance = match change {
+                    BalanceChange::Set(value) => value,
+                    BalanceChange::Increase(value) => balance.saturating_add(value),
+                    BalanceChange::Decrease(value) => {
+                        balance
+                            .checked_sub(value)
+                            .ok_or_else(|| {
+                                TransitionError::DecodeError(format!(
+                                    "Log decreases balance of {token} below zero"
+                                ))
+                            })?
+                    }
+                };
+                changed_tokens.insert(token);
+            }
+        }
+
+        if !changed_tokens.is_empty() {
+            self.balances = balances;
+            self.clear_cache(&HashMap::new(), Some(&changed_tokens));
+            self.circuit_breaker.reset();
+        }
+        Ok(())
+    }
+
+    /// Whether the cached balances are older than `balance_stale_after_blocks`.
+    fn balances_stale(&self) -> bool {
+        match (
+            self.balance_stale_after_blocks,
+            self.adapter_contract
+                .engine
+                .state
+                .block_number(),
+        ) {
+            (Some(max_age), Some(current)) => {
+                current.saturating_sub(self.balances_block) >= max_age
+            }
+            _ => false,
+        }
+    }
+
+    fn clear_all_cache(&mut self, tokens: &HashMap<Bytes, Token>) {
+        self.clear_cache(tokens, None);
+    }
+
+    /// Clears the block's overwrites and marks the spot prices of the pairs containing any of
+    /// `touched_tokens` for recomputation, see `mark_spot_prices_dirty`.
+    fn clear_cache(
+        &mut self,
+        tokens: &HashMap<Bytes, Token>,
+        touched_tokens: Option<&HashSet<Address>>,
+    ) {
         self.adapter_contract
             .engine
             .clear_temp_storage();
         self.block_lasting_overwrites.clear();
-        self.set_spot_prices(tokens)?;
-        Ok(())
+        self.update_token_decimals(tokens);
+        self.mark_spot_prices_dirty(touched_tokens);
     }
 
     fn get_overwrites(
@@ -294,6 +868,19 @@ where
         Ok(merged_overwrites)
     }
 
+    /// Overwrites for calls that don't trade: the pool's balances and the block lasting
+    /// overwrites.
+    fn get_view_overwrites(&self) -> Result<HashMap<Address, Overwrites>, SimulationError> {
+        if self
+            .capabilities
+            .contains(&Capability::TokenBalanceIndependent)
+        {
+            return Ok(self.block_lasting_overwrites.clone());
+        }
+        let balance_overwrites = self.get_balance_overwrites(self.tokens.clone())?;
+        Ok(self.merge(&self.block_lasting_overwrites, &balance_overwrites))
+    }
+
     fn get_token_overwrites(
         &self,
         tokens: Vec<Address>,
@@ -308,23 +895,26 @@ where
             res.push(self.get_balance_overwrites(self.tokens.clone())?);
         }
 
-        let (slots, compiler) = self
-            .token_storage_slots
-            .get(sell_token)
-            .cloned()
-            .unwrap_or((
-                ERC20Slots::new(SlotId::from(0), SlotId::from(1)),
-                ContractCompiler::Solidity,
-            ));
+        // A native sell token is funded as account balance by `get_native_balance_overrides`
+        if !is_native_token(sell_token) {
+            let (slots, compiler) = self
+                .token_storage_slots
+                .get(sell_token)
+                .cloned()
+                .unwrap_or((
+                    ERC20Slots::new(SlotId::from(0), SlotId::from(1)),
+                    ContractCompiler::Solidity,
+                ));
 
-        let mut overwrites = ERC20OverwriteFactory::new(*sell_token, slots.clone(), compiler);
+            let mut overwrites = ERC20OverwriteFactory::new(*sell_token, slots.clone(), compiler);
 
-        overwrites.set_balance(max_amount, Address::from_slice(&*EXTERNAL_ACCOUNT.0));
+            overwrites.set_balance(max_amount, Address::from_slice(&*EXTERNAL_ACCOUNT.0));
 
-        // Set allowance for adapter_address to max_amount
-        overwrites.set_allowance(max_amount, self.adapter_contract.address, *EXTERNAL_ACCOUNT);
+            // Set allowance for adapter_address to max_amount
+            overwrites.set_allowance(max_amount, self.adapter_contract.address, *EXTERNAL_ACCOUNT);
 
-        res.push(overwrites.get_overwrites());
+            res.push(overwrites.get_overwrites());
+        }
 
         // Merge all overwrites into a single HashMap
         Ok(res
@@ -337,17 +927,14 @@ where
         tokens: Vec<Bytes>,
     ) -> Result<HashMap<Address, Overwrites>, SimulationError> {
         let mut balance_overwrites: HashMap<Address, Overwrites> = HashMap::new();
-        let address = match self.balance_owner {
-            Some(address) => Ok(address),
-            None => self.id.parse().map_err(|_| {
-                SimulationError::FatalError(
-                    "Failed to get balance overwrites: Pool ID is not an address".into(),
-                )
-            }),
-        }?;
+        let address = self.get_balance_owner_address()?;
 
         for token in &tokens {
             let token_address = bytes_to_address(token)?;
+            if is_native_token(&token_address) {
+                // Set as account balance by `get_native_balance_overrides`
+                continue;
+            }
             let (slots, compiler) = if self
                 .involved_contracts
                 .contains(&token_address)
@@ -386,6 +973,158 @@ where
         Ok(balance_overwrites)
     }
 
+    /// Returns the storage slots of `token`, discovering them with `discover_slots` if unknown.
+    ///
+    /// Discovered slots are cached in the pool's token storage slots, so later simulations
+    /// overwrite balances and allowances of the token at the right location.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError` if the slots can't be discovered.
+    pub fn discover_token_slots(
+        &mut self,
+        token: Address,
+    ) -> Result<(ERC20Slots, ContractCompiler), SimulationError> {
+        if let Some(slots) = self.token_storage_slots.get(&token) {
+            return Ok(slots.clone());
+        }
+        let slots =
+            discover_slots(&self.adapter_contract.engine, &token, &EXTERNAL_ACCOUNT, &self.block)?;
+        self.token_storage_slots
+            .insert(token, slots.clone());
+        Ok(slots)
+    }
+
+    /// Executes a read-only call on the pool's adapter contract and decodes its return values.
+    ///
+    /// Useful to query protocol specific quantities the adapter exposes as view functions.
+    /// `signature` is a human-readable function signature including its outputs, e.g.
+    /// `"getLimits(bytes32,address,address) returns (uint256[])"`.
+    ///
+    /// The call runs on the pool's engine, with the pool's balances and block lasting overwrites
+    /// applied, at `block` or the pool's current block if not given. Any state changes made by
+    /// the call are discarded: neither the pool nor its engine are mutated.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::InvalidInput` if the signature can't be parsed or `args` don't
+    /// match it, and a `SimulationError::FatalError` if the return data can't be decoded.
+    pub fn call_adapter(
+        &self,
+        signature: &str,
+        args: Vec<DynSolValue>,
+        block: Option<BlockHeader>,
+    ) -> Result<Vec<DynSolValue>, SimulationError> {
+        let function = Function::parse(signature).map_err(|e| {
+            SimulationError::InvalidInput(
+                format!("Invalid function signature {signature}: {e}"),
+                None,
+            )
+        })?;
+        let call_data = function
+            .abi_encode_input(&args)
+            .map_err(|e| {
+                SimulationError::InvalidInput(
+                    format!("Invalid arguments for {signature}: {e}"),
+                    None,
+                )
+            })?;
+        let block = block.unwrap_or(self.block);
+
+        let res = self.adapter()?.call_with_data(
+            call_data,
+            block.number,
+            Some(block.timestamp),
+            Some(self.get_view_overwrites()?),
+            None,
+            U256::from(0u64),
+            None,
+        )?;
+
+        function
+            .abi_decode_output(&res.return_value, true)
+            .map_err(|e| {
+                SimulationError::FatalError(format!(
+                    "Failed to decode return value of {signature}: {e}"
+                ))
+            })
+    }
+
+    /// Native balances to apply to simulations: the pool's balance of the native token, set on the
+    /// balance owner, and `MAX_BALANCE / 100` on the caller if it sells the native token. Empty
+    /// if neither the pool nor the caller hold native token.
+    fn get_native_balance_overrides(
+        &self,
+        sell_token: Option<&Address>,
+    ) -> Result<HashMap<Address, U256>, SimulationError> {
+        let mut overrides = HashMap::new();
+        if sell_token.is_some_and(is_native_token) {
+            overrides.insert(*EXTERNAL_ACCOUNT, *MAX_BALANCE / U256::from(100));
+        }
+        if self
+            .capabilities
+            .contains(&Capability::TokenBalanceIndependent)
+        {
+            return Ok(overrides);
+        }
+        for token in &self.tokens {
+            let token_address = bytes_to_address(token)?;
+            if !is_native_token(&token_address) {
+                continue;
+            }
+            let balance = self
+                .balances
+                .get(&token_address)
+                .cloned()
+                .ok_or_else(|| {
+                    SimulationError::InvalidInput(
+                        format!(
+                            "Failed to get balance overrides: Token balance not found for {}",
+                            token
+                        ),
+                        None,
+                    )
+                })?;
+            overrides.insert(self.get_balance_owner_address()?, balance);
+        }
+        Ok(overrides)
+    }
+
+    /// The adapter contract to simulate on, funded with the pool's native balance if it holds
+    /// the native token.
+    fn adapter(&self) -> Result<Cow<'_, TychoSimulationContract<D>>, SimulationError> {
+        self.adapter_selling(None)
+    }
+
+    /// The adapter contract to simulate a swap selling `sell_token` on, like `adapter`, with the
+    /// caller funded if it sells the native token.
+    fn adapter_selling(
+        &self,
+        sell_token: Option<&Address>,
+    ) -> Result<Cow<'_, TychoSimulationContract<D>>, SimulationError> {
+        let balance_overrides = self.get_native_balance_overrides(sell_token)?;
+        if balance_overrides.is_empty() {
+            return Ok(Cow::Borrowed(&self.adapter_contract));
+        }
+        Ok(Cow::Owned(
+            self.adapter_contract
+                .clone()
+                .with_balance_overrides(balance_overrides),
+        ))
+    }
+
+    /// The address holding the pool's balances: the balance owner if set, the pool otherwise.
+    fn get_balance_owner_address(&self) -> Result<Address, SimulationError> {
+        match self.balance_owner {
+            Some(address) => Ok(address),
+            None => self.id.parse().map_err(|_| {
+                SimulationError::FatalError(
+                    "Failed to get balance overwrites: Pool ID is not an address".into(),
+                )
+            }),
+        }
+    }
+
     fn merge(
         &self,
         target: &HashMap<Address, Overwrites>,
@@ -403,56 +1142,63 @@ where
         merged
     }
 
-    #[cfg(test)]
-    pub fn get_involved_contracts(&self) -> HashSet<Address> {
-        self.involved_contracts.clone()
-    }
-
-    #[cfg(test)]
-    pub fn get_manual_updates(&self) -> bool {
-        self.manual_updates
-    }
-
-    #[cfg(test)]
-    pub fn get_balance_owner(&self) -> Option<Address> {
-        self.balance_owner
-    }
-}
-
-impl<D> ProtocolSim for EVMPoolState<D>
-where
-    D: EngineDatabaseInterface + Clone + Debug + 'static,
-    <D as DatabaseRef>::Error: Debug,
-    <D as EngineDatabaseInterface>::Error: Debug,
-{
-    fn fee(&self) -> f64 {
-        todo!()
-    }
+    /// Simulates a swap like `simulate_swap`, within the pool's `SimulationBudget`.
+    ///
+    /// Failures count towards the circuit breaker, except for amounts beyond the sell limit,
+    /// which the pool did quote. Once it is open, swaps fail fast with a
+    /// `SimulationError::RecoverableError` until the next `delta_transition`.
+    fn guarded_swap(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        gas_limit: Option<u64>,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let budget = &self.simulation_budget;
+        if self
+            .circuit_breaker
+            .reject(budget.max_consecutive_failures)
+        {
+            return Err(SimulationError::RecoverableError(format!(
+                "Circuit breaker open for pool {}: {} consecutive failed simulations",
+                self.id,
+                self.simulation_stats()
+                    .consecutive_failures
+            )));
+        }
 
-    fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
-        let base_address = bytes_to_address(&base.address)?;
-        let quote_address = bytes_to_address(&quote.address)?;
-        self.spot_prices
-            .get(&(base_address, quote_address))
-            .cloned()
-            .ok_or(SimulationError::FatalError(format!(
-                "Spot price not found for base token {} and quote token {}",
-                base_address, quote_address
-            )))
+        let start = Instant::now();
+        let result = self.simulate_swap(amount_in, token_in, token_out, gas_limit);
+        match &result {
+            Ok(_) | Err(SimulationError::InvalidInput(_, Some(_))) => {
+                self.circuit_breaker.record_success()
+            }
+            Err(_) => {
+                let timed_out = budget
+                    .max_duration
+                    .is_some_and(|max| start.elapsed() >= max);
+                self.circuit_breaker
+                    .record_failure(timed_out)
+            }
+        }
+        result
     }
 
-    fn get_amount_out(
+    /// Simulates a swap through the adapter, with the engine's default gas limit unless
+    /// `gas_limit` is given.
+    fn simulate_swap(
         &self,
         amount_in: BigUint,
         token_in: &Token,
         token_out: &Token,
+        gas_limit: Option<u64>,
     ) -> Result<GetAmountOutResult, SimulationError> {
         let sell_token_address = bytes_to_address(&token_in.address)?;
         let buy_token_address = bytes_to_address(&token_out.address)?;
-        let sell_amount = U256::from_be_slice(&amount_in.to_bytes_be());
+        let sell_amount = biguint_to_u256_checked(&amount_in)?;
         let overwrites = self.get_overwrites(
             vec![sell_token_address, buy_token_address],
-            U256::from_be_slice(&(*MAX_BALANCE / U256::from(100)).to_be_bytes::<32>()),
+            *MAX_BALANCE / U256::from(100),
         )?;
         let sell_amount_limit = self.get_sell_amount_limit(
             vec![sell_token_address, buy_token_address],
@@ -472,20 +1218,35 @@ where
             self.get_overwrites(vec![sell_token_address, buy_token_address], sell_amount_limit)?;
         let complete_overwrites = self.merge(&overwrites, &overwrites_with_sell_limit);
 
-        let (trade, state_changes) = self.adapter_contract.swap(
-            &self.id,
-            sell_token_address,
-            buy_token_address,
-            false,
-            sell_amount_respecting_limit,
-            self.block.number,
-            Some(complete_overwrites),
-        )?;
+        let (trade, state_changes) = self
+            .adapter_selling(Some(&sell_token_address))?
+            .swap(
+                &self.id,
+                sell_token_address,
+                buy_token_address,
+                false,
+                sell_amount_respecting_limit,
+                self.block.number,
+                Some(complete_overwrites),
+                gas_limit,
+            )?;
 
         let mut new_state = self.clone();
 
         // Apply state changes to the new state
+        let balance_owner = self.get_balance_owner_address().ok();
         for (address, state_update) in state_changes {
+            if let (Some(balance), true) = (state_update.balance, Some(address) == balance_owner) {
+                // The swap moved native tokens in or out of the pool
+                for token in &self.tokens {
+                    let token_address = bytes_to_address(token)?;
+                    if is_native_token(&token_address) {
+                        new_state
+                            .balances
+                            .insert(token_address, balance);
+                    }
+                }
+            }
             if let Some(storage) = state_update.storage {
                 let block_overwrites = new_state
                     .block_lasting_overwrites
@@ -506,54 +1267,231 @@ where
         // Update spot prices
         let new_price = trade.price;
         if new_price != 0.0f64 {
-            new_state
-                .spot_prices
-                .insert((sell_token_address, buy_token_address), new_price);
-            new_state
+            let cache = new_state
                 .spot_prices
-                .insert((buy_token_address, sell_token_address), 1.0f64 / new_price);
+                .0
+                .get_mut()
+                .unwrap();
+            for (pair, price) in [
+                ((sell_token_address, buy_token_address), new_price),
+                ((buy_token_address, sell_token_address), 1.0f64 / new_price),
+            ] {
+                cache.prices.insert(pair, price);
+                cache.dirty_pairs.remove(&pair);
+            }
         }
 
         let buy_amount = trade.received_amount;
+        // The adapter measures the pool's swap including the token transfers. The measurement
+        // also covers the adapter pulling the sell token from the caller, which a swap through a
+        // router doesn't pay, but no trace of the adapters has been measured to calibrate that
+        // part, so the measured gas is reported as is.
+        let gas_breakdown = vec![
+            GasItem::new(GasSource::BaseTx, BASE_TX_GAS),
+            GasItem::new(GasSource::PoolComputation, u256_to_biguint(trade.gas_used)),
+        ];
 
         if sell_amount_exceeds_limit {
             return Err(SimulationError::InvalidInput(
                 format!("Sell amount exceeds limit {}", sell_amount_limit),
-                Some(GetAmountOutResult::new(
+                Some(GetAmountOutResult::with_gas_breakdown(
                     u256_to_biguint(buy_amount),
-                    u256_to_biguint(trade.gas_used),
+                    gas_breakdown.clone(),
                     Box::new(new_state.clone()),
                 )),
             ));
         }
-        Ok(GetAmountOutResult::new(
+        Ok(GetAmountOutResult::with_gas_breakdown(
             u256_to_biguint(buy_amount),
-            u256_to_biguint(trade.gas_used),
+            gas_breakdown,
             Box::new(new_state.clone()),
         ))
     }
 
-    fn delta_transition(
-        &mut self,
-        delta: ProtocolStateDelta,
-        tokens: &HashMap<Bytes, Token>,
-    ) -> Result<(), TransitionError<String>> {
-        if self.manual_updates {
-            // Directly check for "update_marker" in `updated_attributes`
-            if let Some(marker) = delta
-                .updated_attributes
-                .get("update_marker")
-            {
-                // Assuming `marker` is of type `Bytes`, check its value for "truthiness"
-                if !marker.is_empty() && marker[0] != 0 {
-                    self.clear_all_cache(tokens)?;
-                }
-            }
-        } else {
-            self.clear_all_cache(tokens)?;
-        }
+    #[cfg(test)]
+    pub fn get_involved_contracts(&self) -> HashSet<Address> {
+        self.involved_contracts.clone()
+    }
+
+    #[cfg(test)]
+    pub fn get_manual_updates(&self) -> bool {
+        self.manual_updates
+    }
+
+    #[cfg(test)]
+    pub fn get_balance_owner(&self) -> Option<Address> {
+        self.balance_owner
+    }
+
+    #[cfg(test)]
+    pub fn get_balances(&self) -> &HashMap<Address, U256> {
+        &self.balances
+    }
+}
+
+impl<D> fmt::Display for EVMPoolState<D>
+where
+    D: EngineDatabaseInterface + Clone + Debug + 'static,
+    <D as DatabaseRef>::Error: Debug,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
+impl<D> ProtocolSim for EVMPoolState<D>
+where
+    D: EngineDatabaseInterface + Clone + Debug + 'static,
+    <D as DatabaseRef>::Error: Debug,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    /// Adapters include the pool's fee in the prices and amounts they return, there is no
+    /// separate fee to apply on top of them.
+    fn fee(&self) -> f64 {
+        0.0
+    }
+
+    fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        let base_address = bytes_to_address(&base.address)?;
+        let quote_address = bytes_to_address(&quote.address)?;
+        let pair = (base_address, quote_address);
+        if self
+            .spot_prices
+            .0
+            .read()
+            .unwrap()
+            .dirty_pairs
+            .contains(&pair)
+        {
+            let mut decimals = self.token_decimals.clone();
+            decimals.insert(base_address, base.decimals);
+            decimals.insert(quote_address, quote.decimals);
+            let prices = self.compute_spot_prices(&decimals, &[pair])?;
+            let mut cache = self.spot_prices.0.write().unwrap();
+            cache.prices.extend(prices);
+            cache.dirty_pairs.remove(&pair);
+        }
+        self.spot_prices
+            .0
+            .read()
+            .unwrap()
+            .prices
+            .get(&pair)
+            .cloned()
+            .ok_or(SimulationError::FatalError(format!(
+                "Spot price not found for base token {} and quote token {}",
+                base_address, quote_address
+            )))
+    }
+
+    fn get_amount_out(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        self.guarded_swap(amount_in, token_in, token_out, None)
+    }
+
+    fn get_amount_out_with_gas_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        gas_limit: u64,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        // Abort simulations that can't fit the limit early, the estimate is checked afterwards
+        let simulation_gas_limit = gas_limit.saturating_add(ADAPTER_GAS_HEADROOM);
+        self.guarded_swap(amount_in, token_in, token_out, Some(simulation_gas_limit))?
+            .ensure_gas_limit(gas_limit)
+    }
+
+    /// The sell amount limit the adapter reports, if the pool has `HardLimits`.
+    fn get_limit(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<Option<BigUint>, SimulationError> {
+        if !self
+            .capabilities
+            .contains(&Capability::HardLimits)
+        {
+            return Ok(None);
+        }
+        let tokens =
+            vec![bytes_to_address(&token_in.address)?, bytes_to_address(&token_out.address)?];
+        let overwrites = self.get_overwrites(tokens.clone(), *MAX_BALANCE / U256::from(100))?;
+        let limit = self.get_sell_amount_limit(tokens, Some(overwrites))?;
+        Ok(Some(u256_to_biguint(limit)))
+    }
+
+    /// Applies the delta and marks the spot prices for recomputation, see
+    /// `transition_with_contracts` to also apply balance updates.
+    fn delta_transition(
+        &mut self,
+        delta: ProtocolStateDelta,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> Result<(), TransitionError<String>> {
+        self.apply_delta(delta, &HashMap::new(), tokens)
+    }
+
+    fn is_active(&self) -> bool {
+        !self.paused &&
+            (self
+                .capabilities
+                .contains(&Capability::SellSide) ||
+                self.capabilities
+                    .contains(&Capability::BuySide))
+    }
+
+    /// The pool is active and, if its balances are known, holds some tokens.
+    fn is_tradeable(&self) -> bool {
+        self.is_active() &&
+            (self.balances.is_empty() ||
+                self.balances
+                    .values()
+                    .any(|balance| !balance.is_zero()))
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.tokens.clone()
+    }
 
-        Ok(())
+    fn capabilities(&self) -> &HashSet<Capability> {
+        EVMPoolState::capabilities(self)
+    }
+
+    /// Covers the pool's own fields and the block of the engine's database. The storage of the
+    /// involved contracts lives in that database, so states of different blocks never share a
+    /// fingerprint, even if the pool itself didn't change.
+    fn state_fingerprint(&self) -> u64 {
+        let balances = self
+            .balances
+            .iter()
+            .sorted()
+            .collect_vec();
+        let overwrites = self
+            .block_lasting_overwrites
+            .iter()
+            .map(|(address, slots)| (address, slots.iter().sorted().collect_vec()))
+            .sorted()
+            .collect_vec();
+        fingerprint(&(
+            &self.id,
+            &self.tokens,
+            self.block.number,
+            self.block.hash,
+            self.adapter_contract
+                .engine
+                .state
+                .block_number(),
+            self.adapter_contract.address,
+            balances,
+            self.balance_owner,
+            overwrites,
+            self.paused,
+        ))
     }
 
     fn clone_box(&self) -> Box<dyn ProtocolSim> {
@@ -580,6 +1518,11 @@ where
     }
 }
 
+/// Interprets a boolean attribute as sent by tycho, where any non-zero first byte means `true`.
+pub(super) fn is_truthy(value: &Bytes) -> bool {
+    !value.is_empty() && value[0] != 0
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
@@ -599,7 +1542,11 @@ mod tests {
     };
     use crate::evm::{
         engine_db::{create_engine, SHARED_TYCHO_DB},
-        protocol::vm::constants::BALANCER_V2,
+        protocol::vm::{
+            constants::{BALANCER_V2, NATIVE_TOKEN_SENTINEL},
+            utils::string_to_bytes32,
+        },
+        recorder::SimulationRecorder,
         simulation::SimulationEngine,
         tycho_models::AccountUpdate,
     };
@@ -642,11 +1589,14 @@ mod tests {
 
         let block = BlockHeader {
             number: 20463609,
-            hash: B256::from_str(
-                "0x4315fd1afc25cc2ebc72029c543293f9fd833eeb305e2e30159459c827733b1b",
-            )
-            .unwrap(),
+            hash: Some(
+                B256::from_str(
+                    "0x4315fd1afc25cc2ebc72029c543293f9fd833eeb305e2e30159459c827733b1b",
+                )
+                .unwrap(),
+            ),
             timestamp: 1722875891,
+            ..Default::default()
         };
 
         for account in accounts.clone() {
@@ -670,11 +1620,14 @@ mod tests {
         let tokens = vec![dai().address, bal().address];
         let block = BlockHeader {
             number: 18485417,
-            hash: B256::from_str(
-                "0x28d41d40f2ac275a4f5f621a636b9016b527d11d37d610a45ac3a821346ebf8c",
-            )
-            .expect("Invalid block hash"),
+            hash: Some(
+                B256::from_str(
+                    "0x28d41d40f2ac275a4f5f621a636b9016b527d11d37d610a45ac3a821346ebf8c",
+                )
+                .expect("Invalid block hash"),
+            ),
             timestamp: 0,
+            ..Default::default()
         };
 
         let pool_id: String =
@@ -740,6 +1693,10 @@ mod tests {
             .clone()
             .ensure_capability(Capability::MarginalPrice)
             .is_err());
+        assert!(pool_state.supports(Capability::PriceFunction));
+        assert!(!pool_state.supports(Capability::MarginalPrice));
+        let protocol_sim: &dyn ProtocolSim = &pool_state;
+        assert_eq!(protocol_sim.capabilities(), &expected_capabilities);
 
         // Verify all tokens are initialized in the engine
         let engine_accounts = pool_state
@@ -781,7 +1738,14 @@ mod tests {
             .downcast_ref::<EVMPoolState<PreCachedDB>>()
             .unwrap();
         assert_eq!(result.amount, BigUint::from_str("137780051463393923").unwrap());
-        assert_eq!(result.gas, BigUint::from_str("102770").unwrap());
+        assert_eq!(result.gas, BigUint::from_str("123770").unwrap());
+        assert_eq!(
+            result.gas_breakdown,
+            vec![
+                GasItem::new(GasSource::BaseTx, BASE_TX_GAS),
+                GasItem::new(GasSource::PoolComputation, 102_770u64),
+            ]
+        );
         assert_ne!(new_state.spot_prices, pool_state.spot_prices);
         assert!(pool_state
             .block_lasting_overwrites
@@ -802,7 +1766,7 @@ mod tests {
             .downcast_ref::<EVMPoolState<PreCachedDB>>()
             .unwrap();
         assert_eq!(result.amount, BigUint::from_str("137780051463393923").unwrap());
-        assert_eq!(result.gas, BigUint::from_str("102770").unwrap());
+        assert_eq!(result.gas, BigUint::from_str("123770").unwrap());
         assert_ne!(new_state.spot_prices, pool_state.spot_prices);
 
         let new_result = new_state
@@ -815,10 +1779,35 @@ mod tests {
             .unwrap();
 
         assert_eq!(new_result.amount, BigUint::from_str("136964651490065626").unwrap());
-        assert_eq!(new_result.gas, BigUint::from_str("70048").unwrap());
+        assert_eq!(new_result.gas, BigUint::from_str("91048").unwrap());
         assert_ne!(new_state_second_swap.spot_prices, new_state.spot_prices);
     }
 
+    #[tokio::test]
+    async fn test_get_amount_out_with_gas_limit() {
+        let pool_state = setup_pool_state().await;
+        let amount_in = BigUint::from_str("1000000000000000000").unwrap();
+
+        let result = pool_state
+            .get_amount_out_with_gas_limit(amount_in.clone(), &dai(), &bal(), 123_770)
+            .unwrap();
+        assert_eq!(result.amount, BigUint::from_str("137780051463393923").unwrap());
+
+        // The simulation fits in the limit, but the estimate including the base cost doesn't
+        let err = pool_state
+            .get_amount_out_with_gas_limit(amount_in.clone(), &dai(), &bal(), 123_769)
+            .unwrap_err();
+        assert!(
+            matches!(err, SimulationError::InvalidInput(_, Some(res)) if res.gas == result.gas)
+        );
+
+        // The simulation itself runs out of gas, even with the headroom for the adapter
+        let err = pool_state
+            .get_amount_out_with_gas_limit(amount_in, &dai(), &bal(), 50_000)
+            .unwrap_err();
+        assert!(matches!(err, SimulationError::InvalidInput(_, None)));
+    }
+
     #[tokio::test]
     async fn test_get_amount_out_dust() {
         let pool_state = setup_pool_state().await;
@@ -833,7 +1822,7 @@ mod tests {
             .downcast_ref::<EVMPoolState<PreCachedDB>>()
             .unwrap();
         assert_eq!(result.amount, BigUint::ZERO);
-        assert_eq!(result.gas, 68656.to_biguint().unwrap());
+        assert_eq!(result.gas, 89656.to_biguint().unwrap());
         assert_eq!(new_state.spot_prices, pool_state.spot_prices)
     }
 
@@ -887,6 +1876,65 @@ mod tests {
             )
             .unwrap();
         assert_eq!(bal_limit, U256::from_str("13997408640689987484").unwrap());
+        assert_eq!(
+            pool_state
+                .get_limit(&dai(), &bal())
+                .unwrap(),
+            Some(BigUint::from_str("100279494253364362835").unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_adapter() {
+        let pool_state = setup_pool_state().await;
+        let pool_id = string_to_bytes32(&pool_state.id).unwrap();
+
+        let res = pool_state
+            .call_adapter(
+                "getLimits(bytes32,address,address) returns (uint256[])",
+                vec![
+                    DynSolValue::FixedBytes(pool_id.into(), 32),
+                    DynSolValue::Address(dai_addr()),
+                    DynSolValue::Address(bal_addr()),
+                ],
+                None,
+            )
+            .unwrap();
+
+        let expected = pool_state
+            .get_sell_amount_limit(
+                vec![dai_addr(), bal_addr()],
+                Some(
+                    pool_state
+                        .get_view_overwrites()
+                        .unwrap(),
+                ),
+            )
+            .unwrap();
+        let limits = res[0].as_array().unwrap();
+        assert_eq!(limits[0].as_uint(), Some((expected, 256)));
+        assert!(pool_state
+            .call_adapter("getLimits(bytes32) returns (uint256[])", vec![], None)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_summary() {
+        let pool_state = setup_pool_state().await;
+
+        let summary = pool_state.summary();
+
+        assert_eq!(summary.id, pool_state.id);
+        assert_eq!(summary.block, pool_state.block);
+        assert_eq!(summary.balances.len(), 2);
+        assert_eq!(pool_state.to_string(), summary.to_string());
+        let display = summary.to_string();
+        assert!(display.starts_with(&format!(
+            "{} at block {}: tokens [{}, {}]",
+            pool_state.id, pool_state.block.number, pool_state.tokens[0], pool_state.tokens[1]
+        )));
+        assert!(display.contains("SellSide"));
+        assert!(display.len() < 1_000, "{display}");
     }
 
     #[tokio::test]
@@ -903,20 +1951,546 @@ mod tests {
             .unwrap();
 
         let dai_bal_spot_price = pool_state
-            .spot_prices
-            .get(&(
-                bytes_to_address(&pool_state.tokens[0]).unwrap(),
-                bytes_to_address(&pool_state.tokens[1]).unwrap(),
-            ))
+            .spot_price(&dai(), &bal())
             .unwrap();
         let bal_dai_spot_price = pool_state
+            .spot_price(&bal(), &dai())
+            .unwrap();
+        assert_eq!(dai_bal_spot_price, 0.137_778_914_319_047_9);
+        assert_eq!(bal_dai_spot_price, 7.071_503_245_428_246);
+    }
+
+    /// Attaches a recorder to the pool's engine to count the simulations it runs
+    fn record_simulations(pool_state: &mut EVMPoolState<PreCachedDB>) -> SimulationRecorder {
+        let recorder = SimulationRecorder::in_memory();
+        pool_state
+            .adapter_contract
+            .engine
+            .recorder = Some(recorder.clone());
+        recorder
+    }
+
+    fn empty_delta(pool_state: &EVMPoolState<PreCachedDB>) -> ProtocolStateDelta {
+        ProtocolStateDelta {
+            component_id: pool_state.id.clone(),
+            updated_attributes: HashMap::new(),
+            deleted_attributes: HashSet::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spot_price_lazy_recompute() {
+        let mut pool_state = setup_pool_state().await;
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        pool_state
+            .set_spot_prices(&tokens)
+            .unwrap();
+        let recorder = record_simulations(&mut pool_state);
+
+        // Clean pools are served from the cache
+        let price = pool_state
+            .spot_price(&dai(), &bal())
+            .unwrap();
+        assert!(recorder.records().is_empty());
+
+        pool_state
+            .delta_transition(empty_delta(&pool_state), &tokens)
+            .unwrap();
+        assert!(pool_state.spot_prices_dirty());
+        assert!(recorder.records().is_empty());
+
+        // Dirty pairs are recomputed once, when requested
+        assert_eq!(
+            pool_state
+                .spot_price(&dai(), &bal())
+                .unwrap(),
+            price
+        );
+        let simulations = recorder.records().len();
+        assert!(simulations > 0);
+        pool_state
+            .spot_price(&dai(), &bal())
+            .unwrap();
+        assert_eq!(recorder.records().len(), simulations);
+        assert!(pool_state.spot_prices_dirty());
+        pool_state
+            .spot_price(&bal(), &dai())
+            .unwrap();
+        assert!(!pool_state.spot_prices_dirty());
+    }
+
+    #[tokio::test]
+    async fn test_balance_update_invalidates_touched_pairs() {
+        let mut pool_state = setup_pool_state().await;
+        let usdc = Address::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48").unwrap();
+        pool_state
+            .tokens
+            .push(Bytes::from(usdc.to_vec()));
+        let prices = pool_state
+            .token_pairs()
+            .unwrap()
+            .into_iter()
+            .map(|pair| (pair, 1.0))
+            .collect();
+        pool_state.spot_prices = SpotPrices::new(prices);
+        let block = pool_state.block;
+        let recorder = record_simulations(&mut pool_state);
+
+        let balances = HashMap::from([(
+            Bytes::from(usdc.to_vec()),
+            Bytes::from(U256::from(1_000u64).to_be_bytes_vec()),
+        )]);
+        pool_state
+            .transition_with_contracts(
+                empty_delta(&pool_state),
+                &balances,
+                &HashSet::new(),
+                block,
+                &HashMap::new(),
+            )
+            .unwrap();
+
+        assert_eq!(pool_state.balances[&usdc], U256::from(1_000u64));
+        let dirty_pairs = pool_state
             .spot_prices
-            .get(&(
-                bytes_to_address(&pool_state.tokens[1]).unwrap(),
-                bytes_to_address(&pool_state.tokens[0]).unwrap(),
-            ))
+            .0
+            .read()
+            .unwrap()
+            .dirty_pairs
+            .clone();
+        assert_eq!(dirty_pairs.len(), 4);
+        assert!(dirty_pairs
+            .iter()
+            .all(|(sell, buy)| *sell == usdc || *buy == usdc));
+        // The unrelated pair is still served from the cache, without recomputing it
+        assert_eq!(
+            pool_state
+                .spot_price(&dai(), &bal())
+                .unwrap(),
+            1.0
+        );
+        assert!(recorder.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_native_balance_overrides() {
+        let erc20_pool = setup_pool_state().await;
+        let vault = erc20_pool.get_balance_owner().unwrap();
+        assert!(erc20_pool
+            .get_native_balance_overrides(None)
+            .unwrap()
+            .is_empty());
+        assert!(matches!(erc20_pool.adapter().unwrap(), Cow::Borrowed(_)));
+
+        let eth = *NATIVE_TOKEN_SENTINEL;
+        let eth_balance = U256::from_str("42000000000000000000").unwrap();
+        let balances = HashMap::from([(eth, eth_balance), (bal_addr(), U256::from(1_000u64))]);
+        let eth_pool = EVMPoolState::new(
+            erc20_pool.id.clone(),
+            vec![Bytes::from(eth.to_vec()), bal().address],
+            erc20_pool.block,
+            balances,
+            Some(vault),
+            HashMap::new(),
+            erc20_pool.capabilities.clone(),
+            HashMap::new(),
+            HashSet::new(),
+            HashMap::new(),
+            false,
+            erc20_pool.adapter_contract.clone(),
+            None,
+            false,
+        );
+
+        // The ETH balance is an account balance of the vault, not an ERC20 storage overwrite
+        assert_eq!(
+            eth_pool
+                .get_native_balance_overrides(None)
+                .unwrap(),
+            HashMap::from([(vault, eth_balance)])
+        );
+        let overwrites = eth_pool
+            .get_balance_overwrites(eth_pool.tokens.clone())
+            .unwrap();
+        assert!(!overwrites.contains_key(&eth));
+        assert!(overwrites.contains_key(&bal_addr()));
+        assert_eq!(
+            eth_pool
+                .adapter()
+                .unwrap()
+                .balance_overrides,
+            HashMap::from([(vault, eth_balance)])
+        );
+        // The pool's own adapter contract is left untouched
+        assert!(eth_pool
+            .adapter_contract
+            .balance_overrides
+            .is_empty());
+
+        // Selling ETH funds the caller with ETH instead of overwriting a token balance
+        let max_amount = *MAX_BALANCE / U256::from(100);
+        assert_eq!(
+            eth_pool
+                .adapter_selling(Some(&eth))
+                .unwrap()
+                .balance_overrides,
+            HashMap::from([(vault, eth_balance), (*EXTERNAL_ACCOUNT, max_amount)])
+        );
+        let overwrites = eth_pool
+            .get_token_overwrites(vec![eth, bal_addr()], max_amount)
+            .unwrap();
+        assert!(!overwrites.contains_key(&eth));
+        // Selling BAL doesn't fund the caller with ETH
+        assert_eq!(
+            eth_pool
+                .adapter_selling(Some(&bal_addr()))
+                .unwrap()
+                .balance_overrides,
+            HashMap::from([(vault, eth_balance)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ensure_spot_prices() {
+        let mut pool_state = setup_pool_state().await;
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        pool_state
+            .set_spot_prices(&tokens)
+            .unwrap();
+        let recorder = record_simulations(&mut pool_state);
+
+        pool_state.ensure_spot_prices().unwrap();
+        assert!(recorder.records().is_empty());
+
+        pool_state
+            .delta_transition(empty_delta(&pool_state), &tokens)
+            .unwrap();
+        pool_state.ensure_spot_prices().unwrap();
+        assert!(!pool_state.spot_prices_dirty());
+        let simulations = recorder.records().len();
+        assert!(simulations > 0);
+
+        pool_state
+            .spot_price(&dai(), &bal())
+            .unwrap();
+        assert_eq!(recorder.records().len(), simulations);
+    }
+
+    #[tokio::test]
+    async fn test_spot_price_manual_updates() {
+        let mut pool_state = setup_pool_state().await;
+        pool_state.manual_updates = true;
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        pool_state
+            .set_spot_prices(&tokens)
+            .unwrap();
+
+        pool_state
+            .delta_transition(empty_delta(&pool_state), &tokens)
+            .unwrap();
+        assert!(!pool_state.spot_prices_dirty());
+
+        let mut delta = empty_delta(&pool_state);
+        delta
+            .updated_attributes
+            .insert("update_marker".to_string(), Bytes::from(vec![1u8]));
+        pool_state
+            .delta_transition(delta, &tokens)
+            .unwrap();
+        assert!(pool_state.spot_prices_dirty());
+    }
+
+    #[tokio::test]
+    async fn test_transition_with_contracts() {
+        let mut pool_state = setup_pool_state().await;
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        let capabilities = pool_state.capabilities.clone();
+        let recorder = record_simulations(&mut pool_state);
+        let block = BlockHeader::new(18485418, B256::repeat_byte(1), 1);
+
+        let new_balance = U256::from_str("45541493881684942848").unwrap();
+        let balances = HashMap::from([(bal().address, Bytes::from(new_balance.to_be_bytes_vec()))]);
+        pool_state
+            .transition_with_contracts(
+                empty_delta(&pool_state),
+                &balances,
+                &HashSet::new(),
+                block,
+                &tokens,
+            )
+            .unwrap();
+        pool_state
+            .get_amount_out(BigUint::from_str("1000000000000000000").unwrap(), &dai(), &bal())
+            .unwrap();
+
+        // The swap is simulated at the new block, with the new balance, without re-detecting the
+        // capabilities
+        assert_eq!(pool_state.balances[&bal_addr()], new_balance);
+        assert_eq!(pool_state.capabilities, capabilities);
+        let records = recorder.records();
+        assert!(!records.is_empty());
+        assert!(records
+            .iter()
+            .all(|record| record.params.block_number == block.number));
+        assert!(records.iter().any(|record| {
+            record
+                .params
+                .overrides
+                .as_ref()
+                .and_then(|overrides| overrides.get(&bal_addr()))
+                .is_some_and(|slots| {
+                    slots
+                        .values()
+                        .any(|value| *value == new_balance)
+                })
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_transition_with_touched_contracts() {
+        let mut pool_state = setup_pool_state().await;
+        let vault = Address::from_str("0xBA12222222228d8Ba445958a75a0704d566BF2C8").unwrap();
+        let pool = Address::from_str("0x4626d81b3a1711beb79f4cecff2413886d461677").unwrap();
+        pool_state.involved_contracts = HashSet::from([vault, pool]);
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        assert_eq!(pool_state.balance_owner, Some(vault));
+        assert!(pool_state.is_touched_by(&HashSet::from([pool])));
+        // Balance changes come as component balances
+        assert!(!pool_state.is_touched_by(&HashSet::from([vault])));
+        assert!(!pool_state.is_touched_by(&HashSet::from([Address::repeat_byte(0x11)])));
+
+        // Manual pools only recompute on an update marker, touched contracts are ignored
+        pool_state.manual_updates = true;
+        assert!(!pool_state.is_touched_by(&HashSet::from([pool])));
+        pool_state
+            .set_spot_prices(&tokens)
+            .unwrap();
+        let block = BlockHeader::new(18485418, B256::repeat_byte(1), 1);
+        pool_state
+            .transition_with_contracts(
+                empty_delta(&pool_state),
+                &HashMap::new(),
+                &HashSet::from([pool]),
+                block,
+                &tokens,
+            )
+            .unwrap();
+        assert!(!pool_state.spot_prices_dirty());
+        assert_eq!(pool_state.block, block);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_balances() {
+        let mut pool_state = setup_pool_state().await;
+        assert!(!pool_state.balances_stale());
+
+        // The engine is at block 20463609, the pool was created at block 18485417
+        pool_state.balance_stale_after_blocks = Some(10);
+        assert!(pool_state.balances_stale());
+
+        pool_state.refresh_balances().unwrap();
+
+        assert_eq!(pool_state.balances_block, 20463609);
+        assert!(!pool_state.balances_stale());
+        assert!(pool_state
+            .balances
+            .contains_key(&dai_addr()));
+        assert!(pool_state
+            .balances
+            .contains_key(&bal_addr()));
+    }
+
+    #[tokio::test]
+    async fn test_paused_delta_transition() {
+        let mut pool_state = setup_pool_state().await;
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        assert!(pool_state.is_active());
+
+        let delta = ProtocolStateDelta {
+            component_id: pool_state.id.clone(),
+            updated_attributes: HashMap::from([("paused".to_string(), Bytes::from(vec![1u8]))]),
+            deleted_attributes: HashSet::new(),
+        };
+        pool_state
+            .delta_transition(delta, &tokens)
+            .unwrap();
+        assert!(!pool_state.is_active());
+
+        let delta = ProtocolStateDelta {
+            component_id: pool_state.id.clone(),
+            updated_attributes: HashMap::new(),
+            deleted_attributes: HashSet::from(["paused".to_string()]),
+        };
+        pool_state
+            .delta_transition(delta, &tokens)
+            .unwrap();
+        assert!(pool_state.is_active());
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_after_delta() {
+        let mut pool_state = setup_pool_state().await;
+        let tokens = vec![bal(), dai()]
+            .into_iter()
+            .map(|t| (t.address.clone(), t))
+            .collect();
+        let initial = pool_state.state_fingerprint();
+
+        pool_state
+            .delta_transition(empty_delta(&pool_state), &tokens)
+            .unwrap();
+        assert_eq!(pool_state.state_fingerprint(), initial);
+
+        let delta = ProtocolStateDelta {
+            component_id: pool_state.id.clone(),
+            updated_attributes: HashMap::from([("paused".to_string(), Bytes::from(vec![1u8]))]),
+            deleted_attributes: HashSet::new(),
+        };
+        pool_state
+            .delta_transition(delta, &tokens)
+            .unwrap();
+        assert_ne!(pool_state.state_fingerprint(), initial);
+    }
+
+    #[tokio::test]
+    async fn test_discover_token_slots() {
+        let mut pool_state = setup_pool_state().await;
+        let recorder = record_simulations(&mut pool_state);
+
+        let slots = pool_state
+            .discover_token_slots(dai_addr())
+            .unwrap();
+
+        assert_eq!(
+            slots,
+            (ERC20Slots::new(SlotId::from(0), SlotId::from(1)), ContractCompiler::Solidity)
+        );
+        assert_eq!(pool_state.token_storage_slots[&dai_addr()], slots);
+
+        // Cached slots are returned without simulating again
+        let simulations = recorder.records().len();
+        pool_state
+            .discover_token_slots(dai_addr())
+            .unwrap();
+        assert_eq!(recorder.records().len(), simulations);
+    }
+
+    #[tokio::test]
+    async fn test_transition_from_logs() {
+        let mut pool_state = setup_pool_state().await;
+        pool_state
+            .spot_price(&dai(), &bal())
+            .unwrap();
+        let vault = pool_state.balance_owner.unwrap();
+        let pool_id = pool_state.id.clone();
+        let swap = |pool_id: &str, amount_in: u128, amount_out: u128| {
+            Log::new_unchecked(
+                vault,
+                vec![
+                    alloy_primitives::keccak256("Swap(bytes32,address,address,uint256,uint256)"),
+                    B256::from_str(pool_id).unwrap(),
+                    dai_addr().into_word(),
+                    bal_addr().into_word(),
+                ],
+                (U256::from(amount_in), U256::from(amount_out))
+                    .abi_encode()
+                    .into(),
+            )
+        };
+        let other_pool = "0x5c6ee304399dbdb9c8ef030ab642b10820db8f56000200000000000000000014";
+
+        pool_state
+            .transition_from_logs(&[
+                swap(&pool_id, 1_000_000_000_000_000_000, 137_780_051_463_393_923),
+                swap(other_pool, 5, 5),
+            ])
+            .unwrap();
+
+        assert_eq!(pool_state.balances[&dai_addr()], U256::from(179_754_012_737_301_807_104u128));
+        assert_eq!(pool_state.balances[&bal_addr()], U256::from(90_945_207_711_906_491_773u128));
+        assert!(pool_state.spot_prices_dirty());
+
+        // More than the pool holds, the pool is left unchanged
+        let res = pool_state.transition_from_logs(&[swap(&pool_id, 0, u128::MAX)]);
+        assert!(matches!(res, Err(TransitionError::DecodeError(_))));
+        assert_eq!(pool_state.balances[&bal_addr()], U256::from(90_945_207_711_906_491_773u128));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        // JUMPDEST PUSH1 0 JUMP: an adapter looping until it runs out of gas or time
+        let adapter = Bytecode::new_raw(
+            alloy_primitives::hex::decode("5b600056")
+                .unwrap()
+                .into(),
+        );
+        let balances =
+            HashMap::from([(dai_addr(), U256::from(1_000)), (bal_addr(), U256::from(1_000))]);
+        let mut pool_state = EVMPoolStateBuilder::new(
+            "0x0000000000000000000000000000000000004321".to_string(),
+            vec![dai().address, bal().address],
+            balances,
+            BlockHeader::default(),
+            Address::from_str("0x0000000000000000000000000000000000001234").unwrap(),
+        )
+        .capabilities(HashSet::from([Capability::SellSide]))
+        .adapter_contract_bytecode(adapter)
+        .max_simulation_gas(u64::MAX / 2)
+        .max_simulation_millis(50)
+        .max_consecutive_failures(2)
+        .build(PreCachedDB::new().unwrap())
+        .await
+        .unwrap();
+
+        for _ in 0..2 {
+            let err = pool_state
+                .get_amount_out(BigUint::one(), &dai(), &bal())
+                .unwrap_err();
+            assert!(
+                matches!(err, SimulationError::RecoverableError(msg) if msg.contains("timed out"))
+            );
+        }
+        assert!(pool_state.is_circuit_open());
+
+        let err = pool_state
+            .get_amount_out(BigUint::one(), &dai(), &bal())
+            .unwrap_err();
+        let SimulationError::RecoverableError(msg) = &err else { panic!("Unexpected error {err}") };
+        assert!(msg.starts_with("Circuit breaker open"), "{msg}");
+        assert_eq!(
+            pool_state.simulation_stats(),
+            SimulationStats { consecutive_failures: 2, failures: 2, timeouts: 2, rejected: 1 }
+        );
+
+        // A state delta closes the breaker
+        let delta = empty_delta(&pool_state);
+        pool_state
+            .delta_transition(delta, &HashMap::new())
             .unwrap();
-        assert_eq!(dai_bal_spot_price, &0.137_778_914_319_047_9);
-        assert_eq!(bal_dai_spot_price, &7.071_503_245_428_246);
+        assert!(!pool_state.is_circuit_open());
+        assert_eq!(
+            pool_state
+                .simulation_stats()
+                .consecutive_failures,
+            0
+        );
     }
 }
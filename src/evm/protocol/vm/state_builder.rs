@@ -1,6 +1,8 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
+    sync::Arc,
+    time::Duration,
 };
 
 use alloy_primitives::{Address, U256};
@@ -16,12 +18,13 @@ use tracing::warn;
 use tycho_core::Bytes as TychoBytes;
 
 use super::{
-    constants::{EXTERNAL_ACCOUNT, MAX_BALANCE},
+    circuit_breaker::SimulationBudget,
+    constants::{is_native_token, EXTERNAL_ACCOUNT, MAX_BALANCE},
     erc20_token::{brute_force_slots, ERC20Slots},
     models::Capability,
     state::EVMPoolState,
     tycho_simulation_contract::TychoSimulationContract,
-    utils::get_code_for_contract,
+    utils::get_code_for_contracts,
 };
 use crate::{
     evm::{
@@ -29,6 +32,7 @@ use crate::{
             create_engine, engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
         },
         protocol::{utils::bytes_to_address, vm::constants::ERC20_BYTECODE},
+        rpc::{EnvRpc, EthRpc},
         simulation::{SimulationEngine, SimulationParameters},
         ContractCompiler,
     },
@@ -65,11 +69,7 @@ use crate::{
 ///         Bytes::from("0x6b175474e89094c44da98b954eedeac495271d0f"),
 ///         Bytes::from("0xba100000625a3754423978a60c9317c58a424e3d"),
 ///     ];
-///     let block = BlockHeader {
-///         number: 1,
-///         hash: Default::default(),
-///         timestamp: 1632456789,
-///     };
+///     let block = BlockHeader::new(1, Default::default(), 1632456789);
 ///
 ///     // Optional: Add token balances
 ///     let mut balances = HashMap::new();
@@ -98,12 +98,17 @@ where
     capabilities: Option<HashSet<Capability>>,
     involved_contracts: Option<HashSet<Address>>,
     stateless_contracts: Option<HashMap<String, Option<Vec<u8>>>>,
+    stateless_contract_overrides: Option<HashMap<String, Vec<u8>>>,
     token_storage_slots: Option<HashMap<Address, (ERC20Slots, ContractCompiler)>>,
     manual_updates: Option<bool>,
     trace: Option<bool>,
     engine: Option<SimulationEngine<D>>,
     adapter_contract: Option<TychoSimulationContract<D>>,
     adapter_contract_bytecode: Option<Bytecode>,
+    balance_stale_after_blocks: Option<u64>,
+    paused: Option<bool>,
+    simulation_budget: SimulationBudget,
+    rpc_client: Option<Arc<dyn EthRpc>>,
 }
 
 impl<D> EVMPoolStateBuilder<D>
@@ -129,12 +134,17 @@ where
             capabilities: None,
             involved_contracts: None,
             stateless_contracts: None,
+            stateless_contract_overrides: None,
             token_storage_slots: None,
             manual_updates: None,
             trace: None,
             engine: None,
             adapter_contract: None,
             adapter_contract_bytecode: None,
+            balance_stale_after_blocks: None,
+            paused: None,
+            simulation_budget: SimulationBudget::default(),
+            rpc_client: None,
         }
     }
 
@@ -161,6 +171,19 @@ where
         self
     }
 
+    /// Sets runtime code for contracts the pool calls into, e.g. external math libraries it
+    /// delegatecalls into.
+    ///
+    /// Keys are contract addresses. The code is set on the engine's database before any simulation
+    /// of the pool runs, and takes precedence over the `stateless_contracts` entry of the same
+    /// address, which is then not fetched. Accounts already present in the database are not
+    /// replaced: this includes the `involved_contracts`, whose code and storage are indexed by
+    /// Tycho, so overrides only apply to contracts Tycho doesn't track.
+    pub fn stateless_contract_overrides(mut self, overrides: HashMap<String, Vec<u8>>) -> Self {
+        self.stateless_contract_overrides = Some(overrides);
+        self
+    }
+
     pub fn token_storage_slots(
         mut self,
         token_storage_slots: HashMap<Address, (ERC20Slots, ContractCompiler)>,
@@ -194,9 +217,51 @@ where
         self
     }
 
+    /// Re-read token balances during `delta_transition` once they are this many blocks old.
+    /// Needed for pools holding rebasing tokens.
+    pub fn balance_stale_after_blocks(mut self, blocks: u64) -> Self {
+        self.balance_stale_after_blocks = Some(blocks);
+        self
+    }
+
+    /// Marks the pool as paused, i.e. not accepting swaps.
+    pub fn paused(mut self, paused: bool) -> Self {
+        self.paused = Some(paused);
+        self
+    }
+
+    /// Limits the gas of the pool's simulations, in addition to the limit a caller may pass.
+    pub fn max_simulation_gas(mut self, gas: u64) -> Self {
+        self.simulation_budget.max_gas = Some(gas);
+        self
+    }
+
+    /// Halts simulations of the pool running longer than `millis` milliseconds.
+    pub fn max_simulation_millis(mut self, millis: u64) -> Self {
+        self.simulation_budget.max_duration = Some(Duration::from_millis(millis));
+        self
+    }
+
+    /// Stops simulating swaps after `failures` consecutive failed swaps, until the next state
+    /// delta. Swaps fail fast with a `SimulationError::RecoverableError` in the meantime.
+    pub fn max_consecutive_failures(mut self, failures: u32) -> Self {
+        self.simulation_budget
+            .max_consecutive_failures = Some(failures);
+        self
+    }
+
+    /// Sets the client the code of `stateless_contracts` without code is fetched with.
+    ///
+    /// Defaults to an `EnvRpc`, connecting to the node at the `RPC_URL` environment variable.
+    pub fn rpc_client(mut self, client: Arc<dyn EthRpc>) -> Self {
+        self.rpc_client = Some(client);
+        self
+    }
+
     /// Build the final EVMPoolState object
     pub async fn build(mut self, db: D) -> Result<EVMPoolState<D>, SimulationError> {
         let engine = if let Some(engine) = &self.engine {
+            self.init_stateless_contract_overrides(engine)?;
             engine.clone()
         } else {
             self.engine = Some(self.get_default_engine(db).await?);
@@ -204,15 +269,22 @@ where
         };
 
         if self.adapter_contract.is_none() {
-            self.adapter_contract = Some(TychoSimulationContract::new_swap_adapter(
-                self.adapter_address,
-                self.adapter_contract_bytecode
-                    .clone()
-                    .ok_or_else(|| {
-                        SimulationError::FatalError("Adapter contract bytecode not set".to_string())
-                    })?,
-                engine.clone(),
-            )?)
+            self.adapter_contract = Some(
+                TychoSimulationContract::new_swap_adapter(
+                    self.adapter_address,
+                    self.adapter_contract_bytecode
+                        .clone()
+                        .ok_or_else(|| {
+                            SimulationError::FatalError(
+                                "Adapter contract bytecode not set".to_string(),
+                            )
+                        })?,
+                    engine.clone(),
+                )?
+                // Capabilities are detected through the adapter, within the budget already
+                .with_timeout(self.simulation_budget.max_duration)
+                .with_max_gas(self.simulation_budget.max_gas),
+            )
         };
 
         self.init_token_storage_slots()?;
@@ -221,7 +293,7 @@ where
         } else {
             self.get_default_capabilities()?
         };
-        Ok(EVMPoolState::new(
+        let state = EVMPoolState::new(
             self.id,
             self.tokens,
             self.block,
@@ -240,12 +312,26 @@ where
                     "Failed to get build engine: Adapter contract not initialized".to_string(),
                 )
             })?,
-        ))
+            self.balance_stale_after_blocks,
+            self.paused.unwrap_or(false),
+        );
+        // Without a budget, keep the timeout of a given adapter contract
+        if self.simulation_budget == SimulationBudget::default() {
+            return Ok(state);
+        }
+        Ok(state.with_simulation_budget(self.simulation_budget))
     }
 
     async fn get_default_engine(&self, db: D) -> Result<SimulationEngine<D>, SimulationError> {
         let engine = create_engine(db, self.trace.unwrap_or(false))?;
+        let overridden = self.init_stateless_contract_overrides(&engine)?;
         for token_address in &self.tokens {
+            let token_address = bytes_to_address(token_address)?;
+            // The native token is no contract, mocking it as an ERC20 would let calls to it
+            // succeed instead of transferring value
+            if is_native_token(&token_address) {
+                continue;
+            }
             let info = AccountInfo {
                 balance: Default::default(),
                 nonce: 0,
@@ -254,7 +340,7 @@ where
             };
             engine
                 .state
-                .init_account(bytes_to_address(token_address)?, info, None, false);
+                .init_account(token_address, info, None, false);
         }
 
         engine.state.init_account(
@@ -265,34 +351,63 @@ where
         );
 
         if let Some(stateless_contracts) = &self.stateless_contracts {
+            let mut contracts = Vec::new();
             for (address, bytecode) in stateless_contracts.iter() {
+                if address
+                    .parse::<Address>()
+                    .is_ok_and(|addr| overridden.contains(&addr))
+                {
+                    continue;
+                }
                 let mut addr_str = address.clone();
-                let (code, code_hash) = if bytecode.is_none() {
-                    if addr_str.starts_with("call") {
-                        addr_str = self
-                            .get_address_from_call(&engine, &addr_str)?
-                            .to_string();
-                    }
-                    let code = get_code_for_contract(&addr_str, None).await?;
-                    (Some(code.clone()), code.hash_slow())
-                } else {
-                    let code =
-                        Bytecode::new_raw(Bytes::from(bytecode.clone().ok_or_else(|| {
-                            SimulationError::FatalError(
-                                "Failed to get default engine: Byte code from stateless contracts is None".into(),
-                            )
-                        })?));
-                    (Some(code.clone()), code.hash_slow())
-                };
+                if bytecode.is_none() && addr_str.starts_with("call") {
+                    addr_str = self
+                        .get_address_from_call(&engine, &addr_str)?
+                        .to_string();
+                }
                 let account_address: Address = addr_str.parse().map_err(|_| {
                     SimulationError::FatalError(format!(
                         "Failed to get default engine: Couldn't parse address string {}",
                         address
                     ))
                 })?;
+                let code = bytecode
+                    .as_ref()
+                    .map(|bytecode| Bytecode::new_raw(Bytes::from(bytecode.clone())));
+                contracts.push((account_address, code));
+            }
+
+            // Contracts without code are fetched from the node, in a single request
+            let missing: Vec<Address> = contracts
+                .iter()
+                .filter(|(_, code)| code.is_none())
+                .map(|(address, _)| *address)
+                .collect();
+            let mut fetched = if missing.is_empty() {
+                Vec::new()
+            } else {
+                let rpc = self
+                    .rpc_client
+                    .clone()
+                    .unwrap_or_else(|| Arc::new(EnvRpc::default()));
+                get_code_for_contracts(&missing, rpc.as_ref()).await?
+            }
+            .into_iter();
+            for (address, code) in contracts {
+                let code = match code {
+                    Some(code) => code,
+                    None => fetched.next().ok_or_else(|| {
+                        SimulationError::FatalError(format!("No code fetched for {address}"))
+                    })?,
+                };
                 engine.state.init_account(
-                    alloy_primitives::Address(*account_address),
-                    AccountInfo { balance: Default::default(), nonce: 0, code_hash, code },
+                    address,
+                    AccountInfo {
+                        balance: Default::default(),
+                        nonce: 0,
+                        code_hash: code.hash_slow(),
+                        code: Some(code),
+                    },
                     None,
                     false,
                 );
@@ -301,6 +416,51 @@ where
         Ok(engine)
     }
 
+    /// Initializes the accounts of `stateless_contract_overrides` on the engine's database and
+    /// returns their addresses.
+    fn init_stateless_contract_overrides(
+        &self,
+        engine: &SimulationEngine<D>,
+    ) -> Result<HashSet<Address>, SimulationError> {
+        let mut overridden = HashSet::new();
+        for (address, bytecode) in self
+            .stateless_contract_overrides
+            .iter()
+            .flatten()
+        {
+            let account_address: Address = address.parse().map_err(|_| {
+                SimulationError::FatalError(format!(
+                    "Failed to override stateless contract: Couldn't parse address string {}",
+                    address
+                ))
+            })?;
+            if self
+                .involved_contracts
+                .as_ref()
+                .is_some_and(|contracts| contracts.contains(&account_address))
+            {
+                warn!(
+                    "Code override for {} of pool {} is ignored if the contract is indexed",
+                    address, self.id
+                );
+            }
+            let code = Bytecode::new_raw(Bytes::from(bytecode.clone()));
+            engine.state.init_account(
+                account_address,
+                AccountInfo {
+                    balance: Default::default(),
+                    nonce: 0,
+                    code_hash: code.hash_slow(),
+                    code: Some(code),
+                },
+                None,
+                false,
+            );
+            overridden.insert(account_address);
+        }
+        Ok(overridden)
+    }
+
     fn init_token_storage_slots(&mut self) -> Result<(), SimulationError> {
         for t in self.tokens.iter() {
             let t_erc20_address = bytes_to_address(t)?;
@@ -431,9 +591,11 @@ where
             block_number: self.block.number,
             timestamp,
             overrides: Some(HashMap::new()),
+            balance_overrides: None,
             caller: *EXTERNAL_ACCOUNT,
             value: U256::from(0u64),
             gas_limit: None,
+            timeout: None,
         };
 
         let sim_result = engine
@@ -455,9 +617,12 @@ mod tests {
     use alloy_primitives::B256;
 
     use super::*;
-    use crate::evm::{
-        engine_db::{tycho_db::PreCachedDB, SHARED_TYCHO_DB},
-        protocol::utils::bytes_to_address,
+    use crate::{
+        evm::{
+            engine_db::{tycho_db::PreCachedDB, SHARED_TYCHO_DB},
+            protocol::utils::bytes_to_address,
+        },
+        testing::MockEthRpc,
     };
 
     #[test]
@@ -466,7 +631,7 @@ mod tests {
         let tokens =
             vec![TychoBytes::from_str("0000000000000000000000000000000000000000").unwrap()];
         let balances = HashMap::new();
-        let block = BlockHeader { number: 1, hash: B256::default(), timestamp: 234 };
+        let block = BlockHeader::new(1, B256::default(), 234);
         let adapter_address =
             Address::from_str("0xA2C5C98A892fD6656a7F39A2f63228C0Bc846270").unwrap();
         let result = tokio_test::block_on(
@@ -489,7 +654,7 @@ mod tests {
         let token2 = TychoBytes::from_str("0000000000000000000000000000000000000002").unwrap();
         let token3 = TychoBytes::from_str("0000000000000000000000000000000000000003").unwrap();
         let tokens = vec![token2.clone(), token3.clone()];
-        let block = BlockHeader { number: 1, hash: B256::default(), timestamp: 234 };
+        let block = BlockHeader::new(1, B256::default(), 234);
         let balances = HashMap::new();
         let adapter_address =
             Address::from_str("0xA2C5C98A892fD6656a7F39A2f63228C0Bc846270").unwrap();
@@ -508,4 +673,75 @@ mod tests {
             .get_account_storage()
             .account_present(&bytes_to_address(&token3).unwrap()));
     }
+
+    #[test]
+    fn test_engine_setup_stateless_contract_overrides() {
+        let library = "0x0000000000000000000000000000000000001234";
+        let code = vec![0x60, 0x00, 0x60, 0x00, 0xf3];
+        let block = BlockHeader::new(1, B256::default(), 234);
+        let adapter_address =
+            Address::from_str("0xA2C5C98A892fD6656a7F39A2f63228C0Bc846270").unwrap();
+        // Without the override, the stateless contract would be fetched from the node
+        let builder = EVMPoolStateBuilder::<PreCachedDB>::new(
+            "pool_1".to_string(),
+            vec![],
+            HashMap::new(),
+            block,
+            adapter_address,
+        )
+        .stateless_contracts(HashMap::from([(library.to_string(), None)]))
+        .stateless_contract_overrides(HashMap::from([(library.to_string(), code.clone())]));
+
+        let engine =
+            tokio_test::block_on(builder.get_default_engine(PreCachedDB::new().unwrap())).unwrap();
+
+        let account = engine
+            .state
+            .basic_ref(Address::from_str(library).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(account.code.unwrap().original_bytes(), Bytes::from(code));
+    }
+
+    #[test]
+    fn test_engine_setup_fetches_stateless_contracts_in_batch() {
+        let libraries = [Address::repeat_byte(0x12), Address::repeat_byte(0x34)];
+        let known = Address::repeat_byte(0x56);
+        let rpc = Arc::new(
+            MockEthRpc::new()
+                .with_code(libraries[0], vec![0x60, 0x01])
+                .with_code(libraries[1], vec![0x60, 0x02]),
+        );
+        let block = BlockHeader::new(1, B256::default(), 234);
+        let adapter_address =
+            Address::from_str("0xA2C5C98A892fD6656a7F39A2f63228C0Bc846270").unwrap();
+        let stateless_contracts = HashMap::from([
+            (libraries[0].to_string(), None),
+            (libraries[1].to_string(), None),
+            (known.to_string(), Some(vec![0x60, 0x03])),
+        ]);
+        let builder = EVMPoolStateBuilder::<PreCachedDB>::new(
+            "pool_1".to_string(),
+            vec![],
+            HashMap::new(),
+            block,
+            adapter_address,
+        )
+        .stateless_contracts(stateless_contracts)
+        .rpc_client(rpc.clone());
+
+        let engine =
+            tokio_test::block_on(builder.get_default_engine(PreCachedDB::new().unwrap())).unwrap();
+
+        for (address, code) in [(libraries[0], 0x01), (libraries[1], 0x02), (known, 0x03)] {
+            let account = engine
+                .state
+                .basic_ref(address)
+                .unwrap()
+                .unwrap();
+            assert_eq!(account.code.unwrap().original_bytes(), Bytes::from(vec![0x60, code]));
+        }
+        assert_eq!(rpc.calls("get_code_batch"), 1);
+        assert_eq!(rpc.calls("get_code"), 0);
+    }
 }
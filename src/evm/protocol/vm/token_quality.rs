@@ -0,0 +1,299 @@
+//! Token behavior classification
+//!
+//! Tokens with non-standard behavior, e.g. taxed transfers or rebasing balances, silently poison
+//! the simulations of the pools holding them. `TokenQualityAnalyzer` runs a short battery of
+//! simulated ERC20 calls against a token to detect such behavior.
+use std::{collections::HashMap, fmt::Debug};
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolValue;
+use lazy_static::lazy_static;
+use revm::{
+    primitives::{AccountInfo, KECCAK_EMPTY},
+    DatabaseRef,
+};
+
+use super::{
+    constants::{EXTERNAL_ACCOUNT, MAX_BALANCE},
+    erc20_token::{discover_slots, ERC20OverwriteFactory, Overwrites},
+    tycho_simulation_contract::{TychoSimulationContract, TychoSimulationResponse},
+};
+use crate::{
+    evm::{
+        engine_db::{
+            create_engine, engine_db_interface::EngineDatabaseInterface, simulation_db::BlockHeader,
+        },
+        simulation::SimulationEngine,
+    },
+    models::TransferTax,
+    protocol::errors::SimulationError,
+};
+
+const BPS_DENOMINATOR: u64 = 10_000;
+
+lazy_static! {
+    static ref RECIPIENT: Address = Address::repeat_byte(0x72);
+    static ref SPENDER: Address = Address::repeat_byte(0x73);
+    /// Amount minted to the holder, transferred and approved
+    static ref AMOUNT: U256 = U256::from(10).pow(U256::from(18));
+}
+
+/// Behavior of a token, as classified by `TokenQualityAnalyzer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenClassification {
+    /// Behaves like a standard ERC20 token
+    Good,
+    /// Charges a tax on transfers, in basis points of the transferred amount
+    FeeOnTransfer { bps: u32 },
+    /// Balances aren't plain stored amounts, e.g. share based tokens, or transfers deliver more
+    /// than was sent
+    Rebasing,
+    /// Reverts or misreports on `balanceOf`, `transfer`, `approve` or `allowance`, e.g. paused
+    /// tokens or tokens blacklisting the simulated accounts
+    Broken,
+}
+
+impl TokenClassification {
+    /// The tax of fee-on-transfer tokens, charged on buys and sells alike
+    pub fn transfer_tax(&self) -> Option<TransferTax> {
+        match self {
+            TokenClassification::FeeOnTransfer { bps } => Some(TransferTax::new(*bps, *bps)),
+            _ => None,
+        }
+    }
+}
+
+/// Classifies tokens by simulating ERC20 calls on them, see `analyze`.
+#[derive(Debug, Clone)]
+pub struct TokenQualityAnalyzer<D: EngineDatabaseInterface + Clone + Debug>
+where
+    <D as DatabaseRef>::Error: Debug,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    engine: SimulationEngine<D>,
+}
+
+impl<D: EngineDatabaseInterface + Clone + Debug> TokenQualityAnalyzer<D>
+where
+    <D as DatabaseRef>::Error: Debug,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    /// Creates an analyzer simulating on `db`, which needs to hold the code and storage of the
+    /// analyzed tokens. The calls are made from `EXTERNAL_ACCOUNT`, which is initialized on `db`
+    /// like `EVMPoolStateBuilder` does.
+    pub fn new(db: D) -> Result<Self, SimulationError> {
+        let engine = create_engine(db, false)?;
+        engine.state.init_account(
+            *EXTERNAL_ACCOUNT,
+            AccountInfo { balance: *MAX_BALANCE, nonce: 0, code_hash: KECCAK_EMPTY, code: None },
+            None,
+            false,
+        );
+        Ok(Self { engine })
+    }
+
+    /// Classifies the token at `token` by simulating, at `block`:
+    ///  1. `balanceOf` of a holder, `EXTERNAL_ACCOUNT`, which must not revert.
+    ///  2. A mint to the holder by overwriting its balance. Tokens whose balance slot
+    ///     `discover_slots` can't find are `Rebasing`.
+    ///  3. A `transfer` of the minted amount to a fresh recipient, whose `balanceOf` afterwards
+    ///     measures the tax, if any.
+    ///  4. An `approve` of a spender, which `allowance` must report back.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::RecoverableError` if the database holds no code for the token,
+    /// e.g. because its contract wasn't loaded yet.
+    pub fn analyze(
+        &self,
+        token: &Address,
+        block: &BlockHeader,
+    ) -> Result<TokenClassification, SimulationError> {
+        let has_code = self
+            .engine
+            .state
+            .basic_ref(*token)
+            .ok()
+            .flatten()
+            .and_then(|info| info.code)
+            .is_some_and(|code| !code.is_empty());
+        if !has_code {
+            return Err(SimulationError::RecoverableError(format!(
+                "No code to analyze for token {token}"
+            )));
+        }
+        let contract = TychoSimulationContract::new(*token, self.engine.clone())?;
+
+        if self
+            .call(&contract, "balanceOf(address)", *EXTERNAL_ACCOUNT, block, &HashMap::new())
+            .and_then(|res| decode_u256(&res))
+            .is_none()
+        {
+            return Ok(TokenClassification::Broken);
+        }
+        let Ok((slots, compiler)) = discover_slots(&self.engine, token, &EXTERNAL_ACCOUNT, block)
+        else {
+            return Ok(TokenClassification::Rebasing);
+        };
+        let mut factory = ERC20OverwriteFactory::new(*token, slots, compiler);
+        factory.set_balance(*AMOUNT, *EXTERNAL_ACCOUNT);
+        let mut overwrites = factory.get_overwrites();
+
+        let transfer = self.call(
+            &contract,
+            "transfer(address,uint256)",
+            (*RECIPIENT, *AMOUNT),
+            block,
+            &overwrites,
+        );
+        let Some(transfer) = transfer.filter(returned_true) else {
+            return Ok(TokenClassification::Broken);
+        };
+        apply_storage_updates(&mut overwrites, &transfer);
+        let Some(received) = self
+            .call(&contract, "balanceOf(address)", *RECIPIENT, block, &overwrites)
+            .and_then(|res| decode_u256(&res))
+        else {
+            return Ok(TokenClassification::Broken);
+        };
+
+        let approve = self.call(
+            &contract,
+            "approve(address,uint256)",
+            (*SPENDER, *AMOUNT),
+            block,
+            &overwrites,
+        );
+        let Some(approve) = approve.filter(returned_true) else {
+            return Ok(TokenClassification::Broken);
+        };
+        apply_storage_updates(&mut overwrites, &approve);
+        let allowance = self
+            .call(
+                &contract,
+                "allowance(address,address)",
+                (*EXTERNAL_ACCOUNT, *SPENDER),
+                block,
+                &overwrites,
+            )
+            .and_then(|res| decode_u256(&res));
+        if allowance != Some(*AMOUNT) {
+            return Ok(TokenClassification::Broken);
+        }
+
+        Ok(if received > *AMOUNT {
+            TokenClassification::Rebasing
+        } else if received == *AMOUNT {
+            TokenClassification::Good
+        } else if received.is_zero() {
+            TokenClassification::Broken
+        } else {
+            // Rounded up, a tax below one basis point still marks the token
+            let lost = (*AMOUNT - received) * U256::from(BPS_DENOMINATOR);
+            let bps = lost.div_ceil(*AMOUNT);
+            TokenClassification::FeeOnTransfer { bps: bps.to::<u32>() }
+        })
+    }
+
+    /// Calls the token from `EXTERNAL_ACCOUNT`, `None` if the call fails.
+    fn call(
+        &self,
+        contract: &TychoSimulationContract<D>,
+        signature: &str,
+        args: impl SolValue,
+        block: &BlockHeader,
+        overwrites: &HashMap<Address, Overwrites>,
+    ) -> Option<TychoSimulationResponse> {
+        contract
+            .call(
+                signature,
+                args,
+                block.number,
+                Some(block.timestamp),
+                Some(overwrites.clone()),
+                Some(*EXTERNAL_ACCOUNT),
+                U256::ZERO,
+            )
+            .ok()
+    }
+}
+
+fn decode_u256(res: &TychoSimulationResponse) -> Option<U256> {
+    U256::abi_decode(&res.return_value, true).ok()
+}
+
+/// Whether a `transfer` or `approve` succeeded. Tokens returning nothing, like USDT, succeed if
+/// they don't revert.
+fn returned_true(res: &TychoSimulationResponse) -> bool {
+    res.return_value.is_empty() || bool::abi_decode(&res.return_value, true).unwrap_or(false)
+}
+
+/// Applies the storage changes of a call, so the next call sees them
+fn apply_storage_updates(
+    overwrites: &mut HashMap<Address, Overwrites>,
+    res: &TychoSimulationResponse,
+) {
+    for (address, update) in &res.simulation_result.state_updates {
+        if let Some(storage) = &update.storage {
+            overwrites
+                .entry(*address)
+                .or_default()
+                .extend(storage.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use revm::primitives::{AccountInfo, Bytecode, Bytes};
+    use rstest::rstest;
+
+    use super::*;
+    use crate::evm::{engine_db::tycho_db::PreCachedDB, protocol::vm::constants::ERC20_BYTECODE};
+
+    /// ERC20 with balances at slot 0 and allowances at slot 1, burning 1% of every transfer
+    const TAXED_ERC20_BYTECODE: &[u8] = include_bytes!("assets/TaxedERC20.bin");
+    /// PUSH1 0 DUP1 REVERT
+    const REVERTING_BYTECODE: &[u8] = &[0x60, 0x00, 0x80, 0xfd];
+
+    fn analyzer_with_token(code: &[u8]) -> (TokenQualityAnalyzer<PreCachedDB>, Address) {
+        let db = PreCachedDB::new().unwrap();
+        let token = Address::from_str("0x0000000000000000000000000000000000003333").unwrap();
+        let code = Bytecode::new_raw(Bytes::copy_from_slice(code));
+        db.init_account(token, AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code), None, true);
+        (TokenQualityAnalyzer::new(db).unwrap(), token)
+    }
+
+    #[rstest]
+    #[case::standard(ERC20_BYTECODE, TokenClassification::Good)]
+    #[case::taxed(TAXED_ERC20_BYTECODE, TokenClassification::FeeOnTransfer { bps: 100 })]
+    #[case::reverting(REVERTING_BYTECODE, TokenClassification::Broken)]
+    fn test_analyze(#[case] code: &[u8], #[case] exp: TokenClassification) {
+        let (analyzer, token) = analyzer_with_token(code);
+
+        let res = analyzer
+            .analyze(&token, &BlockHeader::default())
+            .unwrap();
+
+        assert_eq!(res, exp);
+    }
+
+    #[test]
+    fn test_analyze_missing_code() {
+        let analyzer = TokenQualityAnalyzer::new(PreCachedDB::new().unwrap()).unwrap();
+
+        let res = analyzer.analyze(&Address::repeat_byte(0x33), &BlockHeader::default());
+
+        assert!(matches!(res, Err(SimulationError::RecoverableError(_))));
+    }
+
+    #[test]
+    fn test_transfer_tax() {
+        let classification = TokenClassification::FeeOnTransfer { bps: 100 };
+
+        assert_eq!(classification.transfer_tax(), Some(TransferTax::new(100, 100)));
+        assert_eq!(TokenClassification::Good.transfer_tax(), None);
+    }
+}
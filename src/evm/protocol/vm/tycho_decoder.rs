@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    str::FromStr,
+    sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
 
@@ -9,11 +9,15 @@ use revm::primitives::Bytecode;
 use tycho_client::feed::{synchronizer::ComponentWithState, Header};
 use tycho_core::Bytes;
 
-use super::{state::EVMPoolState, state_builder::EVMPoolStateBuilder};
+use super::{
+    state::{is_truthy, EVMPoolState},
+    state_builder::EVMPoolStateBuilder,
+};
 use crate::{
     evm::{
         engine_db::{simulation_db::BlockHeader, tycho_db::PreCachedDB, SHARED_TYCHO_DB},
-        protocol::vm::constants::get_adapter_file,
+        protocol::vm::constants::{default_adapter_address, get_adapter_file},
+        rpc::EthRpc,
     },
     models::Token,
     protocol::{errors::InvalidSnapshotError, models::TryFromWithBlock},
@@ -27,14 +31,10 @@ impl From<Header> for BlockHeader {
             .as_secs();
         BlockHeader {
             number: header.number,
-            hash: B256::new(
-                header
-                    .hash
-                    .as_ref()
-                    .try_into()
-                    .expect("Hash must be 32 bytes"),
-            ),
+            // Feeds of some chains don't provide the block hash
+            hash: B256::try_from(header.hash.as_ref()).ok(),
             timestamp: now,
+            ..Default::default()
         }
     }
 }
@@ -42,7 +42,8 @@ impl From<Header> for BlockHeader {
 impl TryFromWithBlock<ComponentWithState> for EVMPoolState<PreCachedDB> {
     type Error = InvalidSnapshotError;
 
-    /// Decodes a `ComponentWithState` into an `EVMPoolState`.
+    /// Decodes a `ComponentWithState` into an `EVMPoolState`, simulating through the protocol's
+    /// default adapter address.
     ///
     /// Errors with a `InvalidSnapshotError`.
     async fn try_from_with_block(
@@ -50,10 +51,51 @@ impl TryFromWithBlock<ComponentWithState> for EVMPoolState<PreCachedDB> {
         block: Header,
         all_tokens: &HashMap<Bytes, Token>,
     ) -> Result<Self, Self::Error> {
+        Self::try_from_with_adapter(snapshot, block, all_tokens, None).await
+    }
+}
+
+impl EVMPoolState<PreCachedDB> {
+    /// Decodes a `ComponentWithState` into an `EVMPoolState`, deploying the protocol's adapter at
+    /// `adapter_address` instead of its default address if given.
+    ///
+    /// Errors with a `InvalidSnapshotError`.
+    pub async fn try_from_with_adapter(
+        snapshot: ComponentWithState,
+        block: Header,
+        all_tokens: &HashMap<Bytes, Token>,
+        adapter_address: Option<Address>,
+    ) -> Result<Self, InvalidSnapshotError> {
+        Self::try_from_with_db(
+            snapshot,
+            block,
+            all_tokens,
+            adapter_address,
+            SHARED_TYCHO_DB.clone(),
+            None,
+        )
+        .await
+    }
+
+    /// Decodes a `ComponentWithState` into an `EVMPoolState` simulating on `db` instead of the
+    /// global `SHARED_TYCHO_DB`. The contracts of the component must already be present in `db`.
+    ///
+    /// Stateless contracts whose code isn't part of the snapshot are fetched with `rpc_client`,
+    /// or from the node at the `RPC_URL` environment variable if none is given.
+    ///
+    /// Errors with a `InvalidSnapshotError`.
+    pub async fn try_from_with_db(
+        snapshot: ComponentWithState,
+        block: Header,
+        all_tokens: &HashMap<Bytes, Token>,
+        adapter_address: Option<Address>,
+        db: PreCachedDB,
+        rpc_client: Option<Arc<dyn EthRpc>>,
+    ) -> Result<Self, InvalidSnapshotError> {
         let id = snapshot.component.id.clone();
         let tokens = snapshot.component.tokens.clone();
 
-        let block = BlockHeader::from(block);
+        let block = BlockHeader::from(block).with_chain(db.chain().unwrap_or_default());
         let balances = snapshot
             .state
             .balances
@@ -66,47 +108,26 @@ impl TryFromWithBlock<ComponentWithState> for EVMPoolState<PreCachedDB> {
             .get("balance_owner")
             .map(|bytes: &Bytes| Address::from_slice(bytes.as_ref()));
 
+        let paused = snapshot
+            .state
+            .attributes
+            .get("paused")
+            .is_some_and(is_truthy);
+
         let manual_updates = snapshot
             .component
             .static_attributes
             .contains_key("manual_updates");
 
         // Decode involved contracts
-        let mut stateless_contracts = HashMap::new();
-        let mut index = 0;
-
-        loop {
-            let address_key = format!("stateless_contract_addr_{}", index);
-            if let Some(encoded_address_bytes) = snapshot
-                .state
-                .attributes
-                .get(&address_key)
-            {
-                let encoded_address = hex::encode(encoded_address_bytes);
-                // Stateless contracts address are UTF-8 encoded
-                let address_hex = encoded_address
-                    .strip_prefix("0x")
-                    .unwrap_or(&encoded_address);
-
-                let decoded = match hex::decode(address_hex) {
-                    Ok(decoded_bytes) => match String::from_utf8(decoded_bytes) {
-                        Ok(decoded_string) => decoded_string,
-                        Err(_) => continue,
-                    },
-                    Err(_) => continue,
-                };
-
-                let code_key = format!("stateless_contract_code_{}", index);
-                let code = snapshot
-                    .state
-                    .attributes
-                    .get(&code_key)
-                    .map(|value| value.to_vec());
-
-                stateless_contracts.insert(decoded, code);
-                index += 1;
-            } else {
-                break;
+        let mut stateless_contracts = stateless_contracts(&snapshot);
+        // Contracts loaded before, e.g. while warming up the stream, aren't fetched again
+        for (address, code) in stateless_contracts.iter_mut() {
+            if code.is_none() {
+                *code = address
+                    .parse::<Address>()
+                    .ok()
+                    .and_then(|address| loaded_code(&db, &address));
             }
         }
 
@@ -129,8 +150,7 @@ impl TryFromWithBlock<ComponentWithState> for EVMPoolState<PreCachedDB> {
             });
         let adapter_bytecode = Bytecode::new_raw(get_adapter_file(protocol_name)?.into());
         let adapter_contract_address =
-            Address::from_str(&format!("{:0>40}", hex::encode(protocol_name)))
-                .expect("Can't convert protocol name to address");
+            adapter_address.unwrap_or_else(|| default_adapter_address(protocol_name));
 
         let mut pool_state_builder = EVMPoolStateBuilder::new(
             id.clone(),
@@ -147,18 +167,79 @@ impl TryFromWithBlock<ComponentWithState> for EVMPoolState<PreCachedDB> {
         if let Some(balance_owner) = balance_owner {
             pool_state_builder = pool_state_builder.balance_owner(balance_owner)
         };
+        if let Some(rpc_client) = rpc_client {
+            pool_state_builder = pool_state_builder.rpc_client(rpc_client)
+        };
 
         let mut pool_state = pool_state_builder
-            .build(SHARED_TYCHO_DB.clone())
+            .build(db)
             .await
             .map_err(InvalidSnapshotError::VMError)?;
 
-        pool_state.set_spot_prices(all_tokens)?;
+        // Paused pools can't be priced, spot prices are computed once they get unpaused
+        if !paused {
+            pool_state.set_spot_prices(all_tokens)?;
+        }
 
         Ok(pool_state)
     }
 }
 
+/// Returns the stateless contracts a VM component's snapshot declares, by address, with their
+/// code if the snapshot contains it.
+///
+/// Addresses are either hex encoded, or a call resolving the address on the engine.
+pub(crate) fn stateless_contracts(
+    snapshot: &ComponentWithState,
+) -> HashMap<String, Option<Vec<u8>>> {
+    let mut stateless_contracts = HashMap::new();
+    let mut index = 0;
+
+    loop {
+        let address_key = format!("stateless_contract_addr_{}", index);
+        if let Some(encoded_address_bytes) = snapshot
+            .state
+            .attributes
+            .get(&address_key)
+        {
+            let encoded_address = hex::encode(encoded_address_bytes);
+            // Stateless contracts address are UTF-8 encoded
+            let address_hex = encoded_address
+                .strip_prefix("0x")
+                .unwrap_or(&encoded_address);
+
+            let decoded = match hex::decode(address_hex) {
+                Ok(decoded_bytes) => match String::from_utf8(decoded_bytes) {
+                    Ok(decoded_string) => decoded_string,
+                    Err(_) => continue,
+                },
+                Err(_) => continue,
+            };
+
+            let code_key = format!("stateless_contract_code_{}", index);
+            let code = snapshot
+                .state
+                .attributes
+                .get(&code_key)
+                .map(|value| value.to_vec());
+
+            stateless_contracts.insert(decoded, code);
+            index += 1;
+        } else {
+            break;
+        }
+    }
+    stateless_contracts
+}
+
+/// Returns the code of `address` if it's already loaded into `db`.
+pub(crate) fn loaded_code(db: &PreCachedDB, address: &Address) -> Option<Vec<u8>> {
+    db.snapshot_account(address)
+        .and_then(|account| account.info.code)
+        .filter(|code| !code.is_empty())
+        .map(|code| code.original_bytes().to_vec())
+}
+
 #[cfg(test)]
 mod tests {
     use std::{collections::HashSet, fs, path::Path, str::FromStr};
@@ -176,12 +257,25 @@ mod tests {
     use crate::{
         evm::{
             engine_db::{create_engine, engine_db_interface::EngineDatabaseInterface},
-            protocol::vm::constants::{get_adapter_file, BALANCER_V2, CURVE},
+            protocol::vm::constants::{
+                get_adapter_file, BALANCER_V2, CURVE, NATIVE_TOKEN_SENTINEL,
+            },
             tycho_models::AccountUpdate,
         },
         protocol::models::TryFromWithBlock,
     };
 
+    #[test]
+    fn test_block_header_without_hash() {
+        let header =
+            Header { number: 1, hash: Bytes::new(), parent_hash: Bytes::new(), revert: false };
+
+        let block = BlockHeader::from(header);
+
+        assert_eq!(block.number, 1);
+        assert_eq!(block.hash, None);
+    }
+
     #[test]
     fn test_to_adapter_file_name() {
         assert_eq!(get_adapter_file("balancer_v2").unwrap(), BALANCER_V2);
@@ -308,7 +402,7 @@ mod tests {
         }
         db.update(accounts, Some(block.into()));
 
-        let res = EVMPoolState::try_from_with_block(snapshot, header(), &tokens)
+        let res = EVMPoolState::try_from_with_block(snapshot.clone(), header(), &tokens)
             .await
             .unwrap();
 
@@ -321,5 +415,97 @@ mod tests {
             .insert(Address::from_str("0xBA12222222228d8Ba445958a75a0704d566BF2C8").unwrap());
         assert_eq!(res.get_involved_contracts(), exp_involved_contracts);
         assert!(res.get_manual_updates());
+        assert_eq!(res.adapter_address(), default_adapter_address("balancer_v2"));
+
+        let adapter_address =
+            Address::from_str("0xA2C5C98A892fD6656a7F39A2f63228C0Bc846270").unwrap();
+        let res =
+            EVMPoolState::try_from_with_adapter(snapshot, header(), &tokens, Some(adapter_address))
+                .await
+                .unwrap();
+
+        assert_eq!(res.adapter_address(), adapter_address);
+    }
+
+    #[tokio::test]
+    async fn test_try_from_with_native_token() {
+        let eth = Bytes::from(NATIVE_TOKEN_SENTINEL.to_vec());
+        let bal = Bytes::from_str("0xba100000625a3754423978a60c9317c58a424e3d").unwrap();
+        let vault = Bytes::from_str("0xBA12222222228d8Ba445958a75a0704d566BF2C8").unwrap();
+        let tokens = [
+            Token::new(
+                "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee",
+                18,
+                "ETH",
+                10_000.to_biguint().unwrap(),
+            ),
+            Token::new(
+                "0xba100000625a3754423978a60c9317c58a424e3d",
+                18,
+                "BAL",
+                10_000.to_biguint().unwrap(),
+            ),
+        ]
+        .into_iter()
+        .map(|t| (t.address.clone(), t))
+        .collect::<HashMap<_, _>>();
+        let snapshot = ComponentWithState {
+            state: ResponseProtocolState {
+                component_id: "0x4626d81b3a1711beb79f4cecff2413886d461677000200000000000000000011"
+                    .to_owned(),
+                attributes: [
+                    ("balance_owner".to_string(), vault.clone()),
+                    // Skips pricing, the vault's storage doesn't know the pool
+                    ("paused".to_string(), Bytes::from("0x01")),
+                ]
+                .into_iter()
+                .collect(),
+                balances: [
+                    (eth.clone(), Bytes::from("0x0de0b6b3a7640000")),
+                    (bal.clone(), Bytes::from("0x01")),
+                ]
+                .into_iter()
+                .collect(),
+            },
+            component: ProtocolComponent {
+                tokens: vec![eth.clone(), bal.clone()],
+                ..vm_component()
+            },
+        };
+        let db = PreCachedDB::new().unwrap();
+        let accounts = load_balancer_account_data();
+        let engine = create_engine(db.clone(), false).unwrap();
+        for account in accounts.clone() {
+            engine.state.init_account(
+                account.address,
+                AccountInfo {
+                    balance: account.balance.unwrap_or_default(),
+                    nonce: 0u64,
+                    code_hash: KECCAK_EMPTY,
+                    code: account
+                        .code
+                        .clone()
+                        .map(|arg0: Vec<u8>| Bytecode::new_raw(arg0.into())),
+                },
+                None,
+                false,
+            );
+        }
+        db.update(accounts, Some(header().into()));
+
+        let res =
+            EVMPoolState::try_from_with_db(snapshot, header(), &tokens, None, db.clone(), None)
+                .await
+                .unwrap();
+
+        // Only the ERC20 is mocked, the native token stays a plain account
+        let accounts = db.get_account_storage();
+        assert!(accounts
+            .get_account_info(&*NATIVE_TOKEN_SENTINEL)
+            .is_none());
+        assert!(accounts
+            .get_account_info(&Address::from_slice(bal.as_ref()))
+            .is_some());
+        assert_eq!(res.tokens, vec![eth, bal]);
     }
 }
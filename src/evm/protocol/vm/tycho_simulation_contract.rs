@@ -1,4 +1,4 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, time::Duration};
 
 use alloy_primitives::{keccak256, Address, Keccak256, B256, U256};
 use alloy_sol_types::SolValue;
@@ -45,6 +45,10 @@ pub struct TychoSimulationResponse {
 /// - `address`: The address of the contract being simulated.
 /// - `engine`: The `SimulationEngine` instance responsible for simulating transactions and managing
 ///   the contract's state.
+/// - `balance_overrides`: Native balances applied to every simulation, e.g. the ETH held by a pool.
+/// - `timeout`: Wall-clock limit of every simulation, none by default.
+/// - `max_gas`: Gas limit of every simulation, lowering the limit a call passes if needed. The
+///   engine's default applies if neither is set.
 ///
 /// # Errors
 /// Returns errors of type `SimulationError` when encoding, decoding, or simulation operations
@@ -57,6 +61,9 @@ where
 {
     pub address: Address,
     pub engine: SimulationEngine<D>,
+    pub balance_overrides: HashMap<Address, U256>,
+    pub timeout: Option<Duration>,
+    pub max_gas: Option<u64>,
 }
 
 impl<D: EngineDatabaseInterface + Clone + Debug> TychoSimulationContract<D>
@@ -65,7 +72,13 @@ where
     <D as EngineDatabaseInterface>::Error: std::fmt::Debug,
 {
     pub fn new(address: Address, engine: SimulationEngine<D>) -> Result<Self, SimulationError> {
-        Ok(Self { address, engine })
+        Ok(Self {
+            address,
+            engine,
+            balance_overrides: HashMap::new(),
+            timeout: None,
+            max_gas: None,
+        })
     }
 
     // Creates a new instance with the ISwapAdapter ABI
@@ -90,7 +103,31 @@ where
             false,
         );
 
-        Ok(Self { address, engine })
+        Ok(Self {
+            address,
+            engine,
+            balance_overrides: HashMap::new(),
+            timeout: None,
+            max_gas: None,
+        })
+    }
+
+    /// Returns the contract with `balance_overrides` as the native balances of its simulations.
+    pub fn with_balance_overrides(mut self, balance_overrides: HashMap<Address, U256>) -> Self {
+        self.balance_overrides = balance_overrides;
+        self
+    }
+
+    /// Returns the contract with its simulations halted once they run longer than `timeout`.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the contract with the gas of its simulations limited to `max_gas`.
+    pub fn with_max_gas(mut self, max_gas: Option<u64>) -> Self {
+        self.max_gas = max_gas;
+        self
     }
 
     fn encode_input(&self, selector: &str, args: impl SolValue) -> Vec<u8> {
@@ -125,8 +162,53 @@ where
         overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
         caller: Option<Address>,
         value: U256,
+    ) -> Result<TychoSimulationResponse, SimulationError> {
+        self.call_with_gas_limit(
+            selector,
+            args,
+            block_number,
+            timestamp,
+            overrides,
+            caller,
+            value,
+            None,
+        )
+    }
+
+    /// Like `call`, but runs the simulation with the given gas limit instead of the engine's
+    /// default.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_with_gas_limit(
+        &self,
+        selector: &str,
+        args: impl SolValue,
+        block_number: u64,
+        timestamp: Option<u64>,
+        overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
+        caller: Option<Address>,
+        value: U256,
+        gas_limit: Option<u64>,
     ) -> Result<TychoSimulationResponse, SimulationError> {
         let call_data = self.encode_input(selector, args);
+        self.call_with_data(call_data, block_number, timestamp, overrides, caller, value, gas_limit)
+    }
+
+    /// Like `call_with_gas_limit`, but with already encoded call data, selector included.
+    #[allow(clippy::too_many_arguments)]
+    pub fn call_with_data(
+        &self,
+        call_data: Vec<u8>,
+        block_number: u64,
+        timestamp: Option<u64>,
+        overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
+        caller: Option<Address>,
+        value: U256,
+        gas_limit: Option<u64>,
+    ) -> Result<TychoSimulationResponse, SimulationError> {
+        let gas_limit = match (gas_limit, self.max_gas) {
+            (Some(limit), Some(max)) => Some(limit.min(max)),
+            (limit, max) => limit.or(max),
+        };
         let params = SimulationParameters {
             data: call_data,
             to: self.address,
@@ -138,9 +220,12 @@ where
                     .timestamp() as u64
             }),
             overrides,
+            balance_overrides: (!self.balance_overrides.is_empty())
+                .then(|| self.balance_overrides.clone()),
             caller: caller.unwrap_or(*EXTERNAL_ACCOUNT),
             value,
-            gas_limit: None,
+            gas_limit,
+            timeout: self.timeout,
         };
 
         let sim_result = self.simulate(params)?;
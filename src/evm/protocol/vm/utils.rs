@@ -1,18 +1,19 @@
-use std::{collections::HashMap, env, str::FromStr};
+use std::str::FromStr;
 
-use alloy::{
-    providers::{Provider, ProviderBuilder},
-    transports::{RpcError, TransportErrorKind},
-};
-use alloy_primitives::{Address, U256};
+use alloy::transports::{RpcError, TransportErrorKind};
+use alloy_primitives::{Address, Bytes as AlloyBytes, U256};
 use alloy_sol_types::SolValue;
 use hex::FromHex;
 use num_bigint::BigInt;
 use revm::primitives::{Bytecode, Bytes};
 use serde_json::Value;
 
+pub use crate::evm::erc20::get_storage_slot_index_at_key;
 use crate::{
-    evm::{simulation::SimulationEngineError, ContractCompiler, SlotId},
+    evm::{
+        rpc::{EthRpc, EthRpcError},
+        simulation::{decode_revert_reason, SimulationEngineError},
+    },
     protocol::errors::SimulationError,
 };
 
@@ -22,14 +23,23 @@ pub fn coerce_error(
     gas_limit: Option<u64>,
 ) -> SimulationError {
     match err {
-        // Check for revert situation (if error message starts with "0x")
-        SimulationEngineError::TransactionError { ref data, ref gas_used }
-            if data.starts_with("0x") =>
-        {
-            let reason = parse_solidity_error_message(data);
+        // Check for revert situation
+        SimulationEngineError::TransactionError {
+            ref data,
+            ref gas_used,
+            revert_data: Some(ref revert_data),
+            ..
+        } => {
+            let reason = if data.starts_with("0x") {
+                parse_solidity_error_message(data)
+            } else {
+                data.clone()
+            };
             let err = SimulationEngineError::TransactionError {
                 data: format!("Revert! Reason: {}", reason),
                 gas_used: *gas_used,
+                revert_data: Some(revert_data.clone()),
+                trace: None,
             };
 
             // Check if we are running out of gas
@@ -56,7 +66,7 @@ pub fn coerce_error(
             ))
         }
         // Check if "OutOfGas" is part of the error message
-        SimulationEngineError::TransactionError { ref data, ref gas_used }
+        SimulationEngineError::TransactionError { ref data, ref gas_used, .. }
             if data.contains("OutOfGas") =>
         {
             let usage_msg = if let (Some(gas_limit), Some(gas_used)) = (gas_limit, gas_used) {
@@ -80,6 +90,14 @@ pub fn coerce_error(
         SimulationEngineError::StorageError(message) => {
             SimulationError::RecoverableError(message.clone())
         }
+        SimulationEngineError::Timeout(timeout) => SimulationError::RecoverableError(format!(
+            "Simulation timed out after {timeout:?}. Pool state: {pool_state}"
+        )),
+        SimulationEngineError::BlockMismatch { state_block, params_block } => {
+            SimulationError::FatalError(format!(
+                "Simulation at block {params_block} on a state at block {state_block}"
+            ))
+        }
         _ => SimulationError::FatalError(err.clone().to_string()), /* Otherwise return the
                                                                     * original error */
     }
@@ -93,22 +111,9 @@ fn parse_solidity_error_message(data: &str) -> String {
             Err(_) => return format!("Failed to decode: {}", data),
         };
 
-        // Check for specific error selectors:
-        // Solidity Error(string) signature: 0x08c379a0
-        if data_bytes.starts_with(&[0x08, 0xc3, 0x79, 0xa0]) {
-            if let Ok(decoded) = String::abi_decode(&data_bytes[4..], true) {
-                return decoded;
-            }
-
-            // Solidity Panic(uint256) signature: 0x4e487b71
-        } else if data_bytes.starts_with(&[0x4e, 0x48, 0x7b, 0x71]) {
-            if let Ok(decoded) = U256::abi_decode(&data_bytes[4..], true) {
-                let panic_codes = get_solidity_panic_codes();
-                return panic_codes
-                    .get(&decoded.as_limbs()[0])
-                    .cloned()
-                    .unwrap_or_else(|| format!("Panic({})", decoded));
-            }
+        // Standard Error(string) and Panic(uint256) payloads
+        if let Some(reason) = decode_revert_reason(&data_bytes) {
+            return reason;
         }
 
         // Try decoding as a string (old Solidity revert case)
@@ -125,158 +130,76 @@ fn parse_solidity_error_message(data: &str) -> String {
     format!("Failed to decode: {}", data)
 }
 
-/// Get storage slot index of a value stored at a certain key in a mapping
-///
-/// # Arguments
-///
-/// * `key`: Key in a mapping. Can be any H160 value (such as an address).
-/// * `mapping_slot`: An `U256` representing the storage slot at which the mapping itself is stored.
-///   See the examples for more explanation.
-/// * `compiler`: The compiler with which the target contract was compiled. Solidity and Vyper
-///   handle maps differently.
-///
-/// # Returns
-///
-/// An `U256` representing the  index of a storage slot where the value at the given
-/// key is stored.
-///
-/// # Examples
-///
-/// If a mapping is declared as a first variable in Solidity code, its storage slot
-/// is 0 (e.g. `balances` in our mocked ERC20 contract). Here's how to compute
-/// a storage slot where balance of a given account is stored:
-///
-/// ```
-/// use alloy_primitives::{U256, Address};
-/// use tycho_simulation::evm::ContractCompiler;
-/// use tycho_simulation::evm::protocol::vm::utils::get_storage_slot_index_at_key;
-/// let address: Address = "0xC63135E4bF73F637AF616DFd64cf701866BB2628".parse().expect("Invalid address");
-/// get_storage_slot_index_at_key(address, U256::from(0), ContractCompiler::Solidity);
-/// ```
-///
-/// For nested mappings, we need to apply the function twice. An example of this is
-/// `allowances` in ERC20. It is a mapping of form:
-/// `HashMap<Owner, HashMap<Spender, U256>>`. In our mocked ERC20 contract, `allowances`
-/// is a second variable, so it is stored at slot 1. Here's how to get a storage slot
-/// where an allowance of `address_spender` to spend `address_owner`'s money is stored:
-///
-/// ```
-/// use alloy_primitives::{U256, Address};
-/// use tycho_simulation::evm::ContractCompiler;
-/// use tycho_simulation::evm::protocol::vm::utils::get_storage_slot_index_at_key;
-/// let address_spender: Address = "0xC63135E4bF73F637AF616DFd64cf701866BB2628".parse().expect("Invalid address");
-/// let address_owner: Address = "0x6F4Feb566b0f29e2edC231aDF88Fe7e1169D7c05".parse().expect("Invalid address");
-/// get_storage_slot_index_at_key(address_spender, get_storage_slot_index_at_key(address_owner, U256::from(1), ContractCompiler::Solidity), ContractCompiler::Solidity);
-/// ```
-///
-/// # See Also
-///
-/// [Solidity Storage Layout documentation](https://docs.soliditylang.org/en/v0.8.13/internals/layout_in_storage.html#mappings-and-dynamic-arrays)
-pub fn get_storage_slot_index_at_key(
-    key: Address,
-    mapping_slot: SlotId,
-    compiler: ContractCompiler,
-) -> SlotId {
-    let mut key_bytes = key.as_slice().to_vec();
-    if key_bytes.len() < 32 {
-        let padding = vec![0u8; 32 - key_bytes.len()];
-        key_bytes.splice(0..0, padding); // Prepend zeros to the start
-    }
-
-    let mapping_slot_bytes: [u8; 32] = mapping_slot.to_be_bytes();
-    compiler.compute_map_slot(&mapping_slot_bytes, &key_bytes)
-}
-
-fn get_solidity_panic_codes() -> HashMap<u64, String> {
-    let mut panic_codes = HashMap::new();
-    panic_codes.insert(0, "GenericCompilerPanic".to_string());
-    panic_codes.insert(1, "AssertionError".to_string());
-    panic_codes.insert(17, "ArithmeticOver/Underflow".to_string());
-    panic_codes.insert(18, "ZeroDivisionError".to_string());
-    panic_codes.insert(33, "UnknownEnumMember".to_string());
-    panic_codes.insert(34, "BadStorageByteArrayEncoding".to_string());
-    panic_codes.insert(51, "EmptyArray".to_string());
-    panic_codes.insert(0x32, "OutOfBounds".to_string());
-    panic_codes.insert(0x41, "OutOfMemory".to_string());
-    panic_codes.insert(0x51, "BadFunctionPointer".to_string());
-    panic_codes
-}
-
 /// Fetches the bytecode for a specified contract address, returning an error if the address is
 /// an Externally Owned Account (EOA) or if no code is associated with it.
 ///
-/// This function checks the specified address on the blockchain, attempting to retrieve any
-/// contract bytecode deployed at that address. If the address corresponds to an EOA or any
-/// other address without associated bytecode, an `RpcError::EmptyResponse` error is returned.
-///
-/// # Parameters
-/// - `address`: The address of the account or contract to query, as a string.
-/// - `connection_string`: An optional RPC connection string. If not provided, the function will
-///   default to the `RPC_URL` environment variable.
-///
-/// # Returns
-/// - `Ok(Bytecode)`: The bytecode of the contract at the specified address, if present.
-/// - `Err(RpcError)`: An error if the address does not have associated bytecode, if there is an
-///   issue with the RPC connection, or if the address is invalid.
+/// The code is requested at the latest block through `rpc`, e.g. an `EnvRpc` to use the node at
+/// the `RPC_URL` environment variable.
 ///
 /// # Errors
-/// - Returns `RpcError::InvalidRequest` if `address` is not parsable or if no RPC URL is set.
-/// - Returns `RpcError::EmptyResponse` if the address has no associated bytecode (e.g., EOA).
-/// - Returns `RpcError::InvalidResponse` for issues with the RPC provider response.
+/// - Returns `SimulationError::FatalError` if `address` is not parsable, if the address has no
+///   associated bytecode (e.g. EOA) or for invalid responses of the node.
+/// - Returns `SimulationError::RecoverableError` if the request to the node failed.
 pub async fn get_code_for_contract(
     address: &str,
-    connection_string: Option<String>,
+    rpc: &dyn EthRpc,
 ) -> Result<Bytecode, SimulationError> {
-    // Get the connection string, defaulting to the RPC_URL environment variable
-    let connection_string = connection_string.or_else(|| env::var("RPC_URL").ok());
-
-    let connection_string = match connection_string {
-        Some(url) => url,
-        None => {
-            return Err(SimulationError::FatalError(
-                "RPC_URL environment variable is not set".to_string(),
-            ))
-        }
-    };
+    let addr = Address::from_str(address).map_err(|_| {
+        SimulationError::FatalError(format!("Failed to parse address {address} to get code for"))
+    })?;
+    let code = rpc
+        .get_code(addr, None)
+        .await
+        .map_err(code_request_error)?;
+    to_bytecode(&addr, code)
+}
 
-    let addr = Address::from_str(address).expect("Failed to parse address to get code for");
-    // Call eth_getCode to get the bytecode of the contract
-    match sync_get_code(&connection_string, addr) {
-        Ok(code) if code.is_empty() => {
-            Err(SimulationError::FatalError("Empty code response from RPC".to_string()))
-        }
-        Ok(code) => {
-            let bytecode = Bytecode::new_raw(Bytes::from(code.to_vec()));
-            Ok(bytecode)
-        }
-        Err(e) => match e {
-            RpcError::Transport(err) => Err(SimulationError::RecoverableError(format!(
-                "Failed to get code for contract due to internal RPC error: {:?}",
-                err
-            ))),
-            _ => Err(SimulationError::FatalError(format!(
-                "Failed to get code for contract. Invalid response from RPC: {:?}",
-                e
-            ))),
-        },
+/// Fetches the bytecode of several contracts with a single batched request, in order.
+///
+/// Errors like `get_code_for_contract`, if any of the addresses has no code.
+pub async fn get_code_for_contracts(
+    addresses: &[Address],
+    rpc: &dyn EthRpc,
+) -> Result<Vec<Bytecode>, SimulationError> {
+    let code = rpc
+        .get_code_batch(addresses, None)
+        .await
+        .map_err(code_request_error)?;
+    if code.len() != addresses.len() {
+        return Err(SimulationError::FatalError(format!(
+            "RPC returned code for {} of {} contracts",
+            code.len(),
+            addresses.len()
+        )));
+    }
+    addresses
+        .iter()
+        .zip(code)
+        .map(|(address, code)| to_bytecode(address, code))
+        .collect()
+}
+
+fn to_bytecode(address: &Address, code: AlloyBytes) -> Result<Bytecode, SimulationError> {
+    if code.is_empty() {
+        return Err(SimulationError::FatalError(format!(
+            "Empty code response from RPC for {address}"
+        )));
     }
+    Ok(Bytecode::new_raw(Bytes::from(code.to_vec())))
 }
 
-fn sync_get_code(
-    connection_string: &str,
-    addr: Address,
-) -> Result<Bytes, RpcError<TransportErrorKind>> {
-    tokio::task::block_in_place(|| {
-        tokio::runtime::Handle::current().block_on(async {
-            // Create a provider with the URL
-            let provider = ProviderBuilder::new()
-                .on_builtin(connection_string)
-                .await
-                .unwrap();
-
-            provider.get_code_at(addr).await
-        })
-    })
+fn code_request_error(err: EthRpcError) -> SimulationError {
+    match err.downcast_ref::<RpcError<TransportErrorKind>>() {
+        // Errors of custom clients are treated as transport errors
+        Some(RpcError::Transport(_)) | None => SimulationError::RecoverableError(format!(
+            "Failed to get code for contract due to internal RPC error: {:?}",
+            err
+        )),
+        Some(_) => SimulationError::FatalError(format!(
+            "Failed to get code for contract. Invalid response from RPC: {:?}",
+            err
+        )),
+    }
 }
 
 /// Converts a hexadecimal string into a fixed-size 32-byte array.
@@ -436,10 +359,13 @@ pub fn json_deserialize_be_bigint_list(input: &[u8]) -> Result<Vec<BigInt>, Simu
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+
+    use alloy::providers::ProviderBuilder;
     use dotenv::dotenv;
 
     use super::*;
-    use crate::utils::hexstring_to_vec;
+    use crate::{testing::MockEthRpc, utils::hexstring_to_vec};
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     #[cfg_attr(not(feature = "network_tests"), ignore)]
@@ -449,8 +375,13 @@ mod tests {
             env::var("ETH_RPC_URL").expect("Missing ETH_RPC_URL in .env file")
         });
 
+        let provider = ProviderBuilder::new()
+            .on_builtin(&rpc_url)
+            .await
+            .unwrap();
+
         let address = "0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640";
-        let result = get_code_for_contract(address, Some(rpc_url)).await;
+        let result = get_code_for_contract(address, &provider).await;
 
         assert!(result.is_ok(), "Network call should not fail");
 
@@ -458,11 +389,45 @@ mod tests {
         assert!(!code.bytes().is_empty(), "Code should not be empty");
     }
 
+    #[tokio::test]
+    async fn test_get_code_for_contracts() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let rpc = MockEthRpc::new()
+            .with_code(a, vec![0x60, 0x00])
+            .with_code(b, vec![0x60, 0x01]);
+
+        let code = get_code_for_contracts(&[a, b], &rpc)
+            .await
+            .unwrap();
+        let missing = get_code_for_contract(&Address::repeat_byte(3).to_string(), &rpc).await;
+
+        assert_eq!(code[0].original_bytes(), Bytes::from(vec![0x60, 0x00]));
+        assert_eq!(code[1].original_bytes(), Bytes::from(vec![0x60, 0x01]));
+        assert_eq!(rpc.calls("get_code_batch"), 1);
+        assert!(matches!(missing, Err(SimulationError::FatalError(_))));
+        assert_eq!(rpc.calls("get_code"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_code_for_contracts_partial_response() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let rpc = MockEthRpc::new()
+            .with_code(a, vec![0x60, 0x00])
+            .with_code(b, vec![0x60, 0x01])
+            .with_code_batch_limit(1);
+
+        let code = get_code_for_contracts(&[a, b], &rpc).await;
+
+        assert!(matches!(code, Err(SimulationError::FatalError(_))));
+    }
+
     #[test]
     fn test_maybe_coerce_error_revert_no_gas_info() {
-        let err = SimulationEngineError::TransactionError{
-            data: "0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000011496e76616c6964206f7065726174696f6e000000000000000000000000000000".to_string(),
-            gas_used: None
+        let err = SimulationEngineError::TransactionError {
+            data: "Invalid operation".to_string(),
+            gas_used: None,
+            revert_data: Some(Bytes::from(hexstring_to_vec("0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000011496e76616c6964206f7065726174696f6e000000000000000000000000000000").unwrap())),
+            trace: None,
         };
 
         let result = coerce_error(&err, "test_pool", None);
@@ -477,9 +442,11 @@ mod tests {
     #[test]
     fn test_maybe_coerce_error_out_of_gas() {
         // Test out-of-gas situation with gas limit and gas used provided
-        let err = SimulationEngineError::TransactionError{
-            data: "0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000011496e76616c6964206f7065726174696f6e000000000000000000000000000000".to_string(),
-            gas_used: Some(980)
+        let err = SimulationEngineError::TransactionError {
+            data: "Invalid operation".to_string(),
+            gas_used: Some(980),
+            revert_data: Some(Bytes::from(hexstring_to_vec("0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000011496e76616c6964206f7065726174696f6e000000000000000000000000000000").unwrap())),
+            trace: None,
         };
 
         let result = coerce_error(&err, "test_pool", Some(1000));
@@ -498,6 +465,8 @@ mod tests {
         let err = SimulationEngineError::TransactionError {
             data: "OutOfGas".to_string(),
             gas_used: None,
+            revert_data: None,
+            trace: None,
         };
 
         let result = coerce_error(&err, "test_pool", None);
@@ -530,6 +499,8 @@ mod tests {
         let err = SimulationEngineError::TransactionError {
             data: "Some other error".to_string(),
             gas_used: None,
+            revert_data: None,
+            trace: None,
         };
 
         let result = coerce_error(&err, "test_pool", None);
@@ -0,0 +1,247 @@
+//! Recording and replaying of simulations
+//!
+//! A `SimulationRecorder` attached to a `SimulationEngine` captures, for every simulation, the
+//! parameters, the accounts, storage slots and block hashes read from the database, and the
+//! outcome. A `SimulationRecord` contains everything needed to re-execute the call through
+//! `SimulationEngine::replay`, without access to the original database or an RPC node.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use revm::{
+    primitives::{AccountInfo, Address, Bytecode, Bytes, B256, U256},
+    DatabaseRef,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{
+    engine_db::simulation_db::BlockHeader,
+    simulation::{SimulationEngineError, SimulationParameters, SimulationResult},
+};
+
+/// Everything read from the database during a single simulation
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecordedReads {
+    pub accounts: HashMap<Address, Option<AccountInfo>>,
+    pub storage: HashMap<Address, HashMap<U256, U256>>,
+    pub contracts: HashMap<B256, Bytecode>,
+    pub block_hashes: HashMap<u64, B256>,
+}
+
+/// Outcome of a recorded simulation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedOutput {
+    pub output: Bytes,
+    pub gas_used: u64,
+}
+
+/// A single recorded simulation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SimulationRecord {
+    pub params: SimulationParameters,
+    /// Header of the block the database state reflected at the time of the simulation, if known
+    pub block: Option<BlockHeader>,
+    pub reads: RecordedReads,
+    /// The simulation output, or the error message if it failed
+    pub result: Result<RecordedOutput, String>,
+}
+
+impl SimulationRecord {
+    pub(crate) fn new(
+        params: &SimulationParameters,
+        block: Option<BlockHeader>,
+        reads: RecordedReads,
+        result: &Result<SimulationResult, SimulationEngineError>,
+    ) -> Self {
+        let result = match result {
+            Ok(res) => {
+                Ok(RecordedOutput { output: res.result.clone().into(), gas_used: res.gas_used })
+            }
+            Err(err) => Err(format!("{err:?}")),
+        };
+        Self { params: params.clone(), block, reads, result }
+    }
+}
+
+#[derive(Debug)]
+enum RecordSink {
+    Memory(Vec<SimulationRecord>),
+    File(BufWriter<File>),
+}
+
+/// Collects `SimulationRecord`s, either in memory or appended as JSON lines to a file.
+///
+/// Clones share the same sink, so a recorder can be attached to several engines.
+#[derive(Clone, Debug)]
+pub struct SimulationRecorder {
+    sink: Arc<Mutex<RecordSink>>,
+}
+
+impl SimulationRecorder {
+    /// Creates a recorder keeping all records in memory, see `records`.
+    pub fn in_memory() -> Self {
+        Self { sink: Arc::new(Mutex::new(RecordSink::Memory(Vec::new()))) }
+    }
+
+    /// Creates a recorder appending one JSON encoded record per line to the file at `path`.
+    pub fn to_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self { sink: Arc::new(Mutex::new(RecordSink::File(BufWriter::new(file)))) })
+    }
+
+    /// Returns the records collected so far. Always empty for file backed recorders.
+    pub fn records(&self) -> Vec<SimulationRecord> {
+        match &*self.sink.lock().unwrap() {
+            RecordSink::Memory(records) => records.clone(),
+            RecordSink::File(_) => Vec::new(),
+        }
+    }
+
+    /// Reads all records from a JSON lines file written by a file backed recorder.
+    pub fn read_file(path: impl AsRef<Path>) -> io::Result<Vec<SimulationRecord>> {
+        BufReader::new(File::open(path)?)
+            .lines()
+            .filter(|line| {
+                line.as_ref()
+                    .map_or(true, |l| !l.trim().is_empty())
+            })
+            .map(|line| serde_json::from_str(&line?).map_err(io::Error::from))
+            .collect()
+    }
+
+    pub(crate) fn record(&self, record: SimulationRecord) {
+        match &mut *self.sink.lock().unwrap() {
+            RecordSink::Memory(records) => records.push(record),
+            RecordSink::File(writer) => {
+                let res = serde_json::to_writer(&mut *writer, &record)
+                    .map_err(io::Error::from)
+                    .and_then(|_| writer.write_all(b"\n"))
+                    .and_then(|_| writer.flush());
+                if let Err(e) = res {
+                    warn!(error = %e, "Failed to write simulation record");
+                }
+            }
+        }
+    }
+}
+
+/// A database wrapper logging every successful read from the wrapped database.
+pub(crate) struct RecordingDB<'a, DB: DatabaseRef> {
+    inner_db: &'a DB,
+    reads: RefCell<RecordedReads>,
+}
+
+impl<'a, DB: DatabaseRef> RecordingDB<'a, DB> {
+    pub(crate) fn new(inner_db: &'a DB) -> Self {
+        Self { inner_db, reads: RefCell::new(RecordedReads::default()) }
+    }
+
+    pub(crate) fn into_reads(self) -> RecordedReads {
+        self.reads.into_inner()
+    }
+}
+
+impl<DB: DatabaseRef> DatabaseRef for RecordingDB<'_, DB> {
+    type Error = DB::Error;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        let info = self.inner_db.basic_ref(address)?;
+        self.reads
+            .borrow_mut()
+            .accounts
+            .insert(address, info.clone());
+        Ok(info)
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        let code = self
+            .inner_db
+            .code_by_hash_ref(code_hash)?;
+        self.reads
+            .borrow_mut()
+            .contracts
+            .insert(code_hash, code.clone());
+        Ok(code)
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        let value = self
+            .inner_db
+            .storage_ref(address, index)?;
+        self.reads
+            .borrow_mut()
+            .storage
+            .entry(address)
+            .or_default()
+            .insert(index, value);
+        Ok(value)
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        let hash = self.inner_db.block_hash_ref(number)?;
+        self.reads
+            .borrow_mut()
+            .block_hashes
+            .insert(number, hash);
+        Ok(hash)
+    }
+}
+
+/// A database serving only the reads of a `SimulationRecord`. Anything not recorded is an error.
+pub(crate) struct ReplayDB<'a> {
+    reads: &'a RecordedReads,
+}
+
+impl<'a> ReplayDB<'a> {
+    pub(crate) fn new(reads: &'a RecordedReads) -> Self {
+        Self { reads }
+    }
+}
+
+impl DatabaseRef for ReplayDB<'_> {
+    type Error = String;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.reads
+            .accounts
+            .get(&address)
+            .cloned()
+            .ok_or_else(|| format!("Account {address} was not read during recording"))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        self.reads
+            .contracts
+            .get(&code_hash)
+            .cloned()
+            .ok_or_else(|| format!("Code {code_hash} was not read during recording"))
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.reads
+            .storage
+            .get(&address)
+            .and_then(|slots| slots.get(&index))
+            .copied()
+            .ok_or_else(|| {
+                format!("Storage slot {index} of {address} was not read during recording")
+            })
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.reads
+            .block_hashes
+            .get(&number)
+            .copied()
+            .ok_or_else(|| format!("Hash of block {number} was not read during recording"))
+    }
+}
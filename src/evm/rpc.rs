@@ -0,0 +1,364 @@
+//! Node access of the VM
+//!
+//! `SimulationDB` and the VM pool setup request accounts, storage and contract code from a node
+//! through the `EthRpc` trait. Every alloy `Provider` implements it, and `EnvRpc` connects to the
+//! node at the `RPC_URL` environment variable, which is the default where no client is injected.
+//!
+//! Implement `EthRpc` to route requests through a custom client, e.g. one adding authentication
+//! headers or rate limiting, and pass it to `ProtocolStreamBuilder::rpc_client`.
+use std::{env, fmt::Debug};
+
+use alloy::{
+    eips::BlockId,
+    providers::{Provider, ProviderBuilder, RootProvider},
+    rpc::client::BatchRequest,
+    transports::BoxTransport,
+};
+use alloy_primitives::{Address, Bytes, U256};
+use futures::future::{try_join_all, BoxFuture};
+use tokio::sync::OnceCell;
+
+/// Error of a request to the node
+pub type EthRpcError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Requests to an Ethereum node
+///
+/// Requests take the number of the block to query, `None` for the latest block.
+pub trait EthRpc: Debug + Send + Sync {
+    /// Returns the runtime code of `address`, empty for accounts without code.
+    fn get_code(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<Bytes, EthRpcError>>;
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        index: U256,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<U256, EthRpcError>>;
+
+    fn get_balance(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<U256, EthRpcError>>;
+
+    fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<u64, EthRpcError>>;
+
+    /// Returns the runtime code of each of `addresses`, in order.
+    ///
+    /// Sends the requests concurrently by default. Override it to send them as a single batch.
+    fn get_code_batch<'a>(
+        &'a self,
+        addresses: &'a [Address],
+        block: Option<u64>,
+    ) -> BoxFuture<'a, Result<Vec<Bytes>, EthRpcError>> {
+        Box::pin(try_join_all(
+            addresses
+                .iter()
+                .map(|address| self.get_code(*address, block)),
+        ))
+    }
+
+    /// Returns the value of each of the `(address, index)` storage slots, in order.
+    ///
+    /// Sends the requests concurrently by default. Override it to send them as a single batch.
+    fn get_storage_batch<'a>(
+        &'a self,
+        slots: &'a [(Address, U256)],
+        block: Option<u64>,
+    ) -> BoxFuture<'a, Result<Vec<U256>, EthRpcError>> {
+        Box::pin(try_join_all(
+            slots
+                .iter()
+                .map(|(address, index)| self.get_storage_at(*address, *index, block)),
+        ))
+    }
+}
+
+impl<P: Provider + Debug> EthRpc for P {
+    fn get_code(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<Bytes, EthRpcError>> {
+        Box::pin(async move {
+            let mut request = self.get_code_at(address);
+            if let Some(number) = block {
+                request = request.number(number);
+            }
+            Ok(request.await?)
+        })
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        index: U256,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+        Box::pin(async move {
+            let mut request = Provider::get_storage_at(self, address, index);
+            if let Some(number) = block {
+                request = request.number(number);
+            }
+            Ok(request.await?)
+        })
+    }
+
+    fn get_balance(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+        Box::pin(async move {
+            let mut request = Provider::get_balance(self, address);
+            if let Some(number) = block {
+                request = request.number(number);
+            }
+            Ok(request.await?)
+        })
+    }
+
+    fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<u64, EthRpcError>> {
+        Box::pin(async move {
+            let mut request = Provider::get_transaction_count(self, address);
+            if let Some(number) = block {
+                request = request.number(number);
+            }
+            Ok(request.await?)
+        })
+    }
+
+    /// Sends the requests as a single JSON-RPC batch.
+    fn get_code_batch<'a>(
+        &'a self,
+        addresses: &'a [Address],
+        block: Option<u64>,
+    ) -> BoxFuture<'a, Result<Vec<Bytes>, EthRpcError>> {
+        Box::pin(async move {
+            if addresses.is_empty() {
+                return Ok(Vec::new());
+            }
+            let block = block_id(block);
+            let mut batch = BatchRequest::new(self.client());
+            let waiters = addresses
+                .iter()
+                .map(|address| batch.add_call::<_, Bytes>("eth_getCode", &(*address, block)))
+                .collect::<Result<Vec<_>, _>>()?;
+            batch.send().await?;
+            Ok(try_join_all(waiters).await?)
+        })
+    }
+
+    /// Sends the requests as a single JSON-RPC batch.
+    fn get_storage_batch<'a>(
+        &'a self,
+        slots: &'a [(Address, U256)],
+        block: Option<u64>,
+    ) -> BoxFuture<'a, Result<Vec<U256>, EthRpcError>> {
+        Box::pin(async move {
+            if slots.is_empty() {
+                return Ok(Vec::new());
+            }
+            let block = block_id(block);
+            let mut batch = BatchRequest::new(self.client());
+            let waiters = slots
+                .iter()
+                .map(|(address, index)| {
+                    batch.add_call::<_, U256>("eth_getStorageAt", &(*address, *index, block))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            batch.send().await?;
+            Ok(try_join_all(waiters).await?)
+        })
+    }
+}
+
+fn block_id(block: Option<u64>) -> BlockId {
+    block.map_or(BlockId::latest(), BlockId::number)
+}
+
+/// Client of the node at the `RPC_URL` environment variable
+///
+/// Connects on the first request, which fails if the variable isn't set.
+#[derive(Debug, Default)]
+pub struct EnvRpc {
+    provider: OnceCell<RootProvider<BoxTransport>>,
+}
+
+impl EnvRpc {
+    async fn provider(&self) -> Result<&RootProvider<BoxTransport>, EthRpcError> {
+        self.provider
+            .get_or_try_init(|| async {
+                let url = env::var("RPC_URL")
+                    .map_err(|_| EthRpcError::from("RPC_URL environment variable is not set"))?;
+                Ok::<_, EthRpcError>(
+                    ProviderBuilder::new()
+                        .on_builtin(&url)
+                        .await?,
+                )
+            })
+            .await
+    }
+}
+
+impl EthRpc for EnvRpc {
+    fn get_code(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<Bytes, EthRpcError>> {
+        Box::pin(async move { EthRpc::get_code(self.provider().await?, address, block).await })
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        index: U256,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+        Box::pin(async move {
+            EthRpc::get_storage_at(self.provider().await?, address, index, block).await
+        })
+    }
+
+    fn get_balance(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+        Box::pin(async move { EthRpc::get_balance(self.provider().await?, address, block).await })
+    }
+
+    fn get_transaction_count(
+        &self,
+        address: Address,
+        block: Option<u64>,
+    ) -> BoxFuture<'_, Result<u64, EthRpcError>> {
+        Box::pin(async move {
+            EthRpc::get_transaction_count(self.provider().await?, address, block).await
+        })
+    }
+
+    fn get_code_batch<'a>(
+        &'a self,
+        addresses: &'a [Address],
+        block: Option<u64>,
+    ) -> BoxFuture<'a, Result<Vec<Bytes>, EthRpcError>> {
+        Box::pin(
+            async move { EthRpc::get_code_batch(self.provider().await?, addresses, block).await },
+        )
+    }
+
+    fn get_storage_batch<'a>(
+        &'a self,
+        slots: &'a [(Address, U256)],
+        block: Option<u64>,
+    ) -> BoxFuture<'a, Result<Vec<U256>, EthRpcError>> {
+        Box::pin(
+            async move { EthRpc::get_storage_batch(self.provider().await?, slots, block).await },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::MockEthRpc;
+
+    /// Implements single requests only, so batches use the default implementations
+    #[derive(Debug)]
+    struct Unbatched(MockEthRpc);
+
+    impl EthRpc for Unbatched {
+        fn get_code(
+            &self,
+            address: Address,
+            block: Option<u64>,
+        ) -> BoxFuture<'_, Result<Bytes, EthRpcError>> {
+            self.0.get_code(address, block)
+        }
+
+        fn get_storage_at(
+            &self,
+            address: Address,
+            index: U256,
+            block: Option<u64>,
+        ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+            self.0
+                .get_storage_at(address, index, block)
+        }
+
+        fn get_balance(
+            &self,
+            address: Address,
+            block: Option<u64>,
+        ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+            self.0.get_balance(address, block)
+        }
+
+        fn get_transaction_count(
+            &self,
+            address: Address,
+            block: Option<u64>,
+        ) -> BoxFuture<'_, Result<u64, EthRpcError>> {
+            self.0
+                .get_transaction_count(address, block)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_batches_send_single_requests() {
+        let (a, b) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let rpc = Unbatched(
+            MockEthRpc::new()
+                .with_code(a, vec![0x60, 0x00])
+                .with_storage(b, U256::from(3), U256::from(42)),
+        );
+
+        let code = rpc
+            .get_code_batch(&[a, b], None)
+            .await
+            .unwrap();
+        let storage = rpc
+            .get_storage_batch(&[(b, U256::from(3)), (b, U256::from(4))], Some(10))
+            .await
+            .unwrap();
+
+        assert_eq!(code, vec![Bytes::from(vec![0x60, 0x00]), Bytes::new()]);
+        assert_eq!(storage, vec![U256::from(42), U256::ZERO]);
+        assert_eq!(rpc.0.calls("get_code"), 2);
+        assert_eq!(rpc.0.calls("get_storage_at"), 2);
+        assert_eq!(rpc.0.calls("get_code_batch"), 0);
+        assert_eq!(rpc.0.calls("get_storage_batch"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_dyn_rpc_batches() {
+        let a = Address::repeat_byte(1);
+        let mock = Arc::new(MockEthRpc::new().with_code(a, vec![0x00]));
+        let rpc: Arc<dyn EthRpc> = mock.clone();
+
+        let code = rpc
+            .get_code_batch(&[a, a, a], None)
+            .await
+            .unwrap();
+
+        assert_eq!(code.len(), 3);
+        assert_eq!(mock.calls("get_code_batch"), 1);
+        assert_eq!(mock.calls("get_code"), 0);
+    }
+}
@@ -1,28 +1,43 @@
-use std::{clone::Clone, collections::HashMap, default::Default, fmt::Debug};
+use std::{
+    clone::Clone,
+    collections::HashMap,
+    default::Default,
+    fmt::Debug,
+    time::{Duration, Instant},
+};
 
 use alloy_primitives::U256;
+use alloy_sol_types::SolValue;
 use foundry_config::{Chain, Config};
 use foundry_evm::traces::{SparsedTraceArena, TraceKind};
 use revm::{
     inspector_handle_register,
-    interpreter::{return_ok, InstructionResult},
+    interpreter::{return_ok, InstructionResult, Interpreter},
     primitives::{
-        alloy_primitives, bytes, Address, BlockEnv, EVMError, EVMResult, EvmState, ExecutionResult,
-        Output, ResultAndState, SpecId, TransactTo, TxEnv,
+        alloy_primitives, bytes, Address, BlockEnv, Bytes, EVMError, EVMResult, EvmState,
+        ExecutionResult, Log, Output, ResultAndState, SpecId, TransactTo, TxEnv,
     },
-    DatabaseRef, Evm,
+    Database, DatabaseRef, Evm, EvmContext, Inspector,
 };
 use revm_inspectors::tracing::{TracingInspector, TracingInspectorConfig};
+use serde::{Deserialize, Serialize};
 use strum_macros::Display;
 use tokio::runtime::{Handle, Runtime};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use super::{
     account_storage::StateUpdate,
-    traces::{handle_traces, TraceResult},
+    erc20::{ERC20OverwriteFactory, ERC20Slots},
+    metrics::recorder as metrics_recorder,
+    recorder::{RecordingDB, ReplayDB, SimulationRecord, SimulationRecorder},
+    traces::{handle_traces, CallTrace, TraceResult},
+    tycho_models,
 };
-use crate::evm::engine_db::{
-    engine_db_interface::EngineDatabaseInterface, simulation_db::OverriddenSimulationDB,
+use crate::evm::{
+    engine_db::{
+        engine_db_interface::EngineDatabaseInterface, simulation_db::OverriddenSimulationDB,
+    },
+    ContractCompiler,
 };
 
 /// An error representing any transaction simulation result other than successful execution
@@ -34,8 +49,43 @@ pub enum SimulationEngineError {
     /// Gas limit has been reached. Retrying while increasing gas limit or waiting for a gas price
     /// reduction may help.
     OutOfGas(String, String),
-    /// Simulation didn't succeed; likely not related to network or gas, so retrying won't help
-    TransactionError { data: String, gas_used: Option<u64> },
+    /// Simulation didn't succeed; likely not related to network or gas, so retrying won't help.
+    ///
+    /// For reverts, `data` holds the decoded reason if the payload is a standard `Error(string)`
+    /// or `Panic(uint256)`, and the hex encoded payload otherwise. The raw payload is kept in
+    /// `revert_data`, which is `None` for halts and other non-revert failures.
+    ///
+    /// `trace` holds the call trace up to the failure if the transaction was executed through
+    /// `SimulationEngine::simulate_with_trace`.
+    TransactionError {
+        data: String,
+        gas_used: Option<u64>,
+        revert_data: Option<Bytes>,
+        trace: Option<Box<CallTrace>>,
+    },
+    /// The simulation didn't finish within `SimulationParameters::timeout`.
+    Timeout(Duration),
+    /// The block of the state doesn't match `SimulationParameters::block_number`, see
+    /// `BlockMismatchPolicy`.
+    BlockMismatch { state_block: u64, params_block: u64 },
+}
+
+/// How `SimulationEngine::simulate` handles a `SimulationParameters::block_number` different from
+/// the block of its state
+///
+/// The parameters' block is what the transaction sees, the state's block is the one storage is
+/// read at. They legitimately differ when simulating on top of the next block, or on Arbitrum,
+/// where contracts see the L1 block number. When reproducing a historical `eth_call` with an
+/// archival `SimulationDB`, a mismatch usually means storage is read at the wrong block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockMismatchPolicy {
+    /// Simulate without checking
+    #[default]
+    Ignore,
+    /// Log a warning and simulate
+    Warn,
+    /// Fail with `SimulationEngineError::BlockMismatch`
+    Error,
 }
 
 /// A result of a successful transaction simulation
@@ -47,6 +97,8 @@ pub struct SimulationResult {
     pub state_updates: HashMap<Address, StateUpdate>,
     /// Gas used by the transaction (already reduced by the refunded gas)
     pub gas_used: u64,
+    /// Logs emitted by the transaction, in execution order
+    pub logs: Vec<Log>,
 }
 
 /// Simulation engine
@@ -58,6 +110,14 @@ where
 {
     pub state: D,
     pub trace: bool,
+    /// If set, every simulation is recorded so it can be replayed later
+    pub recorder: Option<SimulationRecorder>,
+    /// How simulations at a different block than the state's are handled
+    pub block_mismatch: BlockMismatchPolicy,
+    /// EVM version the transactions are executed with, e.g. to reject `PUSH0` before Shanghai
+    pub spec_id: SpecId,
+    /// Chain id the `CHAINID` opcode returns
+    pub chain_id: u64,
 }
 
 impl<D: EngineDatabaseInterface + Clone + Debug> SimulationEngine<D>
@@ -71,17 +131,212 @@ where
     ///
     /// * `state` - Database reference to be used for simulation
     /// * `trace` - Whether to print the entire execution trace
+    ///
+    /// Simulates Ethereum mainnet, see `with_chain`.
     pub fn new(state: D, trace: bool) -> Self {
-        Self { state, trace }
+        let chain = tycho_models::Chain::Ethereum;
+        Self {
+            state,
+            trace,
+            recorder: None,
+            block_mismatch: BlockMismatchPolicy::default(),
+            spec_id: chain.spec_id(),
+            chain_id: chain.id(),
+        }
+    }
+
+    /// Records all subsequent simulations with the given recorder.
+    pub fn with_recorder(mut self, recorder: SimulationRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Sets how simulations whose block differs from the state's block are handled.
+    pub fn with_block_mismatch(mut self, policy: BlockMismatchPolicy) -> Self {
+        self.block_mismatch = policy;
+        self
+    }
+
+    /// Simulates `chain`: sets its chain id and the EVM version of its latest hardfork.
+    pub fn with_chain(mut self, chain: tycho_models::Chain) -> Self {
+        self.spec_id = chain.spec_id();
+        self.chain_id = chain.id();
+        self
+    }
+
+    /// Sets the EVM version, e.g. to simulate blocks before the chain's latest hardfork.
+    pub fn with_spec_id(mut self, spec_id: SpecId) -> Self {
+        self.spec_id = spec_id;
+        self
+    }
+
+    /// Sets the chain id the `CHAINID` opcode returns.
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
     }
 
     /// Simulate a transaction
     ///
-    /// State's block will be modified to be the last block before the simulation's block.
+    /// State's block will be modified to be the last block before the simulation's block. The
+    /// duration and outcome are reported to the global `MetricsRecorder`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationEngineError::Timeout` if `params.timeout` is set and exceeded, and
+    /// `SimulationEngineError::BlockMismatch` if `params.block_number` differs from the state's
+    /// block under `BlockMismatchPolicy::Error`.
     pub fn simulate(
         &self,
         params: &SimulationParameters,
     ) -> Result<SimulationResult, SimulationEngineError> {
+        self.check_block(params)?;
+        let start = Instant::now();
+        let result = match &self.recorder {
+            Some(recorder) => {
+                let db = RecordingDB::new(&self.state);
+                let result = self.transact(&db, params);
+                recorder.record(SimulationRecord::new(
+                    params,
+                    self.state.block(),
+                    db.into_reads(),
+                    &result,
+                ));
+                result
+            }
+            None => self.transact(&self.state, params),
+        };
+        metrics_recorder().on_simulation(start.elapsed(), result.is_ok());
+        result
+    }
+
+    /// Re-executes a recorded simulation using only the recorded reads.
+    ///
+    /// The engine's own database is not accessed. Reading anything that wasn't read during
+    /// recording results in a `SimulationEngineError::StorageError`.
+    pub fn replay(
+        &self,
+        record: &SimulationRecord,
+    ) -> Result<SimulationResult, SimulationEngineError> {
+        self.transact(&ReplayDB::new(&record.reads), &record.params)
+    }
+
+    /// Simulate a transaction and return its call trace
+    ///
+    /// Unlike `simulate`, the trace is always collected, independent of the `trace` flag, and
+    /// the simulation is not recorded.
+    ///
+    /// # Errors
+    ///
+    /// Same as `simulate`. If the transaction reverts or halts, the returned
+    /// `SimulationEngineError::TransactionError` holds the trace up to the failure.
+    pub fn simulate_with_trace(
+        &self,
+        params: &SimulationParameters,
+    ) -> Result<(SimulationResult, CallTrace), SimulationEngineError> {
+        let mut tracer = TracingInspector::new(TracingInspectorConfig::default());
+        let evm_result = self.execute(&self.state, params, Some(&mut tracer), None);
+        // Nothing was traced if the transaction couldn't be executed at all
+        let trace = evm_result
+            .is_ok()
+            .then(|| CallTrace::from_arena(tracer.traces()));
+
+        match interpret_evm_result(evm_result) {
+            Ok(result) => Ok((result, trace.unwrap_or_default())),
+            Err(SimulationEngineError::TransactionError {
+                data, gas_used, revert_data, ..
+            }) => Err(SimulationEngineError::TransactionError {
+                data,
+                gas_used,
+                revert_data,
+                trace: trace.map(Box::new),
+            }),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Simulate a transaction and return its raw trace arena alongside the result
+    ///
+    /// Like `simulate_with_trace`, the trace is collected independent of the `trace` flag and the
+    /// simulation is not recorded. The arena is what the `trace` flag prints, returned instead,
+    /// e.g. to attach it to error reports or render it with foundry's trace decoders. It holds
+    /// the trace up to the failure for reverts and halts, and is `None` if the transaction
+    /// couldn't be executed at all.
+    pub fn simulate_with_trace_arena(
+        &self,
+        params: &SimulationParameters,
+    ) -> (Result<SimulationResult, SimulationEngineError>, Option<SparsedTraceArena>) {
+        if let Err(err) = self.check_block(params) {
+            return (Err(err), None);
+        }
+        let mut tracer = TracingInspector::new(TracingInspectorConfig::default());
+        let evm_result = self.execute(&self.state, params, Some(&mut tracer), None);
+        let arena = evm_result
+            .is_ok()
+            .then(|| Self::trace_arena(tracer));
+        (interpret_evm_result(evm_result), arena)
+    }
+
+    fn check_block(&self, params: &SimulationParameters) -> Result<(), SimulationEngineError> {
+        let Some(state_block) = self.state.block_number() else {
+            return Ok(());
+        };
+        if state_block == params.block_number {
+            return Ok(());
+        }
+        match self.block_mismatch {
+            BlockMismatchPolicy::Ignore => Ok(()),
+            BlockMismatchPolicy::Warn => {
+                warn!(
+                    state_block,
+                    params_block = params.block_number,
+                    "Simulating at a different block than the state's"
+                );
+                Ok(())
+            }
+            BlockMismatchPolicy::Error => Err(SimulationEngineError::BlockMismatch {
+                state_block,
+                params_block: params.block_number,
+            }),
+        }
+    }
+
+    fn transact<DB: DatabaseRef>(
+        &self,
+        db: &DB,
+        params: &SimulationParameters,
+    ) -> Result<SimulationResult, SimulationEngineError>
+    where
+        DB::Error: std::fmt::Debug,
+    {
+        let evm_result = if self.trace {
+            let mut tracer = TracingInspector::new(TracingInspectorConfig::default());
+            let res = self.execute(db, params, Some(&mut tracer), None);
+            if let Ok(result) = res.as_ref() {
+                Self::print_traces(tracer, result)
+            }
+            res
+        } else if let Some(timeout) = params.timeout {
+            let mut watchdog = Watchdog::new(timeout);
+            let res = self.execute(db, params, None, Some(&mut watchdog));
+            if watchdog.timed_out {
+                return Err(SimulationEngineError::Timeout(timeout));
+            }
+            res
+        } else {
+            self.execute(db, params, None, None)
+        };
+
+        interpret_evm_result(evm_result)
+    }
+
+    fn execute<DB: DatabaseRef>(
+        &self,
+        db: &DB,
+        params: &SimulationParameters,
+        tracer: Option<&mut TracingInspector>,
+        watchdog: Option<&mut Watchdog>,
+    ) -> EVMResult<DB::Error> {
         // We allocate a new EVM so we can work with a simple referenced DB instead of a fully
         // concurrently save shared reference and write locked object. Note that concurrently
         // calling this method is therefore not possible.
@@ -91,11 +346,12 @@ where
 
         // We protect the state from being consumed.
         let db_ref = OverriddenSimulationDB {
-            inner_db: &self.state,
+            inner_db: db,
             overrides: &params
                 .overrides
                 .clone()
                 .unwrap_or_default(),
+            balance_overrides: params.balance_overrides.as_ref(),
         };
 
         let tx_env = TxEnv {
@@ -116,43 +372,51 @@ where
         };
 
         let default_builder = Evm::builder()
-            .with_spec_id(SpecId::CANCUN)
+            .with_spec_id(self.spec_id)
             .with_ref_db(db_ref)
             .with_block_env(block_env)
-            .with_tx_env(tx_env);
+            .with_tx_env(tx_env)
+            .modify_cfg_env(|cfg| cfg.chain_id = self.chain_id);
 
-        let evm_result = if self.trace {
-            let mut tracer = TracingInspector::new(TracingInspectorConfig::default());
-            let res = {
+        match (tracer, watchdog) {
+            (Some(tracer), _) => {
                 let mut vm = default_builder
-                    .with_external_context(&mut tracer)
+                    .with_external_context(tracer)
                     .append_handler_register(inspector_handle_register)
                     .build();
 
                 debug!("Starting simulation with tx parameters: {:#?} {:#?}", vm.tx(), vm.block());
                 vm.transact()
-            };
-
-            if let Ok(result) = res.as_ref() {
-                Self::print_traces(tracer, result)
             }
+            (None, Some(watchdog)) => {
+                let mut vm = default_builder
+                    .with_external_context(watchdog)
+                    .append_handler_register(inspector_handle_register)
+                    .build();
 
-            res
-        } else {
-            let mut vm = default_builder.build();
-
-            debug!("Starting simulation with tx parameters: {:#?} {:#?}", vm.tx(), vm.block());
-
-            vm.transact()
-        };
+                debug!("Starting simulation with tx parameters: {:#?} {:#?}", vm.tx(), vm.block());
+                vm.transact()
+            }
+            (None, None) => {
+                let mut vm = default_builder.build();
 
-        interpret_evm_result(evm_result)
+                debug!("Starting simulation with tx parameters: {:#?} {:#?}", vm.tx(), vm.block());
+                vm.transact()
+            }
+        }
     }
 
     pub fn clear_temp_storage(&mut self) {
         self.state.clear_temp_storage();
     }
 
+    fn trace_arena(tracer: TracingInspector) -> SparsedTraceArena {
+        SparsedTraceArena {
+            arena: tracer.into_traces(),
+            ignored: alloy_primitives::map::HashMap::default(),
+        }
+    }
+
     fn print_traces(tracer: TracingInspector, res: &ResultAndState) {
         let ResultAndState { result, state: _ } = res;
         let (exit_reason, _gas_refunded, gas_used, _out, _exec_logs) = match result.clone() {
@@ -170,13 +434,7 @@ where
 
         let trace_res = TraceResult {
             success: matches!(exit_reason, return_ok!()),
-            traces: Some(vec![(
-                TraceKind::Execution,
-                SparsedTraceArena {
-                    arena: tracer.into_traces(),
-                    ignored: alloy_primitives::map::HashMap::default(),
-                },
-            )]),
+            traces: Some(vec![(TraceKind::Execution, Self::trace_arena(tracer))]),
             gas_used,
         };
 
@@ -198,6 +456,37 @@ where
     }
 }
 
+/// Number of instructions between two checks of the `Watchdog`'s deadline
+const WATCHDOG_CHECK_INTERVAL: u64 = 1_000;
+
+/// Inspector halting the EVM once a deadline passed
+///
+/// revm can't be interrupted from the outside, so the interpreter checks the deadline itself
+/// between instructions. Once it passed, every remaining call frame halts at its next instruction.
+struct Watchdog {
+    deadline: Instant,
+    steps: u64,
+    timed_out: bool,
+}
+
+impl Watchdog {
+    fn new(timeout: Duration) -> Self {
+        Self { deadline: Instant::now() + timeout, steps: 0, timed_out: false }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for Watchdog {
+    fn step(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        self.steps += 1;
+        if !self.timed_out && self.steps % WATCHDOG_CHECK_INTERVAL == 0 {
+            self.timed_out = Instant::now() >= self.deadline;
+        }
+        if self.timed_out {
+            interp.instruction_result = InstructionResult::OutOfGas;
+        }
+    }
+}
+
 /// Convert a complex EVMResult into a simpler structure
 ///
 /// EVMResult is not of an error type even if the transaction was not successful.
@@ -218,19 +507,29 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
 ) -> Result<SimulationResult, SimulationEngineError> {
     match evm_result {
         Ok(result_and_state) => match result_and_state.result {
-            ExecutionResult::Success { gas_used, gas_refunded, output, .. } => {
-                Ok(interpret_evm_success(gas_used, gas_refunded, output, result_and_state.state))
-            }
+            ExecutionResult::Success { gas_used, gas_refunded, output, logs, .. } => Ok(
+                interpret_evm_success(gas_used, gas_refunded, output, logs, result_and_state.state),
+            ),
             ExecutionResult::Revert { output, gas_used } => {
+                let data = if output.is_empty() {
+                    "Reverted without reason".to_string()
+                } else {
+                    decode_revert_reason(&output)
+                        .unwrap_or_else(|| format!("0x{}", hex::encode(&output)))
+                };
                 Err(SimulationEngineError::TransactionError {
-                    data: format!("0x{}", hex::encode(output)),
+                    data,
                     gas_used: Some(gas_used),
+                    revert_data: Some(output),
+                    trace: None,
                 })
             }
             ExecutionResult::Halt { reason, gas_used } => {
                 Err(SimulationEngineError::TransactionError {
                     data: format!("{:?}", reason),
                     gas_used: Some(gas_used),
+                    revert_data: None,
+                    trace: None,
                 })
             }
         },
@@ -238,6 +537,8 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
             EVMError::Transaction(invalid_tx) => Err(SimulationEngineError::TransactionError {
                 data: format!("EVM error: {invalid_tx:?}"),
                 gas_used: None,
+                revert_data: None,
+                trace: None,
             }),
             EVMError::Database(db_error) => {
                 info!("Are we at database error? {:?}", &db_error);
@@ -246,24 +547,75 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
             EVMError::Custom(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
                 gas_used: None,
+                revert_data: None,
+                trace: None,
             }),
             EVMError::Header(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
                 gas_used: None,
+                revert_data: None,
+                trace: None,
             }),
             EVMError::Precompile(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
                 gas_used: None,
+                revert_data: None,
+                trace: None,
             }),
         },
     }
 }
 
+/// Decode a standard Solidity revert payload into a human-readable reason
+///
+/// Handles `Error(string)` (selector `0x08c379a0`) and `Panic(uint256)` (selector `0x4e487b71`).
+///
+/// # Returns
+///
+/// The revert message or the name of the panic code. `None` if the payload has neither shape,
+/// e.g. for custom errors or empty reverts.
+pub fn decode_revert_reason(output: &[u8]) -> Option<String> {
+    if output.len() < 4 {
+        return None;
+    }
+    let (selector, payload) = output.split_at(4);
+    match selector {
+        [0x08, 0xc3, 0x79, 0xa0] => String::abi_decode(payload, true).ok(),
+        [0x4e, 0x48, 0x7b, 0x71] => {
+            let code = U256::abi_decode(payload, true).ok()?;
+            Some(
+                get_solidity_panic_codes()
+                    .get(&code.as_limbs()[0])
+                    .cloned()
+                    .unwrap_or_else(|| format!("Panic({})", code)),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Names of the panic codes emitted by the Solidity compiler via `Panic(uint256)`
+pub fn get_solidity_panic_codes() -> HashMap<u64, String> {
+    let mut panic_codes = HashMap::new();
+    panic_codes.insert(0, "GenericCompilerPanic".to_string());
+    panic_codes.insert(1, "AssertionError".to_string());
+    panic_codes.insert(17, "ArithmeticOver/Underflow".to_string());
+    panic_codes.insert(18, "ZeroDivisionError".to_string());
+    panic_codes.insert(33, "UnknownEnumMember".to_string());
+    panic_codes.insert(34, "BadStorageByteArrayEncoding".to_string());
+    panic_codes.insert(51, "EmptyArray".to_string());
+    panic_codes.insert(0x32, "OutOfBounds".to_string());
+    panic_codes.insert(0x41, "OutOfMemory".to_string());
+    panic_codes.insert(0x51, "BadFunctionPointer".to_string());
+    panic_codes
+}
+
 // Helper function to extract some details from a successful transaction execution
 fn interpret_evm_success(
     gas_used: u64,
     gas_refunded: u64,
     output: Output,
+    logs: Vec<Log>,
     state: EvmState,
 ) -> SimulationResult {
     SimulationResult {
@@ -308,10 +660,11 @@ fn interpret_evm_success(
             account_updates
         },
         gas_used: gas_used - gas_refunded,
+        logs,
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 /// Data needed to invoke a transaction simulation
 pub struct SimulationParameters {
     /// Address of the sending account
@@ -325,12 +678,27 @@ pub struct SimulationParameters {
     /// EVM state overrides.
     /// Will be merged with existing state. Will take effect only for current simulation.
     pub overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
+    /// Native balance overrides, e.g. to fund a pool holding ETH.
+    /// Will take effect only for current simulation.
+    #[serde(default)]
+    pub balance_overrides: Option<HashMap<Address, U256>>,
     /// Limit of gas to be used by the transaction
     pub gas_limit: Option<u64>,
     /// The block number to be used by the transaction. This is independent of the states block.
+    ///
+    /// This is what the `NUMBER` opcode returns. On Arbitrum, contracts expect it to be the
+    /// number of the L1 block the L2 block was built on, not the L2 block number Tycho reports.
     pub block_number: u64,
     /// The timestamp to be used by the transaction
     pub timestamp: u64,
+    /// Wall-clock limit of the simulation, on top of the gas limit
+    ///
+    /// revm can't be interrupted from the outside, so a timed simulation runs the EVM with an
+    /// inspector checking the deadline between instructions, which halts the interpreter once it
+    /// passed. A single slow database read, e.g. an RPC request, isn't interrupted. Not enforced
+    /// while tracing.
+    #[serde(default)]
+    pub timeout: Option<Duration>,
 }
 
 // Converters of fields to revm types
@@ -365,6 +733,56 @@ impl SimulationParameters {
     }
 }
 
+// Helpers to build ERC20 overrides
+impl SimulationParameters {
+    /// Sets the caller's balance of `token` to `amount`.
+    ///
+    /// `slots` and `compiler` describe the token's storage layout, e.g. as found by
+    /// `brute_force_slots`. The override is merged into `overrides`.
+    pub fn fund_caller(
+        mut self,
+        token: Address,
+        slots: ERC20Slots,
+        amount: U256,
+        compiler: ContractCompiler,
+    ) -> Self {
+        let mut factory = ERC20OverwriteFactory::new(token, slots, compiler);
+        factory.set_balance(amount, self.caller);
+        self.merge_overrides(factory.get_overwrites());
+        self
+    }
+
+    /// Sets the allowance of `spender` over the caller's `token` to `amount`.
+    ///
+    /// `slots` and `compiler` describe the token's storage layout, e.g. as found by
+    /// `brute_force_slots`. The override is merged into `overrides`.
+    pub fn approve(
+        mut self,
+        token: Address,
+        slots: ERC20Slots,
+        spender: Address,
+        amount: U256,
+        compiler: ContractCompiler,
+    ) -> Self {
+        let mut factory = ERC20OverwriteFactory::new(token, slots, compiler);
+        factory.set_allowance(amount, spender, self.caller);
+        self.merge_overrides(factory.get_overwrites());
+        self
+    }
+
+    fn merge_overrides(&mut self, overrides: HashMap<Address, HashMap<U256, U256>>) {
+        let merged = self
+            .overrides
+            .get_or_insert_with(HashMap::new);
+        for (address, slots) in overrides {
+            merged
+                .entry(address)
+                .or_default()
+                .extend(slots);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{env, error::Error, str::FromStr, sync::Arc, time::Instant};
@@ -381,11 +799,20 @@ mod tests {
         EvmState as rState, EvmStorageSlot, ExecutionResult, HaltReason, InvalidTransaction,
         OutOfGasError, Output, ResultAndState, SuccessReason, B256,
     };
+    use rstest::rstest;
 
     use super::*;
     use crate::{
-        evm::engine_db::{
-            engine_db_interface::EngineDatabaseInterface, simulation_db::SimulationDB,
+        evm::{
+            engine_db::{
+                create_engine,
+                engine_db_interface::EngineDatabaseInterface,
+                simulation_db::{BlockHeader, SimulationDB},
+                tycho_db::PreCachedDB,
+            },
+            erc20::get_storage_slot_index_at_key,
+            protocol::vm::constants::ERC20_BYTECODE,
+            recorder::RecordedOutput,
         },
         protocol::errors::SimulationError,
     };
@@ -410,9 +837,11 @@ mod tests {
                 .cloned()
                 .collect(),
             ),
+            balance_overrides: None,
             gas_limit: Some(33),
             block_number: 0,
             timestamp: 0,
+            timeout: None,
         };
 
         assert_eq!(params.revm_caller(), Address::from_str(address_string).unwrap());
@@ -451,9 +880,11 @@ mod tests {
             data: Vec::new(),
             value: U256::from(0u64),
             overrides: None,
+            balance_overrides: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
+            timeout: None,
         };
 
         assert_eq!(params.overrides, None);
@@ -462,12 +893,17 @@ mod tests {
 
     #[test]
     fn test_interpret_result_ok_success() {
+        let logs = vec![Log::new_unchecked(
+            Address::ZERO,
+            vec![B256::repeat_byte(1), B256::repeat_byte(2)],
+            Bytes::from_static(b"data"),
+        )];
         let evm_result: EVMResult<TransportError> = Ok(ResultAndState {
             result: ExecutionResult::Success {
                 reason: SuccessReason::Return,
                 gas_used: 100_u64,
                 gas_refunded: 10_u64,
-                logs: Vec::new(),
+                logs: logs.clone(),
                 output: Output::Call(Bytes::from_static(b"output")),
             },
             state: [(
@@ -532,6 +968,7 @@ mod tests {
         .collect();
         assert_eq!(simulation_result.state_updates, expected_state_updates);
         assert_eq!(simulation_result.gas_used, 90);
+        assert_eq!(simulation_result.logs, logs);
     }
 
     #[test]
@@ -549,12 +986,43 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         match err {
-            SimulationEngineError::TransactionError { data: _, gas_used } => {
-                assert_eq!(
-                    format!("0x{}", hex::encode::<Vec<u8>>("output".into())),
-                    "0x6f7574707574"
-                );
+            SimulationEngineError::TransactionError { data, gas_used, revert_data, .. } => {
+                assert_eq!(data, "0x6f7574707574");
+                assert_eq!(gas_used, Some(100));
+                assert_eq!(revert_data, Some(revm::primitives::Bytes::from_static(b"output")));
+            }
+            _ => panic!("Wrong type of SimulationError!"),
+        }
+    }
+
+    #[rstest]
+    #[case::error_string(
+        "08c379a00000000000000000000000000000000000000000000000000000000000000020000000000000000000000000000000000000000000000000000000000000000e416d6f756e7420746f6f206c6f77000000000000000000000000000000000000",
+        "Amount too low"
+    )]
+    #[case::panic(
+        "4e487b710000000000000000000000000000000000000000000000000000000000000011",
+        "ArithmeticOver/Underflow"
+    )]
+    #[case::unknown_panic_code(
+        "4e487b710000000000000000000000000000000000000000000000000000000000000099",
+        "Panic(153)"
+    )]
+    #[case::empty("", "Reverted without reason")]
+    fn test_interpret_result_revert_reason(#[case] output: &str, #[case] expected: &str) {
+        let output = revm::primitives::Bytes::from(hex::decode(output).unwrap());
+        let evm_result: EVMResult<TransportError> = Ok(ResultAndState {
+            result: ExecutionResult::Revert { gas_used: 100_u64, output: output.clone() },
+            state: rState::default(),
+        });
+
+        let result = interpret_evm_result(evm_result);
+
+        match result.err().unwrap() {
+            SimulationEngineError::TransactionError { data, gas_used, revert_data, .. } => {
+                assert_eq!(data, expected);
                 assert_eq!(gas_used, Some(100));
+                assert_eq!(revert_data, Some(output));
             }
             _ => panic!("Wrong type of SimulationError!"),
         }
@@ -575,9 +1043,10 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         match err {
-            SimulationEngineError::TransactionError { data, gas_used } => {
+            SimulationEngineError::TransactionError { data, gas_used, revert_data, .. } => {
                 assert_eq!(data, "OutOfGas(Basic)");
                 assert_eq!(gas_used, Some(100));
+                assert_eq!(revert_data, None);
             }
             _ => panic!("Wrong type of SimulationError!"),
         }
@@ -593,7 +1062,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         match err {
-            SimulationEngineError::TransactionError { data, gas_used } => {
+            SimulationEngineError::TransactionError { data, gas_used, .. } => {
                 assert_eq!(data, "EVM error: PriorityFeeGreaterThanMaxFee");
                 assert_eq!(gas_used, None);
             }
@@ -678,9 +1147,11 @@ mod tests {
             data: encoded,
             value: U256::from(0u64),
             overrides: None,
+            balance_overrides: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
+            timeout: None,
         };
         let eng = SimulationEngine::new(state, true);
 
@@ -715,6 +1186,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[cfg_attr(not(feature = "network_tests"), ignore)]
+    fn test_v2_swap_emits_swap_event() {
+        let engine = SimulationEngine::new(new_state(), false);
+        let caller = Address::from_str("0x0000000000000000000000000000000000004444").unwrap();
+        let router_addr = Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap();
+        let weth_addr = Address::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+        let usdc_addr = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let pair_addr = Address::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
+        let amount_in = U256::from(10).pow(U256::from(18));
+        // WETH9 keeps balances in slot 3 and allowances in slot 4
+        let weth_slots = ERC20Slots::new(U256::from(3), U256::from(4));
+
+        let mut data = alloy_primitives::keccak256(
+            "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)",
+        )[..4]
+            .to_vec();
+        data.extend(
+            (amount_in, U256::ZERO, vec![weth_addr, usdc_addr], caller, U256::MAX)
+                .abi_encode_params(),
+        );
+        let params = SimulationParameters {
+            caller,
+            to: router_addr,
+            data,
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        }
+        .fund_caller(weth_addr, weth_slots.clone(), amount_in, ContractCompiler::Solidity)
+        .approve(weth_addr, weth_slots, router_addr, amount_in, ContractCompiler::Solidity);
+
+        let result = engine.simulate(&params).unwrap();
+
+        let swap_topic =
+            alloy_primitives::keccak256("Swap(address,uint256,uint256,uint256,uint256,address)");
+        let swap = result
+            .logs
+            .iter()
+            .find(|log| log.address == pair_addr && log.topics().first() == Some(&swap_topic))
+            .expect("No Swap event emitted by the pair");
+        // Swap(sender, amount0In, amount1In, amount0Out, amount1Out, to), token1 is WETH
+        let (amount0_in, amount1_in, amount0_out, _) =
+            <(U256, U256, U256, U256)>::abi_decode_params(&swap.data.data, true).unwrap();
+        assert_eq!(amount0_in, U256::ZERO);
+        assert_eq!(amount1_in, amount_in);
+        assert!(amount0_out > U256::ZERO);
+    }
+
     #[test]
     fn test_contract_deployment() -> Result<(), Box<dyn Error>> {
         let readonly_state = new_state();
@@ -806,9 +1330,11 @@ mod tests {
             data: calldata,
             value: U256::from(0u64),
             overrides: Some(overrides),
+            balance_overrides: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
+            timeout: None,
         };
 
         let eng = SimulationEngine::new(state, false);
@@ -834,4 +1360,445 @@ mod tests {
 
         Ok(())
     }
+
+    fn recorded_engine_and_params() -> (SimulationEngine<PreCachedDB>, SimulationParameters) {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false)
+            .unwrap()
+            .with_recorder(SimulationRecorder::in_memory());
+        // Returns the value of storage slot 0
+        let code = Bytecode::new_raw(Bytes::from(hex::decode("60005460005260206000f3").unwrap()));
+        let contract = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        engine.state.init_account(
+            contract,
+            AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+            Some(HashMap::from([(U256::ZERO, U256::from(42))])),
+            true,
+        );
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to: contract,
+            data: Vec::new(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        };
+        (engine, params)
+    }
+
+    #[rstest]
+    #[case::ignore(BlockMismatchPolicy::Ignore, 11, true)]
+    #[case::warn(BlockMismatchPolicy::Warn, 11, true)]
+    #[case::error(BlockMismatchPolicy::Error, 11, false)]
+    #[case::matching(BlockMismatchPolicy::Error, 10, true)]
+    fn test_block_mismatch(
+        #[case] policy: BlockMismatchPolicy,
+        #[case] params_block: u64,
+        #[case] succeeds: bool,
+    ) {
+        let (engine, mut params) = recorded_engine_and_params();
+        engine
+            .state
+            .update(Vec::new(), Some(BlockHeader { number: 10, ..Default::default() }));
+        let engine = engine.with_block_mismatch(policy);
+        params.block_number = params_block;
+
+        let result = engine.simulate(&params);
+
+        if succeeds {
+            assert_eq!(U256::abi_decode(&result.unwrap().result, true).unwrap(), U256::from(42));
+        } else {
+            assert_eq!(
+                result.unwrap_err(),
+                SimulationEngineError::BlockMismatch { state_block: 10, params_block: 11 }
+            );
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let (engine, params) = recorded_engine_and_params();
+        let header = BlockHeader::new(0, B256::repeat_byte(1), 1_700_000_000);
+        engine
+            .state
+            .update(Vec::new(), Some(header));
+
+        let result = engine.simulate(&params).unwrap();
+        let records = engine
+            .recorder
+            .as_ref()
+            .unwrap()
+            .records();
+
+        assert_eq!(records.len(), 1);
+        let record: SimulationRecord =
+            serde_json::from_str(&serde_json::to_string(&records[0]).unwrap()).unwrap();
+        assert_eq!(record.reads.storage[&params.to][&U256::ZERO], U256::from(42));
+        assert_eq!(record.block, Some(header));
+
+        // Replay against an empty database, everything has to come from the record
+        let replay_engine = SimulationEngine::new(PreCachedDB::new().unwrap(), false);
+        let replayed = replay_engine.replay(&record).unwrap();
+
+        assert_eq!(replayed.result, result.result);
+        assert_eq!(replayed.gas_used, result.gas_used);
+        assert_eq!(
+            record.result,
+            Ok(RecordedOutput { output: result.result.into(), gas_used: result.gas_used })
+        );
+    }
+
+    #[test]
+    fn test_replay_missing_read() {
+        let (engine, params) = recorded_engine_and_params();
+        engine.simulate(&params).unwrap();
+        let mut record = engine
+            .recorder
+            .as_ref()
+            .unwrap()
+            .records()[0]
+            .clone();
+        record.reads.storage.clear();
+
+        let result = engine.replay(&record);
+
+        assert!(matches!(result, Err(SimulationEngineError::StorageError(_))));
+    }
+
+    /// An engine with a contract looping forever at `0x...1234`, and parameters calling it with
+    /// plenty of gas.
+    fn looping_engine_and_params() -> (SimulationEngine<PreCachedDB>, SimulationParameters) {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        // JUMPDEST PUSH1 0 JUMP
+        let code = Bytecode::new_raw(Bytes::from(hex::decode("5b600056").unwrap()));
+        let contract = Address::from_str("0x0000000000000000000000000000000000001234").unwrap();
+        engine.state.init_account(
+            contract,
+            AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+            None,
+            true,
+        );
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to: contract,
+            data: Vec::new(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: Some(u64::MAX / 2),
+            block_number: 0,
+            timestamp: 0,
+            timeout: Some(Duration::from_millis(50)),
+        };
+        (engine, params)
+    }
+
+    #[test]
+    fn test_simulate_timeout() {
+        let (engine, params) = looping_engine_and_params();
+
+        let start = Instant::now();
+        let result = engine.simulate(&params);
+
+        assert_eq!(result.unwrap_err(), SimulationEngineError::Timeout(Duration::from_millis(50)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_fund_caller_and_approve() {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        let token = Address::from_str("0x0000000000000000000000000000000000003333").unwrap();
+        let caller = Address::from_str("0x0000000000000000000000000000000000004444").unwrap();
+        let recipient = Address::from_str("0x0000000000000000000000000000000000005555").unwrap();
+        let code = Bytecode::new_raw(Bytes::from(ERC20_BYTECODE));
+        engine.state.init_account(
+            token,
+            AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+            None,
+            true,
+        );
+        engine
+            .state
+            .init_account(caller, AccountInfo::default(), None, true);
+        let amount = U256::from(1_000_000);
+        let slots = ERC20Slots::new(U256::from(0), U256::from(1));
+        let mut data = Keccak256::new();
+        data.update("transferFrom(address,address,uint256)");
+        let mut data = data.finalize()[..4].to_vec();
+        data.extend((caller, recipient, amount).abi_encode());
+        let params = SimulationParameters {
+            caller,
+            to: token,
+            data,
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        }
+        .fund_caller(token, slots.clone(), amount, ContractCompiler::Solidity)
+        .approve(token, slots, caller, amount, ContractCompiler::Solidity);
+
+        assert_eq!(params.overrides.as_ref().unwrap()[&token].len(), 2);
+        let result = engine.simulate(&params).unwrap();
+
+        assert!(bool::abi_decode(&result.result, true).unwrap());
+        let storage = result.state_updates[&token]
+            .storage
+            .clone()
+            .unwrap();
+        let recipient_slot =
+            get_storage_slot_index_at_key(recipient, U256::from(0), ContractCompiler::Solidity);
+        assert_eq!(storage[&recipient_slot], amount);
+    }
+
+    #[test]
+    fn test_simulate_within_timeout() {
+        let (engine, mut params) = recorded_engine_and_params();
+        params.timeout = Some(Duration::from_secs(10));
+
+        let result = engine.simulate(&params).unwrap();
+
+        assert_eq!(U256::abi_decode(&result.result, true).unwrap(), U256::from(42));
+    }
+
+    /// Deploys a pair returning 42 and a router calling the pair and returning its output. If
+    /// `router_reverts` is set, the router reverts after the call instead.
+    fn traced_engine_and_params(
+        router_reverts: bool,
+    ) -> (SimulationEngine<PreCachedDB>, SimulationParameters, Address, Address) {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        let pair = Address::from_str("0x0000000000000000000000000000000000002222").unwrap();
+        let router = Address::from_str("0x0000000000000000000000000000000000001111").unwrap();
+
+        let pair_code =
+            Bytecode::new_raw(Bytes::from(hex::decode("602a60005260206000f3").unwrap()));
+        // CALL(gas, pair, 0, 0, 0, 0, 32), then RETURN or REVERT memory[0..32]
+        let router_code = Bytecode::new_raw(Bytes::from(
+            hex::decode(format!(
+                "6020600060006000600073{}5af15060206000{}",
+                hex::encode(pair),
+                if router_reverts { "fd" } else { "f3" }
+            ))
+            .unwrap(),
+        ));
+        for (address, code) in [(pair, pair_code), (router, router_code)] {
+            engine.state.init_account(
+                address,
+                AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+                None,
+                true,
+            );
+        }
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to: router,
+            data: hex::decode("d06ca61f").unwrap(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        };
+        (engine, params, router, pair)
+    }
+
+    #[test]
+    fn test_simulate_with_trace_nested_call() {
+        let (engine, params, router, pair) = traced_engine_and_params(false);
+
+        let (result, trace) = engine
+            .simulate_with_trace(&params)
+            .unwrap();
+
+        assert_eq!(U256::abi_decode(&result.result, true).unwrap(), U256::from(42));
+        assert_eq!(trace.depth(), 2);
+        assert_eq!(trace.address, router);
+        assert_eq!(trace.selector, Some(alloy_primitives::Selector::new([0xd0, 0x6c, 0xa6, 0x1f])));
+        assert!(trace.success);
+        assert_eq!(trace.children.len(), 1);
+        let pair_call = &trace.children[0];
+        assert_eq!(pair_call.address, pair);
+        assert_eq!(pair_call.selector, None);
+        assert_eq!(U256::abi_decode(&pair_call.output, true).unwrap(), U256::from(42));
+        assert!(pair_call.children.is_empty());
+
+        let trace: CallTrace =
+            serde_json::from_str(&serde_json::to_string(&trace).unwrap()).unwrap();
+        assert_eq!(trace.children[0].address, pair);
+    }
+
+    #[test]
+    fn test_simulate_with_trace_revert() {
+        let (engine, params, router, pair) = traced_engine_and_params(true);
+
+        let err = engine
+            .simulate_with_trace(&params)
+            .unwrap_err();
+
+        match err {
+            SimulationEngineError::TransactionError { trace: Some(trace), .. } => {
+                assert_eq!(trace.depth(), 2);
+                assert_eq!(trace.address, router);
+                assert!(!trace.success);
+                assert_eq!(trace.children[0].address, pair);
+                assert!(trace.children[0].success);
+            }
+            other => panic!("Expected a TransactionError with a trace, got {other:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case::success(false)]
+    #[case::revert(true)]
+    fn test_simulate_with_trace_arena(#[case] router_reverts: bool) {
+        let (engine, params, router, pair) = traced_engine_and_params(router_reverts);
+
+        let (result, arena) = engine.simulate_with_trace_arena(&params);
+
+        assert_eq!(result.is_err(), router_reverts);
+        let arena = arena.unwrap();
+        let nodes = arena.arena.nodes();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].trace.address, router);
+        assert_eq!(nodes[0].trace.success, !router_reverts);
+        assert_eq!(nodes[0].children, vec![1]);
+        assert_eq!(nodes[1].trace.address, pair);
+        assert!(nodes[1].trace.success);
+    }
+
+    #[test]
+    fn test_simulate_with_trace_arena_block_mismatch() {
+        let (engine, mut params, _, _) = traced_engine_and_params(false);
+        let engine = engine.with_block_mismatch(BlockMismatchPolicy::Error);
+        engine
+            .state
+            .update(Vec::new(), Some(BlockHeader { number: 5, ..Default::default() }));
+        params.block_number = 6;
+
+        let (result, arena) = engine.simulate_with_trace_arena(&params);
+
+        assert!(matches!(result, Err(SimulationEngineError::BlockMismatch { .. })));
+        assert!(arena.is_none());
+    }
+
+    /// An engine with a contract running `code`, and parameters calling it
+    fn engine_with_code(code: &str) -> (SimulationEngine<PreCachedDB>, SimulationParameters) {
+        let engine = create_engine(PreCachedDB::new().unwrap(), false).unwrap();
+        let contract = Address::from_str("0x0000000000000000000000000000000000003333").unwrap();
+        let code = Bytecode::new_raw(Bytes::from(hex::decode(code).unwrap()));
+        engine.state.init_account(
+            contract,
+            AccountInfo::new(U256::ZERO, 0, code.hash_slow(), code),
+            None,
+            true,
+        );
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to: contract,
+            data: Vec::new(),
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        };
+        (engine, params)
+    }
+
+    #[rstest]
+    #[case::cancun(SpecId::CANCUN, true)]
+    #[case::shanghai(SpecId::SHANGHAI, true)]
+    #[case::merge(SpecId::MERGE, false)]
+    fn test_spec_id_push0(#[case] spec_id: SpecId, #[case] supports_push0: bool) {
+        // PUSH0, PUSH0, MSTORE, RETURN memory[0..32]
+        let (engine, params) = engine_with_code("5f5f5260205ff3");
+        let engine = engine.with_spec_id(spec_id);
+
+        let res = engine.simulate(&params);
+
+        if supports_push0 {
+            assert_eq!(U256::abi_decode(&res.unwrap().result, true).unwrap(), U256::ZERO);
+        } else {
+            let Err(SimulationEngineError::TransactionError { data, .. }) = res else {
+                panic!("Expected a halt, got {res:?}");
+            };
+            assert_eq!(data, "OpcodeNotFound");
+        }
+    }
+
+    #[rstest]
+    #[case::default(None, 1)]
+    #[case::arbitrum(Some(tycho_models::Chain::Arbitrum), 42161)]
+    fn test_chain_id(#[case] chain: Option<tycho_models::Chain>, #[case] exp: u64) {
+        // CHAINID, PUSH1 0, MSTORE, RETURN memory[0..32]
+        let (mut engine, params) = engine_with_code("4660005260206000f3");
+        if let Some(chain) = chain {
+            engine = engine.with_chain(chain);
+        }
+
+        let res = engine.simulate(&params).unwrap();
+
+        assert_eq!(U256::abi_decode(&res.result, true).unwrap(), U256::from(exp));
+        assert_eq!(engine.spec_id, SpecId::CANCUN);
+    }
+
+    #[rstest]
+    #[case::unbound(None, 1)]
+    #[case::arbitrum(Some(tycho_models::Chain::Arbitrum), 42161)]
+    fn test_create_engine_chain(#[case] chain: Option<tycho_models::Chain>, #[case] exp: u64) {
+        let db = match chain {
+            Some(chain) => PreCachedDB::for_chain(chain).unwrap(),
+            None => PreCachedDB::new().unwrap(),
+        };
+
+        let engine = create_engine(db, false).unwrap();
+
+        assert_eq!(engine.chain_id, exp);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "network_tests"), ignore)]
+    fn test_record_and_replay_v2_get_amounts_out() {
+        let recorder = SimulationRecorder::in_memory();
+        let engine = SimulationEngine::new(new_state(), false).with_recorder(recorder.clone());
+
+        let router_addr = Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap();
+        let weth_addr = Address::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap();
+        let usdc_addr = Address::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap();
+        let mut data =
+            alloy_primitives::keccak256("getAmountsOut(uint256,address[])")[..4].to_vec();
+        data.extend((U256::from(100_000_000), vec![usdc_addr, weth_addr]).abi_encode_params());
+        let params = SimulationParameters {
+            caller: Address::ZERO,
+            to: router_addr,
+            data,
+            value: U256::ZERO,
+            overrides: None,
+            balance_overrides: None,
+            gas_limit: None,
+            block_number: 0,
+            timestamp: 0,
+            timeout: None,
+        };
+
+        let result = engine.simulate(&params).unwrap();
+        let record = recorder.records().pop().unwrap();
+        // No RPC is involved in the replay
+        let replayed = SimulationEngine::new(PreCachedDB::new().unwrap(), false)
+            .replay(&record)
+            .unwrap();
+
+        assert_eq!(replayed.result, result.result);
+        assert_eq!(replayed.gas_used, result.gas_used);
+    }
 }
@@ -1,19 +1,42 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
 
-use futures::{Stream, StreamExt};
-use tokio_stream::wrappers::ReceiverStream;
+use alloy_primitives::Address;
+use futures::{stream, Stream};
+use tokio::{
+    sync::{mpsc, mpsc::Receiver},
+    time::timeout,
+};
+use tracing::warn;
 use tycho_client::{
-    feed::{component_tracker::ComponentFilter, synchronizer::ComponentWithState},
+    feed::{component_tracker::ComponentFilter, synchronizer::ComponentWithState, FeedMessage},
     stream::{StreamError, TychoStreamBuilder},
 };
 use tycho_core::{dto::Chain, Bytes};
 
 use crate::{
-    evm::decoder::{StreamDecodeError, TychoStreamDecoder},
+    evm::{
+        decoder::{StreamDecodeError, TychoStreamDecoder},
+        engine_db::tycho_db::PreCachedDB,
+        message_tap::{MessageTap, TappedMessage, TappedMessageReader},
+        metrics::{record_stream_event, MetricsRecorder},
+        protocol::filters::ComponentFilterFn,
+        rpc::EthRpc,
+        stream_health::{StalenessConfig, StreamMonitor},
+    },
     models::Token,
     protocol::{
         errors::InvalidSnapshotError,
+        gas::GasModel,
         models::{BlockUpdate, TryFromWithBlock},
+        registry::{validate_adapter_registration, validate_registration, RegistrationError},
         state::ProtocolSim,
     },
 };
@@ -40,34 +63,141 @@ use crate::{
 /// efficient handling of protocol components. Protocol components containing tokens which are not
 /// included in this initial list, or added when applying deltas, will not be decoded.
 ///
+/// **Reconnection:** If the connection to Tycho drops, the stream reconnects with exponential
+/// backoff (see `reconnect`) and starts over from a fresh snapshot. The first `BlockUpdate` after
+/// a reconnection has `is_resync` set and contains all tracked pools.
+///
+/// **Restarts:** The Tycho client (tycho-client 0.46) can't resume a subscription from a given
+/// block, it fetches the snapshot of all tracked components on every connection. A new stream
+/// therefore always starts from Tycho's snapshot, pool states persisted by a previous run can't be
+/// used to only catch up on the deltas since.
+///
+/// **Engine database:** Each builder loads VM contracts into its own `PreCachedDB`, bound to the
+/// builder's chain, so streams for several chains can run in the same process. Use `engine_db` to
+/// provide the database explicitly, e.g. to simulate on it outside of the stream.
+///
+/// **Warmup:** Before subscribing, `build` loads the adapter contracts of the VM exchanges and the
+/// contracts given to `preload_contracts` into the engine database. Once streaming, new VM pools
+/// missing stateless contracts are held back while the contracts load in the background, so they
+/// don't delay the block, and are emitted with a later block. See `background_contract_loading`.
+///
+/// **Metrics:** The global `MetricsRecorder`, and the recorder set with `metrics` if any, are
+/// notified of every decoded block, component decode failure and reconnection.
+///
+/// **Gas:** States report execution gas only, unless a `GasModel` for the builder's chain is set
+/// with `gas_model`, e.g. to include the L1 data fee of rollups.
+///
+/// **Staleness:** The stream waits for new blocks indefinitely. Set a `StalenessConfig` with
+/// `staleness` to mark it stale, and optionally reconnect, when Tycho stops sending blocks. The
+/// `StreamMonitor` returned by `monitor` reports the stream's health and last block.
+///
+/// **Recording:** Set a `MessageTap` with `message_tap` to persist the raw messages received from
+/// Tycho, and replay them later with a `ReplayStreamBuilder`.
+///
+/// **Validation:** Exchanges are checked against the protocols of `protocol::registry`: a
+/// built-in state registered for a protocol system it doesn't decode fails the `build`.
+///
 /// # Returns
 /// A result containing a stream of decoded block updates, where each item is either:
 /// - `Ok(BlockUpdate)` if decoding succeeds.
-/// - `Err(StreamDecodeError)` if a decoding error occurs.
+/// - `Err(StreamDecodeError)` if a decoding error occurs, or reconnecting failed. The stream ends
+///   after a failed reconnection.
 ///
 /// # Errors
-/// Returns a `StreamError` if the underlying stream builder fails to initialize.
+/// Returns a `StreamError` if an exchange registration is invalid, or if the underlying stream
+/// builder fails to initialize.
 pub struct ProtocolStreamBuilder {
     decoder: TychoStreamDecoder,
-    stream_builder: TychoStreamBuilder,
+    tycho_url: String,
+    chain: Chain,
+    /// Settings applied to a fresh `TychoStreamBuilder` on every (re)connection
+    config: Vec<Box<ConfigFn>>,
+    /// The exchanges subscribed to and their component filters
+    exchanges: Vec<(String, ComponentFilter)>,
+    /// Pools subscribed to by id, regardless of the exchanges' filters, see `include_pools`
+    included_pools: HashMap<String, Vec<String>>,
+    reconnect: ReconnectConfig,
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    staleness: Option<StalenessConfig>,
+    monitor: StreamMonitor,
+    tap: Option<MessageTap>,
+    /// Invalid exchange registrations, reported by `build`
+    registration_errors: Vec<RegistrationError>,
+}
+
+type ConfigFn = dyn Fn(TychoStreamBuilder) -> TychoStreamBuilder + Send + Sync;
+type ConnectFut = Pin<Box<dyn Future<Output = Result<Receiver<FeedMessage>, String>> + Send>>;
+type FeedConnector = Arc<dyn Fn() -> ConnectFut + Send + Sync>;
+
+/// How the protocol stream reconnects after losing its connection to Tycho
+///
+/// Attempt `n` (starting at 0) waits `min(base_delay * 2^n, max_delay)` before connecting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Consecutive failed attempts after which the stream gives up. 0 disables reconnecting:
+    /// the stream simply ends when the connection drops.
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
 }
 
 impl ProtocolStreamBuilder {
     pub fn new(tycho_url: &str, chain: Chain) -> Self {
+        let mut decoder = TychoStreamDecoder::new();
+        decoder
+            .engine_db(PreCachedDB::for_chain(chain.into()).expect("Failed to create PreCachedDB"));
+        decoder.background_contract_loading(true);
         Self {
-            decoder: TychoStreamDecoder::new(),
-            stream_builder: TychoStreamBuilder::new(tycho_url, chain),
+            decoder,
+            tycho_url: tycho_url.to_string(),
+            chain,
+            config: Vec::new(),
+            exchanges: Vec::new(),
+            included_pools: HashMap::new(),
+            reconnect: ReconnectConfig::default(),
+            metrics: None,
+            staleness: None,
+            monitor: StreamMonitor::default(),
+            tap: None,
+            registration_errors: Vec::new(),
         }
     }
 
+    fn configure(
+        mut self,
+        f: impl Fn(TychoStreamBuilder) -> TychoStreamBuilder + Send + Sync + 'static,
+    ) -> Self {
+        self.config.push(Box::new(f));
+        self
+    }
+
     /// Adds an exchange and its corresponding filter to the Tycho client and decoder.
     ///
-    /// These are the exchanges for which `BlockUpdate`s will be provided.
+    /// These are the exchanges for which `BlockUpdate`s will be provided. The registration is
+    /// validated with `registry::validate_registration`, `build` fails if it's invalid.
     pub fn exchange<T>(
         mut self,
         name: &str,
         filter: ComponentFilter,
-        filter_fn: Option<fn(&ComponentWithState) -> bool>,
+        filter_fn: Option<ComponentFilterFn>,
     ) -> Self
     where
         T: ProtocolSim
@@ -75,46 +205,137 @@ impl ProtocolStreamBuilder {
             + Send
             + 'static,
     {
-        self.stream_builder = self
-            .stream_builder
-            .exchange(name, filter);
+        if let Err(err) = validate_registration::<T>(name) {
+            self.registration_errors.push(err);
+        }
         self.decoder.register_decoder::<T>(name);
         if let Some(predicate) = filter_fn {
             self.decoder
                 .register_filter(name, predicate);
         }
+        self.exchanges
+            .push((name.to_string(), filter));
         self
     }
 
-    /// Sets the block time for the Tycho client.
-    pub fn block_time(mut self, block_time: u64) -> Self {
-        self.stream_builder = self
-            .stream_builder
-            .block_time(block_time);
+    /// Adds a VM exchange like `exchange` does, but simulates its pools through an adapter
+    /// contract deployed at `adapter_address` instead of the protocol's default address.
+    ///
+    /// Useful to test a new adapter build, or to compare two adapter versions side by side from
+    /// two streams.
+    pub fn vm_exchange_with_adapter(
+        mut self,
+        name: &str,
+        filter: ComponentFilter,
+        filter_fn: Option<ComponentFilterFn>,
+        adapter_address: Address,
+    ) -> Self {
+        if let Err(err) = validate_adapter_registration(name) {
+            self.registration_errors.push(err);
+        }
+        self.decoder
+            .register_vm_decoder(name, adapter_address);
+        if let Some(predicate) = filter_fn {
+            self.decoder
+                .register_filter(name, predicate);
+        }
+        self.exchanges
+            .push((name.to_string(), filter));
         self
     }
 
-    /// Sets the timeout duration for network operations.
-    pub fn timeout(mut self, timeout: u64) -> Self {
-        self.stream_builder = self.stream_builder.timeout(timeout);
+    /// Always subscribes to and decodes the given pools of an exchange.
+    ///
+    /// Listed pools are tracked by a second Tycho client subscribed to them by id, so they are
+    /// delivered regardless of the exchange's `ComponentFilter`, e.g. outside of its TVL range.
+    /// Their updates are emitted in separate `BlockUpdate`s, with the same block number as the
+    /// updates of the other pools. They also bypass the client-side `filter_fn` passed to
+    /// `exchange`, and are never reported as removed for falling below the TVL range.
+    pub fn include_pools(mut self, exchange: &str, ids: Vec<String>) -> Self {
+        self.decoder
+            .register_included_pools(exchange, ids.clone());
+        self.included_pools
+            .entry(exchange.to_string())
+            .or_default()
+            .extend(ids);
         self
     }
 
-    /// Configures the client to exclude state updates from the stream.
-    pub fn no_state(mut self, no_state: bool) -> Self {
-        self.stream_builder = self.stream_builder.no_state(no_state);
+    /// Drops the given pools of an exchange from snapshots, deltas and `removed_pairs` before
+    /// decoding, regardless of any other filter.
+    pub fn exclude_pools(mut self, exchange: &str, ids: Vec<String>) -> Self {
+        self.decoder
+            .register_excluded_pools(exchange, ids);
         self
     }
 
+    /// Sets the block time for the Tycho client.
+    pub fn block_time(self, block_time: u64) -> Self {
+        self.configure(move |builder| builder.block_time(block_time))
+    }
+
+    /// Sets the timeout duration for network operations.
+    pub fn timeout(self, timeout: u64) -> Self {
+        self.configure(move |builder| builder.timeout(timeout))
+    }
+
+    /// Configures the client to exclude state updates from the stream.
+    pub fn no_state(self, no_state: bool) -> Self {
+        self.configure(move |builder| builder.no_state(no_state))
+    }
+
     /// Sets the API key for authenticating with the Tycho server.
-    pub fn auth_key(mut self, auth_key: Option<String>) -> Self {
-        self.stream_builder = self.stream_builder.auth_key(auth_key);
-        self
+    pub fn auth_key(self, auth_key: Option<String>) -> Self {
+        self.configure(move |builder| builder.auth_key(auth_key.clone()))
     }
 
     /// Disables TLS/ SSL for the connection, using http and ws protocols.
-    pub fn no_tls(mut self, no_tls: bool) -> Self {
-        self.stream_builder = self.stream_builder.no_tls(no_tls);
+    pub fn no_tls(self, no_tls: bool) -> Self {
+        self.configure(move |builder| builder.no_tls(no_tls))
+    }
+
+    /// Sets the backoff and attempt cap for reconnecting after the connection to Tycho drops.
+    pub fn reconnect(mut self, config: ReconnectConfig) -> Self {
+        self.reconnect = config;
+        self
+    }
+
+    /// Sets a recorder notified of this stream's decoded blocks, component decode failures and
+    /// reconnections, in addition to the global `MetricsRecorder`.
+    ///
+    /// Pass an `Arc` to keep access to the metrics, e.g. to render a `PrometheusMetrics`.
+    pub fn metrics(mut self, metrics: impl MetricsRecorder + 'static) -> Self {
+        let metrics: Arc<dyn MetricsRecorder> = Arc::new(metrics);
+        self.decoder.metrics(metrics.clone());
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Marks the stream stale when no block arrives within `config.max_block_age`, and reconnects
+    /// if `config.reconnect` is set. Staleness is reported by the `StreamMonitor`.
+    pub fn staleness(mut self, config: StalenessConfig) -> Self {
+        self.staleness = Some(config);
+        self
+    }
+
+    /// Writes every message received from Tycho to `tap` before decoding it, see
+    /// `ReplayStreamBuilder`.
+    pub fn message_tap(mut self, tap: MessageTap) -> Self {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Returns a handle on the health and last block of the stream built by this builder.
+    pub fn monitor(&self) -> StreamMonitor {
+        self.monitor.clone()
+    }
+
+    /// Sets the gas model adjusting the gas estimates of the emitted states to the stream's chain.
+    ///
+    /// Defaults to `EthereumGasModel`, which leaves execution gas unchanged. On rollups, use an
+    /// `L1DataGasModel` to include the cost of posting the swap's calldata to L1.
+    pub fn gas_model(mut self, model: impl GasModel + 'static) -> Self {
+        self.decoder.gas_model(Arc::new(model));
         self
     }
 
@@ -135,18 +356,965 @@ impl ProtocolStreamBuilder {
         self
     }
 
+    /// Sets the minimum token quality (0-100) required for a pool to be decoded.
+    ///
+    /// Pools containing any token below this threshold are never emitted. If a token of an already
+    /// tracked pool later drops below it, the pool is reported in `BlockUpdate::removed_pairs`.
+    pub fn min_token_quality(mut self, quality: u8) -> Self {
+        self.decoder.min_token_quality(quality);
+        self
+    }
+
+    /// Classifies the tokens of new pools by simulating ERC20 calls on them, see
+    /// `TokenQualityAnalyzer`.
+    ///
+    /// Pools containing a `Broken` token are never emitted, and the transfer tax of
+    /// `FeeOnTransfer` tokens is set on their `Token`, so `get_amount_out` can account for it. Only
+    /// tokens whose contract is streamed as part of a VM protocol's storage can be analyzed.
+    pub fn quarantine_bad_tokens(mut self, quarantine: bool) -> Self {
+        self.decoder
+            .quarantine_bad_tokens(quarantine);
+        self
+    }
+
+    /// Sets the database VM contracts are loaded into and `EVMPoolState`s simulate on.
+    ///
+    /// Defaults to a new database bound to the stream's chain. Avoid sharing one database between
+    /// streams of different chains.
+    pub fn engine_db(mut self, db: PreCachedDB) -> Self {
+        self.decoder.engine_db(db);
+        self
+    }
+
+    /// Sets the client VM pools request contract code from the node with, e.g. a client adding
+    /// authentication headers or rate limiting.
+    ///
+    /// Defaults to the node at the `RPC_URL` environment variable. Only the code of stateless
+    /// contracts missing from the stream's snapshots is requested.
+    pub fn rpc_client(mut self, client: Arc<dyn EthRpc>) -> Self {
+        self.decoder.rpc_client(client);
+        self
+    }
+
+    /// Sets how many components of the initial snapshot are decoded concurrently.
+    ///
+    /// Defaults to the number of available CPUs. Deltas are always decoded in order.
+    pub fn decode_concurrency(mut self, n: usize) -> Self {
+        self.decoder.decode_concurrency(n);
+        self
+    }
+
+    /// Loads the given contracts into the engine database before subscribing, e.g. the stateless
+    /// contracts used by the pools of the VM exchanges.
+    ///
+    /// Their code is requested with the `rpc_client` in a single request. Pools using them then
+    /// never wait for their code.
+    pub fn preload_contracts(mut self, addresses: Vec<Address>) -> Self {
+        self.decoder
+            .preload_contracts(addresses);
+        self
+    }
+
+    /// Sets whether new VM pools missing stateless contracts are held back while the contracts
+    /// load in the background, rather than delaying the block until they are fetched.
+    ///
+    /// Enabled by default. Held back pools are emitted as new pairs with the first block decoded
+    /// after their contracts loaded.
+    pub fn background_contract_loading(mut self, enabled: bool) -> Self {
+        self.decoder
+            .background_contract_loading(enabled);
+        self
+    }
+
     pub async fn build(
         self,
     ) -> Result<impl Stream<Item = Result<BlockUpdate, StreamDecodeError>>, StreamError> {
-        let (_, rx) = self.stream_builder.build().await?;
-        let decoder = Arc::new(self.decoder);
+        if let Some(err) = self.registration_errors.first() {
+            return Err(StreamError::SetUpError(err.to_string()));
+        }
+        self.decoder
+            .warmup()
+            .await
+            .map_err(|e| StreamError::SetUpError(format!("Failed to warm up: {e}")))?;
+        let clients = Arc::new(TychoClients {
+            tycho_url: self.tycho_url,
+            chain: self.chain,
+            config: self.config,
+            exchanges: self.exchanges,
+            included_pools: self.included_pools,
+        });
+
+        let rx = clients.connect().await?;
+        let connector: FeedConnector = Arc::new(move || -> ConnectFut {
+            let clients = clients.clone();
+            Box::pin(async move {
+                clients
+                    .connect()
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        });
 
-        Ok(Box::pin(ReceiverStream::new(rx).then({
-            let decoder = decoder.clone(); // Clone the decoder for the closure
-            move |msg| {
-                let decoder = decoder.clone(); // Clone again for the async block
-                async move { decoder.decode(msg).await }
+        Ok(Box::pin(resilient_stream(
+            connector,
+            rx,
+            Arc::new(self.decoder),
+            self.reconnect,
+            self.metrics,
+            self.staleness,
+            self.monitor,
+            self.tap,
+        )))
+    }
+}
+
+/// Creates the Tycho clients of a `ProtocolStreamBuilder` on every (re)connection
+struct TychoClients {
+    tycho_url: String,
+    chain: Chain,
+    config: Vec<Box<ConfigFn>>,
+    exchanges: Vec<(String, ComponentFilter)>,
+    included_pools: HashMap<String, Vec<String>>,
+}
+
+impl TychoClients {
+    /// Returns the client subscribed to the exchanges with their filters and, if pools were
+    /// included by id, the client subscribed to them.
+    ///
+    /// A Tycho client tracks the components of an exchange with a single filter, either a TVL
+    /// range or a list of ids, so included pools need a client of their own.
+    fn builders(&self) -> (TychoStreamBuilder, Option<TychoStreamBuilder>) {
+        let new_builder = || {
+            self.config
+                .iter()
+                .fold(TychoStreamBuilder::new(&self.tycho_url, self.chain), |builder, f| f(builder))
+        };
+        let main = self
+            .exchanges
+            .iter()
+            .fold(new_builder(), |builder, (name, filter)| builder.exchange(name, filter.clone()));
+        let included = (!self.included_pools.is_empty()).then(|| {
+            self.included_pools
+                .iter()
+                .fold(new_builder(), |builder, (name, ids)| {
+                    builder.exchange(name, ComponentFilter::Ids(ids.clone()))
+                })
+        });
+        (main, included)
+    }
+
+    /// Connects the clients, merging their feeds into one.
+    async fn connect(&self) -> Result<Receiver<FeedMessage>, StreamError> {
+        let (main, included) = self.builders();
+        let (_, rx) = main.build().await?;
+        match included {
+            Some(included) => {
+                let (_, included_rx) = included.build().await?;
+                Ok(merge_feeds(rx, included_rx))
+            }
+            None => Ok(rx),
+        }
+    }
+}
+
+/// Forwards the messages of both feeds into a single feed, in the order they arrive.
+///
+/// The merged feed closes as soon as either feed closes, so a reconnection restarts both.
+fn merge_feeds(
+    mut first: Receiver<FeedMessage>,
+    mut second: Receiver<FeedMessage>,
+) -> Receiver<FeedMessage> {
+    let (tx, rx) = mpsc::channel(MERGED_FEED_CAPACITY);
+    tokio::spawn(async move {
+        loop {
+            let msg = tokio::select! {
+                msg = first.recv() => msg,
+                msg = second.recv() => msg,
+            };
+            let Some(msg) = msg else {
+                break;
+            };
+            if tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Number of messages buffered by `merge_feeds`
+const MERGED_FEED_CAPACITY: usize = 64;
+
+/// Decodes the messages of `rx` in order, reconnecting through `connector` whenever the feed
+/// ends.
+///
+/// The old feed is fully drained before a new one is requested, so messages are never
+/// interleaved across a reconnection. Successful reconnections are reported to `metrics` and to
+/// the global recorder.
+///
+/// Received messages and blocks are reported to `monitor`. With `staleness`, the monitor is
+/// marked stale whenever no message arrives within `max_block_age`, and a stale feed is dropped
+/// and reconnected like a closed one if `staleness.reconnect` is set.
+///
+/// Messages are written to `tap`, if any, before they are decoded.
+#[allow(clippy::too_many_arguments)]
+fn resilient_stream(
+    connector: FeedConnector,
+    rx: Receiver<FeedMessage>,
+    decoder: Arc<TychoStreamDecoder>,
+    reconnect: ReconnectConfig,
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    staleness: Option<StalenessConfig>,
+    monitor: StreamMonitor,
+    tap: Option<MessageTap>,
+) -> impl Stream<Item = Result<BlockUpdate, StreamDecodeError>> {
+    // `None` once the stream ended. The flag marks a pending resync.
+    let initial: Option<(Receiver<FeedMessage>, bool)> = Some((rx, false));
+    stream::unfold(initial, move |feed| {
+        let connector = connector.clone();
+        let decoder = decoder.clone();
+        let reconnect = reconnect.clone();
+        let metrics = metrics.clone();
+        let staleness = staleness.clone();
+        let monitor = monitor.clone();
+        let tap = tap.clone();
+        async move {
+            let (mut rx, mut resync) = feed?;
+            // Set until the first message of a new connection is tapped
+            let mut reconnected = false;
+            monitor.on_start();
+            loop {
+                let msg = match &staleness {
+                    Some(config) => match timeout(config.max_block_age, rx.recv()).await {
+                        Ok(msg) => msg,
+                        Err(_) => {
+                            let health = monitor.on_stale();
+                            warn!(?health, "No block received from Tycho in time");
+                            if !config.reconnect || reconnect.max_attempts == 0 {
+                                continue;
+                            }
+                            // Dropped like a closed connection
+                            None
+                        }
+                    },
+                    None => rx.recv().await,
+                };
+                if let Some(msg) = msg {
+                    monitor.on_message();
+                    if let Some(tap) = &tap {
+                        let tapped = TappedMessage::new(msg.clone(), reconnected);
+                        if let Err(e) = tap.write_async(tapped).await {
+                            warn!(error = %e, "Failed to write feed message to tap");
+                        }
+                    }
+                    let update = decoder
+                        .decode(msg)
+                        .await
+                        .map(|update| update.set_is_resync(resync));
+                    if let Ok(update) = &update {
+                        monitor.on_block(update.block_number);
+                        resync = false;
+                    }
+                    return Some((update, Some((rx, resync))));
+                }
+
+                if reconnect.max_attempts == 0 {
+                    return None;
+                }
+                warn!("Connection to Tycho dropped, reconnecting");
+                match connect_with_backoff(&connector, &reconnect).await {
+                    Ok(new_rx) => {
+                        record_stream_event(metrics.as_ref(), |r| r.on_stream_reconnect());
+                        decoder.reset_states().await;
+                        rx = new_rx;
+                        resync = true;
+                        reconnected = true;
+                    }
+                    Err(e) => {
+                        let err = StreamDecodeError::Fatal(format!(
+                            "Failed to reconnect to Tycho after {} attempts: {e}",
+                            reconnect.max_attempts
+                        ));
+                        return Some((Err(err), None));
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn connect_with_backoff(
+    connector: &FeedConnector,
+    reconnect: &ReconnectConfig,
+) -> Result<Receiver<FeedMessage>, String> {
+    let mut last_err = String::new();
+    for attempt in 0..reconnect.max_attempts {
+        tokio::time::sleep(reconnect.delay(attempt)).await;
+        match connector().await {
+            Ok(rx) => return Ok(rx),
+            Err(e) => {
+                warn!(attempt, error = %e, "Failed to reconnect to Tycho");
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// Builds a stream of `BlockUpdate`s from feed messages recorded by a `MessageTap`.
+///
+/// Messages are decoded by a `TychoStreamDecoder`, like in the live stream. Configured with the
+/// same exchanges, tokens and decoder settings as the recording `ProtocolStreamBuilder`, the replay
+/// yields the same updates, including the `is_resync` flag of updates following a reconnection.
+/// Tycho side settings, like `ComponentFilter`s, were already applied to the recorded messages.
+///
+/// By default messages are replayed as fast as they are consumed. With `realtime`, the stream
+/// waits between messages as long as the recording stream did.
+///
+/// A frame that can't be read, e.g. a truncated last frame, yields a
+/// `StreamDecodeError::Fatal` and ends the stream.
+pub struct ReplayStreamBuilder {
+    decoder: TychoStreamDecoder,
+    path: PathBuf,
+    realtime: bool,
+    registration_errors: Vec<RegistrationError>,
+}
+
+impl ReplayStreamBuilder {
+    /// Creates a builder replaying the file at `path`, recorded from a stream of `chain`.
+    pub fn new(path: impl AsRef<Path>, chain: Chain) -> Self {
+        let mut decoder = TychoStreamDecoder::new();
+        decoder
+            .engine_db(PreCachedDB::for_chain(chain.into()).expect("Failed to create PreCachedDB"));
+        Self {
+            decoder,
+            path: path.as_ref().to_path_buf(),
+            realtime: false,
+            registration_errors: Vec::new(),
+        }
+    }
+
+    /// Registers the decoder, and optionally a client-side filter, of an exchange, see
+    /// `ProtocolStreamBuilder::exchange`.
+    pub fn exchange<T>(mut self, name: &str, filter_fn: Option<ComponentFilterFn>) -> Self
+    where
+        T: ProtocolSim
+            + TryFromWithBlock<ComponentWithState, Error = InvalidSnapshotError>
+            + Send
+            + 'static,
+    {
+        if let Err(err) = validate_registration::<T>(name) {
+            self.registration_errors.push(err);
+        }
+        self.decoder.register_decoder::<T>(name);
+        if let Some(predicate) = filter_fn {
+            self.decoder
+                .register_filter(name, predicate);
+        }
+        self
+    }
+
+    /// Registers a VM exchange simulated through the adapter at `adapter_address`, see
+    /// `ProtocolStreamBuilder::vm_exchange_with_adapter`.
+    pub fn vm_exchange_with_adapter(
+        mut self,
+        name: &str,
+        filter_fn: Option<ComponentFilterFn>,
+        adapter_address: Address,
+    ) -> Self {
+        if let Err(err) = validate_adapter_registration(name) {
+            self.registration_errors.push(err);
+        }
+        self.decoder
+            .register_vm_decoder(name, adapter_address);
+        if let Some(predicate) = filter_fn {
+            self.decoder
+                .register_filter(name, predicate);
+        }
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::include_pools`.
+    pub fn include_pools(mut self, exchange: &str, ids: Vec<String>) -> Self {
+        self.decoder
+            .register_included_pools(exchange, ids);
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::exclude_pools`.
+    pub fn exclude_pools(mut self, exchange: &str, ids: Vec<String>) -> Self {
+        self.decoder
+            .register_excluded_pools(exchange, ids);
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::gas_model`.
+    pub fn gas_model(mut self, model: impl GasModel + 'static) -> Self {
+        self.decoder.gas_model(Arc::new(model));
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::set_tokens`.
+    pub async fn set_tokens(self, tokens: HashMap<Bytes, Token>) -> Self {
+        self.decoder.set_tokens(tokens).await;
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::skip_state_decode_failures`.
+    pub fn skip_state_decode_failures(mut self, skip: bool) -> Self {
+        self.decoder
+            .skip_state_decode_failures(skip);
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::min_token_quality`.
+    pub fn min_token_quality(mut self, quality: u8) -> Self {
+        self.decoder.min_token_quality(quality);
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::quarantine_bad_tokens`.
+    pub fn quarantine_bad_tokens(mut self, quarantine: bool) -> Self {
+        self.decoder
+            .quarantine_bad_tokens(quarantine);
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::engine_db`.
+    pub fn engine_db(mut self, db: PreCachedDB) -> Self {
+        self.decoder.engine_db(db);
+        self
+    }
+
+    /// See `ProtocolStreamBuilder::rpc_client`.
+    pub fn rpc_client(mut self, client: Arc<dyn EthRpc>) -> Self {
+        self.decoder.rpc_client(client);
+        self
+    }
+
+    /// Replays messages at the cadence they were recorded at, instead of as fast as possible.
+    pub fn realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Opens the recording.
+    ///
+    /// # Errors
+    /// Returns an `io::Error` if the file can't be opened, or of kind `InvalidInput` if an
+    /// exchange registration is invalid.
+    pub fn build(self) -> io::Result<impl Stream<Item = Result<BlockUpdate, StreamDecodeError>>> {
+        if let Some(err) = self.registration_errors.first() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err.to_string()));
+        }
+        let reader = TappedMessageReader::open(&self.path)?;
+        let decoder = Arc::new(self.decoder);
+        let realtime = self.realtime;
+        // The reader, the receive time of the previous message and whether a resync is pending,
+        // tracked like the live stream does
+        let initial: (TappedMessageReader<_>, Option<u64>, bool) = (reader, None, false);
+        Ok(Box::pin(stream::unfold(initial, move |(mut reader, last_received, mut resync)| {
+            let decoder = decoder.clone();
+            async move {
+                let tapped = match reader.next()? {
+                    Ok(tapped) => tapped,
+                    Err(e) => {
+                        let err = StreamDecodeError::Fatal(format!(
+                            "Failed to read recorded feed message: {e}"
+                        ));
+                        return Some((Err(err), (reader, last_received, resync)));
+                    }
+                };
+                if let Some(last) = last_received.filter(|_| realtime) {
+                    let wait = tapped
+                        .received_at_ms
+                        .saturating_sub(last);
+                    tokio::time::sleep(Duration::from_millis(wait)).await;
+                }
+                if tapped.reconnected {
+                    decoder.reset_states().await;
+                    resync = true;
+                }
+                let update = decoder
+                    .decode(tapped.message)
+                    .await
+                    .map(|update| update.set_is_resync(resync));
+                if update.is_ok() {
+                    resync = false;
+                }
+                Some((update, (reader, Some(tapped.received_at_ms), resync)))
             }
         })))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+    };
+
+    use futures::StreamExt;
+    use rstest::rstest;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::{
+        evm::{
+            protocol::{
+                maverick_v2::state::MaverickV2State, uniswap_v2::state::UniswapV2State,
+                uniswap_v3::state::UniswapV3State, uniswap_v4::state::UniswapV4State,
+                vm::state::EVMPoolState,
+            },
+            stream_health::StreamHealth,
+        },
+        testing::{token_at, token_map},
+    };
+
+    fn load_test_msg(name: &str) -> FeedMessage {
+        let project_root = env!("CARGO_MANIFEST_DIR");
+        let asset_path =
+            Path::new(project_root).join(format!("tests/assets/decoder/{}.json", name));
+        let json_data = fs::read_to_string(asset_path).expect("Failed to read test asset");
+        serde_json::from_str(&json_data).expect("Failed to deserialize FeedMsg json!")
+    }
+
+    async fn setup_decoder() -> Arc<TychoStreamDecoder> {
+        let mut decoder = TychoStreamDecoder::new();
+        decoder.register_decoder::<UniswapV2State>("uniswap_v2");
+        let tokens = [
+            ("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", "WETH", 18),
+            ("0xdac17f958d2ee523a2206206994597c13d831ec7", "USDT", 6),
+        ]
+        .map(|(address, symbol, decimals)| token_at(Bytes::from(address), symbol, decimals));
+        decoder
+            .set_tokens(token_map(tokens))
+            .await;
+        Arc::new(decoder)
+    }
+
+    /// A feed delivering `msgs` and then dropping the connection.
+    fn mock_feed(msgs: Vec<FeedMessage>) -> Receiver<FeedMessage> {
+        let (tx, rx) = mpsc::channel(msgs.len().max(1));
+        for msg in msgs {
+            tx.try_send(msg).unwrap();
+        }
+        rx
+    }
+
+    /// A connector serving the given connection results in order, failing once they ran out.
+    fn mock_connector(
+        connections: Vec<Result<Vec<FeedMessage>, String>>,
+    ) -> (FeedConnector, Arc<AtomicUsize>) {
+        let connections = Arc::new(Mutex::new(connections.into_iter()));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let connector: FeedConnector = Arc::new({
+            let calls = calls.clone();
+            move || -> ConnectFut {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let connection = connections
+                    .lock()
+                    .unwrap()
+                    .next()
+                    .unwrap_or_else(|| Err("connection refused".to_string()));
+                Box::pin(async move { connection.map(mock_feed) })
+            }
+        });
+        (connector, calls)
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingMetrics {
+        reconnects: AtomicUsize,
+    }
+
+    impl MetricsRecorder for CountingMetrics {
+        fn on_stream_reconnect(&self) {
+            self.reconnects
+                .fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn test_reconnect_config(max_attempts: u32) -> ReconnectConfig {
+        ReconnectConfig {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+            max_attempts,
+        }
+    }
+
+    #[rstest]
+    #[case::ethereum(Chain::Ethereum)]
+    #[case::arbitrum(Chain::Arbitrum)]
+    #[case::zksync(Chain::ZkSync)]
+    #[case::starknet(Chain::Starknet)]
+    fn test_builder_setup_for_chain(#[case] chain: Chain) {
+        let builder = ProtocolStreamBuilder::new("localhost:4242", chain)
+            .exchange::<UniswapV2State>(
+                "uniswap_v2",
+                ComponentFilter::with_tvl_range(1.0, 1.0),
+                None,
+            )
+            .auth_key(Some("key".to_string()))
+            .no_tls(true);
+
+        assert_eq!(builder.chain, chain);
+        // Applies the settings as `build` does, without connecting
+        let clients = TychoClients {
+            tycho_url: builder.tycho_url,
+            chain,
+            config: builder.config,
+            exchanges: builder.exchanges,
+            included_pools: builder.included_pools,
+        };
+        let (_, included) = clients.builders();
+        assert!(included.is_none());
+    }
+
+    #[test]
+    fn test_builder_accepts_built_in_exchanges() {
+        let filter = ComponentFilter::with_tvl_range(1.0, 1.0);
+
+        let builder = ProtocolStreamBuilder::new("localhost:4242", Chain::Ethereum)
+            .exchange::<UniswapV2State>("uniswap_v2", filter.clone(), None)
+            .exchange::<UniswapV2State>("sushiswap_v2", filter.clone(), None)
+            .exchange::<UniswapV3State>("uniswap_v3", filter.clone(), None)
+            .exchange::<UniswapV4State>("uniswap_v4", filter.clone(), None)
+            .exchange::<MaverickV2State>("maverick_v2", filter.clone(), None)
+            .exchange::<EVMPoolState<PreCachedDB>>("vm:balancer_v2", filter.clone(), None)
+            .vm_exchange_with_adapter("vm:curve", filter, None, Address::ZERO);
+
+        assert_eq!(builder.registration_errors, vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_build_fails_on_invalid_exchange() {
+        let builder = ProtocolStreamBuilder::new("localhost:4242", Chain::Ethereum)
+            .exchange::<UniswapV3State>(
+                "uniswap_v2",
+                ComponentFilter::with_tvl_range(1.0, 1.0),
+                None,
+            );
+
+        // Fails before connecting to Tycho
+        let Err(StreamError::SetUpError(msg)) = builder.build().await else {
+            panic!("Expected a set up error");
+        };
+        assert_eq!(
+            msg,
+            "Protocol system uniswap_v2 is decoded by UniswapV2State, not UniswapV3State"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_resyncs_after_disconnect() {
+        let decoder = setup_decoder().await;
+        let first_feed = mock_feed(vec![
+            load_test_msg("uniswap_v2_snapshot"),
+            load_test_msg("uniswap_v2_delta"),
+        ]);
+        // The first reconnection attempt fails, the second one succeeds
+        let (connector, calls) = mock_connector(vec![
+            Err("connection refused".to_string()),
+            Ok(vec![load_test_msg("uniswap_v2_snapshot"), load_test_msg("uniswap_v2_delta")]),
+        ]);
+
+        let reconnects = Arc::new(CountingMetrics::default());
+        let updates: Vec<_> = resilient_stream(
+            connector,
+            first_feed,
+            decoder,
+            test_reconnect_config(2),
+            Some(reconnects.clone()),
+            None,
+            StreamMonitor::default(),
+            None,
+        )
+        .collect()
+        .await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        assert_eq!(
+            reconnects
+                .reconnects
+                .load(Ordering::SeqCst),
+            1
+        );
+        assert_eq!(updates.len(), 5);
+        let delivered: Vec<_> = updates[..4]
+            .iter()
+            .map(|update| {
+                let update = update.as_ref().unwrap();
+                (update.block_number, update.is_resync)
+            })
+            .collect();
+        assert_eq!(
+            delivered,
+            vec![(21284145, false), (21284148, false), (21284145, true), (21284148, false)]
+        );
+        // The resync update holds the full state again
+        assert_eq!(
+            updates[2]
+                .as_ref()
+                .unwrap()
+                .states
+                .len(),
+            1
+        );
+        assert!(matches!(updates[4], Err(StreamDecodeError::Fatal(_))));
+    }
+
+    #[tokio::test]
+    async fn test_stream_without_reconnect_ends() {
+        let decoder = setup_decoder().await;
+        let (connector, calls) = mock_connector(vec![]);
+
+        let updates: Vec<_> = resilient_stream(
+            connector,
+            mock_feed(vec![load_test_msg("uniswap_v2_snapshot")]),
+            decoder,
+            test_reconnect_config(0),
+            None,
+            None,
+            StreamMonitor::default(),
+            None,
+        )
+        .collect()
+        .await;
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_reports_stale_feed() {
+        let decoder = setup_decoder().await;
+        let (connector, calls) = mock_connector(vec![]);
+        let (tx, rx) = mpsc::channel(1);
+        let monitor = StreamMonitor::default();
+        let mut health = monitor.health_receiver();
+        let mut stream = Box::pin(resilient_stream(
+            connector,
+            rx,
+            decoder,
+            test_reconnect_config(0),
+            None,
+            Some(StalenessConfig::new(Duration::from_secs(36))),
+            monitor.clone(),
+            None,
+        ));
+        assert_eq!(monitor.health(), StreamHealth::Starting);
+
+        tx.send(load_test_msg("uniswap_v2_snapshot"))
+            .await
+            .unwrap();
+        stream.next().await.unwrap().unwrap();
+        assert_eq!(monitor.last_block_number(), Some(21284145));
+        assert!(monitor.last_message_at().is_some());
+        assert_eq!(*health.borrow_and_update(), StreamHealth::Healthy { last_block: 21284145 });
+
+        // The stream keeps waiting for blocks while reporting it's stale
+        let (update, _) = tokio::join!(stream.next(), async {
+            health.changed().await.unwrap();
+            let StreamHealth::Stale { last_block, age } = health.borrow_and_update().clone() else {
+                panic!("Stream should be stale");
+            };
+            assert_eq!(last_block, Some(21284145));
+            assert!(age >= Duration::from_secs(36));
+            tx.send(load_test_msg("uniswap_v2_delta"))
+                .await
+                .unwrap();
+        });
+
+        assert_eq!(update.unwrap().unwrap().block_number, 21284148);
+        assert_eq!(monitor.health(), StreamHealth::Healthy { last_block: 21284148 });
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        drop(tx);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_reconnects_stale_feed() {
+        let decoder = setup_decoder().await;
+        let (connector, calls) = mock_connector(vec![Ok(vec![
+            load_test_msg("uniswap_v2_snapshot"),
+            load_test_msg("uniswap_v2_delta"),
+        ])]);
+        // The first feed stalls after its snapshot without closing
+        let (tx, rx) = mpsc::channel(1);
+        tx.send(load_test_msg("uniswap_v2_snapshot"))
+            .await
+            .unwrap();
+        let monitor = StreamMonitor::default();
+
+        let updates: Vec<_> = resilient_stream(
+            connector,
+            rx,
+            decoder,
+            test_reconnect_config(1),
+            None,
+            Some(StalenessConfig::new(Duration::from_secs(36)).with_reconnect(true)),
+            monitor.clone(),
+            None,
+        )
+        .collect()
+        .await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let delivered: Vec<_> = updates[..3]
+            .iter()
+            .map(|update| {
+                let update = update.as_ref().unwrap();
+                (update.block_number, update.is_resync)
+            })
+            .collect();
+        assert_eq!(delivered, vec![(21284145, false), (21284145, true), (21284148, false)]);
+        // The second feed closes and can't be replaced
+        assert!(matches!(updates[3], Err(StreamDecodeError::Fatal(_))));
+        assert_eq!(monitor.last_block_number(), Some(21284148));
+        drop(tx);
+    }
+
+    async fn replay_builder(path: &Path) -> ReplayStreamBuilder {
+        let tokens = [
+            ("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2", "WETH", 18),
+            ("0xdac17f958d2ee523a2206206994597c13d831ec7", "USDT", 6),
+        ]
+        .map(|(address, symbol, decimals)| token_at(Bytes::from(address), symbol, decimals));
+        ReplayStreamBuilder::new(path, Chain::Ethereum)
+            .exchange::<UniswapV2State>("uniswap_v2", None)
+            .set_tokens(token_map(tokens))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_tap_and_replay_roundtrip() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let (connector, _) = mock_connector(vec![Ok(vec![
+            load_test_msg("uniswap_v2_snapshot"),
+            load_test_msg("uniswap_v2_delta"),
+        ])]);
+        let live: Vec<_> = resilient_stream(
+            connector,
+            mock_feed(vec![
+                load_test_msg("uniswap_v2_snapshot"),
+                load_test_msg("uniswap_v2_delta"),
+            ]),
+            setup_decoder().await,
+            test_reconnect_config(1),
+            None,
+            None,
+            StreamMonitor::default(),
+            Some(MessageTap::create(file.path()).unwrap()),
+        )
+        .filter_map(|update| async move { update.ok() })
+        .collect()
+        .await;
+
+        let replayed: Vec<_> = replay_builder(file.path())
+            .await
+            .build()
+            .unwrap()
+            .map(|update| update.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(live.len(), 4);
+        assert_eq!(replayed.len(), live.len());
+        for (live, replayed) in live.iter().zip(&replayed) {
+            assert_eq!(live.block_number, replayed.block_number);
+            assert_eq!(live.is_resync, replayed.is_resync);
+            assert_eq!(
+                live.new_pairs
+                    .keys()
+                    .collect::<HashSet<_>>(),
+                replayed
+                    .new_pairs
+                    .keys()
+                    .collect::<HashSet<_>>()
+            );
+            assert_eq!(
+                live.removed_pairs
+                    .keys()
+                    .collect::<HashSet<_>>(),
+                replayed
+                    .removed_pairs
+                    .keys()
+                    .collect::<HashSet<_>>()
+            );
+            assert_eq!(live.states.len(), replayed.states.len());
+            for (id, state) in &live.states {
+                assert!(state.eq(replayed.states[id].as_ref()), "State of {id} differs");
+            }
+        }
+        assert!(replayed[2].is_resync);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_realtime() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let tap = MessageTap::create(file.path()).unwrap();
+        for (received_at_ms, name) in [(0, "uniswap_v2_snapshot"), (12_000, "uniswap_v2_delta")] {
+            tap.write(&TappedMessage {
+                received_at_ms,
+                reconnected: false,
+                message: load_test_msg(name),
+            })
+            .unwrap();
+        }
+        let start = tokio::time::Instant::now();
+
+        let replayed: Vec<_> = replay_builder(file.path())
+            .await
+            .realtime(true)
+            .build()
+            .unwrap()
+            .collect()
+            .await;
+
+        assert_eq!(replayed.len(), 2);
+        assert!(start.elapsed() >= Duration::from_secs(12));
+    }
+
+    #[tokio::test]
+    async fn test_replay_truncated_recording() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        MessageTap::create(file.path())
+            .unwrap()
+            .write(&TappedMessage::new(load_test_msg("uniswap_v2_snapshot"), false))
+            .unwrap();
+        let mut bytes = fs::read(file.path()).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(file.path(), bytes).unwrap();
+
+        let replayed: Vec<_> = replay_builder(file.path())
+            .await
+            .build()
+            .unwrap()
+            .collect()
+            .await;
+
+        assert_eq!(replayed.len(), 1);
+        assert!(matches!(replayed[0], Err(StreamDecodeError::Fatal(_))));
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        let config = ReconnectConfig {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_attempts: 100,
+        };
+
+        assert_eq!(config.delay(0), Duration::from_secs(1));
+        assert_eq!(config.delay(3), Duration::from_secs(8));
+        assert_eq!(config.delay(4), Duration::from_secs(10));
+        assert_eq!(config.delay(99), Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,138 @@
+//! Staleness detection of the protocol stream
+//!
+//! If Tycho stops sending blocks, e.g. because its indexer stalls, the protocol stream keeps
+//! waiting and the states it emitted last silently become stale. A `StreamMonitor`, obtained
+//! with `ProtocolStreamBuilder::monitor`, tracks the last received block and publishes the
+//! stream's `StreamHealth`. With a `StalenessConfig`, the health turns `Stale` once no block
+//! arrived for longer than `max_block_age`, and the stream optionally reconnects.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::watch, time::Instant};
+
+/// Health of the protocol stream, as published by `StreamMonitor`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamHealth {
+    /// No block was received yet
+    Starting,
+    /// Blocks are received in time
+    Healthy { last_block: u64 },
+    /// No block was received for `age`, longer than `StalenessConfig::max_block_age`.
+    /// `last_block` is the last block received, if any.
+    Stale { last_block: Option<u64>, age: Duration },
+}
+
+/// When the protocol stream is considered stale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StalenessConfig {
+    /// Time without a new block after which the stream is stale, e.g. 3 times the chain's block
+    /// time. Checked again every `max_block_age` while the stream stays stale.
+    pub max_block_age: Duration,
+    /// Whether to reconnect to Tycho once the stream is stale, following the stream's
+    /// `ReconnectConfig`
+    pub reconnect: bool,
+}
+
+impl StalenessConfig {
+    /// Marks the stream stale after `max_block_age` without a block, without reconnecting.
+    pub fn new(max_block_age: Duration) -> Self {
+        Self { max_block_age, reconnect: false }
+    }
+
+    pub fn with_reconnect(mut self, reconnect: bool) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+}
+
+/// A handle on the progress of a protocol stream
+///
+/// Clones share the same state, so a monitor can be obtained before the stream is built and
+/// queried from any task while the stream runs.
+#[derive(Debug, Clone)]
+pub struct StreamMonitor {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    progress: Mutex<Progress>,
+    health: watch::Sender<StreamHealth>,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    last_block: Option<u64>,
+    last_message_at: Option<Instant>,
+    /// When the stream started waiting for its first message
+    started_at: Option<Instant>,
+}
+
+impl Default for StreamMonitor {
+    fn default() -> Self {
+        let (health, _) = watch::channel(StreamHealth::Starting);
+        Self { shared: Arc::new(Shared { progress: Mutex::default(), health }) }
+    }
+}
+
+impl StreamMonitor {
+    /// Returns the number of the last block the stream emitted.
+    pub fn last_block_number(&self) -> Option<u64> {
+        self.progress().last_block
+    }
+
+    /// Returns when the stream last received a message from Tycho.
+    pub fn last_message_at(&self) -> Option<Instant> {
+        self.progress().last_message_at
+    }
+
+    /// Returns the current health of the stream.
+    pub fn health(&self) -> StreamHealth {
+        self.shared.health.borrow().clone()
+    }
+
+    /// Returns a receiver notified whenever the health of the stream changes. A `Healthy`
+    /// health changes with every block.
+    pub fn health_receiver(&self) -> watch::Receiver<StreamHealth> {
+        self.shared.health.subscribe()
+    }
+
+    pub(crate) fn on_start(&self) {
+        self.progress()
+            .started_at
+            .get_or_insert_with(Instant::now);
+    }
+
+    pub(crate) fn on_message(&self) {
+        self.progress().last_message_at = Some(Instant::now());
+    }
+
+    pub(crate) fn on_block(&self, block_number: u64) {
+        self.progress().last_block = Some(block_number);
+        self.shared
+            .health
+            .send_replace(StreamHealth::Healthy { last_block: block_number });
+    }
+
+    /// Marks the stream stale and returns the published health.
+    pub(crate) fn on_stale(&self) -> StreamHealth {
+        let health = {
+            let progress = self.progress();
+            let since = progress
+                .last_message_at
+                .or(progress.started_at)
+                .unwrap_or_else(Instant::now);
+            StreamHealth::Stale { last_block: progress.last_block, age: since.elapsed() }
+        };
+        self.shared
+            .health
+            .send_replace(health.clone());
+        health
+    }
+
+    fn progress(&self) -> std::sync::MutexGuard<'_, Progress> {
+        self.shared.progress.lock().unwrap()
+    }
+}
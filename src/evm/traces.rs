@@ -1,9 +1,96 @@
+use std::fmt;
+
+use alloy_primitives::Selector;
 use foundry_config::{Chain, Config};
 use foundry_evm::traces::{
     decode_trace_arena,
     identifier::{EtherscanIdentifier, SignaturesIdentifier},
     render_trace_arena, CallTraceDecoder, CallTraceDecoderBuilder, DebugTraceIdentifier, Traces,
 };
+use revm::primitives::{Address, Bytes};
+use revm_inspectors::tracing::{types::CallTraceNode, CallTraceArena};
+use serde::{Deserialize, Serialize};
+
+/// A single call frame of a simulated transaction, together with all the calls it made
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallTrace {
+    /// The called contract, or the deployed one for contract creations
+    pub address: Address,
+    /// The function selector, `None` for contract creations and calls with less than 4 bytes
+    /// of input
+    pub selector: Option<Selector>,
+    pub input: Bytes,
+    pub output: Bytes,
+    pub gas_used: u64,
+    pub success: bool,
+    /// Calls made by this frame, in execution order
+    pub children: Vec<CallTrace>,
+}
+
+impl CallTrace {
+    /// Builds the call tree from the arena of a `TracingInspector`. The root of the arena is the
+    /// transaction's top level call.
+    pub fn from_arena(arena: &CallTraceArena) -> Self {
+        Self::from_node(arena.nodes(), 0)
+    }
+
+    fn from_node(nodes: &[CallTraceNode], idx: usize) -> Self {
+        let node = &nodes[idx];
+        let trace = &node.trace;
+        let selector = (!trace.kind.is_any_create() && trace.data.len() >= 4)
+            .then(|| Selector::from_slice(&trace.data[..4]));
+        Self {
+            address: trace.address,
+            selector,
+            input: trace.data.clone(),
+            output: trace.output.clone(),
+            gas_used: trace.gas_used,
+            success: trace.success,
+            children: node
+                .children
+                .iter()
+                .map(|child| Self::from_node(nodes, *child))
+                .collect(),
+        }
+    }
+
+    /// Number of nested call levels, 1 for a call without any sub calls.
+    pub fn depth(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .map(CallTrace::depth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        match self.selector {
+            Some(selector) => writeln!(
+                f,
+                "[{}] {}::{}({})",
+                self.gas_used,
+                self.address,
+                selector,
+                Bytes::copy_from_slice(&self.input[4..])
+            )?,
+            None => writeln!(f, "[{}] {}({})", self.gas_used, self.address, self.input)?,
+        }
+        for child in &self.children {
+            write!(f, "{prefix}├─ ")?;
+            child.fmt_tree(f, &format!("{prefix}│  "))?;
+        }
+        let status = if self.success { "" } else { "[Revert] " };
+        writeln!(f, "{prefix}└─ ← {status}{}", self.output)
+    }
+}
+
+/// Renders the call tree similar to foundry's traces, without decoding calldata.
+impl fmt::Display for CallTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_tree(f, "")
+    }
+}
 
 /// A slimmed down return from the executor used for returning minimal trace + gas metering info
 #[derive(Debug)]
@@ -82,3 +169,38 @@ pub async fn print_traces(
     println!("Gas used: {}", result.gas_used);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_trace_display() {
+        let pair = Address::repeat_byte(0x22);
+        let trace = CallTrace {
+            address: Address::repeat_byte(0x11),
+            selector: Some(Selector::new([0xd0, 0x6c, 0xa6, 0x1f])),
+            input: Bytes::from(vec![0xd0, 0x6c, 0xa6, 0x1f, 0x01]),
+            output: Bytes::new(),
+            gas_used: 100,
+            success: false,
+            children: vec![CallTrace {
+                address: pair,
+                input: Bytes::new(),
+                output: Bytes::from(vec![0x2a]),
+                gas_used: 20,
+                success: true,
+                ..Default::default()
+            }],
+        };
+
+        assert_eq!(trace.depth(), 2);
+        assert_eq!(
+            trace.to_string(),
+            format!(
+                "[100] {}::0xd06ca61f(0x01)\n├─ [20] {pair}(0x)\n│  └─ ← 0x2a\n└─ ← [Revert] 0x\n",
+                Address::repeat_byte(0x11)
+            )
+        );
+    }
+}
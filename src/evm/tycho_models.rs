@@ -2,6 +2,7 @@ use std::{collections::HashMap, fmt::Display};
 
 use alloy_primitives::{Address, B256, U256};
 use chrono::{NaiveDateTime, Utc};
+use revm::primitives::SpecId;
 use serde::{Deserialize, Serialize};
 use strum_macros::{Display, EnumString};
 use uuid::Uuid;
@@ -67,8 +68,10 @@ impl From<Block> for BlockHeader {
     fn from(value: Block) -> Self {
         Self {
             number: value.number,
-            hash: value.hash,
+            hash: Some(value.hash),
             timestamp: value.ts.and_utc().timestamp() as u64,
+            chain: value.chain,
+            l1_block_number: None,
         }
     }
 }
@@ -121,6 +124,54 @@ pub enum Chain {
     ZkSync,
     Starknet,
     Arbitrum,
+    Optimism,
+    Base,
+}
+
+impl Chain {
+    /// Returns the chain id, which the `CHAINID` opcode returns.
+    ///
+    /// Starknet's id is its `SN_MAIN` identifier read as a number.
+    pub fn id(&self) -> u64 {
+        match self {
+            Chain::Ethereum => 1,
+            Chain::ZkSync => 324,
+            Chain::Starknet => 0x534e5f4d41494e,
+            Chain::Arbitrum => 42161,
+            Chain::Optimism => 10,
+            Chain::Base => 8453,
+        }
+    }
+
+    /// Returns the EVM version of the chain's latest hardfork, used to simulate recent blocks.
+    ///
+    /// Simulations of older blocks may need an earlier version, see
+    /// `SimulationEngine::with_spec_id`.
+    pub fn spec_id(&self) -> SpecId {
+        match self {
+            Chain::Ethereum | Chain::Arbitrum | Chain::Optimism | Chain::Base | Chain::Starknet => {
+                SpecId::CANCUN
+            }
+            // Supports PUSH0 but not all Cancun opcodes
+            Chain::ZkSync => SpecId::SHANGHAI,
+        }
+    }
+
+    /// Returns the address of the chain's wrapped native token, e.g. WETH on Ethereum and its
+    /// rollups, the usual start and end token of routes.
+    ///
+    /// Returns `None` for Starknet, whose native token is already an ERC20 token.
+    pub fn wrapped_native_token(&self) -> Option<tycho_core::Bytes> {
+        let address = match self {
+            Chain::Ethereum => "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+            Chain::Arbitrum => "0x82af49447d8a07e3bd95bd0d56f35241523fbab1",
+            Chain::ZkSync => "0x5aea5775959fbc2557cc8789bc1bf90a239d9a91",
+            // WETH is a predeploy on OP Stack chains
+            Chain::Optimism | Chain::Base => "0x4200000000000000000000000000000000000006",
+            Chain::Starknet => return None,
+        };
+        Some(tycho_core::Bytes::from(address))
+    }
 }
 
 impl From<tycho_core::dto::Chain> for Chain {
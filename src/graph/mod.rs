@@ -0,0 +1,5 @@
+//! Token graph
+//!
+//! `protograph::ProtoGraph` connects tokens through the pools containing them, to enumerate and
+//! search the swap sequences between tokens.
+pub mod protograph;
@@ -0,0 +1,1772 @@
+//! Route search over the pool graph
+//!
+//! `ProtoGraph` holds the tracked pools as edges between their tokens: a pool with `n` tokens
+//! connects each two of them, in both directions. Routes may swap several times through a pool,
+//! each swap simulated from the state left by the previous one, e.g. to capture a mispriced
+//! 3 token pool with the circular route through all its tokens. The routes between two tokens,
+//! or the circular routes starting and ending at the same token, are enumerated once with
+//! `build_routes` and cached by id.
+//!
+//! The graph is kept up to date by feeding it every `BlockUpdate` of a stream with
+//! `apply_update`, which returns the pools whose state changed. Passing these to
+//! `search_opportunities` only re-evaluates the routes going through them, found with the route
+//! membership cache. `search_opportunities_par` evaluates the routes in parallel instead, on the
+//! rayon thread pool.
+//!
+//! Once built, the route cache is maintained incrementally: inserting a pair only searches the
+//! routes through its edges, and removing a pair only drops the routes through it.
+//!
+//! Building the routes of all token pairs is too costly on large graphs. `routes_between` instead
+//! computes the routes of a single token pair on demand and keeps the most recently used route sets
+//! in an LRU cache.
+//!
+//! # Examples
+//! ```ignore
+//! let mut graph = ProtoGraph::new(3);
+//! graph.apply_update(&snapshot);
+//! graph.build_routes(&weth.address, &weth.address);
+//!
+//! while let Some(Ok(update)) = stream.next().await {
+//!     let changed = graph.apply_update(&update);
+//!     let opportunities = graph.search_opportunities(find_arbitrage, Some(&changed));
+//! }
+//! ```
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::Mutex,
+};
+
+use alloy_primitives::U256;
+use itertools::Itertools;
+use lru::LruCache;
+use num_bigint::BigUint;
+use num_traits::ToPrimitive;
+use rayon::prelude::*;
+use tycho_core::Bytes;
+
+use crate::{
+    models::{NativePrice, Token},
+    protocol::{
+        errors::SimulationError,
+        models::{BlockUpdate, GasSource, ProtocolComponent},
+        state::ProtocolSim,
+    },
+};
+
+/// Number of token pairs whose routes are kept by `routes_between` by default
+pub const DEFAULT_ROUTE_CACHE_SIZE: usize = 1024;
+
+/// A pool of the graph: its static properties and current state
+#[derive(Debug, Clone)]
+pub struct Pair {
+    pub properties: ProtocolComponent,
+    pub state: Box<dyn ProtocolSim>,
+}
+
+impl Pair {
+    pub fn new(properties: ProtocolComponent, state: Box<dyn ProtocolSim>) -> Self {
+        Pair { properties, state }
+    }
+}
+
+/// A single swap of a `SwapSequence`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Swap {
+    /// Address of the pool swapped with
+    pub pool: Bytes,
+    pub token_in: Token,
+    pub token_out: Token,
+    pub amount_in: BigUint,
+    pub amount_out: BigUint,
+    /// Fee of the pool as a ratio, see `ProtocolSim::fee`
+    pub fee: f64,
+}
+
+/// Swaps executed one after the other, each spending the output of the previous one
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapSequence {
+    pub swaps: Vec<Swap>,
+    /// Gas of all swaps, paying for the transaction itself (`GasSource::BaseTx`) only once
+    pub gas: BigUint,
+}
+
+impl SwapSequence {
+    /// Returns the amount spent by the first swap.
+    pub fn amount_in(&self) -> Option<&BigUint> {
+        self.swaps
+            .first()
+            .map(|swap| &swap.amount_in)
+    }
+
+    /// Returns the amount received by the last swap.
+    pub fn amount_out(&self) -> Option<&BigUint> {
+        self.swaps
+            .last()
+            .map(|swap| &swap.amount_out)
+    }
+}
+
+/// A route through the graph, borrowing its tokens and pairs from the `ProtoGraph`
+///
+/// `tokens` has one more element than `pairs`: the i-th pair swaps `tokens[i]` for
+/// `tokens[i + 1]`.
+#[derive(Debug, Clone)]
+pub struct Route<'a> {
+    /// Id of the route in the graph's route cache. Ids are only valid for the
+    /// `ProtoGraph::route_generation` they were handed out in. Routes returned by
+    /// `routes_between` are numbered in the order they are returned instead.
+    pub id: usize,
+    pub tokens: Vec<&'a Token>,
+    pub pairs: Vec<&'a Pair>,
+}
+
+impl<'a> Route<'a> {
+    /// Returns the price of the route: the product of the spot prices of its swaps, i.e. the
+    /// amount of the last token received per unit of the first one, without price impact.
+    pub fn price(&self) -> Result<f64, SimulationError> {
+        self.hops()
+            .map(|(pair, token_in, token_out)| {
+                pair.state
+                    .spot_price(token_in, token_out)
+            })
+            .product()
+    }
+
+    /// Returns the price of the route net of gas, for a trade of one unit of the first token.
+    ///
+    /// The gas of swapping one unit along the route is priced at `gas_price_wei` and converted
+    /// to the last token with `native_per_out_token`, the amount of native token (e.g. ETH) one
+    /// unit of the last token is worth. This cost is then subtracted from the gross `price`:
+    ///
+    /// ```text
+    /// net_price = price - gas * gas_price_wei / 1e18 / native_per_out_token
+    /// ```
+    ///
+    /// Unlike `price`, this simulates the route, so it's slower. The net price is negative when
+    /// gas costs more than the output of a single unit.
+    pub fn net_price(
+        &self,
+        gas_price_wei: U256,
+        native_per_out_token: f64,
+    ) -> Result<f64, SimulationError> {
+        let price = self.price()?;
+        let first = self
+            .tokens
+            .first()
+            .ok_or_else(|| SimulationError::InvalidInput("Route has no tokens".into(), None))?;
+        let one_unit = BigUint::from(10u64).pow(first.decimals as u32);
+        let gas = self.get_amount_out(one_unit)?.gas;
+        let gas_price = BigUint::from_bytes_be(&gas_price_wei.to_be_bytes::<32>());
+        let gas_cost_native = (gas * gas_price)
+            .to_f64()
+            .unwrap_or(f64::INFINITY) /
+            1e18;
+        Ok(price - gas_cost_native / native_per_out_token)
+    }
+
+    /// Returns the net price of the route like `net_price`, with the last token's native price
+    /// taken from `prices`.
+    ///
+    /// Fails with `SimulationError::InvalidInput` if `prices` doesn't know the last token.
+    pub fn net_price_with(
+        &self,
+        gas_price_wei: U256,
+        prices: &dyn NativePrice,
+    ) -> Result<f64, SimulationError> {
+        let last = self
+            .tokens
+            .last()
+            .ok_or_else(|| SimulationError::InvalidInput("Route has no tokens".into(), None))?;
+        let native_per_out_token = prices
+            .native_per_token(last)
+            .ok_or_else(|| {
+                SimulationError::InvalidInput(format!("No native price for {}", last.symbol), None)
+            })?;
+        self.net_price(gas_price_wei, native_per_out_token)
+    }
+
+    /// Simulates swapping `amount_in` of the first token along the route.
+    ///
+    /// Each pair is simulated from its current state. A pool swapped through again is simulated
+    /// from the state left by its previous swap, see `GetAmountOutResult::new_state`.
+    ///
+    /// The gas of the swaps is added up like `GetAmountOutResult::aggregate` does: the base cost
+    /// of the transaction is only paid once.
+    pub fn get_amount_out(&self, amount_in: BigUint) -> Result<SwapSequence, SimulationError> {
+        let mut swaps = Vec::with_capacity(self.pairs.len());
+        let mut gas = BigUint::default();
+        let mut base_tx_gas = BigUint::default();
+        let mut amount = amount_in;
+        // States of the pools already swapped through, by address
+        let mut swapped: HashMap<&Bytes, Box<dyn ProtocolSim>> = HashMap::new();
+        for (pair, token_in, token_out) in self.hops() {
+            let address = &pair.properties.address;
+            let state = swapped
+                .get(address)
+                .map_or(pair.state.as_ref(), |state| state.as_ref());
+            let res = state.get_amount_out(amount.clone(), token_in, token_out)?;
+            let hop_base_tx_gas: BigUint = res
+                .gas_breakdown
+                .iter()
+                .filter(|item| item.source == GasSource::BaseTx)
+                .map(|item| &item.gas)
+                .sum();
+            gas += &res.gas - &hop_base_tx_gas;
+            base_tx_gas = base_tx_gas.max(hop_base_tx_gas);
+            swaps.push(Swap {
+                pool: address.clone(),
+                token_in: token_in.clone(),
+                token_out: token_out.clone(),
+                amount_in: amount,
+                amount_out: res.amount.clone(),
+                fee: state.fee(),
+            });
+            amount = res.amount;
+            swapped.insert(address, res.new_state);
+        }
+        gas += base_tx_gas;
+        Ok(SwapSequence { swaps, gas })
+    }
+
+    /// Returns each pair with the tokens it swaps.
+    fn hops(&self) -> impl Iterator<Item = (&'a Pair, &'a Token, &'a Token)> + '_ {
+        self.pairs
+            .iter()
+            .zip(self.tokens.windows(2))
+            .map(|(pair, tokens)| (*pair, tokens[0], tokens[1]))
+    }
+}
+
+/// Native token prices read from reference pools of a `ProtoGraph`, see `pool_native_price`
+///
+/// A token is priced with the first reference pool containing both it and the native token, at
+/// the pool's spot price. The native token itself is worth 1.
+#[derive(Debug, Clone)]
+pub struct PoolNativePrice<'a> {
+    graph: &'a ProtoGraph,
+    native: Bytes,
+    pools: Vec<Bytes>,
+}
+
+impl NativePrice for PoolNativePrice<'_> {
+    fn native_per_token(&self, token: &Token) -> Option<f64> {
+        if token.address == self.native {
+            return Some(1.0);
+        }
+        let native = self.graph.tokens.get(&self.native)?;
+        self.pools
+            .iter()
+            .filter_map(|address| self.graph.pairs.get(address))
+            .filter(|pair| pair.state.is_active())
+            .filter(|pair| {
+                let tokens = &pair.properties.tokens;
+                tokens.contains(token) && tokens.contains(native)
+            })
+            .find_map(|pair| {
+                pair.state
+                    .spot_price(token, native)
+                    .ok()
+            })
+    }
+}
+
+/// A route of the cache, referring to its tokens and pools by address
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CachedRoute {
+    tokens: Vec<Bytes>,
+    pools: Vec<Bytes>,
+}
+
+/// Graph of tokens connected by the pools containing them
+#[derive(Debug, Default)]
+pub struct ProtoGraph {
+    /// Maximum number of swaps of a route
+    n_hops: usize,
+    tokens: HashMap<Bytes, Token>,
+    pairs: HashMap<Bytes, Pair>,
+    /// Addresses of the pairs inserted by `apply_update`, by component id
+    pair_ids: HashMap<String, Bytes>,
+    /// The neighbouring tokens of each token, with the pool connecting them
+    edges: HashMap<Bytes, Vec<(Bytes, Bytes)>>,
+    /// Start and end token of the cached routes, set by `build_routes`
+    route_ends: Option<(Bytes, Bytes)>,
+    routes: Vec<CachedRoute>,
+    /// Ids of the cached routes going through each pool, in ascending order
+    route_memberships: HashMap<Bytes, Vec<usize>>,
+    /// Incremented whenever route ids change
+    route_generation: u64,
+    /// Routes computed by `routes_between`, by start token, end token and maximum hops. Cleared
+    /// whenever pairs are added or removed.
+    route_sets: Option<Mutex<LruCache<(Bytes, Bytes, usize), Vec<CachedRoute>>>>,
+}
+
+/// Buffers a route is resolved into before being handed out, reused across routes
+struct RouteBuffers<'a> {
+    tokens: Vec<&'a Token>,
+    pairs: Vec<&'a Pair>,
+}
+
+impl RouteBuffers<'_> {
+    fn new(n_hops: usize) -> Self {
+        Self { tokens: Vec::with_capacity(n_hops + 1), pairs: Vec::with_capacity(n_hops) }
+    }
+}
+
+impl ProtoGraph {
+    /// Creates an empty graph whose routes have at most `n_hops` swaps.
+    pub fn new(n_hops: usize) -> Self {
+        ProtoGraph { n_hops, ..Default::default() }.with_route_cache_size(DEFAULT_ROUTE_CACHE_SIZE)
+    }
+
+    /// Sets the number of token pairs whose routes are kept by `routes_between`, the least
+    /// recently used ones are evicted first. A size of 0 disables the cache.
+    pub fn with_route_cache_size(mut self, size: usize) -> Self {
+        self.route_sets = NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size)));
+        self
+    }
+
+    /// Returns a `NativePrice` reading prices from the given pools of the graph, e.g. the deepest
+    /// WETH/USDC pool. It reflects the pools' current states, so it's valid until the next
+    /// update.
+    ///
+    /// Pick reference pools that are not among the pools searched for opportunities, see
+    /// `NativePrice` about circularity.
+    pub fn pool_native_price(&self, native: Bytes, pools: Vec<Bytes>) -> PoolNativePrice<'_> {
+        PoolNativePrice { graph: self, native, pools }
+    }
+
+    /// Returns a `NativePrice` like `pool_native_price`, with the wrapped native token of `chain`
+    /// as the native token. Returns `None` if the chain has none, like Starknet.
+    #[cfg(feature = "evm")]
+    pub fn chain_native_price(
+        &self,
+        chain: crate::evm::tycho_models::Chain,
+        pools: Vec<Bytes>,
+    ) -> Option<PoolNativePrice<'_>> {
+        let native = chain.wrapped_native_token()?;
+        Some(self.pool_native_price(native, pools))
+    }
+
+    /// Inserts a pair, connecting each two of its tokens. Returns the pair previously stored at
+    /// the same address, if any, after removing it with `remove_pair`.
+    ///
+    /// If routes were built, the routes through the new pair are appended to the route cache.
+    /// Only routes using the new pair are enumerated, whether it connects tokens already in the
+    /// graph or introduces new ones, so the cache never needs to be rebuilt with `build_routes`.
+    /// Ids of other routes are kept, unless a pair is replaced.
+    pub fn insert_pair(&mut self, pair: Pair) -> Option<Pair> {
+        let address = pair.properties.address.clone();
+        let previous = self.remove_pair(&address);
+        self.clear_route_sets();
+        let properties = &pair.properties;
+        for token in &properties.tokens {
+            self.tokens
+                .entry(token.address.clone())
+                .or_insert_with(|| token.clone());
+        }
+        for tokens in properties.tokens.iter().combinations(2) {
+            let (a, b) = (&tokens[0].address, &tokens[1].address);
+            self.edges
+                .entry(a.clone())
+                .or_default()
+                .push((b.clone(), address.clone()));
+            self.edges
+                .entry(b.clone())
+                .or_default()
+                .push((a.clone(), address.clone()));
+        }
+        self.pairs.insert(address.clone(), pair);
+        if let Some((start, end)) = self.route_ends.clone() {
+            let routes = self.find_routes_through(&start, &end, &address);
+            self.add_routes(routes);
+        }
+        previous
+    }
+
+    /// Removes the pair at `address` and the cached routes going through it. Returns the removed
+    /// pair.
+    ///
+    /// Removing routes shifts the ids of the routes cached after them, so ids handed out before
+    /// are invalidated: `route_generation` is incremented when this happens.
+    pub fn remove_pair(&mut self, address: &Bytes) -> Option<Pair> {
+        let pair = self.pairs.remove(address)?;
+        self.clear_route_sets();
+        for token in &pair.properties.tokens {
+            if let Some(edges) = self.edges.get_mut(&token.address) {
+                edges.retain(|(_, pool)| pool != address);
+                if edges.is_empty() {
+                    self.edges.remove(&token.address);
+                }
+            }
+        }
+        self.drop_routes_through(address);
+        Some(pair)
+    }
+
+    /// Replaces the state of the pair at `address`. Returns `false` if the pair is unknown.
+    pub fn update_state(&mut self, address: &Bytes, state: Box<dyn ProtocolSim>) -> bool {
+        match self.pairs.get_mut(address) {
+            Some(pair) => {
+                pair.state = state;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Applies the pairs and states of a `BlockUpdate` and returns the addresses of the pairs
+    /// whose state changed, including the new ones.
+    ///
+    /// New pairs are only inserted together with their state. Pairs are inserted and removed
+    /// with `insert_pair` and `remove_pair`, which maintain the route cache. A resync update
+    /// replaces all pairs, rebuilding the route cache from its pairs.
+    pub fn apply_update(&mut self, update: &BlockUpdate) -> HashSet<Bytes> {
+        if update.is_resync {
+            self.pairs.clear();
+            self.pair_ids.clear();
+            self.edges.clear();
+            self.clear_route_sets();
+            self.routes.clear();
+            self.route_memberships.clear();
+            self.route_generation += 1;
+        }
+        for (id, component) in &update.removed_pairs {
+            self.pair_ids.remove(id);
+            self.remove_pair(&component.address);
+        }
+        let mut changed = HashSet::new();
+        for (id, component) in &update.new_pairs {
+            if let Some(state) = update.states.get(id) {
+                self.pair_ids
+                    .insert(id.clone(), component.address.clone());
+                self.insert_pair(Pair::new(component.clone(), state.clone()));
+                changed.insert(component.address.clone());
+            }
+        }
+        for (id, state) in &update.states {
+            if update.new_pairs.contains_key(id) {
+                continue;
+            }
+            if let Some(address) = self.pair_ids.get(id).cloned() {
+                if self.update_state(&address, state.clone()) {
+                    changed.insert(address);
+                }
+            }
+        }
+        changed
+    }
+
+    /// Returns the pair at `address`.
+    pub fn pair(&self, address: &Bytes) -> Option<&Pair> {
+        self.pairs.get(address)
+    }
+
+    /// Enumerates the routes from `start` to `end` with at most `n_hops` swaps and caches them,
+    /// replacing the previously built routes. If `start` and `end` are the same token, the
+    /// circular routes through it are built.
+    ///
+    /// Routes never visit a token twice, except for `start` closing a circular route, and never
+    /// swap straight back through the pool of their previous swap. They may swap through a pool
+    /// again otherwise.
+    ///
+    /// The cache is then kept up to date by `insert_pair` and `remove_pair`.
+    pub fn build_routes(&mut self, start: &Bytes, end: &Bytes) {
+        let routes = self.find_routes(start, end, self.n_hops);
+        self.route_ends = Some((start.clone(), end.clone()));
+        self.routes.clear();
+        self.route_memberships.clear();
+        self.route_generation += 1;
+        self.add_routes(routes);
+    }
+
+    /// Returns the generation of the route ids. Ids of routes obtained in an older generation
+    /// may refer to other routes or none at all.
+    pub fn route_generation(&self) -> u64 {
+        self.route_generation
+    }
+
+    /// Returns the routes from `start` to `end` with at most `max_hops` swaps, following the same
+    /// rules as `build_routes`.
+    ///
+    /// Routes are computed on demand and kept in an LRU cache by token pair, independently of the
+    /// routes built by `build_routes`. The cache only holds the topology, so the returned routes
+    /// always use the current states.
+    pub fn routes_between(&self, start: &Bytes, end: &Bytes, max_hops: usize) -> Vec<Route<'_>> {
+        let key = (start.clone(), end.clone(), max_hops);
+        let cached = self
+            .route_sets
+            .as_ref()
+            .and_then(|cache| cache.lock().unwrap().get(&key).cloned());
+        let routes = cached.unwrap_or_else(|| {
+            let routes = self.find_routes(start, end, max_hops);
+            if let Some(cache) = &self.route_sets {
+                cache
+                    .lock()
+                    .unwrap()
+                    .put(key, routes.clone());
+            }
+            routes
+        });
+        routes
+            .iter()
+            .enumerate()
+            .filter_map(|(id, route)| self.resolve(id, route))
+            .collect()
+    }
+
+    /// Returns the cached routes.
+    pub fn routes(&self) -> Vec<Route<'_>> {
+        (0..self.routes.len())
+            .filter_map(|id| self.route(id))
+            .collect()
+    }
+
+    /// Searches the cached routes for opportunities.
+    ///
+    /// `search` is called with every route going through one of `changed_pairs`, or with every
+    /// route if `None`, and returns the opportunity found on it, if any. Routes are visited in
+    /// the order of their ids. Routes through inactive pairs are skipped.
+    pub fn search_opportunities<F>(
+        &self,
+        search: F,
+        changed_pairs: Option<&HashSet<Bytes>>,
+    ) -> Vec<SwapSequence>
+    where
+        F: Fn(Route) -> Option<SwapSequence>,
+    {
+        let mut buffers = RouteBuffers::new(self.n_hops);
+        self.route_ids(changed_pairs)
+            .into_iter()
+            .filter_map(|id| self.active_route(id, &mut buffers))
+            .filter_map(search)
+            .collect()
+    }
+
+    /// Searches the cached routes for opportunities like `search_opportunities`, evaluating
+    /// routes in parallel on the rayon thread pool.
+    ///
+    /// Opportunities are returned in the order of the ids of their routes, so the result is the
+    /// same as the one of `search_opportunities`.
+    pub fn search_opportunities_par<F>(
+        &self,
+        search: F,
+        changed_pairs: Option<&HashSet<Bytes>>,
+    ) -> Vec<SwapSequence>
+    where
+        F: Fn(Route) -> Option<SwapSequence> + Sync,
+    {
+        let mut opportunities: Vec<(usize, SwapSequence)> = self
+            .route_ids(changed_pairs)
+            .into_par_iter()
+            .map_init(
+                || RouteBuffers::new(self.n_hops),
+                |buffers, id| {
+                    self.active_route(id, buffers)
+                        .and_then(&search)
+                        .map(|swaps| (id, swaps))
+                },
+            )
+            .flatten()
+            .collect();
+        opportunities.sort_unstable_by_key(|(id, _)| *id);
+        opportunities
+            .into_iter()
+            .map(|(_, swaps)| swaps)
+            .collect()
+    }
+
+    /// Returns the ids of the cached routes going through one of `changed_pairs`, or of all
+    /// routes if `None`, in ascending order.
+    fn route_ids(&self, changed_pairs: Option<&HashSet<Bytes>>) -> Vec<usize> {
+        match changed_pairs {
+            Some(changed) => changed
+                .iter()
+                .filter_map(|address| self.route_memberships.get(address))
+                .flatten()
+                .copied()
+                .sorted_unstable()
+                .dedup()
+                .collect_vec(),
+            None => (0..self.routes.len()).collect_vec(),
+        }
+    }
+
+    /// Resolves the cached route `id` if all its pairs are active.
+    ///
+    /// The route is first resolved into `buffers`, so routes through inactive pairs don't
+    /// allocate. The returned route gets buffers of its own since `search` takes ownership.
+    fn active_route<'a>(&'a self, id: usize, buffers: &mut RouteBuffers<'a>) -> Option<Route<'a>> {
+        let cached = self.routes.get(id)?;
+        buffers.tokens.clear();
+        buffers.pairs.clear();
+        for address in &cached.tokens {
+            buffers
+                .tokens
+                .push(self.tokens.get(address)?);
+        }
+        for address in &cached.pools {
+            let pair = self.pairs.get(address)?;
+            if !pair.state.is_active() {
+                return None;
+            }
+            buffers.pairs.push(pair);
+        }
+        Some(Route { id, tokens: buffers.tokens.clone(), pairs: buffers.pairs.clone() })
+    }
+
+    /// Resolves the cached route `id`.
+    fn route(&self, id: usize) -> Option<Route<'_>> {
+        self.resolve(id, self.routes.get(id)?)
+    }
+
+    /// Looks up the tokens and pairs of a route. Returns `None` if one of its pairs was removed.
+    fn resolve(&self, id: usize, cached: &CachedRoute) -> Option<Route<'_>> {
+        let tokens = cached
+            .tokens
+            .iter()
+            .map(|address| self.tokens.get(address))
+            .collect::<Option<Vec<_>>>()?;
+        let pairs = cached
+            .pools
+            .iter()
+            .map(|address| self.pairs.get(address))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Route { id, tokens, pairs })
+    }
+
+    fn find_routes(&self, start: &Bytes, end: &Bytes, max_hops: usize) -> Vec<CachedRoute> {
+        let mut routes = Vec::new();
+        self.extend_routes(end, max_hops, &mut vec![start.clone()], &mut Vec::new(), &mut routes);
+        routes
+    }
+
+    /// Depth-first search of the routes to `end` continuing the route made of `tokens` and
+    /// `pools`.
+    fn extend_routes(
+        &self,
+        end: &Bytes,
+        max_hops: usize,
+        tokens: &mut Vec<Bytes>,
+        pools: &mut Vec<Bytes>,
+        routes: &mut Vec<CachedRoute>,
+    ) {
+        if pools.len() >= max_hops {
+            return;
+        }
+        let current = tokens
+            .last()
+            .expect("routes start with a token")
+            .clone();
+        for (next, pool) in self
+            .edges
+            .get(&current)
+            .into_iter()
+            .flatten()
+        {
+            if undoes_last_swap(tokens, pools, next, pool) {
+                continue;
+            }
+            if next == end {
+                let mut route = CachedRoute { tokens: tokens.clone(), pools: pools.clone() };
+                route.tokens.push(next.clone());
+                route.pools.push(pool.clone());
+                routes.push(route);
+            } else if !tokens.contains(next) {
+                tokens.push(next.clone());
+                pools.push(pool.clone());
+                self.extend_routes(end, max_hops, tokens, pools, routes);
+                tokens.pop();
+                pools.pop();
+            }
+        }
+    }
+
+    /// Finds the routes from `start` to `end` going through the pool at `address`.
+    ///
+    /// Each route is split at its first swap through the pool: the part before it is searched
+    /// backwards from the swap's input token without using the pool, the rest forwards from its
+    /// output token like `build_routes` does. Each ordered pair of the pool's tokens is a possible
+    /// swap, so a pool with `n` tokens is entered through `n * (n - 1)` swaps.
+    fn find_routes_through(&self, start: &Bytes, end: &Bytes, address: &Bytes) -> Vec<CachedRoute> {
+        let mut routes = Vec::new();
+        let Some(pair) = self.pairs.get(address) else {
+            return routes;
+        };
+        if self.n_hops == 0 {
+            return routes;
+        }
+        for tokens in pair
+            .properties
+            .tokens
+            .iter()
+            .permutations(2)
+        {
+            let (token_in, token_out) = (&tokens[0].address, &tokens[1].address);
+            for (mut tokens, mut pools) in self.find_prefixes(start, end, token_in, address) {
+                tokens.push(token_out.clone());
+                pools.push(address.clone());
+                if token_out == end {
+                    routes.push(CachedRoute { tokens, pools });
+                } else if !tokens[..tokens.len() - 1].contains(token_out) {
+                    self.extend_routes(end, self.n_hops, &mut tokens, &mut pools, &mut routes);
+                }
+            }
+        }
+        routes
+    }
+
+    /// Returns the routes from `start` to `token`, not using the pool at `excluded` and leaving
+    /// at least one swap for the rest of the route.
+    fn find_prefixes(
+        &self,
+        start: &Bytes,
+        end: &Bytes,
+        token: &Bytes,
+        excluded: &Bytes,
+    ) -> Vec<(Vec<Bytes>, Vec<Bytes>)> {
+        if token == start {
+            return vec![(vec![start.clone()], Vec::new())];
+        }
+        // Routes end as soon as they reach `end`
+        if token == end {
+            return Vec::new();
+        }
+        let mut prefixes = Vec::new();
+        self.extend_prefixes(
+            start,
+            end,
+            excluded,
+            &mut vec![token.clone()],
+            &mut Vec::new(),
+            &mut prefixes,
+        );
+        prefixes
+    }
+
+    /// Depth-first search of the routes from `start` to the first of `tokens`, walking
+    /// backwards.
+    fn extend_prefixes(
+        &self,
+        start: &Bytes,
+        end: &Bytes,
+        excluded: &Bytes,
+        tokens: &mut Vec<Bytes>,
+        pools: &mut Vec<Bytes>,
+        prefixes: &mut Vec<(Vec<Bytes>, Vec<Bytes>)>,
+    ) {
+        if pools.len() + 2 > self.n_hops {
+            return;
+        }
+        let current = tokens
+            .last()
+            .expect("prefixes start with a token")
+            .clone();
+        for (next, pool) in self
+            .edges
+            .get(&current)
+            .into_iter()
+            .flatten()
+        {
+            if pool == excluded || undoes_last_swap(tokens, pools, next, pool) {
+                continue;
+            }
+            if next == start {
+                let prefix_tokens = tokens
+                    .iter()
+                    .chain([next])
+                    .rev()
+                    .cloned()
+                    .collect();
+                let prefix_pools = pools
+                    .iter()
+                    .chain([pool])
+                    .rev()
+                    .cloned()
+                    .collect();
+                prefixes.push((prefix_tokens, prefix_pools));
+            } else if next != end && !tokens.contains(next) {
+                tokens.push(next.clone());
+                pools.push(pool.clone());
+                self.extend_prefixes(start, end, excluded, tokens, pools, prefixes);
+                tokens.pop();
+                pools.pop();
+            }
+        }
+    }
+
+    /// Appends routes to the cache.
+    fn add_routes(&mut self, routes: Vec<CachedRoute>) {
+        for route in routes {
+            let id = self.routes.len();
+            for pool in route.pools.iter().unique() {
+                self.route_memberships
+                    .entry(pool.clone())
+                    .or_default()
+                    .push(id);
+            }
+            self.routes.push(route);
+        }
+    }
+
+    /// Drops the cached routes going through the pool at `address` and renumbers the others.
+    fn drop_routes_through(&mut self, address: &Bytes) {
+        let Some(dropped) = self.route_memberships.remove(address) else {
+            return;
+        };
+        let dropped: HashSet<usize> = dropped.into_iter().collect();
+        let mut new_ids = Vec::with_capacity(self.routes.len());
+        let mut kept = Vec::with_capacity(self.routes.len() - dropped.len());
+        for (id, route) in std::mem::take(&mut self.routes)
+            .into_iter()
+            .enumerate()
+        {
+            if dropped.contains(&id) {
+                new_ids.push(None);
+            } else {
+                new_ids.push(Some(kept.len()));
+                kept.push(route);
+            }
+        }
+        self.routes = kept;
+        for ids in self.route_memberships.values_mut() {
+            ids.retain_mut(|id| match new_ids[*id] {
+                Some(new_id) => {
+                    *id = new_id;
+                    true
+                }
+                None => false,
+            });
+        }
+        self.route_memberships
+            .retain(|_, ids| !ids.is_empty());
+        self.route_generation += 1;
+    }
+
+    fn clear_route_sets(&mut self) {
+        if let Some(cache) = &mut self.route_sets {
+            cache.get_mut().unwrap().clear();
+        }
+    }
+}
+
+/// Returns whether swapping to `next` through `pool` undoes the last swap of the route made of
+/// `tokens` and `pools`. Also holds for routes walked backwards.
+fn undoes_last_swap(tokens: &[Bytes], pools: &[Bytes], next: &Bytes, pool: &Bytes) -> bool {
+    pools.last() == Some(pool) && tokens.len() >= 2 && &tokens[tokens.len() - 2] == next
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+    #[cfg(feature = "evm")]
+    use crate::evm::tycho_models::Chain;
+    use crate::{
+        protocol::models::RemovalReason,
+        testing::{symbol_address, token, BlockUpdateBuilder, MockProtocolSim, MOCK_SWAP_GAS},
+    };
+
+    fn graph(n_hops: usize, pairs: &[&str]) -> ProtoGraph {
+        let mut builder = BlockUpdateBuilder::new(1);
+        for pair in pairs {
+            builder = builder.pool(pair, pair, MockProtocolSim::new());
+        }
+        let mut graph = ProtoGraph::new(n_hops);
+        graph.apply_update(&builder.build());
+        graph
+    }
+
+    /// Describes routes by the symbols of their tokens, e.g. `"A-B-C"`.
+    fn symbols(routes: &[Route]) -> HashSet<String> {
+        routes
+            .iter()
+            .map(|route| {
+                route
+                    .tokens
+                    .iter()
+                    .map(|token| token.symbol.as_str())
+                    .join("-")
+            })
+            .collect()
+    }
+
+    #[rstest]
+    #[case::too_short(2, &[])]
+    #[case::both_directions(3, &["A-B-C-A", "A-C-B-A"])]
+    fn test_build_routes_triangle(#[case] n_hops: usize, #[case] expected: &[&str]) {
+        let mut graph = graph(n_hops, &["A/B", "B/C", "C/A"]);
+
+        graph.build_routes(&token("A").address, &token("A").address);
+
+        let expected: HashSet<String> = expected
+            .iter()
+            .map(|route| route.to_string())
+            .collect();
+        assert_eq!(symbols(&graph.routes()), expected);
+    }
+
+    #[rstest]
+    #[case::direct(2, &["A-B-D", "A-C-D"])]
+    #[case::through_cross_edge(3, &["A-B-D", "A-C-D", "A-B-C-D", "A-C-B-D"])]
+    fn test_build_routes_diamond(#[case] n_hops: usize, #[case] expected: &[&str]) {
+        let mut graph = graph(n_hops, &["A/B", "A/C", "B/D", "C/D", "B/C"]);
+
+        graph.build_routes(&token("A").address, &token("D").address);
+
+        let expected: HashSet<String> = expected
+            .iter()
+            .map(|route| route.to_string())
+            .collect();
+        assert_eq!(symbols(&graph.routes()), expected);
+    }
+
+    #[rstest]
+    #[case::circular("A", "A")]
+    #[case::non_circular("A", "D")]
+    #[case::reversed("D", "B")]
+    fn test_routes_between_matches_build_routes(#[case] start: &str, #[case] end: &str) {
+        let mut graph = graph(3, &["A/B", "A/C", "B/D", "C/D", "B/C", "D/E"]);
+        let (start, end) = (token(start).address, token(end).address);
+
+        graph.build_routes(&start, &end);
+        let lazy = graph.routes_between(&start, &end, 3);
+
+        assert!(!lazy.is_empty());
+        assert_eq!(symbols(&lazy), symbols(&graph.routes()));
+        // Served from the cache the second time
+        assert_eq!(symbols(&graph.routes_between(&start, &end, 3)), symbols(&lazy));
+    }
+
+    #[test]
+    fn test_routes_between_cache() {
+        let mut graph = graph(3, &["A/B", "B/C", "C/D"]).with_route_cache_size(1);
+        let (a, c, d) = (token("A").address, token("C").address, token("D").address);
+
+        assert_eq!(symbols(&graph.routes_between(&a, &d, 3)), HashSet::from(["A-B-C-D".into()]));
+        assert_eq!(symbols(&graph.routes_between(&a, &c, 3)), HashSet::from(["A-B-C".into()]));
+        {
+            let cache = graph
+                .route_sets
+                .as_mut()
+                .unwrap()
+                .get_mut()
+                .unwrap();
+            assert_eq!(cache.len(), 1);
+            assert!(cache.contains(&(a.clone(), c.clone(), 3)));
+        }
+
+        // New pairs invalidate the cached routes
+        graph.insert_pair(Pair::new(
+            ProtocolComponent::new(symbol_address("A/C"), vec![token("A"), token("C")]),
+            Box::new(MockProtocolSim::new()),
+        ));
+        assert_eq!(
+            symbols(&graph.routes_between(&a, &c, 3)),
+            HashSet::from(["A-B-C".into(), "A-C".into()])
+        );
+
+        let uncached = graph.with_route_cache_size(0);
+        assert_eq!(symbols(&uncached.routes_between(&a, &d, 2)), HashSet::from(["A-C-D".into()]));
+        assert!(uncached.route_sets.is_none());
+    }
+
+    #[test]
+    fn test_build_routes_parallel_pools() {
+        let mut graph = graph(2, &["A/B"]);
+        graph.insert_pair(Pair::new(
+            ProtocolComponent::new(symbol_address("A/B 2"), vec![token("A"), token("B")]),
+            Box::new(MockProtocolSim::new()),
+        ));
+
+        graph.build_routes(&token("A").address, &token("A").address);
+
+        // Two pools connect A and B, each can be used to go back
+        assert_eq!(graph.routes().len(), 2);
+    }
+
+    #[rstest]
+    #[case::a_b("A", "B")]
+    #[case::a_c("A", "C")]
+    #[case::b_a("B", "A")]
+    #[case::b_c("B", "C")]
+    #[case::c_a("C", "A")]
+    #[case::c_b("C", "B")]
+    fn test_three_token_pool_routes(#[case] token_in: &str, #[case] token_out: &str) {
+        let state = MockProtocolSim::new()
+            .with_spot_price("A", "B", 2.0)
+            .with_spot_price("A", "C", 4.0)
+            .with_spot_price("B", "C", 2.0);
+        let graph = {
+            let update = BlockUpdateBuilder::new(1)
+                .pool("A/B/C", "A/B/C", state.clone())
+                .build();
+            let mut graph = ProtoGraph::new(3);
+            graph.apply_update(&update);
+            graph
+        };
+        let (start, end) = (token(token_in), token(token_out));
+        let third = ["A", "B", "C"]
+            .into_iter()
+            .find(|symbol| ![token_in, token_out].contains(symbol))
+            .unwrap();
+
+        // The direct swap, and the detour through the pool's third token
+        let routes = graph.routes_between(&start.address, &end.address, 3);
+        assert_eq!(
+            symbols(&routes),
+            HashSet::from([
+                format!("{token_in}-{token_out}"),
+                format!("{token_in}-{third}-{token_out}")
+            ])
+        );
+
+        let direct = routes
+            .iter()
+            .find(|route| route.pairs.len() == 1)
+            .unwrap();
+        let amount_in = BigUint::from(10u64).pow(18);
+        let swaps = direct
+            .get_amount_out(amount_in.clone())
+            .unwrap();
+        let expected = state
+            .get_amount_out(amount_in, &start, &end)
+            .unwrap();
+        assert_eq!(swaps.swaps.len(), 1);
+        assert_eq!((&swaps.swaps[0].token_in, &swaps.swaps[0].token_out), (&start, &end));
+        assert_eq!(swaps.amount_out(), Some(&expected.amount));
+        assert_eq!(direct.price().unwrap(), state.spot_price(&start, &end).unwrap());
+    }
+
+    #[cfg(feature = "evm")]
+    #[test]
+    fn test_get_amount_out_revisiting_pool() {
+        use crate::{
+            evm::protocol::uniswap_v2::state::UniswapV2State, protocol::models::BASE_TX_GAS,
+        };
+
+        let (a, b) = (token("A"), token("B"));
+        let state = UniswapV2State::new(U256::from(10u64).pow(U256::from(21)), U256::from(2) << 70);
+        let pair = Pair::new(
+            ProtocolComponent::new(symbol_address("pool"), vec![a.clone(), b.clone()]),
+            Box::new(state.clone()),
+        );
+        let route = Route { id: 0, tokens: vec![&a, &b, &a], pairs: vec![&pair, &pair] };
+        let amount_in = BigUint::from(10u64).pow(20);
+
+        let swaps = route
+            .get_amount_out(amount_in.clone())
+            .unwrap();
+
+        let first = state
+            .get_amount_out(amount_in, &a, &b)
+            .unwrap();
+        let second = first
+            .new_state
+            .get_amount_out(first.amount.clone(), &b, &a)
+            .unwrap();
+        let independent = state
+            .get_amount_out(first.amount.clone(), &b, &a)
+            .unwrap();
+        assert_eq!(swaps.swaps[0].amount_out, first.amount);
+        // The second swap sees the reserves left by the first one
+        assert_eq!(swaps.amount_out(), Some(&second.amount));
+        assert_ne!(second.amount, independent.amount);
+        // The transaction's base cost is paid once, not once per swap
+        assert_eq!(swaps.gas, &first.gas + &second.gas - BASE_TX_GAS);
+        let mut total = first;
+        total.aggregate(&second);
+        assert_eq!(swaps.gas, total.gas);
+    }
+
+    #[test]
+    fn test_get_amount_out_built_route_revisiting_pool() {
+        let state = MockProtocolSim::new()
+            .with_spot_price("A", "B", 2.0)
+            .with_spot_price("B", "C", 3.0)
+            .with_spot_price("A", "C", 4.0)
+            .with_price_impact(0.1);
+        let update = BlockUpdateBuilder::new(1)
+            .pool("a_b_c", "A/B/C", state.clone())
+            .build();
+        let mut graph = ProtoGraph::new(3);
+        graph.apply_update(&update);
+        graph.build_routes(&token("A").address, &token("A").address);
+        let (a, b, c) = (token("A"), token("B"), token("C"));
+
+        let routes = graph.routes();
+        let route = routes
+            .iter()
+            .find(|route| {
+                route
+                    .tokens
+                    .iter()
+                    .map(|token| token.symbol.as_str())
+                    .eq(["A", "B", "C", "A"])
+            })
+            .unwrap();
+        let amount_in = BigUint::from(10u64).pow(18);
+        let swaps = route
+            .get_amount_out(amount_in.clone())
+            .unwrap();
+
+        // Every hop swaps through the same pool, each from the state left by the previous one
+        let mut expected_state: Box<dyn ProtocolSim> = Box::new(state.clone());
+        let mut amount = amount_in;
+        for (swap, (token_in, token_out)) in swaps
+            .swaps
+            .iter()
+            .zip([(&a, &b), (&b, &c), (&c, &a)])
+        {
+            assert_eq!(swap.pool, symbol_address("a_b_c"));
+            let res = expected_state
+                .get_amount_out(amount.clone(), token_in, token_out)
+                .unwrap();
+            assert_eq!(swap.amount_out, res.amount);
+            amount = res.amount;
+            expected_state = res.new_state;
+        }
+        let independent = state
+            .get_amount_out(swaps.swaps[2].amount_in.clone(), &c, &a)
+            .unwrap();
+        assert!(swaps.amount_out().unwrap() < &independent.amount);
+    }
+
+    #[rstest]
+    #[case::circular("A", "A", &["A-C-A", "A-B-C-A", "A-C-B-A"], 6)]
+    #[case::non_circular("A", "B", &["A-B", "A-C-B"], 3)]
+    fn test_routes_reuse_pool(
+        #[case] start: &str,
+        #[case] end: &str,
+        #[case] expected: &[&str],
+        #[case] n_routes: usize,
+    ) {
+        let mut graph = graph(3, &["A/B/C", "C/A"]);
+
+        graph.build_routes(&token(start).address, &token(end).address);
+
+        let expected: HashSet<String> = expected
+            .iter()
+            .map(|route| route.to_string())
+            .collect();
+        let routes = graph.routes();
+        assert_eq!(symbols(&routes), expected);
+        assert_eq!(routes.len(), n_routes);
+        // Never straight back through the pool of the previous swap
+        for route in &routes {
+            for (hops, tokens) in route
+                .pairs
+                .windows(2)
+                .zip(route.tokens.windows(3))
+            {
+                let same_pool = hops[0].properties.address == hops[1].properties.address;
+                assert!(!same_pool || tokens[0] != tokens[2]);
+            }
+        }
+    }
+
+    fn pair(pair: &str) -> Pair {
+        let tokens = pair.split('/').map(token).collect();
+        Pair::new(
+            ProtocolComponent::new(symbol_address(pair), tokens),
+            Box::new(MockProtocolSim::new()),
+        )
+    }
+
+    /// Describes the cached routes by their token and pool addresses.
+    fn route_keys(graph: &ProtoGraph) -> HashSet<(Vec<Bytes>, Vec<Bytes>)> {
+        graph
+            .routes()
+            .iter()
+            .map(|route| {
+                let tokens = route
+                    .tokens
+                    .iter()
+                    .map(|token| token.address.clone())
+                    .collect();
+                let pools = route
+                    .pairs
+                    .iter()
+                    .map(|pair| pair.properties.address.clone())
+                    .collect();
+                (tokens, pools)
+            })
+            .collect()
+    }
+
+    /// Checks that the membership cache lists exactly the routes going through each pool.
+    fn assert_memberships_consistent(graph: &ProtoGraph) {
+        let mut expected: HashMap<Bytes, Vec<usize>> = HashMap::new();
+        for (id, route) in graph.routes.iter().enumerate() {
+            for pool in route.pools.iter().unique() {
+                expected
+                    .entry(pool.clone())
+                    .or_default()
+                    .push(id);
+            }
+        }
+        assert_eq!(graph.route_memberships, expected);
+    }
+
+    const INCREMENTAL_PAIRS: [&str; 7] = ["A/B", "A/C", "B/D", "C/D", "B/C", "D/E", "A/B/E"];
+
+    #[rstest]
+    #[case::circular("A", "A", 0)]
+    #[case::circular_partial("A", "A", 3)]
+    #[case::non_circular("A", "D", 0)]
+    #[case::non_circular_partial("A", "D", 4)]
+    #[case::from_pool_token("E", "C", 2)]
+    fn test_insert_pair_extends_routes(
+        #[case] start: &str,
+        #[case] end: &str,
+        #[case] n_built: usize,
+    ) {
+        let (start, end) = (token(start).address, token(end).address);
+        let mut expected = graph(4, &INCREMENTAL_PAIRS);
+        expected.build_routes(&start, &end);
+        let mut graph = graph(4, &INCREMENTAL_PAIRS[..n_built]);
+        graph.build_routes(&start, &end);
+        let ids = graph
+            .routes()
+            .iter()
+            .map(|route| route.id)
+            .collect_vec();
+        let generation = graph.route_generation();
+
+        for pair_name in &INCREMENTAL_PAIRS[n_built..] {
+            graph.insert_pair(pair(pair_name));
+            assert_memberships_consistent(&graph);
+        }
+
+        assert!(!expected.routes().is_empty());
+        assert_eq!(graph.routes().len(), expected.routes().len());
+        assert_eq!(route_keys(&graph), route_keys(&expected));
+        // Insertions only append routes
+        assert_eq!(graph.route_generation(), generation);
+        assert_eq!(
+            graph.routes()[..ids.len()]
+                .iter()
+                .map(|route| route.id)
+                .collect_vec(),
+            ids
+        );
+    }
+
+    #[rstest]
+    #[case::circular("A", "A")]
+    #[case::non_circular("A", "D")]
+    fn test_insert_pair_matches_rebuild(#[case] start: &str, #[case] end: &str) {
+        let (start, end) = (token(start).address, token(end).address);
+        let mut graph = graph(4, &INCREMENTAL_PAIRS);
+        graph.build_routes(&start, &end);
+
+        // A second pool between tokens that are already connected
+        graph.insert_pair(Pair::new(
+            ProtocolComponent::new(symbol_address("A/B 2"), vec![token("A"), token("B")]),
+            Box::new(MockProtocolSim::new()),
+        ));
+        // A pool replaced by one connecting other tokens
+        graph.insert_pair(Pair::new(
+            ProtocolComponent::new(symbol_address("B/C"), vec![token("C"), token("E")]),
+            Box::new(MockProtocolSim::new()),
+        ));
+        assert_memberships_consistent(&graph);
+        let (n_incremental, incremental) = (graph.routes().len(), route_keys(&graph));
+        graph.build_routes(&start, &end);
+
+        assert_eq!(n_incremental, graph.routes().len());
+        assert_eq!(incremental, route_keys(&graph));
+    }
+
+    #[rstest]
+    #[case::circular("A", "A")]
+    #[case::non_circular("A", "D")]
+    fn test_remove_pair_drops_routes(#[case] start: &str, #[case] end: &str) {
+        let (start, end) = (token(start).address, token(end).address);
+        let removed = ["B/C", "A/B/E"];
+        let kept = INCREMENTAL_PAIRS
+            .into_iter()
+            .filter(|pair| !removed.contains(pair))
+            .collect_vec();
+        let mut expected = graph(4, &kept);
+        expected.build_routes(&start, &end);
+        let mut graph = graph(4, &INCREMENTAL_PAIRS);
+        graph.build_routes(&start, &end);
+        let generation = graph.route_generation();
+
+        for pair_name in removed {
+            assert!(graph
+                .remove_pair(&symbol_address(pair_name))
+                .is_some());
+            assert_memberships_consistent(&graph);
+        }
+
+        assert_eq!(route_keys(&graph), route_keys(&expected));
+        assert_eq!(graph.route_generation(), generation + 2);
+        assert!(graph
+            .remove_pair(&symbol_address("B/C"))
+            .is_none());
+        assert_eq!(graph.route_generation(), generation + 2);
+        // Reinserting a pair restores its routes
+        graph.insert_pair(pair("B/C"));
+        expected.insert_pair(pair("B/C"));
+        assert_eq!(route_keys(&graph), route_keys(&expected));
+    }
+
+    #[test]
+    fn test_net_price() {
+        // The route through C has a better gross price but pays gas twice
+        let update = BlockUpdateBuilder::new(1)
+            .pool("a_b", "A/B", MockProtocolSim::new().with_spot_price("A", "B", 2.0))
+            .pool("a_c", "A/C", MockProtocolSim::new().with_spot_price("A", "C", 1.5))
+            .pool("c_b", "C/B", MockProtocolSim::new().with_spot_price("C", "B", 1.4))
+            .build();
+        let mut graph = ProtoGraph::new(2);
+        graph.apply_update(&update);
+        graph.build_routes(&token("A").address, &token("B").address);
+        let routes = graph.routes();
+        let direct = routes
+            .iter()
+            .find(|route| route.pairs.len() == 1)
+            .unwrap();
+        let through_c = routes
+            .iter()
+            .find(|route| route.pairs.len() == 2)
+            .unwrap();
+        // 10 gwei, B worth 0.001 ETH: each swap costs 1 B
+        let gas_price = U256::from(10_000_000_000u64);
+        let native_per_b = MOCK_SWAP_GAS as f64 * 1e-8;
+
+        assert!(through_c.price().unwrap() > direct.price().unwrap());
+        let direct_net = direct
+            .net_price(gas_price, native_per_b)
+            .unwrap();
+        let through_c_net = through_c
+            .net_price(gas_price, native_per_b)
+            .unwrap();
+        approx::assert_relative_eq!(direct_net, 1.0, max_relative = 1e-9);
+        approx::assert_relative_eq!(through_c_net, 0.1, max_relative = 1e-9);
+        assert!(through_c_net < direct_net);
+    }
+
+    #[cfg(feature = "evm")]
+    #[test]
+    fn test_net_price_through_uniswap_v4() {
+        use crate::evm::protocol::{
+            uniswap_v4::state::{UniswapV4Fees, UniswapV4State},
+            utils::uniswap::{tick_list::TickInfo, tick_math::get_sqrt_ratio_at_tick},
+        };
+
+        let liquidity = 10u128.pow(24);
+        let state = UniswapV4State::new(
+            liquidity,
+            get_sqrt_ratio_at_tick(0).unwrap(),
+            UniswapV4Fees::new(0, 0, 3000),
+            0,
+            60,
+            vec![TickInfo::new(-600, liquidity as i128), TickInfo::new(600, -(liquidity as i128))],
+        );
+        let update = BlockUpdateBuilder::new(1)
+            .pool("a_b", "A/B", state)
+            .pool("b_eth", "B/ETH", MockProtocolSim::new().with_spot_price("ETH", "B", 1000.0))
+            .build();
+        let mut graph = ProtoGraph::new(1);
+        graph.apply_update(&update);
+        graph.build_routes(&token("A").address, &token("B").address);
+        let routes = graph.routes();
+        let route = &routes[0];
+        let gas_price = U256::from(10_000_000_000u64);
+
+        let prices = graph.pool_native_price(token("ETH").address, vec![symbol_address("b_eth")]);
+        let net = route
+            .net_price_with(gas_price, &prices)
+            .unwrap();
+        let swaps = route
+            .get_amount_out(BigUint::from(10u64).pow(18))
+            .unwrap();
+
+        assert_eq!(swaps.swaps[0].fee, 0.003);
+        assert!(net.is_finite());
+        assert!(net < route.price().unwrap());
+    }
+
+    #[test]
+    fn test_pool_native_price() {
+        let update = BlockUpdateBuilder::new(1)
+            .pool(
+                "weth_usdc",
+                "WETH/USDC",
+                MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0),
+            )
+            .pool(
+                "usdc_dai",
+                "USDC/DAI",
+                MockProtocolSim::new().with_spot_price("USDC", "DAI", 1.0),
+            )
+            .build();
+        let mut graph = ProtoGraph::new(2);
+        graph.apply_update(&update);
+
+        let prices = graph.pool_native_price(
+            token("WETH").address,
+            vec![symbol_address("weth_usdc"), symbol_address("unknown")],
+        );
+
+        assert_eq!(prices.native_per_token(&token("WETH")), Some(1.0));
+        assert_eq!(prices.native_per_token(&token("USDC")), Some(1.0 / 2000.0));
+        // Not in a reference pool with WETH
+        assert_eq!(prices.native_per_token(&token("DAI")), None);
+    }
+
+    #[cfg(feature = "evm")]
+    #[rstest]
+    #[case::ethereum(Chain::Ethereum, "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")]
+    #[case::arbitrum(Chain::Arbitrum, "0x82af49447d8a07e3bd95bd0d56f35241523fbab1")]
+    #[case::optimism(Chain::Optimism, "0x4200000000000000000000000000000000000006")]
+    #[case::base(Chain::Base, "0x4200000000000000000000000000000000000006")]
+    fn test_chain_native_price(#[case] chain: Chain, #[case] weth: &str) {
+        use crate::testing::token_at;
+
+        let update = BlockUpdateBuilder::new(1)
+            .token(token_at(Bytes::from(weth), "WETH", 18))
+            .pool(
+                "weth_usdc",
+                "WETH/USDC",
+                MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0),
+            )
+            .build();
+        let mut graph = ProtoGraph::new(2);
+        graph.apply_update(&update);
+
+        let prices = graph
+            .chain_native_price(chain, vec![symbol_address("weth_usdc")])
+            .unwrap();
+
+        assert_eq!(prices.native_per_token(&token("USDC")), Some(1.0 / 2000.0));
+        assert!(graph
+            .chain_native_price(Chain::Starknet, vec![])
+            .is_none());
+    }
+
+    #[test]
+    fn test_net_price_with() {
+        let update = BlockUpdateBuilder::new(1)
+            .pool("a_b", "A/B", MockProtocolSim::new().with_spot_price("A", "B", 2.0))
+            .pool("b_eth", "B/ETH", MockProtocolSim::new().with_spot_price("ETH", "B", 1000.0))
+            .build();
+        let mut graph = ProtoGraph::new(1);
+        graph.apply_update(&update);
+        graph.build_routes(&token("A").address, &token("B").address);
+        let routes = graph.routes();
+        let route = &routes[0];
+        let gas_price = U256::from(10_000_000_000u64);
+
+        let prices = graph.pool_native_price(token("ETH").address, vec![symbol_address("b_eth")]);
+        let net = route
+            .net_price_with(gas_price, &prices)
+            .unwrap();
+
+        let expected = route
+            .net_price(gas_price, 0.001)
+            .unwrap();
+        approx::assert_relative_eq!(net, expected, max_relative = 1e-9);
+        let no_prices: HashMap<Bytes, f64> = HashMap::new();
+        assert!(matches!(
+            route.net_price_with(gas_price, &no_prices),
+            Err(SimulationError::InvalidInput(..))
+        ));
+    }
+
+    /// Swaps 1 unit of the route's first token if its price is above 1 and returns the swaps if
+    /// they end up with more than they started with.
+    fn find_arbitrage(route: Route) -> Option<SwapSequence> {
+        if route.price().ok()? <= 1.0 {
+            return None;
+        }
+        let amount_in = BigUint::from(10u64).pow(route.tokens[0].decimals as u32);
+        let swaps = route.get_amount_out(amount_in).ok()?;
+        (swaps.amount_out()? > swaps.amount_in()?).then_some(swaps)
+    }
+
+    /// WETH is bought at 2000 USDC and sold for 1900 DAI, with USDC and DAI at parity.
+    fn arbitrage_graph() -> ProtoGraph {
+        let update = BlockUpdateBuilder::new(1)
+            .pool(
+                "weth_usdc",
+                "WETH/USDC",
+                MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0),
+            )
+            .pool(
+                "usdc_dai",
+                "USDC/DAI",
+                MockProtocolSim::new().with_spot_price("USDC", "DAI", 1.0),
+            )
+            .pool(
+                "dai_weth",
+                "DAI/WETH",
+                MockProtocolSim::new().with_spot_price("WETH", "DAI", 1900.0),
+            )
+            .pool("foo_bar", "FOO/BAR", MockProtocolSim::new().with_spot_price("FOO", "BAR", 1.0))
+            .build();
+        let mut graph = ProtoGraph::new(3);
+        graph.apply_update(&update);
+        graph.build_routes(&token("WETH").address, &token("WETH").address);
+        graph
+    }
+
+    #[test]
+    fn test_search_opportunities() {
+        let graph = arbitrage_graph();
+
+        let opportunities = graph.search_opportunities(find_arbitrage, None);
+
+        assert_eq!(opportunities.len(), 1);
+        let swaps = &opportunities[0];
+        let symbols = swaps
+            .swaps
+            .iter()
+            .map(|swap| swap.token_in.symbol.as_str())
+            .collect_vec();
+        assert_eq!(symbols, ["WETH", "USDC", "DAI"]);
+        assert_eq!(swaps.swaps[2].token_out, token("WETH"));
+        let profit = swaps
+            .amount_out()
+            .unwrap()
+            .to_f64()
+            .unwrap() /
+            1e18;
+        approx::assert_relative_eq!(profit, 2000.0 / 1900.0, max_relative = 1e-9);
+        assert_eq!(swaps.gas, BigUint::from(3 * MOCK_SWAP_GAS));
+    }
+
+    #[test]
+    fn test_search_opportunities_after_update() {
+        let mut graph = arbitrage_graph();
+
+        // Unrelated pools don't trigger a search
+        let update = BlockUpdateBuilder::new(2)
+            .state("foo_bar", MockProtocolSim::new().with_spot_price("FOO", "BAR", 2.0))
+            .build();
+        let changed = graph.apply_update(&update);
+        assert_eq!(changed, HashSet::from([symbol_address("foo_bar")]));
+        assert!(graph
+            .search_opportunities(find_arbitrage, Some(&changed))
+            .is_empty());
+
+        // Closing the price gap removes the opportunity
+        let update = BlockUpdateBuilder::new(3)
+            .state(
+                "dai_weth",
+                MockProtocolSim::new()
+                    .with_spot_price("WETH", "DAI", 2000.0)
+                    .with_fee(0.01),
+            )
+            .build();
+        let changed = graph.apply_update(&update);
+        assert!(graph
+            .search_opportunities(find_arbitrage, Some(&changed))
+            .is_empty());
+
+        // Routes through removed pools are skipped
+        let update = BlockUpdateBuilder::new(4)
+            .state("dai_weth", MockProtocolSim::new().with_spot_price("WETH", "DAI", 1900.0))
+            .build();
+        let changed = graph.apply_update(&update);
+        assert_eq!(
+            graph
+                .search_opportunities(find_arbitrage, Some(&changed))
+                .len(),
+            1
+        );
+        let update = BlockUpdateBuilder::new(5)
+            .removed_pair("usdc_dai", "USDC/DAI", RemovalReason::BelowTvlThreshold)
+            .build();
+        graph.apply_update(&update);
+        assert!(graph
+            .search_opportunities(find_arbitrage, None)
+            .is_empty());
+    }
+
+    /// WETH is quoted at 2000 USDC directly and through 20 tokens at slightly different prices.
+    fn wide_arbitrage_graph() -> ProtoGraph {
+        let mut builder = BlockUpdateBuilder::new(1).pool(
+            "weth_usdc",
+            "WETH/USDC",
+            MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0),
+        );
+        for i in 0..20 {
+            let symbol = format!("T{i}");
+            builder = builder
+                .pool(
+                    &format!("weth_{i}"),
+                    &format!("WETH/{symbol}"),
+                    MockProtocolSim::new().with_spot_price("WETH", &symbol, 100.0),
+                )
+                .pool(
+                    &format!("usdc_{i}"),
+                    &format!("{symbol}/USDC"),
+                    MockProtocolSim::new().with_spot_price(&symbol, "USDC", 19.0 + i as f64 * 0.1),
+                );
+        }
+        let mut graph = ProtoGraph::new(4);
+        graph.apply_update(&builder.build());
+        graph.build_routes(&token("WETH").address, &token("WETH").address);
+        graph
+    }
+
+    #[rstest]
+    #[case::all_routes(None)]
+    #[case::changed_pairs(Some(&["usdc_3", "weth_usdc", "weth_17"][..]))]
+    fn test_search_opportunities_par_matches_sequential(#[case] changed: Option<&[&str]>) {
+        let graph = wide_arbitrage_graph();
+        let changed = changed.map(|ids| {
+            ids.iter()
+                .map(|id| symbol_address(id))
+                .collect::<HashSet<_>>()
+        });
+
+        let sequential = graph.search_opportunities(find_arbitrage, changed.as_ref());
+        let parallel = graph.search_opportunities_par(find_arbitrage, changed.as_ref());
+
+        assert!(!sequential.is_empty());
+        assert_eq!(parallel, sequential);
+    }
+
+    #[test]
+    fn test_search_opportunities_skips_inactive_pairs() {
+        let mut graph = arbitrage_graph();
+        let update = BlockUpdateBuilder::new(2)
+            .state(
+                "usdc_dai",
+                MockProtocolSim::new()
+                    .with_spot_price("USDC", "DAI", 1.0)
+                    .inactive(),
+            )
+            .build();
+        graph.apply_update(&update);
+
+        assert!(graph
+            .search_opportunities(find_arbitrage, None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_insert_pair_connects_all_token_pairs() {
+        let mut graph = ProtoGraph::new(3);
+
+        graph.insert_pair(pair("A/B/C"));
+
+        // One edge per pair of tokens, in both directions, all through the same pool
+        let address = symbol_address("A/B/C");
+        for symbol in ["A", "B", "C"] {
+            let neighbours: HashSet<_> = graph.edges[&token(symbol).address]
+                .iter()
+                .map(|(next, pool)| {
+                    assert_eq!(pool, &address);
+                    next.clone()
+                })
+                .collect();
+            let expected: HashSet<_> = ["A", "B", "C"]
+                .into_iter()
+                .filter(|other| *other != symbol)
+                .map(|other| token(other).address)
+                .collect();
+            assert_eq!(neighbours, expected);
+        }
+    }
+
+    /// The 3-coin pool `a_b_c` is mispriced internally: A -> B -> C -> A through it returns 1.5 A
+    /// per A. `c_a` quotes A at `price_c_a` C.
+    #[rstest]
+    #[case::internal_only(
+        4.0,
+        &[
+            &[("a_b_c", "A", "B"), ("a_b_c", "B", "C"), ("a_b_c", "C", "A")],
+            &[("a_b_c", "A", "B"), ("a_b_c", "B", "C"), ("c_a", "C", "A")],
+        ]
+    )]
+    #[case::through_other_pool(
+        5.0,
+        &[
+            &[("c_a", "A", "C"), ("a_b_c", "C", "A")],
+            &[("a_b_c", "A", "B"), ("a_b_c", "B", "C"), ("a_b_c", "C", "A")],
+            &[("a_b_c", "A", "B"), ("a_b_c", "B", "C"), ("c_a", "C", "A")],
+        ]
+    )]
+    fn test_search_opportunities_three_token_pool(
+        #[case] price_c_a: f64,
+        #[case] expected: &[&[(&str, &str, &str)]],
+    ) {
+        let update = BlockUpdateBuilder::new(1)
+            .pool(
+                "a_b_c",
+                "A/B/C",
+                MockProtocolSim::new()
+                    .with_spot_price("A", "B", 2.0)
+                    .with_spot_price("B", "C", 3.0)
+                    .with_spot_price("A", "C", 4.0),
+            )
+            .pool("c_a", "C/A", MockProtocolSim::new().with_spot_price("A", "C", price_c_a))
+            .build();
+        let mut graph = ProtoGraph::new(3);
+        graph.apply_update(&update);
+        graph.build_routes(&token("A").address, &token("A").address);
+
+        let opportunities = graph.search_opportunities(find_arbitrage, None);
+
+        // The internal arbitrage swaps three times through the pool. Swaps through the pool use
+        // the tokens of the route, not just any pair of the pool's tokens.
+        let routes: HashSet<_> = opportunities
+            .iter()
+            .map(|opportunity| {
+                opportunity
+                    .swaps
+                    .iter()
+                    .map(|swap| (swap.pool.clone(), swap.token_in.clone(), swap.token_out.clone()))
+                    .collect_vec()
+            })
+            .collect();
+        let expected: HashSet<_> = expected
+            .iter()
+            .map(|swaps| {
+                swaps
+                    .iter()
+                    .map(|(pool, token_in, token_out)| {
+                        (symbol_address(pool), token(token_in), token(token_out))
+                    })
+                    .collect_vec()
+            })
+            .collect();
+        assert_eq!(routes, expected);
+        // Each hop swaps what the previous one received, back into the starting token
+        for opportunity in &opportunities {
+            for hops in opportunity.swaps.windows(2) {
+                assert_eq!(hops[1].token_in, hops[0].token_out);
+                assert_eq!(hops[1].amount_in, hops[0].amount_out);
+            }
+            assert_eq!(opportunity.swaps[0].token_in, token("A"));
+            assert_eq!(
+                opportunity
+                    .swaps
+                    .last()
+                    .unwrap()
+                    .token_out,
+                token("A")
+            );
+        }
+        let internal = opportunities
+            .iter()
+            .find(|opportunity| {
+                opportunity
+                    .swaps
+                    .iter()
+                    .all(|swap| swap.pool == symbol_address("a_b_c"))
+            })
+            .unwrap();
+        assert_eq!(
+            internal.amount_out(),
+            Some(&(BigUint::from(15u64) * BigUint::from(10u64).pow(17)))
+        );
+    }
+}
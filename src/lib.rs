@@ -14,7 +14,11 @@ pub use tycho_core;
 
 #[cfg(feature = "evm")]
 pub mod evm;
+pub mod graph;
 pub mod models;
+pub mod optimize;
 pub mod protocol;
 pub mod serde_helpers;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 pub mod utils;
@@ -5,17 +5,68 @@
 //!
 //! Tokens provide instructions on how to handle prices and amounts.
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     hash::{Hash, Hasher},
 };
 
 use alloy_primitives::U256;
 use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
 use tycho_core::{dto::ResponseToken, Bytes};
 
-use crate::utils::hexstring_to_vec;
+#[cfg(feature = "evm")]
+use crate::evm::protocol::safe_math::checked_pow;
+use crate::{
+    protocol::{errors::SimulationError, models::TOKEN_TRANSFER_GAS},
+    utils::hexstring_to_vec,
+};
+
+const BPS_DENOMINATOR: u32 = 10_000;
+
+/// Tax charged on transfers of a fee-on-transfer token
+///
+/// Both rates are in basis points of the transferred amount. The `sell_bps` rate applies when the
+/// token is sent into a pool, the `buy_bps` rate when the pool sends it out to the recipient.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TransferTax {
+    pub buy_bps: u32,
+    pub sell_bps: u32,
+}
+
+impl TransferTax {
+    /// Creates a new transfer tax.
+    ///
+    /// ## Panic
+    /// - Panics if any of the rates exceeds 10_000 basis points
+    pub fn new(buy_bps: u32, sell_bps: u32) -> Self {
+        assert!(
+            buy_bps <= BPS_DENOMINATOR && sell_bps <= BPS_DENOMINATOR,
+            "Transfer tax can't exceed 10000 bps"
+        );
+        TransferTax { buy_bps, sell_bps }
+    }
 
-#[derive(Clone, Debug, Eq)]
+    /// Returns the amount that arrives at the pool when `amount` of the token is sold into it.
+    pub fn apply_sell(&self, amount: U256) -> Result<U256, SimulationError> {
+        deduct_bps(amount, self.sell_bps)
+    }
+
+    /// Returns the amount the recipient receives when the pool sends out `amount` of the token.
+    pub fn apply_buy(&self, amount: U256) -> Result<U256, SimulationError> {
+        deduct_bps(amount, self.buy_bps)
+    }
+}
+
+fn deduct_bps(amount: U256, bps: u32) -> Result<U256, SimulationError> {
+    let kept = U256::from(BPS_DENOMINATOR.saturating_sub(bps));
+    amount
+        .checked_mul(kept)
+        .map(|v| v / U256::from(BPS_DENOMINATOR))
+        .ok_or_else(|| SimulationError::FatalError("U256 arithmetic overflow".to_string()))
+}
+
+#[derive(Clone, Debug, Eq, Serialize, Deserialize)]
 pub struct Token {
     /// The address of the token on the blockchain network
     pub address: Bytes,
@@ -25,6 +76,10 @@ pub struct Token {
     pub symbol: String,
     /// The amount of gas it takes to transfer the token
     pub gas: BigUint,
+    /// Tax charged on transfers, `None` for regular tokens
+    pub tax: Option<TransferTax>,
+    /// Quality score assigned by Tycho, from 0 (unusable) to 100 (fully verified)
+    pub quality: u8,
 }
 
 impl Token {
@@ -38,6 +93,8 @@ impl Token {
     /// - `symbol`: token symbol as string
     /// - `gas`: token gas as U256
     ///
+    /// The quality defaults to 100, see `with_quality` to override it.
+    ///
     /// ## Return
     /// Return a new Token struct
     ///
@@ -49,17 +106,59 @@ impl Token {
                 .unwrap_or_else(|_| panic!("Invalid token address: {:?}", address)),
         );
         let sym = symbol.to_string();
-        Token { address: addr, decimals, symbol: sym, gas }
+        Token { address: addr, decimals, symbol: sym, gas, tax: None, quality: 100 }
+    }
+
+    /// Converts a token as returned by Tycho.
+    ///
+    /// The gas is the cheapest of the transfer costs Tycho measured for the token, which are the
+    /// costs of transfers between accounts already holding it, as in swaps. Tokens without
+    /// measurements get `TOKEN_TRANSFER_GAS`. Quality scores are capped at 100. Tycho's tax is
+    /// charged both on buys and sells, see `transfer_tax`.
+    pub fn from_tycho(dto: ResponseToken) -> Self {
+        Token {
+            address: dto.address,
+            decimals: dto.decimals as usize,
+            symbol: dto.symbol,
+            gas: BigUint::from(transfer_gas(&dto.gas)),
+            tax: transfer_tax(dto.tax),
+            quality: token_quality(dto.quality),
+        }
+    }
+
+    /// Sets the quality score of the token
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Marks the token as fee-on-transfer with the given tax
+    pub fn with_transfer_tax(mut self, tax: TransferTax) -> Self {
+        self.tax = Some(tax);
+        self
     }
 
     /// One
     /// Get one token in U256 format
     ///
     /// ## Return
-    /// Return one token as U256
+    /// Return one token as U256, see `checked_one` for tokens with 78 decimals and above, whose
+    /// unit doesn't fit into a U256.
     pub fn one(&self) -> U256 {
         U256::from(10).pow(U256::from(self.decimals))
     }
+
+    /// Get one token in U256 format, checking for overflows
+    ///
+    /// ## Return
+    /// Return one token as U256, or a `SimulationError::FatalError` if `10^decimals` doesn't fit
+    /// into a U256 (78 decimals and above), see `safe_math::checked_pow`.
+    #[cfg(feature = "evm")]
+    pub fn checked_one(&self) -> Result<U256, SimulationError> {
+        // Decimals beyond u32 overflow as well
+        let decimals = u32::try_from(self.decimals).unwrap_or(u32::MAX);
+        checked_pow(U256::from(10), decimals)
+    }
 }
 
 impl PartialOrd for Token {
@@ -80,6 +179,27 @@ impl Hash for Token {
     }
 }
 
+/// Source of native token prices, e.g. to convert gas costs into the output token of a route
+///
+/// Prices are in units of the chain's native token (e.g. ETH) per whole unit of `token`. See
+/// `graph::protograph::PoolNativePrice` for prices read from pools of the graph itself.
+///
+/// Beware of circularity when prices come from the same pools a strategy trades: the price of a
+/// token being arbitraged is the mispriced one, so the gas cost converted with it is off by the
+/// same margin, and a manipulated pool can make an unprofitable route look profitable. Price gas
+/// from deep reference pools, or from an external oracle, rather than from the pools searched.
+pub trait NativePrice {
+    /// Returns the amount of native token one unit of `token` is worth, `None` if unknown.
+    fn native_per_token(&self, token: &Token) -> Option<f64>;
+}
+
+/// Fixed prices, by token address
+impl NativePrice for HashMap<Bytes, f64> {
+    fn native_per_token(&self, token: &Token) -> Option<f64> {
+        self.get(&token.address).copied()
+    }
+}
+
 impl TryFrom<ResponseToken> for Token {
     type Error = std::num::TryFromIntError;
 
@@ -88,21 +208,35 @@ impl TryFrom<ResponseToken> for Token {
             address: value.address,
             decimals: value.decimals.try_into()?,
             symbol: value.symbol,
-            gas: BigUint::from(
-                value
-                    .gas
-                    .into_iter()
-                    .flatten()
-                    .collect::<Vec<u64>>()
-                    .iter()
-                    .min()
-                    .copied()
-                    .expect("Expected a value in gas"),
-            ),
+            gas: BigUint::from(transfer_gas(&value.gas)),
+            tax: transfer_tax(value.tax),
+            quality: token_quality(value.quality),
         })
     }
 }
 
+/// Converts a quality score reported by Tycho, capping it at 100.
+pub(crate) fn token_quality(quality: u32) -> u8 {
+    quality.min(100) as u8
+}
+
+/// Returns the transfer tax of a token charging `tax_bps` basis points on transfers, `None` if it
+/// charges nothing. Rates are capped at 10_000 basis points.
+fn transfer_tax(tax_bps: u64) -> Option<TransferTax> {
+    let bps = tax_bps.min(u64::from(BPS_DENOMINATOR)) as u32;
+    (bps > 0).then(|| TransferTax::new(bps, bps))
+}
+
+/// Returns the lowest measured transfer gas, or `TOKEN_TRANSFER_GAS` if none was measured.
+fn transfer_gas(measured: &[Option<u64>]) -> u64 {
+    measured
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+        .unwrap_or(TOKEN_TRANSFER_GAS)
+}
+
 #[cfg(test)]
 mod tests {
     use num_bigint::ToBigUint;
@@ -159,4 +293,106 @@ mod tests {
 
         assert_eq!(usdc.one(), U256::from(1000000));
     }
+
+    #[cfg(feature = "evm")]
+    #[test]
+    fn test_checked_one() {
+        let token = Token::new(
+            "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            78,
+            "BAD",
+            10000.to_biguint().unwrap(),
+        );
+
+        assert!(token.checked_one().is_err());
+        let token = Token { decimals: 77, ..token };
+        assert_eq!(token.checked_one().unwrap(), token.one());
+    }
+
+    #[test]
+    fn test_transfer_tax() {
+        let tax = TransferTax::new(100, 500);
+
+        assert_eq!(
+            tax.apply_buy(U256::from(10_000))
+                .unwrap(),
+            U256::from(9_900)
+        );
+        assert_eq!(
+            tax.apply_sell(U256::from(10_000))
+                .unwrap(),
+            U256::from(9_500)
+        );
+        assert_eq!(
+            TransferTax::default()
+                .apply_sell(U256::from(7))
+                .unwrap(),
+            U256::from(7)
+        );
+    }
+
+    fn tycho_token(gas: Vec<Option<u64>>, quality: u32) -> ResponseToken {
+        tycho_token_with_tax(gas, quality, 0)
+    }
+
+    fn tycho_token_with_tax(gas: Vec<Option<u64>>, quality: u32, tax: u64) -> ResponseToken {
+        ResponseToken {
+            chain: tycho_core::dto::Chain::Ethereum,
+            address: Bytes::from("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"),
+            symbol: "USDC".to_string(),
+            decimals: 6,
+            tax,
+            gas,
+            quality,
+        }
+    }
+
+    #[rstest::rstest]
+    #[case::cheapest_measurement(vec![Some(45_000), None, Some(29_000)], 29_000)]
+    #[case::no_measurement(vec![None], TOKEN_TRANSFER_GAS)]
+    #[case::empty(vec![], TOKEN_TRANSFER_GAS)]
+    fn test_from_tycho(#[case] gas: Vec<Option<u64>>, #[case] expected_gas: u64) {
+        let token = Token::from_tycho(tycho_token(gas, 100));
+
+        assert_eq!(format!("{:#x}", token.address), "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
+        assert_eq!(token.symbol, "USDC");
+        assert_eq!(token.decimals, 6);
+        assert_eq!(token.quality, 100);
+        assert_eq!(token.tax, None);
+        assert_eq!(token.gas, BigUint::from(expected_gas));
+    }
+
+    #[rstest::rstest]
+    #[case::no_tax(0, None)]
+    #[case::taxed(300, Some(TransferTax::new(300, 300)))]
+    #[case::capped(20_000, Some(TransferTax::new(10_000, 10_000)))]
+    fn test_from_tycho_tax(#[case] tax: u64, #[case] expected: Option<TransferTax>) {
+        let dto = tycho_token_with_tax(vec![], 100, tax);
+
+        assert_eq!(Token::from_tycho(dto.clone()).tax, expected);
+        assert_eq!(Token::try_from(dto).unwrap().tax, expected);
+    }
+
+    #[test]
+    fn test_from_tycho_caps_quality() {
+        assert_eq!(Token::from_tycho(tycho_token(vec![], 300)).quality, 100);
+        assert_eq!(
+            Token::try_from(tycho_token(vec![], 300))
+                .unwrap()
+                .quality,
+            100
+        );
+        assert_eq!(
+            Token::try_from(tycho_token(vec![], 51))
+                .unwrap()
+                .quality,
+            51
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Transfer tax can't exceed 10000 bps")]
+    fn test_transfer_tax_out_of_range() {
+        TransferTax::new(10_001, 0);
+    }
 }
@@ -0,0 +1,5 @@
+//! Order optimization
+//!
+//! `split::optimal_split` divides a large order across several pools of the same token pair, to
+//! reduce the price impact of routing it through a single pool.
+pub mod split;
@@ -0,0 +1,176 @@
+//! Splitting an order across pools
+//!
+//! The output of a pool grows slower with every unit sold into it: its marginal price drops. An
+//! order is best executed by selling into the pools until their marginal prices are equal, like
+//! water filling the deepest basins first. `optimal_split` approximates this by allocating the
+//! order chunk by chunk, each chunk to the pool with the largest output for it.
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+use crate::{models::Token, protocol::state::ProtocolSim};
+
+/// Number of chunks `optimal_split` divides the order into
+const SPLIT_STEPS: u32 = 100;
+
+/// What a pool received of the order so far
+#[derive(Default)]
+struct Allocation {
+    amount_in: BigUint,
+    amount_out: BigUint,
+    /// Amount in after the next chunk and its output, if the pool can take it. Only the pool
+    /// receiving a chunk needs to be simulated again.
+    next: Option<(BigUint, Option<BigUint>)>,
+}
+
+/// Splits `amount_in` of `token_in` across `pools` to maximize the total amount of `token_out`.
+///
+/// The amount is allocated in 100 chunks, each to the pool whose output grows the most with it.
+/// For pools with concave outputs, e.g. constant product or concentrated liquidity pools, this
+/// equalizes the marginal prices of the pools up to the size of a chunk. Pools are only simulated
+/// with `get_amount_out`, and never receive more than their `get_limit`.
+///
+/// Returns the index in `pools` and amount of each pool receiving part of the order. Pools that
+/// fail to simulate are skipped, so the amounts only add up to less than `amount_in` if no pool
+/// could take the rest.
+pub fn optimal_split(
+    pools: &[&dyn ProtocolSim],
+    amount_in: BigUint,
+    token_in: &Token,
+    token_out: &Token,
+) -> Vec<(usize, BigUint)> {
+    if amount_in.is_zero() {
+        return Vec::new();
+    }
+    let chunk = &amount_in / SPLIT_STEPS;
+    let (steps, first_chunk) = if chunk.is_zero() {
+        (1, amount_in.clone())
+    } else {
+        (SPLIT_STEPS, &amount_in - &chunk * (SPLIT_STEPS - 1))
+    };
+    let limits = pools
+        .iter()
+        .map(|pool| {
+            pool.get_limit(token_in, token_out)
+                .unwrap_or_else(|_| Some(BigUint::zero()))
+        })
+        .collect::<Vec<_>>();
+    let mut allocations = pools
+        .iter()
+        .map(|_| Allocation::default())
+        .collect::<Vec<_>>();
+
+    for step in 0..steps {
+        let size = if step == 0 { &first_chunk } else { &chunk };
+        let mut best: Option<(usize, BigUint)> = None;
+        for (index, pool) in pools.iter().enumerate() {
+            let allocation = &mut allocations[index];
+            let next_amount_in = &allocation.amount_in + size;
+            if limits[index]
+                .as_ref()
+                .is_some_and(|limit| &next_amount_in > limit)
+            {
+                continue;
+            }
+            if allocation
+                .next
+                .as_ref()
+                .map(|(amount, _)| amount) !=
+                Some(&next_amount_in)
+            {
+                let amount_out = pool
+                    .get_amount_out(next_amount_in.clone(), token_in, token_out)
+                    .ok()
+                    .map(|result| result.amount);
+                allocation.next = Some((next_amount_in, amount_out));
+            }
+            let Some((_, Some(next_amount_out))) = &allocation.next else {
+                continue;
+            };
+            if next_amount_out <= &allocation.amount_out {
+                continue;
+            }
+            let gain = next_amount_out - &allocation.amount_out;
+            let is_better = match &best {
+                Some((_, best_gain)) => &gain > best_gain,
+                None => true,
+            };
+            if is_better {
+                best = Some((index, gain));
+            }
+        }
+        let Some((index, _)) = best else {
+            break;
+        };
+        let allocation = &mut allocations[index];
+        if let Some((next_amount_in, Some(next_amount_out))) = allocation.next.take() {
+            allocation.amount_in = next_amount_in;
+            allocation.amount_out = next_amount_out;
+        }
+    }
+
+    allocations
+        .into_iter()
+        .enumerate()
+        .filter(|(_, allocation)| !allocation.amount_in.is_zero())
+        .map(|(index, allocation)| (index, allocation.amount_in))
+        .collect()
+}
+
+#[cfg(all(test, feature = "evm"))]
+mod tests {
+    use alloy_primitives::U256;
+
+    use super::*;
+    use crate::{evm::protocol::uniswap_v2::state::UniswapV2State, testing::token};
+
+    fn pool(reserve: u64) -> UniswapV2State {
+        let reserve = U256::from(reserve) * U256::from(10).pow(U256::from(18));
+        UniswapV2State::new(reserve, reserve)
+    }
+
+    fn ether(amount: u64) -> BigUint {
+        BigUint::from(amount) * BigUint::from(10u64).pow(18)
+    }
+
+    #[test]
+    fn test_optimal_split() {
+        let (a, b) = (token("A"), token("B"));
+        let deep = pool(20_000);
+        let shallow = pool(10_000);
+        let pools: [&dyn ProtocolSim; 2] = [&deep, &shallow];
+        let amount_in = ether(3_000);
+
+        let split = optimal_split(&pools, amount_in.clone(), &a, &b);
+
+        // Close to the reserves' 2:1 ratio, which equalizes the pools' marginal prices
+        assert_eq!(split, vec![(0, ether(2_010)), (1, ether(990))]);
+        let split_out: BigUint = split
+            .iter()
+            .map(|(index, amount)| {
+                pools[*index]
+                    .get_amount_out(amount.clone(), &a, &b)
+                    .unwrap()
+                    .amount
+            })
+            .sum();
+        let deep_out = deep
+            .get_amount_out(amount_in, &a, &b)
+            .unwrap()
+            .amount;
+        assert!(split_out > deep_out, "{split_out} <= {deep_out}");
+    }
+
+    #[test]
+    fn test_optimal_split_small_amount() {
+        let (a, b) = (token("A"), token("B"));
+        let deep = pool(20_000);
+        let shallow = pool(10_000);
+
+        let pools: [&dyn ProtocolSim; 2] = [&deep, &shallow];
+
+        let split = optimal_split(&pools, BigUint::from(50u64), &a, &b);
+
+        // Less than a unit per chunk, the whole amount goes to a single pool
+        assert_eq!(split, vec![(0, BigUint::from(50u64))]);
+    }
+}
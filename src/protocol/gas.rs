@@ -0,0 +1,324 @@
+//! Gas models
+//!
+//! `get_amount_out` estimates the gas executing a swap costs. On rollups, each transaction also
+//! pays for posting its calldata to L1, which often costs more than the execution itself. A
+//! `GasModel` computes this cost, converted to L2 gas, and `GasModelState` adds it to the
+//! `GetAmountOutResult`s of a state as a `GasSource::L1Data` item, so `gas` reflects the total
+//! expected cost.
+//!
+//! `ProtocolStreamBuilder::gas_model` wraps all states of a stream. `EthereumGasModel`, the
+//! default, adds nothing.
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+};
+
+use num_bigint::BigUint;
+use num_traits::Zero;
+use tycho_core::{dto::ProtocolStateDelta, Bytes};
+
+use crate::{
+    models::Token,
+    protocol::{
+        errors::{SimulationError, TransitionError},
+        models::{Capability, GasItem, GasSource, GetAmountOutResult},
+        state::{fingerprint, ProtocolSim},
+    },
+};
+
+/// Estimated calldata size of a single swap, in bytes: a function selector and six words (pool,
+/// token in, token out, amount in, minimum amount out and receiver).
+pub const SWAP_CALLDATA_BYTES: usize = 4 + 6 * 32;
+
+/// L1 gas charged per non-zero calldata byte
+const CALLDATA_BYTE_GAS: u64 = 16;
+
+/// Precision of `L1DataGasModel::fee_scalar`
+const FEE_SCALAR_PRECISION: u64 = 1_000_000;
+
+/// Computes the gas a chain charges on top of the execution gas of a swap
+pub trait GasModel: Debug + Send + Sync {
+    /// Returns the extra gas of a swap whose calldata is `calldata_size` bytes long, in units of
+    /// the chain's gas.
+    fn l1_data_gas(&self, calldata_size: usize) -> BigUint;
+
+    /// Adds the extra gas of a swap to `result` as a `GasSource::L1Data` item. Nothing is added
+    /// if it's zero.
+    fn apply(&self, mut result: GetAmountOutResult, calldata_size: usize) -> GetAmountOutResult {
+        let gas = self.l1_data_gas(calldata_size);
+        if !gas.is_zero() {
+            result.add_gas(GasItem::new(GasSource::L1Data, gas));
+        }
+        result
+    }
+}
+
+/// Allows sharing a model between streams.
+impl<T: GasModel + ?Sized> GasModel for Arc<T> {
+    fn l1_data_gas(&self, calldata_size: usize) -> BigUint {
+        (**self).l1_data_gas(calldata_size)
+    }
+}
+
+/// Gas model of Ethereum mainnet: execution gas is the total cost, estimates are left unchanged.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EthereumGasModel;
+
+impl GasModel for EthereumGasModel {
+    fn l1_data_gas(&self, _calldata_size: usize) -> BigUint {
+        BigUint::zero()
+    }
+}
+
+/// Gas model of rollups charging a fee for posting calldata to L1, like OP Stack chains
+/// (Optimism, Base) and Arbitrum
+///
+/// The L1 fee of a swap is converted to L2 gas at the L2 gas price:
+///
+/// ```text
+/// l1_data_gas = (16 * calldata_size + overhead) * l1_base_fee * fee_scalar / 1e6 / l2_gas_price
+/// ```
+///
+/// All calldata bytes are counted as non-zero, so the estimate is an upper bound. Prices change
+/// every block: create a new model when they move significantly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L1DataGasModel {
+    /// Base fee of L1, in wei
+    pub l1_base_fee: u128,
+    /// Gas price of the L2, in wei. If zero, no gas is added.
+    pub l2_gas_price: u128,
+    /// Multiplier applied to the L1 fee, with 6 decimals: 1_000_000 charges the L1 fee as is.
+    pub fee_scalar: u64,
+    /// Fixed L1 gas charged per transaction
+    pub overhead: u64,
+}
+
+impl L1DataGasModel {
+    /// Creates a model charging the L1 fee as is, without overhead.
+    pub fn new(l1_base_fee: u128, l2_gas_price: u128) -> Self {
+        Self { l1_base_fee, l2_gas_price, fee_scalar: FEE_SCALAR_PRECISION, overhead: 0 }
+    }
+}
+
+impl GasModel for L1DataGasModel {
+    fn l1_data_gas(&self, calldata_size: usize) -> BigUint {
+        if self.l2_gas_price == 0 {
+            return BigUint::zero();
+        }
+        let l1_gas = BigUint::from(CALLDATA_BYTE_GAS) * calldata_size + self.overhead;
+        l1_gas * self.l1_base_fee * self.fee_scalar / FEE_SCALAR_PRECISION / self.l2_gas_price
+    }
+}
+
+/// A state whose gas estimates are adjusted by a `GasModel`
+///
+/// Every method delegates to the wrapped state, `get_amount_out` then adds the gas of the model
+/// for a swap of `SWAP_CALLDATA_BYTES`. The returned `new_state` is wrapped again, so chained
+/// swaps keep the model.
+///
+/// `as_any` also delegates, so the state can still be downcast to the wrapped type.
+#[derive(Debug, Clone)]
+pub struct GasModelState {
+    inner: Box<dyn ProtocolSim>,
+    model: Arc<dyn GasModel>,
+}
+
+impl GasModelState {
+    pub fn new(inner: Box<dyn ProtocolSim>, model: Arc<dyn GasModel>) -> Self {
+        Self { inner, model }
+    }
+
+    /// Returns the wrapped state.
+    pub fn inner(&self) -> &dyn ProtocolSim {
+        self.inner.as_ref()
+    }
+
+    fn adjust(&self, mut result: GetAmountOutResult) -> GetAmountOutResult {
+        result.new_state = Box::new(Self::new(result.new_state, self.model.clone()));
+        self.model
+            .apply(result, SWAP_CALLDATA_BYTES)
+    }
+}
+
+impl ProtocolSim for GasModelState {
+    fn fee(&self) -> f64 {
+        self.inner.fee()
+    }
+
+    fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.inner.spot_price(base, quote)
+    }
+
+    fn raw_spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.inner.raw_spot_price(base, quote)
+    }
+
+    fn spot_price_inverse(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.inner
+            .spot_price_inverse(base, quote)
+    }
+
+    fn fee_adjusted_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.inner
+            .fee_adjusted_price(base, quote)
+    }
+
+    fn effective_price(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<f64, SimulationError> {
+        self.inner
+            .effective_price(amount_in, token_in, token_out)
+    }
+
+    fn get_amount_out(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        self.inner
+            .get_amount_out(amount_in, token_in, token_out)
+            .map(|result| self.adjust(result))
+    }
+
+    /// The limit only applies to the execution gas of the wrapped state.
+    fn get_amount_out_with_gas_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        gas_limit: u64,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        self.inner
+            .get_amount_out_with_gas_limit(amount_in, token_in, token_out, gas_limit)
+            .map(|result| self.adjust(result))
+    }
+
+    fn get_limit(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<Option<BigUint>, SimulationError> {
+        self.inner
+            .get_limit(token_in, token_out)
+    }
+
+    fn delta_transition(
+        &mut self,
+        delta: ProtocolStateDelta,
+        tokens: &HashMap<Bytes, Token>,
+    ) -> Result<(), TransitionError<String>> {
+        self.inner
+            .delta_transition(delta, tokens)
+    }
+
+    fn is_active(&self) -> bool {
+        self.inner.is_active()
+    }
+
+    fn is_tradeable(&self) -> bool {
+        self.inner.is_tradeable()
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.inner.tokens()
+    }
+
+    fn capabilities(&self) -> &HashSet<Capability> {
+        self.inner.capabilities()
+    }
+
+    fn state_fingerprint(&self) -> u64 {
+        fingerprint(&(
+            self.inner.state_fingerprint(),
+            self.model
+                .l1_data_gas(SWAP_CALLDATA_BYTES),
+        ))
+    }
+
+    fn clone_box(&self) -> Box<dyn ProtocolSim> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self.inner.as_any()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self.inner.as_any_mut()
+    }
+
+    fn eq(&self, other: &dyn ProtocolSim) -> bool {
+        self.inner.eq(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{token, MockProtocolSim, MOCK_SWAP_GAS};
+
+    #[test]
+    fn test_l1_data_gas() {
+        // 20 gwei on L1, 0.1 gwei on L2
+        let model = L1DataGasModel::new(20_000_000_000, 100_000_000);
+        assert_eq!(model.l1_data_gas(100), BigUint::from(16 * 100 * 200u64));
+
+        let model = L1DataGasModel { fee_scalar: 500_000, overhead: 400, ..model };
+        assert_eq!(model.l1_data_gas(100), BigUint::from((16 * 100 + 400) * 100u64));
+
+        let model = L1DataGasModel { l2_gas_price: 0, ..model };
+        assert!(model.l1_data_gas(100).is_zero());
+    }
+
+    #[test]
+    fn test_ethereum_gas_model_is_pass_through() {
+        let state = MockProtocolSim::new().with_spot_price("A", "B", 2.0);
+        let expected = state
+            .get_amount_out(BigUint::from(1000u64), &token("A"), &token("B"))
+            .unwrap();
+
+        let wrapped = GasModelState::new(Box::new(state), Arc::new(EthereumGasModel));
+        let res = wrapped
+            .get_amount_out(BigUint::from(1000u64), &token("A"), &token("B"))
+            .unwrap();
+
+        assert_eq!(res.amount, expected.amount);
+        assert_eq!(res.gas, expected.gas);
+        assert_eq!(res.gas_breakdown, expected.gas_breakdown);
+    }
+
+    #[test]
+    fn test_gas_model_state_adds_l1_data_gas() {
+        let model = L1DataGasModel::new(10, 1);
+        let l1_gas = model.l1_data_gas(SWAP_CALLDATA_BYTES);
+        let state = MockProtocolSim::new().with_spot_price("A", "B", 2.0);
+        let wrapped = GasModelState::new(Box::new(state.clone()), Arc::new(model));
+
+        let res = wrapped
+            .get_amount_out(BigUint::from(1000u64), &token("A"), &token("B"))
+            .unwrap();
+
+        assert_eq!(res.gas, BigUint::from(MOCK_SWAP_GAS) + &l1_gas);
+        assert_eq!(res.gas_breakdown[1], GasItem::new(GasSource::L1Data, l1_gas.clone()));
+        // The new state keeps the model
+        let next = res
+            .new_state
+            .get_amount_out(BigUint::from(1000u64), &token("B"), &token("A"))
+            .unwrap();
+        assert_eq!(next.gas, BigUint::from(MOCK_SWAP_GAS) + &l1_gas);
+        // The wrapped state can still be downcast
+        assert_eq!(
+            wrapped
+                .as_any()
+                .downcast_ref::<MockProtocolSim>(),
+            Some(&state)
+        );
+        assert_ne!(wrapped.state_fingerprint(), state.state_fingerprint());
+    }
+}
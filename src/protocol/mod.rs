@@ -1,3 +1,8 @@
 pub mod errors;
+pub mod gas;
 pub mod models;
+pub mod price_index;
+#[cfg(feature = "evm")]
+pub mod registry;
 pub mod state;
+pub mod state_store;
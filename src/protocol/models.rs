@@ -24,13 +24,30 @@
 //! It's worth emphasizing that although the term "pair" used in this
 //! module refers to a trading pair, it does not necessarily imply two
 //! tokens only. Some pairs might have more than two tokens.
+//!
+//! Finally, `ProtocolSimSnapshot` is a serializable form of the known `ProtocolSim`
+//! implementations, used to persist states across process restarts.
 use std::{collections::HashMap, future::Future};
 
+use alloy_primitives::U256;
 use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
 use tycho_client::feed::Header;
 use tycho_core::Bytes;
 
-use super::state::ProtocolSim;
+#[cfg(feature = "evm")]
+use super::errors::InvalidSnapshotError;
+use super::{errors::SimulationError, state::ProtocolSim};
+#[cfg(feature = "evm")]
+use crate::evm::{
+    engine_db::tycho_db::PreCachedDB,
+    protocol::{
+        maverick_v2::state::MaverickV2State, uniswap_v2::state::UniswapV2State,
+        uniswap_v3::state::UniswapV3State, uniswap_v4::state::UniswapV4State,
+        vm::state::EVMPoolState,
+    },
+};
 use crate::models::Token;
 
 /// ProtocolComponent struct represents the properties of a trading pair
@@ -39,7 +56,7 @@ use crate::models::Token;
 ///
 /// * `address`: String, the address of the trading pair
 /// * `tokens`: `Vec<ERC20Token>`, the tokens of the trading pair
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProtocolComponent {
     pub address: Bytes,
     pub tokens: Vec<Token>,
@@ -64,31 +81,300 @@ pub trait TryFromWithBlock<T> {
         Self: Sized;
 }
 
+/// Represents a distinct functionality or feature that a pool can support.
+///
+/// Each `Capability` variant corresponds to a specific functionality that influences how the
+/// simulation interacts with the pool.
+///
+/// # Variants
+///
+/// - `SellSide`: Supports swapping with a fixed sell amount.
+/// - `BuySide`: Supports swapping with a fixed buy amount.
+/// - `PriceFunction`: Supports evaluating dynamic pricing based on a function.
+/// - `FeeOnTransfer`: Support tokens that charge a fee on transfer.
+/// - `ConstantPrice`: The pool does not suffer from price impact and maintains a constant price for
+///   increasingly larger specified amounts.
+/// - `TokenBalanceIndependent`:Indicates that the pool does not read its own token balances from
+///   token contracts while swapping.
+/// - `ScaledPrice`: Indicates that prices are returned scaled, else it is assumed prices still
+///   require scaling by token decimals.
+/// - `HardLimits`: Indicates that if we try to go over the sell limits, the pool will revert.
+/// - `MarginalPrice`: Indicates whether the pool's price function can be called with amountIn=0 to
+///   return the current price
+#[derive(Eq, PartialEq, Hash, Debug, Display, Clone, Serialize, Deserialize)]
+pub enum Capability {
+    SellSide = 1,
+    BuySide = 2,
+    PriceFunction = 3,
+    FeeOnTransfer = 4,
+    ConstantPrice = 5,
+    TokenBalanceIndependent = 6,
+    ScaledPrice = 7,
+    HardLimits = 8,
+    MarginalPrice = 9,
+}
+
+impl Capability {
+    pub fn from_u256(value: U256) -> Result<Self, SimulationError> {
+        let value_as_u8 = value.to_le_bytes::<32>()[0];
+        match value_as_u8 {
+            1 => Ok(Capability::SellSide),
+            2 => Ok(Capability::BuySide),
+            3 => Ok(Capability::PriceFunction),
+            4 => Ok(Capability::FeeOnTransfer),
+            5 => Ok(Capability::ConstantPrice),
+            6 => Ok(Capability::TokenBalanceIndependent),
+            7 => Ok(Capability::ScaledPrice),
+            8 => Ok(Capability::HardLimits),
+            9 => Ok(Capability::MarginalPrice),
+            _ => {
+                Err(SimulationError::FatalError(format!("Unexpected Capability value: {}", value)))
+            }
+        }
+    }
+}
+
+/// Intrinsic gas cost of an Ethereum transaction, paid once no matter how many swaps it contains
+pub const BASE_TX_GAS: u64 = 21_000;
+/// Estimated gas cost of a single ERC20 transfer
+pub const TOKEN_TRANSFER_GAS: u64 = 30_000;
+
+/// What a part of a gas estimate pays for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GasSource {
+    /// The intrinsic transaction cost, only paid once per transaction
+    BaseTx,
+    /// The pool's own swap logic
+    PoolComputation,
+    /// Moving the tokens in and out of the pool
+    TokenTransfer,
+    /// Posting the transaction's calldata to L1 on rollups, converted to L2 gas. See
+    /// `GasModel`.
+    L1Data,
+}
+
+/// A labelled part of a gas estimate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasItem {
+    pub source: GasSource,
+    pub gas: BigUint,
+}
+
+impl GasItem {
+    pub fn new(source: GasSource, gas: impl Into<BigUint>) -> Self {
+        GasItem { source, gas: gas.into() }
+    }
+}
+
+/// Gas breakdown of a single swap sent as its own transaction: the base transaction cost, a
+/// transfer in and out of the pool and the given pool computation cost.
+pub fn swap_gas_breakdown(pool_gas: impl Into<BigUint>) -> Vec<GasItem> {
+    vec![
+        GasItem::new(GasSource::BaseTx, BASE_TX_GAS),
+        GasItem::new(GasSource::TokenTransfer, 2 * TOKEN_TRANSFER_GAS),
+        GasItem::new(GasSource::PoolComputation, pool_gas),
+    ]
+}
+
 /// GetAmountOutResult struct represents the result of getting the amount out of a trading pair
 ///
 /// # Fields
 ///
 /// * `amount`: BigUint, the amount of the trading pair
-/// * `gas`: BigUint, the gas of the trading pair
+/// * `gas`: BigUint, the gas of the trading pair, always the sum of `gas_breakdown`
+/// * `gas_breakdown`: `Vec<GasItem>`, the parts the gas estimate is made of
+/// * `crossed_ticks`: `Option<u32>`, the number of initialized ticks crossed, for concentrated
+///   liquidity pools
 #[derive(Debug)]
 pub struct GetAmountOutResult {
     pub amount: BigUint,
     pub gas: BigUint,
+    pub gas_breakdown: Vec<GasItem>,
     pub new_state: Box<dyn ProtocolSim>,
+    pub crossed_ticks: Option<u32>,
 }
 
 impl GetAmountOutResult {
     /// Constructs a new GetAmountOutResult struct with the given amount and gas
+    ///
+    /// The gas is reported as a single `GasSource::PoolComputation` item, use
+    /// `with_gas_breakdown` if its parts are known.
     pub fn new(amount: BigUint, gas: BigUint, new_state: Box<dyn ProtocolSim>) -> Self {
-        GetAmountOutResult { amount, gas, new_state }
+        let gas_breakdown = vec![GasItem::new(GasSource::PoolComputation, gas.clone())];
+        GetAmountOutResult { amount, gas, gas_breakdown, new_state, crossed_ticks: None }
+    }
+
+    /// Constructs a new GetAmountOutResult struct whose gas is the sum of the given breakdown
+    pub fn with_gas_breakdown(
+        amount: BigUint,
+        gas_breakdown: Vec<GasItem>,
+        new_state: Box<dyn ProtocolSim>,
+    ) -> Self {
+        let gas = gas_breakdown
+            .iter()
+            .map(|item| &item.gas)
+            .sum();
+        GetAmountOutResult { amount, gas, gas_breakdown, new_state, crossed_ticks: None }
+    }
+
+    /// Sets the number of initialized ticks the swap crossed.
+    pub fn with_crossed_ticks(mut self, crossed_ticks: u32) -> Self {
+        self.crossed_ticks = Some(crossed_ticks);
+        self
+    }
+
+    /// Returns the result unchanged if its gas fits within `gas_limit`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError::InvalidInput` carrying the result if its gas exceeds the
+    /// limit.
+    pub fn ensure_gas_limit(self, gas_limit: u64) -> Result<Self, SimulationError> {
+        if self.gas > BigUint::from(gas_limit) {
+            return Err(SimulationError::InvalidInput(
+                format!("Swap needs {} gas, exceeding the limit of {}", self.gas, gas_limit),
+                Some(self),
+            ));
+        }
+        Ok(self)
     }
 
     /// Aggregates the given GetAmountOutResult struct to the current one.
-    /// It updates the amount with the other's amount and adds the other's gas to the current one's
-    /// gas.
+    /// It updates the amount with the other's amount and merges the other's gas breakdown into
+    /// the current one: gas of the same source is added up, except for `GasSource::BaseTx`
+    /// which is only paid once per transaction. Crossed ticks are added up.
     pub fn aggregate(&mut self, other: &Self) {
         self.amount = other.amount.clone();
-        self.gas += &other.gas;
+        self.crossed_ticks = match (self.crossed_ticks, other.crossed_ticks) {
+            (Some(own), Some(other)) => Some(own + other),
+            (own, other) => own.or(other),
+        };
+        for item in &other.gas_breakdown {
+            self.add_gas(item.clone());
+        }
+    }
+
+    /// Adds a gas item to the breakdown and the total, merging it like `aggregate` does.
+    pub fn add_gas(&mut self, item: GasItem) {
+        match self
+            .gas_breakdown
+            .iter_mut()
+            .find(|existing| existing.source == item.source)
+        {
+            Some(existing) if item.source == GasSource::BaseTx => {
+                existing.gas = existing.gas.clone().max(item.gas);
+            }
+            Some(existing) => existing.gas += item.gas,
+            None => self.gas_breakdown.push(item),
+        }
+        self.gas = self
+            .gas_breakdown
+            .iter()
+            .map(|item| &item.gas)
+            .sum();
+    }
+}
+
+/// A point of a pool's price curve, see `ProtocolSim::quote_curve`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurvePoint {
+    pub amount_in: BigUint,
+    pub amount_out: BigUint,
+    /// Gas of a swap of `amount_in`, like `GetAmountOutResult::gas`
+    pub gas: BigUint,
+    /// Whether `amount_out` is at least the previous point's, always `true` for the first point.
+    /// A curve with a non-monotonic point hints at a rounding issue or a broken simulation.
+    pub monotonic: bool,
+}
+
+impl CurvePoint {
+    /// Builds the points of a curve from `(amount_in, amount_out, gas)` quotes in grid order,
+    /// flagging points whose amount out is below the previous point's.
+    pub fn curve(quotes: impl IntoIterator<Item = (BigUint, BigUint, BigUint)>) -> Vec<Self> {
+        let mut previous: Option<BigUint> = None;
+        quotes
+            .into_iter()
+            .map(|(amount_in, amount_out, gas)| {
+                let monotonic = previous
+                    .as_ref()
+                    .map_or(true, |previous| &amount_out >= previous);
+                previous = Some(amount_out.clone());
+                CurvePoint { amount_in, amount_out, gas, monotonic }
+            })
+            .collect()
+    }
+
+    /// Checks that the amounts of a curve's grid are in non-decreasing order.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::InvalidInput` if an amount is below its predecessor.
+    pub fn check_grid(amounts: &[BigUint]) -> Result<(), SimulationError> {
+        match amounts
+            .windows(2)
+            .find(|pair| pair[1] < pair[0])
+        {
+            Some(pair) => Err(SimulationError::InvalidInput(
+                format!("Curve amounts must be sorted, {} follows {}", pair[1], pair[0]),
+                None,
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Why a component was reported in `BlockUpdate::removed_pairs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RemovalReason {
+    /// The component's TVL dropped out of the tracked range
+    BelowTvlThreshold,
+    /// The component was deleted by the protocol
+    Deleted,
+    /// The component's state could no longer be decoded
+    DecodeFailure,
+    /// One of the component's tokens dropped below the minimum token quality
+    LowTokenQuality,
+}
+
+/// A component that was left out of a `BlockUpdate` because it couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodeError {
+    pub component_id: String,
+    /// The protocol system of the component, e.g. `uniswap_v2`
+    pub exchange: String,
+    pub reason: DecodeErrorReason,
+}
+
+/// Why a component couldn't be decoded, see `DecodeErrorReason::is_recoverable`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecodeErrorReason {
+    /// One of the component's tokens is unknown, e.g. because its metadata failed to load
+    MissingToken(Bytes),
+    /// No decoder is registered for the component's exchange
+    MissingDecoder,
+    /// The component's state failed to set up, e.g. because its contracts failed to load
+    StateSetup(String),
+    /// The component's state is invalid or of a pool type the decoder doesn't support
+    Unsupported(String),
+}
+
+impl DecodeErrorReason {
+    /// Whether the component may decode later, e.g. once its tokens are known or a decoder is
+    /// registered for its exchange. Unsupported components will keep failing to decode.
+    pub fn is_recoverable(&self) -> bool {
+        !matches!(self, DecodeErrorReason::Unsupported(_))
+    }
+}
+
+#[cfg(feature = "evm")]
+impl From<&InvalidSnapshotError> for DecodeErrorReason {
+    fn from(error: &InvalidSnapshotError) -> Self {
+        match error {
+            InvalidSnapshotError::VMError(_) => DecodeErrorReason::StateSetup(error.to_string()),
+            InvalidSnapshotError::MissingAttribute(_) | InvalidSnapshotError::ValueError(_) => {
+                DecodeErrorReason::Unsupported(error.to_string())
+            }
+        }
     }
 }
 
@@ -99,8 +385,19 @@ pub struct BlockUpdate {
     pub states: HashMap<String, Box<dyn ProtocolSim>>,
     /// The new pairs that were added in this block
     pub new_pairs: HashMap<String, ProtocolComponent>,
-    /// The pairs that were removed in this block
+    /// The pairs that stopped being tracked in this block, e.g. because their TVL dropped below
+    /// the filter threshold or they were deleted. Their states won't be updated anymore, so any
+    /// local copies should be dropped.
     pub removed_pairs: HashMap<String, ProtocolComponent>,
+    /// Why each of the `removed_pairs` was removed
+    pub removal_reasons: HashMap<String, RemovalReason>,
+    /// The components of this block that couldn't be decoded and were left out. Components with
+    /// unknown tokens are always left out, other failures only with
+    /// `ProtocolStreamBuilder::skip_state_decode_failures` set and fail the block otherwise.
+    pub decode_errors: Vec<DecodeError>,
+    /// Set on the first update after the stream reconnected. It holds a full snapshot of all
+    /// tracked pools: local state maps should be replaced with it, not merged.
+    pub is_resync: bool,
 }
 
 impl BlockUpdate {
@@ -109,11 +406,182 @@ impl BlockUpdate {
         states: HashMap<String, Box<dyn ProtocolSim>>,
         new_pairs: HashMap<String, ProtocolComponent>,
     ) -> Self {
-        BlockUpdate { block_number, states, new_pairs, removed_pairs: HashMap::new() }
+        BlockUpdate {
+            block_number,
+            states,
+            new_pairs,
+            removed_pairs: HashMap::new(),
+            removal_reasons: HashMap::new(),
+            decode_errors: Vec::new(),
+            is_resync: false,
+        }
     }
 
     pub fn set_removed_pairs(mut self, pairs: HashMap<String, ProtocolComponent>) -> Self {
         self.removed_pairs = pairs;
         self
     }
+
+    pub fn set_removal_reasons(mut self, reasons: HashMap<String, RemovalReason>) -> Self {
+        self.removal_reasons = reasons;
+        self
+    }
+
+    pub fn set_decode_errors(mut self, errors: Vec<DecodeError>) -> Self {
+        self.decode_errors = errors;
+        self
+    }
+
+    pub fn set_is_resync(mut self, is_resync: bool) -> Self {
+        self.is_resync = is_resync;
+        self
+    }
+
+    /// Returns the updated states sorted by component id.
+    ///
+    /// `states` iterates in an arbitrary order that changes between runs, use this to process or
+    /// log the states in a reproducible order.
+    pub fn states_sorted(&self) -> Vec<(&String, &dyn ProtocolSim)> {
+        let mut states = self
+            .states
+            .iter()
+            .map(|(id, state)| (id, state.as_ref()))
+            .collect::<Vec<_>>();
+        states.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        states
+    }
+}
+
+/// Serialized form of a `BlockUpdate`, holding the states as `ProtocolSimSnapshot`s
+#[cfg(feature = "evm")]
+#[derive(Serialize, Deserialize)]
+struct BlockUpdateRecord {
+    block_number: u64,
+    states: HashMap<String, ProtocolSimSnapshot>,
+    new_pairs: HashMap<String, ProtocolComponent>,
+    removed_pairs: HashMap<String, ProtocolComponent>,
+    #[serde(default)]
+    removal_reasons: HashMap<String, RemovalReason>,
+    #[serde(default)]
+    decode_errors: Vec<DecodeError>,
+    #[serde(default)]
+    is_resync: bool,
+}
+
+/// Serializes the update with its states as `ProtocolSimSnapshot`s.
+///
+/// Fails if a state is not supported by `to_snapshot`.
+#[cfg(feature = "evm")]
+impl Serialize for BlockUpdate {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let states = self
+            .states
+            .iter()
+            .map(|(id, state)| {
+                to_snapshot(state.as_ref())
+                    .map(|snapshot| (id.clone(), snapshot))
+                    .ok_or_else(|| {
+                        serde::ser::Error::custom(format!("State of {id} is not serializable"))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        BlockUpdateRecord {
+            block_number: self.block_number,
+            states,
+            new_pairs: self.new_pairs.clone(),
+            removed_pairs: self.removed_pairs.clone(),
+            removal_reasons: self.removal_reasons.clone(),
+            decode_errors: self.decode_errors.clone(),
+            is_resync: self.is_resync,
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializes an update written by its `Serialize` implementation.
+///
+/// Fails on snapshots of VM-backed pools, see `from_snapshot`.
+#[cfg(feature = "evm")]
+impl<'de> Deserialize<'de> for BlockUpdate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = BlockUpdateRecord::deserialize(deserializer)?;
+        let states = record
+            .states
+            .into_iter()
+            .map(|(id, snapshot)| {
+                Ok((id, from_snapshot(snapshot).map_err(serde::de::Error::custom)?))
+            })
+            .collect::<Result<_, D::Error>>()?;
+        Ok(BlockUpdate::new(record.block_number, states, record.new_pairs)
+            .set_removed_pairs(record.removed_pairs)
+            .set_removal_reasons(record.removal_reasons)
+            .set_decode_errors(record.decode_errors)
+            .set_is_resync(record.is_resync))
+    }
+}
+
+/// Serializable snapshot of a protocol state, see `to_snapshot` and `from_snapshot`.
+///
+/// Serialized with a `protocol` field telling the state type apart, e.g.
+/// `{"protocol": "uniswap_v2", "reserve0": ..., "reserve1": ..., "fee_bps": 30}`.
+#[cfg(feature = "evm")]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "protocol", rename_all = "snake_case")]
+pub enum ProtocolSimSnapshot {
+    UniswapV2(UniswapV2State),
+    UniswapV3(UniswapV3State),
+    UniswapV4(UniswapV4State),
+    MaverickV2(MaverickV2State),
+    /// A VM-backed pool. The contracts' storage is not part of the snapshot, so the state can't
+    /// be restored from it: the component needs to be decoded from Tycho again.
+    Vm {
+        id: String,
+        block_number: u64,
+        tokens: Vec<Bytes>,
+        /// Token balances, big-endian encoded
+        balances: HashMap<Bytes, Bytes>,
+        /// The pool attributes, encoded as in Tycho's protocol state
+        attributes: HashMap<String, Bytes>,
+    },
+}
+
+/// Creates a serializable snapshot of `state`.
+///
+/// Returns `None` if `state` is not one of the types listed in `ProtocolSimSnapshot`.
+#[cfg(feature = "evm")]
+pub fn to_snapshot(state: &dyn ProtocolSim) -> Option<ProtocolSimSnapshot> {
+    let state = state.as_any();
+    if let Some(state) = state.downcast_ref::<UniswapV2State>() {
+        Some(ProtocolSimSnapshot::UniswapV2(state.clone()))
+    } else if let Some(state) = state.downcast_ref::<UniswapV3State>() {
+        Some(ProtocolSimSnapshot::UniswapV3(state.clone()))
+    } else if let Some(state) = state.downcast_ref::<UniswapV4State>() {
+        Some(ProtocolSimSnapshot::UniswapV4(state.clone()))
+    } else if let Some(state) = state.downcast_ref::<MaverickV2State>() {
+        Some(ProtocolSimSnapshot::MaverickV2(state.clone()))
+    } else {
+        state
+            .downcast_ref::<EVMPoolState<PreCachedDB>>()
+            .map(EVMPoolState::to_snapshot)
+    }
+}
+
+/// Restores a protocol state from its snapshot.
+///
+/// # Errors
+/// Returns `InvalidSnapshotError::ValueError` for snapshots of VM-backed pools, which can't be
+/// restored without their contracts' storage.
+#[cfg(feature = "evm")]
+pub fn from_snapshot(
+    snapshot: ProtocolSimSnapshot,
+) -> Result<Box<dyn ProtocolSim>, InvalidSnapshotError> {
+    match snapshot {
+        ProtocolSimSnapshot::UniswapV2(state) => Ok(Box::new(state)),
+        ProtocolSimSnapshot::UniswapV3(state) => Ok(Box::new(state)),
+        ProtocolSimSnapshot::UniswapV4(state) => Ok(Box::new(state)),
+        ProtocolSimSnapshot::MaverickV2(state) => Ok(Box::new(state)),
+        ProtocolSimSnapshot::Vm { id, .. } => Err(InvalidSnapshotError::ValueError(format!(
+            "VM pool {id} can't be restored from a snapshot, decode it from Tycho instead"
+        ))),
+    }
 }
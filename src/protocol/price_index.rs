@@ -0,0 +1,412 @@
+//! Token prices derived from the pool graph
+//!
+//! A `PriceIndex` tracks the pools of a protocol stream and prices every token reachable from a
+//! numéraire token (e.g. WETH or USDC), by walking the pool graph from the numéraire.
+//!
+//! Each token is priced along the path that converts a probe amount of the numéraire into the
+//! most of it, i.e. the highest-liquidity path. The price itself is the product of the spot prices
+//! along that path, so it isn't affected by the probe's price impact.
+use std::collections::{HashMap, HashSet};
+
+use num_bigint::BigUint;
+use num_traits::FromPrimitive;
+use tycho_core::Bytes;
+
+use super::{
+    models::{BlockUpdate, ProtocolComponent},
+    state::ProtocolSim,
+};
+use crate::models::Token;
+
+/// Value of the probe trade used to rank paths, in whole units of the numéraire
+pub const DEFAULT_PROBE_VALUE: f64 = 1.0;
+
+/// Maximum number of pools on a pricing path by default
+pub const DEFAULT_MAX_HOPS: usize = 3;
+
+/// Best known way to reach a token from the numéraire
+#[derive(Debug, Clone)]
+struct PathEnd {
+    /// Amount of the token received for the probe, in its smallest unit
+    received: BigUint,
+    /// Price in numéraire per whole unit of the token
+    price: f64,
+    /// Tokens on the path, starting with the numéraire
+    path: Vec<Bytes>,
+    /// Ids of the pools along the path
+    pools: Vec<String>,
+}
+
+/// Prices of all tokens reachable from a numéraire token through the tracked pools
+///
+/// Feed every `BlockUpdate` of a stream to `apply_update`: the pool graph is updated with the new,
+/// changed and removed pools, and the prices of the tokens the changes can affect are recomputed.
+#[derive(Debug)]
+pub struct PriceIndex {
+    numeraire: Token,
+    probe_value: f64,
+    max_hops: usize,
+    tokens: HashMap<Bytes, Token>,
+    components: HashMap<String, ProtocolComponent>,
+    states: HashMap<String, Box<dyn ProtocolSim>>,
+    /// Ids of the tracked pools containing each token
+    pools_by_token: HashMap<Bytes, HashSet<String>>,
+    /// Best known path to each priced token
+    paths: HashMap<Bytes, PathEnd>,
+}
+
+impl PriceIndex {
+    /// Creates an empty index pricing tokens in `numeraire`.
+    pub fn new(numeraire: Token) -> Self {
+        let tokens = HashMap::from([(numeraire.address.clone(), numeraire.clone())]);
+        let mut index = Self {
+            numeraire,
+            probe_value: DEFAULT_PROBE_VALUE,
+            max_hops: DEFAULT_MAX_HOPS,
+            tokens,
+            components: HashMap::new(),
+            states: HashMap::new(),
+            pools_by_token: HashMap::new(),
+            paths: HashMap::new(),
+        };
+        index.paths = index.root_paths();
+        index
+    }
+
+    /// Sets the value of the probe trade used to find the highest-liquidity path, in whole units
+    /// of the numéraire. Should be about the size of a typical trade.
+    pub fn with_probe_value(mut self, value: f64) -> Self {
+        self.probe_value = value;
+        self.paths = self.root_paths();
+        self
+    }
+
+    /// Sets the maximum number of pools on a pricing path. Tokens further away are unpriced.
+    pub fn with_max_hops(mut self, hops: usize) -> Self {
+        self.max_hops = hops;
+        self
+    }
+
+    /// Applies the pools of a `BlockUpdate` and recomputes the prices the changed pools affect.
+    ///
+    /// States of pools whose component is unknown are kept, they are used once the component is
+    /// added. A resync update replaces all tracked pools.
+    pub fn apply_update(&mut self, update: &BlockUpdate) {
+        if update.is_resync {
+            self.components.clear();
+            self.states.clear();
+            self.pools_by_token.clear();
+            self.paths = self.root_paths();
+        }
+        let mut changed = HashSet::new();
+        for id in update.removed_pairs.keys() {
+            self.remove_pool(id);
+            changed.insert(id.clone());
+        }
+        for (id, component) in &update.new_pairs {
+            self.add_component(id, component);
+            changed.insert(id.clone());
+        }
+        for (id, state) in &update.states {
+            if !update.removed_pairs.contains_key(id) {
+                self.states
+                    .insert(id.clone(), state.clone_box());
+                changed.insert(id.clone());
+            }
+        }
+        self.reprice(&changed);
+    }
+
+    /// Returns the price of `token` in whole units of the numéraire per whole unit of `token`.
+    ///
+    /// Returns `None` if the token can't be reached from the numéraire through the tracked pools.
+    pub fn price_of(&self, token: &Token) -> Option<f64> {
+        self.paths
+            .get(&token.address)
+            .map(|end| end.price)
+    }
+
+    fn add_component(&mut self, id: &str, component: &ProtocolComponent) {
+        for token in &component.tokens {
+            self.tokens
+                .entry(token.address.clone())
+                .or_insert_with(|| token.clone());
+            self.pools_by_token
+                .entry(token.address.clone())
+                .or_default()
+                .insert(id.to_string());
+        }
+        self.components
+            .insert(id.to_string(), component.clone());
+    }
+
+    fn remove_pool(&mut self, id: &str) {
+        self.states.remove(id);
+        if let Some(component) = self.components.remove(id) {
+            for token in &component.tokens {
+                if let Some(pools) = self
+                    .pools_by_token
+                    .get_mut(&token.address)
+                {
+                    pools.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Returns the paths before any pool is known: the numéraire reaching itself.
+    fn root_paths(&self) -> HashMap<Bytes, PathEnd> {
+        let numeraire = self.numeraire.address.clone();
+        BigUint::from_f64(self.probe_value * 10f64.powi(self.numeraire.decimals as i32))
+            .map(|probe| {
+                let root = PathEnd {
+                    received: probe,
+                    price: 1.0,
+                    path: vec![numeraire.clone()],
+                    pools: vec![],
+                };
+                HashMap::from([(numeraire, root)])
+            })
+            .unwrap_or_default()
+    }
+
+    /// Updates the paths after the `changed` pools were added, updated or removed.
+    ///
+    /// Paths through a changed pool are dropped. The paths are then relaxed hop by hop from the
+    /// tokens that can reach a dropped or changed token in one hop, keeping for each token the
+    /// path receiving the most of it, until no path improves. Tokens outside of the changed pools'
+    /// reach keep their paths. Paths never visit a token twice.
+    fn reprice(&mut self, changed: &HashSet<String>) {
+        let dropped: Vec<Bytes> = self
+            .paths
+            .iter()
+            .filter(|(_, end)| {
+                end.pools
+                    .iter()
+                    .any(|id| changed.contains(id))
+            })
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in &dropped {
+            self.paths.remove(address);
+        }
+
+        let neighbour_pools = dropped.iter().flat_map(|address| {
+            self.pools_by_token
+                .get(address)
+                .into_iter()
+                .flatten()
+        });
+        let mut frontier: HashSet<Bytes> = changed
+            .iter()
+            .chain(neighbour_pools)
+            .filter_map(|id| self.components.get(id))
+            .flat_map(|component| {
+                component
+                    .tokens
+                    .iter()
+                    .map(|token| token.address.clone())
+            })
+            .filter(|address| self.paths.contains_key(address))
+            .collect();
+
+        while !frontier.is_empty() {
+            let mut updated = HashSet::new();
+            for address in &frontier {
+                let from = self.paths[address].clone();
+                if from.pools.len() >= self.max_hops {
+                    continue;
+                }
+                for (token_out, end) in self.relax(address, &from) {
+                    let improves = self
+                        .paths
+                        .get(&token_out)
+                        .map_or(true, |current| end.received > current.received);
+                    if improves {
+                        self.paths
+                            .insert(token_out.clone(), end);
+                        updated.insert(token_out);
+                    }
+                }
+            }
+            frontier = updated;
+        }
+    }
+
+    /// Extends the path ending at `address` by each pool containing the token.
+    fn relax(&self, address: &Bytes, from: &PathEnd) -> Vec<(Bytes, PathEnd)> {
+        let Some(token_in) = self.tokens.get(address) else {
+            return Vec::new();
+        };
+        let mut ends = Vec::new();
+        for id in self
+            .pools_by_token
+            .get(address)
+            .into_iter()
+            .flatten()
+        {
+            let (Some(component), Some(state)) = (self.components.get(id), self.states.get(id))
+            else {
+                continue;
+            };
+            if !state.is_active() {
+                continue;
+            }
+            for token_out in &component.tokens {
+                if from.path.contains(&token_out.address) {
+                    continue;
+                }
+                let Ok(res) = state.get_amount_out(from.received.clone(), token_in, token_out)
+                else {
+                    continue;
+                };
+                let Ok(spot_price) = state.spot_price(token_out, token_in) else {
+                    continue;
+                };
+                let price = spot_price * from.price;
+                if !price.is_finite() || price <= 0.0 {
+                    continue;
+                }
+                let mut path = from.path.clone();
+                path.push(token_out.address.clone());
+                let mut pools = from.pools.clone();
+                pools.push(id.clone());
+                ends.push((
+                    token_out.address.clone(),
+                    PathEnd { received: res.amount, price, path, pools },
+                ));
+            }
+        }
+        ends
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+    use crate::{
+        protocol::models::RemovalReason,
+        testing::{token, token_with_decimals, BlockUpdateBuilder, MockProtocolSim},
+    };
+
+    fn usdc() -> Token {
+        token_with_decimals("USDC", 6)
+    }
+
+    /// WETH at 2000 USDC, WBTC at 20 WETH through a deep pool and at 50000 USDC through a thin
+    /// one, DAI at 1 USDC, and an isolated FOO/BAR pool.
+    fn snapshot() -> BlockUpdate {
+        BlockUpdateBuilder::new(1)
+            .token(usdc())
+            .pool(
+                "weth_usdc",
+                "WETH/USDC",
+                MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0),
+            )
+            .pool(
+                "wbtc_weth",
+                "WBTC/WETH",
+                MockProtocolSim::new().with_spot_price("WBTC", "WETH", 20.0),
+            )
+            .pool(
+                "wbtc_usdc",
+                "WBTC/USDC",
+                MockProtocolSim::new()
+                    .with_spot_price("WBTC", "USDC", 50000.0)
+                    .with_amount_out("USDC", "WBTC", 1_000_000u64, 1_000_000_000u64),
+            )
+            .pool(
+                "dai_usdc",
+                "DAI/USDC",
+                MockProtocolSim::new().with_spot_price("DAI", "USDC", 1.0),
+            )
+            .pool(
+                "dai_weth",
+                "DAI/WETH",
+                MockProtocolSim::new().with_spot_price("DAI", "WETH", 0.0005),
+            )
+            .pool("foo_bar", "FOO/BAR", MockProtocolSim::new().with_spot_price("FOO", "BAR", 3.0))
+            .build()
+    }
+
+    #[test]
+    fn test_prices_follow_highest_liquidity_paths() {
+        let mut index = PriceIndex::new(usdc());
+        index.apply_update(&snapshot());
+
+        assert_eq!(index.price_of(&usdc()), Some(1.0));
+        assert_relative_eq!(index.price_of(&token("WETH")).unwrap(), 2000.0);
+        assert_relative_eq!(index.price_of(&token("DAI")).unwrap(), 1.0);
+        // The direct pool only returns 1e-9 WBTC for the probe, the path through WETH wins
+        assert_relative_eq!(index.price_of(&token("WBTC")).unwrap(), 40000.0);
+        assert_eq!(index.price_of(&token("FOO")), None);
+        assert_eq!(index.price_of(&token("BAR")), None);
+    }
+
+    #[test]
+    fn test_apply_update_incrementally() {
+        let mut index = PriceIndex::new(usdc());
+        index.apply_update(&snapshot());
+
+        let update = BlockUpdateBuilder::new(2)
+            .token(usdc())
+            .state("weth_usdc", MockProtocolSim::new().with_spot_price("WETH", "USDC", 2500.0))
+            .removed_pair("wbtc_weth", "WBTC/WETH", RemovalReason::BelowTvlThreshold)
+            .pool(
+                "bar_usdc",
+                "BAR/USDC",
+                MockProtocolSim::new().with_spot_price("BAR", "USDC", 2.0),
+            )
+            .build();
+        index.apply_update(&update);
+
+        assert_relative_eq!(index.price_of(&token("WETH")).unwrap(), 2500.0);
+        // Only the thin pool is left to price WBTC
+        assert_relative_eq!(index.price_of(&token("WBTC")).unwrap(), 50000.0);
+        assert_relative_eq!(index.price_of(&token("BAR")).unwrap(), 2.0);
+        assert_relative_eq!(index.price_of(&token("FOO")).unwrap(), 6.0);
+    }
+
+    #[test]
+    fn test_apply_update_reprices_downstream_tokens() {
+        let mut index = PriceIndex::new(usdc());
+        index.apply_update(&snapshot());
+
+        let update = BlockUpdateBuilder::new(2)
+            .token(usdc())
+            .state("wbtc_weth", MockProtocolSim::new().with_spot_price("WBTC", "WETH", 30.0))
+            .build();
+        index.apply_update(&update);
+
+        assert_relative_eq!(index.price_of(&token("WETH")).unwrap(), 2000.0);
+        assert_relative_eq!(index.price_of(&token("WBTC")).unwrap(), 60000.0);
+
+        // Once the pool on its path is inactive, the token falls back to the next best path
+        let update = BlockUpdateBuilder::new(3)
+            .token(usdc())
+            .state(
+                "weth_usdc",
+                MockProtocolSim::new()
+                    .with_spot_price("WETH", "USDC", 2000.0)
+                    .inactive(),
+            )
+            .build();
+        index.apply_update(&update);
+
+        // WETH is now priced through DAI
+        assert_relative_eq!(index.price_of(&token("WETH")).unwrap(), 2000.0);
+        assert_relative_eq!(index.price_of(&token("WBTC")).unwrap(), 60000.0);
+        assert_relative_eq!(index.price_of(&token("DAI")).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_max_hops() {
+        let mut index = PriceIndex::new(usdc()).with_max_hops(1);
+        index.apply_update(&snapshot());
+
+        assert_relative_eq!(index.price_of(&token("WETH")).unwrap(), 2000.0);
+        assert_relative_eq!(index.price_of(&token("WBTC")).unwrap(), 50000.0);
+    }
+}
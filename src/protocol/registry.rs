@@ -0,0 +1,292 @@
+//! Protocols supported by the built-in decoders
+//!
+//! Each built-in protocol state, and the VM path, is described by a `ProtocolDescriptor`: the
+//! Tycho protocol systems it decodes and the attributes it needs. Descriptors serialize to JSON,
+//! e.g. to document the supported protocols for integrators.
+//!
+//! `ProtocolStreamBuilder` validates its exchange registrations against `supported_protocols`, so
+//! a misspelled protocol system or a mismatched decoder fails when the stream is built rather
+//! than leaving the exchange's pools undecoded.
+use std::any::{type_name, TypeId};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use super::models::Capability;
+use crate::evm::{
+    engine_db::tycho_db::PreCachedDB,
+    protocol::{
+        maverick_v2::state::MaverickV2State, uniswap_v2::state::UniswapV2State,
+        uniswap_v3::state::UniswapV3State, uniswap_v4::state::UniswapV4State,
+        vm::state::EVMPoolState,
+    },
+};
+
+/// Capabilities of the native states: they quote sell orders and report decimal-adjusted spot
+/// prices from their state alone.
+const NATIVE_CAPABILITIES: &[Capability] =
+    &[Capability::SellSide, Capability::PriceFunction, Capability::ScaledPrice];
+
+/// How a Tycho protocol system is decoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProtocolDescriptor {
+    /// The Tycho protocol system, e.g. `uniswap_v3`
+    pub system: &'static str,
+    /// Type name of the decoded state
+    pub decoder: &'static str,
+    /// Static attributes of the components the decoder requires
+    pub static_attributes: &'static [&'static str],
+    /// State attributes of the components the decoder requires
+    pub state_attributes: &'static [&'static str],
+    /// Whether pools are simulated through an adapter contract, on the VM
+    pub requires_adapter: bool,
+    /// Capabilities of all pools of the protocol. Pools simulated through an adapter report
+    /// theirs at runtime, see `ProtocolSim::capabilities`.
+    pub capabilities: &'static [Capability],
+    #[serde(skip)]
+    decoder_type: TypeId,
+}
+
+impl ProtocolDescriptor {
+    fn native<T: 'static>(
+        system: &'static str,
+        static_attributes: &'static [&'static str],
+        state_attributes: &'static [&'static str],
+    ) -> Self {
+        Self {
+            system,
+            decoder: short_type_name::<T>(),
+            static_attributes,
+            state_attributes,
+            requires_adapter: false,
+            capabilities: NATIVE_CAPABILITIES,
+            decoder_type: TypeId::of::<T>(),
+        }
+    }
+
+    fn vm(system: &'static str) -> Self {
+        Self {
+            system,
+            decoder: short_type_name::<EVMPoolState<PreCachedDB>>(),
+            static_attributes: &[],
+            state_attributes: &[],
+            requires_adapter: true,
+            capabilities: &[],
+            decoder_type: TypeId::of::<EVMPoolState<PreCachedDB>>(),
+        }
+    }
+}
+
+/// Returns the type name without its module path, e.g. `UniswapV3State`.
+fn short_type_name<T: ?Sized>() -> &'static str {
+    let name = type_name::<T>();
+    // Generic parameters may contain paths as well
+    let end = name.find('<').unwrap_or(name.len());
+    let start = name[..end]
+        .rfind("::")
+        .map_or(0, |i| i + 2);
+    &name[start..end]
+}
+
+/// Returns the descriptors of all protocol systems the built-in decoders support.
+pub fn supported_protocols() -> Vec<ProtocolDescriptor> {
+    const V2_STATE: &[&str] = &["reserve0", "reserve1"];
+    vec![
+        ProtocolDescriptor::native::<UniswapV2State>("uniswap_v2", &[], V2_STATE),
+        ProtocolDescriptor::native::<UniswapV2State>("sushiswap_v2", &[], V2_STATE),
+        ProtocolDescriptor::native::<UniswapV2State>("pancakeswap_v2", &[], V2_STATE),
+        ProtocolDescriptor::native::<UniswapV3State>(
+            "uniswap_v3",
+            &["fee"],
+            &["liquidity", "sqrt_price_x96", "tick"],
+        ),
+        ProtocolDescriptor::native::<UniswapV4State>(
+            "uniswap_v4",
+            &["tick_spacing"],
+            &[
+                "liquidity",
+                "sqrt_price_x96",
+                "fee",
+                "protocol_fees/zero2one",
+                "protocol_fees/one2zero",
+                "tick",
+            ],
+        ),
+        ProtocolDescriptor::native::<MaverickV2State>(
+            "maverick_v2",
+            &["tick_spacing", "fee_a_in", "fee_b_in"],
+            &["active_tick"],
+        ),
+        ProtocolDescriptor::vm("vm:balancer_v2"),
+        ProtocolDescriptor::vm("vm:curve"),
+    ]
+}
+
+/// Returns the descriptor of a protocol system, if a built-in decoder supports it.
+pub fn find_protocol(system: &str) -> Option<ProtocolDescriptor> {
+    supported_protocols()
+        .into_iter()
+        .find(|descriptor| descriptor.system == system)
+}
+
+/// A protocol system registered with a decoder that can't decode it
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RegistrationError {
+    #[error("Unknown protocol system {system} for {decoder}, it decodes: {supported}")]
+    UnknownProtocol { system: String, decoder: &'static str, supported: String },
+    #[error("Protocol system {system} is decoded by {expected}, not {actual}")]
+    DecoderMismatch { system: String, expected: &'static str, actual: &'static str },
+    #[error("Protocol system {0} is not simulated through an adapter")]
+    AdapterNotSupported(String),
+}
+
+/// Checks that the state `T` can decode the components of `system`.
+///
+/// Built-in states are only accepted for the systems they support. Other states are custom
+/// decoders, which are accepted for any system that isn't already supported by a built-in one.
+pub fn validate_registration<T: 'static>(system: &str) -> Result<(), RegistrationError> {
+    validate_decoder(system, TypeId::of::<T>(), short_type_name::<T>())
+}
+
+fn validate_decoder(
+    system: &str,
+    decoder_type: TypeId,
+    decoder: &'static str,
+) -> Result<(), RegistrationError> {
+    let protocols = supported_protocols();
+    if let Some(descriptor) = protocols
+        .iter()
+        .find(|descriptor| descriptor.system == system)
+    {
+        if descriptor.decoder_type != decoder_type {
+            return Err(RegistrationError::DecoderMismatch {
+                system: system.to_string(),
+                expected: descriptor.decoder,
+                actual: decoder,
+            });
+        }
+        return Ok(());
+    }
+    let supported: Vec<&str> = protocols
+        .iter()
+        .filter(|descriptor| descriptor.decoder_type == decoder_type)
+        .map(|descriptor| descriptor.system)
+        .collect();
+    if supported.is_empty() {
+        return Ok(());
+    }
+    Err(RegistrationError::UnknownProtocol {
+        system: system.to_string(),
+        decoder,
+        supported: supported.join(", "),
+    })
+}
+
+/// Checks that `system` can be simulated through a custom adapter, see
+/// `ProtocolStreamBuilder::vm_exchange_with_adapter`.
+pub fn validate_adapter_registration(system: &str) -> Result<(), RegistrationError> {
+    match find_protocol(system) {
+        Some(descriptor) if !descriptor.requires_adapter => {
+            Err(RegistrationError::AdapterNotSupported(system.to_string()))
+        }
+        _ => validate_registration::<EVMPoolState<PreCachedDB>>(system),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    struct CustomState;
+
+    #[test]
+    fn test_supported_protocols_pass_validation() {
+        let protocols = supported_protocols();
+
+        let systems: HashSet<_> = protocols
+            .iter()
+            .map(|descriptor| descriptor.system)
+            .collect();
+        assert_eq!(systems.len(), protocols.len(), "Protocol systems must be unique");
+        for descriptor in &protocols {
+            let res =
+                validate_decoder(descriptor.system, descriptor.decoder_type, descriptor.decoder);
+            assert_eq!(res, Ok(()), "{}", descriptor.system);
+        }
+        // The registrations of the examples
+        assert_eq!(validate_registration::<UniswapV2State>("uniswap_v2"), Ok(()));
+        assert_eq!(validate_registration::<UniswapV3State>("uniswap_v3"), Ok(()));
+        assert_eq!(validate_registration::<UniswapV4State>("uniswap_v4"), Ok(()));
+        assert_eq!(validate_registration::<MaverickV2State>("maverick_v2"), Ok(()));
+        assert_eq!(validate_registration::<EVMPoolState<PreCachedDB>>("vm:balancer_v2"), Ok(()));
+        assert_eq!(validate_registration::<EVMPoolState<PreCachedDB>>("vm:curve"), Ok(()));
+    }
+
+    #[test]
+    fn test_decoder_mismatch() {
+        let err = validate_registration::<UniswapV3State>("uniswap_v2").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Protocol system uniswap_v2 is decoded by UniswapV2State, not UniswapV3State"
+        );
+    }
+
+    #[test]
+    fn test_unknown_protocol() {
+        let err = validate_registration::<UniswapV2State>("uniswap_v2_typo").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Unknown protocol system uniswap_v2_typo for UniswapV2State, it decodes: uniswap_v2, \
+             sushiswap_v2, pancakeswap_v2"
+        );
+    }
+
+    #[rstest]
+    #[case::custom_protocol("my_amm", Ok(()))]
+    #[case::built_in_protocol(
+        "uniswap_v2",
+        Err(RegistrationError::DecoderMismatch {
+            system: "uniswap_v2".to_string(),
+            expected: "UniswapV2State",
+            actual: "CustomState",
+        })
+    )]
+    fn test_custom_decoder(#[case] system: &str, #[case] expected: Result<(), RegistrationError>) {
+        assert_eq!(validate_registration::<CustomState>(system), expected);
+    }
+
+    #[rstest]
+    #[case::vm("vm:curve", Ok(()))]
+    #[case::native("uniswap_v3", Err(RegistrationError::AdapterNotSupported("uniswap_v3".into())))]
+    fn test_adapter_registration(
+        #[case] system: &str,
+        #[case] expected: Result<(), RegistrationError>,
+    ) {
+        assert_eq!(validate_adapter_registration(system), expected);
+    }
+
+    #[test]
+    fn test_descriptor_json() {
+        let descriptor = find_protocol("uniswap_v3").unwrap();
+
+        let json = serde_json::to_value(&descriptor).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "system": "uniswap_v3",
+                "decoder": "UniswapV3State",
+                "static_attributes": ["fee"],
+                "state_attributes": ["liquidity", "sqrt_price_x96", "tick"],
+                "requires_adapter": false,
+                "capabilities": ["SellSide", "PriceFunction", "ScaledPrice"],
+            })
+        );
+    }
+}
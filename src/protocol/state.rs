@@ -7,8 +7,18 @@
 //! The `ProtocolSim` trait has several key methods:
 //!  - `fee`: Returns the protocol's fee as a ratio.
 //!  - `spot_price`: Returns the current spot price between two tokens.
+//!  - `spot_price_inverse`: Returns the spot price in the opposite direction.
+//!  - `fee_adjusted_price`: Returns the spot price net of the protocol's fee.
+//!  - `spot_price_in`: Returns the spot price of a token in terms of an external numeraire.
+//!  - `effective_price`: Returns the price realized by a trade of a given size.
 //!  - `get_amount_out`: Returns the amount of output tokens given an amount of input tokens.
+//!  - `quote_curve`: Returns the amounts out for a grid of amounts in.
+//!  - `min_amount_out`: Returns the minimum amount out to accept given a slippage tolerance.
+//!  - `get_limit`: Returns the largest amount a single swap can sell into the pool.
 //!  - `delta_transition`: Applies a state delta to the simulated protocol.
+//!  - `is_active`: Whether the protocol currently accepts swaps.
+//!  - `capabilities`: The features the protocol reports to support.
+//!  - `state_fingerprint`: Hashes the state, e.g. to deduplicate identical states.
 //!  - `clone_box`: Clones the simulated protocol state as a trait object.
 //!  - `as_any`: Allows downcasting of the trait object.
 //!  - `as_any_mut`: Allows mutable downcasting of the trait object.
@@ -44,19 +54,32 @@
 //! assert_eq!(state.spot_price(&weth, &usdc).unwrap(), 1218.0683462769755f64);
 //! assert_eq!(out, 1214374202.to_biguint().unwrap());
 //! ```
-use std::{any::Any, collections::HashMap};
+use std::{
+    any::{type_name, Any},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
 
+use lazy_static::lazy_static;
 use num_bigint::BigUint;
+use num_traits::{ToPrimitive, Zero};
 use tycho_core::{dto::ProtocolStateDelta, Bytes};
 
 use crate::{
     models::Token,
     protocol::{
         errors::{SimulationError, TransitionError},
-        models::GetAmountOutResult,
+        models::{Capability, CurvePoint, GetAmountOutResult},
     },
 };
 
+/// Basis points in 100%
+const BPS_DENOMINATOR: u16 = 10_000;
+
+lazy_static! {
+    static ref NO_CAPABILITIES: HashSet<Capability> = HashSet::new();
+}
+
 /// ProtocolSim trait
 /// This trait defines the methods that a protocol state must implement in order to be used
 /// in the trade simulation.
@@ -81,8 +104,133 @@ pub trait ProtocolSim: std::fmt::Debug + Send + Sync + 'static {
     ///   BTC/USDT, BTC would be the base asset.
     /// * `b` - Quote Token: refers to the token that is the price of a pair. For the symbol
     ///   BTC/USDT, USDT would be the quote asset.
+    ///
+    /// The price is decimal-adjusted: it's expressed in whole units of the quote token per whole
+    /// unit of the base token, e.g. ~1000 for WETH/USDC even though they have 18 and 6 decimals.
+    /// All implementations of this crate follow this convention, see `raw_spot_price` for the
+    /// price in the tokens' smallest units.
+    ///
+    /// This is the marginal price, i.e. the price of an infinitesimally small trade. Near a
+    /// liquidity boundary (e.g. a tick with thin liquidity) it can be far from the price a real
+    /// trade gets, see `effective_price` for that.
     fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError>;
 
+    /// Returns the spot price in the tokens' smallest units, without adjusting for decimals
+    ///
+    /// This is the on-chain ratio, e.g. the amount of USDC wei received per WETH wei, and equals
+    /// `spot_price(base, quote) * 10^(quote.decimals - base.decimals)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - Base Token, see `spot_price`.
+    /// * `quote` - Quote Token, see `spot_price`.
+    fn raw_spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        let price = self.spot_price(base, quote)?;
+        Ok(price * 10f64.powi(quote.decimals as i32 - base.decimals as i32))
+    }
+
+    /// Returns the spot price of `quote` in units of `base`, the reciprocal of
+    /// `spot_price(base, quote)`
+    ///
+    /// The default computes `1 / spot_price(base, quote)`, which rounds twice and can lose
+    /// precision for pairs with very different prices and decimals, e.g. WBTC/SHIB.
+    /// Implementations that can compute the price from their state in either direction override
+    /// it.
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - Base Token, see `spot_price`.
+    /// * `quote` - Quote Token, see `spot_price`.
+    fn spot_price_inverse(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.spot_price(base, quote)
+            .map(|price| 1.0 / price)
+    }
+
+    /// Returns the spot price of `base` in `quote` net of the protocol's fee:
+    /// `spot_price(base, quote) * (1 - fee)`
+    ///
+    /// This is the marginal amount of `quote` received for selling one `base`, the limit of
+    /// `effective_price` for small trades.
+    fn fee_adjusted_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.spot_price(base, quote)
+            .map(|price| price * (1.0 - self.fee()))
+    }
+
+    /// Returns the price of `token` in the unit `numeraire_price` is expressed in, e.g. USD
+    ///
+    /// Composes the pool's spot price of `token` in `numeraire`, another token of the pool, with
+    /// the externally supplied price of one `numeraire`: `spot_price(token, numeraire) *
+    /// numeraire_price`. Returns `None` if the pool has no spot price for the two tokens.
+    ///
+    /// # Examples
+    /// ```
+    /// use std::str::FromStr;
+    /// use alloy_primitives::U256;
+    /// use num_bigint::ToBigUint;
+    /// use tycho_simulation::evm::protocol::uniswap_v2::state::UniswapV2State;
+    /// use tycho_simulation::protocol::state::ProtocolSim;
+    /// use tycho_simulation::models::Token;
+    ///
+    /// let state = UniswapV2State::new(
+    ///     U256::from_str("36925554990922").unwrap(),
+    ///     U256::from_str("30314846538607556521556").unwrap(),
+    /// );
+    /// let usdc = Token::new(
+    ///     "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48", 6, "USDC", 10_000.to_biguint().unwrap()
+    /// );
+    /// let weth = Token::new(
+    ///     "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2", 18, "WETH", 10_000.to_biguint().unwrap()
+    /// );
+    ///
+    /// // USDC trading at 0.999 USD
+    /// let weth_usd = state.spot_price_in(&weth, &usdc, 0.999).unwrap();
+    /// assert_eq!(weth_usd, 1218.0683462769755 * 0.999);
+    /// ```
+    fn spot_price_in(&self, token: &Token, numeraire: &Token, numeraire_price: f64) -> Option<f64> {
+        self.spot_price(token, numeraire)
+            .ok()
+            .map(|price| price * numeraire_price)
+    }
+
+    /// Returns the price realized by a trade of `amount_in`, in units of `token_out` per unit of
+    /// `token_in`.
+    ///
+    /// Unlike `spot_price`, which is the marginal price at the current state, this simulates the
+    /// trade and returns `amount_out / amount_in` adjusted for decimals, so it includes fees and
+    /// the price impact of the given size. For small amounts it converges to
+    /// `spot_price(token_in, token_out)` minus fees.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount_in` - The amount in of the input token.
+    /// * `token_in` - The input token ERC20 token.
+    /// * `token_out` - The output token ERC20 token.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::InvalidInput` if `amount_in` is zero, or any error raised by
+    /// `get_amount_out`.
+    fn effective_price(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<f64, SimulationError> {
+        if amount_in.is_zero() {
+            return Err(SimulationError::InvalidInput(
+                "Amount in must be greater than zero".to_string(),
+                None,
+            ));
+        }
+        let amount_out = self
+            .get_amount_out(amount_in.clone(), token_in, token_out)?
+            .amount;
+        let to_units = |amount: &BigUint, decimals: usize| {
+            amount.to_f64().unwrap_or(f64::INFINITY) / 10f64.powi(decimals as i32)
+        };
+        Ok(to_units(&amount_out, token_out.decimals) / to_units(&amount_in, token_in.decimals))
+    }
+
     /// Returns the amount out given an amount in and input/output tokens.
     ///
     /// # Arguments
@@ -102,6 +250,97 @@ pub trait ProtocolSim: std::fmt::Debug + Send + Sync + 'static {
         token_out: &Token,
     ) -> Result<GetAmountOutResult, SimulationError>;
 
+    /// Like `get_amount_out`, but fails if the swap needs more than `gas_limit` gas.
+    ///
+    /// The default implementation compares the estimate of `get_amount_out`, which is exact for
+    /// protocols with deterministic gas costs. VM-backed protocols also run the simulation with
+    /// this gas limit instead of the engine's default.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationError::InvalidInput` carrying the result if the gas estimate exceeds
+    /// the limit, or any error of `get_amount_out`.
+    fn get_amount_out_with_gas_limit(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        gas_limit: u64,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        self.get_amount_out(amount_in, token_in, token_out)?
+            .ensure_gas_limit(gas_limit)
+    }
+
+    /// Quotes every amount of `amounts`, e.g. to sample a pool's price curve once per block.
+    ///
+    /// Each point carries the amount out and gas `get_amount_out` returns for its amount in,
+    /// and whether the amount out kept growing along the grid. The default calls
+    /// `get_amount_out` once per amount, implementations that can reuse work between amounts
+    /// override it with identical results.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::InvalidInput` if `amounts` isn't sorted in non-decreasing
+    /// order, or the error of the first amount `get_amount_out` fails for.
+    fn quote_curve(
+        &self,
+        token_in: &Token,
+        token_out: &Token,
+        amounts: &[BigUint],
+    ) -> Result<Vec<CurvePoint>, SimulationError> {
+        CurvePoint::check_grid(amounts)?;
+        let quotes = amounts
+            .iter()
+            .map(|amount_in| {
+                let result = self.get_amount_out(amount_in.clone(), token_in, token_out)?;
+                Ok((amount_in.clone(), result.amount, result.gas))
+            })
+            .collect::<Result<Vec<_>, SimulationError>>()?;
+        Ok(CurvePoint::curve(quotes))
+    }
+
+    /// Returns the minimum amount of `token_out` to accept for `amount_in`, e.g. as a swap's
+    /// `amountOutMinimum`: the amount of `get_amount_out` reduced by `slippage_bps` basis points.
+    ///
+    /// The result is rounded down, so it never exceeds the tolerated amount and a swap executing
+    /// exactly at the tolerance doesn't revert.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::InvalidInput` if `slippage_bps` exceeds 10_000, or any error of
+    /// `get_amount_out`.
+    fn min_amount_out(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+        slippage_bps: u16,
+    ) -> Result<BigUint, SimulationError> {
+        if slippage_bps > BPS_DENOMINATOR {
+            return Err(SimulationError::InvalidInput(
+                format!("Slippage of {slippage_bps} bps exceeds 100%"),
+                None,
+            ));
+        }
+        let amount_out = self
+            .get_amount_out(amount_in, token_in, token_out)?
+            .amount;
+        Ok(amount_out * (BPS_DENOMINATOR - slippage_bps) / BPS_DENOMINATOR)
+    }
+
+    /// Returns the largest amount of `token_in` a single swap to `token_out` can sell into the
+    /// pool, or `None` if the protocol doesn't bound it.
+    ///
+    /// Selling more fails or is capped, e.g. by the hard limits VM-backed pools report. Defaults
+    /// to `None`: the output of an unbounded pool only approaches its reserve as the amount grows.
+    fn get_limit(
+        &self,
+        _token_in: &Token,
+        _token_out: &Token,
+    ) -> Result<Option<BigUint>, SimulationError> {
+        Ok(None)
+    }
+
     /// Decodes and applies a protocol state delta to the state
     ///
     /// Will error if the provided delta is missing any required attributes or if any of the
@@ -121,11 +360,99 @@ pub trait ProtocolSim: std::fmt::Debug + Send + Sync + 'static {
         tokens: &HashMap<Bytes, Token>,
     ) -> Result<(), TransitionError<String>>;
 
+    /// Returns whether the pool currently accepts swaps.
+    ///
+    /// Paused pools may keep reporting the prices they had before being paused, so consumers
+    /// should skip inactive pools when quoting. Defaults to `true` for protocols that can't be
+    /// paused.
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    /// Returns whether the state passes cheap sanity checks for quoting, e.g. nonzero reserves,
+    /// positive liquidity and a fee below 100%.
+    ///
+    /// Use it to filter pools before quoting: `get_amount_out` fails or returns nothing useful on
+    /// non-tradeable states. A `true` result doesn't guarantee a quote succeeds for every amount.
+    /// Defaults to `is_active`.
+    fn is_tradeable(&self) -> bool {
+        self.is_active()
+    }
+
+    /// Returns the addresses of the pool's tokens, in the pool's order, e.g. token 0 first for
+    /// Uniswap pools.
+    ///
+    /// States decoded from Tycho know their tokens. States created by hand only report them if
+    /// they were given, e.g. with `UniswapV2State::with_tokens`. An empty list means the tokens
+    /// are unknown, which is the default for implementations that don't track them: look them up
+    /// in the pool's `ProtocolComponent` instead.
+    fn tokens(&self) -> Vec<Bytes> {
+        Vec::new()
+    }
+
+    /// Returns the capabilities the pool reports, e.g. whether it supports a price function.
+    ///
+    /// Routers can use this to skip pools that can't do what they need instead of calling the
+    /// relevant method and handling the error. Defaults to an empty set for protocols that don't
+    /// report capabilities, which does not mean they lack them.
+    fn capabilities(&self) -> &HashSet<Capability> {
+        &NO_CAPABILITIES
+    }
+
+    /// Returns a fingerprint of the state, e.g. to deduplicate unchanged states across blocks
+    ///
+    /// Implementations hash all fields that affect simulation results, so two states with equal
+    /// fingerprints return identical results from `get_amount_out` and `spot_price` for all
+    /// inputs, barring hash collisions. States of different types never share a fingerprint if
+    /// they're computed with `fingerprint`.
+    ///
+    /// Fingerprints are only comparable within the same build of this crate, they should not be
+    /// persisted.
+    ///
+    /// The default hashes the state's type, `fee`, `is_active` and the spot prices between its
+    /// `tokens`, taken as 18 decimals tokens. It can't see the rest of the state, e.g. the
+    /// liquidity behind the prices, so states only differing there share a fingerprint:
+    /// implementations should override it to cover all their fields.
+    fn state_fingerprint(&self) -> u64 {
+        let tokens = self
+            .tokens()
+            .into_iter()
+            .map(|address| Token {
+                address,
+                decimals: 18,
+                symbol: String::new(),
+                gas: BigUint::zero(),
+                tax: None,
+                quality: 100,
+            })
+            .collect::<Vec<_>>();
+        let mut spot_prices = Vec::new();
+        for base in &tokens {
+            for quote in tokens
+                .iter()
+                .filter(|quote| *quote != base)
+            {
+                spot_prices.push(
+                    self.spot_price(base, quote)
+                        .ok()
+                        .map(f64::to_bits),
+                );
+            }
+        }
+        let type_id = <dyn Any>::type_id(self.as_any());
+        fingerprint(&(type_id, self.fee().to_bits(), self.is_active(), spot_prices))
+    }
+
     /// Clones the protocol state as a trait object.
     /// This allows the state to be cloned when it is being used as a `Box<dyn ProtocolSim>`.
     fn clone_box(&self) -> Box<dyn ProtocolSim>;
 
     /// Allows downcasting of the trait object to its underlying type.
+    ///
+    /// This is an escape hatch for protocol specific data the trait doesn't expose, e.g. the
+    /// ticks of a `UniswapV3State` via `state.as_any().downcast_ref::<UniswapV3State>()`. Code
+    /// that works across protocols should prefer the trait methods, which every protocol
+    /// implements.
     fn as_any(&self) -> &dyn Any;
 
     /// Allows downcasting of the trait object to its mutable underlying type.
@@ -137,6 +464,15 @@ pub trait ProtocolSim: std::fmt::Debug + Send + Sync + 'static {
     fn eq(&self, other: &dyn ProtocolSim) -> bool;
 }
 
+/// Hashes `value` together with its type name, for implementations of
+/// `ProtocolSim::state_fingerprint`.
+pub fn fingerprint<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    type_name::<T>().hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
 impl Clone for Box<dyn ProtocolSim> {
     fn clone(&self) -> Box<dyn ProtocolSim> {
         self.clone_box()
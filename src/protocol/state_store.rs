@@ -0,0 +1,290 @@
+//! Pool states shared across threads
+//!
+//! A `StateStore` keeps the latest states of the pools of a protocol stream. The thread consuming
+//! the stream feeds it every `BlockUpdate` with `apply_update`, while other threads read the
+//! states through `BlockSnapshot`s: immutable views of the pools as of one block.
+//!
+//! States are held behind `Arc`s, and the maps of a snapshot are shared with the store until the
+//! next update. Applying an update only copies the map of pointers if a snapshot still refers to
+//! it, the states of unchanged pools are never cloned.
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::watch;
+
+use super::{
+    models::{BlockUpdate, ProtocolComponent},
+    state::ProtocolSim,
+};
+
+/// The pools of a `StateStore` as of one block
+///
+/// Cloning a snapshot is cheap, and a snapshot is never modified: it stays consistent while the
+/// store advances to later blocks.
+#[derive(Debug, Clone, Default)]
+pub struct BlockSnapshot {
+    block_number: u64,
+    states: Arc<HashMap<String, Arc<dyn ProtocolSim>>>,
+    components: Arc<HashMap<String, ProtocolComponent>>,
+}
+
+impl BlockSnapshot {
+    /// The block of the last update applied before the snapshot was taken, 0 if none was.
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    /// Returns the state of the pool with the given component id.
+    pub fn get(&self, id: &str) -> Option<&Arc<dyn ProtocolSim>> {
+        self.states.get(id)
+    }
+
+    /// Returns the component of the pool with the given id.
+    pub fn component(&self, id: &str) -> Option<&ProtocolComponent> {
+        self.components.get(id)
+    }
+
+    /// Iterates over the states of all pools, by component id, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Arc<dyn ProtocolSim>)> {
+        self.states.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+/// Latest pool states of a protocol stream, readable from any thread
+///
+/// Clones share the same states, so a clone can be handed to each reader. Updates are meant to
+/// be applied by a single writer, in block order.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    latest: Arc<watch::Sender<BlockSnapshot>>,
+}
+
+impl Default for StateStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateStore {
+    /// Creates an empty store, at block 0.
+    pub fn new() -> Self {
+        Self { latest: Arc::new(watch::Sender::new(BlockSnapshot::default())) }
+    }
+
+    /// Applies the pools of a `BlockUpdate` and advances the store to its block.
+    ///
+    /// Only the entries of new, changed and removed pools are replaced. States of pools whose
+    /// component is unknown are kept, like `PriceIndex` does. A resync update replaces all pools.
+    pub fn apply_update(&self, update: &BlockUpdate) {
+        self.latest.send_modify(|latest| {
+            if update.is_resync {
+                latest.states = Arc::default();
+                latest.components = Arc::default();
+            }
+            // Copies the maps only if a snapshot still refers to them
+            let states = Arc::make_mut(&mut latest.states);
+            let components = Arc::make_mut(&mut latest.components);
+            for id in update.removed_pairs.keys() {
+                states.remove(id);
+                components.remove(id);
+            }
+            for (id, component) in &update.new_pairs {
+                components.insert(id.clone(), component.clone());
+            }
+            for (id, state) in &update.states {
+                if !update.removed_pairs.contains_key(id) {
+                    states.insert(id.clone(), Arc::from(state.clone_box()));
+                }
+            }
+            latest.block_number = update.block_number;
+        });
+    }
+
+    /// Returns a snapshot of the current pools.
+    pub fn snapshot(&self) -> BlockSnapshot {
+        self.latest.borrow().clone()
+    }
+
+    /// Returns the current block number, 0 before the first update.
+    pub fn block_number(&self) -> u64 {
+        self.latest.borrow().block_number
+    }
+
+    /// Returns the current state of the pool with the given component id.
+    pub fn get(&self, id: &str) -> Option<Arc<dyn ProtocolSim>> {
+        self.latest.borrow().get(id).cloned()
+    }
+
+    /// Waits until the store reached block `block_number`, or a later one, and returns a
+    /// snapshot of it.
+    pub async fn wait_for_block(&self, block_number: u64) -> BlockSnapshot {
+        self.latest
+            .subscribe()
+            .wait_for(|latest| latest.block_number >= block_number)
+            .await
+            .expect("The store holds the sender")
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        protocol::models::RemovalReason,
+        testing::{BlockUpdateBuilder, MockProtocolSim},
+    };
+
+    const POOLS: [&str; 3] = ["pool_a", "pool_b", "pool_c"];
+
+    /// Updates all pools, setting their fee to the block number so readers can tell which block
+    /// a state belongs to.
+    fn update(block_number: u64) -> BlockUpdate {
+        let mut builder = BlockUpdateBuilder::new(block_number);
+        for id in POOLS {
+            let state = MockProtocolSim::new().with_fee(block_number as f64);
+            builder = if block_number == 1 {
+                builder.pool(id, "WETH/USDC", state)
+            } else {
+                builder.state(id, state)
+            };
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_snapshot_is_immutable() {
+        let store = StateStore::new();
+        store.apply_update(&update(1));
+        let snapshot = store.snapshot();
+
+        store.apply_update(&update(2));
+        store.apply_update(
+            &BlockUpdateBuilder::new(3)
+                .removed_pair("pool_c", "WETH/USDC", RemovalReason::BelowTvlThreshold)
+                .build(),
+        );
+
+        assert_eq!(snapshot.block_number(), 1);
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot.get("pool_a").unwrap().fee(), 1.0);
+        assert_eq!(store.block_number(), 3);
+        assert_eq!(store.get("pool_a").unwrap().fee(), 2.0);
+        assert!(store.get("pool_c").is_none());
+        assert!(store
+            .snapshot()
+            .component("pool_c")
+            .is_none());
+        assert!(store
+            .snapshot()
+            .component("pool_a")
+            .is_some());
+    }
+
+    #[test]
+    fn test_unchanged_states_are_shared() {
+        let store = StateStore::new();
+        store.apply_update(&update(1));
+        let before = store.snapshot();
+
+        store.apply_update(
+            &BlockUpdateBuilder::new(2)
+                .state("pool_a", MockProtocolSim::new().with_fee(2.0))
+                .build(),
+        );
+
+        let after = store.snapshot();
+        assert!(!Arc::ptr_eq(before.get("pool_a").unwrap(), after.get("pool_a").unwrap()));
+        assert!(Arc::ptr_eq(before.get("pool_b").unwrap(), after.get("pool_b").unwrap()));
+    }
+
+    #[test]
+    fn test_resync_replaces_pools() {
+        let store = StateStore::new();
+        store.apply_update(&update(1));
+
+        store.apply_update(
+            &BlockUpdateBuilder::new(2)
+                .pool("pool_d", "WETH/DAI", MockProtocolSim::new())
+                .resync()
+                .build(),
+        );
+
+        let snapshot = store.snapshot();
+        assert_eq!(
+            snapshot
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>(),
+            ["pool_d"]
+        );
+        assert!(snapshot.component("pool_a").is_none());
+    }
+
+    #[test]
+    fn test_concurrent_readers_see_consistent_blocks() {
+        let store = StateStore::new();
+        store.apply_update(&update(1));
+        let n_blocks = 200;
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    let mut last_block = 0;
+                    while last_block < n_blocks {
+                        let snapshot = store.snapshot();
+                        let block = snapshot.block_number();
+                        assert!(block >= last_block, "Blocks went backwards");
+                        assert_eq!(snapshot.len(), POOLS.len());
+                        for (id, state) in snapshot.iter() {
+                            assert_eq!(state.fee(), block as f64, "{id} isn't at block {block}");
+                        }
+                        last_block = block;
+                    }
+                })
+            })
+            .collect();
+        for block_number in 2..=n_blocks {
+            store.apply_update(&update(block_number));
+            thread::sleep(Duration::from_micros(50));
+        }
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_block() {
+        let store = StateStore::new();
+        let writer = store.clone();
+
+        let (snapshot, _) = tokio::join!(store.wait_for_block(2), async move {
+            for block_number in 1..=3 {
+                tokio::task::yield_now().await;
+                writer.apply_update(&update(block_number));
+            }
+        });
+
+        assert!(snapshot.block_number() >= 2);
+        assert_eq!(snapshot.get("pool_a").unwrap().fee(), snapshot.block_number() as f64);
+        // Already reached blocks return immediately
+        assert_eq!(
+            store
+                .wait_for_block(1)
+                .await
+                .block_number(),
+            3
+        );
+    }
+}
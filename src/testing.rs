@@ -0,0 +1,752 @@
+//! Test utilities
+//!
+//! Deterministic building blocks for tests of code consuming this crate, without the need for a
+//! Tycho connection or an EVM:
+//!  - `token` and `token_with_decimals` create tokens whose addresses are derived from their
+//!    symbol, so the same symbol always refers to the same token.
+//!  - `MockProtocolSim` is a `ProtocolSim` with programmable spot prices and amounts out.
+//!  - `BlockUpdateBuilder` assembles `BlockUpdate`s from short pair descriptions like
+//!    `"WETH/USDC"`.
+//!  - `MockEthRpc` serves node requests of the VM from memory and counts them.
+//!
+//! Only available with the `test-utils` feature.
+//!
+//! # Examples
+//! ```ignore
+//! // Requires the `test-utils` feature
+//! use tycho_simulation::testing::{token, BlockUpdateBuilder, MockProtocolSim};
+//!
+//! let pool = MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0);
+//! let update = BlockUpdateBuilder::new(1)
+//!     .pool("pool_a", "WETH/USDC", pool)
+//!     .build();
+//!
+//! let state = &update.states["pool_a"];
+//! assert_eq!(state.spot_price(&token("WETH"), &token("USDC")).unwrap(), 2000.0);
+//! assert_eq!(update.new_pairs["pool_a"].tokens.len(), 2);
+//! ```
+use std::{any::Any, collections::HashMap, str::FromStr};
+
+use alloy_primitives::keccak256;
+use itertools::Itertools;
+use num_bigint::BigUint;
+use num_traits::{FromPrimitive, ToPrimitive};
+use tycho_core::{dto::ProtocolStateDelta, Bytes};
+
+use crate::{
+    models::Token,
+    protocol::{
+        errors::{SimulationError, TransitionError},
+        models::{
+            BlockUpdate, GetAmountOutResult, ProtocolComponent, RemovalReason, TOKEN_TRANSFER_GAS,
+        },
+        state::{fingerprint, ProtocolSim},
+    },
+};
+
+/// Gas cost of a swap on a `MockProtocolSim`, unless set with `with_gas`.
+pub const MOCK_SWAP_GAS: u64 = 100_000;
+
+/// Returns the deterministic address used for `symbol`: the last 20 bytes of its keccak hash.
+pub fn symbol_address(symbol: &str) -> Bytes {
+    Bytes::from(keccak256(symbol.as_bytes())[12..].to_vec())
+}
+
+/// Creates an 18 decimals token at the address derived from `symbol`.
+pub fn token(symbol: &str) -> Token {
+    token_with_decimals(symbol, 18)
+}
+
+/// Creates a token with the given decimals at the address derived from `symbol`.
+pub fn token_with_decimals(symbol: &str, decimals: usize) -> Token {
+    token_at(symbol_address(symbol), symbol, decimals)
+}
+
+/// Creates a token at a fixed address, e.g. to match the tokens of a recorded Tycho message.
+pub fn token_at(address: Bytes, symbol: &str, decimals: usize) -> Token {
+    Token {
+        address,
+        decimals,
+        symbol: symbol.to_string(),
+        gas: BigUint::from(TOKEN_TRANSFER_GAS),
+        tax: None,
+        quality: 100,
+    }
+}
+
+/// Indexes tokens by address, the form expected by the decoder and `delta_transition`.
+pub fn token_map(tokens: impl IntoIterator<Item = Token>) -> HashMap<Bytes, Token> {
+    tokens
+        .into_iter()
+        .map(|t| (t.address.clone(), t))
+        .collect()
+}
+
+/// A `ProtocolSim` returning programmed values
+///
+/// Tokens are matched by symbol, so any token created with the helpers of this module (or by
+/// hand) works as long as the symbols match.
+///
+/// `get_amount_out` first looks up amounts programmed with `with_amount_out`. Other amounts are
+/// converted at the programmed spot price minus the fee, without any price impact. The returned
+/// state is a copy of the mock, so chained swaps see the same prices.
+///
+/// `delta_transition` only counts the applied deltas, see `transitions`. `tokens` reports the
+/// addresses of the tokens of all programmed prices and amounts, see `symbol_address`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MockProtocolSim {
+    fee: f64,
+    gas: u64,
+    active: bool,
+    spot_prices: HashMap<(String, String), f64>,
+    amounts_out: HashMap<(String, String, BigUint), BigUint>,
+    /// Share of the amounts out derived from spot prices that each swap takes away
+    price_impact: f64,
+    /// Scales the amounts out derived from spot prices, reduced by every swap
+    depth: f64,
+    transitions: usize,
+}
+
+impl Default for MockProtocolSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockProtocolSim {
+    /// Creates an active mock without fees or prices, charging `MOCK_SWAP_GAS` per swap.
+    pub fn new() -> Self {
+        MockProtocolSim {
+            fee: 0.0,
+            gas: MOCK_SWAP_GAS,
+            active: true,
+            spot_prices: HashMap::new(),
+            amounts_out: HashMap::new(),
+            price_impact: 0.0,
+            depth: 1.0,
+            transitions: 0,
+        }
+    }
+
+    /// Sets the fee, as a ratio.
+    pub fn with_fee(mut self, fee: f64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Sets the gas reported for each swap.
+    pub fn with_gas(mut self, gas: u64) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    /// Marks the pool as inactive, see `ProtocolSim::is_active`.
+    pub fn inactive(mut self) -> Self {
+        self.active = false;
+        self
+    }
+
+    /// Programs the spot price of `base` in `quote`. The inverse direction is derived from it
+    /// unless programmed separately.
+    pub fn with_spot_price(mut self, base: &str, quote: &str, price: f64) -> Self {
+        self.spot_prices
+            .insert((base.to_string(), quote.to_string()), price);
+        self
+    }
+
+    /// Programs the exact amount out returned for swapping `amount_in` of `token_in`.
+    pub fn with_amount_out(
+        mut self,
+        token_in: &str,
+        token_out: &str,
+        amount_in: impl Into<BigUint>,
+        amount_out: impl Into<BigUint>,
+    ) -> Self {
+        self.amounts_out.insert(
+            (token_in.to_string(), token_out.to_string(), amount_in.into()),
+            amount_out.into(),
+        );
+        self
+    }
+
+    /// Makes swaps move the pool: the state returned by a swap quotes amounts out reduced by
+    /// `impact`, as a ratio. Only amounts derived from spot prices are affected.
+    pub fn with_price_impact(mut self, impact: f64) -> Self {
+        self.price_impact = impact;
+        self
+    }
+
+    /// Returns the number of deltas applied to this state.
+    pub fn transitions(&self) -> usize {
+        self.transitions
+    }
+
+    fn price(&self, base: &Token, quote: &Token) -> Option<f64> {
+        let key = (base.symbol.clone(), quote.symbol.clone());
+        self.spot_prices
+            .get(&key)
+            .copied()
+            .or_else(|| {
+                self.spot_prices
+                    .get(&(key.1, key.0))
+                    .map(|price| 1.0 / price)
+            })
+    }
+}
+
+impl ProtocolSim for MockProtocolSim {
+    fn fee(&self) -> f64 {
+        self.fee
+    }
+
+    fn spot_price(&self, base: &Token, quote: &Token) -> Result<f64, SimulationError> {
+        self.price(base, quote).ok_or_else(|| {
+            SimulationError::InvalidInput(
+                format!("No spot price programmed for {}/{}", base.symbol, quote.symbol),
+                None,
+            )
+        })
+    }
+
+    fn get_amount_out(
+        &self,
+        amount_in: BigUint,
+        token_in: &Token,
+        token_out: &Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let key = (token_in.symbol.clone(), token_out.symbol.clone(), amount_in);
+        let amount = match self.amounts_out.get(&key) {
+            Some(amount) => amount.clone(),
+            None => {
+                let price = self.spot_price(token_in, token_out)?;
+                key.2
+                    .to_f64()
+                    .map(|amount| amount / 10f64.powi(token_in.decimals as i32))
+                    .and_then(|units| {
+                        let out = units * price * (1.0 - self.fee) * self.depth;
+                        BigUint::from_f64((out * 10f64.powi(token_out.decimals as i32)).floor())
+                    })
+                    .ok_or_else(|| {
+                        SimulationError::FatalError("Amount out is not representable".to_string())
+                    })?
+            }
+        };
+        let mut new_state = self.clone();
+        new_state.depth *= 1.0 - self.price_impact;
+        Ok(GetAmountOutResult::new(amount, BigUint::from(self.gas), Box::new(new_state)))
+    }
+
+    fn delta_transition(
+        &mut self,
+        _delta: ProtocolStateDelta,
+        _tokens: &HashMap<Bytes, Token>,
+    ) -> Result<(), TransitionError<String>> {
+        self.transitions += 1;
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn tokens(&self) -> Vec<Bytes> {
+        self.spot_prices
+            .keys()
+            .flat_map(|(base, quote)| [base, quote])
+            .chain(
+                self.amounts_out
+                    .keys()
+                    .flat_map(|(token_in, token_out, _)| [token_in, token_out]),
+            )
+            .unique()
+            .sorted()
+            .map(|symbol| symbol_address(symbol))
+            .collect()
+    }
+
+    /// Covers everything but the transition counter, which doesn't affect the results.
+    fn state_fingerprint(&self) -> u64 {
+        let spot_prices = self
+            .spot_prices
+            .iter()
+            .map(|(pair, price)| (pair, price.to_bits()))
+            .sorted()
+            .collect_vec();
+        let amounts_out = self
+            .amounts_out
+            .iter()
+            .sorted()
+            .collect_vec();
+        fingerprint(&(
+            self.fee.to_bits(),
+            self.gas,
+            self.active,
+            spot_prices,
+            amounts_out,
+            self.price_impact.to_bits(),
+            self.depth.to_bits(),
+        ))
+    }
+
+    fn clone_box(&self) -> Box<dyn ProtocolSim> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn eq(&self, other: &dyn ProtocolSim) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .is_some_and(|other| self == other)
+    }
+}
+
+/// Builds `BlockUpdate`s from short pair descriptions
+///
+/// Pairs are described by their token symbols separated by `/`, e.g. `"WETH/USDC"`. Symbols are
+/// resolved to the tokens registered with `token`, or to an 18 decimals token created by the
+/// `token` function of this module otherwise. Component ids that are valid hex strings are used
+/// as the component address, other ids get an address derived like token addresses.
+#[derive(Debug, Default)]
+pub struct BlockUpdateBuilder {
+    block_number: u64,
+    tokens: HashMap<String, Token>,
+    states: HashMap<String, Box<dyn ProtocolSim>>,
+    new_pairs: HashMap<String, ProtocolComponent>,
+    removed_pairs: HashMap<String, ProtocolComponent>,
+    removal_reasons: HashMap<String, RemovalReason>,
+    is_resync: bool,
+}
+
+impl BlockUpdateBuilder {
+    pub fn new(block_number: u64) -> Self {
+        BlockUpdateBuilder { block_number, ..Default::default() }
+    }
+
+    /// Registers a token to use for its symbol, e.g. to give it different decimals.
+    pub fn token(mut self, token: Token) -> Self {
+        self.tokens
+            .insert(token.symbol.clone(), token);
+        self
+    }
+
+    /// Adds a pair to `new_pairs`.
+    pub fn new_pair(mut self, id: &str, pair: &str) -> Self {
+        let component = self.component(id, pair);
+        self.new_pairs
+            .insert(id.to_string(), component);
+        self
+    }
+
+    /// Adds a new or updated state.
+    pub fn state(mut self, id: &str, state: impl ProtocolSim) -> Self {
+        self.states
+            .insert(id.to_string(), Box::new(state));
+        self
+    }
+
+    /// Adds a new pair together with its state.
+    pub fn pool(self, id: &str, pair: &str, state: impl ProtocolSim) -> Self {
+        self.new_pair(id, pair).state(id, state)
+    }
+
+    /// Adds a pair to `removed_pairs`, removed for the given reason.
+    pub fn removed_pair(mut self, id: &str, pair: &str, reason: RemovalReason) -> Self {
+        let component = self.component(id, pair);
+        self.removed_pairs
+            .insert(id.to_string(), component);
+        self.removal_reasons
+            .insert(id.to_string(), reason);
+        self
+    }
+
+    /// Marks the update as a resync, see `BlockUpdate::is_resync`.
+    pub fn resync(mut self) -> Self {
+        self.is_resync = true;
+        self
+    }
+
+    pub fn build(self) -> BlockUpdate {
+        BlockUpdate::new(self.block_number, self.states, self.new_pairs)
+            .set_removed_pairs(self.removed_pairs)
+            .set_removal_reasons(self.removal_reasons)
+            .set_is_resync(self.is_resync)
+    }
+
+    fn component(&self, id: &str, pair: &str) -> ProtocolComponent {
+        let tokens = pair
+            .split('/')
+            .map(|symbol| {
+                let symbol = symbol.trim();
+                self.tokens
+                    .get(symbol)
+                    .cloned()
+                    .unwrap_or_else(|| token(symbol))
+            })
+            .collect();
+        let address = Bytes::from_str(id).unwrap_or_else(|_| symbol_address(id));
+        ProtocolComponent::new(address, tokens)
+    }
+}
+
+#[cfg(feature = "evm")]
+pub use mock_rpc::MockEthRpc;
+
+#[cfg(feature = "evm")]
+mod mock_rpc {
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use alloy_primitives::{Address, Bytes, U256};
+    use futures::future::{ready, BoxFuture};
+
+    use crate::evm::rpc::{EthRpc, EthRpcError};
+
+    /// An `EthRpc` serving accounts and storage from memory, counting the requests it receives
+    ///
+    /// Unknown accounts have no code, balance or nonce, and unknown storage slots are zero.
+    /// Batched requests count as a single request of the batch method, e.g. `get_code_batch`.
+    #[derive(Debug, Default)]
+    pub struct MockEthRpc {
+        code: HashMap<Address, Bytes>,
+        storage: HashMap<(Address, U256), U256>,
+        balances: HashMap<Address, U256>,
+        code_delay: Option<Duration>,
+        code_batch_limit: Option<usize>,
+        calls: Mutex<HashMap<&'static str, usize>>,
+    }
+
+    impl MockEthRpc {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn with_code(mut self, address: Address, code: Vec<u8>) -> Self {
+            self.code.insert(address, code.into());
+            self
+        }
+
+        pub fn with_storage(mut self, address: Address, index: U256, value: U256) -> Self {
+            self.storage
+                .insert((address, index), value);
+            self
+        }
+
+        pub fn with_balance(mut self, address: Address, balance: U256) -> Self {
+            self.balances.insert(address, balance);
+            self
+        }
+
+        /// Delays every batched code request by `delay`, like a slow node would.
+        pub fn with_code_delay(mut self, delay: Duration) -> Self {
+            self.code_delay = Some(delay);
+            self
+        }
+
+        /// Answers batched code requests with the code of the first `limit` addresses only, like
+        /// a node dropping part of a batch.
+        pub fn with_code_batch_limit(mut self, limit: usize) -> Self {
+            self.code_batch_limit = Some(limit);
+            self
+        }
+
+        /// Returns the number of requests received for `method`, e.g. `get_storage_at`.
+        pub fn calls(&self, method: &str) -> usize {
+            self.calls
+                .lock()
+                .unwrap()
+                .get(method)
+                .copied()
+                .unwrap_or(0)
+        }
+
+        fn record(&self, method: &'static str) {
+            *self
+                .calls
+                .lock()
+                .unwrap()
+                .entry(method)
+                .or_default() += 1;
+        }
+
+        fn code_of(&self, address: &Address) -> Bytes {
+            self.code
+                .get(address)
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        fn storage_of(&self, address: Address, index: U256) -> U256 {
+            self.storage
+                .get(&(address, index))
+                .copied()
+                .unwrap_or_default()
+        }
+    }
+
+    impl EthRpc for MockEthRpc {
+        fn get_code(
+            &self,
+            address: Address,
+            _block: Option<u64>,
+        ) -> BoxFuture<'_, Result<Bytes, EthRpcError>> {
+            self.record("get_code");
+            Box::pin(ready(Ok(self.code_of(&address))))
+        }
+
+        fn get_storage_at(
+            &self,
+            address: Address,
+            index: U256,
+            _block: Option<u64>,
+        ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+            self.record("get_storage_at");
+            Box::pin(ready(Ok(self.storage_of(address, index))))
+        }
+
+        fn get_balance(
+            &self,
+            address: Address,
+            _block: Option<u64>,
+        ) -> BoxFuture<'_, Result<U256, EthRpcError>> {
+            self.record("get_balance");
+            let balance = self
+                .balances
+                .get(&address)
+                .copied()
+                .unwrap_or_default();
+            Box::pin(ready(Ok(balance)))
+        }
+
+        fn get_transaction_count(
+            &self,
+            _address: Address,
+            _block: Option<u64>,
+        ) -> BoxFuture<'_, Result<u64, EthRpcError>> {
+            self.record("get_transaction_count");
+            Box::pin(ready(Ok(0)))
+        }
+
+        fn get_code_batch<'a>(
+            &'a self,
+            addresses: &'a [Address],
+            _block: Option<u64>,
+        ) -> BoxFuture<'a, Result<Vec<Bytes>, EthRpcError>> {
+            self.record("get_code_batch");
+            let code = addresses
+                .iter()
+                .take(
+                    self.code_batch_limit
+                        .unwrap_or(usize::MAX),
+                )
+                .map(|address| self.code_of(address))
+                .collect();
+            match self.code_delay {
+                Some(delay) => Box::pin(async move {
+                    tokio::time::sleep(delay).await;
+                    Ok(code)
+                }),
+                None => Box::pin(ready(Ok(code))),
+            }
+        }
+
+        fn get_storage_batch<'a>(
+            &'a self,
+            slots: &'a [(Address, U256)],
+            _block: Option<u64>,
+        ) -> BoxFuture<'a, Result<Vec<U256>, EthRpcError>> {
+            self.record("get_storage_batch");
+            let values = slots
+                .iter()
+                .map(|(address, index)| self.storage_of(*address, *index))
+                .collect();
+            Box::pin(ready(Ok(values)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_addresses_are_deterministic() {
+        assert_eq!(token("WETH"), token_with_decimals("WETH", 6));
+        assert_ne!(token("WETH").address, token("USDC").address);
+        assert_eq!(token("WETH").address.len(), 20);
+    }
+
+    #[test]
+    fn test_mock_spot_price() {
+        let pool = MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0);
+
+        assert_eq!(
+            pool.spot_price(&token("WETH"), &token("USDC"))
+                .unwrap(),
+            2000.0
+        );
+        assert_eq!(
+            pool.spot_price(&token("USDC"), &token("WETH"))
+                .unwrap(),
+            0.0005
+        );
+        assert!(pool
+            .spot_price(&token("WETH"), &token("DAI"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_mock_get_amount_out() {
+        let weth = token("WETH");
+        let usdc = token_with_decimals("USDC", 6);
+        let pool = MockProtocolSim::new()
+            .with_spot_price("WETH", "USDC", 2000.0)
+            .with_fee(0.01)
+            .with_gas(50_000)
+            .with_amount_out("WETH", "USDC", 7u64, 3u64);
+
+        let res = pool
+            .get_amount_out(BigUint::from(10u64).pow(18), &weth, &usdc)
+            .unwrap();
+        assert_eq!(res.amount, BigUint::from(1_980_000_000u64));
+        assert_eq!(res.gas, BigUint::from(50_000u64));
+        assert!(ProtocolSim::eq(res.new_state.as_ref(), &pool));
+
+        let res = pool
+            .get_amount_out(BigUint::from(7u64), &weth, &usdc)
+            .unwrap();
+        assert_eq!(res.amount, BigUint::from(3u64));
+    }
+
+    #[test]
+    fn test_mock_fingerprint() {
+        let pool = MockProtocolSim::new().with_spot_price("WETH", "USDC", 2000.0);
+        let mut transitioned = pool.clone();
+        transitioned
+            .delta_transition(
+                ProtocolStateDelta {
+                    component_id: "pool".to_string(),
+                    updated_attributes: HashMap::new(),
+                    deleted_attributes: Default::default(),
+                },
+                &HashMap::new(),
+            )
+            .unwrap();
+
+        assert_eq!(transitioned.state_fingerprint(), pool.state_fingerprint());
+        assert_ne!(
+            pool.clone()
+                .with_fee(0.01)
+                .state_fingerprint(),
+            pool.state_fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_block_update_builder() {
+        let update = BlockUpdateBuilder::new(10)
+            .token(token_with_decimals("USDC", 6))
+            .pool("pool_a", "WETH/USDC", MockProtocolSim::new())
+            .new_pair("0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852", "WETH / DAI")
+            .removed_pair("pool_b", "DAI/USDC", RemovalReason::Deleted)
+            .resync()
+            .build();
+
+        assert_eq!(update.block_number, 10);
+        assert!(update.is_resync);
+        assert_eq!(update.states.len(), 1);
+        let pool_a = &update.new_pairs["pool_a"];
+        assert_eq!(pool_a.address, symbol_address("pool_a"));
+        assert!(pool_a
+            .tokens
+            .iter()
+            .any(|t| t.symbol == "USDC" && t.decimals == 6));
+        assert_eq!(
+            update.new_pairs["0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852"].address,
+            Bytes::from_str("0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852").unwrap()
+        );
+        assert_eq!(update.removal_reasons["pool_b"], RemovalReason::Deleted);
+        assert_eq!(
+            update.removed_pairs["pool_b"]
+                .tokens
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_mock_tokens() {
+        let pool = MockProtocolSim::new()
+            .with_spot_price("WETH", "USDC", 2000.0)
+            .with_amount_out("USDC", "DAI", 1u64, 1u64);
+
+        assert_eq!(
+            pool.tokens(),
+            vec![symbol_address("DAI"), symbol_address("USDC"), symbol_address("WETH")]
+        );
+    }
+
+    #[test]
+    fn test_quote_curve() {
+        let weth = token_with_decimals("WETH", 0);
+        let usdc = token_with_decimals("USDC", 0);
+        let pool = MockProtocolSim::new()
+            .with_spot_price("WETH", "USDC", 2000.0)
+            .with_gas(50_000)
+            .with_amount_out("WETH", "USDC", 3u64, 5_000u64);
+        let amounts = [1u64, 2, 3, 4].map(BigUint::from);
+
+        let curve = pool
+            .quote_curve(&weth, &usdc, &amounts)
+            .unwrap();
+
+        let outs = curve
+            .iter()
+            .map(|point| point.amount_out.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(outs, [2_000u64, 4_000, 5_000, 8_000].map(BigUint::from));
+        assert!(curve
+            .iter()
+            .all(|point| point.gas == BigUint::from(50_000u64) && point.monotonic));
+        assert_eq!(curve[2].amount_in, BigUint::from(3u64));
+
+        let pool = pool.with_amount_out("WETH", "USDC", 4u64, 4_000u64);
+        let curve = pool
+            .quote_curve(&weth, &usdc, &amounts)
+            .unwrap();
+        let flags = curve
+            .iter()
+            .map(|point| point.monotonic)
+            .collect::<Vec<_>>();
+        assert_eq!(flags, [true, true, true, false]);
+
+        let unsorted = [2u64, 1].map(BigUint::from);
+        assert!(matches!(
+            pool.quote_curve(&weth, &usdc, &unsorted),
+            Err(SimulationError::InvalidInput(_, None))
+        ));
+    }
+
+    #[test]
+    fn test_block_update_states_sorted() {
+        let update = BlockUpdateBuilder::new(1)
+            .pool("pool_c", "WETH/USDC", MockProtocolSim::new())
+            .pool("pool_a", "WETH/DAI", MockProtocolSim::new())
+            .pool("pool_b", "DAI/USDC", MockProtocolSim::new())
+            .build();
+
+        let ids = update
+            .states_sorted()
+            .into_iter()
+            .map(|(id, _)| id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, ["pool_a", "pool_b", "pool_c"]);
+    }
+}
@@ -1,10 +1,25 @@
 use std::collections::HashMap;
 
 use tycho_client::{rpc::RPCClient, HttpRPCClient};
-use tycho_core::{dto::Chain, Bytes};
+use tycho_core::{
+    dto::{Chain, PaginationParams, TokensRequestBody},
+    Bytes,
+};
 
 use crate::{models::Token, protocol::errors::SimulationError};
 
+pub mod amounts;
+#[cfg(feature = "evm")]
+pub mod recorder;
+#[cfg(feature = "evm")]
+pub mod token;
+
+#[cfg(feature = "evm")]
+pub use crate::evm::protocol::u256_num::{
+    biguint_to_u256_checked, from_f64_with_decimals, scale_between_decimals, to_f64_with_decimals,
+    u256_to_biguint, Rounding,
+};
+
 /// Converts a hexadecimal string into a `Vec<u8>`.
 ///
 /// This function accepts a hexadecimal string with or without the `0x` prefix. If the prefix
@@ -35,30 +50,114 @@ pub fn hexstring_to_vec(hexstring: &str) -> Result<Vec<u8>, SimulationError> {
     Ok(bytes)
 }
 
-/// Loads all tokens from Tycho and returns them as a Hashmap of address->Token.
+/// Loads all tokens of `chain` from Tycho and returns them as a Hashmap of address->Token.
 pub async fn load_all_tokens(
     tycho_url: &str,
     no_tls: bool,
     auth_key: Option<&str>,
+    chain: Chain,
+) -> HashMap<Bytes, Token> {
+    load_tokens_filtered(tycho_url, no_tls, auth_key, chain, |_| true).await
+}
+
+/// Returns the address of the wrapped native token of `chain`, see
+/// `evm::tycho_models::Chain::wrapped_native_token`, which also covers chains Tycho doesn't
+/// index yet, like Optimism and Base.
+#[cfg(feature = "evm")]
+pub fn wrapped_native_token(chain: Chain) -> Option<Bytes> {
+    crate::evm::tycho_models::Chain::from(chain).wrapped_native_token()
+}
+
+/// Number of tokens requested from Tycho per page.
+const TOKENS_PAGE_SIZE: i64 = 3_000;
+
+/// Server-side filters of the tokens requested from Tycho, see `load_tokens_with_query`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenQuery {
+    /// Minimum quality of the tokens, from 0 to 100. `None` loads tokens of any quality.
+    pub min_quality: Option<i32>,
+    /// Only load tokens traded within this many days. `None` loads tokens regardless of activity.
+    pub traded_n_days_ago: Option<u64>,
+}
+
+impl Default for TokenQuery {
+    /// Tokens of quality 100 traded within the last 42 days
+    fn default() -> Self {
+        Self { min_quality: Some(100), traded_n_days_ago: Some(42) }
+    }
+}
+
+/// Loads tokens from Tycho page by page, keeping only those accepted by `predicate`.
+///
+/// Each page is converted and filtered before the next one is requested, so only matching tokens
+/// are held in memory. Use this instead of [`load_all_tokens`] when only a subset of tokens is of
+/// interest, e.g. an address allowlist.
+///
+/// # Arguments
+///
+/// * `tycho_url` - Tycho host, without the scheme.
+/// * `no_tls` - Whether to use `http` instead of `https`.
+/// * `auth_key` - Optional Tycho API key.
+/// * `chain` - Chain whose tokens are loaded.
+/// * `predicate` - Called with every converted token; only tokens it returns `true` for are kept.
+///
+/// # Returns
+///
+/// A map of token address to `Token` for all tokens matching the predicate.
+pub async fn load_tokens_filtered(
+    tycho_url: &str,
+    no_tls: bool,
+    auth_key: Option<&str>,
+    chain: Chain,
+    predicate: impl Fn(&Token) -> bool,
+) -> HashMap<Bytes, Token> {
+    load_tokens_with_query(tycho_url, no_tls, auth_key, chain, TokenQuery::default(), predicate)
+        .await
+}
+
+/// Loads tokens like `load_tokens_filtered`, requesting only the tokens matching `query` from
+/// Tycho, e.g. to include tokens below the default minimum quality.
+pub async fn load_tokens_with_query(
+    tycho_url: &str,
+    no_tls: bool,
+    auth_key: Option<&str>,
+    chain: Chain,
+    query: TokenQuery,
+    predicate: impl Fn(&Token) -> bool,
 ) -> HashMap<Bytes, Token> {
     let rpc_url =
         if no_tls { format!("http://{tycho_url}") } else { format!("https://{tycho_url}") };
     let rpc_client = HttpRPCClient::new(rpc_url.as_str(), auth_key).unwrap();
 
     #[allow(clippy::mutable_key_type)]
-    rpc_client
-        .get_all_tokens(Chain::Ethereum, Some(100), Some(42), 3_000)
-        .await
-        .expect("Unable to load tokens")
-        .into_iter()
-        .map(|token| {
-            let token_clone = token.clone();
-            (
-                token.address.clone(),
-                token.try_into().unwrap_or_else(|_| {
-                    panic!("Couldn't convert {:?} into ERC20 token.", token_clone)
-                }),
-            )
-        })
-        .collect::<HashMap<_, Token>>()
+    let mut tokens = HashMap::new();
+    let mut page = 0;
+    loop {
+        let request = TokensRequestBody {
+            token_addresses: None,
+            min_quality: query.min_quality,
+            traded_n_days_ago: query.traded_n_days_ago,
+            pagination: PaginationParams { page, page_size: TOKENS_PAGE_SIZE },
+            chain,
+        };
+        let response = rpc_client
+            .get_tokens(&request)
+            .await
+            .expect("Unable to load tokens");
+        let page_len = response.tokens.len() as i64;
+
+        for token in response.tokens {
+            let address = token.address.clone();
+            let token = Token::from_tycho(token);
+            if predicate(&token) {
+                tokens.insert(address, token);
+            }
+        }
+
+        page += 1;
+        if page_len < TOKENS_PAGE_SIZE || page * TOKENS_PAGE_SIZE >= response.pagination.total {
+            break;
+        }
+    }
+    tokens
 }
@@ -0,0 +1,134 @@
+//! Conversions between token amounts in whole units, as shown to users, and in the token's
+//! smallest unit, as used on-chain and by `ProtocolSim`.
+//!
+//! Both directions go through the decimal representation of the amount: `to_wei(0.1, 18)` is
+//! exactly 10^17, unlike scaling the binary `f64` which gives 100000000000000005.
+use num_bigint::BigUint;
+use num_traits::Zero;
+
+use crate::protocol::errors::SimulationError;
+
+/// Converts an amount in whole units into the token's smallest unit, e.g. 1.5 USDC with 6
+/// decimals into 1_500_000.
+///
+/// The amount is taken as the shortest decimal that `f64` prints for it, so `0.1` is one tenth.
+/// Digits beyond `decimals` are rounded to the nearest unit, ties away from zero. Amounts of any
+/// size are converted exactly, but an `f64` only holds ~16 significant digits, so the digits
+/// after those are zero, see `from_wei`.
+///
+/// # Errors
+///
+/// Returns `SimulationError::InvalidInput` if `human` is negative, NaN or infinite.
+pub fn to_wei(human: f64, decimals: usize) -> Result<BigUint, SimulationError> {
+    if !human.is_finite() || human < 0.0 {
+        return Err(SimulationError::InvalidInput(
+            format!("Can't convert {human} into an unsigned amount"),
+            None,
+        ));
+    }
+    // `f64` displays as the shortest decimal parsing back to the same value, never in exponent
+    // notation. `abs` turns -0 into 0.
+    let decimal = human.abs().to_string();
+    let (integer, fraction) = decimal
+        .split_once('.')
+        .unwrap_or((&decimal, ""));
+    let kept = &fraction[..fraction.len().min(decimals)];
+    let digits = format!("{integer}{kept:0<decimals$}");
+    let mut raw = digits
+        .parse::<BigUint>()
+        .map_err(|e| SimulationError::FatalError(format!("Failed to parse {digits}: {e}")))?;
+    if fraction
+        .as_bytes()
+        .get(decimals)
+        .is_some_and(|digit| *digit >= b'5')
+    {
+        raw += 1u8;
+    }
+    Ok(raw)
+}
+
+/// Converts an amount in the token's smallest unit into whole units, e.g. 1_500_000 with 6
+/// decimals into 1.5.
+///
+/// Returns the `f64` closest to the exact amount. Amounts with more than ~16 significant digits
+/// lose their last digits, and amounts beyond `f64::MAX` units return infinity.
+pub fn from_wei(raw: &BigUint, decimals: usize) -> f64 {
+    if raw.is_zero() {
+        return 0.0;
+    }
+    let digits = raw.to_string();
+    let decimal = if decimals == 0 {
+        digits
+    } else if digits.len() > decimals {
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        format!("{integer}.{fraction}")
+    } else {
+        format!("0.{digits:0>decimals$}")
+    };
+    // Parsing a decimal rounds correctly to the closest `f64`
+    decimal
+        .parse()
+        .expect("Formatted amount is a valid decimal")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case::usdc(1.5, 6, "1500000")]
+    #[case::usdc_cent(0.01, 6, "10000")]
+    #[case::weth(0.1, 18, "100000000000000000")]
+    #[case::weth_large(1234.5678, 18, "1234567800000000000000")]
+    #[case::whole(42.0, 0, "42")]
+    #[case::rounds_down(1.2345674, 6, "1234567")]
+    #[case::rounds_up(1.2345675, 6, "1234568")]
+    #[case::tiny(0.0000001, 6, "0")]
+    #[case::huge(1e30, 18, "1000000000000000000000000000000000000000000000000")]
+    fn test_to_wei(#[case] human: f64, #[case] decimals: usize, #[case] exp: &str) {
+        assert_eq!(to_wei(human, decimals).unwrap(), BigUint::from_str(exp).unwrap());
+    }
+
+    #[rstest]
+    #[case::negative(-1.0)]
+    #[case::nan(f64::NAN)]
+    #[case::infinite(f64::INFINITY)]
+    fn test_to_wei_invalid(#[case] human: f64) {
+        assert!(matches!(to_wei(human, 18), Err(SimulationError::InvalidInput(_, None))));
+    }
+
+    #[rstest]
+    #[case::usdc(1.5, 6)]
+    #[case::usdc_small(0.000001, 6)]
+    #[case::weth(0.1, 18)]
+    #[case::weth_large(3_141.592653589793, 18)]
+    fn test_round_trip(#[case] human: f64, #[case] decimals: usize) {
+        let raw = to_wei(human, decimals).unwrap();
+
+        assert_eq!(from_wei(&raw, decimals), human);
+        assert_eq!(to_wei(from_wei(&raw, decimals), decimals).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_from_wei() {
+        assert_eq!(from_wei(&BigUint::from(1_500_000u32), 6), 1.5);
+        assert_eq!(from_wei(&BigUint::from(1u32), 18), 1e-18);
+        assert_eq!(from_wei(&BigUint::ZERO, 18), 0.0);
+        assert_eq!(from_wei(&(BigUint::from(1u8) << 1100), 0), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_from_wei_beyond_f64_precision() {
+        // 21 significant digits, more than an f64 holds
+        let raw = BigUint::from_str("123456789012345678901").unwrap();
+
+        let human = from_wei(&raw, 18);
+
+        assert_eq!(human, 123.45678901234568);
+        assert_eq!(to_wei(human, 18).unwrap(), BigUint::from_str("123456789012345680000").unwrap());
+    }
+}
@@ -0,0 +1,153 @@
+//! Recording and replaying of protocol streams
+//!
+//! A `StreamRecorder` writes every `BlockUpdate` of a stream to a sink as one JSON line, while
+//! passing the stream's items through unchanged. `replay` reads such a file back into a stream of
+//! `BlockUpdate`s, e.g. to backtest a strategy deterministically.
+//!
+//! States are stored as `ProtocolSimSnapshot`s, so only updates of snapshot-able states can be
+//! recorded and VM-backed states can't be replayed.
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use futures::{stream, Stream, StreamExt};
+use tracing::warn;
+
+use crate::protocol::models::BlockUpdate;
+
+/// Writes the `BlockUpdate`s of a stream as JSON lines to a sink
+pub struct StreamRecorder<W: Write> {
+    sink: W,
+}
+
+impl<W: Write> StreamRecorder<W> {
+    pub fn new(sink: W) -> Self {
+        Self { sink }
+    }
+
+    /// Wraps `stream`, writing each `BlockUpdate` to the sink before yielding it.
+    ///
+    /// All items, including errors, are passed through unchanged. Errors are not recorded. An
+    /// update that can't be written is logged and skipped in the recording only.
+    pub fn record<S, E>(mut self, stream: S) -> impl Stream<Item = Result<BlockUpdate, E>>
+    where
+        S: Stream<Item = Result<BlockUpdate, E>>,
+    {
+        stream.map(move |item| {
+            if let Ok(update) = &item {
+                if let Err(e) = self.write(update) {
+                    warn!(error = %e, block = update.block_number, "Failed to record block update");
+                }
+            }
+            item
+        })
+    }
+
+    fn write(&mut self, update: &BlockUpdate) -> io::Result<()> {
+        serde_json::to_writer(&mut self.sink, update)?;
+        self.sink.write_all(b"\n")?;
+        self.sink.flush()
+    }
+}
+
+/// Reads a file written by a `StreamRecorder` back into a stream of `BlockUpdate`s, in recording
+/// order.
+///
+/// Lines are read lazily as the stream is polled. A line that can't be read or decoded yields an
+/// error, the stream continues with the next line.
+pub fn replay(path: impl AsRef<Path>) -> io::Result<impl Stream<Item = io::Result<BlockUpdate>>> {
+    let lines = BufReader::new(File::open(path)?).lines();
+    Ok(stream::iter(
+        lines
+            .filter(|line| {
+                line.as_ref()
+                    .map_or(true, |l| !l.trim().is_empty())
+            })
+            .map(|line| serde_json::from_str(&line?).map_err(io::Error::from)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use alloy_primitives::U256;
+
+    use super::*;
+    use crate::{
+        evm::protocol::uniswap_v2::state::UniswapV2State,
+        protocol::{models::RemovalReason, state::ProtocolSim},
+        testing::{token, BlockUpdateBuilder},
+    };
+
+    const POOL: &str = "0x0d4a11d5eeaac28ec3f61d100daf4d40471f1852";
+    const DROPPED_POOL: &str = "0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc";
+
+    fn two_blocks() -> Vec<BlockUpdate> {
+        vec![
+            BlockUpdateBuilder::new(1)
+                .token(token("WETH"))
+                .token(token("USDC"))
+                .pool(POOL, "WETH/USDC", UniswapV2State::new(U256::from(1_000), U256::from(2_000)))
+                .pool(
+                    DROPPED_POOL,
+                    "WETH/USDC",
+                    UniswapV2State::new_with_fee(U256::from(10), U256::from(20), 25),
+                )
+                .build(),
+            BlockUpdateBuilder::new(2)
+                .token(token("WETH"))
+                .token(token("USDC"))
+                .state(POOL, UniswapV2State::new(U256::from(1_100), U256::from(1_900)))
+                .removed_pair(DROPPED_POOL, "WETH/USDC", RemovalReason::BelowTvlThreshold)
+                .build(),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let sink = OpenOptions::new()
+            .append(true)
+            .open(file.path())
+            .unwrap();
+        let items = vec![
+            Ok(two_blocks().remove(0)),
+            Err("decode failure".to_string()),
+            Ok(two_blocks().remove(1)),
+        ];
+
+        let passed: Vec<_> = StreamRecorder::new(sink)
+            .record(stream::iter(items))
+            .collect()
+            .await;
+        let replayed: Vec<_> = replay(file.path())
+            .unwrap()
+            .map(|update| update.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(passed.len(), 3);
+        assert_eq!(passed[1].as_ref().unwrap_err(), "decode failure");
+        let expected = two_blocks();
+        assert_eq!(replayed.len(), expected.len());
+        for (replayed, expected) in replayed.iter().zip(&expected) {
+            assert_eq!(replayed.block_number, expected.block_number);
+            assert_eq!(replayed.new_pairs, expected.new_pairs);
+            assert_eq!(replayed.removed_pairs, expected.removed_pairs);
+            assert_eq!(replayed.removal_reasons, expected.removal_reasons);
+            assert_eq!(replayed.is_resync, expected.is_resync);
+            assert_eq!(replayed.states.len(), expected.states.len());
+            for (id, state) in &expected.states {
+                assert!(replayed.states[id].eq(state.as_ref()), "state of {id} differs");
+            }
+        }
+        let pool = replayed[1].states[POOL]
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+        assert_eq!(pool.reserve0, U256::from(1_100));
+    }
+}
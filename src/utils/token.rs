@@ -0,0 +1,54 @@
+//! Token properties measured by simulating the token contract
+use std::fmt::Debug;
+
+use alloy_primitives::{Address, U256};
+use revm::DatabaseRef;
+
+use crate::{
+    evm::{
+        engine_db::engine_db_interface::EngineDatabaseInterface,
+        protocol::vm::erc20_token::measure_transfer_tax_at, simulation::SimulationEngine,
+    },
+    protocol::errors::SimulationError,
+};
+
+/// Measures the fraction of `amount` lost when transferring `token`, e.g. `0.05` for a token
+/// taxing transfers by 5%. Regular tokens return `0.0`.
+///
+/// Simulates a `transfer` from an override account funded with `amount` at the block the engine's
+/// database is at, and compares the recipient's `balanceOf` to `amount`. The token contract must
+/// already be set up on the engine. The result can be set as `Token::tax` when loading tokens.
+///
+/// # Errors
+///
+/// Returns `SimulationError::InvalidInput` if the engine's database has no block or `amount` is
+/// zero, and the simulation's error if it fails.
+pub fn measure_transfer_tax<D: EngineDatabaseInterface + Clone + Debug>(
+    engine: &SimulationEngine<D>,
+    token: Address,
+    amount: U256,
+) -> Result<f64, SimulationError>
+where
+    <D as DatabaseRef>::Error: Debug,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    let block = engine.state.block().ok_or_else(|| {
+        SimulationError::InvalidInput("The engine's database has no block set".to_string(), None)
+    })?;
+    measure_transfer_tax_at(engine, &token, amount, &block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evm::engine_db::tycho_db::PreCachedDB;
+
+    #[test]
+    fn test_measure_transfer_tax_without_block() {
+        let engine = SimulationEngine::new(PreCachedDB::new().unwrap(), false);
+
+        let res = measure_transfer_tax(&engine, Address::ZERO, U256::from(1_000_000u64));
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_, None))));
+    }
+}
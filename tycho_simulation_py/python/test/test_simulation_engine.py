@@ -0,0 +1,52 @@
+import eth_abi
+import pytest
+from eth_utils import function_signature_to_4byte_selector
+
+from tycho_simulation_py.evm import SimulationParameters
+from tycho_simulation_py.evm.constants import EXTERNAL_ACCOUNT
+from tycho_simulation_py.evm.storage import TychoDBSingleton
+from tycho_simulation_py.evm.utils import create_engine, ERC20OverwriteFactory
+from tycho_simulation_py.models import EthereumToken
+
+TOKEN = EthereumToken(
+    symbol="MOCK", address="0x0000000000000000000000000000000000001234", decimals=18
+)
+RECIPIENT = "0x0000000000000000000000000000000000005678"
+
+
+def _transfer_params(amount: int) -> SimulationParameters:
+    overwrites = ERC20OverwriteFactory(TOKEN)
+    overwrites.set_balance(amount, EXTERNAL_ACCOUNT)
+    data = function_signature_to_4byte_selector(
+        "transfer(address,uint256)"
+    ) + eth_abi.encode(["address", "uint256"], [RECIPIENT, amount])
+    return SimulationParameters(
+        caller=EXTERNAL_ACCOUNT,
+        to=TOKEN.address,
+        data=bytearray(data),
+        value=0,
+        overrides=overwrites.get_tycho_overwrites(),
+    )
+
+
+def _balance_of_params(owner: str) -> SimulationParameters:
+    data = function_signature_to_4byte_selector("balanceOf(address)") + eth_abi.encode(
+        ["address"], [owner]
+    )
+    return SimulationParameters(
+        caller=EXTERNAL_ACCOUNT, to=TOKEN.address, data=bytearray(data), value=0
+    )
+
+
+@pytest.mark.parametrize("apply_updates, expected_balance", [(True, 100), (False, 0)])
+def test_run_sequence(apply_updates, expected_balance):
+    TychoDBSingleton.initialize()
+    engine = create_engine([TOKEN.address])
+
+    results = engine.run_sequence(
+        [_transfer_params(100), _balance_of_params(RECIPIENT)], apply_updates
+    )
+
+    assert len(results) == 2
+    (balance,) = eth_abi.decode(["uint256"], bytes(results[1].result))
+    assert balance == expected_balance
@@ -8,4 +8,5 @@ from tycho_simulation_py._tycho_simulation_py import (
     StateUpdate,
     SimulationDB,
     TychoDB,
+    init_logging,
 )
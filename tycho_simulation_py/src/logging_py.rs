@@ -0,0 +1,163 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+use tracing_subscriber::EnvFilter;
+
+/// Configuration of the first successful `init_logging` call. Set at most once per process.
+static LOGGING_CONFIG: OnceLock<LoggingConfig> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LogFormat {
+    Compact,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format '{other}', expected 'compact' or 'json'")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LoggingConfig {
+    /// Filter directive (e.g. `"info"` or `"tycho_simulation=debug"`). Falls back to `RUST_LOG`
+    /// if not set.
+    pub level: Option<String>,
+    pub format: LogFormat,
+    pub with_thread_ids: bool,
+}
+
+impl LoggingConfig {
+    fn env_filter(&self) -> Result<EnvFilter, String> {
+        match &self.level {
+            Some(level) => {
+                EnvFilter::try_new(level).map_err(|e| format!("Invalid log level '{level}': {e}"))
+            }
+            None => Ok(EnvFilter::from_default_env()),
+        }
+    }
+
+    /// Installs a global `fmt` subscriber built from this configuration.
+    ///
+    /// Returns `false` if another global subscriber was already set, e.g. by the host
+    /// application.
+    fn install(&self) -> Result<bool, String> {
+        let filter = self.env_filter()?;
+        let builder = tracing_subscriber::fmt()
+            .with_file(true)
+            .with_line_number(true)
+            .with_thread_ids(self.with_thread_ids)
+            .with_target(false)
+            .with_env_filter(filter);
+
+        let result = match self.format {
+            LogFormat::Compact => {
+                tracing::subscriber::set_global_default(builder.compact().finish())
+            }
+            LogFormat::Json => tracing::subscriber::set_global_default(builder.json().finish()),
+        };
+        Ok(result.is_ok())
+    }
+}
+
+/// Initializes logging with the given configuration, at most once per process.
+///
+/// Returns `true` if this call installed the subscriber. Subsequent calls are no-ops returning
+/// `false`, and the first configuration stays in effect.
+///
+/// # Errors
+/// Returns an error if the level directive can't be parsed. Nothing is initialized in that case.
+pub(crate) fn try_init_logging(config: LoggingConfig) -> Result<bool, String> {
+    // Validate before claiming the slot so a bad directive doesn't lock out a later valid call.
+    config.env_filter()?;
+
+    let mut installed = Ok(false);
+    LOGGING_CONFIG.get_or_init(|| {
+        installed = config.install();
+        config
+    });
+    installed
+}
+
+/// Returns the configuration logging was initialized with, if any.
+pub(crate) fn logging_config() -> Option<&'static LoggingConfig> {
+    LOGGING_CONFIG.get()
+}
+
+/// Initialize logging for the Rust side of the module
+///
+/// Importing the module does not configure logging - call this once if you want Rust logs to be
+/// emitted. Calling it again has no effect.
+///
+/// Parameters
+/// ----------
+/// level: Optional[str]
+///     Log filter directive, e.g. ``"info"`` or ``"tycho_simulation=debug"``. Defaults to the
+///     ``RUST_LOG`` environment variable.
+/// format: Optional[str]
+///     Either ``"compact"`` (default) or ``"json"`` for structured log collection.
+/// with_thread_ids: bool
+///     Whether to display the thread ID each event was recorded on.
+///
+/// Returns
+/// -------
+/// bool
+///     ``True`` if this call initialized logging, ``False`` if it was already initialized.
+#[pyfunction]
+#[pyo3(signature = (level = None, format = None, with_thread_ids = false))]
+pub fn init_logging(
+    level: Option<String>,
+    format: Option<String>,
+    with_thread_ids: bool,
+) -> PyResult<bool> {
+    let format = match format {
+        Some(format) => LogFormat::from_str(&format).map_err(PyValueError::new_err)?,
+        None => LogFormat::Compact,
+    };
+    try_init_logging(LoggingConfig { level, format, with_thread_ids })
+        .map_err(PyValueError::new_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_logging_is_idempotent() {
+        let first = LoggingConfig {
+            level: Some("debug".to_string()),
+            format: LogFormat::Json,
+            with_thread_ids: true,
+        };
+        let second = LoggingConfig {
+            level: Some("warn".to_string()),
+            format: LogFormat::Compact,
+            with_thread_ids: false,
+        };
+
+        assert!(try_init_logging(LoggingConfig {
+            level: Some("tycho_simulation=loud".to_string()),
+            format: LogFormat::Compact,
+            with_thread_ids: false,
+        })
+        .is_err());
+        assert_eq!(logging_config(), None);
+
+        assert!(try_init_logging(first.clone()).unwrap());
+        assert!(!try_init_logging(second).unwrap());
+        assert_eq!(logging_config(), Some(&first));
+    }
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!(LogFormat::from_str("JSON").unwrap(), LogFormat::Json);
+        assert_eq!(LogFormat::from_str("compact").unwrap(), LogFormat::Compact);
+        assert!(LogFormat::from_str("pretty").is_err());
+    }
+}
@@ -145,6 +145,69 @@ impl SimulationEngine {
         }
     }
 
+    /// Simulate a sequence of transactions in order.
+    ///
+    /// With `apply_updates`, the storage changes of each simulation are kept in a temporary overlay
+    /// that later simulations in the sequence see. Overrides passed in the parameters take
+    /// precedence over the overlay. The overlay is discarded once the sequence is done, the
+    /// underlying database is never modified. Native balance changes are not carried over.
+    ///
+    /// Parameters
+    /// ----------
+    /// params_list : list[SimulationParameters]
+    ///     The simulations to run, in order.
+    /// apply_updates : bool
+    ///     Whether each simulation should see the storage changes of the previous ones.
+    ///
+    /// Returns
+    /// -------
+    /// list[SimulationResult]
+    ///     Results in the same order as `params_list`. Raises on the first failed simulation.
+    #[pyo3(signature = (params_list, apply_updates = true))]
+    fn run_sequence(
+        self_: PyRef<Self>,
+        params_list: Vec<SimulationParameters>,
+        apply_updates: bool,
+    ) -> PyResult<Vec<SimulationResult>> {
+        let mut overlay: HashMap<Address, HashMap<rU256, rU256>> = HashMap::new();
+        let mut results = Vec::with_capacity(params_list.len());
+        for params in params_list {
+            let mut params = simulation::SimulationParameters::from(params);
+            if !overlay.is_empty() {
+                let mut overrides = overlay.clone();
+                for (address, slots) in params
+                    .overrides
+                    .take()
+                    .unwrap_or_default()
+                {
+                    overrides
+                        .entry(address)
+                        .or_default()
+                        .extend(slots);
+                }
+                params.overrides = Some(overrides);
+            }
+
+            let sim_res = self_
+                .0
+                .simulate(&params)
+                .map_err(|sim_err| PyErr::from(SimulationErrorDetails::from(sim_err)))?;
+
+            if apply_updates {
+                for (address, update) in &sim_res.state_updates {
+                    if let Some(storage) = &update.storage {
+                        overlay
+                            .entry(*address)
+                            .or_default()
+                            .extend(storage);
+                    }
+                }
+            }
+            results.push(SimulationResult::from(sim_res));
+        }
+        Ok(results)
+    }
+
     /// Sets up a single account.
     ///
     /// Full control over setting up accounts. Allows setting up EOAs as
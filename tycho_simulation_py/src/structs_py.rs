@@ -111,9 +111,11 @@ impl From<SimulationParameters> for simulation::SimulationParameters {
             data: params.data,
             value: U256::from_be_slice(params.value.to_bytes_be().as_slice()),
             overrides,
+            balance_overrides: None,
             gas_limit: params.gas_limit,
             block_number: params.block_number.unwrap_or(0),
             timestamp: params.timestamp.unwrap_or(0),
+            timeout: None,
         }
     }
 }
@@ -450,12 +452,22 @@ impl From<simulation::SimulationEngineError> for SimulationErrorDetails {
             simulation::SimulationEngineError::StorageError(reason) => {
                 SimulationErrorDetails { data: reason, gas_used: None }
             }
-            simulation::SimulationEngineError::TransactionError { data, gas_used } => {
+            // Python side decodes reverts itself, so pass on the raw payload
+            simulation::SimulationEngineError::TransactionError {
+                revert_data: Some(revert_data),
+                gas_used,
+                ..
+            } => SimulationErrorDetails { data: revert_data.to_string(), gas_used },
+            simulation::SimulationEngineError::TransactionError { data, gas_used, .. } => {
                 SimulationErrorDetails { data, gas_used }
             }
             simulation::SimulationEngineError::OutOfGas(reason, _) => {
                 SimulationErrorDetails { data: reason, gas_used: None }
             }
+            simulation::SimulationEngineError::Timeout(timeout) => SimulationErrorDetails {
+                data: format!("Simulation timed out after {timeout:?}"),
+                gas_used: None,
+            },
         }
     }
 }